@@ -22,6 +22,7 @@ extern crate rand;
 use criterion::Criterion;
 use maelstrom::ciphersuite::*;
 use maelstrom::creds::*;
+use maelstrom::group::*;
 use maelstrom::key_packages::*;
 
 fn criterion_kp_bundle(c: &mut Criterion) {
@@ -46,8 +47,152 @@ fn criterion_kp_bundle(c: &mut Criterion) {
     });
 }
 
+// Compare runs with and without the `parallel` feature to see how the tree
+// hash scales with the number of threads.
+fn criterion_tree_hash(c: &mut Criterion) {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let identity = Identity::new(ciphersuite, vec![1, 2, 3]);
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let signature_key = identity.get_signature_key_pair().get_private_key();
+    for &leaf_count in &[1_000, 10_000] {
+        let key_packages = (1..leaf_count)
+            .map(|_| {
+                KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+                    .get_key_package()
+                    .clone()
+            })
+            .collect();
+        let (group, _) = MlsGroup::new_with_members(
+            &[1, 2, 3, 4],
+            ciphersuite,
+            KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None),
+            signature_key,
+            key_packages,
+        )
+        .unwrap();
+        let nodes = group.get_tree().public_key_tree();
+        let group_context = group.get_context().clone();
+        c.bench_function(
+            &format!("Verify exported tree with {} leaves", leaf_count),
+            move |b| {
+                b.iter(|| {
+                    verify_exported_tree(&ciphersuite, &nodes, &group_context).unwrap();
+                })
+            },
+        );
+    }
+}
+
+// Every other member of a group with 1000 leaves is removed, so that the
+// resolutions of the copath nodes are large when Bob commits. Bob has
+// processed a Commit before, which fills the resolution cache of his tree.
+fn criterion_commit(c: &mut Criterion) {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let identity = Identity::new(ciphersuite, vec![1, 2, 3]);
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let signature_key = identity.get_signature_key_pair().get_private_key();
+    let new_kpb = || KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None);
+    let leaf_count = 1_000u32;
+
+    let bob_identity = Identity::new(ciphersuite, vec![4, 5, 6]);
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let new_bob_kpb = || {
+        KeyPackageBundle::new(
+            &ciphersuite,
+            bob_signature_key,
+            bob_credential.clone(),
+            None,
+        )
+    };
+    let bob_kpb = new_bob_kpb();
+    let key_packages = (1..leaf_count)
+        .map(|i| match i {
+            1 => bob_kpb.get_key_package().clone(),
+            _ => new_kpb().get_key_package().clone(),
+        })
+        .collect();
+    let (mut group_alice, welcome) = MlsGroup::new_with_members(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(),
+        signature_key,
+        key_packages,
+    )
+    .unwrap();
+    let mut group_bob = MlsGroup::new_from_welcome(
+        welcome,
+        Some(group_alice.get_tree().public_key_tree()),
+        bob_kpb,
+    )
+    .unwrap();
+
+    let proposals: Vec<_> = (2..leaf_count)
+        .step_by(2)
+        .map(|leaf_index| {
+            let (plaintext, proposal) = group_alice
+                .create_remove_proposal(&[], signature_key, leaf_index.into())
+                .unwrap();
+            (plaintext.sender(), proposal)
+        })
+        .collect();
+    let commit_bundle = group_alice
+        .create_commit(&[], signature_key, new_kpb(), proposals.clone(), false)
+        .unwrap();
+    group_bob
+        .apply_commit(commit_bundle.get_commit().clone(), proposals)
+        .unwrap();
+
+    let kpb = new_bob_kpb();
+    let signature_key = bob_signature_key.clone();
+    c.bench_function(
+        "Create commit in a tree with 1000 leaves, half of them blank",
+        move |b| {
+            b.iter(|| {
+                group_bob
+                    .update_self(&[], &signature_key, kpb.clone())
+                    .unwrap();
+            })
+        },
+    );
+}
+
+// The sender data key and the handshake key are derived once per epoch and
+// the application secrets once per generation, so that encryption time is
+// dominated by the AEAD.
+fn criterion_encrypt(c: &mut Criterion) {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let identity = Identity::new(ciphersuite, vec![1, 2, 3]);
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let signature_key = identity.get_signature_key_pair().get_private_key();
+    let (mut group, _) = MlsGroup::new_with_members(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None),
+        signature_key,
+        vec![
+            KeyPackageBundle::new(&ciphersuite, signature_key, credential, None)
+                .get_key_package()
+                .clone(),
+        ],
+    )
+    .unwrap();
+    let mls_plaintext = group.create_application_message(&[], &[0u8; 100], signature_key);
+    c.bench_function("Encrypt application messages of 100 bytes", move |b| {
+        b.iter(|| {
+            group.encrypt(mls_plaintext.clone()).unwrap();
+        })
+    });
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     criterion_kp_bundle(c);
+    criterion_tree_hash(c);
+    criterion_commit(c);
+    criterion_encrypt(c);
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,96 @@
+//! Alice creates a group with Bob and sends him the `Welcome` and an
+//! application message over a TCP connection.
+//!
+//! Run with `cargo run --example tcp_transport --features transport`.
+
+use maelstrom::ciphersuite::*;
+use maelstrom::creds::*;
+use maelstrom::group::*;
+use maelstrom::key_packages::*;
+use maelstrom::transport::*;
+
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+fn main() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_key_package_bundle = KeyPackageBundle::new(
+        &ciphersuite,
+        bob_identity.get_signature_key_pair().get_private_key(),
+        bob_credential,
+        None,
+    );
+    let bob_key_package = bob_key_package_bundle.get_key_package().clone();
+
+    // Bob waits for Alice to connect
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let bob = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut transport = StreamTransport::new(stream);
+        let mut group_bob = None;
+        receive_loop(&mut transport, |message| match message {
+            MLSMessage::Welcome {
+                welcome,
+                ratchet_tree,
+            } => {
+                let group = MlsGroup::new_from_welcome(
+                    welcome,
+                    ratchet_tree,
+                    bob_key_package_bundle.clone(),
+                )
+                .unwrap();
+                println!(
+                    "Bob joined the group in epoch {}",
+                    group.get_context().epoch.0
+                );
+                group_bob = Some(group);
+                true
+            }
+            MLSMessage::Ciphertext(mls_ciphertext) => {
+                let processed_message = group_bob
+                    .as_mut()
+                    .expect("Bob is not a member yet")
+                    .decrypt(mls_ciphertext)
+                    .unwrap();
+                println!(
+                    "Bob received: {}",
+                    String::from_utf8_lossy(processed_message.application_data().unwrap())
+                );
+                false
+            }
+            MLSMessage::Plaintext(_) => true,
+        })
+        .unwrap();
+    });
+
+    // Alice adds Bob and sends him the Welcome and a message
+    let (mut group_alice, welcome) = MlsGroup::new_with_members(
+        b"example group",
+        ciphersuite,
+        KeyPackageBundle::new(&ciphersuite, alice_signature_key, alice_credential, None),
+        alice_signature_key,
+        vec![bob_key_package],
+    )
+    .unwrap();
+    let mls_plaintext =
+        group_alice.create_application_message(&[], b"Hello, Bob!", alice_signature_key);
+    let mls_ciphertext = group_alice.encrypt(mls_plaintext).unwrap();
+    let messages = vec![
+        MLSMessage::Welcome {
+            welcome,
+            ratchet_tree: Some(group_alice.get_tree().public_key_tree()),
+        },
+        MLSMessage::Ciphertext(mls_ciphertext),
+    ];
+    let mut transport = StreamTransport::new(TcpStream::connect(address).unwrap());
+    send_loop(&mut transport, messages).unwrap();
+
+    bob.join().unwrap();
+}
@@ -26,7 +26,12 @@ impl Codec for CiphersuiteName {
         Ok(())
     }
     fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-        Ok(CiphersuiteName::from(u16::decode(cursor)?))
+        // Only ciphersuites that `Ciphersuite::new` can instantiate are
+        // accepted. The 256-bit suites have no signature scheme or KEM yet.
+        match u16::decode(cursor)? {
+            value @ 0x0001..=0x0003 => Ok(CiphersuiteName::from(value)),
+            _ => Err(CodecError::DecodingError),
+        }
     }
 }
 
@@ -36,9 +41,7 @@ impl Codec for Ciphersuite {
         Ok(())
     }
     fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-        Ok(Ciphersuite::new(CiphersuiteName::from(u16::decode(
-            cursor,
-        )?)))
+        Ok(Ciphersuite::new(CiphersuiteName::decode(cursor)?))
     }
 }
 
@@ -101,8 +104,8 @@ impl Codec for HPKEKeyPair {
         Ok(())
     }
     fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-        let public_key = HPKEPublicKey::decode(cursor)?;
         let private_key = HPKEPrivateKey::decode(cursor)?;
+        let public_key = HPKEPublicKey::decode(cursor)?;
         Ok(Self {
             private_key,
             public_key,
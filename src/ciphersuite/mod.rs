@@ -19,8 +19,10 @@
 //! This file contains the API to interact with ciphersuites.
 //! See `codec.rs` and `ciphersuites.rs` for internals.
 
+use crate::codec::{encode_vec, VecSize};
 use evercrypt::prelude::*;
 use hpke::{aead::Mode as HpkeAeadMode, kdf::Mode as HpkeKdfMode, kem::Mode as KemMode, *};
+use zeroize::Zeroize;
 
 mod ciphersuites;
 mod codec;
@@ -35,6 +37,11 @@ pub const TAG_BYTES: usize = 16;
 
 #[allow(non_camel_case_types)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize),
+    serde(crate = "serde_crate")
+)]
 pub enum CiphersuiteName {
     MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519 = 0x0001,
     MLS10_128_DHKEMP256_AES128GCM_SHA256_P256 = 0x0002,
@@ -44,6 +51,18 @@ pub enum CiphersuiteName {
     MLS10_256_DHKEMX448_CHACHA20POLY1305_SHA512_Ed448 = 0x0006,
 }
 
+/// Prefix of all labels of `Ciphersuite::sign_with_label`
+const SIGNATURE_LABEL_PREFIX: &str = "MLS 1.0 ";
+
+/// Encode the `SignContent` struct that is signed by `sign_with_label`.
+fn sign_content(label: &str, content: &[u8]) -> Vec<u8> {
+    let full_label = SIGNATURE_LABEL_PREFIX.to_owned() + label;
+    let mut buffer = vec![];
+    encode_vec(VecSize::VecU8, &mut buffer, full_label.as_bytes()).unwrap();
+    encode_vec(VecSize::VecU32, &mut buffer, content).unwrap();
+    buffer
+}
+
 #[derive(Debug)]
 pub enum HKDFError {
     InvalidLength,
@@ -78,6 +97,11 @@ pub enum AEADError {
     WrongKeyLength,
 }
 
+#[derive(Debug)]
+pub enum HPKEError {
+    DecryptionError,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct AeadKey {
     value: Vec<u8>,
@@ -137,6 +161,11 @@ impl Ciphersuite {
         }
     }
 
+    /// Get the name of this ciphersuite.
+    pub fn get_name(&self) -> CiphersuiteName {
+        self.name
+    }
+
     /// Sign a `msg` with the given `sk`.
     pub(crate) fn sign(
         &self,
@@ -155,7 +184,34 @@ impl Ciphersuite {
 
     /// Verify a `msg` against `sig` and `pk`.
     pub(crate) fn verify(&self, sig: &Signature, pk: &SignaturePublicKey, msg: &[u8]) -> bool {
-        verify(self.signature, Some(self.hash), &pk.value, &sig.value, msg).unwrap()
+        // Malformed keys and signatures, e.g. in key packages of other
+        // implementations, don't verify
+        verify(self.signature, Some(self.hash), &pk.value, &sig.value, msg).unwrap_or(false)
+    }
+
+    /// `SignWithLabel` from the spec: sign `content` together with `label`.
+    /// A signature for one label doesn't verify with another one, so
+    /// signatures can't be reused across structures that happen to have the
+    /// same encoding.
+    pub fn sign_with_label(
+        &self,
+        sk: &SignaturePrivateKey,
+        label: &str,
+        content: &[u8],
+    ) -> Result<Signature, SignatureError> {
+        self.sign(sk, &sign_content(label, content))
+    }
+
+    /// `VerifyWithLabel` from the spec: verify a signature created with
+    /// `sign_with_label` for the same `label` and `content`.
+    pub fn verify_with_label(
+        &self,
+        sig: &Signature,
+        pk: &SignaturePublicKey,
+        label: &str,
+        content: &[u8],
+    ) -> bool {
+        self.verify(sig, pk, &sign_content(label, content))
     }
 
     /// Create a new signature key pair and return it.
@@ -176,6 +232,15 @@ impl Ciphersuite {
         hash(self.hash, payload)
     }
 
+    /// Hash `value` together with `label` (`RefHash` in the spec) to compute
+    /// a reference to an object. The label binds the reference to its context.
+    pub(crate) fn reference_hash(&self, label: &str, value: &[u8]) -> Vec<u8> {
+        let mut buffer = vec![];
+        encode_vec(VecSize::VecU8, &mut buffer, label.as_bytes()).unwrap();
+        encode_vec(VecSize::VecU32, &mut buffer, value).unwrap();
+        self.hash(&buffer)
+    }
+
     /// Get the length of the used hash algorithm.
     pub(crate) fn hash_length(&self) -> usize {
         get_digest_size(self.hash)
@@ -186,6 +251,11 @@ impl Ciphersuite {
         hkdf_extract(self.hmac, salt, ikm)
     }
 
+    /// HMAC of `data` under `key`.
+    pub(crate) fn mac(&self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        hmac(self.hmac, key, data, None)
+    }
+
     /// HKDF expand
     pub(crate) fn hkdf_expand(
         &self,
@@ -246,6 +316,16 @@ impl Ciphersuite {
         NONCE_BYTES
     }
 
+    /// Returns the length of the public keys of the HPKE KEM, or `None` if it
+    /// is not known for the KEM.
+    pub(crate) fn hpke_public_key_length(&self) -> Option<usize> {
+        match self.hpke_kem {
+            KemMode::DhKem25519 => Some(32),
+            KemMode::DhKem448 => Some(56),
+            _ => None,
+        }
+    }
+
     /// HPKE single-shot encryption of `ptxt` to `pk_r`, using `info` and `aad`.
     pub(crate) fn hpke_seal(
         &self,
@@ -270,19 +350,33 @@ impl Ciphersuite {
         sk_r: &HPKEPrivateKey,
         info: &[u8],
         aad: &[u8],
-    ) -> Vec<u8> {
+    ) -> Result<Vec<u8>, HPKEError> {
+        if input.ciphertext.len() < TAG_BYTES {
+            return Err(HPKEError::DecryptionError);
+        }
+        if let Some(length) = self.hpke_public_key_length() {
+            if input.kem_output.len() != length {
+                return Err(HPKEError::DecryptionError);
+            }
+        }
         // TODO: put hpke in the ciphersuite.
         let hpke = Hpke::new(Mode::Base, self.hpke_kem, self.hpke_kdf, self.hpke_aead);
-        hpke.open(
-            &input.kem_output,
-            &sk_r.value,
-            info,
-            aad,
-            &input.ciphertext,
-            None,
-            None,
-            None,
-        )
+        // The HPKE backend doesn't report authentication failures in its
+        // return type and may panic on them instead. The ciphertexts come
+        // from other members, so turn that into an error.
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            hpke.open(
+                &input.kem_output,
+                &sk_r.value,
+                info,
+                aad,
+                &input.ciphertext,
+                None,
+                None,
+                None,
+            )
+        }))
+        .map_err(|_| HPKEError::DecryptionError)
     }
 
     /// Generate a new HPKE key pair and return it.
@@ -314,6 +408,10 @@ impl HPKEPrivateKey {
     pub(crate) fn as_slice(&self) -> &[u8] {
         &self.value
     }
+    /// Overwrite the key with zeros.
+    pub(crate) fn wipe(&mut self) {
+        self.value.zeroize();
+    }
     pub(crate) fn from_slice(bytes: &[u8]) -> Self {
         Self {
             value: bytes.to_vec(),
@@ -355,6 +453,11 @@ impl HPKEKeyPair {
     pub(crate) fn get_public_key(&self) -> &HPKEPublicKey {
         &self.public_key
     }
+
+    /// Overwrite the private key with zeros.
+    pub(crate) fn wipe(&mut self) {
+        self.private_key.wipe();
+    }
 }
 
 impl AeadKey {
@@ -371,6 +474,20 @@ impl AeadKey {
     }
 }
 
+// Keys and nonces derived from the key schedule are secret, e.g. the
+// application secrets of a sender ratchet
+impl Drop for AeadKey {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
+impl Drop for AeadNonce {
+    fn drop(&mut self) {
+        self.value.zeroize();
+    }
+}
+
 impl AeadNonce {
     /// Build a new nonce for an AEAD from `bytes`.
     pub(crate) fn from_slice(bytes: &[u8]) -> Self {
@@ -428,3 +545,67 @@ fn test_sign_verify() {
         .unwrap();
     assert!(ciphersuite.verify(&signature, keypair.get_public_key(), payload));
 }
+
+#[test]
+fn test_sign_with_label() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let keypair = ciphersuite.new_signature_keypair();
+    let public_key = keypair.get_public_key();
+    let payload = &[1, 2, 3];
+    let signature = ciphersuite
+        .sign_with_label(keypair.get_private_key(), "GroupInfoTBS", payload)
+        .unwrap();
+    assert!(ciphersuite.verify_with_label(&signature, public_key, "GroupInfoTBS", payload));
+
+    // The signature is only valid for the same label and content
+    assert!(!ciphersuite.verify_with_label(&signature, public_key, "KeyPackageTBS", payload));
+    assert!(!ciphersuite.verify_with_label(&signature, public_key, "GroupInfoTBS", &[1, 2]));
+    assert!(!ciphersuite.verify(&signature, public_key, payload));
+    assert!(ciphersuite.verify(
+        &signature,
+        public_key,
+        &sign_content("GroupInfoTBS", payload)
+    ));
+}
+
+#[test]
+fn test_reference_hash() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let value = &[1, 2, 3];
+    let reference = ciphersuite.reference_hash("MLS 1.0 Proposal Reference", value);
+    assert_eq!(reference.len(), ciphersuite.hash_length());
+    assert_eq!(
+        reference,
+        ciphersuite.reference_hash("MLS 1.0 Proposal Reference", value)
+    );
+    assert_ne!(reference, ciphersuite.hash(value));
+    assert_ne!(
+        reference,
+        ciphersuite.reference_hash("MLS 1.0 KeyPackage Reference", value)
+    );
+}
+
+#[test]
+fn test_hpke_keypair_codec() {
+    use crate::codec::*;
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let keypair = ciphersuite.new_hpke_keypair();
+    let encoded = keypair.encode_detached().unwrap();
+
+    // The private key is encoded first and must be decoded first
+    assert_eq!(
+        encoded,
+        [
+            keypair.get_private_key().encode_detached().unwrap(),
+            keypair.get_public_key().encode_detached().unwrap()
+        ]
+        .concat()
+    );
+    assert_eq!(
+        HPKEKeyPair::decode(&mut Cursor::new(&encoded)).unwrap(),
+        keypair
+    );
+}
@@ -2,11 +2,15 @@ use crate::ciphersuite::*;
 use crate::creds::*;
 
 /// The `Signable` trait is implemented by all struct that are being signed.
-/// The implementation has to provide the `unsigned_payload` function.
+/// The implementation has to provide the `unsigned_payload` function and the
+/// label the payload is signed with.
 pub trait Signable: Sized {
+    /// Label for `Ciphersuite::sign_with_label` that is unique to the type
+    const SIGNATURE_LABEL: &'static str;
+
     fn unsigned_payload(&self) -> Result<Vec<u8>, crate::codec::CodecError>;
 
-    /// Sign the payload with the given `id`.
+    /// Sign the payload with the given `signature_key`.
     ///
     /// Returns a `Signature`.
     fn sign(
@@ -15,14 +19,16 @@ pub trait Signable: Sized {
         signature_key: &SignaturePrivateKey,
     ) -> Signature {
         let payload = self.unsigned_payload().unwrap();
-        ciphersuite.sign(signature_key, &payload).unwrap()
+        ciphersuite
+            .sign_with_label(signature_key, Self::SIGNATURE_LABEL, &payload)
+            .unwrap()
     }
 
-    /// Verifies the payload against the given `id` and `signature`.
+    /// Verifies the payload against the given `credential` and `signature`.
     ///
     /// Returns a `true` if the signature is valid and `false` otherwise.
-    fn verify(&self, id: &Identity, signature: &Signature) -> bool {
+    fn verify_signature(&self, credential: &Credential, signature: &Signature) -> bool {
         let payload = self.unsigned_payload().unwrap();
-        id.verify(&payload, signature)
+        credential.verify_with_label(Self::SIGNATURE_LABEL, &payload, signature)
     }
 }
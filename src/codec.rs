@@ -14,14 +14,21 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see http://www.gnu.org/licenses/.
 
-use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+use core::convert::*;
+#[cfg(feature = "std")]
 use std::collections::HashMap;
-use std::convert::*;
-use std::io::Write;
+#[cfg(feature = "std")]
+use thiserror::Error;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "std", derive(Error))]
+#[non_exhaustive]
 pub enum CodecError {
+    #[cfg_attr(feature = "std", error("the value could not be encoded"))]
     EncodingError,
+    #[cfg_attr(feature = "std", error("the value could not be decoded"))]
     DecodingError,
 }
 
@@ -102,14 +109,14 @@ impl Codec for u8 {
 
 impl Codec for u16 {
     fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
-        buffer.write_u16::<BigEndian>(*self).unwrap();
+        buffer.extend_from_slice(&self.to_be_bytes());
         Ok(())
     }
 
     fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
         let bytes_option = cursor.consume(2);
         match bytes_option {
-            Ok(mut bytes) => Ok(bytes.read_u16::<BigEndian>().unwrap()),
+            Ok(bytes) => Ok(u16::from_be_bytes(bytes.try_into().unwrap())),
             Err(e) => Err(e),
         }
     }
@@ -117,14 +124,14 @@ impl Codec for u16 {
 
 impl Codec for u32 {
     fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
-        buffer.write_u32::<BigEndian>(*self).unwrap();
+        buffer.extend_from_slice(&self.to_be_bytes());
         Ok(())
     }
 
     fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
         let bytes_option = cursor.consume(4);
         match bytes_option {
-            Ok(mut bytes) => Ok(bytes.read_u32::<BigEndian>().unwrap()),
+            Ok(bytes) => Ok(u32::from_be_bytes(bytes.try_into().unwrap())),
             Err(e) => Err(e),
         }
     }
@@ -132,14 +139,14 @@ impl Codec for u32 {
 
 impl Codec for u64 {
     fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
-        buffer.write_u64::<BigEndian>(*self).unwrap();
+        buffer.extend_from_slice(&self.to_be_bytes());
         Ok(())
     }
 
     fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
         let bytes_option = cursor.consume(8);
         match bytes_option {
-            Ok(mut bytes) => Ok(bytes.read_u64::<BigEndian>().unwrap()),
+            Ok(bytes) => Ok(u64::from_be_bytes(bytes.try_into().unwrap())),
             Err(e) => Err(e),
         }
     }
@@ -182,11 +189,12 @@ impl<T1: Codec, T2: Codec> Codec for (T1, T2) {
     }
 }
 
+#[cfg(feature = "std")]
 impl<K: Codec + Eq + ::std::hash::Hash, V: Codec, S: ::std::hash::BuildHasher + Default> Codec
     for HashMap<K, V, S>
 {
     fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
-        let size = self.len() as u32;
+        let size = u32::try_from(self.len()).map_err(|_| CodecError::EncodingError)?;
         size.encode(buffer)?;
         for (key, val) in self.iter() {
             key.encode(buffer)?;
@@ -212,42 +220,33 @@ pub fn encode_vec<T: Codec>(
     bytes: &mut Vec<u8>,
     slice: &[T],
 ) -> Result<(), CodecError> {
-    let slice_len = slice.len();
     let mut buffer = Vec::new();
-
-    match vec_size {
-        VecSize::VecU8 => {
-            if slice_len > (u8::max_value() as usize) {
-                return Err(CodecError::EncodingError);
-            }
-        }
-        VecSize::VecU16 => {
-            if slice_len > (u16::max_value() as usize) {
-                return Err(CodecError::EncodingError);
-            }
-        }
-        VecSize::VecU32 => {
-            if slice_len > (u32::max_value() as usize) {
-                return Err(CodecError::EncodingError);
-            }
-        }
-        VecSize::VecU64 => {}
-    }
     for e in slice.iter() {
         e.encode(&mut buffer)?;
     }
+    // The length prefix is the length of the encoded elements in bytes, so it
+    // is checked after encoding them
+    let too_long = |_| CodecError::EncodingError;
     match vec_size {
         VecSize::VecU8 => {
-            (buffer.len() as u8).encode(bytes)?;
+            u8::try_from(buffer.len())
+                .map_err(too_long)?
+                .encode(bytes)?;
         }
         VecSize::VecU16 => {
-            (buffer.len() as u16).encode(bytes)?;
+            u16::try_from(buffer.len())
+                .map_err(too_long)?
+                .encode(bytes)?;
         }
         VecSize::VecU32 => {
-            (buffer.len() as u32).encode(bytes)?;
+            u32::try_from(buffer.len())
+                .map_err(too_long)?
+                .encode(bytes)?;
         }
         VecSize::VecU64 => {
-            (buffer.len() as u64).encode(bytes)?;
+            u64::try_from(buffer.len())
+                .map_err(too_long)?
+                .encode(bytes)?;
         }
     }
     bytes.extend(buffer);
@@ -265,10 +264,10 @@ pub fn decode_vec<T: Codec>(vec_size: VecSize, cursor: &mut Cursor) -> Result<Ve
             len = usize::from(u16::decode(cursor)?);
         }
         VecSize::VecU32 => {
-            len = u32::decode(cursor)? as usize;
+            len = usize::try_from(u32::decode(cursor)?).map_err(|_| CodecError::DecodingError)?;
         }
         VecSize::VecU64 => {
-            len = u64::decode(cursor)? as usize;
+            len = usize::try_from(u64::decode(cursor)?).map_err(|_| CodecError::DecodingError)?;
         }
     }
     let mut sub_cursor = cursor.sub_cursor(len)?;
@@ -306,6 +305,25 @@ fn test_primitives() {
     assert_eq!(buffer, vec![0u8, 0u8, 0u8, 0u8, 0u8, 0u8, 0u8, 1u8]);
 }
 
+#[test]
+fn test_option() {
+    let mut buffer = vec![];
+    Some(7u8).encode(&mut buffer).unwrap();
+    None::<u8>.encode(&mut buffer).unwrap();
+    assert_eq!(buffer, vec![1, 7, 0]);
+    let cursor = &mut Cursor::new(&buffer);
+    assert_eq!(Option::<u8>::decode(cursor), Ok(Some(7)));
+    assert_eq!(Option::<u8>::decode(cursor), Ok(None));
+
+    // Flags other than 0 and 1 are rejected
+    for flag in 2..=255u8 {
+        assert_eq!(
+            Option::<u8>::decode(&mut Cursor::new(&[flag, 7])),
+            Err(CodecError::DecodingError)
+        );
+    }
+}
+
 #[test]
 fn test_encode_vec() {
     let v: Vec<u8> = vec![1, 2, 3];
@@ -341,3 +359,73 @@ fn test_encode_vec() {
         v
     );
 }
+
+#[test]
+fn test_vec_boundaries() {
+    // The length limit applies to the encoded length in bytes
+    let mut buffer = vec![];
+    encode_vec(VecSize::VecU8, &mut buffer, &[0u8; 255]).unwrap();
+    assert_eq!(buffer[0], 255);
+    assert!(encode_vec(VecSize::VecU8, &mut vec![], &[0u8; 256]).is_err());
+    assert!(encode_vec(VecSize::VecU8, &mut vec![], &[0u16; 128]).is_err());
+    assert!(encode_vec(VecSize::VecU16, &mut vec![], &[0u16; 32_767]).is_ok());
+    assert!(encode_vec(VecSize::VecU16, &mut vec![], &[0u16; 32_768]).is_err());
+
+    // Lengths that exceed the remaining data are rejected
+    let mut cursor = Cursor::new(&[0xff, 0xff, 0xff, 0xff, 1, 2, 3]);
+    assert!(decode_vec::<u8>(VecSize::VecU32, &mut cursor).is_err());
+    let mut cursor = Cursor::new(&[0xff; 12]);
+    assert!(decode_vec::<u8>(VecSize::VecU64, &mut cursor).is_err());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_u64_boundaries() {
+    use crate::group::GroupEpoch;
+
+    for value in &[0u64, u32::MAX as u64 + 1, u64::MAX] {
+        let encoded = value.encode_detached().unwrap();
+        assert_eq!(u64::decode(&mut Cursor::new(&encoded)).unwrap(), *value);
+        let epoch = GroupEpoch(*value);
+        let encoded = epoch.encode_detached().unwrap();
+        assert_eq!(
+            GroupEpoch::decode(&mut Cursor::new(&encoded)).unwrap(),
+            epoch
+        );
+    }
+    assert!(u64::decode(&mut Cursor::new(&[0xff; 7])).is_err());
+
+    // The epoch doesn't wrap around
+    let mut epoch = GroupEpoch(u64::MAX - 1);
+    assert!(epoch.increment());
+    assert_eq!(epoch, GroupEpoch(u64::MAX));
+    assert!(!epoch.increment());
+    assert_eq!(epoch, GroupEpoch(u64::MAX));
+}
+
+#[cfg(target_pointer_width = "32")]
+#[test]
+fn test_vec_lengths_32_bit() {
+    // Lengths beyond the address space are rejected instead of truncated
+    let mut buffer = vec![];
+    (u32::MAX as u64 + 1).encode(&mut buffer).unwrap();
+    buffer.extend_from_slice(&[1, 2, 3]);
+    assert_eq!(
+        decode_vec::<u8>(VecSize::VecU64, &mut Cursor::new(&buffer)).err(),
+        Some(CodecError::DecodingError)
+    );
+}
+
+#[cfg(target_endian = "big")]
+#[test]
+fn test_big_endian_host() {
+    // The wire format is big-endian, which is the native byte order here
+    assert_eq!(
+        0x0102_0304u32.encode_detached().unwrap(),
+        0x0102_0304u32.to_ne_bytes().to_vec()
+    );
+    assert_eq!(
+        u64::decode(&mut Cursor::new(&1u64.to_ne_bytes())).unwrap(),
+        1
+    );
+}
@@ -100,9 +100,9 @@ impl Codec for CredentialType {
         (*self as u8).encode(buffer)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     Ok(CredentialType::from(u8::decode(cursor)?))
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        Ok(CredentialType::from(u8::decode(cursor)?))
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -120,6 +120,15 @@ impl Credential {
             ),
         }
     }
+    /// Verify a signature that was created with
+    /// `Ciphersuite::sign_with_label` for `label`.
+    pub fn verify_with_label(&self, label: &str, payload: &[u8], signature: &Signature) -> bool {
+        match self {
+            Credential::Basic(basic_credential) => {
+                basic_credential.verify_with_label(label, payload, signature)
+            }
+        }
+    }
 }
 
 impl Codec for Credential {
@@ -132,13 +141,13 @@ impl Codec for Credential {
         }
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let credential_type = CredentialType::from(u8::decode(cursor)?);
-    //     match credential_type {
-    //         CredentialType::Basic => Ok(Credential::Basic(BasicCredential::decode(cursor)?)),
-    //         _ => Err(CodecError::DecodingError),
-    //     }
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let credential_type = CredentialType::from(u8::decode(cursor)?);
+        match credential_type {
+            CredentialType::Basic => Ok(Credential::Basic(BasicCredential::decode(cursor)?)),
+            _ => Err(CodecError::DecodingError),
+        }
+    }
 }
 
 // TODO: Drop ciphersuite
@@ -154,6 +163,12 @@ impl BasicCredential {
         self.ciphersuite
             .verify(signature, &self.public_key, payload)
     }
+    /// Verify a signature that was created with
+    /// `Ciphersuite::sign_with_label` for `label`.
+    pub fn verify_with_label(&self, label: &str, payload: &[u8], signature: &Signature) -> bool {
+        self.ciphersuite
+            .verify_with_label(signature, &self.public_key, label, payload)
+    }
 }
 
 impl From<&Identity> for BasicCredential {
@@ -173,16 +188,16 @@ impl Codec for BasicCredential {
         self.public_key.encode(buffer)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let identity = decode_vec(VecSize::VecU16, cursor)?;
-    //     let ciphersuite = Ciphersuite::decode(cursor)?;
-    //     let public_key = SignaturePublicKey::decode(cursor)?;
-    //     Ok(BasicCredential {
-    //         identity,
-    //         ciphersuite,
-    //         public_key,
-    //     })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let identity = decode_vec(VecSize::VecU16, cursor)?;
+        let ciphersuite = Ciphersuite::decode(cursor)?;
+        let public_key = SignaturePublicKey::decode(cursor)?;
+        Ok(BasicCredential {
+            identity,
+            ciphersuite,
+            public_key,
+        })
+    }
 }
 
 #[test]
@@ -0,0 +1,228 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+//! Summaries of the state of a group for debugging and bug reports.
+//!
+//! The summaries only contain public values like public keys, hashes,
+//! indices and counters, never secrets, so they can be attached to bug
+//! reports. The library doesn't print them, they implement `Display` for
+//! applications that want to.
+
+use crate::ciphersuite::*;
+use crate::creds::*;
+use crate::extensions::*;
+use crate::tree::{index::*, node::*, *};
+
+use std::fmt;
+
+pub use crate::tree::astree::SenderRatchetState;
+
+/// Encode `bytes` as uppercase hex.
+pub fn bytes_to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::new();
+    for b in bytes {
+        hex += &format!("{:02X}", *b);
+    }
+    hex
+}
+
+/// Kind of a node in a `TreeSummary`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum NodeKind {
+    Leaf,
+    Parent,
+    Blank,
+}
+
+/// Public values of a node of the ratchet tree
+#[derive(Debug, PartialEq, Clone)]
+pub struct NodeSummary {
+    pub index: u32,
+    /// Level of the node in the tree, leaves are at level 0
+    pub level: usize,
+    pub kind: NodeKind,
+    /// Empty for blank nodes
+    pub public_key: Vec<u8>,
+    /// Empty for blank nodes and nodes without a parent hash
+    pub parent_hash: Vec<u8>,
+    /// Identity of the member at a leaf, empty for parent and blank nodes
+    pub identity: Vec<u8>,
+}
+
+/// Public values of all nodes of a ratchet tree
+#[derive(Debug, PartialEq, Clone)]
+pub struct TreeSummary {
+    pub nodes: Vec<NodeSummary>,
+}
+
+impl TreeSummary {
+    pub(crate) fn new(tree: &RatchetTree) -> Self {
+        let nodes = tree
+            .nodes()
+            .iter()
+            .zip(0u32..)
+            .map(|(node, i)| summarize_node(NodeIndex::from(i), node))
+            .collect();
+        TreeSummary { nodes }
+    }
+}
+
+fn summarize_node(index: NodeIndex, node: &Node) -> NodeSummary {
+    let mut summary = NodeSummary {
+        index: index.as_u32(),
+        level: treemath::level(index),
+        kind: NodeKind::Blank,
+        public_key: vec![],
+        parent_hash: vec![],
+        identity: vec![],
+    };
+    if node.is_blank() {
+        return summary;
+    }
+    match node.node_type {
+        NodeType::Leaf => {
+            summary.kind = NodeKind::Leaf;
+            if let Some(leaf_node) = &node.leaf_node {
+                summary.public_key = leaf_node.get_encryption_key().as_slice().to_vec();
+                if let Some(ExtensionPayload::ParentHash(parent_hash_extension)) =
+                    leaf_node.get_extension(ExtensionType::ParentHash)
+                {
+                    summary.parent_hash = parent_hash_extension.parent_hash;
+                }
+                let Credential::Basic(basic_credential) = leaf_node.get_credential();
+                summary.identity = basic_credential.identity.clone();
+            }
+        }
+        NodeType::Parent => {
+            summary.kind = NodeKind::Parent;
+            if let Some(parent_node) = &node.node {
+                summary.public_key = parent_node.get_public_key().as_slice().to_vec();
+            }
+            summary.parent_hash = node.parent_hash().unwrap_or_default();
+        }
+        _ => unreachable!(),
+    }
+    summary
+}
+
+/// One line per node with its public key, its parent hash and its position
+/// in the tree.
+impl fmt::Display for TreeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let factor = 3;
+        for node in self.nodes.iter() {
+            write!(f, "{:04}", node.index)?;
+            match node.kind {
+                NodeKind::Blank => {
+                    write!(f, "\tB\t\t\t\t\t\t\t\t\t\t\t\t\t\t\t\t\t\t\t| ")?;
+                    write!(f, "{}❑", " ".repeat(node.level * factor))?;
+                }
+                NodeKind::Leaf | NodeKind::Parent => {
+                    if node.kind == NodeKind::Leaf {
+                        write!(f, "\tL")?;
+                    } else {
+                        write!(f, "\tP")?;
+                    }
+                    if !node.public_key.is_empty() {
+                        write!(f, "\tPK: {}", bytes_to_hex(&node.public_key))?;
+                    } else {
+                        write!(f, "\tPK:\t\t\t")?;
+                    }
+                    if !node.parent_hash.is_empty() {
+                        write!(f, "\tPH: {}", bytes_to_hex(&node.parent_hash))?;
+                    } else {
+                        write!(f, "\tPH:\t\t\t\t\t\t\t\t")?;
+                    }
+                    write!(f, "\t| {}◼︎", " ".repeat(node.level * factor))?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Public values of the current epoch of a group
+#[derive(Debug, PartialEq, Clone)]
+pub struct EpochSummary {
+    pub group_id: Vec<u8>,
+    pub epoch: u64,
+    pub tree_hash: Vec<u8>,
+    pub confirmed_transcript_hash: Vec<u8>,
+    /// Time the epoch started at, in seconds since the Unix epoch
+    pub started_at: u64,
+    /// Number of messages encrypted or decrypted in the epoch
+    pub message_count: u32,
+    pub member_count: usize,
+}
+
+/// Everything `MlsGroup::diagnostics` collects about a group. Secrets are
+/// redacted, only public values and counters are included.
+#[derive(Debug, PartialEq, Clone)]
+pub struct GroupDiagnostics {
+    pub ciphersuite: CiphersuiteName,
+    pub epoch: EpochSummary,
+    pub tree: TreeSummary,
+    /// Leaf index of the own leaf
+    pub own_leaf: u32,
+    pub ratchets: Vec<SenderRatchetState>,
+    /// Number of messages this member encrypted across all epochs
+    pub send_counter: u32,
+    /// Number of own Updates and Commits of the epoch that were not applied
+    pub pending_key_package_bundles: usize,
+    /// `true` if the group was imported from a transfer package
+    pub observer: bool,
+}
+
+impl fmt::Display for GroupDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Ciphersuite: {:?}", self.ciphersuite)?;
+        writeln!(f, "Group ID: {}", bytes_to_hex(&self.epoch.group_id))?;
+        writeln!(
+            f,
+            "Epoch: {} (started at {}, {} messages)",
+            self.epoch.epoch, self.epoch.started_at, self.epoch.message_count
+        )?;
+        writeln!(f, "Tree hash: {}", bytes_to_hex(&self.epoch.tree_hash))?;
+        writeln!(
+            f,
+            "Confirmed transcript hash: {}",
+            bytes_to_hex(&self.epoch.confirmed_transcript_hash)
+        )?;
+        writeln!(
+            f,
+            "Members: {}, own leaf: {}",
+            self.epoch.member_count, self.own_leaf
+        )?;
+        writeln!(
+            f,
+            "Send counter: {}, pending key package bundles: {}, observer: {}",
+            self.send_counter, self.pending_key_package_bundles, self.observer
+        )?;
+        for ratchet in self.ratchets.iter() {
+            writeln!(
+                f,
+                "Sender {}: generation {}, window {}/{}{}",
+                ratchet.sender.as_u32(),
+                ratchet.generation,
+                ratchet.window_occupancy,
+                ratchet.window_size,
+                if ratchet.evicted { ", evicted" } else { "" }
+            )?;
+        }
+        write!(f, "{}", self.tree)
+    }
+}
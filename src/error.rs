@@ -0,0 +1,81 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+//! Top-level error type of the crate.
+//!
+//! Every operation returns its own error enum, e.g. `WelcomeError` or
+//! `ApplyCommitError`, so that callers can handle its failures precisely.
+//! All of them convert into `MlsError`, so that code that drives several
+//! operations can use `?` and still get at the original error with
+//! `std::error::Error::source` or by matching on the variant. The enums are
+//! `#[non_exhaustive]`, new errors can be added without breaking matches.
+
+use crate::codec::CodecError;
+use crate::extensions::ExtensionError;
+use crate::group::*;
+use crate::key_packages::*;
+use crate::storage::StorageError;
+#[cfg(feature = "transport")]
+use crate::transport::TransportError;
+
+use thiserror::Error;
+
+/// Any error that can be returned by this crate.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum MlsError {
+    #[error("the group could not be joined")]
+    Welcome(#[from] WelcomeError),
+    #[error("the Commit could not be applied")]
+    ApplyCommit(#[from] ApplyCommitError),
+    #[error("the Commit could not be created")]
+    CreateCommit(#[from] CreateCommitError),
+    #[error("the message could not be encrypted")]
+    Encryption(#[from] EncryptionError),
+    #[error("the message could not be decrypted")]
+    Decryption(#[from] DecryptionError),
+    #[error("the sender could not be authenticated")]
+    Sender(#[from] SenderError),
+    #[error("the proposal could not be processed")]
+    Proposal(#[from] ProposalError),
+    #[error("the membership proof could not be verified")]
+    MembershipProof(#[from] MembershipProofError),
+    #[error("the payload signature could not be verified")]
+    PayloadSignature(#[from] PayloadSignatureError),
+    #[error("the proposal could not be created")]
+    CreateProposal(#[from] CreateProposalError),
+    #[error("the GroupInfo could not be verified")]
+    GroupInfo(#[from] GroupInfoError),
+    #[error("the ratchet tree could not be verified")]
+    TreeValidation(#[from] TreeValidationError),
+    #[error("the delivery hint could not be verified")]
+    DeliveryHint(#[from] DeliveryHintError),
+    #[error("the key package is invalid")]
+    KeyPackage(#[from] KeyPackageError),
+    #[error("the key packages could not be fetched")]
+    KeyPackageFetch(#[from] KeyPackageFetchError),
+    #[error("the group configuration could not be negotiated")]
+    Capability(#[from] CapabilityError),
+    #[error("the extension is invalid")]
+    Extension(#[from] ExtensionError),
+    #[error("the envelope could not be opened")]
+    Storage(#[from] StorageError),
+    #[error("the encoding is invalid")]
+    Codec(#[from] CodecError),
+    #[cfg(feature = "transport")]
+    #[error("the message could not be sent or received")]
+    Transport(#[from] TransportError),
+}
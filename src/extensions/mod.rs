@@ -0,0 +1,498 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+use crate::ciphersuite::*;
+use crate::codec::*;
+use crate::creds::*;
+use crate::tree::node::*;
+use crate::utils::*;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::*;
+
+mod parsing;
+
+pub use self::parsing::*;
+
+/// Check that `extensions` contains no duplicate extension types, that all
+/// `required` extension types are present and that the data of every known
+/// extension is well-formed. Unknown extensions are ignored.
+pub fn validate_extensions(
+    extensions: &[Extension],
+    required: &[ExtensionType],
+) -> Result<(), ExtensionError> {
+    for (i, e) in extensions.iter().enumerate() {
+        if extensions[..i]
+            .iter()
+            .any(|other| other.extension_type == e.extension_type)
+        {
+            return Err(ExtensionError::DuplicateExtension);
+        }
+        if e.extension_data.len() > u16::MAX as usize {
+            return Err(ExtensionError::ExtensionDataTooLong);
+        }
+        match e.extension_type {
+            ExtensionType::Capabilities => {
+                CapabilitiesExtension::try_from_bytes(&e.extension_data)?;
+            }
+            ExtensionType::Lifetime => {
+                let lifetime_extension = LifetimeExtension::try_from_bytes(&e.extension_data)?;
+                if lifetime_extension.not_before > lifetime_extension.not_after {
+                    return Err(ExtensionError::InvalidExtensionData);
+                }
+            }
+            ExtensionType::KeyID => {
+                KeyIDExtension::try_from_bytes(&e.extension_data)?;
+            }
+            ExtensionType::ParentHash => {
+                ParentHashExtension::try_from_bytes(&e.extension_data)?;
+            }
+            ExtensionType::RatchetTree => {
+                RatchetTreeExtension::try_from_bytes(&e.extension_data)?;
+            }
+            ExtensionType::ExternalSenders => {
+                ExternalSendersExtension::try_from_bytes(&e.extension_data)?;
+            }
+            ExtensionType::ApplicationProtocol => {
+                ApplicationProtocolExtension::try_from_bytes(&e.extension_data)?;
+            }
+            ExtensionType::DeviceCapabilities => {
+                DeviceCapabilitiesExtension::try_from_bytes(&e.extension_data)?;
+            }
+            ExtensionType::Deniability => {
+                DeniabilityExtension::try_from_bytes(&e.extension_data)?;
+            }
+            ExtensionType::Invalid | ExtensionType::Default => {}
+        }
+    }
+    for extension_type in required {
+        if !extensions
+            .iter()
+            .any(|e| e.extension_type == *extension_type)
+        {
+            return Err(ExtensionError::MissingExtension);
+        }
+    }
+    Ok(())
+}
+
+/// Get the payload of the extension of `extension_type` in `extensions`.
+/// Returns `None` if the extension is not present or its data is malformed.
+pub(crate) fn get_extension_payload(
+    extensions: &[Extension],
+    extension_type: ExtensionType,
+) -> Option<ExtensionPayload> {
+    let e = extensions.iter().find(|e| e.get_type() == extension_type)?;
+    match extension_type {
+        ExtensionType::Capabilities => CapabilitiesExtension::try_from_bytes(&e.extension_data)
+            .ok()
+            .map(ExtensionPayload::Capabilities),
+        ExtensionType::Lifetime => LifetimeExtension::try_from_bytes(&e.extension_data)
+            .ok()
+            .map(ExtensionPayload::Lifetime),
+        ExtensionType::KeyID => KeyIDExtension::try_from_bytes(&e.extension_data)
+            .ok()
+            .map(ExtensionPayload::KeyID),
+        ExtensionType::ParentHash => ParentHashExtension::try_from_bytes(&e.extension_data)
+            .ok()
+            .map(ExtensionPayload::ParentHash),
+        ExtensionType::ApplicationProtocol => {
+            ApplicationProtocolExtension::try_from_bytes(&e.extension_data)
+                .ok()
+                .map(ExtensionPayload::ApplicationProtocol)
+        }
+        ExtensionType::DeviceCapabilities => {
+            DeviceCapabilitiesExtension::try_from_bytes(&e.extension_data)
+                .ok()
+                .map(ExtensionPayload::DeviceCapabilities)
+        }
+        ExtensionType::Deniability => DeniabilityExtension::try_from_bytes(&e.extension_data)
+            .ok()
+            .map(ExtensionPayload::Deniability),
+        _ => None,
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum ExtensionPayload {
+    Capabilities(CapabilitiesExtension),
+    Lifetime(LifetimeExtension),
+    KeyID(KeyIDExtension),
+    ParentHash(ParentHashExtension),
+    RatchetTree(RatchetTreeExtension),
+    ExternalSenders(ExternalSendersExtension),
+    ApplicationProtocol(ApplicationProtocolExtension),
+    DeviceCapabilities(DeviceCapabilitiesExtension),
+    Deniability(DeniabilityExtension),
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct CapabilitiesExtension {
+    pub versions: Vec<ProtocolVersion>,
+    pub ciphersuites: Vec<CiphersuiteName>,
+    pub extensions: Vec<ExtensionType>,
+}
+
+impl CapabilitiesExtension {
+    pub fn new(
+        versions: Vec<ProtocolVersion>,
+        ciphersuites: Vec<CiphersuiteName>,
+        extensions: Vec<ExtensionType>,
+    ) -> Self {
+        CapabilitiesExtension {
+            versions,
+            ciphersuites,
+            extensions,
+        }
+    }
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, ExtensionError> {
+        let cursor = &mut Cursor::new(bytes);
+        let versions = decode_vec(VecSize::VecU8, cursor)?;
+        let ciphersuites = decode_vec(VecSize::VecU8, cursor)?;
+        let extensions = decode_vec(VecSize::VecU8, cursor)?;
+        check_trailing_data(cursor)?;
+        Ok(CapabilitiesExtension {
+            versions,
+            ciphersuites,
+            extensions,
+        })
+    }
+    pub fn to_extension(&self) -> Extension {
+        let mut extension_data: Vec<u8> = vec![];
+        encode_vec(VecSize::VecU8, &mut extension_data, &self.versions).unwrap();
+        encode_vec(VecSize::VecU8, &mut extension_data, &self.ciphersuites).unwrap();
+        encode_vec(VecSize::VecU8, &mut extension_data, &self.extensions).unwrap();
+        let extension_type = ExtensionType::Capabilities;
+        Extension {
+            extension_type,
+            extension_data,
+        }
+    }
+}
+
+/// `TimeProvider` that reads the system clock.
+pub struct SystemTimeProvider;
+
+impl TimeProvider for SystemTimeProvider {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+}
+
+/// How `LifetimeExtension`s are created and checked. The default creates
+/// lifetimes of `LIFETIME_4_WEEKS` that are extended by `LIFETIME_MARGIN` on
+/// both ends, accepts no lifetime that doesn't include the current time, and
+/// reads the system clock. Lifetimes are optional unless they are required
+/// with `set_require_lifetime`.
+#[derive(Clone)]
+pub struct LifetimeConfig {
+    lifetime: u64,
+    margin: u64,
+    acceptance_margin: u64,
+    require_lifetime: bool,
+    time_provider: Arc<dyn TimeProvider>,
+}
+
+impl Default for LifetimeConfig {
+    fn default() -> Self {
+        Self {
+            lifetime: LifetimeExtension::LIFETIME_4_WEEKS,
+            margin: LifetimeExtension::LIFETIME_MARGIN,
+            acceptance_margin: 0,
+            require_lifetime: false,
+            time_provider: Arc::new(SystemTimeProvider),
+        }
+    }
+}
+
+impl LifetimeConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the lifetime of new `LifetimeExtension`s in seconds.
+    pub fn set_lifetime(&mut self, lifetime: u64) {
+        self.lifetime = lifetime;
+    }
+
+    pub fn get_lifetime(&self) -> u64 {
+        self.lifetime
+    }
+
+    /// Set the number of seconds by which new lifetimes are extended on both
+    /// ends, so that clients whose clocks are off still accept them.
+    pub fn set_margin(&mut self, margin: u64) {
+        self.margin = margin;
+    }
+
+    pub fn get_margin(&self) -> u64 {
+        self.margin
+    }
+
+    /// Set the number of seconds by which the current time may lie outside of
+    /// a lifetime that is checked, to tolerate clients whose clocks are off.
+    pub fn set_acceptance_margin(&mut self, acceptance_margin: u64) {
+        self.acceptance_margin = acceptance_margin;
+    }
+
+    pub fn get_acceptance_margin(&self) -> u64 {
+        self.acceptance_margin
+    }
+
+    /// Reject key packages without a `LifetimeExtension` when they are
+    /// validated with this config.
+    pub fn set_require_lifetime(&mut self, require_lifetime: bool) {
+        self.require_lifetime = require_lifetime;
+    }
+
+    pub fn get_require_lifetime(&self) -> bool {
+        self.require_lifetime
+    }
+
+    /// Set the `TimeProvider` lifetimes are created and checked with.
+    pub fn set_time_provider(&mut self, time_provider: Arc<dyn TimeProvider>) {
+        self.time_provider = time_provider;
+    }
+
+    pub fn get_time_provider(&self) -> &dyn TimeProvider {
+        self.time_provider.as_ref()
+    }
+}
+
+impl LifetimeExtension {
+    /// Create a lifetime of `t` seconds starting now, with the default margin
+    /// of `LIFETIME_MARGIN`.
+    pub fn new(t: u64) -> Self {
+        Self::new_with_margin(t, Self::LIFETIME_MARGIN, &SystemTimeProvider)
+    }
+    /// Create a lifetime with the lifetime, margin and clock of `config`.
+    pub fn new_with_config(config: &LifetimeConfig) -> Self {
+        Self::new_with_margin(
+            config.lifetime,
+            config.margin,
+            config.time_provider.as_ref(),
+        )
+    }
+    /// Returns `true` if the current time of `config` lies within the
+    /// lifetime, extended by the acceptance margin of `config`.
+    pub fn is_valid_with_config(&self, config: &LifetimeConfig) -> bool {
+        self.is_valid_at_with_margin(config.time_provider.now(), config.acceptance_margin)
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct RatchetTreeExtension {
+    pub tree: Vec<Option<Node>>,
+}
+
+impl RatchetTreeExtension {
+    pub fn new(tree: Vec<Option<Node>>) -> Self {
+        RatchetTreeExtension { tree }
+    }
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, ExtensionError> {
+        let cursor = &mut Cursor::new(bytes);
+        let tree = decode_vec(VecSize::VecU32, cursor)?;
+        check_trailing_data(cursor)?;
+        Ok(Self { tree })
+    }
+    pub fn to_extension(&self) -> Extension {
+        let mut extension_data: Vec<u8> = vec![];
+        encode_vec(VecSize::VecU32, &mut extension_data, &self.tree).unwrap();
+        let extension_type = ExtensionType::RatchetTree;
+        Extension {
+            extension_type,
+            extension_data,
+        }
+    }
+}
+
+/// Group context extension listing the credentials of the non-members that are
+/// allowed to send proposals to the group.
+#[derive(PartialEq, Clone, Debug)]
+pub struct ExternalSendersExtension {
+    pub senders: Vec<Credential>,
+}
+
+impl ExternalSendersExtension {
+    pub fn new(senders: Vec<Credential>) -> Self {
+        ExternalSendersExtension { senders }
+    }
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, ExtensionError> {
+        let cursor = &mut Cursor::new(bytes);
+        let senders = decode_vec(VecSize::VecU16, cursor)?;
+        check_trailing_data(cursor)?;
+        Ok(Self { senders })
+    }
+    pub fn to_extension(&self) -> Extension {
+        let mut extension_data: Vec<u8> = vec![];
+        encode_vec(VecSize::VecU16, &mut extension_data, &self.senders).unwrap();
+        let extension_type = ExtensionType::ExternalSenders;
+        Extension {
+            extension_type,
+            extension_data,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct KeyPackageId {
+    uuid: Uuid,
+}
+
+impl KeyPackageId {
+    pub fn new() -> Self {
+        let uuid = Uuid::from_slice(&randombytes(16)).unwrap();
+        Self { uuid }
+    }
+    pub fn from_slice(bytes: &[u8]) -> Self {
+        let uuid = Uuid::from_slice(bytes).unwrap();
+        Self { uuid }
+    }
+    pub fn to_vec(&self) -> Vec<u8> {
+        let bytes = self.uuid.as_bytes();
+        bytes.to_vec()
+    }
+}
+
+impl Codec for KeyPackageId {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        encode_vec(VecSize::VecU8, buffer, &self.to_vec())?;
+        Ok(())
+    }
+
+    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+    //     let bytes = decode_vec(VecSize::VecU8, cursor)?;
+    //     let id = KeyPackageId::from_slice(&bytes);
+    //     Ok(id)
+    // }
+}
+
+#[test]
+fn test_protocol_version() {
+    let mls10_version = ProtocolVersion::Mls10;
+    let default_version = ProtocolVersion::Default;
+    let mls10_e = mls10_version.encode_detached().unwrap();
+    assert_eq!(mls10_e[0], mls10_version as u8);
+    let default_e = default_version.encode_detached().unwrap();
+    assert_eq!(default_e[0], default_version as u8);
+    assert_eq!(mls10_e[0], 0);
+    assert_eq!(default_e[0], 255);
+}
+
+#[test]
+fn test_extension_codec() {
+    use crate::key_packages::*;
+
+    let capabilities_extension = CapabilitiesExtension::new(
+        SUPPORTED_PROTOCOL_VERSIONS.to_vec(),
+        CIPHERSUITES.to_vec(),
+        SUPPORTED_EXTENSIONS.to_vec(),
+    );
+    let extension = capabilities_extension.to_extension();
+    let bytes = extension.encode_detached().unwrap();
+    // let _dec = Extension::decode(&mut Cursor::new(&bytes));
+}
+
+#[test]
+fn test_malformed_extension_data() {
+    use crate::key_packages::*;
+
+    // Valid data round-trips
+    let capabilities_extension = CapabilitiesExtension::new(
+        SUPPORTED_PROTOCOL_VERSIONS.to_vec(),
+        CIPHERSUITES.to_vec(),
+        SUPPORTED_EXTENSIONS.to_vec(),
+    );
+    let extension = capabilities_extension.to_extension();
+    assert_eq!(
+        CapabilitiesExtension::try_from_bytes(&extension.extension_data),
+        Ok(capabilities_extension)
+    );
+    let lifetime_extension = LifetimeExtension::new(LifetimeExtension::LIFETIME_1_DAY);
+    let extension = lifetime_extension.to_extension();
+    assert_eq!(
+        LifetimeExtension::try_from_bytes(&extension.extension_data),
+        Ok(lifetime_extension)
+    );
+
+    // Truncated data
+    assert_eq!(
+        LifetimeExtension::try_from_bytes(&extension.extension_data[..12]),
+        Err(ExtensionError::InvalidExtensionData)
+    );
+    assert_eq!(
+        ParentHashExtension::try_from_bytes(&[32, 1, 2, 3]),
+        Err(ExtensionError::InvalidExtensionData)
+    );
+    assert_eq!(
+        KeyIDExtension::try_from_bytes(&[]),
+        Err(ExtensionError::InvalidExtensionData)
+    );
+
+    // Trailing data
+    let mut extension_data = extension.extension_data;
+    extension_data.push(0);
+    assert_eq!(
+        LifetimeExtension::try_from_bytes(&extension_data),
+        Err(ExtensionError::TrailingData)
+    );
+
+    // Unknown ciphersuites are rejected instead of crashing the parser
+    assert_eq!(
+        CapabilitiesExtension::try_from_bytes(&[1, 0, 2, 0xff, 0xff, 0]),
+        Err(ExtensionError::InvalidExtensionData)
+    );
+}
+
+#[test]
+fn test_lifetime_validity() {
+    struct FixedTime(u64);
+    impl TimeProvider for FixedTime {
+        fn now(&self) -> u64 {
+            self.0
+        }
+    }
+
+    let lifetime_extension = LifetimeExtension::new_with_margin(100, 10, &FixedTime(1_000));
+    assert!(!lifetime_extension.is_valid_at(989));
+    assert!(lifetime_extension.is_valid_at(990));
+    assert!(lifetime_extension.is_valid_at(1_110));
+    assert!(!lifetime_extension.is_valid_at(1_111));
+
+    // The margin doesn't underflow close to the epoch
+    let lifetime_extension = LifetimeExtension::new_with_margin(100, 10, &FixedTime(5));
+    assert!(lifetime_extension.is_valid_at(0));
+
+    // Lifetime, margin and clock are configurable
+    let mut config = LifetimeConfig::new();
+    config.set_lifetime(200);
+    config.set_margin(0);
+    config.set_time_provider(Arc::new(FixedTime(1_000)));
+    let lifetime_extension = LifetimeExtension::new_with_config(&config);
+    assert_eq!(
+        lifetime_extension,
+        LifetimeExtension::new_with_margin(200, 0, &FixedTime(1_000))
+    );
+    assert!(lifetime_extension.is_valid_with_config(&config));
+    config.set_time_provider(Arc::new(FixedTime(1_205)));
+    assert!(!lifetime_extension.is_valid_with_config(&config));
+    config.set_acceptance_margin(5);
+    assert!(lifetime_extension.is_valid_with_config(&config));
+    assert!(!lifetime_extension.is_valid_at_with_margin(994, 5));
+}
@@ -0,0 +1,432 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+//! The extension types and the parsing of extensions that don't depend on
+//! the crypto and the tree. They are also built without `std`.
+
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+use core::cmp::Ordering;
+
+use crate::codec::*;
+#[cfg(feature = "std")]
+use thiserror::Error;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ProtocolVersion {
+    Mls10 = 0,
+    Default = 255,
+}
+
+impl From<u8> for ProtocolVersion {
+    fn from(a: u8) -> ProtocolVersion {
+        match a {
+            0 => ProtocolVersion::Mls10,
+            _ => ProtocolVersion::Default,
+        }
+    }
+}
+
+impl PartialOrd for ProtocolVersion {
+    fn partial_cmp(&self, other: &ProtocolVersion) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ProtocolVersion {
+    fn cmp(&self, other: &ProtocolVersion) -> Ordering {
+        (*self as u8).cmp(&(*other as u8))
+    }
+}
+
+impl Codec for ProtocolVersion {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        (*self as u8).encode(buffer)?;
+        Ok(())
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let version = u8::decode(cursor)?;
+        Ok(version.into())
+    }
+}
+
+pub const CURRENT_PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion::Mls10;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize),
+    serde(crate = "serde_crate")
+)]
+#[repr(u16)]
+pub enum ExtensionType {
+    Invalid = 0,
+    Capabilities = 1,
+    Lifetime = 2,
+    KeyID = 3,
+    ParentHash = 4,
+    RatchetTree = 5,
+    ExternalSenders = 6,
+    ApplicationProtocol = 7,
+    DeviceCapabilities = 8,
+    Deniability = 9,
+    Default = 65535,
+}
+
+impl From<u16> for ExtensionType {
+    fn from(a: u16) -> ExtensionType {
+        match a {
+            0 => ExtensionType::Invalid,
+            1 => ExtensionType::Capabilities,
+            2 => ExtensionType::Lifetime,
+            3 => ExtensionType::KeyID,
+            4 => ExtensionType::ParentHash,
+            5 => ExtensionType::RatchetTree,
+            6 => ExtensionType::ExternalSenders,
+            7 => ExtensionType::ApplicationProtocol,
+            8 => ExtensionType::DeviceCapabilities,
+            9 => ExtensionType::Deniability,
+            _ => ExtensionType::Default,
+        }
+    }
+}
+
+impl Codec for ExtensionType {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        (*self as u16).encode(buffer)?;
+        Ok(())
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let extension = u16::decode(cursor)?;
+        Ok(extension.into())
+    }
+}
+
+/// Errors that can occur when the data of an extension is parsed.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "std", derive(Error))]
+#[non_exhaustive]
+pub enum ExtensionError {
+    /// The extension data could not be decoded
+    #[cfg_attr(feature = "std", error("the extension data could not be decoded"))]
+    InvalidExtensionData,
+    /// The extension data is longer than the encoded extension
+    #[cfg_attr(
+        feature = "std",
+        error("the extension data is longer than the extension")
+    )]
+    TrailingData,
+    /// The extension data doesn't fit into an `Extension`
+    #[cfg_attr(feature = "std", error("the extension data is too long"))]
+    ExtensionDataTooLong,
+    /// The same extension type is present more than once
+    #[cfg_attr(feature = "std", error("an extension type is present more than once"))]
+    DuplicateExtension,
+    /// A required extension is missing
+    #[cfg_attr(feature = "std", error("a required extension is missing"))]
+    MissingExtension,
+}
+
+impl From<CodecError> for ExtensionError {
+    fn from(_err: CodecError) -> ExtensionError {
+        ExtensionError::InvalidExtensionData
+    }
+}
+
+pub(crate) fn check_trailing_data(cursor: &Cursor) -> Result<(), ExtensionError> {
+    if cursor.has_more() {
+        return Err(ExtensionError::TrailingData);
+    }
+    Ok(())
+}
+
+/// Source of the current time in seconds since the Unix epoch. Used instead of
+/// the system clock where it is not available or when testing.
+pub trait TimeProvider: Send + Sync {
+    fn now(&self) -> u64;
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct LifetimeExtension {
+    pub(crate) not_before: u64,
+    pub(crate) not_after: u64,
+}
+
+impl LifetimeExtension {
+    pub const LIFETIME_1_MINUTE: u64 = 60;
+    pub const LIFETIME_1_HOUR: u64 = 60 * LifetimeExtension::LIFETIME_1_MINUTE;
+    pub const LIFETIME_1_DAY: u64 = 24 * LifetimeExtension::LIFETIME_1_HOUR;
+    pub const LIFETIME_1_WEEK: u64 = 7 * LifetimeExtension::LIFETIME_1_DAY;
+    pub const LIFETIME_4_WEEKS: u64 = 4 * LifetimeExtension::LIFETIME_1_WEEK;
+    pub const LIFETIME_MARGIN: u64 = LifetimeExtension::LIFETIME_1_HOUR;
+    /// Create a lifetime of `t` seconds starting at the current time of
+    /// `time_provider`. The lifetime is extended by `margin` seconds on both
+    /// ends to tolerate clock skew between clients.
+    pub fn new_with_margin(t: u64, margin: u64, time_provider: &dyn TimeProvider) -> Self {
+        let now = time_provider.now();
+        Self {
+            not_before: now.saturating_sub(margin),
+            not_after: now.saturating_add(t).saturating_add(margin),
+        }
+    }
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, ExtensionError> {
+        let cursor = &mut Cursor::new(bytes);
+        let not_before = u64::decode(cursor)?;
+        let not_after = u64::decode(cursor)?;
+        check_trailing_data(cursor)?;
+        Ok(Self {
+            not_before,
+            not_after,
+        })
+    }
+    pub fn to_extension(&self) -> Extension {
+        let mut extension_data: Vec<u8> = vec![];
+        self.not_before.encode(&mut extension_data).unwrap();
+        self.not_after.encode(&mut extension_data).unwrap();
+        let extension_type = ExtensionType::Lifetime;
+        Extension {
+            extension_type,
+            extension_data,
+        }
+    }
+    /// Returns `true` if `timestamp` lies within the lifetime.
+    pub fn is_valid_at(&self, timestamp: u64) -> bool {
+        self.is_valid_at_with_margin(timestamp, 0)
+    }
+    /// Returns `true` if `timestamp` lies within the lifetime extended by
+    /// `margin` seconds on both ends, to tolerate clock skew between the
+    /// creator of the lifetime and this client.
+    pub fn is_valid_at_with_margin(&self, timestamp: u64, margin: u64) -> bool {
+        self.not_before.saturating_sub(margin) <= timestamp
+            && timestamp <= self.not_after.saturating_add(margin)
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct KeyIDExtension {
+    key_id: Vec<u8>,
+}
+
+impl KeyIDExtension {
+    pub fn new(key_id: &[u8]) -> Self {
+        Self {
+            key_id: key_id.to_vec(),
+        }
+    }
+    /// Get the key ID.
+    pub fn get_key_id(&self) -> &[u8] {
+        &self.key_id
+    }
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, ExtensionError> {
+        let cursor = &mut Cursor::new(bytes);
+        let key_id = decode_vec(VecSize::VecU16, cursor)?;
+        check_trailing_data(cursor)?;
+        Ok(Self { key_id })
+    }
+    pub fn to_extension(&self) -> Extension {
+        let mut extension_data: Vec<u8> = vec![];
+        encode_vec(VecSize::VecU16, &mut extension_data, &self.key_id).unwrap();
+        let extension_type = ExtensionType::KeyID;
+        Extension {
+            extension_type,
+            extension_data,
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct ParentHashExtension {
+    pub parent_hash: Vec<u8>,
+}
+
+impl ParentHashExtension {
+    pub fn new(hash: &[u8]) -> Self {
+        ParentHashExtension {
+            parent_hash: hash.to_vec(),
+        }
+    }
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, ExtensionError> {
+        let cursor = &mut Cursor::new(bytes);
+        let parent_hash = decode_vec(VecSize::VecU8, cursor)?;
+        check_trailing_data(cursor)?;
+        Ok(Self { parent_hash })
+    }
+    pub fn to_extension(&self) -> Extension {
+        let mut extension_data: Vec<u8> = vec![];
+        encode_vec(VecSize::VecU8, &mut extension_data, &self.parent_hash).unwrap();
+        let extension_type = ExtensionType::ParentHash;
+        Extension {
+            extension_type,
+            extension_data,
+        }
+    }
+}
+
+/// Group context extension that identifies the application protocol of a
+/// group, e.g. "chat-v2". Clients that put the extension into their
+/// `KeyPackage` only join groups of the same application protocol.
+#[derive(PartialEq, Clone, Debug)]
+pub struct ApplicationProtocolExtension {
+    protocol: Vec<u8>,
+}
+
+impl ApplicationProtocolExtension {
+    pub fn new(protocol: &[u8]) -> Self {
+        Self {
+            protocol: protocol.to_vec(),
+        }
+    }
+    /// Get the identifier of the application protocol.
+    pub fn get_protocol(&self) -> &[u8] {
+        &self.protocol
+    }
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, ExtensionError> {
+        let cursor = &mut Cursor::new(bytes);
+        let protocol = decode_vec(VecSize::VecU8, cursor)?;
+        check_trailing_data(cursor)?;
+        Ok(Self { protocol })
+    }
+    pub fn to_extension(&self) -> Extension {
+        let mut extension_data: Vec<u8> = vec![];
+        encode_vec(VecSize::VecU8, &mut extension_data, &self.protocol).unwrap();
+        let extension_type = ExtensionType::ApplicationProtocol;
+        Extension {
+            extension_type,
+            extension_data,
+        }
+    }
+}
+
+/// Capability of a member's device, see `DeviceCapabilitiesExtension`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DeviceCapability {
+    /// The device is a permanent device of its user. Devices without this
+    /// capability are temporary, e.g. a browser session.
+    Permanent = 0,
+    /// The member can't be removed by Remove proposals. Only members that
+    /// have this capability can grant it to new members.
+    NonRemovableCap = 1,
+}
+
+impl Codec for DeviceCapability {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        (*self as u8).encode(buffer)?;
+        Ok(())
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        match u8::decode(cursor)? {
+            0 => Ok(DeviceCapability::Permanent),
+            1 => Ok(DeviceCapability::NonRemovableCap),
+            _ => Err(CodecError::DecodingError),
+        }
+    }
+}
+
+/// Leaf extension with the `DeviceCapability`s of a member's device. Members
+/// change their capabilities with an Update, see
+/// `validate_device_capabilities`.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct DeviceCapabilitiesExtension {
+    capabilities: Vec<DeviceCapability>,
+}
+
+impl DeviceCapabilitiesExtension {
+    pub fn new(capabilities: &[DeviceCapability]) -> Self {
+        Self {
+            capabilities: capabilities.to_vec(),
+        }
+    }
+    /// Get the capabilities of the device.
+    pub fn get_capabilities(&self) -> &[DeviceCapability] {
+        &self.capabilities
+    }
+    /// Returns `true` if the device has `capability`.
+    pub fn has(&self, capability: DeviceCapability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, ExtensionError> {
+        let cursor = &mut Cursor::new(bytes);
+        let capabilities = decode_vec(VecSize::VecU8, cursor)?;
+        check_trailing_data(cursor)?;
+        Ok(Self { capabilities })
+    }
+    pub fn to_extension(&self) -> Extension {
+        let mut extension_data: Vec<u8> = vec![];
+        encode_vec(VecSize::VecU8, &mut extension_data, &self.capabilities).unwrap();
+        let extension_type = ExtensionType::DeviceCapabilities;
+        Extension {
+            extension_type,
+            extension_data,
+        }
+    }
+}
+
+/// Group context extension that makes application messages deniable. They are
+/// not signed and only authenticated through the group's symmetric keys, so
+/// any member could have created them. Handshake messages are still signed.
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct DeniabilityExtension {}
+
+impl DeniabilityExtension {
+    pub fn new() -> Self {
+        Self {}
+    }
+    pub fn try_from_bytes(bytes: &[u8]) -> Result<Self, ExtensionError> {
+        let cursor = &mut Cursor::new(bytes);
+        check_trailing_data(cursor)?;
+        Ok(Self {})
+    }
+    pub fn to_extension(&self) -> Extension {
+        Extension {
+            extension_type: ExtensionType::Deniability,
+            extension_data: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Extension {
+    pub extension_type: ExtensionType,
+    pub extension_data: Vec<u8>,
+}
+
+impl Extension {
+    pub fn get_type(&self) -> ExtensionType {
+        self.extension_type
+    }
+}
+
+impl Codec for Extension {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        self.extension_type.encode(buffer)?;
+        encode_vec(VecSize::VecU16, buffer, &self.extension_data)?;
+        Ok(())
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let extension_type = ExtensionType::decode(cursor)?;
+        let extension_data = decode_vec(VecSize::VecU16, cursor)?;
+        Ok(Extension {
+            extension_type,
+            extension_data,
+        })
+    }
+}
@@ -23,15 +23,28 @@ use crate::schedule::*;
 use crate::tree::{astree::*, index::*};
 use crate::utils::*;
 
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use zeroize::Zeroize;
+
+/// Maximum size in bytes of the authenticated additional data (AAD) that can be
+/// attached to a message. The AAD is sent in the clear next to the ciphertext and
+/// is authenticated, but not encrypted.
+pub const MAX_AAD_SIZE: usize = u16::MAX as usize;
+
+/// Label the `MLSPlaintextTBS` is signed with, see
+/// `Ciphersuite::sign_with_label`
+const MLS_PLAINTEXT_SIGNATURE_LABEL: &str = "MLSPlaintextTBS";
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct MLSPlaintext {
-    pub group_id: GroupId,
-    pub epoch: GroupEpoch,
-    pub sender: Sender,
-    pub authenticated_data: Vec<u8>,
-    pub content_type: ContentType,
-    pub content: MLSPlaintextContentType,
-    pub signature: Signature,
+    pub(crate) group_id: GroupId,
+    pub(crate) epoch: GroupEpoch,
+    pub(crate) sender: Sender,
+    pub(crate) authenticated_data: Vec<u8>,
+    pub(crate) content_type: ContentType,
+    pub(crate) content: MLSPlaintextContentType,
+    pub(crate) signature: Signature,
 }
 
 impl MLSPlaintext {
@@ -43,10 +56,24 @@ impl MLSPlaintext {
         signature_key: &SignaturePrivateKey,
         context: &GroupContext,
     ) -> Self {
-        let sender = Sender {
-            sender_type: SenderType::Member,
-            sender,
-        };
+        Self::new_with_sender(
+            ciphersuite,
+            Sender::member(sender),
+            authenticated_data,
+            content,
+            signature_key,
+            context,
+        )
+    }
+    /// Create a new signed `MLSPlaintext` for any type of `sender`.
+    pub fn new_with_sender(
+        ciphersuite: &Ciphersuite,
+        sender: Sender,
+        authenticated_data: &[u8],
+        content: MLSPlaintextContentType,
+        signature_key: &SignaturePrivateKey,
+        context: &GroupContext,
+    ) -> Self {
         let mut mls_plaintext = MLSPlaintext {
             group_id: context.group_id.clone(),
             epoch: context.epoch,
@@ -59,16 +86,34 @@ impl MLSPlaintext {
         mls_plaintext.sign(ciphersuite, signature_key, context);
         mls_plaintext
     }
+    /// Create a new `MLSPlaintext` without a signature. Only application
+    /// messages of deniable groups are unsigned, see `DeniabilityExtension`.
+    pub fn new_unsigned(
+        sender: LeafIndex,
+        authenticated_data: &[u8],
+        content: MLSPlaintextContentType,
+        context: &GroupContext,
+    ) -> Self {
+        MLSPlaintext {
+            group_id: context.group_id.clone(),
+            epoch: context.epoch,
+            sender: Sender::member(sender),
+            authenticated_data: authenticated_data.to_vec(),
+            content_type: ContentType::from(content.clone()),
+            content,
+            signature: Signature::new_empty(),
+        }
+    }
     // XXX: Only used in tests right now.
     pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, CodecError> {
         let mut cursor = Cursor::new(bytes);
-        let group_id = GroupId::decode(&mut cursor).unwrap();
-        let epoch = GroupEpoch::decode(&mut cursor).unwrap();
-        let sender = Sender::decode(&mut cursor).unwrap();
-        let authenticated_data = decode_vec(VecSize::VecU32, &mut cursor).unwrap();
-        let content_type = ContentType::decode(&mut cursor).unwrap();
-        let content = MLSPlaintextContentType::decode(&mut cursor).unwrap();
-        let signature = Signature::decode(&mut cursor).unwrap();
+        let group_id = GroupId::decode(&mut cursor)?;
+        let epoch = GroupEpoch::decode(&mut cursor)?;
+        let sender = Sender::decode(&mut cursor)?;
+        let authenticated_data = decode_vec(VecSize::VecU32, &mut cursor)?;
+        let content_type = ContentType::decode(&mut cursor)?;
+        let content = MLSPlaintextContentType::decode(&mut cursor)?;
+        let signature = Signature::decode(&mut cursor)?;
 
         Ok(MLSPlaintext {
             group_id,
@@ -93,6 +138,277 @@ impl MLSPlaintext {
         let signature_input = MLSPlaintextTBS::new_from(&self, context);
         signature_input.verify(credential, &self.signature)
     }
+
+    /// Get the ID of the group the message was sent in.
+    pub fn group_id(&self) -> &GroupId {
+        &self.group_id
+    }
+
+    /// Get the epoch the message was sent in.
+    pub fn epoch(&self) -> GroupEpoch {
+        self.epoch
+    }
+
+    /// Get the sender of the message.
+    pub fn sender(&self) -> Sender {
+        self.sender
+    }
+
+    /// Get the authenticated additional data the sender attached to the
+    /// message, without the sequence number or the ordering token the group
+    /// put in front of it.
+    pub fn authenticated_data(&self) -> &[u8] {
+        &self.authenticated_data
+    }
+
+    /// Get the type of the content.
+    pub fn content_type(&self) -> ContentType {
+        self.content_type
+    }
+
+    /// Get the content, e.g. to match on all content types at once.
+    pub fn content(&self) -> &MLSPlaintextContentType {
+        &self.content
+    }
+
+    /// Get the signature of the message.
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// Get the application data of an application message. Returns `None`
+    /// for handshake messages.
+    pub fn as_application(&self) -> Option<&ApplicationData> {
+        self.content.as_application()
+    }
+
+    /// Get the proposal of a proposal message. Returns `None` for other
+    /// messages.
+    pub fn as_proposal(&self) -> Option<&Proposal> {
+        self.content.as_proposal()
+    }
+
+    /// Get the Commit of a commit message. Returns `None` for other
+    /// messages.
+    pub fn as_commit(&self) -> Option<&Commit> {
+        self.content.as_commit()
+    }
+
+    /// Get the confirmation tag of a commit message. Returns `None` for
+    /// other messages.
+    pub fn confirmation_tag(&self) -> Option<&ConfirmationTag> {
+        self.content.confirmation_tag()
+    }
+}
+
+/// The authenticated context of a processed message, i.e. everything but its
+/// content. Applications should rely on it instead of the fields of the
+/// `MLSPlaintext`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct AuthenticatedContent {
+    group_id: GroupId,
+    epoch: GroupEpoch,
+    sender_index: LeafIndex,
+    sender_credential: Credential,
+    content_type: ContentType,
+    authenticated_data: Vec<u8>,
+    timestamp: u64,
+}
+
+impl AuthenticatedContent {
+    /// Get the ID of the group the message was sent in.
+    pub fn group_id(&self) -> &GroupId {
+        &self.group_id
+    }
+
+    /// Get the epoch the message was sent in.
+    pub fn epoch(&self) -> GroupEpoch {
+        self.epoch
+    }
+
+    /// Get the leaf index of the member that sent the message.
+    pub fn sender_index(&self) -> LeafIndex {
+        self.sender_index
+    }
+
+    /// Get the credential of the member that sent the message.
+    pub fn sender_credential(&self) -> &Credential {
+        &self.sender_credential
+    }
+
+    /// Get the type of the message content.
+    pub fn content_type(&self) -> ContentType {
+        self.content_type
+    }
+
+    /// Get the authenticated additional data the sender attached to the
+    /// message.
+    pub fn authenticated_data(&self) -> &[u8] {
+        &self.authenticated_data
+    }
+
+    /// Get the time the message was processed at, in seconds since the Unix
+    /// epoch, as reported by the `TimeProvider` of the group. Messages don't
+    /// carry the time they were sent at.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+/// A message that was successfully decrypted and verified by the group.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProcessedMessage {
+    plaintext: MLSPlaintext,
+    authenticated_content: AuthenticatedContent,
+    // Sequence number of the message and the number of skipped sequence
+    // numbers of the sender, if sequence numbers are enabled
+    sequence_number: Option<(u64, u64)>,
+}
+
+impl ProcessedMessage {
+    pub(crate) fn new(
+        plaintext: MLSPlaintext,
+        sender_index: LeafIndex,
+        sender_credential: Credential,
+        timestamp: u64,
+    ) -> Self {
+        let authenticated_content = AuthenticatedContent {
+            group_id: plaintext.group_id.clone(),
+            epoch: plaintext.epoch,
+            sender_index,
+            sender_credential,
+            content_type: plaintext.content_type,
+            authenticated_data: plaintext.authenticated_data.clone(),
+            timestamp,
+        };
+        Self {
+            plaintext,
+            authenticated_content,
+            sequence_number: None,
+        }
+    }
+
+    pub(crate) fn set_sequence_number(&mut self, sequence_number: u64, missed_messages: u64) {
+        self.sequence_number = Some((sequence_number, missed_messages));
+    }
+
+    // Remove the sequence number or ordering token in front of the
+    // authenticated data of the application
+    pub(crate) fn strip_aad_prefix(&mut self) {
+        if let Some((_, authenticated_data)) =
+            split_sequenced_aad(&self.plaintext.authenticated_data)
+        {
+            self.authenticated_content.authenticated_data = authenticated_data.to_vec();
+        }
+    }
+
+    /// Get the authenticated context of the message.
+    pub fn authenticated_content(&self) -> &AuthenticatedContent {
+        &self.authenticated_content
+    }
+
+    /// Get the authenticated additional data of the message as it was sent.
+    /// If sequence numbers are enabled, it starts with the sequence number.
+    /// Use `AuthenticatedContent::authenticated_data` to get the data of the
+    /// application alone.
+    pub fn aad(&self) -> &[u8] {
+        &self.plaintext.authenticated_data
+    }
+
+    /// Get the sequence number of an application message. Returns `None` if
+    /// sequence numbers are not enabled and for handshake messages.
+    pub fn sequence_number(&self) -> Option<u64> {
+        self.sequence_number
+            .map(|(sequence_number, _)| sequence_number)
+    }
+
+    /// Get the number of sequence numbers of the sender that were skipped
+    /// since its last message, i.e. messages that were dropped or are still
+    /// in flight. Returns 0 for the first message of a sender and if sequence
+    /// numbers are not enabled.
+    pub fn missed_messages(&self) -> u64 {
+        self.sequence_number
+            .map(|(_, missed_messages)| missed_messages)
+            .unwrap_or(0)
+    }
+
+    /// Get the leaf index of the member that sent the message.
+    pub fn sender_index(&self) -> LeafIndex {
+        self.authenticated_content.sender_index
+    }
+
+    /// Get the credential of the member that sent the message.
+    pub fn sender_credential(&self) -> &Credential {
+        &self.authenticated_content.sender_credential
+    }
+
+    /// Get the payload of an application message. Returns `None` for
+    /// handshake messages.
+    pub fn application_data(&self) -> Option<&[u8]> {
+        match &self.plaintext.content {
+            MLSPlaintextContentType::Application(application_data) => Some(&application_data.data),
+            _ => None,
+        }
+    }
+
+    /// Get the application-defined content type of an application message.
+    /// Returns `None` for handshake messages and for application messages
+    /// that were sent without a content type.
+    pub fn application_content_type(&self) -> Option<u16> {
+        match &self.plaintext.content {
+            MLSPlaintextContentType::Application(application_data) => application_data.content_type,
+            _ => None,
+        }
+    }
+
+    /// Get a reference to the decrypted `MLSPlaintext`.
+    pub fn plaintext(&self) -> &MLSPlaintext {
+        &self.plaintext
+    }
+
+    /// Consume the message and return the decrypted `MLSPlaintext`.
+    pub fn into_plaintext(self) -> MLSPlaintext {
+        self.plaintext
+    }
+}
+
+/// A MAC over an encoded `MLSCiphertext` under the epoch's membership key.
+/// It proves to anyone holding the membership key (e.g. the delivery service)
+/// that the message was sent by a member of the current epoch.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MembershipTag(pub Vec<u8>);
+
+impl MembershipTag {
+    /// Compute the membership tag of `mls_ciphertext`.
+    pub fn new(
+        ciphersuite: &Ciphersuite,
+        membership_key: &[u8],
+        mls_ciphertext: &MLSCiphertext,
+    ) -> Self {
+        let serialized_ciphertext = mls_ciphertext.encode_detached().unwrap();
+        MembershipTag(ciphersuite.mac(membership_key, &serialized_ciphertext))
+    }
+    /// Verify the membership tag against `mls_ciphertext`.
+    pub fn verify(
+        &self,
+        ciphersuite: &Ciphersuite,
+        membership_key: &[u8],
+        mls_ciphertext: &MLSCiphertext,
+    ) -> bool {
+        let tag = Self::new(ciphersuite, membership_key, mls_ciphertext);
+        equal_ct(&tag.0, &self.0)
+    }
+}
+
+impl Codec for MembershipTag {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        encode_vec(VecSize::VecU8, buffer, &self.0)?;
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let inner = decode_vec(VecSize::VecU8, cursor)?;
+        Ok(MembershipTag(inner))
+    }
 }
 
 impl Codec for MLSPlaintext {
@@ -107,13 +423,13 @@ impl Codec for MLSPlaintext {
         Ok(())
     }
     fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-        let group_id = GroupId::decode(cursor).unwrap();
-        let epoch = GroupEpoch::decode(cursor).unwrap();
-        let sender = Sender::decode(cursor).unwrap();
-        let authenticated_data = decode_vec(VecSize::VecU32, cursor).unwrap();
-        let content_type = ContentType::decode(cursor).unwrap();
-        let content = MLSPlaintextContentType::decode(cursor).unwrap();
-        let signature = Signature::decode(cursor).unwrap();
+        let group_id = GroupId::decode(cursor)?;
+        let epoch = GroupEpoch::decode(cursor)?;
+        let sender = Sender::decode(cursor)?;
+        let authenticated_data = decode_vec(VecSize::VecU32, cursor)?;
+        let content_type = ContentType::decode(cursor)?;
+        let content = MLSPlaintextContentType::decode(cursor)?;
+        let signature = Signature::decode(cursor)?;
 
         Ok(MLSPlaintext {
             group_id,
@@ -171,27 +487,12 @@ impl MLSCiphertext {
             Some(mls_plaintext) => mls_plaintext.sender.encode_detached().unwrap(),
             None => sender_data.sender.encode_detached().unwrap(),
         };
-        let mut handshake_nonce_input = hkdf_expand_label(
-            ciphersuite,
-            &epoch_secrets.handshake_secret,
-            "hs nonce",
-            &sender_id,
-            ciphersuite.aead_nonce_length(),
-        );
-        let reuse_guard = sender_data.reuse_guard.encode_detached().unwrap();
-        for i in 0..4 {
-            handshake_nonce_input[i] ^= reuse_guard[i];
-        }
-        let handshake_nonce = AeadNonce::from_slice(&handshake_nonce_input);
-        let handshake_key_input = hkdf_expand_label(
-            ciphersuite,
-            &epoch_secrets.handshake_secret,
-            "hs key",
-            &sender_id,
-            ciphersuite.aead_key_length(),
-        );
-        let handshake_key = AeadKey::from_slice(&handshake_key_input);
-        (handshake_key, handshake_nonce)
+        let (handshake_key, handshake_nonce) =
+            derive_handshake_key(ciphersuite, epoch_secrets, &sender_id);
+        (
+            AeadKey::from_slice(&handshake_key),
+            apply_reuse_guard(&handshake_nonce, sender_data.reuse_guard),
+        )
     }
     pub fn new_from_plaintext(
         mls_plaintext: &MLSPlaintext,
@@ -199,11 +500,35 @@ impl MLSCiphertext {
         generation: u32,
         application_secrets: &ApplicationSecrets,
     ) -> MLSCiphertext {
-        const PADDING_SIZE: usize = 10;
-
         let ciphersuite = mls_group.get_ciphersuite();
         let context = mls_group.get_context();
-        let epoch_secrets = mls_group.get_epoch_secrets();
+        let sender_keys = SenderKeys::derive(
+            ciphersuite,
+            mls_group.get_epoch_secrets(),
+            context.epoch,
+            &mls_plaintext.sender,
+        );
+        Self::new_from_plaintext_with_keys(
+            mls_plaintext,
+            ciphersuite,
+            context,
+            generation,
+            application_secrets,
+            &sender_keys,
+        )
+    }
+
+    /// Like `new_from_plaintext`, but with the `SenderKeys` of the sender,
+    /// e.g. from the group's `SenderKeyCache`.
+    pub(crate) fn new_from_plaintext_with_keys(
+        mls_plaintext: &MLSPlaintext,
+        ciphersuite: &Ciphersuite,
+        context: &GroupContext,
+        generation: u32,
+        application_secrets: &ApplicationSecrets,
+        sender_keys: &SenderKeys,
+    ) -> MLSCiphertext {
+        const PADDING_SIZE: usize = 10;
 
         match mls_plaintext.content_type {
             ContentType::Application => {}
@@ -212,15 +537,8 @@ impl MLSCiphertext {
             _ => {}
         }
         let sender_data = MLSSenderData::new(mls_plaintext.sender.sender, generation);
-        let sender_data_key_bytes = hkdf_expand_label(
-            ciphersuite,
-            &epoch_secrets.sender_data_secret,
-            "sd key",
-            &[],
-            ciphersuite.aead_key_length(),
-        );
         let sender_data_nonce = AeadNonce::random();
-        let sender_data_key = AeadKey::from_slice(&sender_data_key_bytes);
+        let sender_data_key = AeadKey::from_slice(&sender_keys.sender_data_key);
         let mls_ciphertext_sender_data_aad = MLSCiphertextSenderDataAAD::new(
             context.group_id.clone(),
             context.epoch,
@@ -274,27 +592,22 @@ impl MLSCiphertext {
             padding: padding_block,
         };
 
-        let (k1, n1) = Self::compute_handshake_key(
-            &ciphersuite,
-            epoch_secrets,
-            &sender_data,
-            Some(mls_plaintext),
-        );
-        let (key, nonce) = match mls_plaintext.content_type {
-            ContentType::Application => (
+        let content_bytes = mls_ciphertext_content.encode_detached().unwrap();
+        let ciphertext = match mls_plaintext.content_type {
+            ContentType::Application => ciphersuite.aead_seal(
+                &content_bytes,
+                &mls_ciphertext_content_aad_bytes,
                 application_secrets.get_key(),
                 application_secrets.get_nonce(),
             ),
-            _ => (&k1, &n1),
-        };
-        let ciphertext = ciphersuite
-            .aead_seal(
-                &mls_ciphertext_content.encode_detached().unwrap(),
+            _ => ciphersuite.aead_seal(
+                &content_bytes,
                 &mls_ciphertext_content_aad_bytes,
-                key,
-                nonce,
-            )
-            .unwrap();
+                &AeadKey::from_slice(&sender_keys.handshake_key),
+                &apply_reuse_guard(&sender_keys.handshake_nonce, sender_data.reuse_guard),
+            ),
+        }
+        .unwrap();
         MLSCiphertext {
             group_id: context.group_id.clone(),
             epoch: context.epoch,
@@ -309,20 +622,44 @@ impl MLSCiphertext {
     pub fn to_plaintext(
         &self,
         ciphersuite: &Ciphersuite,
-        roster: &[&Credential],
+        roster: &[Option<&Credential>],
         epoch_secrets: &EpochSecrets,
         astree: &mut ASTree,
         context: &GroupContext,
-    ) -> MLSPlaintext {
-        let sender_data_nonce = AeadNonce::from_slice(&self.sender_data_nonce);
-        let sender_data_key_bytes = hkdf_expand_label(
+    ) -> Result<MLSPlaintext, DecryptionError> {
+        let sender_data = self.decrypt_sender_data(ciphersuite, epoch_secrets, context)?;
+        let credential = roster
+            .get(sender_data.sender.as_usize())
+            .and_then(|credential| *credential)
+            .ok_or(DecryptionError::UnknownSender)?;
+        let application_secrets = sender_data.get_application_secrets(ciphersuite, astree)?;
+        let mls_plaintext = self.decrypt_content(
             ciphersuite,
-            &epoch_secrets.sender_data_secret,
-            "sd key",
-            &[],
-            ciphersuite.aead_key_length(),
-        );
-        let sender_data_key = AeadKey::from_slice(&sender_data_key_bytes);
+            credential,
+            epoch_secrets,
+            &sender_data,
+            &application_secrets,
+            context,
+        )?;
+        // Messages that can't be decrypted or verified don't move the window
+        sender_data.advance_sender_ratchet(ciphersuite, astree)?;
+        Ok(mls_plaintext)
+    }
+
+    /// Decrypt the sender data with the sender data secret of the current
+    /// epoch.
+    pub(crate) fn decrypt_sender_data(
+        &self,
+        ciphersuite: &Ciphersuite,
+        epoch_secrets: &EpochSecrets,
+        context: &GroupContext,
+    ) -> Result<MLSSenderData, DecryptionError> {
+        if self.group_id != context.group_id || self.epoch != context.epoch {
+            return Err(DecryptionError::WrongEpoch);
+        }
+        let sender_data_nonce = AeadNonce::from_slice(&self.sender_data_nonce);
+        let sender_data_key =
+            AeadKey::from_slice(&derive_sender_data_key(ciphersuite, epoch_secrets));
         let mls_ciphertext_sender_data_aad = MLSCiphertextSenderDataAAD::new(
             self.group_id.clone(),
             self.epoch,
@@ -339,11 +676,23 @@ impl MLSCiphertext {
                 &sender_data_key,
                 &sender_data_nonce,
             )
-            .unwrap();
-        let sender_data = MLSSenderData::from_bytes(&sender_data_bytes).unwrap();
-        let application_secrets = astree
-            .get_secret(ciphersuite, sender_data.sender, sender_data.generation)
-            .unwrap();
+            .map_err(|_| DecryptionError::AeadError)?;
+        MLSSenderData::from_bytes(&sender_data_bytes).map_err(|_| DecryptionError::AeadError)
+    }
+
+    /// Decrypt the content of the message and verify its signature with the
+    /// sender's `credential`. Doesn't modify the group state, so messages can
+    /// be decrypted in parallel once their `application_secrets` are known.
+    pub(crate) fn decrypt_content(
+        &self,
+        ciphersuite: &Ciphersuite,
+        credential: &Credential,
+        epoch_secrets: &EpochSecrets,
+        sender_data: &MLSSenderData,
+        application_secrets: &ApplicationSecrets,
+        context: &GroupContext,
+    ) -> Result<MLSPlaintext, DecryptionError> {
+        let sender_data_nonce = AeadNonce::from_slice(&self.sender_data_nonce);
         let mls_ciphertext_content_aad = MLSCiphertextContentAAD {
             group_id: self.group_id.clone(),
             epoch: self.epoch,
@@ -354,7 +703,7 @@ impl MLSCiphertext {
         };
         let mls_ciphertext_content_aad_bytes =
             mls_ciphertext_content_aad.encode_detached().unwrap();
-        let (k1, n1) = Self::compute_handshake_key(&ciphersuite, epoch_secrets, &sender_data, None);
+        let (k1, n1) = Self::compute_handshake_key(&ciphersuite, epoch_secrets, sender_data, None);
         let (key, nonce) = match self.content_type {
             ContentType::Application => (
                 application_secrets.get_key(),
@@ -369,9 +718,13 @@ impl MLSCiphertext {
                 key,
                 nonce,
             )
-            .unwrap();
+            .map_err(|_| DecryptionError::AeadError)?;
         let mls_ciphertext_content =
-            MLSCiphertextContent::from_bytes(&mls_ciphertext_content_bytes).unwrap();
+            MLSCiphertextContent::from_bytes(&mls_ciphertext_content_bytes)
+                .map_err(|_| DecryptionError::InvalidContentType)?;
+        if ContentType::from(mls_ciphertext_content.content.clone()) != self.content_type {
+            return Err(DecryptionError::InvalidContentType);
+        }
         let sender = Sender {
             sender_type: SenderType::Member,
             sender: sender_data.sender,
@@ -385,9 +738,13 @@ impl MLSCiphertext {
             content: mls_ciphertext_content.content,
             signature: mls_ciphertext_content.signature,
         };
-        let credential = &roster.get(sender_data.sender.as_usize()).unwrap();
-        assert!(mls_plaintext.verify(context, credential));
-        mls_plaintext
+        // Application messages of deniable groups are only authenticated
+        // through the AEAD
+        let deniable = self.content_type == ContentType::Application && context.is_deniable();
+        if !deniable && !mls_plaintext.verify(context, credential) {
+            return Err(DecryptionError::InvalidSignature);
+        }
+        Ok(mls_plaintext)
     }
 }
 
@@ -423,13 +780,72 @@ impl Codec for MLSCiphertext {
     }
 }
 
+/// The content type found in the header of a message by
+/// `parse_message_header`. It is not authenticated until the message is
+/// verified or decrypted, so it must only be used for routing.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ContentTypeHint {
+    /// The content type of an `MLSPlaintext`
+    Plaintext(ContentType),
+    /// The content type of an `MLSCiphertext`
+    Ciphertext(ContentType),
+}
+
+impl ContentTypeHint {
+    /// Get the content type regardless of the wire format.
+    pub fn content_type(&self) -> ContentType {
+        match self {
+            ContentTypeHint::Plaintext(content_type)
+            | ContentTypeHint::Ciphertext(content_type) => *content_type,
+        }
+    }
+
+    /// Returns `true` if the message is an `MLSCiphertext`.
+    pub fn is_encrypted(&self) -> bool {
+        matches!(self, ContentTypeHint::Ciphertext(_))
+    }
+}
+
+// Wire formats in front of an encoded message, as used by `MLSMessage` of the
+// `transport` module
+const WIRE_FORMAT_PLAINTEXT: u8 = 1;
+const WIRE_FORMAT_CIPHERTEXT: u8 = 2;
+
+/// Parse the unencrypted header of a message without decoding the rest of
+/// it. This lets servers and clients with many groups route a message to its
+/// group and epoch cheaply. `bytes` has to start with the wire format of the
+/// message like an encoded `transport::MLSMessage`, i.e. 1 for an
+/// `MLSPlaintext` and 2 for an `MLSCiphertext`. Other messages are rejected.
+pub fn parse_message_header(
+    bytes: &[u8],
+) -> Result<(GroupId, GroupEpoch, ContentTypeHint), CodecError> {
+    let cursor = &mut Cursor::new(bytes);
+    let wire_format = u8::decode(cursor)?;
+    let group_id = GroupId::decode(cursor)?;
+    let epoch = GroupEpoch::decode(cursor)?;
+    let content_type_hint = match wire_format {
+        WIRE_FORMAT_PLAINTEXT => {
+            // The sender and the authenticated data come before the content
+            // type of an `MLSPlaintext`
+            Sender::decode(cursor)?;
+            let aad_length = u32::decode(cursor)?;
+            cursor.consume(aad_length as usize)?;
+            ContentTypeHint::Plaintext(ContentType::decode(cursor)?)
+        }
+        WIRE_FORMAT_CIPHERTEXT => ContentTypeHint::Ciphertext(ContentType::decode(cursor)?),
+        _ => return Err(CodecError::DecodingError),
+    };
+    Ok((group_id, epoch, content_type_hint))
+}
+
 #[derive(PartialEq, Clone, Copy, Debug)]
 #[repr(u8)]
 pub enum SenderType {
     Invalid = 0,
     Member = 1,
-    Preconfigured = 2,
-    NewMember = 3,
+    External = 2,
+    NewMemberProposal = 3,
+    NewMemberCommit = 4,
     Default = 255,
 }
 
@@ -438,8 +854,9 @@ impl From<u8> for SenderType {
         match value {
             0 => SenderType::Invalid,
             1 => SenderType::Member,
-            2 => SenderType::Preconfigured,
-            3 => SenderType::NewMember,
+            2 => SenderType::External,
+            3 => SenderType::NewMemberProposal,
+            4 => SenderType::NewMemberCommit,
             _ => SenderType::Default,
         }
     }
@@ -455,6 +872,9 @@ impl Codec for SenderType {
     }
 }
 
+/// The sender of an `MLSPlaintext`. Depending on the `sender_type`, `sender`
+/// is the leaf index of a member, the index of a preconfigured sender in the
+/// `ExternalSendersExtension`, or unused for new members.
 #[derive(PartialEq, Clone, Copy, Debug)]
 pub struct Sender {
     pub sender_type: SenderType,
@@ -468,11 +888,34 @@ impl Sender {
             sender,
         }
     }
+    pub fn external(sender_index: u32) -> Self {
+        Sender {
+            sender_type: SenderType::External,
+            sender: LeafIndex::from(sender_index),
+        }
+    }
+    pub fn new_member_proposal() -> Self {
+        Sender {
+            sender_type: SenderType::NewMemberProposal,
+            sender: LeafIndex::from(0u32),
+        }
+    }
+    pub fn new_member_commit() -> Self {
+        Sender {
+            sender_type: SenderType::NewMemberCommit,
+            sender: LeafIndex::from(0u32),
+        }
+    }
+    pub fn is_member(&self) -> bool {
+        self.sender_type == SenderType::Member
+    }
     pub fn as_leaf_index(&self) -> LeafIndex {
         self.sender
     }
-    pub fn as_node_index(self) -> NodeIndex {
-        NodeIndex::from(self.sender)
+    /// Get the node index of the sender's leaf. Returns `None` if the leaf
+    /// index is beyond the largest tree.
+    pub fn as_node_index(self) -> Option<NodeIndex> {
+        NodeIndex::try_from(self.sender).ok()
     }
 }
 
@@ -534,20 +977,88 @@ impl Codec for ContentType {
     }
 }
 
+/// The content of an application message. Applications can tag the payload
+/// with their own content type code, e.g. to tell text messages, read receipts
+/// and control messages apart. The code is encrypted like the payload.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ApplicationData {
+    pub data: Vec<u8>,
+    pub content_type: Option<u16>,
+}
+
+impl ApplicationData {
+    pub fn new(data: Vec<u8>, content_type: Option<u16>) -> Self {
+        ApplicationData { data, content_type }
+    }
+}
+
+impl From<Vec<u8>> for ApplicationData {
+    fn from(data: Vec<u8>) -> Self {
+        ApplicationData::new(data, None)
+    }
+}
+
+impl Codec for ApplicationData {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        encode_vec(VecSize::VecU32, buffer, &self.data)?;
+        self.content_type.encode(buffer)?;
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let data = decode_vec(VecSize::VecU32, cursor)?;
+        let content_type = Option::<u16>::decode(cursor)?;
+        Ok(ApplicationData { data, content_type })
+    }
+}
+
 #[allow(clippy::large_enum_variant)]
 #[derive(Debug, PartialEq, Clone)]
 pub enum MLSPlaintextContentType {
-    Application(Vec<u8>),
+    Application(ApplicationData),
     Proposal(Proposal),
     Commit((Commit, ConfirmationTag)),
 }
 
+impl MLSPlaintextContentType {
+    /// Get the application data if this is an application message.
+    pub fn as_application(&self) -> Option<&ApplicationData> {
+        match self {
+            MLSPlaintextContentType::Application(application_data) => Some(application_data),
+            _ => None,
+        }
+    }
+
+    /// Get the proposal if this is a proposal.
+    pub fn as_proposal(&self) -> Option<&Proposal> {
+        match self {
+            MLSPlaintextContentType::Proposal(proposal) => Some(proposal),
+            _ => None,
+        }
+    }
+
+    /// Get the Commit if this is a Commit.
+    pub fn as_commit(&self) -> Option<&Commit> {
+        match self {
+            MLSPlaintextContentType::Commit((commit, _)) => Some(commit),
+            _ => None,
+        }
+    }
+
+    /// Get the confirmation tag if this is a Commit.
+    pub fn confirmation_tag(&self) -> Option<&ConfirmationTag> {
+        match self {
+            MLSPlaintextContentType::Commit((_, confirmation_tag)) => Some(confirmation_tag),
+            _ => None,
+        }
+    }
+}
+
 impl Codec for MLSPlaintextContentType {
     fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
         match self {
             MLSPlaintextContentType::Application(application_data) => {
                 ContentType::Application.encode(buffer)?;
-                encode_vec(VecSize::VecU32, buffer, application_data)?;
+                application_data.encode(buffer)?;
             }
             MLSPlaintextContentType::Proposal(proposal) => {
                 ContentType::Proposal.encode(buffer)?;
@@ -565,7 +1076,7 @@ impl Codec for MLSPlaintextContentType {
         let content_type = ContentType::from(u8::decode(cursor)?);
         match content_type {
             ContentType::Application => {
-                let application_data = decode_vec(VecSize::VecU32, cursor)?;
+                let application_data = ApplicationData::decode(cursor)?;
                 Ok(MLSPlaintextContentType::Application(application_data))
             }
             ContentType::Proposal => {
@@ -582,26 +1093,33 @@ impl Codec for MLSPlaintextContentType {
     }
 }
 
+/// The signed part of an `MLSPlaintext`. The group context is only bound to the
+/// signature if the sender is a member of the group, or joins it with the
+/// `Commit` (as only they know it).
 pub struct MLSPlaintextTBS {
-    pub context: GroupContext,
     pub group_id: GroupId,
     pub epoch: GroupEpoch,
-    pub sender: LeafIndex,
+    pub sender: Sender,
     pub authenticated_data: Vec<u8>,
     pub content_type: ContentType,
     pub payload: MLSPlaintextContentType,
+    pub context: Option<GroupContext>,
 }
 
 impl MLSPlaintextTBS {
     pub fn new_from(mls_plaintext: &MLSPlaintext, context: &GroupContext) -> Self {
+        let context = match mls_plaintext.sender.sender_type {
+            SenderType::Member | SenderType::NewMemberCommit => Some(context.clone()),
+            _ => None,
+        };
         MLSPlaintextTBS {
-            context: context.clone(),
             group_id: mls_plaintext.group_id.clone(),
             epoch: mls_plaintext.epoch,
-            sender: mls_plaintext.sender.sender,
+            sender: mls_plaintext.sender,
             authenticated_data: mls_plaintext.authenticated_data.clone(),
             content_type: mls_plaintext.content_type,
             payload: mls_plaintext.content.clone(),
+            context,
         }
     }
     pub fn sign(
@@ -610,42 +1128,49 @@ impl MLSPlaintextTBS {
         signature_key: &SignaturePrivateKey,
     ) -> Signature {
         let bytes = self.encode_detached().unwrap();
-        ciphersuite.sign(signature_key, &bytes).unwrap()
+        ciphersuite
+            .sign_with_label(signature_key, MLS_PLAINTEXT_SIGNATURE_LABEL, &bytes)
+            .unwrap()
     }
     pub fn verify(&self, credential: &Credential, signature: &Signature) -> bool {
         let bytes = self.encode_detached().unwrap();
-        credential.verify(&bytes, &signature)
+        credential.verify_with_label(MLS_PLAINTEXT_SIGNATURE_LABEL, &bytes, &signature)
     }
 }
 
 impl Codec for MLSPlaintextTBS {
     fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
-        self.context.encode(buffer)?;
         self.group_id.encode(buffer)?;
         self.epoch.encode(buffer)?;
         self.sender.encode(buffer)?;
         encode_vec(VecSize::VecU32, buffer, &self.authenticated_data)?;
         self.content_type.encode(buffer)?;
         self.payload.encode(buffer)?;
+        if let Some(context) = &self.context {
+            context.encode(buffer)?;
+        }
         Ok(())
     }
     fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-        let context = GroupContext::decode(cursor)?;
         let group_id = GroupId::decode(cursor)?;
         let epoch = GroupEpoch::decode(cursor)?;
-        let sender = LeafIndex::from(u32::decode(cursor)?);
+        let sender = Sender::decode(cursor)?;
         let authenticated_data = decode_vec(VecSize::VecU32, cursor)?;
         let content_type = ContentType::decode(cursor)?;
         let payload = MLSPlaintextContentType::decode(cursor)?;
+        let context = match sender.sender_type {
+            SenderType::Member | SenderType::NewMemberCommit => Some(GroupContext::decode(cursor)?),
+            _ => None,
+        };
 
         Ok(MLSPlaintextTBS {
-            context,
             group_id,
             epoch,
             sender,
             authenticated_data,
             content_type,
             payload,
+            context,
         })
     }
 }
@@ -693,6 +1218,154 @@ impl Codec for MLSSenderData {
     }
 }
 
+/// Derive the key that protects the sender data of the epoch.
+fn derive_sender_data_key(ciphersuite: &Ciphersuite, epoch_secrets: &EpochSecrets) -> Vec<u8> {
+    hkdf_expand_label(
+        ciphersuite,
+        &epoch_secrets.sender_data_secret,
+        "sd key",
+        &[],
+        ciphersuite.aead_key_length(),
+    )
+}
+
+/// Derive the handshake key and the handshake nonce of `sender_id` before
+/// the reuse guard is applied.
+fn derive_handshake_key(
+    ciphersuite: &Ciphersuite,
+    epoch_secrets: &EpochSecrets,
+    sender_id: &[u8],
+) -> (Vec<u8>, Vec<u8>) {
+    let handshake_nonce = hkdf_expand_label(
+        ciphersuite,
+        &epoch_secrets.handshake_secret,
+        "hs nonce",
+        sender_id,
+        ciphersuite.aead_nonce_length(),
+    );
+    let handshake_key = hkdf_expand_label(
+        ciphersuite,
+        &epoch_secrets.handshake_secret,
+        "hs key",
+        sender_id,
+        ciphersuite.aead_key_length(),
+    );
+    (handshake_key, handshake_nonce)
+}
+
+fn apply_reuse_guard(handshake_nonce: &[u8], reuse_guard: u32) -> AeadNonce {
+    let mut nonce = handshake_nonce.to_vec();
+    for (nonce_byte, guard_byte) in nonce.iter_mut().zip(reuse_guard.to_be_bytes().iter()) {
+        *nonce_byte ^= guard_byte;
+    }
+    AeadNonce::from_slice(&nonce)
+}
+
+/// Keys of a sender that are derived from the epoch secrets and are the
+/// same for every message the sender encrypts in the epoch.
+pub(crate) struct SenderKeys {
+    epoch: GroupEpoch,
+    sender_id: Vec<u8>,
+    sender_data_key: Vec<u8>,
+    handshake_key: Vec<u8>,
+    handshake_nonce: Vec<u8>,
+}
+
+impl SenderKeys {
+    /// Derive the keys of `sender` from the epoch secrets of `epoch`.
+    fn derive(
+        ciphersuite: &Ciphersuite,
+        epoch_secrets: &EpochSecrets,
+        epoch: GroupEpoch,
+        sender: &Sender,
+    ) -> Self {
+        let sender_id = sender.encode_detached().unwrap();
+        let (handshake_key, handshake_nonce) =
+            derive_handshake_key(ciphersuite, epoch_secrets, &sender_id);
+        SenderKeys {
+            epoch,
+            sender_id,
+            sender_data_key: derive_sender_data_key(ciphersuite, epoch_secrets),
+            handshake_key,
+            handshake_nonce,
+        }
+    }
+}
+
+impl Drop for SenderKeys {
+    fn drop(&mut self) {
+        self.sender_data_key.zeroize();
+        self.handshake_key.zeroize();
+        self.handshake_nonce.zeroize();
+    }
+}
+
+/// Caches the values the own leaf derives to encrypt messages in the current
+/// epoch: the `SenderKeys`, which are the same for every message, and the
+/// `ApplicationSecrets` of the sender ratchet, keyed by generation. Secrets
+/// of generations before the one in use are dropped, and everything is
+/// bound to the epoch it was derived in. The group calls `wipe` when it
+/// moves to a new epoch.
+#[derive(Default)]
+pub(crate) struct SenderKeyCache {
+    sender_keys: Option<SenderKeys>,
+    application_secrets: BTreeMap<u32, ApplicationSecrets>,
+}
+
+impl SenderKeyCache {
+    /// Get the keys of `sender` in `epoch` and its application secrets of
+    /// `generation`. Values that are not cached yet are derived, the
+    /// application secrets with `derive_application_secrets`. Cached values
+    /// of another epoch or sender are replaced.
+    pub(crate) fn get_or_derive<E>(
+        &mut self,
+        ciphersuite: &Ciphersuite,
+        epoch_secrets: &EpochSecrets,
+        epoch: GroupEpoch,
+        sender: &Sender,
+        generation: u32,
+        derive_application_secrets: impl FnOnce() -> Result<ApplicationSecrets, E>,
+    ) -> Result<(&SenderKeys, &ApplicationSecrets), E> {
+        let sender_id = sender.encode_detached().unwrap();
+        let cached = match &self.sender_keys {
+            Some(sender_keys) => sender_keys.epoch == epoch && sender_keys.sender_id == sender_id,
+            None => false,
+        };
+        if !cached {
+            self.wipe();
+            self.sender_keys = Some(SenderKeys::derive(
+                ciphersuite,
+                epoch_secrets,
+                epoch,
+                sender,
+            ));
+        }
+        if !self.application_secrets.contains_key(&generation) {
+            let application_secrets = derive_application_secrets()?;
+            // The own sender ratchet never moves backwards
+            self.application_secrets = self.application_secrets.split_off(&generation);
+            self.application_secrets
+                .insert(generation, application_secrets);
+        }
+        Ok((
+            self.sender_keys.as_ref().unwrap(),
+            &self.application_secrets[&generation],
+        ))
+    }
+
+    /// Get the number of generations whose application secrets are cached.
+    #[cfg(test)]
+    pub(crate) fn cached_generations(&self) -> usize {
+        self.application_secrets.len()
+    }
+
+    /// Drop the cached keys and secrets and overwrite them with zeros.
+    pub(crate) fn wipe(&mut self) {
+        self.sender_keys = None;
+        self.application_secrets.clear();
+    }
+}
+
 impl MLSSenderData {
     pub fn new(sender: LeafIndex, generation: u32) -> Self {
         MLSSenderData {
@@ -701,6 +1374,27 @@ impl MLSSenderData {
             reuse_guard: random_u32(),
         }
     }
+
+    /// Derive the secrets of the sender's generation from `astree` without
+    /// advancing the sender ratchet.
+    pub(crate) fn get_application_secrets(
+        &self,
+        ciphersuite: &Ciphersuite,
+        astree: &mut ASTree,
+    ) -> Result<ApplicationSecrets, DecryptionError> {
+        Ok(astree.peek_secret(ciphersuite, self.sender, self.generation)?)
+    }
+
+    /// Advance the sender ratchet in `astree` to the sender's generation once
+    /// the message was decrypted and verified.
+    pub(crate) fn advance_sender_ratchet(
+        &self,
+        ciphersuite: &Ciphersuite,
+        astree: &mut ASTree,
+    ) -> Result<(), DecryptionError> {
+        astree.get_secret(ciphersuite, self.sender, self.generation)?;
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -825,20 +1519,28 @@ impl Codec for MLSCiphertextContentAAD {
     }
 }
 
+/// The part of a `Commit` message that goes into the confirmed transcript hash.
 pub struct MLSPlaintextCommitContent {
     group_id: GroupId,
     epoch: GroupEpoch,
     sender: Sender,
+    authenticated_data: Vec<u8>,
     content_type: ContentType,
     commit: Commit,
 }
 
 impl MLSPlaintextCommitContent {
-    pub fn new(group_context: &GroupContext, sender: LeafIndex, commit: Commit) -> Self {
+    pub fn new(
+        group_context: &GroupContext,
+        sender: LeafIndex,
+        authenticated_data: &[u8],
+        commit: Commit,
+    ) -> Self {
         MLSPlaintextCommitContent {
             group_id: group_context.group_id.clone(),
             epoch: group_context.epoch,
             sender: Sender::member(sender),
+            authenticated_data: authenticated_data.to_vec(),
             content_type: ContentType::Commit,
             commit,
         }
@@ -855,6 +1557,7 @@ impl From<MLSPlaintext> for MLSPlaintextCommitContent {
             group_id: mls_plaintext.group_id,
             epoch: mls_plaintext.epoch,
             sender: mls_plaintext.sender,
+            authenticated_data: mls_plaintext.authenticated_data,
             content_type: mls_plaintext.content_type,
             commit,
         }
@@ -866,6 +1569,7 @@ impl Codec for MLSPlaintextCommitContent {
         self.group_id.encode(buffer)?;
         self.epoch.encode(buffer)?;
         self.sender.encode(buffer)?;
+        encode_vec(VecSize::VecU32, buffer, &self.authenticated_data)?;
         self.content_type.encode(buffer)?;
         self.commit.encode(buffer)?;
         Ok(())
@@ -874,18 +1578,21 @@ impl Codec for MLSPlaintextCommitContent {
         let group_id = GroupId::decode(cursor)?;
         let epoch = GroupEpoch::decode(cursor)?;
         let sender = Sender::decode(cursor)?;
+        let authenticated_data = decode_vec(VecSize::VecU32, cursor)?;
         let content_type = ContentType::decode(cursor)?;
         let commit = Commit::decode(cursor)?;
         Ok(MLSPlaintextCommitContent {
             group_id,
             epoch,
             sender,
+            authenticated_data,
             content_type,
             commit,
         })
     }
 }
 
+/// The part of a `Commit` message that goes into the interim transcript hash.
 pub struct MLSPlaintextCommitAuthData {
     pub confirmation: Vec<u8>,
     pub signature: Vec<u8>,
@@ -935,7 +1642,7 @@ fn codec() {
         sender,
         authenticated_data: vec![1, 2, 3],
         content_type: ContentType::Application,
-        content: MLSPlaintextContentType::Application(vec![4, 5, 6]),
+        content: MLSPlaintextContentType::Application(ApplicationData::new(vec![4, 5, 6], Some(1))),
         signature: Signature::new_empty(),
     };
     let context = GroupContext {
@@ -943,6 +1650,7 @@ fn codec() {
         epoch: GroupEpoch(1u64),
         tree_hash: vec![],
         confirmed_transcript_hash: vec![],
+        extensions: vec![],
     };
     let signature_input = MLSPlaintextTBS::new_from(&orig, &context);
     orig.signature = signature_input.sign(&ciphersuite, &keypair.get_private_key());
@@ -951,3 +1659,93 @@ fn codec() {
     let copy = MLSPlaintext::from_bytes(&enc).unwrap();
     assert_eq!(orig, copy);
 }
+
+#[test]
+fn sender_key_cache() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let epoch_secrets = EpochSecrets {
+        sender_data_secret: vec![1; 32],
+        handshake_secret: vec![2; 32],
+        ..EpochSecrets::default()
+    };
+    let sender = Sender {
+        sender_type: SenderType::Member,
+        sender: LeafIndex::from(0u32),
+    };
+    let application_secrets = |byte: u8| -> Result<ApplicationSecrets, ()> {
+        Ok(ApplicationSecrets::new(
+            AeadNonce::from_slice(&[byte; NONCE_BYTES]),
+            AeadKey::from_slice(&[byte; 16]),
+        ))
+    };
+    let mut cache = SenderKeyCache::default();
+
+    // Cached secrets are used until the generation changes
+    cache
+        .get_or_derive(
+            &ciphersuite,
+            &epoch_secrets,
+            GroupEpoch(1),
+            &sender,
+            3,
+            || application_secrets(1),
+        )
+        .unwrap();
+    let (_, secrets) = cache
+        .get_or_derive(
+            &ciphersuite,
+            &epoch_secrets,
+            GroupEpoch(1),
+            &sender,
+            3,
+            || -> Result<_, ()> { panic!("The secrets of generation 3 are cached") },
+        )
+        .unwrap();
+    assert_eq!(secrets.get_key().as_slice(), &[1; 16]);
+
+    // Secrets of earlier generations are dropped
+    cache
+        .get_or_derive(
+            &ciphersuite,
+            &epoch_secrets,
+            GroupEpoch(1),
+            &sender,
+            4,
+            || application_secrets(2),
+        )
+        .unwrap();
+    assert_eq!(cache.cached_generations(), 1);
+
+    // Nothing is reused in another epoch
+    let (_, secrets) = cache
+        .get_or_derive(
+            &ciphersuite,
+            &epoch_secrets,
+            GroupEpoch(2),
+            &sender,
+            4,
+            || application_secrets(3),
+        )
+        .unwrap();
+    assert_eq!(secrets.get_key().as_slice(), &[3; 16]);
+
+    // Failed derivations are returned and not cached
+    assert_eq!(
+        cache
+            .get_or_derive(
+                &ciphersuite,
+                &epoch_secrets,
+                GroupEpoch(2),
+                &sender,
+                5,
+                || Err(())
+            )
+            .err(),
+        Some(())
+    );
+    assert_eq!(cache.cached_generations(), 1);
+
+    cache.wipe();
+    assert_eq!(cache.cached_generations(), 0);
+}
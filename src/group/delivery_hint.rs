@@ -0,0 +1,132 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+use crate::ciphersuite::*;
+use crate::codec::*;
+use crate::framing::*;
+use crate::group::*;
+use crate::schedule::*;
+use crate::tree::index::*;
+use crate::utils::*;
+
+/// Exporter label of the key that authenticates delivery hints
+pub(crate) const DELIVERY_HINT_LABEL: &str = "delivery hint";
+
+/// Hint that a sender attaches to an `MLSCiphertext` so that store-and-forward
+/// servers can order and deduplicate messages without decrypting them. The
+/// epoch, sender and generation are readable by anyone, the tag binds them to
+/// the ciphertext and can only be verified by members of the epoch. The tag is
+/// an HMAC under a key of the sender, so a hint can't be moved to another
+/// sender without recomputing it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DeliveryHint {
+    epoch: GroupEpoch,
+    sender: LeafIndex,
+    generation: u32,
+    tag: Vec<u8>,
+}
+
+impl DeliveryHint {
+    pub(crate) fn new(
+        ciphersuite: &Ciphersuite,
+        hint_key: &[u8],
+        sender: LeafIndex,
+        generation: u32,
+        mls_ciphertext: &MLSCiphertext,
+    ) -> Self {
+        let mut delivery_hint = DeliveryHint {
+            epoch: mls_ciphertext.epoch,
+            sender,
+            generation,
+            tag: vec![],
+        };
+        delivery_hint.tag = delivery_hint.compute_tag(ciphersuite, hint_key, mls_ciphertext);
+        delivery_hint
+    }
+
+    /// Verify the tag of the hint against `mls_ciphertext` in constant time.
+    pub(crate) fn verify_tag(
+        &self,
+        ciphersuite: &Ciphersuite,
+        hint_key: &[u8],
+        mls_ciphertext: &MLSCiphertext,
+    ) -> bool {
+        let tag = self.compute_tag(ciphersuite, hint_key, mls_ciphertext);
+        equal_ct(&tag, &self.tag)
+    }
+
+    /// Compute the HMAC over the hint and the hash of `mls_ciphertext` under
+    /// the key of the sender, which is derived from the epoch's `hint_key`.
+    fn compute_tag(
+        &self,
+        ciphersuite: &Ciphersuite,
+        hint_key: &[u8],
+        mls_ciphertext: &MLSCiphertext,
+    ) -> Vec<u8> {
+        let buffer = &mut Vec::new();
+        mls_ciphertext.group_id.encode(buffer).unwrap();
+        self.epoch.encode(buffer).unwrap();
+        self.sender.encode(buffer).unwrap();
+        self.generation.encode(buffer).unwrap();
+        let ciphertext_hash = ciphersuite.hash(&mls_ciphertext.encode_detached().unwrap());
+        encode_vec(VecSize::VecU8, buffer, &ciphertext_hash).unwrap();
+        let sender_key = hkdf_expand_label(
+            ciphersuite,
+            hint_key,
+            "sender",
+            &self.sender.encode_detached().unwrap(),
+            ciphersuite.hash_length(),
+        );
+        ciphersuite.mac(&sender_key, buffer)
+    }
+
+    pub fn get_epoch(&self) -> GroupEpoch {
+        self.epoch
+    }
+
+    /// Get the leaf index of the sender.
+    pub fn get_sender(&self) -> LeafIndex {
+        self.sender
+    }
+
+    /// Get the generation of the sender's ratchet the message was encrypted
+    /// with.
+    pub fn get_generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+impl Codec for DeliveryHint {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        self.epoch.encode(buffer)?;
+        self.sender.encode(buffer)?;
+        self.generation.encode(buffer)?;
+        encode_vec(VecSize::VecU8, buffer, &self.tag)?;
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let epoch = GroupEpoch::decode(cursor)?;
+        let sender = LeafIndex::decode(cursor)?;
+        let generation = u32::decode(cursor)?;
+        let tag = decode_vec(VecSize::VecU8, cursor)?;
+        Ok(DeliveryHint {
+            epoch,
+            sender,
+            generation,
+            tag,
+        })
+    }
+}
@@ -14,30 +14,555 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see http://www.gnu.org/licenses/.
 
+use crate::tree::astree::ASError;
+
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
 pub enum WelcomeError {
+    #[error("the Welcome uses another ciphersuite than the key package")]
     CiphersuiteMismatch = 100,
+    #[error("the Welcome contains no secrets for the key package")]
     JoinerSecretNotFound = 101,
+    #[error("the ratchet tree is neither given nor in the Welcome")]
     MissingRatchetTree = 102,
+    #[error("the tree hash of the ratchet tree differs from the GroupInfo")]
     TreeHashMismatch = 103,
+    #[error("the joiner's key package is not in the ratchet tree")]
     JoinerNotInTree = 104,
+    #[error("the confirmation tag of the GroupInfo is invalid")]
     ConfirmationTagMismatch = 105,
+    #[error("the ratchet tree is invalid")]
     InvalidRatchetTree = 106,
+    #[error("the signature of the GroupInfo is invalid")]
     InvalidGroupInfoSignature = 107,
+    #[error("the GroupInfo could not be decrypted")]
     GroupInfoDecryptionFailure = 108,
+    #[error("the group context extensions are invalid")]
+    InvalidGroupContextExtensions = 109,
+    #[error("a PSK of the Welcome was not given")]
+    MissingPsk = 110,
+    #[error("the group uses an unsupported application protocol")]
+    ApplicationProtocolMismatch = 111,
+    #[error("the path secret doesn't match the public keys of the joiner's path")]
+    PathPublicKeyMismatch = 112,
+    #[error("the Welcome was already processed")]
+    WelcomeProcessed = 113,
+    #[error("the group secrets could not be decrypted")]
+    GroupSecretsDecryptionFailure = 114,
 }
 
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
 pub enum ApplyCommitError {
+    #[error("the Commit is from another epoch")]
     EpochMismatch = 200,
+    #[error("the message doesn't contain a Commit")]
     WrongPlaintextContentType = 201,
+    #[error("the own member was removed by the Commit")]
     SelfRemoved = 202,
+    #[error("the key package of the path is invalid")]
     PathKeyPackageVerificationFailure = 203,
+    #[error("the key package of the path has no parent hash extension")]
     NoParentHashExtension = 204,
+    #[error("the parent hash of the path doesn't match the tree")]
     ParentHashMismatch = 205,
+    #[error("the signature of the Commit is invalid")]
     PlaintextSignatureFailure = 206,
+    #[error("the Commit requires a path but has none")]
     RequiredPathNotFound = 207,
+    #[error("the confirmation tag of the Commit is invalid")]
     ConfirmationTagMismatch = 208,
+    #[error("an Update proposal was sent by a non-member")]
+    UpdateFromNonMember = 209,
+    #[error("a Remove proposal targets a non-member")]
+    RemoveTargetNotAMember = 210,
+    #[error("the committer removed itself")]
+    CommitterRemoved = 211,
+    #[error("the committer is not a member of the group")]
+    CommitterNotAMember = 212,
+    #[error("the bundle of an own key package is missing")]
+    MissingOwnKeyPackageBundle = 213,
+    #[error("the sender of the path is invalid")]
+    InvalidPathSender = 214,
+    #[error("the path doesn't match the sender's direct path")]
+    InvalidPathLength = 215,
+    #[error("the ciphertexts of the path don't match the copath resolutions")]
+    PathResolutionSizeMismatch = 216,
+    #[error("no ciphertext of the path is encrypted to the own leaf")]
+    OwnLeafNotInPathResolution = 217,
+    #[error("the path secret doesn't match the public keys of the path")]
+    PathPublicKeyMismatch = 218,
+    #[error("the Commit covers too many proposals")]
+    TooManyProposals = 219,
+    #[error("the Commit is too large")]
+    CommitTooLarge = 220,
+    #[error("a PSK of the Commit can't be resolved")]
+    MissingPsk = 221,
+    #[error("a proposal grants device capabilities without being allowed to")]
+    InvalidDeviceCapabilities = 222,
+    #[error("a Remove proposal targets a non-removable member")]
+    NonRemovableMember = 223,
+    #[error("the epoch counter is exhausted")]
+    EpochOverflow = 224,
+    #[error("the Commit has no ordering token")]
+    MissingSequenceToken = 225,
+    #[error("the ordering token of the Commit is not greater than the last one")]
+    SequenceTokenNotIncreasing = 226,
+    #[error("there is no own Commit to merge")]
+    NoPendingCommit = 227,
+    #[error("the Commit references an unknown proposal")]
+    UnknownProposal = 228,
+    #[error("the path secret of the Commit could not be decrypted")]
+    PathDecryptionFailure = 229,
+    #[error("a leaf is removed more than once")]
+    DuplicateRemove = 230,
+    #[error("the group was imported as an observer and can't decrypt the path of the Commit")]
+    ObserverGroup = 231,
+    #[error("the joiners don't fit into the tree")]
+    TreeFull = 232,
+    #[error("the Commit is not from a member")]
+    InvalidCommitSender = 233,
 }
 
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
 pub enum CreateCommitError {
+    #[error("the committer can't remove itself")]
     CannotRemoveSelf = 300,
+    #[error("an Update proposal was sent by a non-member")]
+    UpdateFromNonMember = 301,
+    #[error("a Remove proposal targets a non-member")]
+    RemoveTargetNotAMember = 302,
+    #[error("the committer is not a member of the group")]
+    CommitterNotAMember = 303,
+    #[error("no members to add were given")]
+    NoMembersToAdd = 304,
+    #[error("the Commit would cover too many proposals")]
+    TooManyProposals = 305,
+    #[error("the Commit would be too large")]
+    CommitTooLarge = 306,
+    #[error("the Welcome would be too large")]
+    WelcomeTooLarge = 307,
+    #[error("a PSK of the Commit can't be resolved")]
+    MissingPsk = 308,
+    #[error("a proposal grants device capabilities without being allowed to")]
+    InvalidDeviceCapabilities = 309,
+    #[error("a Remove proposal targets a non-removable member")]
+    NonRemovableMember = 310,
+    #[error("the epoch counter is exhausted")]
+    EpochOverflow = 311,
+    #[error("the ratchet tree of the group was not validated yet")]
+    ProvisionalGroup = 312,
+    #[error("no ordering token could be obtained for the Commit")]
+    SequenceTokenUnavailable = 313,
+    #[error("the Commit references an unknown proposal")]
+    UnknownProposal = 314,
+    #[error("the group was imported as an observer and can't commit")]
+    ObserverGroup = 315,
+    #[error("the bundle of an own key package is missing")]
+    MissingOwnKeyPackageBundle = 316,
+    #[error("a leaf is removed more than once")]
+    DuplicateRemove = 317,
+    #[error("the joiners don't fit into the tree")]
+    TreeFull = 318,
+    #[error("the path secrets of the Commit could not be derived")]
+    MissingPathSecrets = 319,
+}
+
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum EncryptionError {
+    #[error("the authenticated data is too large")]
+    AadTooLarge = 400,
+    #[error("the send counter is exhausted")]
+    SendCounterExhausted = 401,
+    #[error("the group was imported as an observer and can't send")]
+    ObserverGroup = 402,
+    #[error("too many messages wait for delivery")]
+    SendQueueFull = 403,
+    #[error("sequence numbers are not enabled")]
+    SequenceNumbersDisabled = 404,
+    #[error("the secrets of the sender can't be derived")]
+    SecretDerivation = 405,
+}
+
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum SenderError {
+    #[error("the sender is not a member of the group")]
+    UnknownMember = 500,
+    #[error("the sender is not an external sender of the group")]
+    UnknownExternalSender = 501,
+    #[error("the sender is not allowed to send this content")]
+    InvalidContentForSender = 502,
+    #[error("the sender type is invalid")]
+    InvalidSenderType = 503,
+    #[error("the signature of the message is invalid")]
+    SignatureFailure = 504,
+    #[error("the application message is not encrypted")]
+    UnencryptedApplicationMessage = 505,
+}
+
+/// Errors that can occur when an `MLSCiphertext` is decrypted.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum DecryptionError {
+    /// The message was sent in a different epoch or group
+    #[error("the message is from another epoch or group")]
+    WrongEpoch = 700,
+    /// The sender is not a member of the group
+    #[error("the sender is not a member of the group")]
+    UnknownSender = 701,
+    /// The generation is too far in the past or in the future
+    #[error("the generation is outside of the window")]
+    GenerationOutOfWindow = 702,
+    /// The sender data or the content could not be decrypted
+    #[error("the message could not be decrypted")]
+    AeadError = 703,
+    /// The signature of the decrypted message is invalid
+    #[error("the signature of the message is invalid")]
+    InvalidSignature = 704,
+    /// The decrypted content doesn't match the content type of the message
+    #[error("the content doesn't match the content type")]
+    InvalidContentType = 705,
+    /// Sequence numbers are enabled and the authenticated data of an
+    /// application message doesn't start with one
+    #[error("the application message has no sequence number")]
+    MissingSequenceNumber = 706,
+    /// The sequence number is not larger than the last one of the sender,
+    /// the message was replayed or reordered
+    #[error("the sequence number is not larger than the last one of the sender")]
+    StaleSequenceNumber = 707,
+}
+
+/// Errors that can occur when proposals are applied to a tree.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum ProposalValidationError {
+    /// An Update proposal was sent by a leaf that is blank or not in the tree
+    #[error("an Update proposal was sent by a non-member")]
+    UpdateFromNonMember = 600,
+    /// A Remove proposal targets a leaf that is blank or not in the tree
+    #[error("a Remove proposal targets a non-member")]
+    RemoveTargetNotAMember = 601,
+    /// The creator of the Commit is not a member of the group
+    #[error("the committer is not a member of the group")]
+    CommitterNotAMember = 602,
+    /// An Add or Update proposal grants `NonRemovableCap` without being
+    /// allowed to
+    #[error("a proposal grants device capabilities without being allowed to")]
+    InvalidDeviceCapabilities = 603,
+    /// A Remove proposal targets a member with `NonRemovableCap`
+    #[error("a Remove proposal targets a non-removable member")]
+    NonRemovableMember = 604,
+    /// A proposal reference of the Commit is not in the proposal queue or
+    /// refers to a proposal of another type
+    #[error("the Commit references an unknown proposal")]
+    UnknownProposal = 605,
+    /// An own Update proposal is committed, but its `KeyPackageBundle` is
+    /// not pending in the group
+    #[error("the bundle of an own key package is missing")]
+    MissingOwnKeyPackageBundle = 606,
+    /// Several Remove proposals of the Commit target the same leaf
+    #[error("a leaf is removed more than once")]
+    DuplicateRemove = 607,
+    /// The tree would outgrow the `u32` node indices if the joiners of the
+    /// Add proposals were appended
+    #[error("the joiners don't fit into the tree")]
+    TreeFull = 608,
+}
+
+/// Errors that can occur when the path of a Commit is validated.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum PathValidationError {
+    /// The sender of the path is not a leaf of the tree or is the own leaf
+    #[error("the sender of the path is invalid")]
+    InvalidSender = 800,
+    /// The path doesn't have one node per node in the sender's direct path
+    #[error("the path doesn't match the sender's direct path")]
+    InvalidPathLength = 801,
+    /// The number of ciphertexts of a path node differs from the size of the
+    /// resolution of the corresponding copath node
+    #[error("the ciphertexts of the path don't match the copath resolutions")]
+    ResolutionSizeMismatch = 802,
+    /// None of the ciphertexts was encrypted to a node known to the own leaf
+    #[error("no ciphertext of the path is encrypted to the own leaf")]
+    OwnLeafNotInResolution = 803,
+    /// The public keys derived from the decrypted path secret don't match
+    /// the public keys in the path
+    #[error("the path secret doesn't match the public keys of the path")]
+    PublicKeyMismatch = 804,
+    /// The ciphertext encrypted to the own leaf could not be decrypted
+    #[error("the path secret could not be decrypted")]
+    DecryptionFailure = 805,
+}
+
+/// Errors that can occur when a `MembershipProof` is verified.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum MembershipProofError {
+    /// The signature doesn't match the signer's credential
+    #[error("the signature of the membership proof is invalid")]
+    InvalidSignature = 900,
+    /// The path of the member doesn't lead to the root of the tree
+    #[error("the path of the membership proof doesn't lead to the root")]
+    InvalidPath = 901,
+    /// The tree hash computed from the path differs from the signed one
+    #[error("the tree hash of the membership proof differs from the signed one")]
+    TreeHashMismatch = 902,
+}
+
+/// Errors that can occur when a `GroupInfo` is verified against an exported
+/// tree.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum GroupInfoError {
+    /// The exported tree is malformed or its integrity can't be verified
+    #[error("the ratchet tree is invalid")]
+    InvalidRatchetTree = 1000,
+    /// The tree hash of the exported tree differs from the signed one
+    #[error("the tree hash of the ratchet tree differs from the GroupInfo")]
+    TreeHashMismatch = 1001,
+    /// The signer index doesn't point to a member of the tree
+    #[error("the signer of the GroupInfo is not a member")]
+    UnknownSigner = 1002,
+    /// The signature doesn't match the signer's credential
+    #[error("the signature of the GroupInfo is invalid")]
+    InvalidSignature = 1003,
+}
+
+/// Errors that can occur when an exported tree is verified.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum TreeValidationError {
+    /// The nodes don't form a well-formed tree
+    #[error("the nodes don't form a valid tree")]
+    InvalidStructure = 1200,
+    /// The signature key of a leaf doesn't match its credential
+    #[error("the signature key of a leaf doesn't match its credential")]
+    InvalidLeafCredential = 1201,
+    /// A parent hash doesn't match the nodes below it
+    #[error("a parent hash doesn't match the tree")]
+    ParentHashMismatch = 1202,
+    /// The lifetime of a leaf doesn't include the current time
+    #[error("the lifetime of a leaf expired")]
+    ExpiredLeaf = 1203,
+    /// The tree hash differs from the one in the group context
+    #[error("the tree hash differs from the group context")]
+    TreeHashMismatch = 1204,
+    /// The signature of a leaf is invalid
+    #[error("the signature of a leaf is invalid")]
+    InvalidLeafSignature = 1205,
+}
+
+/// Errors that can occur when a `DeliveryHint` is verified.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum DeliveryHintError {
+    /// The hint or the ciphertext is from another group or epoch
+    #[error("the delivery hint is from another epoch or group")]
+    WrongEpoch = 1300,
+    /// The tag doesn't match the hint and the ciphertext
+    #[error("the tag of the delivery hint is invalid")]
+    InvalidTag = 1301,
+}
+
+/// Errors that can occur when wrapped history keys are imported.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum HistoryError {
+    /// The package could not be decoded
+    #[error("the history key package could not be decoded")]
+    MalformedPackage = 1400,
+    /// The package was wrapped to another key package
+    #[error("the history key package was wrapped to another key package")]
+    WrongRecipient = 1401,
+    /// The keys could not be decrypted with the key package's private key
+    #[error("the history key package could not be decrypted")]
+    DecryptionFailure = 1402,
+}
+
+/// Errors that can occur when the signature of a member on an application
+/// payload is verified.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum PayloadSignatureError {
+    /// There is no member at the leaf
+    #[error("the leaf of the signer is blank or not in the tree")]
+    UnknownMember = 1500,
+    /// The signature doesn't match the member's credential
+    #[error("the signature of the payload is invalid")]
+    InvalidSignature = 1501,
+}
+
+/// Errors that can occur when a proposal is created.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum CreateProposalError {
+    /// The group was imported from a transfer package and can only receive
+    #[error("the group was imported as an observer and can't propose")]
+    ObserverGroup = 1600,
+}
+
+impl From<CodecError> for HistoryError {
+    fn from(_: CodecError) -> HistoryError {
+        HistoryError::MalformedPackage
+    }
+}
+
+/// Errors that can occur when a proposal is processed on receipt.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum ProposalError {
+    /// The proposal was sent in a different epoch
+    #[error("the proposal is from another epoch")]
+    WrongEpoch = 1100,
+    /// The message doesn't contain a proposal
+    #[error("the message doesn't contain a proposal")]
+    NotAProposal = 1101,
+    /// The sender is not a member or external sender of the group
+    #[error("the sender is not a member or external sender of the group")]
+    UnknownSender = 1102,
+    /// The sender type is not allowed for the proposal
+    #[error("the sender is not allowed to send the proposal")]
+    InvalidSender = 1103,
+    /// The signature doesn't match the sender's credential
+    #[error("the signature of the proposal is invalid")]
+    InvalidSignature = 1104,
+    /// The key package of an Add or Update proposal is invalid or uses
+    /// another ciphersuite
+    #[error("the key package of the proposal is invalid")]
+    InvalidKeyPackage = 1105,
+    /// A Remove proposal targets a leaf that is blank or not in the tree
+    #[error("a Remove proposal targets a non-member")]
+    RemoveTargetNotAMember = 1106,
+    /// The proposal is of another type than expected
+    #[error("the proposal has an unexpected type")]
+    UnexpectedProposalType = 1107,
+    /// The PSK of a PSK proposal can't be resolved
+    #[error("the PSK of the proposal can't be resolved")]
+    UnknownPsk = 1108,
+    /// An Add or Update proposal grants `NonRemovableCap` without being
+    /// allowed to
+    #[error("the proposal grants device capabilities without being allowed to")]
+    InvalidDeviceCapabilities = 1109,
+    /// A Remove proposal targets a member with `NonRemovableCap`
+    #[error("a Remove proposal targets a non-removable member")]
+    NonRemovableMember = 1110,
+    /// The proposal was sent in a different group
+    #[error("the proposal is from another group")]
+    WrongGroup = 1111,
+    /// The joiner doesn't fit into the tree
+    #[error("the joiner doesn't fit into the tree")]
+    TreeFull = 1112,
+}
+
+impl From<PathValidationError> for ApplyCommitError {
+    fn from(err: PathValidationError) -> ApplyCommitError {
+        match err {
+            PathValidationError::InvalidSender => ApplyCommitError::InvalidPathSender,
+            PathValidationError::InvalidPathLength => ApplyCommitError::InvalidPathLength,
+            PathValidationError::ResolutionSizeMismatch => {
+                ApplyCommitError::PathResolutionSizeMismatch
+            }
+            PathValidationError::OwnLeafNotInResolution => {
+                ApplyCommitError::OwnLeafNotInPathResolution
+            }
+            PathValidationError::PublicKeyMismatch => ApplyCommitError::PathPublicKeyMismatch,
+            PathValidationError::DecryptionFailure => ApplyCommitError::PathDecryptionFailure,
+        }
+    }
+}
+
+impl From<ProposalValidationError> for ApplyCommitError {
+    fn from(err: ProposalValidationError) -> ApplyCommitError {
+        match err {
+            ProposalValidationError::UpdateFromNonMember => ApplyCommitError::UpdateFromNonMember,
+            ProposalValidationError::RemoveTargetNotAMember => {
+                ApplyCommitError::RemoveTargetNotAMember
+            }
+            ProposalValidationError::CommitterNotAMember => ApplyCommitError::CommitterNotAMember,
+            ProposalValidationError::InvalidDeviceCapabilities => {
+                ApplyCommitError::InvalidDeviceCapabilities
+            }
+            ProposalValidationError::NonRemovableMember => ApplyCommitError::NonRemovableMember,
+            ProposalValidationError::UnknownProposal => ApplyCommitError::UnknownProposal,
+            ProposalValidationError::MissingOwnKeyPackageBundle => {
+                ApplyCommitError::MissingOwnKeyPackageBundle
+            }
+            ProposalValidationError::DuplicateRemove => ApplyCommitError::DuplicateRemove,
+            ProposalValidationError::TreeFull => ApplyCommitError::TreeFull,
+        }
+    }
+}
+
+impl From<ProposalValidationError> for CreateCommitError {
+    fn from(err: ProposalValidationError) -> CreateCommitError {
+        match err {
+            ProposalValidationError::UpdateFromNonMember => CreateCommitError::UpdateFromNonMember,
+            ProposalValidationError::RemoveTargetNotAMember => {
+                CreateCommitError::RemoveTargetNotAMember
+            }
+            ProposalValidationError::CommitterNotAMember => CreateCommitError::CommitterNotAMember,
+            ProposalValidationError::InvalidDeviceCapabilities => {
+                CreateCommitError::InvalidDeviceCapabilities
+            }
+            ProposalValidationError::NonRemovableMember => CreateCommitError::NonRemovableMember,
+            ProposalValidationError::UnknownProposal => CreateCommitError::UnknownProposal,
+            ProposalValidationError::MissingOwnKeyPackageBundle => {
+                CreateCommitError::MissingOwnKeyPackageBundle
+            }
+            ProposalValidationError::DuplicateRemove => CreateCommitError::DuplicateRemove,
+            ProposalValidationError::TreeFull => CreateCommitError::TreeFull,
+        }
+    }
+}
+
+impl From<ProposalValidationError> for ProposalError {
+    fn from(err: ProposalValidationError) -> ProposalError {
+        match err {
+            ProposalValidationError::UpdateFromNonMember => ProposalError::InvalidSender,
+            ProposalValidationError::RemoveTargetNotAMember => {
+                ProposalError::RemoveTargetNotAMember
+            }
+            ProposalValidationError::CommitterNotAMember => ProposalError::UnknownSender,
+            ProposalValidationError::InvalidDeviceCapabilities => {
+                ProposalError::InvalidDeviceCapabilities
+            }
+            ProposalValidationError::NonRemovableMember => ProposalError::NonRemovableMember,
+            ProposalValidationError::UnknownProposal => ProposalError::UnexpectedProposalType,
+            ProposalValidationError::MissingOwnKeyPackageBundle => ProposalError::InvalidKeyPackage,
+            ProposalValidationError::DuplicateRemove => ProposalError::RemoveTargetNotAMember,
+            ProposalValidationError::TreeFull => ProposalError::TreeFull,
+        }
+    }
+}
+
+impl From<SenderError> for ProposalError {
+    fn from(err: SenderError) -> ProposalError {
+        match err {
+            SenderError::UnknownMember | SenderError::UnknownExternalSender => {
+                ProposalError::UnknownSender
+            }
+            SenderError::InvalidContentForSender | SenderError::InvalidSenderType => {
+                ProposalError::InvalidSender
+            }
+            SenderError::SignatureFailure => ProposalError::InvalidSignature,
+            SenderError::UnencryptedApplicationMessage => ProposalError::NotAProposal,
+        }
+    }
+}
+
+impl From<ASError> for DecryptionError {
+    fn from(err: ASError) -> DecryptionError {
+        match err {
+            ASError::IndexOutOfBounds => DecryptionError::UnknownSender,
+            ASError::TooDistantInThePast | ASError::TooDistantInTheFuture => {
+                DecryptionError::GenerationOutOfWindow
+            }
+        }
+    }
 }
@@ -0,0 +1,46 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+//! Escrow of epoch secrets for deployments that have to retain them for audit
+//! or compliance reasons.
+//!
+//! **Dangerous:** whoever receives the escrowed exporter secrets can derive
+//! every secret that members export in these epochs. Only available with the
+//! `dangerous-epoch-escrow` feature.
+
+use crate::group::*;
+
+/// Exporter secret and metadata of an epoch that are handed to an
+/// `EpochEscrow`.
+pub struct EscrowedEpoch<'a> {
+    pub group_id: &'a GroupId,
+    pub epoch: GroupEpoch,
+    pub tree_hash: &'a [u8],
+    pub confirmed_transcript_hash: &'a [u8],
+    pub exporter_secret: &'a [u8],
+}
+
+/// Hook that is called with the exporter secret of every epoch of a group.
+/// See the module documentation before using it.
+pub trait EpochEscrow: Send + Sync {
+    fn escrow_epoch(&self, escrowed_epoch: &EscrowedEpoch);
+}
+
+impl<F: Fn(&EscrowedEpoch) + Send + Sync> EpochEscrow for F {
+    fn escrow_epoch(&self, escrowed_epoch: &EscrowedEpoch) {
+        self(escrowed_epoch)
+    }
+}
@@ -0,0 +1,57 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+use crate::ciphersuite::{signable::*, *};
+use crate::creds::*;
+use crate::group::*;
+use crate::messages::*;
+use crate::tree::{index::*, node::*, *};
+
+use std::convert::TryFrom;
+
+/// Verify the signature of `group_info` against the exported tree `nodes`
+/// without being a member of the group. The tree has to be intact and match
+/// the signed tree hash and the signer has to be a member of the tree.
+/// Returns the credential of the signer.
+pub fn verify_group_info<'a>(
+    ciphersuite: &Ciphersuite,
+    group_info: &GroupInfo,
+    nodes: &'a [Option<Node>],
+) -> Result<&'a Credential, GroupInfoError> {
+    // The tree math is only defined for an odd number of nodes
+    if nodes.len() % 2 != 1 {
+        return Err(GroupInfoError::InvalidRatchetTree);
+    }
+    if !RatchetTree::verify_integrity(ciphersuite, nodes) {
+        return Err(GroupInfoError::InvalidRatchetTree);
+    }
+    let tree_hash =
+        public_tree_hash(ciphersuite, nodes).ok_or(GroupInfoError::InvalidRatchetTree)?;
+    if tree_hash != group_info.tree_hash {
+        return Err(GroupInfoError::TreeHashMismatch);
+    }
+    let signer_leaf_node = NodeIndex::try_from(group_info.signer_index)
+        .ok()
+        .and_then(|index| nodes.get(index.as_usize()))
+        .and_then(|node_option| node_option.as_ref())
+        .and_then(|node| node.leaf_node.as_ref())
+        .ok_or(GroupInfoError::UnknownSigner)?;
+    let credential = signer_leaf_node.get_credential();
+    if !group_info.verify_signature(credential, &group_info.signature) {
+        return Err(GroupInfoError::InvalidSignature);
+    }
+    Ok(credential)
+}
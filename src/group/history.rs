@@ -0,0 +1,261 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+//! History keys that let applications share past messages with new members.
+//!
+//! Applications that opt in record a history key for every epoch of a group
+//! in a `HistoryKeyStore` and encrypt a copy of their messages with it. When
+//! a member is added, the keys of the last N days can be wrapped to the
+//! joiner's `KeyPackage` and sent to it out of band.
+//!
+//! **Caveats:** history keys are retained beyond their epoch, so these epochs
+//! lose forward secrecy until the keys expire. Whoever obtains the store or a
+//! wrapped package can read the history it covers, and a joiner learns
+//! everything that was said in the shared epochs. Wrapped packages are not
+//! signed, the application has to authenticate the channel they are sent
+//! over.
+
+use crate::ciphersuite::*;
+use crate::codec::*;
+use crate::extensions::*;
+use crate::group::*;
+use crate::key_packages::*;
+use crate::storage::*;
+
+use std::convert::TryFrom;
+
+const HISTORY_KEY_LABEL: &str = "history key";
+const HISTORY_WRAP_INFO: &[u8] = b"maelstrom history keys";
+
+/// The history key of an epoch of a group.
+#[derive(Debug, PartialEq, Clone)]
+pub struct HistoryKey {
+    pub group_id: GroupId,
+    pub epoch: GroupEpoch,
+    /// Time the key was recorded at, in seconds since the Unix epoch
+    pub created_at: u64,
+    pub key: Vec<u8>,
+}
+
+impl Codec for HistoryKey {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        self.group_id.encode(buffer)?;
+        self.epoch.encode(buffer)?;
+        self.created_at.encode(buffer)?;
+        encode_vec(VecSize::VecU8, buffer, &self.key)?;
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let group_id = GroupId::decode(cursor)?;
+        let epoch = GroupEpoch::decode(cursor)?;
+        let created_at = u64::decode(cursor)?;
+        let key = decode_vec(VecSize::VecU8, cursor)?;
+        Ok(HistoryKey {
+            group_id,
+            epoch,
+            created_at,
+            key,
+        })
+    }
+}
+
+fn encode_keys<'a>(
+    keys: impl ExactSizeIterator<Item = &'a HistoryKey>,
+    buffer: &mut Vec<u8>,
+) -> Result<(), CodecError> {
+    u32::try_from(keys.len())
+        .map_err(|_| CodecError::EncodingError)?
+        .encode(buffer)?;
+    for key in keys {
+        key.encode(buffer)?;
+    }
+    Ok(())
+}
+
+fn decode_keys(cursor: &mut Cursor) -> Result<Vec<HistoryKey>, CodecError> {
+    let len = u32::decode(cursor)?;
+    let mut keys = vec![];
+    for _ in 0..len {
+        keys.push(HistoryKey::decode(cursor)?);
+    }
+    Ok(keys)
+}
+
+/// Retains the history keys of the epochs of groups for `max_age` seconds.
+/// Store it with `seal` and a storage key, it contains secrets.
+#[derive(Debug, PartialEq, Clone)]
+pub struct HistoryKeyStore {
+    max_age: u64,
+    keys: Vec<HistoryKey>,
+}
+
+impl HistoryKeyStore {
+    /// Create an empty store that retains keys for `max_age` seconds.
+    pub fn new(max_age: u64) -> Self {
+        HistoryKeyStore {
+            max_age,
+            keys: vec![],
+        }
+    }
+
+    /// Derive and retain the history key of the current epoch of `group`.
+    /// Call this after the group was created or joined and after every
+    /// Commit. Does nothing if the key of the epoch is already retained.
+    pub fn record_epoch(&mut self, group: &MlsGroup, time_provider: &dyn TimeProvider) {
+        let context = group.get_context();
+        if self.get(&context.group_id, context.epoch).is_some() {
+            return;
+        }
+        let key = group.export_secret(HISTORY_KEY_LABEL, group.get_ciphersuite().aead_key_length());
+        self.keys.push(HistoryKey {
+            group_id: context.group_id.clone(),
+            epoch: context.epoch,
+            created_at: time_provider.now(),
+            key,
+        });
+    }
+
+    /// Get the history key of `epoch` of the group `group_id`.
+    pub fn get(&self, group_id: &GroupId, epoch: GroupEpoch) -> Option<&HistoryKey> {
+        self.keys
+            .iter()
+            .find(|key| &key.group_id == group_id && key.epoch == epoch)
+    }
+
+    /// Get the retained history keys of the group `group_id`, oldest first.
+    pub fn keys_of_group<'a>(
+        &'a self,
+        group_id: &'a GroupId,
+    ) -> impl Iterator<Item = &'a HistoryKey> + 'a {
+        self.keys
+            .iter()
+            .filter(move |key| &key.group_id == group_id)
+    }
+
+    /// Remove all keys that are older than the maximum age at the current
+    /// time of `time_provider`.
+    pub fn remove_expired(&mut self, time_provider: &dyn TimeProvider) {
+        let not_before = time_provider.now().saturating_sub(self.max_age);
+        self.keys.retain(|key| key.created_at >= not_before);
+    }
+
+    /// Forget all keys of the group `group_id`, e.g. when it is deleted.
+    pub fn remove_group(&mut self, group_id: &GroupId) {
+        self.keys.retain(|key| &key.group_id != group_id);
+    }
+
+    /// Wrap the keys of `group` that were recorded at or after `not_before`
+    /// to the HPKE init key of `key_package`, the key package the joiner was
+    /// added with.
+    pub fn wrap_for_member(
+        &self,
+        group: &MlsGroup,
+        not_before: u64,
+        key_package: &KeyPackage,
+    ) -> Result<Vec<u8>, CodecError> {
+        let ciphersuite = group.get_ciphersuite();
+        let group_id = &group.get_context().group_id;
+        let keys: Vec<&HistoryKey> = self
+            .keys_of_group(group_id)
+            .filter(|key| key.created_at >= not_before)
+            .collect();
+        let mut payload = vec![];
+        encode_keys(keys.into_iter(), &mut payload)?;
+        let aad = group_id.encode_detached()?;
+        let ciphertext = ciphersuite.hpke_seal(
+            key_package.get_hpke_init_key(),
+            HISTORY_WRAP_INFO,
+            &aad,
+            &payload,
+        );
+        let mut buffer = vec![];
+        ciphersuite.encode(&mut buffer)?;
+        key_package.key_package_ref().encode(&mut buffer)?;
+        group_id.encode(&mut buffer)?;
+        ciphertext.encode(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Add the keys of a package created with `wrap_for_member` to the
+    /// store. `kpb` is the bundle of the key package the package was wrapped
+    /// to. Returns the number of keys that were added.
+    pub fn import_wrapped(
+        &mut self,
+        bytes: &[u8],
+        kpb: &KeyPackageBundle,
+    ) -> Result<usize, HistoryError> {
+        let cursor = &mut Cursor::new(bytes);
+        let ciphersuite = Ciphersuite::decode(cursor)?;
+        let key_package_ref = KeyPackageRef::decode(cursor)?;
+        let group_id = GroupId::decode(cursor)?;
+        let ciphertext = HpkeCiphertext::decode(cursor)?;
+        if cursor.has_more() {
+            return Err(HistoryError::MalformedPackage);
+        }
+        if key_package_ref != kpb.get_key_package().key_package_ref()
+            || &ciphersuite != kpb.get_key_package().get_cipher_suite()
+        {
+            return Err(HistoryError::WrongRecipient);
+        }
+        let aad = group_id.encode_detached()?;
+        let payload = ciphersuite
+            .hpke_open(&ciphertext, kpb.get_private_key(), HISTORY_WRAP_INFO, &aad)
+            .map_err(|_| HistoryError::DecryptionFailure)?;
+        let payload_cursor = &mut Cursor::new(&payload);
+        let keys = decode_keys(payload_cursor)?;
+        if payload_cursor.has_more() {
+            return Err(HistoryError::MalformedPackage);
+        }
+        let mut count = 0;
+        for key in keys {
+            if key.group_id != group_id {
+                return Err(HistoryError::MalformedPackage);
+            }
+            if self.get(&key.group_id, key.epoch).is_none() {
+                self.keys.push(key);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Get the number of retained keys.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Returns `true` if no keys are retained.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+impl Codec for HistoryKeyStore {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        self.max_age.encode(buffer)?;
+        encode_keys(self.keys.iter(), buffer)?;
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let max_age = u64::decode(cursor)?;
+        let keys = decode_keys(cursor)?;
+        Ok(HistoryKeyStore { max_age, keys })
+    }
+}
+
+impl Storable for HistoryKeyStore {
+    const STORAGE_TYPE: StorageType = StorageType::HistoryKeyStore;
+}
@@ -89,10 +89,10 @@ impl ManagedGroup {
 
     pub fn get_members(&self) -> Vec<Credential> {
         let mut members = Vec::new();
-        for i in 0..self.group.get_tree().leaf_count().as_usize() {
-            let node = self.group.get_tree().nodes[NodeIndex::from(i).as_usize()].clone();
-            let credential = node.key_package.unwrap().get_credential().clone();
-            members.push(credential);
+        for i in 0..self.group.get_tree().leaf_count() {
+            if let Some(leaf_node) = self.group.get_tree().get_leaf_node(LeafIndex::from(i)) {
+                members.push(leaf_node.get_credential().clone());
+            }
         }
         members
     }
@@ -0,0 +1,178 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+use crate::ciphersuite::{signable::*, *};
+use crate::codec::*;
+use crate::creds::*;
+use crate::group::*;
+use crate::tree::{index::*, node::*, *};
+use std::convert::TryFrom;
+
+/// Statement signed by a member that the member at `leaf_index` was in the
+/// group in an epoch. It contains the path from the member's leaf to the root
+/// of the tree, so the tree hash can be recomputed without the group state.
+/// Services that learned the tree hash of the epoch from another source
+/// don't have to trust the signer.
+pub struct MembershipProof {
+    ciphersuite: Ciphersuite,
+    group_id: GroupId,
+    epoch: GroupEpoch,
+    tree_hash: Vec<u8>,
+    leaf_count: u32,
+    leaf_index: u32,
+    leaf_node: LeafNode,
+    path: MembershipPath,
+    signer: Credential,
+    signature: Signature,
+}
+
+impl MembershipProof {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        ciphersuite: Ciphersuite,
+        group_context: &GroupContext,
+        leaf_count: u32,
+        leaf_index: LeafIndex,
+        leaf_node: LeafNode,
+        path: MembershipPath,
+        signer: Credential,
+        signature_key: &SignaturePrivateKey,
+    ) -> Self {
+        let mut membership_proof = MembershipProof {
+            ciphersuite,
+            group_id: group_context.group_id.clone(),
+            epoch: group_context.epoch,
+            tree_hash: group_context.tree_hash.clone(),
+            leaf_count,
+            leaf_index: leaf_index.as_u32(),
+            leaf_node,
+            path,
+            signer,
+            signature: Signature::new_empty(),
+        };
+        membership_proof.signature = membership_proof.sign(&ciphersuite, signature_key);
+        membership_proof
+    }
+
+    /// Get the credential of the member.
+    pub fn get_credential(&self) -> &Credential {
+        self.leaf_node.get_credential()
+    }
+
+    /// Get the credential of the member that signed the proof.
+    pub fn get_signer(&self) -> &Credential {
+        &self.signer
+    }
+
+    pub fn get_group_id(&self) -> &GroupId {
+        &self.group_id
+    }
+
+    pub fn get_epoch(&self) -> GroupEpoch {
+        self.epoch
+    }
+
+    /// Get the tree hash of the epoch.
+    pub fn get_tree_hash(&self) -> &[u8] {
+        &self.tree_hash
+    }
+}
+
+/// Verify that the signer of `membership_proof` states that the member was
+/// in the group and that the member's path leads to the signed tree hash.
+/// Returns the credential of the member. The caller has to decide whether
+/// the signer is trusted or compare the tree hash with a trusted source.
+pub fn verify_membership_proof(
+    membership_proof: &MembershipProof,
+) -> Result<&Credential, MembershipProofError> {
+    if !membership_proof.verify_signature(&membership_proof.signer, &membership_proof.signature) {
+        return Err(MembershipProofError::InvalidSignature);
+    }
+    let tree_hash = membership_path_tree_hash(
+        &membership_proof.ciphersuite,
+        membership_proof.leaf_count,
+        LeafIndex::from(membership_proof.leaf_index),
+        &membership_proof.leaf_node,
+        &membership_proof.path,
+    )
+    .ok_or(MembershipProofError::InvalidPath)?;
+    if tree_hash != membership_proof.tree_hash {
+        return Err(MembershipProofError::TreeHashMismatch);
+    }
+    Ok(membership_proof.get_credential())
+}
+
+impl Signable for MembershipProof {
+    const SIGNATURE_LABEL: &'static str = "MembershipProofTBS";
+
+    fn unsigned_payload(&self) -> Result<Vec<u8>, CodecError> {
+        let buffer = &mut Vec::new();
+        self.ciphersuite.encode(buffer)?;
+        self.group_id.encode(buffer)?;
+        self.epoch.encode(buffer)?;
+        encode_vec(VecSize::VecU8, buffer, &self.tree_hash)?;
+        self.leaf_count.encode(buffer)?;
+        self.leaf_index.encode(buffer)?;
+        self.leaf_node.encode(buffer)?;
+        u32::try_from(self.path.len())
+            .map_err(|_| CodecError::EncodingError)?
+            .encode(buffer)?;
+        for (parent_node, sibling_hash) in self.path.iter() {
+            parent_node.encode(buffer)?;
+            encode_vec(VecSize::VecU8, buffer, sibling_hash)?;
+        }
+        self.signer.encode(buffer)?;
+        Ok(buffer.to_vec())
+    }
+}
+
+impl Codec for MembershipProof {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        buffer.extend_from_slice(&self.unsigned_payload()?);
+        self.signature.encode(buffer)?;
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let ciphersuite = Ciphersuite::decode(cursor)?;
+        let group_id = GroupId::decode(cursor)?;
+        let epoch = GroupEpoch::decode(cursor)?;
+        let tree_hash = decode_vec(VecSize::VecU8, cursor)?;
+        let leaf_count = u32::decode(cursor)?;
+        let leaf_index = u32::decode(cursor)?;
+        let leaf_node = LeafNode::decode(cursor)?;
+        let path_length = u32::decode(cursor)?;
+        let mut path = vec![];
+        for _ in 0..path_length {
+            let parent_node = Option::<ParentNode>::decode(cursor)?;
+            let sibling_hash = decode_vec(VecSize::VecU8, cursor)?;
+            path.push((parent_node, sibling_hash));
+        }
+        let signer = Credential::decode(cursor)?;
+        let signature = Signature::decode(cursor)?;
+        Ok(MembershipProof {
+            ciphersuite,
+            group_id,
+            epoch,
+            tree_hash,
+            leaf_count,
+            leaf_index,
+            leaf_node,
+            path,
+            signer,
+            signature,
+        })
+    }
+}
@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see http://www.gnu.org/licenses/.
 
+use crate::creds::*;
 use crate::framing::*;
 use crate::group::*;
 use crate::key_packages::*;
@@ -33,8 +34,18 @@ pub trait Api: Sized {
         ratchet_tree: Option<Vec<Option<Node>>>,
         key_package_bundle: KeyPackageBundle,
     ) -> Result<Self, WelcomeError>;
+    /// Join a group from a Welcome message that requires the joiner to inject
+    /// pre-shared keys into the key schedule. All PSKs listed in the Welcome
+    /// must be contained in `psks`.
+    fn new_from_welcome_with_psks(
+        welcome: Welcome,
+        ratchet_tree: Option<Vec<Option<Node>>>,
+        key_package_bundle: KeyPackageBundle,
+        psks: &[PreSharedKey],
+    ) -> Result<Self, WelcomeError>;
 
-    // Create handshake messages
+    // Create handshake messages. Observers can't create proposals and get a
+    // `CreateProposalError::ObserverGroup`.
 
     /// Create an `AddProposal`
     fn create_add_proposal(
@@ -42,39 +53,69 @@ pub trait Api: Sized {
         aad: &[u8],
         signature_key: &SignaturePrivateKey,
         joiner_key_package: KeyPackage,
-    ) -> (MLSPlaintext, Proposal);
-    /// Create an `UpdateProposal`
+    ) -> Result<(MLSPlaintext, Proposal), CreateProposalError>;
+    /// Create an `UpdateProposal`. The `KeyPackageBundle` is kept by the
+    /// group until the Commit that includes the proposal is applied.
     fn create_update_proposal(
-        &self,
+        &mut self,
         aad: &[u8],
         signature_key: &SignaturePrivateKey,
-        key_package: KeyPackage,
-    ) -> (MLSPlaintext, Proposal);
+        key_package_bundle: KeyPackageBundle,
+    ) -> Result<(MLSPlaintext, Proposal), CreateProposalError>;
     /// Create a `RemoveProposal`
     fn create_remove_proposal(
         &self,
         aad: &[u8],
         signature_key: &SignaturePrivateKey,
         removed_index: LeafIndex,
-    ) -> (MLSPlaintext, Proposal);
-    /// Create a `Commit` and an optional `Welcome`
-    fn create_commit(
+    ) -> Result<(MLSPlaintext, Proposal), CreateProposalError>;
+    /// Create a `PreSharedKeyProposal` to inject the PSK `psk_id` into the
+    /// key schedule of the next epoch. All members have to be able to resolve
+    /// the PSK through their `PskStore` or their resumption secrets.
+    fn create_psk_proposal(
         &self,
         aad: &[u8],
         signature_key: &SignaturePrivateKey,
+        psk_id: PreSharedKeyID,
+    ) -> Result<(MLSPlaintext, Proposal), CreateProposalError>;
+    /// Create a `Commit` and an optional `Welcome`. If the Commit contains a
+    /// path, the new leaf `KeyPackageBundle` is kept by the group until the
+    /// Commit is applied.
+    fn create_commit(
+        &mut self,
+        aad: &[u8],
+        signature_key: &SignaturePrivateKey,
+        key_package_bundle: KeyPackageBundle,
+        proposals: Vec<(Sender, Proposal)>,
+        force_self_update: bool,
+    ) -> CreateCommitResult;
+    /// Like `create_commit`, but injects `psks` into the key schedule of the
+    /// new epoch. The IDs of the PSKs are sent to new members in the `Welcome`.
+    fn create_commit_with_psks(
+        &mut self,
+        aad: &[u8],
+        signature_key: &SignaturePrivateKey,
         key_package_bundle: KeyPackageBundle,
         proposals: Vec<(Sender, Proposal)>,
-        own_key_packages: Vec<KeyPackageBundle>,
         force_self_update: bool,
+        psks: &[PreSharedKey],
     ) -> CreateCommitResult;
 
-    /// Apply a `Commit` message
+    /// Apply a `Commit` message and return the resulting membership changes.
+    /// Own `KeyPackageBundle`s of the previous epoch are discarded.
     fn apply_commit(
         &mut self,
         mls_plaintext: MLSPlaintext,
         proposals: Vec<(Sender, Proposal)>,
-        own_key_packages: Vec<KeyPackageBundle>,
-    ) -> Result<(), ApplyCommitError>;
+    ) -> Result<MembershipChanges, ApplyCommitError>;
+    /// Like `apply_commit`, for Commits that inject `psks` into the key
+    /// schedule.
+    fn apply_commit_with_psks(
+        &mut self,
+        mls_plaintext: MLSPlaintext,
+        proposals: Vec<(Sender, Proposal)>,
+        psks: &[PreSharedKey],
+    ) -> Result<MembershipChanges, ApplyCommitError>;
 
     /// Create application message
     fn create_application_message(
@@ -84,20 +125,109 @@ pub trait Api: Sized {
         signature_key: &SignaturePrivateKey,
     ) -> MLSPlaintext;
 
-    /// Encrypt an MLS message
-    fn encrypt(&mut self, mls_plaintext: MLSPlaintext) -> MLSCiphertext;
-    /// Decrypt an MLS message
-    fn decrypt(&mut self, mls_ciphertext: MLSCiphertext) -> MLSPlaintext;
+    /// Create an application message whose payload is tagged with the
+    /// application-defined `content_type`, see `ApplicationData`.
+    fn create_application_message_with_content_type(
+        &self,
+        aad: &[u8],
+        msg: &[u8],
+        content_type: u16,
+        signature_key: &SignaturePrivateKey,
+    ) -> MLSPlaintext;
+
+    /// Encrypt an MLS message.
+    /// Fails if the authenticated data exceeds `MAX_AAD_SIZE`.
+    fn encrypt(&mut self, mls_plaintext: MLSPlaintext) -> Result<MLSCiphertext, EncryptionError>;
+    /// Decrypt an MLS message. Fails with a `DecryptionError` that tells the
+    /// caller whether the message is from another epoch, from an unknown
+    /// sender, out of the generation window or otherwise invalid.
+    fn decrypt(
+        &mut self,
+        mls_ciphertext: MLSCiphertext,
+    ) -> Result<ProcessedMessage, DecryptionError>;
+
+    /// Verify the signature of an `MLSPlaintext`. The signature key is
+    /// resolved according to the sender type: members sign with the key of
+    /// their leaf, external senders with the key listed in the group's
+    /// `ExternalSendersExtension`, and new members with the key of the
+    /// `KeyPackage` in their Add proposal or Commit path.
+    /// Application messages are rejected unless they are explicitly allowed
+    /// as plaintext.
+    /// Returns the credential of the sender.
+    fn verify_plaintext(&self, mls_plaintext: &MLSPlaintext) -> Result<Credential, SenderError>;
 
     /// Export a secret through the exporter
     fn export_secret(&self, label: &str, key_length: usize) -> Vec<u8>;
+
+    /// Export the membership key of the current epoch. The key can be shared
+    /// with the delivery service to let it verify membership tags.
+    fn export_membership_key(&self) -> Vec<u8>;
+    /// Compute the membership tag of an outgoing `MLSCiphertext`
+    fn membership_tag(&self, mls_ciphertext: &MLSCiphertext) -> MembershipTag;
 }
 
-pub type CreateCommitResult = Result<
-    (
-        MLSPlaintext,
-        Option<Welcome>,
-        Option<(HPKEPrivateKey, KeyPackage)>,
-    ),
-    CreateCommitError,
->;
+pub type CreateCommitResult = Result<CommitBundle, CreateCommitError>;
+
+/// The result of `handle_conflicting_commit`.
+pub struct CommitConflict {
+    /// The membership changes of the other member's Commit
+    pub membership_changes: MembershipChanges,
+    /// The own Commit that was discarded, if there was one
+    pub discarded_commit: Option<MLSPlaintext>,
+    /// The proposals of the discarded Commit that the other member's Commit
+    /// doesn't cover. They have to be proposed again in the new epoch.
+    pub uncommitted_proposals: Vec<(Sender, Proposal)>,
+}
+
+/// The result of `create_commit`.
+pub struct CommitBundle {
+    pub(crate) commit: MLSPlaintext,
+    pub(crate) welcome: Option<Welcome>,
+    pub(crate) key_package_bundle: Option<KeyPackageBundle>,
+    pub(crate) group_info: Option<GroupInfo>,
+    pub(crate) proposals: Vec<(Sender, Proposal)>,
+    pub(crate) deferred_proposals: Vec<(Sender, Proposal)>,
+    pub(crate) rejected_proposals: Vec<(Sender, Proposal)>,
+    pub(crate) correlation_id: CorrelationId,
+}
+
+impl CommitBundle {
+    /// Get the Commit message
+    pub fn get_commit(&self) -> &MLSPlaintext {
+        &self.commit
+    }
+    /// Get the `Welcome` message for new members, if members were added
+    pub fn get_welcome(&self) -> Option<&Welcome> {
+        self.welcome.as_ref()
+    }
+    /// Get the new own `KeyPackageBundle`, if the Commit contains a path
+    pub fn get_key_package_bundle(&self) -> Option<&KeyPackageBundle> {
+        self.key_package_bundle.as_ref()
+    }
+    /// Get the `CorrelationId` of the Commit. The `GroupInfo` in the
+    /// `Welcome` and the groups of all members in the new epoch have the same
+    /// one.
+    pub fn correlation_id(&self) -> &CorrelationId {
+        &self.correlation_id
+    }
+    /// Get the signed `GroupInfo` of the new epoch, if members were added
+    pub fn get_group_info(&self) -> Option<&GroupInfo> {
+        self.group_info.as_ref()
+    }
+    /// Get the Add proposals that didn't fit into the Commit because of the
+    /// group's `CommitPolicy`. They should be committed in a later epoch.
+    pub fn get_deferred_proposals(&self) -> &[(Sender, Proposal)] {
+        &self.deferred_proposals
+    }
+    /// Get the Add proposals that make the Commit or Welcome too large on
+    /// their own. They can't be committed under the group's `CommitPolicy`.
+    pub fn get_rejected_proposals(&self) -> &[(Sender, Proposal)] {
+        &self.rejected_proposals
+    }
+    /// Get the proposals that `add_members` or `remove_members` included in
+    /// the Commit by value, as they are passed to `apply_commit`. Members
+    /// don't need them to apply the Commit.
+    pub fn get_proposal_list(&self) -> Vec<(Sender, Proposal)> {
+        self.proposals.clone()
+    }
+}
@@ -18,16 +18,21 @@ use crate::extensions::*;
 use crate::framing::*;
 use crate::group::mls_group::*;
 use crate::group::*;
-use crate::key_packages::*;
 use crate::messages::*;
+use crate::metrics::*;
+use crate::schedule::*;
 use crate::utils::*;
+use crate::validator::*;
+
+use std::convert::TryFrom;
 
 pub fn apply_commit(
     group: &mut MlsGroup,
     mls_plaintext: MLSPlaintext,
     proposals: Vec<(Sender, Proposal)>,
-    own_key_packages: Vec<KeyPackageBundle>,
-) -> Result<(), ApplyCommitError> {
+    psks: &[PreSharedKey],
+) -> Result<MembershipChanges, ApplyCommitError> {
+    trace_span!("apply_commit", epoch = group.group_context.epoch.0);
     let ciphersuite = group.get_ciphersuite();
 
     // Verify epoch
@@ -35,38 +40,96 @@ pub fn apply_commit(
         return Err(ApplyCommitError::EpochMismatch);
     }
 
-    // Create KeyPackageBundles
-    let mut pending_kpbs = vec![];
-    for kpb in own_key_packages {
-        let (pk, kp) = (
-            kpb.private_key,
-            kpb.key_package,
-        );
-        pending_kpbs.push(KeyPackageBundle::from_values(kp, pk));
-    }
-
     // Extract Commit from MLSPlaintext
     let (commit, confirmation_tag) = match mls_plaintext.content.clone() {
         MLSPlaintextContentType::Commit((commit, confirmation)) => (commit, confirmation),
         _ => return Err(ApplyCommitError::WrongPlaintextContentType),
     };
 
+    // Only members can commit, the sender is used as a leaf index below
+    if !mls_plaintext.sender.is_member() {
+        return Err(ApplyCommitError::InvalidCommitSender);
+    }
+
+    // Enforce the commit policy
+    let commit_policy = group.commit_policy;
+    if let Some(max_proposals) = commit_policy.max_proposals {
+        let proposal_count =
+            commit.updates.len() + commit.removes.len() + commit.adds.len() + commit.psks.len();
+        if proposal_count > max_proposals {
+            return Err(ApplyCommitError::TooManyProposals);
+        }
+    }
+    if let Some(max_commit_size) = commit_policy.max_commit_size {
+        if mls_plaintext.encode_detached().unwrap().len() > max_commit_size {
+            return Err(ApplyCommitError::CommitTooLarge);
+        }
+    }
+
+    // Check the ordering token of a server-ordered deployment
+    let commit_token = if group.commit_sequencer.is_some() {
+        let (token, _) = split_sequenced_aad(&mls_plaintext.authenticated_data)
+            .ok_or(ApplyCommitError::MissingSequenceToken)?;
+        if matches!(group.last_commit_token, Some(last) if token <= last) {
+            return Err(ApplyCommitError::SequenceTokenNotIncreasing);
+        }
+        Some(token)
+    } else {
+        None
+    };
+
     // Organize proposals
     let proposal_id_list = ProposalIDList {
         updates: commit.updates.clone(),
         removes: commit.removes.clone(),
         adds: commit.adds.clone(),
+        psks: commit.psks.clone(),
     };
     let mut proposal_queue = ProposalQueue::new();
     for (sender, proposal) in proposals {
         let queued_proposal = QueuedProposal::new(proposal, sender.as_leaf_index(), None);
         proposal_queue.add(queued_proposal, &ciphersuite);
     }
+    // Proposals the committer included by value
+    for proposal in commit.proposals.iter() {
+        let queued_proposal =
+            QueuedProposal::new(proposal.clone(), mls_plaintext.sender.sender, None);
+        proposal_queue.add(queued_proposal, &ciphersuite);
+    }
+
+    // Resolve the PSKs of PSK proposals
+    let psks = group
+        .resolve_psks(psks, &proposal_id_list.psks, &proposal_queue)
+        .ok_or(ApplyCommitError::MissingPsk)?;
 
-    // Create provisional tree and apply proposals
-    let mut provisional_tree = group.tree.borrow_mut();
-    let (membership_changes, _invited_members, group_removed) =
-        provisional_tree.apply_proposals(&proposal_id_list, proposal_queue, pending_kpbs.clone());
+    // The committer cannot remove itself with its own Commit
+    for r in proposal_id_list.removes.iter() {
+        if let Some(remove_proposal) = proposal_queue
+            .get(r)
+            .and_then(|(_proposal_id, queued_proposal)| queued_proposal.proposal.as_remove())
+        {
+            if LeafIndex::from(remove_proposal.removed) == mls_plaintext.sender.sender {
+                return Err(ApplyCommitError::CommitterRemoved);
+            }
+        }
+    }
+
+    // An observer doesn't hold the private keys of a leaf and can only follow
+    // Commits without a path
+    if group.observer && commit.path.is_some() {
+        return Err(ApplyCommitError::ObserverGroup);
+    }
+
+    // Create provisional tree and apply proposals. The group state is only
+    // modified once all checks passed.
+    let mut provisional_tree = group.tree.clone();
+    let (mut membership_changes, _invited_members, group_removed) =
+        provisional_tree.apply_proposals(
+            mls_plaintext.sender.sender,
+            &proposal_id_list,
+            proposal_queue,
+            &group.pending_kpbs,
+        )?;
 
     // Check if we were removed from the group
     if group_removed {
@@ -75,33 +138,73 @@ pub fn apply_commit(
 
     // Determine if Commit is own Commit
     let sender = mls_plaintext.sender.sender;
-    let is_own_commit = mls_plaintext.sender.as_node_index() == provisional_tree.get_own_index(); // XXX: correct?
+    let is_own_commit =
+        mls_plaintext.sender.as_node_index() == Some(provisional_tree.get_own_index());
+
+    // Verify the MLSPlaintext signature. A path replaces the leaf of the
+    // committer, so the Commit is signed with the credential of the new leaf.
+    let old_leaf_node = group
+        .tree
+        .get_leaf_node(sender)
+        .ok_or(ApplyCommitError::CommitterNotAMember)?;
+    let credential = match &commit.path {
+        Some(path) => {
+            if !path.leaf_key_package.verify() {
+                return Err(ApplyCommitError::PathKeyPackageVerificationFailure);
+            }
+            path.leaf_key_package.get_credential()
+        }
+        None => old_leaf_node.get_credential(),
+    };
+    if !mls_plaintext.verify(&group.group_context, credential) {
+        return Err(ApplyCommitError::PlaintextSignatureFailure);
+    }
 
     // Determine if Commit has a path
     let commit_secret = if let Some(path) = commit.path.clone() {
-        // Verify KeyPackage and MLSPlaintext signature
         let kp = &path.leaf_key_package;
-        if !kp.verify() {
-            return Err(ApplyCommitError::PathKeyPackageVerificationFailure);
+        // The committer can't grant itself new device capabilities
+        if validate_device_capabilities(Some(old_leaf_node), kp).is_err() {
+            return Err(ApplyCommitError::InvalidDeviceCapabilities);
         }
-        if !mls_plaintext.verify(&group.group_context, kp.get_credential()) {
-            return Err(ApplyCommitError::PlaintextSignatureFailure);
+        let old_capabilities = get_device_capabilities(old_leaf_node);
+        let new_capabilities = get_key_package_device_capabilities(kp);
+        if old_capabilities != new_capabilities {
+            membership_changes.capability_changes.push(CapabilityChange {
+                index: sender,
+                old_capabilities,
+                new_capabilities,
+            });
         }
         if is_own_commit {
-            // Find the right KeyPackageBundle among the pending bundles
-            let own_kpb = pending_kpbs
-                .iter()
-                .find(|&kpb| kpb.get_key_package() == kp)
-                .unwrap();
-            let (commit_secret, _, _, _) = provisional_tree.update_own_leaf(
-                None,
-                own_kpb.clone(),
+            // Find the right KeyPackageBundle among the pending bundles and
+            // reuse the path secrets the Commit was created with
+            let (own_kpb, pending_commit_state) = match group.get_pending_commit_kpb(kp) {
+                Some(pending) => pending,
+                None => return Err(ApplyCommitError::MissingOwnKeyPackageBundle),
+            };
+            provisional_tree.apply_own_path_secrets(own_kpb, &pending_commit_state.path_secrets);
+            pending_commit_state.commit_secret
+        } else {
+            let commit_secret = provisional_tree.update_direct_path(
+                sender,
+                &path,
                 &group.group_context.serialize(),
-                false,
-            );
+            )?;
+            // The path was accepted, so the sender has a node index
+            let sender_index =
+                NodeIndex::try_from(sender).map_err(|_| PathValidationError::InvalidSender)?;
+            // Verify the parent hash before the new tree is used
+            let parent_hash = provisional_tree.compute_parent_hash(sender_index);
+            match kp.get_extension(ExtensionType::ParentHash) {
+                Some(ExtensionPayload::ParentHash(parent_hash_inner)) => {
+                    if parent_hash != parent_hash_inner.parent_hash {
+                        return Err(ApplyCommitError::ParentHashMismatch);
+                    }
+                }
+                _ => return Err(ApplyCommitError::NoParentHashExtension),
+            }
             commit_secret
-        } else {
-            provisional_tree.update_direct_path(sender, &path, &group.group_context.serialize())
         }
     } else {
         if membership_changes.path_required() {
@@ -112,11 +215,13 @@ pub fn apply_commit(
 
     // Create provisional group state
     let mut provisional_epoch = group.group_context.epoch;
-    provisional_epoch.increment();
+    if !provisional_epoch.increment() {
+        return Err(ApplyCommitError::EpochOverflow);
+    }
 
     let confirmed_transcript_hash = update_confirmed_transcript_hash(
         ciphersuite,
-        &MLSPlaintextCommitContent::new(&group.group_context, sender, commit.clone()),
+        &MLSPlaintextCommitContent::from(mls_plaintext.clone()),
         &group.interim_transcript_hash,
     );
 
@@ -125,13 +230,14 @@ pub fn apply_commit(
         epoch: provisional_epoch,
         tree_hash: provisional_tree.compute_tree_hash(),
         confirmed_transcript_hash: confirmed_transcript_hash.clone(),
+        extensions: group.group_context.extensions.clone(),
     };
 
     let mut provisional_epoch_secrets = group.epoch_secrets.clone();
     provisional_epoch_secrets.get_new_epoch_secrets(
         &ciphersuite,
         commit_secret,
-        None,
+        psk_secret(&ciphersuite, &psks).as_deref(),
         &provisional_group_context,
     );
 
@@ -148,36 +254,48 @@ pub fn apply_commit(
         return Err(ApplyCommitError::ConfirmationTagMismatch);
     }
 
-    // Verify KeyPackage extensions
-    if let Some(path) = commit.path {
-        if !is_own_commit {
-            let parent_hash = provisional_tree.compute_parent_hash(NodeIndex::from(sender));
-            if let Some(received_parent_hash) = path
-                .leaf_key_package
-                .get_extension(ExtensionType::ParentHash)
-            {
-                if let ExtensionPayload::ParentHash(parent_hash_inner) = received_parent_hash {
-                    if parent_hash != parent_hash_inner.parent_hash {
-                        return Err(ApplyCommitError::ParentHashMismatch);
-                    }
-                }
-            } else {
-                return Err(ApplyCommitError::NoParentHashExtension);
-            }
-        }
-    }
-
     // Apply provisional tree and state to group
-    group.group_context = provisional_group_context;
-    group.epoch_secrets = provisional_epoch_secrets;
-    group.interim_transcript_hash = interim_transcript_hash;
-    group
-        .astree
-        .borrow_mut()
-        .set_size(provisional_tree.leaf_count());
+    let staged_commit = StagedCommit {
+        tree: provisional_tree,
+        group_context: provisional_group_context,
+        epoch_secrets: provisional_epoch_secrets,
+        interim_transcript_hash,
+        membership_changes,
+        commit_token,
+    };
+    Ok(merge_staged_commit(group, staged_commit))
+}
+
+/// Move the group to the epoch of `staged_commit`.
+pub(crate) fn merge_staged_commit(
+    group: &mut MlsGroup,
+    staged_commit: StagedCommit,
+) -> MembershipChanges {
+    let leaf_count = staged_commit.tree.leaf_count();
+    group.tree = staged_commit.tree;
+    group.group_context = staged_commit.group_context;
+    group.epoch_secrets = staged_commit.epoch_secrets;
+    group.sender_key_cache.wipe();
+    group.interim_transcript_hash = staged_commit.interim_transcript_hash;
+    group.record_resumption_secret();
+    group.epoch_started_at = group.time_provider.now();
+    group.epoch_message_count = 0;
+    if staged_commit.commit_token.is_some() {
+        group.last_commit_token = staged_commit.commit_token;
+    }
+    #[cfg(feature = "dangerous-epoch-escrow")]
+    group.escrow_epoch();
+    // Own KeyPackageBundles are either consumed by this Commit or belong to
+    // proposals of the previous epoch
+    group.pending_kpbs.clear();
+    group.pending_commit_states.clear();
+    group.pending_commit = None;
+    if let Some(sequence_numbers) = &mut group.sequence_numbers {
+        sequence_numbers.reset_changed_leaves(&staged_commit.membership_changes);
+    }
     group
         .astree
-        .borrow_mut()
-        .set_application_secrets(&group.epoch_secrets.application_secret);
-    Ok(())
+        .resize(&group.epoch_secrets.application_secret, leaf_count);
+    record_epoch_transition(group.group_context.epoch);
+    staged_commit.membership_changes
 }
@@ -22,34 +22,123 @@ use crate::group::mls_group::*;
 use crate::group::*;
 use crate::key_packages::*;
 use crate::messages::*;
+use crate::schedule::*;
 use crate::tree::treemath;
 use crate::utils::*;
 use rayon::prelude::*;
 
+/// Create a Commit that respects the group's `CommitPolicy`. Updates and
+/// removes always have to be covered, Add proposals that exceed the
+/// maximum number of proposals or make the Commit or Welcome too large are
+/// deferred to a later epoch. An Add that is too large on its own is
+/// rejected without failing the Commit. If `by_value` is set, `proposals` are
+/// the committer's own and are included in the Commit by value.
+#[allow(clippy::too_many_arguments)]
 pub fn create_commit(
-    group: &MlsGroup,
+    group: &mut MlsGroup,
     aad: &[u8],
     signature_key: &SignaturePrivateKey,
     key_package_bundle: KeyPackageBundle,
     proposals: Vec<(Sender, Proposal)>,
-    own_key_packages: Vec<KeyPackageBundle>,
     force_group_update: bool,
+    psks: &[PreSharedKey],
+    by_value: bool,
 ) -> CreateCommitResult {
-    let ciphersuite = group.get_ciphersuite();
+    // Path secrets must not be encrypted to the keys of an unvalidated tree
+    if group.is_provisional() {
+        return Err(CreateCommitError::ProvisionalGroup);
+    }
+    if group.observer {
+        return Err(CreateCommitError::ObserverGroup);
+    }
+    let commit_policy = group.commit_policy;
+    let (mut adds, others): (Vec<_>, Vec<_>) = proposals
+        .into_iter()
+        .partition(|(_, proposal)| proposal.as_add().is_some());
+    let mut deferred_proposals = vec![];
+    let mut rejected_proposals = vec![];
+    if let Some(max_proposals) = commit_policy.max_proposals {
+        if others.len() > max_proposals {
+            return Err(CreateCommitError::TooManyProposals);
+        }
+        let add_count = adds.len().min(max_proposals - others.len());
+        deferred_proposals = adds.split_off(add_count);
+    }
+    // Embed the ordering token of a server-ordered deployment. It is only
+    // fetched once the proposals passed the policy.
+    let commit_token = match &group.commit_sequencer {
+        Some(commit_sequencer) => Some(
+            commit_sequencer
+                .next_token(&group.group_context.group_id, group.group_context.epoch)
+                .ok_or(CreateCommitError::SequenceTokenUnavailable)?,
+        ),
+        None => None,
+    };
+    let aad = match commit_token {
+        Some(token) => sequenced_aad(token, aad),
+        None => aad.to_vec(),
+    };
+    loop {
+        let proposals: Vec<_> = others.iter().chain(adds.iter()).cloned().collect();
+        match create_single_commit(
+            group,
+            &aad,
+            signature_key,
+            key_package_bundle.clone(),
+            proposals.clone(),
+            force_group_update,
+            psks,
+            by_value,
+        ) {
+            Ok((mut commit_bundle, mut staged_commit)) => {
+                staged_commit.commit_token = commit_token;
+                group.pending_commit = Some(PendingCommit {
+                    commit: commit_bundle.commit.clone(),
+                    proposals,
+                    staged_commit,
+                });
+                commit_bundle.deferred_proposals = deferred_proposals;
+                commit_bundle.rejected_proposals = rejected_proposals;
+                return Ok(commit_bundle);
+            }
+            // Retry with half of the adds
+            Err(CreateCommitError::CommitTooLarge) | Err(CreateCommitError::WelcomeTooLarge)
+                if adds.len() > 1 =>
+            {
+                let remaining = adds.split_off(adds.len() / 2);
+                deferred_proposals.splice(0..0, remaining);
+            }
+            // A single Add that doesn't fit wouldn't fit in a later epoch
+            // either
+            Err(CreateCommitError::CommitTooLarge) | Err(CreateCommitError::WelcomeTooLarge)
+                if adds.len() == 1 =>
+            {
+                rejected_proposals.append(&mut adds);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_single_commit(
+    group: &mut MlsGroup,
+    aad: &[u8],
+    signature_key: &SignaturePrivateKey,
+    key_package_bundle: KeyPackageBundle,
+    proposals: Vec<(Sender, Proposal)>,
+    force_group_update: bool,
+    psks: &[PreSharedKey],
+    by_value: bool,
+) -> Result<(CommitBundle, StagedCommit), CreateCommitError> {
+    trace_span!("create_commit", epoch = group.group_context.epoch.0);
+    let ciphersuite = *group.get_ciphersuite();
     let (private_key, key_package) = (
         key_package_bundle.private_key,
         key_package_bundle.key_package,
     );
 
-    // Create KeyPackageBundles
-    let mut pending_kpbs = vec![];
-    for kpb in own_key_packages {
-        let (pk, kp) = (
-            kpb.private_key,
-            kpb.key_package,
-        );
-        pending_kpbs.push(KeyPackageBundle::from_values(kp, pk));
-    }
+    let own_proposals = if by_value { proposals.clone() } else { vec![] };
 
     // Organize proposals
     let mut proposal_queue = ProposalQueue::new();
@@ -61,12 +150,22 @@ pub fn create_commit(
     // TODO Dedup proposals
     let proposal_id_list = proposal_queue.get_commit_lists(&ciphersuite);
 
+    // Resolve the PSKs of PSK proposals
+    let psks = group
+        .resolve_psks(psks, &proposal_id_list.psks, &proposal_queue)
+        .ok_or(CreateCommitError::MissingPsk)?;
+
     // Create provisional tree
-    let mut provisional_tree = group.tree.borrow_mut();
+    let mut provisional_tree = group.tree.clone();
 
     // Apply proposals to tree
     let (membership_changes, invited_members, group_removed) =
-        provisional_tree.apply_proposals(&proposal_id_list, proposal_queue, pending_kpbs);
+        provisional_tree.apply_proposals(
+            group.get_sender_index(),
+            &proposal_id_list,
+            proposal_queue,
+            &group.pending_kpbs,
+        )?;
     if group_removed {
         return Err(CreateCommitError::CannotRemoveSelf);
     }
@@ -75,21 +174,36 @@ pub fn create_commit(
     let path_required = membership_changes.path_required() || force_group_update;
 
     let (commit_secret, path, path_secrets_option, key_package_bundle_option) = if path_required {
-        // If path is eeded, compute path values
+        // If path is eeded, compute path values from a fresh leaf secret
+        let leaf_secret = group.rng_provider.random_bytes(ciphersuite.hash_length());
         let (commit_secret, kpb, path_option, path_secrets) = provisional_tree.update_own_leaf(
             Some(signature_key),
             KeyPackageBundle::from_values(key_package, private_key),
+            &leaf_secret,
             &group.group_context.serialize(),
             true,
         );
-        (commit_secret, path_option, path_secrets, Some(kpb))
+        let path_secrets = path_secrets.ok_or(CreateCommitError::MissingPathSecrets)?;
+        // Keep the path secrets to apply the Commit without processing the
+        // path again
+        let pending_commit_state = PendingCommitState {
+            path_secrets: path_secrets.clone(),
+            commit_secret: commit_secret.clone(),
+        };
+        (
+            commit_secret,
+            path_option,
+            Some(path_secrets),
+            Some((kpb, pending_commit_state)),
+        )
     } else {
         // If path is not needed, return empty commit secret
         let commit_secret = CommitSecret(zero(group.get_ciphersuite().hash_length()));
         (commit_secret, None, None, None)
     };
-    let return_kpb_option = if let Some(kpb) = key_package_bundle_option {
-        Some((kpb.get_private_key().clone(), kpb.get_key_package().clone()))
+    let return_kpb_option = if let Some((kpb, pending_commit_state)) = key_package_bundle_option {
+        group.add_pending_commit_kpb(kpb.clone(), pending_commit_state);
+        Some(kpb)
     } else {
         None
     };
@@ -99,35 +213,46 @@ pub fn create_commit(
         updates: proposal_id_list.updates,
         removes: proposal_id_list.removes,
         adds: proposal_id_list.adds,
+        psks: proposal_id_list.psks,
+        proposals: own_proposals
+            .iter()
+            .map(|(_, proposal)| proposal.clone())
+            .collect(),
         path,
     };
 
     // Create provisional group state
     let mut provisional_epoch = group.group_context.epoch;
-    provisional_epoch.increment();
+    if !provisional_epoch.increment() {
+        return Err(CreateCommitError::EpochOverflow);
+    }
 
     let confirmed_transcript_hash = update_confirmed_transcript_hash(
         group.get_ciphersuite(),
         &MLSPlaintextCommitContent::new(
             &group.group_context,
             group.get_sender_index(),
+            aad,
             commit.clone(),
         ),
         &group.interim_transcript_hash,
     );
 
+    let correlation_id = CorrelationId::new(&confirmed_transcript_hash);
+
     let provisional_group_context = GroupContext {
         group_id: group.group_context.group_id.clone(),
         epoch: provisional_epoch,
         tree_hash: provisional_tree.compute_tree_hash(),
         confirmed_transcript_hash: confirmed_transcript_hash.clone(),
+        extensions: group.group_context.extensions.clone(),
     };
 
     let mut provisional_epoch_secrets = group.epoch_secrets.clone();
-    let epoch_secret = provisional_epoch_secrets.get_new_epoch_secrets(
+    let joiner_secret = provisional_epoch_secrets.get_new_epoch_secrets(
         &ciphersuite,
         commit_secret,
-        None,
+        psk_secret(&ciphersuite, &psks).as_deref(),
         &provisional_group_context,
     );
 
@@ -141,42 +266,41 @@ pub fn create_commit(
     // Create MLSPlaintext
     let content = MLSPlaintextContentType::Commit((commit, confirmation_tag.clone()));
     let mls_plaintext = MLSPlaintext::new(
-        ciphersuite,
+        &ciphersuite,
         group.get_sender_index(),
         aad,
         content,
         signature_key,
         &group.get_context(),
     );
+    if let Some(max_commit_size) = group.commit_policy.max_commit_size {
+        if mls_plaintext.encode_detached().unwrap().len() > max_commit_size {
+            return Err(CreateCommitError::CommitTooLarge);
+        }
+    }
+
+    let interim_transcript_hash =
+        update_interim_transcript_hash(&ciphersuite, &mls_plaintext, &confirmed_transcript_hash);
 
     // Check if new members were added an create welcome message
     // TODO: Add support for extensions
-    if !membership_changes.adds.is_empty() {
-        let public_tree = RatchetTreeExtension::new(provisional_tree.public_key_tree());
-        let ratchet_tree_extension = public_tree.to_extension();
-        let tree_hash = ciphersuite.hash(&ratchet_tree_extension.extension_data);
-
+    let (welcome_option, group_info_option) = if !membership_changes.adds.is_empty() {
         // Create GroupInfo object
-        let interim_transcript_hash = update_interim_transcript_hash(
-            &ciphersuite,
-            &mls_plaintext,
-            &confirmed_transcript_hash,
-        );
         let mut group_info = GroupInfo {
             group_id: provisional_group_context.group_id.clone(),
             epoch: provisional_group_context.epoch,
-            tree_hash,
-            confirmed_transcript_hash,
-            interim_transcript_hash,
-            extensions: vec![],
+            tree_hash: provisional_group_context.tree_hash.clone(),
+            confirmed_transcript_hash: confirmed_transcript_hash.clone(),
+            interim_transcript_hash: interim_transcript_hash.clone(),
+            extensions: provisional_group_context.extensions.clone(),
             confirmation_tag: confirmation_tag.as_slice(),
             signer_index: group.get_sender_index(),
             signature: Signature::new_empty(),
         };
-        group_info.signature = group_info.sign(ciphersuite, signature_key);
+        group_info.signature = group_info.sign(&ciphersuite, signature_key);
 
         // Encrypt GroupInfo object
-        let (welcome_key, welcome_nonce) = compute_welcome_key_nonce(ciphersuite, &epoch_secret);
+        let (welcome_key, welcome_nonce) = compute_welcome_key_nonce(&ciphersuite, &joiner_secret);
 
         let encrypted_group_info = ciphersuite
             .aead_seal(
@@ -191,40 +315,41 @@ pub fn create_commit(
         let mut plaintext_secrets = vec![];
         for (index, add_proposal) in invited_members.clone() {
             let key_package = add_proposal.key_package;
-            let key_package_hash = ciphersuite.hash(&key_package.encode_detached().unwrap());
-            let path_secret = if path_required {
-                let common_ancestor = treemath::common_ancestor(index, provisional_tree.get_own_index());
+            let key_package_ref = key_package.key_package_ref();
+            // The path secrets are present if the Commit has a path
+            let path_secret = path_secrets_option.as_ref().map(|path_secrets| {
+                let common_ancestor =
+                    treemath::common_ancestor(index, provisional_tree.get_own_index());
                 let dirpath = treemath::dirpath_root(
                     provisional_tree.get_own_index(),
                     provisional_tree.leaf_count(),
                 );
                 let position = dirpath.iter().position(|&x| x == common_ancestor).unwrap();
-                let path_secrets = path_secrets_option.clone().unwrap();
-                let path_secret = path_secrets[position].clone();
-                Some(PathSecret { path_secret })
-            } else {
-                None
-            };
+                PathSecret {
+                    path_secret: path_secrets[position].clone(),
+                }
+            });
 
             let group_secrets = GroupSecrets {
-                joiner_secret: epoch_secret.clone(),
+                joiner_secret: joiner_secret.clone(),
                 path_secret,
+                psks: psks.iter().map(|(psk_id, _)| psk_id.clone()).collect(),
             };
             let group_secrets_bytes = group_secrets.encode_detached().unwrap();
             plaintext_secrets.push((
                 key_package.get_hpke_init_key().clone(),
                 group_secrets_bytes,
-                key_package_hash,
+                key_package_ref,
             ));
         }
 
         // Encrypt group secrets
         let secrets = plaintext_secrets
             .par_iter()
-            .map(|(init_key, bytes, key_package_hash)| {
+            .map(|(init_key, bytes, key_package_ref)| {
                 let encrypted_group_secrets = ciphersuite.hpke_seal(init_key, &[], &[], bytes);
                 EncryptedGroupSecrets {
-                    key_package_hash: key_package_hash.clone(),
+                    key_package_ref: key_package_ref.clone(),
                     encrypted_group_secrets,
                 }
             })
@@ -237,8 +362,35 @@ pub fn create_commit(
             secrets,
             encrypted_group_info,
         };
-        Ok((mls_plaintext, Some(welcome), return_kpb_option))
+        if let Some(max_welcome_size) = group.commit_policy.max_welcome_size {
+            if welcome.encode_detached().unwrap().len() > max_welcome_size {
+                return Err(CreateCommitError::WelcomeTooLarge);
+            }
+        }
+        (Some(welcome), Some(group_info))
     } else {
-        Ok((mls_plaintext, None, return_kpb_option))
-    }
+        (None, None)
+    };
+
+    // Keep the state of the new epoch, so that the Commit can be merged
+    // without processing it again
+    let staged_commit = StagedCommit {
+        tree: provisional_tree,
+        group_context: provisional_group_context,
+        epoch_secrets: provisional_epoch_secrets,
+        interim_transcript_hash,
+        membership_changes,
+        commit_token: None,
+    };
+    let commit_bundle = CommitBundle {
+        commit: mls_plaintext,
+        welcome: welcome_option,
+        key_package_bundle: return_kpb_option,
+        group_info: group_info_option,
+        proposals: own_proposals,
+        deferred_proposals: vec![],
+        rejected_proposals: vec![],
+        correlation_id,
+    };
+    Ok((commit_bundle, staged_commit))
 }
@@ -18,60 +18,143 @@ mod api;
 mod apply_commit;
 mod create_commit;
 mod new_from_welcome;
+mod send_queue;
+mod sequence_numbers;
+mod transfer;
+
+// Internal tests
+mod test_apply_commit;
 
 use crate::ciphersuite::*;
 use crate::codec::*;
+use crate::creds::*;
+use crate::diagnostics::{EpochSummary, GroupDiagnostics, TreeSummary};
+use crate::error::MlsError;
+use crate::extensions::*;
 use crate::framing::*;
 use crate::group::*;
 use crate::key_packages::*;
 use crate::messages::{proposals::*, *};
+use crate::metrics::*;
 use crate::schedule::*;
+use crate::storage::*;
 use crate::tree::{astree::*, index::*, node::*, *};
+use crate::utils::*;
+use crate::validator::*;
+use rayon::prelude::*;
 
 pub use api::*;
 use apply_commit::*;
 use create_commit::*;
 use new_from_welcome::*;
+pub use new_from_welcome::{WelcomeProcessor, WelcomeStage};
+pub use send_queue::*;
+pub use sequence_numbers::*;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Arc;
+use zeroize::Zeroize;
 
-use std::cell::{Ref, RefCell};
+/// Label of signatures on application payloads, see `sign_payload`
+const PAYLOAD_SIGNATURE_LABEL: &str = "MemberPayloadTBS";
+
+/// Version of the encoding of the group state
+const GROUP_STATE_VERSION: u8 = 5;
 
 pub struct MlsGroup {
     ciphersuite: Ciphersuite,
     group_context: GroupContext,
-    generation: u32,
+    // Number of messages this member encrypted in the group across all
+    // epochs
+    send_counter: u32,
     epoch_secrets: EpochSecrets,
-    astree: RefCell<ASTree>,
-    tree: RefCell<RatchetTree>,
+    astree: ASTree,
+    tree: RatchetTree,
     interim_transcript_hash: Vec<u8>,
+    // Own KeyPackageBundles of Update proposals and Commits that were created
+    // in the current epoch, indexed by the hash of the KeyPackage
+    pending_kpbs: HashMap<KeyPackageRef, KeyPackageBundle>,
+    // Path secrets of own Commits that were created in the current epoch,
+    // indexed by the hash of the new leaf KeyPackage
+    pending_commit_states: HashMap<KeyPackageRef, PendingCommitState>,
+    // The last own Commit that was created in the current epoch, not stored
+    // with the group
+    pending_commit: Option<PendingCommit>,
+    // Local limits for Commits, not stored with the group
+    commit_policy: CommitPolicy,
+    // Local limits for the lifetime of an epoch, not stored with the group
+    epoch_policy: EpochPolicy,
+    // Time the current epoch started at, in seconds since the Unix epoch
+    epoch_started_at: u64,
+    // Source of the current time from the `GroupConfig`, not stored with the
+    // group
+    time_provider: Arc<dyn TimeProvider>,
+    // Source of the leaf secrets of Commits from the `GroupConfig`, not
+    // stored with the group
+    rng_provider: Arc<dyn RngProvider>,
+    // Number of messages encrypted or decrypted in the current epoch
+    epoch_message_count: u32,
+    // Resumption secrets of the current and the most recent past epochs
+    resumption_secrets: Vec<(GroupEpoch, Vec<u8>)>,
+    // Source of PSKs other than the group's own resumption secrets, not
+    // stored with the group
+    psk_store: Option<Box<dyn PskStore>>,
+    // Source of ordering tokens for Commits, not stored with the group
+    commit_sequencer: Option<Box<dyn CommitSequencer>>,
+    // Ordering token of the last Commit that was applied
+    last_commit_token: Option<u64>,
+    // Whether application messages are accepted as MLSPlaintext, not stored
+    // with the group
+    allow_plaintext_application_messages: bool,
+    // Ratchet tree of a provisional join that was not validated yet
+    provisional_tree: Option<ProvisionalTree>,
+    // Whether the group was imported from a transfer package and can only
+    // receive
+    observer: bool,
+    // Sender data and handshake keys of the own leaf in the current epoch,
+    // not stored with the group
+    sender_key_cache: SenderKeyCache,
+    // Encrypted messages that wait for delivery
+    send_queue: SendQueue,
+    // Sequence numbers of application messages, if enabled
+    sequence_numbers: Option<SequenceNumbers>,
+    // Receives the exporter secret of every epoch, not stored with the group
+    #[cfg(feature = "dangerous-epoch-escrow")]
+    epoch_escrow: Option<Box<dyn escrow::EpochEscrow>>,
+}
+
+/// An own Commit that was created in the current epoch and not applied yet,
+/// together with the proposals it covers
+struct PendingCommit {
+    commit: MLSPlaintext,
+    proposals: Vec<(Sender, Proposal)>,
+    staged_commit: StagedCommit,
+}
+
+/// The path secrets of an own Commit, so the committer can apply it without
+/// processing its own `DirectPath`
+#[derive(Clone)]
+struct PendingCommitState {
+    path_secrets: Vec<Vec<u8>>,
+    commit_secret: CommitSecret,
+}
+
+/// The group state of the epoch a Commit leads to, before it is merged into
+/// the group
+pub(crate) struct StagedCommit {
+    tree: RatchetTree,
+    group_context: GroupContext,
+    epoch_secrets: EpochSecrets,
+    interim_transcript_hash: Vec<u8>,
+    membership_changes: MembershipChanges,
+    commit_token: Option<u64>,
 }
 
 impl Api for MlsGroup {
     fn new(id: &[u8], ciphersuite: Ciphersuite, key_package_bundle: KeyPackageBundle) -> MlsGroup {
-        let group_id = GroupId { value: id.to_vec() };
-        let epoch_secrets = EpochSecrets::new();
-        let astree = ASTree::new(&epoch_secrets.application_secret, LeafIndex::from(1u32));
-        let (private_key, key_package) = (
-            key_package_bundle.private_key,
-            key_package_bundle.key_package,
-        );
-        let kpb = KeyPackageBundle::from_values(key_package, private_key);
-        let tree = RatchetTree::new(ciphersuite, kpb);
-        let group_context = GroupContext {
-            group_id,
-            epoch: GroupEpoch(0),
-            tree_hash: tree.compute_tree_hash(),
-            confirmed_transcript_hash: vec![],
-        };
-        let interim_transcript_hash = vec![];
-        MlsGroup {
-            ciphersuite,
-            group_context,
-            generation: 0,
-            epoch_secrets,
-            astree: RefCell::new(astree),
-            tree: RefCell::new(tree),
-            interim_transcript_hash,
-        }
+        Self::new_with_extensions(id, ciphersuite, key_package_bundle, vec![])
     }
     // Join a group from a welcome message
     fn new_from_welcome(
@@ -79,7 +162,15 @@ impl Api for MlsGroup {
         nodes_option: Option<Vec<Option<Node>>>,
         kpb: KeyPackageBundle,
     ) -> Result<Self, WelcomeError> {
-        new_from_welcome(welcome, nodes_option, kpb)
+        new_from_welcome(welcome, nodes_option, kpb, &[], false)
+    }
+    fn new_from_welcome_with_psks(
+        welcome: Welcome,
+        nodes_option: Option<Vec<Option<Node>>>,
+        kpb: KeyPackageBundle,
+        psks: &[PreSharedKey],
+    ) -> Result<Self, WelcomeError> {
+        new_from_welcome(welcome, nodes_option, kpb, psks, false)
     }
 
     // Create handshake messages
@@ -88,70 +179,72 @@ impl Api for MlsGroup {
         aad: &[u8],
         signature_key: &SignaturePrivateKey,
         joiner_key_package: KeyPackage,
-    ) -> (MLSPlaintext, Proposal) {
+    ) -> Result<(MLSPlaintext, Proposal), CreateProposalError> {
         let add_proposal = AddProposal {
             key_package: joiner_key_package,
         };
-        let proposal = Proposal::Add(add_proposal);
-        let content = MLSPlaintextContentType::Proposal(proposal.clone());
-        let mls_plaintext = MLSPlaintext::new(
-            &self.ciphersuite,
-            self.get_sender_index(),
-            aad,
-            content,
-            signature_key,
-            &self.get_context(),
-        );
-        (mls_plaintext, proposal)
+        self.create_proposal(aad, signature_key, Proposal::Add(add_proposal))
     }
     fn create_update_proposal(
-        &self,
+        &mut self,
         aad: &[u8],
         signature_key: &SignaturePrivateKey,
-        key_package: KeyPackage,
-    ) -> (MLSPlaintext, Proposal) {
-        let update_proposal = UpdateProposal { key_package };
-        let proposal = Proposal::Update(update_proposal);
-        let content = MLSPlaintextContentType::Proposal(proposal.clone());
-        let mls_plaintext = MLSPlaintext::new(
-            &self.ciphersuite,
-            self.get_sender_index(),
-            aad,
-            content,
-            signature_key,
-            &self.get_context(),
-        );
-        (mls_plaintext, proposal)
+        key_package_bundle: KeyPackageBundle,
+    ) -> Result<(MLSPlaintext, Proposal), CreateProposalError> {
+        let update_proposal = UpdateProposal {
+            key_package: key_package_bundle.get_key_package().clone(),
+        };
+        let result = self.create_proposal(aad, signature_key, Proposal::Update(update_proposal))?;
+        self.add_pending_kpb(key_package_bundle);
+        Ok(result)
     }
     fn create_remove_proposal(
         &self,
         aad: &[u8],
         signature_key: &SignaturePrivateKey,
         removed_index: LeafIndex,
-    ) -> (MLSPlaintext, Proposal) {
+    ) -> Result<(MLSPlaintext, Proposal), CreateProposalError> {
         let remove_proposal = RemoveProposal {
             removed: removed_index.into(),
         };
-        let proposal = Proposal::Remove(remove_proposal);
-        let content = MLSPlaintextContentType::Proposal(proposal.clone());
-        let mls_plaintext = MLSPlaintext::new(
-            &self.ciphersuite,
-            self.get_sender_index(),
+        self.create_proposal(aad, signature_key, Proposal::Remove(remove_proposal))
+    }
+    fn create_psk_proposal(
+        &self,
+        aad: &[u8],
+        signature_key: &SignaturePrivateKey,
+        psk_id: PreSharedKeyID,
+    ) -> Result<(MLSPlaintext, Proposal), CreateProposalError> {
+        let psk_proposal = PreSharedKeyProposal { psk: psk_id };
+        self.create_proposal(aad, signature_key, Proposal::PreSharedKey(psk_proposal))
+    }
+    fn create_commit(
+        &mut self,
+        aad: &[u8],
+        signature_key: &SignaturePrivateKey,
+        key_package_bundle: KeyPackageBundle,
+        proposals: Vec<(Sender, Proposal)>,
+        force_self_update: bool,
+    ) -> CreateCommitResult {
+        create_commit(
+            self,
             aad,
-            content,
             signature_key,
-            &self.get_context(),
-        );
-        (mls_plaintext, proposal)
+            key_package_bundle,
+            proposals,
+            force_self_update,
+            &[],
+            false,
+        )
     }
-    fn create_commit(
-        &self,
+    fn create_commit_with_psks(
+        &mut self,
         aad: &[u8],
         signature_key: &SignaturePrivateKey,
         key_package_bundle: KeyPackageBundle,
         proposals: Vec<(Sender, Proposal)>,
-        own_key_packages: Vec<KeyPackageBundle>,
         force_self_update: bool,
+        psks: &[PreSharedKey],
     ) -> CreateCommitResult {
         create_commit(
             self,
@@ -159,8 +252,9 @@ impl Api for MlsGroup {
             signature_key,
             key_package_bundle,
             proposals,
-            own_key_packages,
             force_self_update,
+            psks,
+            false,
         )
     }
 
@@ -169,9 +263,16 @@ impl Api for MlsGroup {
         &mut self,
         mls_plaintext: MLSPlaintext,
         proposals: Vec<(Sender, Proposal)>,
-        own_key_packages: Vec<KeyPackageBundle>,
-    ) -> Result<(), ApplyCommitError> {
-        apply_commit(self, mls_plaintext, proposals, own_key_packages)
+    ) -> Result<MembershipChanges, ApplyCommitError> {
+        apply_commit(self, mls_plaintext, proposals, &[])
+    }
+    fn apply_commit_with_psks(
+        &mut self,
+        mls_plaintext: MLSPlaintext,
+        proposals: Vec<(Sender, Proposal)>,
+        psks: &[PreSharedKey],
+    ) -> Result<MembershipChanges, ApplyCommitError> {
+        apply_commit(self, mls_plaintext, proposals, psks)
     }
 
     // Create application message
@@ -181,47 +282,101 @@ impl Api for MlsGroup {
         msg: &[u8],
         signature_key: &SignaturePrivateKey,
     ) -> MLSPlaintext {
-        let content = MLSPlaintextContentType::Application(msg.to_vec());
-        MLSPlaintext::new(
-            &self.ciphersuite,
-            self.get_sender_index(),
-            aad,
-            content,
-            signature_key,
-            &self.get_context(),
-        )
+        let content = MLSPlaintextContentType::Application(msg.to_vec().into());
+        self.new_application_message(aad, content, signature_key)
+    }
+
+    fn create_application_message_with_content_type(
+        &self,
+        aad: &[u8],
+        msg: &[u8],
+        content_type: u16,
+        signature_key: &SignaturePrivateKey,
+    ) -> MLSPlaintext {
+        let content = MLSPlaintextContentType::Application(ApplicationData::new(
+            msg.to_vec(),
+            Some(content_type),
+        ));
+        self.new_application_message(aad, content, signature_key)
     }
 
     // Encrypt/Decrypt MLS message
-    fn encrypt(&mut self, mls_plaintext: MLSPlaintext) -> MLSCiphertext {
-        let mut astree = self.astree.borrow_mut();
-        let generation = astree.get_generation(mls_plaintext.sender.sender);
-        let application_secrets = astree
-            .get_secret(&self.ciphersuite, mls_plaintext.sender.sender, generation)
-            .unwrap();
-        MLSCiphertext::new_from_plaintext(&mls_plaintext, &self, generation, &application_secrets)
-    }
-
-    fn decrypt(&mut self, mls_ciphertext: MLSCiphertext) -> MLSPlaintext {
-        let tree = self.tree.borrow();
-        let mut roster = Vec::new();
-        for i in 0..tree.leaf_count().as_usize() {
-            let node = &tree.nodes[NodeIndex::from(i).as_usize()];
-            let credential = if let Some(kp) = &node.key_package {
-                kp.get_credential()
-            } else {
-                panic!("Missing key package");
-            };
-            roster.push(credential);
+    fn encrypt(&mut self, mls_plaintext: MLSPlaintext) -> Result<MLSCiphertext, EncryptionError> {
+        // An observer shares the sender ratchets of the exporting device
+        if self.observer {
+            return Err(EncryptionError::ObserverGroup);
+        }
+        if mls_plaintext.authenticated_data.len() > MAX_AAD_SIZE {
+            return Err(EncryptionError::AadTooLarge);
         }
+        let send_counter = self
+            .send_counter
+            .checked_add(1)
+            .ok_or(EncryptionError::SendCounterExhausted)?;
+        let generation = self.astree.get_generation(mls_plaintext.sender.sender);
+        let ciphersuite = &self.ciphersuite;
+        let astree = &mut self.astree;
+        let sender = mls_plaintext.sender.sender;
+        let (sender_keys, application_secrets) = self
+            .sender_key_cache
+            .get_or_derive(
+                ciphersuite,
+                &self.epoch_secrets,
+                self.group_context.epoch,
+                &mls_plaintext.sender,
+                generation,
+                || astree.get_secret(ciphersuite, sender, generation),
+            )
+            .map_err(|_| EncryptionError::SecretDerivation)?;
+        let mls_ciphertext = MLSCiphertext::new_from_plaintext_with_keys(
+            &mls_plaintext,
+            ciphersuite,
+            &self.group_context,
+            generation,
+            application_secrets,
+            sender_keys,
+        );
+        // Only messages that were encrypted are counted
+        self.send_counter = send_counter;
+        self.epoch_message_count = self.epoch_message_count.saturating_add(1);
+        Ok(mls_ciphertext)
+    }
 
-        mls_ciphertext.to_plaintext(
+    fn decrypt(
+        &mut self,
+        mls_ciphertext: MLSCiphertext,
+    ) -> Result<ProcessedMessage, DecryptionError> {
+        trace_span!("decrypt", epoch = mls_ciphertext.epoch.0);
+        let roster = get_roster(&self.tree);
+        let mls_plaintext = match mls_ciphertext.to_plaintext(
             &self.ciphersuite,
             &roster,
             &self.epoch_secrets,
-            &mut self.astree.borrow_mut(),
+            &mut self.astree,
             &self.group_context,
-        )
+        ) {
+            Ok(mls_plaintext) => mls_plaintext,
+            Err(error) => {
+                record_decryption_failure(&error);
+                return Err(error);
+            }
+        };
+        self.epoch_message_count = self.epoch_message_count.saturating_add(1);
+        let sender_index = mls_plaintext.sender.sender;
+        // The sender's credential was already used to verify the signature
+        let sender_credential = roster[sender_index.as_usize()].unwrap().clone();
+        let mut processed_message = ProcessedMessage::new(
+            mls_plaintext,
+            sender_index,
+            sender_credential,
+            self.time_provider.now(),
+        );
+        if let Err(error) = self.check_sequence_number(&mut processed_message) {
+            record_decryption_failure(&error);
+            return Err(error);
+        }
+        self.strip_commit_token(&mut processed_message);
+        Ok(processed_message)
     }
 
     // Exporter
@@ -234,62 +389,1489 @@ impl Api for MlsGroup {
             key_length,
         )
     }
+
+    fn verify_plaintext(&self, mls_plaintext: &MLSPlaintext) -> Result<Credential, SenderError> {
+        // Application data must always be encrypted
+        if let MLSPlaintextContentType::Application(_) = mls_plaintext.content {
+            if !self.allow_plaintext_application_messages {
+                return Err(SenderError::UnencryptedApplicationMessage);
+            }
+        }
+        let sender = mls_plaintext.sender;
+        let credential = match sender.sender_type {
+            SenderType::Member => self
+                .tree
+                .get_leaf_node(sender.sender)
+                .map(|leaf_node| leaf_node.get_credential().clone())
+                .ok_or(SenderError::UnknownMember)?,
+            SenderType::External => match &mls_plaintext.content {
+                // External senders can only send proposals
+                MLSPlaintextContentType::Proposal(_) => self
+                    .group_context
+                    .extensions
+                    .iter()
+                    .find(|e| e.get_type() == ExtensionType::ExternalSenders)
+                    .and_then(|e| {
+                        ExternalSendersExtension::try_from_bytes(&e.extension_data)
+                            .ok()?
+                            .senders
+                            .get(sender.sender.as_usize())
+                            .cloned()
+                    })
+                    .ok_or(SenderError::UnknownExternalSender)?,
+                _ => return Err(SenderError::InvalidContentForSender),
+            },
+            SenderType::NewMemberProposal => match &mls_plaintext.content {
+                MLSPlaintextContentType::Proposal(Proposal::Add(add_proposal)) => {
+                    add_proposal.key_package.get_credential().clone()
+                }
+                _ => return Err(SenderError::InvalidContentForSender),
+            },
+            SenderType::NewMemberCommit => match &mls_plaintext.content {
+                MLSPlaintextContentType::Commit((commit, _)) => match &commit.path {
+                    Some(path) => path.leaf_key_package.get_credential().clone(),
+                    None => return Err(SenderError::InvalidContentForSender),
+                },
+                _ => return Err(SenderError::InvalidContentForSender),
+            },
+            _ => return Err(SenderError::InvalidSenderType),
+        };
+        if !mls_plaintext.verify(&self.group_context, &credential) {
+            return Err(SenderError::SignatureFailure);
+        }
+        Ok(credential)
+    }
+
+    fn export_membership_key(&self) -> Vec<u8> {
+        membership_key(self.get_ciphersuite(), &self.epoch_secrets)
+    }
+
+    fn membership_tag(&self, mls_ciphertext: &MLSCiphertext) -> MembershipTag {
+        MembershipTag::new(
+            self.get_ciphersuite(),
+            &self.export_membership_key(),
+            mls_ciphertext,
+        )
+    }
+}
+
+impl Storable for MlsGroup {
+    const STORAGE_TYPE: StorageType = StorageType::MlsGroup;
+}
+
+// Only the public identifiers are printed, the group holds secrets
+impl fmt::Debug for MlsGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MlsGroup")
+            .field("group_id", &self.group_context.group_id)
+            .field("epoch", &self.group_context.epoch)
+            .finish_non_exhaustive()
+    }
 }
 
 impl Codec for MlsGroup {
+    // The encoding starts with `GROUP_STATE_VERSION`, which has to be bumped
+    // whenever fields are added or changed. Groups stored with another
+    // version are rejected instead of being decoded with default values.
+    // Provisional groups can't be stored, their tree would be trusted once
+    // loaded.
     fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        if self.provisional_tree.is_some() {
+            return Err(CodecError::EncodingError);
+        }
+        GROUP_STATE_VERSION.encode(buffer)?;
         self.ciphersuite.encode(buffer)?;
-        self.group_context.encode(buffer)?;
-        self.generation.encode(buffer)?;
+        self.group_context.group_id.encode(buffer)?;
+        self.group_context.epoch.encode(buffer)?;
+        encode_vec(VecSize::VecU8, buffer, &self.group_context.tree_hash)?;
+        encode_vec(
+            VecSize::VecU8,
+            buffer,
+            &self.group_context.confirmed_transcript_hash,
+        )?;
+        self.send_counter.encode(buffer)?;
         self.epoch_secrets.encode(buffer)?;
-        self.astree.borrow().encode(buffer)?;
-        self.tree.borrow().encode(buffer)?;
+        self.astree.encode(buffer)?;
+        self.tree.encode(buffer)?;
         encode_vec(VecSize::VecU8, buffer, &self.interim_transcript_hash)?;
+        encode_vec(VecSize::VecU16, buffer, &self.group_context.extensions)?;
+        (self.resumption_secrets.len() as u8).encode(buffer)?;
+        for (epoch, resumption_secret) in self.resumption_secrets.iter() {
+            epoch.encode(buffer)?;
+            encode_vec(VecSize::VecU8, buffer, resumption_secret)?;
+        }
+        self.epoch_started_at.encode(buffer)?;
+        self.epoch_message_count.encode(buffer)?;
+        self.last_commit_token.encode(buffer)?;
+        (self.observer as u8).encode(buffer)?;
+        self.send_queue.encode(buffer)?;
+        self.sequence_numbers.encode(buffer)?;
+        self.pending_kpbs.encode(buffer)?;
         Ok(())
     }
     fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        if u8::decode(cursor)? != GROUP_STATE_VERSION {
+            return Err(CodecError::DecodingError);
+        }
         let ciphersuite = Ciphersuite::decode(cursor)?;
-        let group_context = GroupContext::decode(cursor)?;
-        let generation = u32::decode(cursor)?;
+        let group_id = GroupId::decode(cursor)?;
+        let epoch = GroupEpoch::decode(cursor)?;
+        let tree_hash = decode_vec(VecSize::VecU8, cursor)?;
+        let confirmed_transcript_hash = decode_vec(VecSize::VecU8, cursor)?;
+        let send_counter = u32::decode(cursor)?;
         let epoch_secrets = EpochSecrets::decode(cursor)?;
-        let astree = ASTree::decode(cursor)?;
+        let mut astree = ASTree::decode(cursor)?;
+        // The application secret is not part of the encoded ASTree
+        astree.retain_application_secret(&epoch_secrets.application_secret);
         let tree = RatchetTree::decode(cursor)?;
         let interim_transcript_hash = decode_vec(VecSize::VecU8, cursor)?;
+        let extensions = decode_vec(VecSize::VecU16, cursor)?;
+        let mut resumption_secrets = vec![];
+        let count = u8::decode(cursor)?;
+        for _ in 0..count {
+            let epoch = GroupEpoch::decode(cursor)?;
+            let resumption_secret = decode_vec(VecSize::VecU8, cursor)?;
+            resumption_secrets.push((epoch, resumption_secret));
+        }
+        let epoch_started_at = u64::decode(cursor)?;
+        let epoch_message_count = u32::decode(cursor)?;
+        let last_commit_token = Option::<u64>::decode(cursor)?;
+        let observer = match u8::decode(cursor)? {
+            0 => false,
+            1 => true,
+            _ => return Err(CodecError::DecodingError),
+        };
+        let send_queue = SendQueue::decode(cursor)?;
+        let sequence_numbers = Option::<SequenceNumbers>::decode(cursor)?;
+        let pending_kpbs = HashMap::decode(cursor)?;
+        let group_context = GroupContext {
+            group_id,
+            epoch,
+            tree_hash,
+            confirmed_transcript_hash,
+            extensions,
+        };
         let group = MlsGroup {
             ciphersuite,
             group_context,
-            generation,
+            send_counter,
             epoch_secrets,
-            astree: RefCell::new(astree),
-            tree: RefCell::new(tree),
+            astree,
+            tree,
             interim_transcript_hash,
+            pending_kpbs,
+            pending_commit_states: HashMap::new(),
+            pending_commit: None,
+            commit_policy: CommitPolicy::default(),
+            epoch_policy: EpochPolicy::default(),
+            epoch_started_at,
+            time_provider: GroupConfig::default().time_provider,
+            rng_provider: GroupConfig::default().rng_provider,
+            epoch_message_count,
+            resumption_secrets,
+            psk_store: None,
+            commit_sequencer: None,
+            last_commit_token,
+            allow_plaintext_application_messages: false,
+            provisional_tree: None,
+            observer,
+            sender_key_cache: SenderKeyCache::default(),
+            send_queue,
+            sequence_numbers,
+            #[cfg(feature = "dangerous-epoch-escrow")]
+            epoch_escrow: None,
         };
         Ok(group)
     }
 }
 
 impl MlsGroup {
-    pub fn get_tree(&self) -> Ref<RatchetTree> {
-        self.tree.borrow()
+    /// Join a group from a `Welcome` without validating the ratchet tree,
+    /// which can take a while for large groups. The GroupInfo signature, the
+    /// public keys on the joiner's own path and the confirmation tag are
+    /// checked, so that messages can be decrypted right away. The group can't
+    /// commit or be stored until the tree was validated with
+    /// `finish_validation`.
+    ///
+    /// Until then, the credentials of the members, including the senders of
+    /// decrypted messages and the signer of the GroupInfo, come from a tree
+    /// that wasn't validated. They must not be trusted before
+    /// `finish_validation` succeeded.
+    pub fn new_from_welcome_provisional(
+        welcome: Welcome,
+        nodes_option: Option<Vec<Option<Node>>>,
+        kpb: KeyPackageBundle,
+    ) -> Result<Self, WelcomeError> {
+        new_from_welcome(welcome, nodes_option, kpb, &[], true)
+    }
+
+    /// Returns `true` if the group was joined with
+    /// `new_from_welcome_provisional` and its tree was not validated yet.
+    pub fn is_provisional(&self) -> bool {
+        self.provisional_tree.is_some()
+    }
+
+    /// Validate the ratchet tree of a provisional join, i.e. the tree hash and
+    /// the parent hashes of the tree the group was joined with. If this fails,
+    /// the group must be discarded. Does nothing if the group isn't
+    /// provisional.
+    pub fn finish_validation(&mut self) -> Result<(), WelcomeError> {
+        if let Some(provisional_tree) = &self.provisional_tree {
+            provisional_tree.validate(&self.ciphersuite)?;
+        }
+        self.provisional_tree = None;
+        Ok(())
+    }
+
+    /// Create a new group with the given group context `extensions`.
+    pub fn new_with_extensions(
+        id: &[u8],
+        ciphersuite: Ciphersuite,
+        key_package_bundle: KeyPackageBundle,
+        extensions: Vec<Extension>,
+    ) -> MlsGroup {
+        Self::new_with_config(
+            id,
+            ciphersuite,
+            key_package_bundle,
+            extensions,
+            &GroupConfig::default(),
+        )
+    }
+
+    /// Create a new group with the given group context `extensions` that
+    /// takes the current time from the time provider of `config`.
+    pub fn new_with_config(
+        id: &[u8],
+        ciphersuite: Ciphersuite,
+        key_package_bundle: KeyPackageBundle,
+        extensions: Vec<Extension>,
+        config: &GroupConfig,
+    ) -> MlsGroup {
+        let group_id = GroupId { value: id.to_vec() };
+        let (private_key, key_package) = (
+            key_package_bundle.private_key,
+            key_package_bundle.key_package,
+        );
+        let kpb = KeyPackageBundle::from_values(key_package, private_key);
+        let tree = RatchetTree::new(ciphersuite, kpb);
+        let group_context = GroupContext {
+            group_id,
+            epoch: GroupEpoch(0),
+            tree_hash: tree.compute_tree_hash(),
+            confirmed_transcript_hash: vec![],
+            extensions,
+        };
+        let init_secret = randombytes(ciphersuite.hash_length());
+        let epoch_secrets = EpochSecrets::new_initial(&ciphersuite, init_secret, &group_context);
+        let astree = ASTree::new(&epoch_secrets.application_secret, 1);
+        let interim_transcript_hash = vec![];
+        let resumption_secrets =
+            vec![(group_context.epoch, epoch_secrets.resumption_secret.clone())];
+        MlsGroup {
+            ciphersuite,
+            group_context,
+            send_counter: 0,
+            epoch_secrets,
+            astree,
+            tree,
+            interim_transcript_hash,
+            pending_kpbs: HashMap::new(),
+            pending_commit_states: HashMap::new(),
+            pending_commit: None,
+            commit_policy: CommitPolicy::default(),
+            epoch_policy: EpochPolicy::default(),
+            epoch_started_at: config.time_provider.now(),
+            time_provider: config.time_provider.clone(),
+            rng_provider: config.rng_provider.clone(),
+            epoch_message_count: 0,
+            resumption_secrets,
+            psk_store: None,
+            commit_sequencer: None,
+            last_commit_token: None,
+            allow_plaintext_application_messages: false,
+            provisional_tree: None,
+            observer: false,
+            sender_key_cache: SenderKeyCache::default(),
+            send_queue: SendQueue::default(),
+            sequence_numbers: None,
+            #[cfg(feature = "dangerous-epoch-escrow")]
+            epoch_escrow: None,
+        }
+    }
+
+    /// Create a new group and add the members of `key_packages` with a single
+    /// Commit that is applied right away, so the group starts out in epoch 1.
+    /// Returns the group and the `Welcome` for the new members.
+    pub fn new_with_members(
+        id: &[u8],
+        ciphersuite: Ciphersuite,
+        key_package_bundle: KeyPackageBundle,
+        signature_key: &SignaturePrivateKey,
+        key_packages: Vec<KeyPackage>,
+    ) -> Result<(MlsGroup, Welcome), CreateCommitError> {
+        if key_packages.is_empty() {
+            return Err(CreateCommitError::NoMembersToAdd);
+        }
+        let mut group = Self::new(id, ciphersuite, key_package_bundle.clone());
+        let commit_bundle =
+            group.add_members(&[], signature_key, key_package_bundle, &key_packages)?;
+        // Our own Commit can't be rejected
+        let proposals = commit_bundle.get_proposal_list();
+        group.apply_commit(commit_bundle.commit, proposals).unwrap();
+        Ok((group, commit_bundle.welcome.unwrap()))
+    }
+
+    /// Add the members of `key_packages` with a Commit that includes the Add
+    /// proposals by value, so no proposal messages have to be sent.
+    /// `key_package_bundle` is only used if the Commit needs a path.
+    pub fn add_members(
+        &mut self,
+        aad: &[u8],
+        signature_key: &SignaturePrivateKey,
+        key_package_bundle: KeyPackageBundle,
+        key_packages: &[KeyPackage],
+    ) -> CreateCommitResult {
+        let proposals = key_packages
+            .iter()
+            .map(|key_package| {
+                Proposal::Add(AddProposal {
+                    key_package: key_package.clone(),
+                })
+            })
+            .collect();
+        self.commit_own_proposals(aad, signature_key, key_package_bundle, proposals, false)
+    }
+
+    /// Like `add_members`, but the key packages of the users `user_ids` are
+    /// fetched with `fetcher` first. For every user, one key package is
+    /// selected that uses the group's ciphersuite and supports the group
+    /// context extensions, see `select_key_package`.
+    pub async fn add_members_by_id(
+        &mut self,
+        aad: &[u8],
+        signature_key: &SignaturePrivateKey,
+        key_package_bundle: KeyPackageBundle,
+        user_ids: &[&[u8]],
+        fetcher: &dyn KeyPackageFetcher,
+    ) -> Result<CommitBundle, MlsError> {
+        let requirements = KeyPackageRequirements {
+            ciphersuite: self.ciphersuite.get_name(),
+            required_extensions: self
+                .group_context
+                .extensions
+                .iter()
+                .map(|extension| extension.get_type())
+                .collect(),
+        };
+        let key_packages = fetch_key_packages(
+            fetcher,
+            user_ids,
+            &requirements,
+            self.time_provider.as_ref(),
+        )
+        .await?;
+        Ok(self.add_members(aad, signature_key, key_package_bundle, &key_packages)?)
+    }
+
+    /// Remove the members at `leaf_indices` with a Commit that includes the
+    /// Remove proposals by value and a fresh own leaf from
+    /// `key_package_bundle`.
+    pub fn remove_members(
+        &mut self,
+        aad: &[u8],
+        signature_key: &SignaturePrivateKey,
+        key_package_bundle: KeyPackageBundle,
+        leaf_indices: &[LeafIndex],
+    ) -> CreateCommitResult {
+        let proposals = leaf_indices
+            .iter()
+            .map(|leaf_index| {
+                Proposal::Remove(RemoveProposal {
+                    removed: leaf_index.as_u32(),
+                })
+            })
+            .collect();
+        self.commit_own_proposals(aad, signature_key, key_package_bundle, proposals, false)
+    }
+
+    /// Close the group: remove all other members with a final Commit, wipe
+    /// the secrets of the group and return the Commit together with a
+    /// `GroupTombstone`. Without other members, the final Commit only
+    /// replaces the own leaf with `key_package_bundle`. The Commit has to be
+    /// sent, but can't be merged anymore. If the Commit can't be created, the
+    /// group is returned unchanged together with the error.
+    #[allow(clippy::result_large_err)]
+    pub fn close(
+        mut self,
+        aad: &[u8],
+        signature_key: &SignaturePrivateKey,
+        key_package_bundle: KeyPackageBundle,
+    ) -> Result<(CommitBundle, GroupTombstone), (MlsGroup, CreateCommitError)> {
+        let own_index = self.get_sender_index();
+        let other_members: Vec<LeafIndex> = (0..self.tree.leaf_count())
+            .map(LeafIndex::from)
+            .filter(|index| *index != own_index && self.tree.get_leaf_node(*index).is_some())
+            .collect();
+        let commit_bundle =
+            match self.remove_members(aad, signature_key, key_package_bundle, &other_members) {
+                Ok(commit_bundle) => commit_bundle,
+                Err(error) => return Err((self, error)),
+            };
+        // The final epoch is only recorded, it is never entered
+        let final_context = &self
+            .pending_commit
+            .as_ref()
+            .expect("an own Commit was just created")
+            .staged_commit
+            .group_context;
+        let tombstone = GroupTombstone {
+            group_id: final_context.group_id.clone(),
+            epoch: final_context.epoch,
+            confirmed_transcript_hash: final_context.confirmed_transcript_hash.clone(),
+            closed_at: self.time_provider.now(),
+        };
+        self.wipe_secrets();
+        Ok((commit_bundle, tombstone))
+    }
+
+    /// Overwrite the secrets of the current and the pending epoch, the
+    /// private keys of the own leaf and its path and the pending key package
+    /// bundles with zeros before the group is dropped.
+    fn wipe_secrets(&mut self) {
+        self.epoch_secrets.wipe();
+        self.astree.wipe();
+        self.tree.wipe_own_secrets();
+        self.sender_key_cache.wipe();
+        for (_, resumption_secret) in self.resumption_secrets.iter_mut() {
+            resumption_secret.zeroize();
+        }
+        for kpb in self.pending_kpbs.values_mut() {
+            kpb.wipe();
+        }
+        for pending_commit_state in self.pending_commit_states.values_mut() {
+            for path_secret in pending_commit_state.path_secrets.iter_mut() {
+                path_secret.zeroize();
+            }
+            pending_commit_state.commit_secret.0.zeroize();
+        }
+        if let Some(pending_commit) = &mut self.pending_commit {
+            pending_commit.staged_commit.epoch_secrets.wipe();
+            pending_commit.staged_commit.tree.wipe_own_secrets();
+        }
+    }
+
+    /// Replace the own leaf with `key_package_bundle` through a Commit
+    /// without proposals.
+    pub fn update_self(
+        &mut self,
+        aad: &[u8],
+        signature_key: &SignaturePrivateKey,
+        key_package_bundle: KeyPackageBundle,
+    ) -> CreateCommitResult {
+        self.commit_own_proposals(aad, signature_key, key_package_bundle, vec![], true)
+    }
+
+    /// Commit `proposals` of this member, which are included in the Commit
+    /// by value.
+    fn commit_own_proposals(
+        &mut self,
+        aad: &[u8],
+        signature_key: &SignaturePrivateKey,
+        key_package_bundle: KeyPackageBundle,
+        proposals: Vec<Proposal>,
+        force_self_update: bool,
+    ) -> CreateCommitResult {
+        let sender = Sender::member(self.get_sender_index());
+        let proposals = proposals
+            .into_iter()
+            .map(|proposal| (sender, proposal))
+            .collect();
+        create_commit(
+            self,
+            aad,
+            signature_key,
+            key_package_bundle,
+            proposals,
+            force_self_update,
+            &[],
+            true,
+        )
+    }
+
+    /// Apply `their_commit` of another member that was created in the same
+    /// epoch as an own Commit that was not applied yet. The own Commit is
+    /// discarded together with the key material of its path, neither it nor
+    /// its `Welcome` must be sent anymore. The proposals it covered that
+    /// `their_commit` doesn't are reported, they have to be proposed again
+    /// in the new epoch. Without a conflicting own Commit, `their_commit` is
+    /// applied like with `apply_commit`. If `their_commit` can't be applied,
+    /// the own Commit is kept.
+    pub fn handle_conflicting_commit(
+        &mut self,
+        their_commit: MLSPlaintext,
+        proposals: Vec<(Sender, Proposal)>,
+    ) -> Result<CommitConflict, ApplyCommitError> {
+        if their_commit.epoch != self.group_context.epoch {
+            return Err(ApplyCommitError::EpochMismatch);
+        }
+        let commit = their_commit
+            .as_commit()
+            .ok_or(ApplyCommitError::WrongPlaintextContentType)?;
+        let (discarded_commit, uncommitted_proposals) = match &self.pending_commit {
+            Some(pending_commit) if pending_commit.commit != their_commit => {
+                let covered: Vec<&ProposalID> = commit
+                    .updates()
+                    .iter()
+                    .chain(commit.removes())
+                    .chain(commit.adds())
+                    .chain(commit.psks())
+                    .collect();
+                let uncommitted_proposals = pending_commit
+                    .proposals
+                    .iter()
+                    .filter(|(_, proposal)| {
+                        let proposal_id = ProposalID::from_proposal(&self.ciphersuite, proposal);
+                        !covered.contains(&&proposal_id)
+                    })
+                    .cloned()
+                    .collect();
+                (Some(pending_commit.commit.clone()), uncommitted_proposals)
+            }
+            _ => (None, vec![]),
+        };
+        // Applying the Commit discards the own pending Commit and its key
+        // material
+        let membership_changes = self.apply_commit(their_commit, proposals)?;
+        Ok(CommitConflict {
+            membership_changes,
+            discarded_commit,
+            uncommitted_proposals,
+        })
+    }
+
+    /// Apply the own Commit that was last created with `create_commit` in the
+    /// current epoch, without processing it again like `apply_commit` does.
+    /// The delivery service has to have accepted the Commit before. Fails
+    /// if there is no such Commit, e.g. because another member's Commit was
+    /// applied in the meantime.
+    pub fn merge_pending_commit(&mut self) -> Result<MembershipChanges, ApplyCommitError> {
+        let pending_commit = self
+            .pending_commit
+            .take()
+            .ok_or(ApplyCommitError::NoPendingCommit)?;
+        Ok(merge_staged_commit(self, pending_commit.staged_commit))
+    }
+
+    /// Abandon the own Commits that were created with `create_commit` in the
+    /// current epoch, e.g. because the delivery service rejected them. The
+    /// `KeyPackageBundle`s and path secrets of all of them are deleted, the
+    /// Commit that was created last is returned if there was one.
+    pub fn discard_pending_commit(&mut self) -> Option<MLSPlaintext> {
+        for (key_package_ref, mut pending_commit_state) in self.pending_commit_states.drain() {
+            if let Some(mut kpb) = self.pending_kpbs.remove(&key_package_ref) {
+                kpb.wipe();
+            }
+            for path_secret in pending_commit_state.path_secrets.iter_mut() {
+                path_secret.zeroize();
+            }
+            pending_commit_state.commit_secret.0.zeroize();
+        }
+        let mut pending_commit = self.pending_commit.take()?;
+        pending_commit.staged_commit.epoch_secrets.wipe();
+        pending_commit.staged_commit.tree.wipe_own_secrets();
+        Some(pending_commit.commit)
+    }
+
+    /// Validate a proposal when it is received, before it is queued for a
+    /// later Commit. The proposal has to be from the current epoch and
+    /// correctly signed by a member or external sender, and it has to be
+    /// applicable to the current tree.
+    pub fn process_proposal(
+        &self,
+        mls_plaintext: MLSPlaintext,
+    ) -> Result<QueuedProposal, ProposalError> {
+        self.verify_proposal(mls_plaintext)
+            .map(|(queued_proposal, _)| queued_proposal)
+    }
+
+    /// Like `process_proposal`, also returns the credential of the sender.
+    pub(crate) fn verify_proposal(
+        &self,
+        mls_plaintext: MLSPlaintext,
+    ) -> Result<(QueuedProposal, Credential), ProposalError> {
+        if mls_plaintext.group_id != self.group_context.group_id {
+            return Err(ProposalError::WrongGroup);
+        }
+        if mls_plaintext.epoch != self.group_context.epoch {
+            return Err(ProposalError::WrongEpoch);
+        }
+        let proposal = match &mls_plaintext.content {
+            MLSPlaintextContentType::Proposal(proposal) => proposal.clone(),
+            _ => return Err(ProposalError::NotAProposal),
+        };
+        let credential = self.verify_plaintext(&mls_plaintext)?;
+        let sender = mls_plaintext.sender;
+        let valid_key_package = |key_package: &KeyPackage| {
+            key_package.get_cipher_suite() == &self.ciphersuite
+                && key_package
+                    .validate_with_time(self.time_provider.as_ref())
+                    .is_ok()
+        };
+        match &proposal {
+            Proposal::Add(add_proposal) => {
+                if !valid_key_package(&add_proposal.key_package) {
+                    return Err(ProposalError::InvalidKeyPackage);
+                }
+                let granter = if sender.is_member() {
+                    self.tree.get_leaf_node(sender.as_leaf_index())
+                } else {
+                    None
+                };
+                validate_device_capabilities(granter, &add_proposal.key_package)?;
+            }
+            Proposal::Update(update_proposal) => {
+                // Only members can update their own leaf
+                if !sender.is_member() {
+                    return Err(ProposalError::InvalidSender);
+                }
+                if !valid_key_package(&update_proposal.key_package) {
+                    return Err(ProposalError::InvalidKeyPackage);
+                }
+                validate_device_capabilities(
+                    self.tree.get_leaf_node(sender.as_leaf_index()),
+                    &update_proposal.key_package,
+                )?;
+            }
+            Proposal::Remove(remove_proposal) => {
+                let removed = LeafIndex::from(remove_proposal.removed);
+                let removed_leaf_node = self
+                    .tree
+                    .get_leaf_node(removed)
+                    .ok_or(ProposalError::RemoveTargetNotAMember)?;
+                validate_removable(removed_leaf_node)?;
+            }
+            Proposal::PreSharedKey(psk_proposal) => {
+                if self.get_psk(&psk_proposal.psk).is_none() {
+                    return Err(ProposalError::UnknownPsk);
+                }
+            }
+        }
+        let queued_proposal = QueuedProposal {
+            proposal,
+            sender,
+            own_kpb: None,
+        };
+        Ok((queued_proposal, credential))
+    }
+
+    /// Create an application message with `content`. Application messages
+    /// of deniable groups are not signed.
+    fn new_application_message(
+        &self,
+        aad: &[u8],
+        content: MLSPlaintextContentType,
+        signature_key: &SignaturePrivateKey,
+    ) -> MLSPlaintext {
+        if self.group_context.is_deniable() {
+            return MLSPlaintext::new_unsigned(
+                self.get_sender_index(),
+                aad,
+                content,
+                self.get_context(),
+            );
+        }
+        MLSPlaintext::new(
+            &self.ciphersuite,
+            self.get_sender_index(),
+            aad,
+            content,
+            signature_key,
+            self.get_context(),
+        )
+    }
+
+    /// Encrypt an MLS message like `encrypt` and create a `DeliveryHint` that
+    /// is sent alongside the ciphertext.
+    pub fn encrypt_with_delivery_hint(
+        &mut self,
+        mls_plaintext: MLSPlaintext,
+    ) -> Result<(MLSCiphertext, DeliveryHint), EncryptionError> {
+        let sender = mls_plaintext.sender.sender;
+        let generation = self.astree.get_generation(sender);
+        let mls_ciphertext = self.encrypt(mls_plaintext)?;
+        let delivery_hint = DeliveryHint::new(
+            &self.ciphersuite,
+            &self.delivery_hint_key(),
+            sender,
+            generation,
+            &mls_ciphertext,
+        );
+        Ok((mls_ciphertext, delivery_hint))
+    }
+
+    /// Get the number of messages this member encrypted in the group. The
+    /// counter is stored with the group and keeps increasing across epochs,
+    /// unlike the generation of the sender ratchet that restarts at 0 in
+    /// every epoch.
+    pub fn send_counter(&self) -> u32 {
+        self.send_counter
+    }
+
+    /// Encrypt an MLS message like `encrypt` and return the value of the
+    /// send counter assigned to it. Applications can use it to order their
+    /// own outbound messages, e.g. in a send queue that spans epochs.
+    pub fn encrypt_with_send_counter(
+        &mut self,
+        mls_plaintext: MLSPlaintext,
+    ) -> Result<(MLSCiphertext, u32), EncryptionError> {
+        let mls_ciphertext = self.encrypt(mls_plaintext)?;
+        Ok((mls_ciphertext, self.send_counter))
+    }
+
+    /// Verify that `delivery_hint` was created by a member of the current
+    /// epoch for `mls_ciphertext`.
+    pub fn verify_delivery_hint(
+        &self,
+        delivery_hint: &DeliveryHint,
+        mls_ciphertext: &MLSCiphertext,
+    ) -> Result<(), DeliveryHintError> {
+        if mls_ciphertext.group_id != self.group_context.group_id
+            || mls_ciphertext.epoch != self.group_context.epoch
+            || delivery_hint.get_epoch() != self.group_context.epoch
+        {
+            return Err(DeliveryHintError::WrongEpoch);
+        }
+        if !delivery_hint.verify_tag(&self.ciphersuite, &self.delivery_hint_key(), mls_ciphertext) {
+            return Err(DeliveryHintError::InvalidTag);
+        }
+        Ok(())
+    }
+
+    fn delivery_hint_key(&self) -> Vec<u8> {
+        self.export_secret(DELIVERY_HINT_LABEL, self.ciphersuite.hash_length())
+    }
+
+    /// Find the leaf of the member whose `KeyIDExtension` contains `key_id`.
+    pub fn find_member_by_key_id(&self, key_id: &[u8]) -> Option<LeafIndex> {
+        let leaves = self
+            .tree
+            .nodes()
+            .iter()
+            .step_by(2)
+            .map(|node| node.leaf_node.as_ref());
+        find_leaf_by_key_id(leaves, key_id)
+    }
+
+    /// Find the leaf of the member that was added or updated with
+    /// `key_package`. Returns `None` if the leaf was updated since.
+    pub fn find_member_by_key_package(&self, key_package: &KeyPackage) -> Option<LeafIndex> {
+        self.tree.find_leaf(key_package)
+    }
+
+    pub fn get_tree(&self) -> &RatchetTree {
+        &self.tree
+    }
+
+    /// Export the public state of the group, i.e. the group ID, the epoch,
+    /// the ciphersuite, the credentials of the members and the types of the
+    /// group context extensions. It contains no secrets.
+    pub fn export_public_state(&self) -> PublicGroupState {
+        let members = get_roster(&self.tree)
+            .into_iter()
+            .enumerate()
+            .filter_map(|(index, credential_option)| {
+                let credential = credential_option?;
+                // Leaf indices of the tree always fit into a u32
+                let index = u32::try_from(index).ok()?;
+                let identity = match credential {
+                    Credential::Basic(basic_credential) => basic_credential.identity.clone(),
+                };
+                Some(PublicMemberState {
+                    index,
+                    identity,
+                    credential: credential.encode_detached().unwrap(),
+                })
+            })
+            .collect();
+        PublicGroupState {
+            group_id: self.group_context.group_id.as_slice(),
+            epoch: self.group_context.epoch.0,
+            ciphersuite: self.ciphersuite.get_name(),
+            members,
+            extensions: self
+                .group_context
+                .extensions
+                .iter()
+                .map(|extension| extension.get_type())
+                .collect(),
+        }
+    }
+
+    /// Get the number of members of the group.
+    pub fn member_count(&self) -> usize {
+        self.tree.member_count()
+    }
+
+    /// Get the number of blank leaves that new members can take without
+    /// growing the tree.
+    pub fn free_leaf_count(&self) -> usize {
+        self.tree.free_leaf_count()
+    }
+
+    /// Returns `true` if there is no member at `leaf_index`.
+    pub fn is_leaf_blank(&self, leaf_index: LeafIndex) -> bool {
+        self.tree.is_leaf_blank(leaf_index)
     }
     fn get_sender_index(&self) -> LeafIndex {
-        self.tree.borrow().get_own_index().into()
+        LeafIndex::try_from_node_index(self.tree.get_own_index()).unwrap()
     }
     pub(crate) fn get_ciphersuite(&self) -> &Ciphersuite {
         &self.ciphersuite
     }
 
-    pub(crate) fn get_context(&self) -> &GroupContext {
+    /// Get the `GroupContext` of the current epoch.
+    pub fn get_context(&self) -> &GroupContext {
         &self.group_context
     }
 
+    /// Get the application protocol the group was created for, if the group
+    /// has an `ApplicationProtocolExtension`.
+    pub fn get_application_protocol(&self) -> Option<ApplicationProtocolExtension> {
+        match get_extension_payload(
+            &self.group_context.extensions,
+            ExtensionType::ApplicationProtocol,
+        ) {
+            Some(ExtensionPayload::ApplicationProtocol(e)) => Some(e),
+            _ => None,
+        }
+    }
+
+    /// Get the confirmed transcript hash of the current epoch.
+    /// It covers all `Commit` messages up to and including the one that
+    /// started the current epoch.
+    pub fn get_confirmed_transcript_hash(&self) -> &[u8] {
+        &self.group_context.confirmed_transcript_hash
+    }
+
+    /// Get the interim transcript hash of the current epoch. It is the
+    /// confirmed transcript hash extended by the authentication data of the
+    /// last `Commit`. Proposals are only covered through the `Commit` that
+    /// references them.
+    pub fn get_interim_transcript_hash(&self) -> &[u8] {
+        &self.interim_transcript_hash
+    }
+
+    /// Serialize the group state and encrypt it under a key derived from the
+    /// application's `storage_key`. Own `KeyPackageBundle`s of pending
+    /// Update proposals are stored with the group, so that the Updates can
+    /// still be committed after the group was loaded.
+    pub fn save_encrypted(&self, storage_key: &[u8]) -> Result<Vec<u8>, StorageError> {
+        seal(&self.ciphersuite, self, Some(storage_key))
+    }
+
+    /// Load a group state that was stored with `save_encrypted`. Fails if the
+    /// state was modified, the key is wrong or the format is not supported.
+    pub fn load_encrypted(bytes: &[u8], storage_key: &[u8]) -> Result<MlsGroup, StorageError> {
+        open(bytes, Some(storage_key))
+    }
+
+    /// Create a `MembershipProof` for the member at `leaf_index` in the
+    /// current epoch, signed with the own `signature_key`. Returns `None` if
+    /// there is no member at `leaf_index`.
+    pub fn export_membership_proof(
+        &self,
+        leaf_index: LeafIndex,
+        signature_key: &SignaturePrivateKey,
+    ) -> Option<MembershipProof> {
+        let (leaf_node, path) = self.tree.membership_path(leaf_index)?;
+        let own_credential = self
+            .tree
+            .get_leaf_node(self.get_sender_index())?
+            .get_credential()
+            .clone();
+        Some(MembershipProof::new(
+            self.ciphersuite,
+            &self.group_context,
+            self.tree.leaf_count(),
+            leaf_index,
+            leaf_node,
+            path,
+            own_credential,
+            signature_key,
+        ))
+    }
+
+    /// Sign an application payload that is sent outside of the group, e.g. a
+    /// call offer, with the own signature key. The signature is bound to the
+    /// group and can't be confused with signatures on MLS structures.
+    pub fn sign_payload(&self, payload: &[u8], signature_key: &SignaturePrivateKey) -> Signature {
+        self.ciphersuite
+            .sign_with_label(
+                signature_key,
+                PAYLOAD_SIGNATURE_LABEL,
+                &self.payload_signature_content(payload),
+            )
+            .unwrap()
+    }
+
+    /// Verify that the member at `leaf_index` signed `payload` with
+    /// `sign_payload`. Returns the credential of the member. The member has
+    /// to use the same signature key as when it signed, payloads signed
+    /// before an Update of the member can't be verified anymore.
+    pub fn verify_member_payload(
+        &self,
+        leaf_index: LeafIndex,
+        payload: &[u8],
+        signature: &Signature,
+    ) -> Result<&Credential, PayloadSignatureError> {
+        let credential = self
+            .tree
+            .get_leaf_node(leaf_index)
+            .map(|leaf_node| leaf_node.get_credential())
+            .ok_or(PayloadSignatureError::UnknownMember)?;
+        if !credential.verify_with_label(
+            PAYLOAD_SIGNATURE_LABEL,
+            &self.payload_signature_content(payload),
+            signature,
+        ) {
+            return Err(PayloadSignatureError::InvalidSignature);
+        }
+        Ok(credential)
+    }
+
+    fn payload_signature_content(&self, payload: &[u8]) -> Vec<u8> {
+        let mut content = self.group_context.group_id.encode_detached().unwrap();
+        content.extend_from_slice(payload);
+        content
+    }
+
+    /// Get the `CorrelationId` of the Commit that started the current epoch.
+    /// A member that joined through a `Welcome` gets the same one as the
+    /// `CommitBundle` of the Commit that created it.
+    pub fn correlation_id(&self) -> CorrelationId {
+        CorrelationId::new(&self.group_context.confirmed_transcript_hash)
+    }
+
+    /// Get the `GroupFingerprint` of the current epoch.
+    pub fn get_fingerprint(&self) -> GroupFingerprint {
+        GroupFingerprint::new(&self.ciphersuite, &self.group_context)
+    }
+
+    /// Get the authentication code between the own leaf and the member at
+    /// `leaf_index`. Both members compute the same code from the
+    /// authentication secret of the current epoch and their credentials, so
+    /// users can compare it out of band. The code consists of 30 digits in
+    /// groups of five. Returns `None` if there is no member at `leaf_index`
+    /// or if `leaf_index` is the own leaf.
+    pub fn authentication_code(&self, leaf_index: LeafIndex) -> Option<String> {
+        let own_index = self.get_sender_index();
+        if leaf_index == own_index {
+            return None;
+        }
+        let credential = |index: LeafIndex| {
+            self.tree
+                .get_leaf_node(index)
+                .map(|leaf_node| leaf_node.get_credential().encode_detached().unwrap())
+        };
+        let other_credential = credential(leaf_index)?;
+        let own_credential = credential(own_index)?;
+
+        // Both members have to use the same order
+        let context = if own_index.as_u32() < leaf_index.as_u32() {
+            [own_credential, other_credential].concat()
+        } else {
+            [other_credential, own_credential].concat()
+        };
+        let code = hkdf_expand_label(
+            &self.ciphersuite,
+            &self.epoch_secrets.authentication_secret,
+            "authentication code",
+            &context,
+            30,
+        );
+        let groups: Vec<String> = code
+            .chunks(5)
+            .map(|chunk| {
+                let value = chunk
+                    .iter()
+                    .fold(0u64, |acc, byte| (acc << 8) | *byte as u64);
+                format!("{:05}", value % 100_000)
+            })
+            .collect();
+        Some(groups.join(" "))
+    }
+
+    /// Decrypt several messages at once, e.g. when catching up after being
+    /// offline. The sender data of all messages is decrypted first, so the
+    /// secrets can be derived once in the order of generations. The contents
+    /// are then decrypted and verified in parallel. The sender ratchets are
+    /// only advanced for the messages that were verified. The results are
+    /// returned in the order of `mls_ciphertexts`.
+    pub fn decrypt_batch(
+        &mut self,
+        mls_ciphertexts: Vec<MLSCiphertext>,
+    ) -> Vec<Result<ProcessedMessage, DecryptionError>> {
+        trace_span!("decrypt_batch", messages = mls_ciphertexts.len());
+        let count = mls_ciphertexts.len();
+        let mut results: Vec<Option<Result<ProcessedMessage, DecryptionError>>> =
+            (0..count).map(|_| None).collect();
+        let roster = get_roster(&self.tree);
+
+        // Decrypt the sender data and order the messages by sender and
+        // generation
+        let mut pending = vec![];
+        for (i, mls_ciphertext) in mls_ciphertexts.into_iter().enumerate() {
+            match mls_ciphertext.decrypt_sender_data(
+                &self.ciphersuite,
+                &self.epoch_secrets,
+                &self.group_context,
+            ) {
+                Ok(sender_data) => pending.push((i, mls_ciphertext, sender_data)),
+                Err(error) => results[i] = Some(Err(error)),
+            }
+        }
+        pending.sort_by_key(|(_, _, sender_data)| {
+            (sender_data.sender.as_u32(), sender_data.generation)
+        });
+
+        // Derive the secrets on a copy of the tree, the sender ratchets are
+        // only advanced for verified messages
+        let mut astree = self.astree.clone();
+        let mut prepared = vec![];
+        for (i, mls_ciphertext, sender_data) in pending {
+            let credential = match roster
+                .get(sender_data.sender.as_usize())
+                .and_then(|credential| *credential)
+            {
+                Some(credential) => credential,
+                None => {
+                    results[i] = Some(Err(DecryptionError::UnknownSender));
+                    continue;
+                }
+            };
+            match astree.get_secret(
+                &self.ciphersuite,
+                sender_data.sender,
+                sender_data.generation,
+            ) {
+                Ok(application_secrets) => prepared.push((
+                    i,
+                    mls_ciphertext,
+                    sender_data,
+                    credential,
+                    application_secrets,
+                )),
+                Err(error) => results[i] = Some(Err(error.into())),
+            }
+        }
+
+        // Decrypt the contents in parallel
+        let timestamp = self.time_provider.now();
+        let decrypted: Vec<(
+            usize,
+            MLSSenderData,
+            Result<ProcessedMessage, DecryptionError>,
+        )> = prepared
+            .par_iter()
+            .map(
+                |(i, mls_ciphertext, sender_data, credential, application_secrets)| {
+                    let result = mls_ciphertext
+                        .decrypt_content(
+                            &self.ciphersuite,
+                            credential,
+                            &self.epoch_secrets,
+                            sender_data,
+                            application_secrets,
+                            &self.group_context,
+                        )
+                        .map(|mls_plaintext| {
+                            ProcessedMessage::new(
+                                mls_plaintext,
+                                sender_data.sender,
+                                (*credential).clone(),
+                                timestamp,
+                            )
+                        });
+                    (*i, sender_data.clone(), result)
+                },
+            )
+            .collect();
+        // The messages of every sender are checked in the order of their
+        // generation
+        for (i, sender_data, mut result) in decrypted {
+            if result.is_ok() {
+                if let Err(error) =
+                    sender_data.advance_sender_ratchet(&self.ciphersuite, &mut self.astree)
+                {
+                    result = Err(error);
+                }
+            }
+            if let Ok(processed_message) = &mut result {
+                self.epoch_message_count = self.epoch_message_count.saturating_add(1);
+                if let Err(error) = self.check_sequence_number(processed_message) {
+                    result = Err(error);
+                } else {
+                    self.strip_commit_token(processed_message);
+                }
+            }
+            results[i] = Some(result);
+        }
+
+        results
+            .into_iter()
+            .map(|result| {
+                let result = result.unwrap();
+                if let Err(error) = &result {
+                    record_decryption_failure(error);
+                }
+                result
+            })
+            .collect()
+    }
+
+    /// Skip the messages of the member at `sender` before `generation`, e.g.
+    /// when only the latest messages are needed after being offline. The
+    /// secrets of the skipped generations are discarded, so these messages
+    /// can't be decrypted anymore. `generation` can be at most 1000
+    /// generations ahead of the last one that was received.
+    pub fn skip_to_generation(
+        &mut self,
+        sender: LeafIndex,
+        generation: u32,
+    ) -> Result<(), DecryptionError> {
+        Ok(self
+            .astree
+            .advance_to(&self.ciphersuite, sender, generation)?)
+    }
+
+    /// Keep at most `limit` sender ratchets to bound the memory used by large
+    /// groups. The least recently used ratchets are evicted and re-derived
+    /// when their sender reappears. Messages of an evicted sender that were
+    /// skipped before the eviction can't be decrypted anymore. `None`
+    /// removes the limit and re-derives the evicted ratchets.
+    ///
+    /// While a limit is set, the sender ratchets keep the application secret
+    /// of the epoch to re-derive evicted ratchets. Anyone who obtains it can
+    /// derive the keys of all senders of the epoch, so a limit trades forward
+    /// secrecy within the epoch for memory.
+    pub fn set_sender_ratchet_limit(
+        &mut self,
+        limit: Option<usize>,
+    ) -> Result<(), DecryptionError> {
+        Ok(self.astree.set_max_sender_ratchets(
+            &self.ciphersuite,
+            limit,
+            &self.epoch_secrets.application_secret,
+        )?)
+    }
+
+    /// Set the limits for Commits this member creates and accepts. The policy
+    /// is not stored with the group and has to be set again after loading it.
+    pub fn set_commit_policy(&mut self, commit_policy: CommitPolicy) {
+        self.commit_policy = commit_policy;
+    }
+
+    /// Get the limits for Commits.
+    pub fn get_commit_policy(&self) -> &CommitPolicy {
+        &self.commit_policy
+    }
+
+    /// Take the current time and the randomness of Commits from the
+    /// providers of `config`, e.g. after the group was loaded. The start of
+    /// the current epoch is not changed.
+    pub fn set_config(&mut self, config: &GroupConfig) {
+        self.time_provider = config.time_provider.clone();
+        self.rng_provider = config.rng_provider.clone();
+    }
+
+    /// Set the limits for the lifetime of an epoch. The policy is not stored
+    /// with the group and has to be set again after loading it.
+    pub fn set_epoch_policy(&mut self, epoch_policy: EpochPolicy) {
+        self.epoch_policy = epoch_policy;
+    }
+
+    /// Get the limits for the lifetime of an epoch.
+    pub fn get_epoch_policy(&self) -> &EpochPolicy {
+        &self.epoch_policy
+    }
+
+    /// Get the limits of the `EpochPolicy` the current epoch exceeds at
+    /// `now`, in seconds since the Unix epoch. If any are returned, the epoch
+    /// should be rotated with `enforce_epoch_policy`.
+    pub fn policy_violations(&self, now: u64) -> Vec<PolicyViolation> {
+        let mut violations = vec![];
+        let age = now.saturating_sub(self.epoch_started_at);
+        if let Some(max_age) = self.epoch_policy.max_epoch_age {
+            if age > max_age {
+                violations.push(PolicyViolation::EpochTooOld { age, max_age });
+            }
+        }
+        if let Some(max_count) = self.epoch_policy.max_epoch_messages {
+            if self.epoch_message_count > max_count {
+                violations.push(PolicyViolation::TooManyMessages {
+                    count: self.epoch_message_count,
+                    max_count,
+                });
+            }
+        }
+        violations
+    }
+
+    /// Create a self-update Commit with `key_package_bundle` if the current
+    /// epoch violates the `EpochPolicy` at `now`. Returns `None` if it
+    /// doesn't. The epoch only rotates once the Commit is applied.
+    pub fn enforce_epoch_policy(
+        &mut self,
+        now: u64,
+        aad: &[u8],
+        signature_key: &SignaturePrivateKey,
+        key_package_bundle: KeyPackageBundle,
+    ) -> Option<CreateCommitResult> {
+        if self.policy_violations(now).is_empty() {
+            return None;
+        }
+        Some(self.update_self(aad, signature_key, key_package_bundle))
+    }
+
+    /// Allow application messages to be sent as `MLSPlaintext` instead of
+    /// `MLSCiphertext`. They are rejected by default since application data
+    /// must always be encrypted. The setting is not stored with the group.
+    pub fn set_allow_plaintext_application_messages(&mut self, allow: bool) {
+        self.allow_plaintext_application_messages = allow;
+    }
+
+    /// Set the `PskStore` that is consulted for PSK proposals. The store is
+    /// not stored with the group and has to be set again after loading it.
+    pub fn set_psk_store(&mut self, psk_store: Box<dyn PskStore>) {
+        self.psk_store = Some(psk_store);
+    }
+
+    /// Set the `CommitSequencer` that provides the ordering tokens of own
+    /// Commits. Once it is set, Commits without a token or with a token that
+    /// isn't greater than the one of the last applied Commit are rejected.
+    /// The sequencer is not stored with the group and has to be set again
+    /// after loading it.
+    pub fn set_commit_sequencer(&mut self, commit_sequencer: Box<dyn CommitSequencer>) {
+        self.commit_sequencer = Some(commit_sequencer);
+    }
+
+    // Remove the ordering token from the authenticated data of a decrypted
+    // Commit. Commits without a token are rejected when they are applied.
+    fn strip_commit_token(&self, processed_message: &mut ProcessedMessage) {
+        if self.commit_sequencer.is_some()
+            && matches!(
+                processed_message.plaintext().content,
+                MLSPlaintextContentType::Commit(_)
+            )
+        {
+            processed_message.strip_aad_prefix();
+        }
+    }
+
+    /// Get the ordering token of the last Commit that was applied, if it had
+    /// one.
+    pub fn get_last_commit_token(&self) -> Option<u64> {
+        self.last_commit_token
+    }
+
+    /// Get the resumption secret of `epoch` if it is still kept. The group
+    /// keeps the resumption secrets of the current and the last
+    /// `RESUMPTION_PSK_EPOCHS` epochs.
+    pub fn get_resumption_secret(&self, epoch: GroupEpoch) -> Option<&[u8]> {
+        self.resumption_secrets
+            .iter()
+            .find(|(e, _)| *e == epoch)
+            .map(|(_, resumption_secret)| &resumption_secret[..])
+    }
+
+    /// Resolve a PSK from the group's own resumption secrets or else from
+    /// the `PskStore`.
+    pub(crate) fn get_psk(&self, psk_id: &PreSharedKeyID) -> Option<Vec<u8>> {
+        let own_resumption_secret = self.resumption_secrets.iter().find(|(epoch, _)| {
+            PreSharedKeyID::resumption(&self.group_context.group_id, *epoch) == *psk_id
+        });
+        if let Some((_, resumption_secret)) = own_resumption_secret {
+            return Some(resumption_secret.clone());
+        }
+        self.psk_store.as_ref()?.get_psk(psk_id)
+    }
+
+    /// Resolve the PSKs of the PSK proposals `psk_ids` refers to and append
+    /// them to `psks`. Returns `None` if a PSK or one of the proposals is
+    /// unknown.
+    pub(crate) fn resolve_psks(
+        &self,
+        psks: &[PreSharedKey],
+        psk_ids: &[ProposalID],
+        proposal_queue: &ProposalQueue,
+    ) -> Option<Vec<PreSharedKey>> {
+        let mut resolved_psks = psks.to_vec();
+        for psk_proposal_id in psk_ids {
+            let (_, queued_proposal) = proposal_queue.get(psk_proposal_id)?;
+            let psk_id = queued_proposal.proposal.as_psk()?.psk;
+            let psk = self.get_psk(&psk_id)?;
+            resolved_psks.push((psk_id, psk));
+        }
+        Some(resolved_psks)
+    }
+
+    /// Hand the exporter secret of the current and all future epochs to
+    /// `epoch_escrow`. The escrow is not stored with the group and has to be
+    /// set again after loading it.
+    ///
+    /// **Dangerous:** see `group::escrow`.
+    #[cfg(feature = "dangerous-epoch-escrow")]
+    pub fn set_epoch_escrow(&mut self, epoch_escrow: Box<dyn escrow::EpochEscrow>) {
+        self.epoch_escrow = Some(epoch_escrow);
+        self.escrow_epoch();
+    }
+
+    /// Hand the exporter secret of the current epoch to the `EpochEscrow`, if
+    /// there is one.
+    #[cfg(feature = "dangerous-epoch-escrow")]
+    pub(crate) fn escrow_epoch(&self) {
+        if let Some(epoch_escrow) = &self.epoch_escrow {
+            epoch_escrow.escrow_epoch(&escrow::EscrowedEpoch {
+                group_id: &self.group_context.group_id,
+                epoch: self.group_context.epoch,
+                tree_hash: &self.group_context.tree_hash,
+                confirmed_transcript_hash: &self.group_context.confirmed_transcript_hash,
+                exporter_secret: &self.epoch_secrets.exporter_secret,
+            });
+        }
+    }
+
+    /// Keep the resumption secret of the current epoch and forget the ones
+    /// that are older than `RESUMPTION_PSK_EPOCHS` epochs.
+    pub(crate) fn record_resumption_secret(&mut self) {
+        self.resumption_secrets.push((
+            self.group_context.epoch,
+            self.epoch_secrets.resumption_secret.clone(),
+        ));
+        if self.resumption_secrets.len() > RESUMPTION_PSK_EPOCHS + 1 {
+            self.resumption_secrets.remove(0);
+        }
+    }
+
+    /// Get the number of sender ratchets that are currently kept.
+    pub fn sender_ratchet_count(&self) -> usize {
+        self.astree.sender_ratchet_count()
+    }
+
+    /// Get the position of the sender ratchets of the current epoch, e.g. to
+    /// diagnose messages that can't be decrypted by comparing the sender's and
+    /// the receiver's state. Senders that are not listed didn't send or
+    /// receive a message in this epoch. No secrets are exposed.
+    pub fn ratchet_state(&self) -> Vec<SenderRatchetState> {
+        self.astree.ratchet_state()
+    }
+
+    /// Collect the public state of the group, the positions of the sender
+    /// ratchets and a summary of the epoch for a bug report. Secrets are
+    /// redacted.
+    pub fn diagnostics(&self) -> GroupDiagnostics {
+        GroupDiagnostics {
+            ciphersuite: self.ciphersuite.get_name(),
+            epoch: EpochSummary {
+                group_id: self.group_context.group_id.value.clone(),
+                epoch: self.group_context.epoch.0,
+                tree_hash: self.group_context.tree_hash.clone(),
+                confirmed_transcript_hash: self.group_context.confirmed_transcript_hash.clone(),
+                started_at: self.epoch_started_at,
+                message_count: self.epoch_message_count,
+                member_count: self.member_count(),
+            },
+            tree: TreeSummary::new(&self.tree),
+            own_leaf: self.get_sender_index().as_u32(),
+            ratchets: self.ratchet_state(),
+            send_counter: self.send_counter,
+            pending_key_package_bundles: self.pending_key_package_bundles(),
+            observer: self.observer,
+        }
+    }
+
     pub(crate) fn get_epoch_secrets(&self) -> &EpochSecrets {
         &self.epoch_secrets
     }
+
+    /// Get the number of own `KeyPackageBundle`s from Update proposals and
+    /// Commits of the current epoch that were not applied yet.
+    pub fn pending_key_package_bundles(&self) -> usize {
+        self.pending_kpbs.len()
+    }
+
+    /// Create and sign an `MLSPlaintext` that contains `proposal`.
+    fn create_proposal(
+        &self,
+        aad: &[u8],
+        signature_key: &SignaturePrivateKey,
+        proposal: Proposal,
+    ) -> Result<(MLSPlaintext, Proposal), CreateProposalError> {
+        if self.observer {
+            return Err(CreateProposalError::ObserverGroup);
+        }
+        let content = MLSPlaintextContentType::Proposal(proposal.clone());
+        let mls_plaintext = MLSPlaintext::new(
+            &self.ciphersuite,
+            self.get_sender_index(),
+            aad,
+            content,
+            signature_key,
+            &self.get_context(),
+        );
+        Ok((mls_plaintext, proposal))
+    }
+
+    /// Track an own `KeyPackageBundle` until the Commit it is used in is
+    /// applied.
+    fn add_pending_kpb(&mut self, key_package_bundle: KeyPackageBundle) {
+        let key_package_ref = key_package_bundle.get_key_package().key_package_ref();
+        self.pending_kpbs
+            .insert(key_package_ref, key_package_bundle);
+    }
+
+    /// Track the `KeyPackageBundle` and the path secrets of an own Commit
+    /// until the Commit is applied.
+    fn add_pending_commit_kpb(
+        &mut self,
+        key_package_bundle: KeyPackageBundle,
+        pending_commit_state: PendingCommitState,
+    ) {
+        let key_package_ref = key_package_bundle.get_key_package().key_package_ref();
+        self.pending_commit_states
+            .insert(key_package_ref.clone(), pending_commit_state);
+        self.pending_kpbs
+            .insert(key_package_ref, key_package_bundle);
+    }
+
+    /// Get the pending `KeyPackageBundle` and path secrets of the own Commit
+    /// with the leaf `key_package`.
+    fn get_pending_commit_kpb(
+        &self,
+        key_package: &KeyPackage,
+    ) -> Option<(KeyPackageBundle, PendingCommitState)> {
+        let key_package_ref = key_package.key_package_ref();
+        let kpb = self.pending_kpbs.get(&key_package_ref).cloned()?;
+        let pending_commit_state = self.pending_commit_states.get(&key_package_ref).cloned()?;
+        Some((kpb, pending_commit_state))
+    }
 }
 
 // Helper functions
 
+/// Get the credentials of all leaves of `tree`, indexed by leaf index.
+fn get_roster(tree: &RatchetTree) -> Vec<Option<&Credential>> {
+    let mut roster = Vec::new();
+    for i in 0..tree.leaf_count() {
+        let credential_option = tree
+            .get_leaf_node(LeafIndex::from(i))
+            .map(|leaf_node| leaf_node.get_credential());
+        roster.push(credential_option);
+    }
+    roster
+}
+
 fn update_confirmed_transcript_hash(
     ciphersuite: &Ciphersuite,
     mls_plaintext_commit_content: &MLSPlaintextCommitContent,
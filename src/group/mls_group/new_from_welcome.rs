@@ -16,96 +16,379 @@
 
 use crate::ciphersuite::{signable::*, *};
 use crate::codec::*;
+use crate::framing::*;
 use crate::group::{mls_group::*, *};
 use crate::key_packages::*;
 use crate::messages::*;
 use crate::schedule::*;
 use crate::tree::{astree::*, index::*, node::*, treemath, *};
 
-pub fn new_from_welcome(
+use std::convert::TryFrom;
+
+/// The ratchet tree of a provisional join. It is kept until it was validated
+/// with `MlsGroup::finish_validation`.
+#[derive(Clone)]
+pub(crate) struct ProvisionalTree {
+    nodes: Vec<Option<Node>>,
+    tree_hash: Vec<u8>,
+}
+
+impl ProvisionalTree {
+    /// Check the tree hash and the parent hashes of the tree
+    pub(crate) fn validate(&self, ciphersuite: &Ciphersuite) -> Result<(), WelcomeError> {
+        if public_tree_hash(ciphersuite, &self.nodes).as_ref() != Some(&self.tree_hash) {
+            return Err(WelcomeError::TreeHashMismatch);
+        }
+        if !RatchetTree::verify_integrity(ciphersuite, &self.nodes) {
+            return Err(WelcomeError::InvalidRatchetTree);
+        }
+        Ok(())
+    }
+}
+
+/// The stages of joining a group with a `WelcomeProcessor`, in the order they
+/// are processed
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum WelcomeStage {
+    /// Decrypt the group secrets and the GroupInfo
+    DecryptGroupInfo,
+    /// Build the ratchet tree from its nodes
+    BuildTree,
+    /// Check the tree hash of the ratchet tree
+    VerifyTreeHash,
+    /// Check the parent hashes of the ratchet tree
+    VerifyParentHashes,
+    /// Verify the GroupInfo and compute the state of the group
+    ComputeGroupState,
+    /// The group was joined or joining it failed
+    Done,
+}
+
+/// The number of nodes `WelcomeProcessor::step` hashes or checks by default
+const DEFAULT_NODES_PER_STEP: usize = 256;
+
+/// Joins a group from a `Welcome` a bit at a time, so that joining a large
+/// group doesn't block the caller for long. Every call to `step` processes one
+/// `WelcomeStage`, except for the checks of the ratchet tree, which process a
+/// bounded number of nodes per call. Dropping the processor or calling
+/// `cancel` cancels the join.
+pub struct WelcomeProcessor {
+    stage: WelcomeStage,
     welcome: Welcome,
     nodes_option: Option<Vec<Option<Node>>>,
-    key_package_bundle: KeyPackageBundle,
-) -> Result<MlsGroup, WelcomeError> {
-    let ciphersuite = welcome.cipher_suite;
-    let (private_key, key_package) = (
-        key_package_bundle.private_key,
-        key_package_bundle.key_package,
-    );
+    key_package_bundle: Option<KeyPackageBundle>,
+    psks: Vec<PreSharedKey>,
+    defer_validation: bool,
+    nodes_per_step: usize,
+    node_count: usize,
+    group_info: Option<GroupInfo>,
+    group_secrets: Option<GroupSecrets>,
+    tree: Option<RatchetTree>,
+    tree_verifier: Option<TreeVerifier>,
+    config: GroupConfig,
+}
 
-    // Find key_package in welcome secrets
-    let egs =
-        if let Some(egs) = find_key_package_from_welcome_secrets(&key_package, &welcome.secrets) {
-            egs
+impl WelcomeProcessor {
+    /// Start joining a group from `welcome` with the ratchet tree
+    /// `nodes_option` and the PSKs `psks`.
+    pub fn new(
+        welcome: Welcome,
+        nodes_option: Option<Vec<Option<Node>>>,
+        key_package_bundle: KeyPackageBundle,
+        psks: &[PreSharedKey],
+    ) -> Self {
+        let node_count = nodes_option.as_ref().map_or(0, |nodes| nodes.len());
+        WelcomeProcessor {
+            stage: WelcomeStage::DecryptGroupInfo,
+            welcome,
+            nodes_option,
+            key_package_bundle: Some(key_package_bundle),
+            psks: psks.to_vec(),
+            defer_validation: false,
+            nodes_per_step: DEFAULT_NODES_PER_STEP,
+            node_count,
+            group_info: None,
+            group_secrets: None,
+            tree: None,
+            tree_verifier: None,
+            config: GroupConfig::default(),
+        }
+    }
+
+    /// Skip the checks of the tree hash and the parent hashes of the ratchet
+    /// tree. The group is provisional until `MlsGroup::finish_validation` was
+    /// called. Has no effect once the ratchet tree was built.
+    pub fn set_defer_validation(&mut self, defer_validation: bool) {
+        if let WelcomeStage::DecryptGroupInfo | WelcomeStage::BuildTree = self.stage {
+            self.defer_validation = defer_validation;
+        }
+    }
+
+    /// Take the current time and the randomness of Commits of the joined
+    /// group from the providers of `config`.
+    pub fn set_config(&mut self, config: &GroupConfig) {
+        self.config = config.clone();
+    }
+
+    /// Set the number of nodes of the ratchet tree that a call to `step`
+    /// hashes or checks. Defaults to 256.
+    pub fn set_nodes_per_step(&mut self, nodes_per_step: usize) {
+        self.nodes_per_step = nodes_per_step.max(1);
+    }
+
+    /// Cancel the join. The secrets of the processor are dropped and `step`
+    /// fails afterwards.
+    pub fn cancel(&mut self) {
+        self.stage = WelcomeStage::Done;
+        self.key_package_bundle = None;
+        self.group_secrets = None;
+        self.tree = None;
+        self.tree_verifier = None;
+    }
+
+    /// Get the stage the next call to `step` processes.
+    pub fn stage(&self) -> WelcomeStage {
+        self.stage
+    }
+
+    /// Get the amount of work that was done and the total amount of work.
+    /// Every stage is one unit of work, except for the checks of the ratchet
+    /// tree, which are one unit per node and check.
+    pub fn progress(&self) -> (usize, usize) {
+        let validation_work = if self.defer_validation {
+            0
         } else {
-            return Err(WelcomeError::JoinerSecretNotFound);
+            2 * self.node_count
+        };
+        let total = 3 + validation_work;
+        let (hashed, verified) = match &self.tree_verifier {
+            Some(tree_verifier) => (tree_verifier.hashed(), tree_verifier.verified()),
+            None => (0, 0),
         };
-    if &ciphersuite != key_package.get_cipher_suite() {
-        return Err(WelcomeError::CiphersuiteMismatch);
+        let completed = match self.stage {
+            WelcomeStage::DecryptGroupInfo => 0,
+            WelcomeStage::BuildTree => 1,
+            WelcomeStage::VerifyTreeHash => 2 + hashed,
+            WelcomeStage::VerifyParentHashes => 2 + self.node_count + verified,
+            WelcomeStage::ComputeGroupState => 2 + validation_work,
+            WelcomeStage::Done => total,
+        };
+        (completed, total)
     }
 
-    // Compute keys to decrypt GroupInfo
-    let (group_info, group_secrets) = decrypt_group_info(
-        &ciphersuite,
-        &egs,
-        &private_key,
-        &welcome.encrypted_group_info,
-    )?;
-
-    // Build the ratchet tree
-    // TODO: check the extensions to see if the tree is in there
-    let nodes = if let Some(nodes) = nodes_option {
-        nodes
-    } else {
-        return Err(WelcomeError::MissingRatchetTree);
-    };
+    /// Process the next stage or the next nodes of the ratchet tree. Returns
+    /// the group once the last stage was processed. After an error or once
+    /// the group was returned, the processor is done and can't be used
+    /// anymore.
+    pub fn step(&mut self) -> Result<Option<MlsGroup>, WelcomeError> {
+        let result = self.process_stage();
+        if result.is_err() {
+            self.stage = WelcomeStage::Done;
+        }
+        result
+    }
 
-    let mut tree = if let Some(tree) = RatchetTree::new_from_nodes(
-        ciphersuite,
-        KeyPackageBundle::from_values(key_package, private_key),
-        &nodes,
-    ) {
-        tree
-    } else {
-        return Err(WelcomeError::JoinerNotInTree);
-    };
+    fn process_stage(&mut self) -> Result<Option<MlsGroup>, WelcomeError> {
+        let ciphersuite = self.welcome.cipher_suite;
+        match self.stage {
+            WelcomeStage::DecryptGroupInfo => {
+                trace_span!("new_from_welcome");
+                let key_package_bundle = self.key_package_bundle.as_ref().unwrap();
+                let key_package = key_package_bundle.get_key_package();
+
+                // Find key_package in welcome secrets
+                let egs = if let Some(egs) =
+                    find_key_package_from_welcome_secrets(key_package, &self.welcome.secrets)
+                {
+                    egs
+                } else {
+                    return Err(WelcomeError::JoinerSecretNotFound);
+                };
+                if &ciphersuite != key_package.get_cipher_suite() {
+                    return Err(WelcomeError::CiphersuiteMismatch);
+                }
 
-    // Verify tree hash
-    if tree.compute_tree_hash() != &group_info.tree_hash[..] {
-        return Err(WelcomeError::TreeHashMismatch);
+                // Compute keys to decrypt GroupInfo
+                let (group_info, group_secrets) = decrypt_group_info(
+                    &ciphersuite,
+                    &egs,
+                    key_package_bundle.get_private_key(),
+                    &self.welcome.encrypted_group_info,
+                )?;
+
+                // Verify that the group uses the same application protocol as
+                // the joiner
+                let application_protocol = |payload| match payload {
+                    Some(ExtensionPayload::ApplicationProtocol(e)) => Some(e),
+                    _ => None,
+                };
+                if application_protocol(get_extension_payload(
+                    &group_info.extensions,
+                    ExtensionType::ApplicationProtocol,
+                )) != application_protocol(
+                    key_package.get_extension(ExtensionType::ApplicationProtocol),
+                ) {
+                    return Err(WelcomeError::ApplicationProtocolMismatch);
+                }
+                self.group_info = Some(group_info);
+                self.group_secrets = Some(group_secrets);
+                self.stage = WelcomeStage::BuildTree;
+                Ok(None)
+            }
+            WelcomeStage::BuildTree => {
+                // Build the ratchet tree
+                // TODO: check the extensions to see if the tree is in there
+                let nodes = match &self.nodes_option {
+                    Some(nodes) => nodes,
+                    None => return Err(WelcomeError::MissingRatchetTree),
+                };
+                let tree = RatchetTree::new_from_nodes(
+                    ciphersuite,
+                    self.key_package_bundle.take().unwrap(),
+                    nodes,
+                )
+                .ok_or(WelcomeError::JoinerNotInTree)?;
+                self.tree = Some(tree);
+                self.stage = if self.defer_validation {
+                    WelcomeStage::ComputeGroupState
+                } else {
+                    WelcomeStage::VerifyTreeHash
+                };
+                Ok(None)
+            }
+            WelcomeStage::VerifyTreeHash => {
+                if self.tree_verifier.is_none() {
+                    let nodes = self.nodes_option.as_ref().unwrap();
+                    self.tree_verifier = Some(
+                        TreeVerifier::new(ciphersuite, nodes)
+                            .ok_or(WelcomeError::InvalidRatchetTree)?,
+                    );
+                }
+                let tree_verifier = self.tree_verifier.as_mut().unwrap();
+                if let Some(tree_hash) = tree_verifier.hash_nodes(self.nodes_per_step) {
+                    if tree_hash != self.group_info.as_ref().unwrap().tree_hash {
+                        return Err(WelcomeError::TreeHashMismatch);
+                    }
+                    self.stage = WelcomeStage::VerifyParentHashes;
+                }
+                Ok(None)
+            }
+            WelcomeStage::VerifyParentHashes => {
+                let tree_verifier = self.tree_verifier.as_mut().unwrap();
+                match tree_verifier.verify_nodes(self.nodes_per_step) {
+                    Some(true) => self.stage = WelcomeStage::ComputeGroupState,
+                    Some(false) => return Err(WelcomeError::InvalidRatchetTree),
+                    None => {}
+                }
+                Ok(None)
+            }
+            WelcomeStage::ComputeGroupState => {
+                self.stage = WelcomeStage::Done;
+                self.tree_verifier = None;
+                let group = compute_group_state(
+                    ciphersuite,
+                    self.group_info.take().unwrap(),
+                    self.group_secrets.take().unwrap(),
+                    self.tree.take().unwrap(),
+                    self.nodes_option.take().unwrap(),
+                    &self.psks,
+                    self.defer_validation,
+                    &self.config,
+                )?;
+                Ok(Some(group))
+            }
+            WelcomeStage::Done => Err(WelcomeError::WelcomeProcessed),
+        }
     }
+}
 
-    // Verify GroupInfo signature
-    let signer_node = tree.nodes[NodeIndex::from(group_info.signer_index).as_usize()].clone();
-    let signer_key_package = signer_node.key_package.unwrap();
-    let payload = group_info.unsigned_payload().unwrap();
-    if !signer_key_package
-        .get_credential()
-        .verify(&payload, &group_info.signature)
-    {
+/// Join a group from a `Welcome`. If `defer_validation` is set, the tree hash
+/// and the parent hashes of the ratchet tree are not checked and the group is
+/// provisional until `MlsGroup::finish_validation` was called.
+pub fn new_from_welcome(
+    welcome: Welcome,
+    nodes_option: Option<Vec<Option<Node>>>,
+    key_package_bundle: KeyPackageBundle,
+    psks: &[PreSharedKey],
+    defer_validation: bool,
+) -> Result<MlsGroup, WelcomeError> {
+    let mut welcome_processor =
+        WelcomeProcessor::new(welcome, nodes_option, key_package_bundle, psks);
+    welcome_processor.set_defer_validation(defer_validation);
+    loop {
+        if let Some(group) = welcome_processor.step()? {
+            return Ok(group);
+        }
+    }
+}
+
+/// Verify the GroupInfo against the built `tree` and compute the state of the
+/// joined group.
+#[allow(clippy::too_many_arguments)]
+fn compute_group_state(
+    ciphersuite: Ciphersuite,
+    group_info: GroupInfo,
+    group_secrets: GroupSecrets,
+    mut tree: RatchetTree,
+    nodes: Vec<Option<Node>>,
+    psks: &[PreSharedKey],
+    defer_validation: bool,
+    config: &GroupConfig,
+) -> Result<MlsGroup, WelcomeError> {
+    let provisional_tree = if defer_validation {
+        Some(ProvisionalTree {
+            nodes,
+            tree_hash: group_info.tree_hash.clone(),
+        })
+    } else {
+        None
+    };
+
+    // Verify GroupInfo signature. The tree might not be validated yet, the
+    // signer's leaf can be missing.
+    let signer_leaf_node = match tree.get_leaf_node(group_info.signer_index) {
+        Some(leaf_node) => leaf_node,
+        None => return Err(WelcomeError::InvalidGroupInfoSignature),
+    };
+    if !group_info.verify_signature(signer_leaf_node.get_credential(), &group_info.signature) {
         return Err(WelcomeError::InvalidGroupInfoSignature);
     }
 
-    // Verify ratchet tree
-    if !RatchetTree::verify_integrity(&ciphersuite, &nodes) {
-        return Err(WelcomeError::InvalidRatchetTree);
+    // Verify group context extensions
+    if validate_extensions(&group_info.extensions, &[]).is_err() {
+        return Err(WelcomeError::InvalidGroupContextExtensions);
     }
 
     // Compute path secrets
     // TODO: check if path_secret has to be optional
     if let Some(path_secret) = group_secrets.path_secret {
-        let common_ancestor = treemath::common_ancestor(
-            tree.get_own_index(),
-            NodeIndex::from(group_info.signer_index),
-        );
-        let common_path = treemath::dirpath_root(common_ancestor, tree.leaf_count());
+        // The signer has a leaf, so it has a node index
+        let signer_index = NodeIndex::try_from(group_info.signer_index)
+            .map_err(|_| WelcomeError::InvalidGroupInfoSignature)?;
+        let common_ancestor = treemath::common_ancestor(tree.get_own_index(), signer_index);
+        // The path secret is for the common ancestor, which is part of the
+        // common path
+        let own_path = treemath::dirpath_root(tree.get_own_index(), tree.leaf_count());
+        let position = match own_path.iter().position(|&x| x == common_ancestor) {
+            Some(position) => position,
+            None => return Err(WelcomeError::InvalidRatchetTree),
+        };
+        let common_path = own_path[position..].to_vec();
         let (path_secrets, _commit_secret) = OwnLeaf::continue_path_secrets(
             &ciphersuite,
             &path_secret.path_secret,
             common_path.len(),
         );
         let keypairs = OwnLeaf::generate_path_keypairs(&ciphersuite, &path_secrets);
-        tree.merge_keypairs(&keypairs, &common_path);
+        // The tree already contains the public keys, replacing the nodes would
+        // drop their parent hashes
+        for (keypair, index) in keypairs.iter().zip(common_path.iter()) {
+            if tree.nodes()[index.as_usize()].get_public_hpke_key()
+                != Some(keypair.get_public_key())
+            {
+                return Err(WelcomeError::PathPublicKeyMismatch);
+            }
+        }
 
         let mut path_keypairs = PathKeypairs::new();
         path_keypairs.add(&keypairs, &common_path);
@@ -116,11 +399,23 @@ pub fn new_from_welcome(
     let group_context = GroupContext {
         group_id: group_info.group_id,
         epoch: group_info.epoch,
-        tree_hash: tree.compute_tree_hash(),
+        tree_hash: group_info.tree_hash,
         confirmed_transcript_hash: group_info.confirmed_transcript_hash,
+        extensions: group_info.extensions,
     };
-    let epoch_secrets =
-        EpochSecrets::derive_epoch_secrets(&ciphersuite, &group_secrets.joiner_secret, vec![]);
+    let mut welcome_psks = vec![];
+    for psk_id in group_secrets.psks.iter() {
+        match psks.iter().find(|(id, _)| id == psk_id) {
+            Some(psk) => welcome_psks.push(psk.clone()),
+            None => return Err(WelcomeError::MissingPsk),
+        }
+    }
+    let epoch_secrets = EpochSecrets::from_joiner_secret(
+        &ciphersuite,
+        &group_secrets.joiner_secret,
+        psk_secret(&ciphersuite, &welcome_psks).as_deref(),
+        &group_context,
+    );
     let astree = ASTree::new(&epoch_secrets.application_secret, tree.leaf_count());
 
     // Verify confirmation tag
@@ -132,14 +427,38 @@ pub fn new_from_welcome(
     {
         Err(WelcomeError::ConfirmationTagMismatch)
     } else {
+        trace_event!(epoch = group_context.epoch.0, "joined group");
+        let resumption_secrets =
+            vec![(group_context.epoch, epoch_secrets.resumption_secret.clone())];
         Ok(MlsGroup {
-            ciphersuite: welcome.cipher_suite,
+            ciphersuite,
             group_context,
-            generation: 0,
+            send_counter: 0,
             epoch_secrets,
-            astree: RefCell::new(astree),
-            tree: RefCell::new(tree),
+            astree,
+            tree,
             interim_transcript_hash: group_info.interim_transcript_hash,
+            pending_kpbs: HashMap::new(),
+            pending_commit_states: HashMap::new(),
+            pending_commit: None,
+            commit_policy: CommitPolicy::default(),
+            epoch_policy: EpochPolicy::default(),
+            epoch_started_at: config.time_provider.now(),
+            time_provider: config.time_provider.clone(),
+            rng_provider: config.rng_provider.clone(),
+            epoch_message_count: 0,
+            resumption_secrets,
+            psk_store: None,
+            commit_sequencer: None,
+            last_commit_token: None,
+            allow_plaintext_application_messages: false,
+            provisional_tree,
+            observer: false,
+            sender_key_cache: SenderKeyCache::default(),
+            send_queue: SendQueue::default(),
+            sequence_numbers: None,
+            #[cfg(feature = "dangerous-epoch-escrow")]
+            epoch_escrow: None,
         })
     }
 }
@@ -150,12 +469,11 @@ fn find_key_package_from_welcome_secrets(
     key_package: &KeyPackage,
     welcome_secrets: &[EncryptedGroupSecrets],
 ) -> Option<EncryptedGroupSecrets> {
-    for egs in welcome_secrets {
-        if key_package.hash() == egs.key_package_hash {
-            return Some(egs.clone());
-        }
-    }
-    None
+    let key_package_ref = key_package.key_package_ref();
+    welcome_secrets
+        .iter()
+        .find(|egs| egs.key_package_ref == key_package_ref)
+        .cloned()
 }
 
 fn decrypt_group_info(
@@ -164,13 +482,16 @@ fn decrypt_group_info(
     private_key: &HPKEPrivateKey,
     encrypted_group_info: &[u8],
 ) -> Result<(GroupInfo, GroupSecrets), WelcomeError> {
-    let group_secrets_bytes = ciphersuite.hpke_open(
-        &encrypted_group_secrets.encrypted_group_secrets,
-        &private_key,
-        &[],
-        &[],
-    );
-    let group_secrets = GroupSecrets::decode(&mut Cursor::new(&group_secrets_bytes)).unwrap();
+    let group_secrets_bytes = ciphersuite
+        .hpke_open(
+            &encrypted_group_secrets.encrypted_group_secrets,
+            &private_key,
+            &[],
+            &[],
+        )
+        .map_err(|_| WelcomeError::GroupSecretsDecryptionFailure)?;
+    let group_secrets = GroupSecrets::decode(&mut Cursor::new(&group_secrets_bytes))
+        .map_err(|_| WelcomeError::GroupSecretsDecryptionFailure)?;
     let (welcome_key, welcome_nonce) =
         compute_welcome_key_nonce(ciphersuite, &group_secrets.joiner_secret);
     let group_info_bytes =
@@ -178,8 +499,7 @@ fn decrypt_group_info(
             Ok(bytes) => bytes,
             Err(_) => return Err(WelcomeError::GroupInfoDecryptionFailure),
         };
-    Ok((
-        GroupInfo::from_bytes(&group_info_bytes).unwrap(),
-        group_secrets,
-    ))
+    let group_info = GroupInfo::from_bytes(&group_info_bytes)
+        .map_err(|_| WelcomeError::GroupInfoDecryptionFailure)?;
+    Ok((group_info, group_secrets))
 }
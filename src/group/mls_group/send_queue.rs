@@ -0,0 +1,122 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+//! Encrypted messages that wait to be delivered.
+//!
+//! Every encryption consumes a generation of the sender ratchet. Encrypting
+//! a message again when a send failed burns generations and can push the
+//! receivers' ratchets out of their window. Messages encrypted with
+//! `encrypt_and_stash` are kept with the group until the application
+//! confirms their delivery, so a retry sends the exact same ciphertext.
+
+use crate::codec::*;
+use crate::framing::*;
+use crate::group::{mls_group::*, *};
+
+use std::convert::TryFrom;
+
+/// Maximum number of messages that can wait for delivery in a group.
+pub const MAX_STASHED_MESSAGES: usize = 1_000;
+
+/// Identifies a stashed message. It is the value of the send counter that
+/// was assigned to the message, so tokens are unique within a group and
+/// increase in the order the messages were encrypted.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Clone, Copy)]
+pub struct SendToken(pub u32);
+
+/// Messages that were encrypted and not confirmed as sent yet, oldest first
+#[derive(Clone, Default)]
+pub(crate) struct SendQueue {
+    messages: Vec<(SendToken, MLSCiphertext)>,
+}
+
+impl Codec for SendQueue {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        u32::try_from(self.messages.len())
+            .map_err(|_| CodecError::EncodingError)?
+            .encode(buffer)?;
+        for (token, mls_ciphertext) in self.messages.iter() {
+            token.0.encode(buffer)?;
+            mls_ciphertext.encode(buffer)?;
+        }
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let len = u32::decode(cursor)?;
+        let mut messages = vec![];
+        for _ in 0..len {
+            let token = SendToken(u32::decode(cursor)?);
+            messages.push((token, MLSCiphertext::decode(cursor)?));
+        }
+        Ok(SendQueue { messages })
+    }
+}
+
+impl MlsGroup {
+    /// Encrypt an MLS message like `encrypt` and keep the ciphertext until
+    /// `confirm_sent` is called with the returned token. Store the group
+    /// before the message is handed to the network, so that the ciphertext
+    /// survives a restart. Fails with `SendQueueFull` if
+    /// `MAX_STASHED_MESSAGES` messages wait for delivery.
+    pub fn encrypt_and_stash(
+        &mut self,
+        mls_plaintext: MLSPlaintext,
+    ) -> Result<(MLSCiphertext, SendToken), EncryptionError> {
+        if self.send_queue.messages.len() >= MAX_STASHED_MESSAGES {
+            return Err(EncryptionError::SendQueueFull);
+        }
+        let (mls_ciphertext, send_counter) = self.encrypt_with_send_counter(mls_plaintext)?;
+        let token = SendToken(send_counter);
+        self.send_queue
+            .messages
+            .push((token, mls_ciphertext.clone()));
+        Ok((mls_ciphertext, token))
+    }
+
+    /// Get the stashed ciphertext of `token` to send it again. The sender
+    /// ratchet is not advanced.
+    pub fn stashed_message(&self, token: SendToken) -> Option<&MLSCiphertext> {
+        self.send_queue
+            .messages
+            .iter()
+            .find(|(stashed_token, _)| *stashed_token == token)
+            .map(|(_, mls_ciphertext)| mls_ciphertext)
+    }
+
+    /// Get the tokens of all messages that wait for delivery, oldest first,
+    /// e.g. to send them again after a restart. Messages of past epochs are
+    /// kept, they can only be decrypted by receivers that still have the
+    /// secrets of the epoch.
+    pub fn stashed_tokens(&self) -> Vec<SendToken> {
+        self.send_queue
+            .messages
+            .iter()
+            .map(|(token, _)| *token)
+            .collect()
+    }
+
+    /// Remove the message of `token` from the queue once it was delivered.
+    /// Returns `None` if the message is not stashed, e.g. because it was
+    /// already confirmed.
+    pub fn confirm_sent(&mut self, token: SendToken) -> Option<MLSCiphertext> {
+        let position = self
+            .send_queue
+            .messages
+            .iter()
+            .position(|(stashed_token, _)| *stashed_token == token)?;
+        Some(self.send_queue.messages.remove(position).1)
+    }
+}
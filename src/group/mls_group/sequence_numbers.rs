@@ -0,0 +1,173 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+//! Per-sender sequence numbers of application messages.
+//!
+//! The generation of the sender ratchet restarts in every epoch, so a server
+//! that drops messages at the end of an epoch or delays them to another
+//! epoch goes unnoticed. When sequence numbers are enabled, every member
+//! prefixes the authenticated data of its application messages with a
+//! sequence number that increases across epochs, encoded like the ordering
+//! tokens of `sequenced_aad`. Receivers reject numbers that are not larger
+//! than the last one of the sender and report how many numbers were skipped.
+//! Senders are tracked by their leaf, a leaf that is reused by another member
+//! starts at 1 again. All members have to enable sequence numbers, the
+//! setting is not negotiated in the group.
+
+use crate::ciphersuite::*;
+use crate::codec::*;
+use crate::framing::*;
+use crate::group::{mls_group::*, *};
+use crate::messages::*;
+use crate::tree::index::*;
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+/// Sequence number state of a group, stored with the group
+#[derive(Clone, Default)]
+pub(crate) struct SequenceNumbers {
+    // The last sequence number this member sent
+    last_sent: u64,
+    // The last sequence number received from each member, indexed by the
+    // leaf of the member
+    last_received: BTreeMap<LeafIndex, u64>,
+}
+
+impl SequenceNumbers {
+    /// Copy the state for an observer. The observer doesn't send, so it
+    /// starts without an own sequence number.
+    pub(crate) fn observer_copy(&self) -> Self {
+        SequenceNumbers {
+            last_sent: 0,
+            last_received: self.last_received.clone(),
+        }
+    }
+
+    /// Forget the leaves that were removed or assigned to new members in a
+    /// Commit, so that their next member starts at 1 again.
+    pub(crate) fn reset_changed_leaves(&mut self, membership_changes: &MembershipChanges) {
+        for member_change in membership_changes
+            .removes
+            .iter()
+            .chain(membership_changes.adds.iter())
+        {
+            self.last_received.remove(&member_change.index);
+        }
+    }
+}
+
+impl Codec for SequenceNumbers {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        self.last_sent.encode(buffer)?;
+        u32::try_from(self.last_received.len())
+            .map_err(|_| CodecError::EncodingError)?
+            .encode(buffer)?;
+        for (leaf_index, sequence_number) in self.last_received.iter() {
+            leaf_index.as_u32().encode(buffer)?;
+            sequence_number.encode(buffer)?;
+        }
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let last_sent = u64::decode(cursor)?;
+        let len = u32::decode(cursor)?;
+        let mut last_received = BTreeMap::new();
+        for _ in 0..len {
+            let leaf_index = LeafIndex::from(u32::decode(cursor)?);
+            last_received.insert(leaf_index, u64::decode(cursor)?);
+        }
+        Ok(SequenceNumbers {
+            last_sent,
+            last_received,
+        })
+    }
+}
+
+impl MlsGroup {
+    /// Enable or disable sequence numbers for application messages. Disabling
+    /// them forgets the sequence numbers of all members.
+    pub fn set_sequence_numbers(&mut self, enabled: bool) {
+        match (enabled, &self.sequence_numbers) {
+            (true, None) => self.sequence_numbers = Some(SequenceNumbers::default()),
+            (false, _) => self.sequence_numbers = None,
+            (true, Some(_)) => {}
+        }
+    }
+
+    /// Returns `true` if sequence numbers are enabled.
+    pub fn sequence_numbers_enabled(&self) -> bool {
+        self.sequence_numbers.is_some()
+    }
+
+    /// Create an application message like `create_application_message` and
+    /// prefix `aad` with the next sequence number of this member. Fails if
+    /// sequence numbers are not enabled.
+    pub fn create_sequenced_application_message(
+        &mut self,
+        aad: &[u8],
+        msg: &[u8],
+        signature_key: &SignaturePrivateKey,
+    ) -> Result<MLSPlaintext, EncryptionError> {
+        // An observer can't encrypt the message, its number would be lost
+        if self.observer {
+            return Err(EncryptionError::ObserverGroup);
+        }
+        let sequence_numbers = self
+            .sequence_numbers
+            .as_mut()
+            .ok_or(EncryptionError::SequenceNumbersDisabled)?;
+        let sequence_number = sequence_numbers
+            .last_sent
+            .checked_add(1)
+            .ok_or(EncryptionError::SendCounterExhausted)?;
+        sequence_numbers.last_sent = sequence_number;
+        let aad = sequenced_aad(sequence_number, aad);
+        Ok(self.create_application_message(&aad, msg, signature_key))
+    }
+
+    /// Check the sequence number of a decrypted application message against
+    /// the last one of its sender and record it.
+    pub(crate) fn check_sequence_number(
+        &mut self,
+        processed_message: &mut ProcessedMessage,
+    ) -> Result<(), DecryptionError> {
+        let sequence_numbers = match &mut self.sequence_numbers {
+            Some(sequence_numbers) => sequence_numbers,
+            None => return Ok(()),
+        };
+        if processed_message.application_data().is_none() {
+            return Ok(());
+        }
+        let (sequence_number, _) = split_sequenced_aad(processed_message.aad())
+            .ok_or(DecryptionError::MissingSequenceNumber)?;
+        let sender = processed_message.sender_index();
+        let missed_messages = match sequence_numbers.last_received.get(&sender) {
+            Some(last) if sequence_number <= *last => {
+                return Err(DecryptionError::StaleSequenceNumber)
+            }
+            Some(last) => sequence_number - last - 1,
+            // The first message of the sender this member sees
+            None => 0,
+        };
+        sequence_numbers
+            .last_received
+            .insert(sender, sequence_number);
+        processed_message.set_sequence_number(sequence_number, missed_messages);
+        processed_message.strip_aad_prefix();
+        Ok(())
+    }
+}
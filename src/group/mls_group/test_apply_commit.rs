@@ -0,0 +1,385 @@
+// Negative-path conformance tests for `apply_commit`. Alice creates valid
+// Commits, the tests break them in one specific way and check that Bob
+// rejects them with the matching `ApplyCommitError` without changing his
+// group state.
+
+use crate::ciphersuite::*;
+use crate::creds::*;
+use crate::extensions::*;
+use crate::framing::*;
+use crate::group::*;
+use crate::key_packages::*;
+use crate::messages::{proposals::*, *};
+use crate::tree::{index::*, *};
+
+struct TestGroups {
+    ciphersuite: Ciphersuite,
+    alice_identity: Identity,
+    alice_credential: Credential,
+    group_alice: MlsGroup,
+    group_bob: MlsGroup,
+}
+
+/// Create a group of Alice, Bob and Charlie, seen by Alice and Bob.
+fn setup() -> TestGroups {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let new_member = |name: &str| {
+        let identity = Identity::new(ciphersuite, name.as_bytes().to_vec());
+        let credential = Credential::Basic(BasicCredential::from(&identity));
+        let kpb = KeyPackageBundle::new(
+            &ciphersuite,
+            identity.get_signature_key_pair().get_private_key(),
+            credential.clone(),
+            None,
+        );
+        (identity, credential, kpb)
+    };
+    let (alice_identity, alice_credential, alice_kpb) = new_member("Alice");
+    let (_, _, bob_kpb) = new_member("Bob");
+    let (_, _, charlie_kpb) = new_member("Charlie");
+
+    let (group_alice, welcome) = MlsGroup::new_with_members(
+        &[1, 2, 3],
+        ciphersuite,
+        alice_kpb,
+        alice_identity.get_signature_key_pair().get_private_key(),
+        vec![
+            bob_kpb.get_key_package().clone(),
+            charlie_kpb.get_key_package().clone(),
+        ],
+    )
+    .unwrap();
+    let group_bob = MlsGroup::new_from_welcome(
+        welcome,
+        Some(group_alice.get_tree().public_key_tree()),
+        bob_kpb,
+    )
+    .unwrap();
+    TestGroups {
+        ciphersuite,
+        alice_identity,
+        alice_credential,
+        group_alice,
+        group_bob,
+    }
+}
+
+impl TestGroups {
+    fn alice_signature_key(&self) -> &SignaturePrivateKey {
+        self.alice_identity
+            .get_signature_key_pair()
+            .get_private_key()
+    }
+
+    fn alice_kpb(&self) -> KeyPackageBundle {
+        KeyPackageBundle::new(
+            &self.ciphersuite,
+            self.alice_signature_key(),
+            self.alice_credential.clone(),
+            None,
+        )
+    }
+
+    /// A Commit of Alice with a path and no proposals.
+    fn update_commit(&mut self) -> MLSPlaintext {
+        let kpb = self.alice_kpb();
+        let signature_key = self
+            .alice_identity
+            .get_signature_key_pair()
+            .get_private_key();
+        self.group_alice
+            .update_self(&[], signature_key, kpb)
+            .unwrap()
+            .get_commit()
+            .clone()
+    }
+
+    /// A Commit of Alice that removes Charlie, with the proposals it covers.
+    fn remove_commit(&mut self) -> (MLSPlaintext, Vec<(Sender, Proposal)>) {
+        let kpb = self.alice_kpb();
+        let signature_key = self
+            .alice_identity
+            .get_signature_key_pair()
+            .get_private_key();
+        let (_, proposal) = self
+            .group_alice
+            .create_remove_proposal(&[], signature_key, LeafIndex::from(2u32))
+            .unwrap();
+        let commit_bundle = self
+            .group_alice
+            .create_commit(
+                &[],
+                signature_key,
+                kpb,
+                vec![(Sender::member(LeafIndex::from(0u32)), proposal)],
+                false,
+            )
+            .unwrap();
+        (
+            commit_bundle.get_commit().clone(),
+            commit_bundle.get_proposal_list(),
+        )
+    }
+
+    /// Sign a modified Commit again, so that only the modification is
+    /// rejected.
+    fn resign(&self, mls_plaintext: &mut MLSPlaintext) {
+        mls_plaintext.sign(
+            &self.ciphersuite,
+            self.alice_signature_key(),
+            &self.group_bob.group_context,
+        );
+    }
+
+    /// Check that Bob rejects the Commit with `error` and keeps his state.
+    fn assert_rejected(
+        &mut self,
+        mls_plaintext: MLSPlaintext,
+        proposals: Vec<(Sender, Proposal)>,
+        error: ApplyCommitError,
+    ) {
+        let fingerprint = self.group_bob.get_fingerprint();
+        assert_eq!(
+            self.group_bob.apply_commit(mls_plaintext, proposals).err(),
+            Some(error)
+        );
+        assert_eq!(self.group_bob.get_fingerprint(), fingerprint);
+    }
+}
+
+fn commit_mut(mls_plaintext: &mut MLSPlaintext) -> &mut Commit {
+    match &mut mls_plaintext.content {
+        MLSPlaintextContentType::Commit((commit, _)) => commit,
+        _ => panic!("Expected a Commit"),
+    }
+}
+
+fn path_mut(mls_plaintext: &mut MLSPlaintext) -> &mut DirectPath {
+    commit_mut(mls_plaintext).path.as_mut().unwrap()
+}
+
+#[test]
+fn valid_commit() {
+    let mut groups = setup();
+    let mls_plaintext = groups.update_commit();
+    groups.group_alice.merge_pending_commit().unwrap();
+    groups
+        .group_bob
+        .apply_commit(mls_plaintext, vec![])
+        .unwrap();
+    assert_eq!(
+        groups.group_alice.get_fingerprint(),
+        groups.group_bob.get_fingerprint()
+    );
+}
+
+#[test]
+fn stale_epoch() {
+    let mut groups = setup();
+    let mls_plaintext = groups.update_commit();
+    groups
+        .group_bob
+        .apply_commit(mls_plaintext.clone(), vec![])
+        .unwrap();
+
+    // The same Commit again
+    groups.assert_rejected(mls_plaintext, vec![], ApplyCommitError::EpochMismatch);
+
+    // A Commit from a future epoch
+    let mut mls_plaintext = groups.update_commit();
+    mls_plaintext.epoch.increment();
+    mls_plaintext.epoch.increment();
+    groups.resign(&mut mls_plaintext);
+    groups.assert_rejected(mls_plaintext, vec![], ApplyCommitError::EpochMismatch);
+}
+
+#[test]
+fn wrong_content_type() {
+    let mut groups = setup();
+    let mls_plaintext =
+        groups
+            .group_alice
+            .create_application_message(&[], b"Hello", groups.alice_signature_key());
+    groups.assert_rejected(
+        mls_plaintext,
+        vec![],
+        ApplyCommitError::WrongPlaintextContentType,
+    );
+}
+
+#[test]
+fn unknown_proposal_refs() {
+    let mut groups = setup();
+    let (mls_plaintext, proposals) = groups.remove_commit();
+
+    // The proposal isn't known to the receiver
+    groups.assert_rejected(
+        mls_plaintext.clone(),
+        vec![],
+        ApplyCommitError::UnknownProposal,
+    );
+
+    // The Commit references a proposal that wasn't sent
+    let mut modified = mls_plaintext.clone();
+    let unknown_proposal = Proposal::Remove(RemoveProposal { removed: 1 });
+    commit_mut(&mut modified)
+        .removes
+        .push(unknown_proposal.to_proposal_id(&groups.ciphersuite));
+    groups.resign(&mut modified);
+    groups.assert_rejected(
+        modified,
+        proposals.clone(),
+        ApplyCommitError::UnknownProposal,
+    );
+
+    // The Remove proposal is referenced as an Add proposal
+    let mut modified = mls_plaintext.clone();
+    let commit = commit_mut(&mut modified);
+    let removes = std::mem::take(&mut commit.removes);
+    commit.adds = removes;
+    groups.resign(&mut modified);
+    groups.assert_rejected(
+        modified,
+        proposals.clone(),
+        ApplyCommitError::UnknownProposal,
+    );
+
+    // The Commit references the committer's own removal
+    let mut modified = mls_plaintext;
+    let mut proposals = proposals;
+    let self_removal = Proposal::Remove(RemoveProposal { removed: 0 });
+    commit_mut(&mut modified)
+        .removes
+        .push(self_removal.to_proposal_id(&groups.ciphersuite));
+    proposals.push((Sender::member(LeafIndex::from(0u32)), self_removal));
+    groups.resign(&mut modified);
+    groups.assert_rejected(modified, proposals, ApplyCommitError::CommitterRemoved);
+}
+
+#[test]
+fn duplicate_removes() {
+    let mut groups = setup();
+    let (mls_plaintext, _) = groups.remove_commit();
+    let proposals = vec![(
+        Sender::member(LeafIndex::from(0u32)),
+        Proposal::Remove(RemoveProposal { removed: 2 }),
+    )];
+
+    // The Remove of Charlie is referenced twice
+    let mut modified = mls_plaintext;
+    let commit = commit_mut(&mut modified);
+    let remove = commit.removes[0].clone();
+    commit.removes.push(remove);
+    groups.resign(&mut modified);
+    groups.assert_rejected(modified, proposals, ApplyCommitError::DuplicateRemove);
+}
+
+#[test]
+fn truncated_path() {
+    let mut groups = setup();
+    let mls_plaintext = groups.update_commit();
+    assert_eq!(path_mut(&mut mls_plaintext.clone()).nodes.len(), 2);
+
+    let mut modified = mls_plaintext.clone();
+    path_mut(&mut modified).nodes.pop();
+    groups.resign(&mut modified);
+    groups.assert_rejected(modified, vec![], ApplyCommitError::InvalidPathLength);
+
+    let mut modified = mls_plaintext.clone();
+    path_mut(&mut modified).nodes.clear();
+    groups.resign(&mut modified);
+    groups.assert_rejected(modified, vec![], ApplyCommitError::InvalidPathLength);
+
+    // Bob can't find the ciphertext for his subtree
+    let mut modified = mls_plaintext.clone();
+    path_mut(&mut modified).nodes[0]
+        .encrypted_path_secret
+        .clear();
+    groups.resign(&mut modified);
+    groups.assert_rejected(
+        modified,
+        vec![],
+        ApplyCommitError::PathResolutionSizeMismatch,
+    );
+
+    // A Commit without a path, although an empty Commit requires one
+    let mut modified = mls_plaintext;
+    commit_mut(&mut modified).path = None;
+    groups.resign(&mut modified);
+    groups.assert_rejected(modified, vec![], ApplyCommitError::RequiredPathNotFound);
+}
+
+#[test]
+fn mismatched_public_keys() {
+    let mut groups = setup();
+    let other_public_key = groups
+        .alice_kpb()
+        .get_key_package()
+        .get_hpke_init_key()
+        .clone();
+    let mls_plaintext = groups.update_commit();
+    for i in 0..2 {
+        let mut modified = mls_plaintext.clone();
+        path_mut(&mut modified).nodes[i].public_key = other_public_key.clone();
+        groups.resign(&mut modified);
+        groups.assert_rejected(modified, vec![], ApplyCommitError::PathPublicKeyMismatch);
+    }
+}
+
+#[test]
+fn wrong_parent_hash() {
+    let mut groups = setup();
+    let mls_plaintext = groups.update_commit();
+
+    // A wrong parent hash that is correctly signed by Alice
+    let mut modified = mls_plaintext.clone();
+    let key_package = &mut path_mut(&mut modified).leaf_key_package;
+    key_package.remove_extension(ExtensionType::ParentHash);
+    key_package.add_extension(ParentHashExtension::new(&[0; 32]).to_extension());
+    key_package.update_signature(groups.alice_signature_key());
+    groups.resign(&mut modified);
+    groups.assert_rejected(modified, vec![], ApplyCommitError::ParentHashMismatch);
+
+    // No parent hash at all
+    let mut modified = mls_plaintext.clone();
+    let key_package = &mut path_mut(&mut modified).leaf_key_package;
+    key_package.remove_extension(ExtensionType::ParentHash);
+    key_package.update_signature(groups.alice_signature_key());
+    groups.resign(&mut modified);
+    groups.assert_rejected(modified, vec![], ApplyCommitError::NoParentHashExtension);
+
+    // The key package isn't signed again after the modification
+    let mut modified = mls_plaintext;
+    path_mut(&mut modified)
+        .leaf_key_package
+        .remove_extension(ExtensionType::ParentHash);
+    groups.resign(&mut modified);
+    groups.assert_rejected(
+        modified,
+        vec![],
+        ApplyCommitError::PathKeyPackageVerificationFailure,
+    );
+}
+
+#[test]
+fn invalid_signature_and_confirmation_tag() {
+    let mut groups = setup();
+    let mls_plaintext = groups.update_commit();
+
+    // The signature doesn't cover the modified authenticated data
+    let mut modified = mls_plaintext.clone();
+    modified.authenticated_data = vec![1, 2, 3];
+    groups.assert_rejected(
+        modified,
+        vec![],
+        ApplyCommitError::PlaintextSignatureFailure,
+    );
+
+    let mut modified = mls_plaintext;
+    if let MLSPlaintextContentType::Commit((_, confirmation_tag)) = &mut modified.content {
+        confirmation_tag.0 = vec![0; confirmation_tag.0.len()];
+    }
+    groups.resign(&mut modified);
+    groups.assert_rejected(modified, vec![], ApplyCommitError::ConfirmationTagMismatch);
+}
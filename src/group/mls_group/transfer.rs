@@ -0,0 +1,128 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+//! Transfer of a group to another device of the same user.
+//!
+//! The transfer package lets the new device follow the group until it is
+//! added with a leaf of its own. It only contains what a member without a
+//! leaf needs: the public ratchet tree, the group context and the secrets of
+//! the current epoch. The private keys of the exporting device's leaf and
+//! path, its sender ratchets and pending key material are not included.
+//!
+//! The imported group is an observer. It derives its own sender ratchets
+//! from the epoch's application secret, so it can decrypt the messages of the
+//! current epoch, and it can apply Commits without a path, e.g. ones that only
+//! add members. It can't send messages, create proposals or Commits, and it
+//! can't decrypt the path of a Commit; after such a Commit a new package has
+//! to be exported. Once the new device joined from a `Welcome`, the observer
+//! should be discarded.
+
+use crate::codec::*;
+use crate::framing::*;
+use crate::group::{mls_group::*, *};
+use crate::storage::*;
+use crate::tree::astree::*;
+
+use std::collections::HashMap;
+
+/// Content of a transfer package
+struct GroupTransfer {
+    group: MlsGroup,
+}
+
+impl Storable for GroupTransfer {
+    const STORAGE_TYPE: StorageType = StorageType::GroupTransfer;
+}
+
+impl Codec for GroupTransfer {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        self.group.encode(buffer)
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let group = MlsGroup::decode(cursor)?;
+        // Only observers are exported
+        if !group.observer {
+            return Err(CodecError::DecodingError);
+        }
+        Ok(GroupTransfer { group })
+    }
+}
+
+impl MlsGroup {
+    /// Export the group for another device of the same user. The package is
+    /// encrypted under `transfer_key`, which has to be shared between the
+    /// devices out of band, e.g. with a QR code. Provisional groups can't be
+    /// exported.
+    pub fn export_transfer_package(&self, transfer_key: &[u8]) -> Result<Vec<u8>, StorageError> {
+        if self.provisional_tree.is_some() {
+            return Err(CodecError::EncodingError.into());
+        }
+        let tree = self.tree.observer_copy();
+        let astree = ASTree::new(&self.epoch_secrets.application_secret, tree.leaf_count());
+        let group = MlsGroup {
+            ciphersuite: self.ciphersuite,
+            group_context: self.group_context.clone(),
+            send_counter: 0,
+            epoch_secrets: self.epoch_secrets.clone(),
+            astree,
+            tree,
+            interim_transcript_hash: self.interim_transcript_hash.clone(),
+            pending_kpbs: HashMap::new(),
+            pending_commit_states: HashMap::new(),
+            pending_commit: None,
+            commit_policy: CommitPolicy::default(),
+            epoch_policy: EpochPolicy::default(),
+            epoch_started_at: self.epoch_started_at,
+            time_provider: self.time_provider.clone(),
+            rng_provider: self.rng_provider.clone(),
+            epoch_message_count: self.epoch_message_count,
+            resumption_secrets: vec![],
+            psk_store: None,
+            commit_sequencer: None,
+            last_commit_token: self.last_commit_token,
+            allow_plaintext_application_messages: false,
+            provisional_tree: None,
+            observer: true,
+            sender_key_cache: SenderKeyCache::default(),
+            send_queue: SendQueue::default(),
+            sequence_numbers: self
+                .sequence_numbers
+                .as_ref()
+                .map(SequenceNumbers::observer_copy),
+            #[cfg(feature = "dangerous-epoch-escrow")]
+            epoch_escrow: None,
+        };
+        let group_transfer = GroupTransfer { group };
+        seal(&self.ciphersuite, &group_transfer, Some(transfer_key))
+    }
+
+    /// Import a package created with `export_transfer_package` as an
+    /// observer, see `is_observer`.
+    pub fn import_transfer_package(
+        bytes: &[u8],
+        transfer_key: &[u8],
+    ) -> Result<MlsGroup, StorageError> {
+        let group_transfer: GroupTransfer = open(bytes, Some(transfer_key))?;
+        Ok(group_transfer.group)
+    }
+
+    /// Returns `true` if the group was imported from a transfer package. An
+    /// observer can decrypt messages and apply Commits without a path, but it
+    /// can't encrypt messages or create proposals and Commits.
+    pub fn is_observer(&self) -> bool {
+        self.observer
+    }
+}
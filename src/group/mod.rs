@@ -19,21 +19,49 @@
 //! This file contains the API to interact with groups.
 //!
 //! The low-level standard API is described in the `Api` trait.\
-//! The high-level API is exposed in `ManagedGroup`.
+//! The high-level API is exposed in `ManagedGroup`.\
+//! `SharedMlsGroup` allows using a group from several threads.
 
+#[cfg(feature = "dangerous-epoch-escrow")]
+pub mod escrow;
+mod delivery_hint;
 mod errors;
+mod group_info;
+mod history;
 mod managed_group;
+mod membership_proof;
 mod mls_group;
+mod psk_store;
+mod public_state;
+mod sequencing;
+mod shared_group;
+mod signature_key_pins;
+mod tombstone;
+mod tree_validation;
 
 use crate::ciphersuite::*;
 use crate::codec::*;
+use crate::extensions::*;
 use crate::tree::*;
 use crate::utils::*;
+use std::fmt;
+use std::sync::Arc;
 
 pub use codec::*;
+pub use delivery_hint::*;
 pub use errors::*;
+pub use group_info::*;
+pub use history::*;
 pub use managed_group::*;
+pub use membership_proof::*;
 pub use mls_group::*;
+pub use psk_store::*;
+pub use public_state::*;
+pub use sequencing::*;
+pub use shared_group::*;
+pub use signature_key_pins::*;
+pub use tombstone::*;
+pub use tree_validation::*;
 
 pub enum GroupError {
     Codec(CodecError),
@@ -81,8 +109,17 @@ impl Codec for GroupId {
 pub struct GroupEpoch(pub u64);
 
 impl GroupEpoch {
-    pub fn increment(&mut self) {
-        self.0 += 1;
+    /// Move to the next epoch. Returns `false` and leaves the epoch unchanged
+    /// if it is already the last one.
+    #[must_use]
+    pub fn increment(&mut self) -> bool {
+        match self.0.checked_add(1) {
+            Some(epoch) => {
+                self.0 = epoch;
+                true
+            }
+            None => false,
+        }
     }
 }
 
@@ -103,12 +140,18 @@ pub struct GroupContext {
     pub epoch: GroupEpoch,
     pub tree_hash: Vec<u8>,
     pub confirmed_transcript_hash: Vec<u8>,
+    pub extensions: Vec<Extension>,
 }
 
 impl GroupContext {
     pub fn serialize(&self) -> Vec<u8> {
         self.encode_detached().unwrap()
     }
+    /// Returns `true` if the group has a `DeniabilityExtension`, i.e.
+    /// application messages are not signed.
+    pub fn is_deniable(&self) -> bool {
+        get_extension_payload(&self.extensions, ExtensionType::Deniability).is_some()
+    }
 }
 
 impl Codec for GroupContext {
@@ -117,6 +160,7 @@ impl Codec for GroupContext {
         self.epoch.encode(buffer)?;
         encode_vec(VecSize::VecU8, buffer, &self.tree_hash)?;
         encode_vec(VecSize::VecU8, buffer, &self.confirmed_transcript_hash)?;
+        encode_vec(VecSize::VecU16, buffer, &self.extensions)?;
         Ok(())
     }
     fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
@@ -124,34 +168,131 @@ impl Codec for GroupContext {
         let epoch = GroupEpoch::decode(cursor)?;
         let tree_hash = decode_vec(VecSize::VecU8, cursor)?;
         let confirmed_transcript_hash = decode_vec(VecSize::VecU8, cursor)?;
+        let extensions = decode_vec(VecSize::VecU16, cursor)?;
         Ok(GroupContext {
             group_id,
             epoch,
             tree_hash,
             confirmed_transcript_hash,
+            extensions,
         })
     }
 }
 
-#[derive(Clone, Copy)]
+/// Length of a `GroupFingerprint` in bytes
+pub const GROUP_FINGERPRINT_LENGTH: usize = 16;
+
+/// Identifies the Commit that started an epoch, so that the Commit and the
+/// `Welcome` it produced can be paired afterwards. It is the confirmed
+/// transcript hash of the epoch, which covers the Commit and is contained in
+/// the `GroupInfo` of the `Welcome`.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct CorrelationId(Vec<u8>);
+
+impl CorrelationId {
+    pub(crate) fn new(confirmed_transcript_hash: &[u8]) -> Self {
+        CorrelationId(confirmed_transcript_hash.to_vec())
+    }
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// A short hash of the group ID, epoch, tree hash and confirmed transcript hash
+/// of a group. Two members that have the same fingerprint agree on the state of
+/// the group. Fingerprints don't reveal any secrets, so they can be logged or
+/// compared out of band like a safety number.
+#[derive(Debug, PartialEq, Clone)]
+pub struct GroupFingerprint {
+    value: Vec<u8>,
+}
+
+impl GroupFingerprint {
+    /// Compute the fingerprint of the group in the epoch of `group_context`.
+    pub fn new(ciphersuite: &Ciphersuite, group_context: &GroupContext) -> Self {
+        let mut buffer = b"mls10 fingerprint".to_vec();
+        group_context.group_id.encode(&mut buffer).unwrap();
+        group_context.epoch.encode(&mut buffer).unwrap();
+        encode_vec(VecSize::VecU8, &mut buffer, &group_context.tree_hash).unwrap();
+        encode_vec(
+            VecSize::VecU8,
+            &mut buffer,
+            &group_context.confirmed_transcript_hash,
+        )
+        .unwrap();
+        let mut value = ciphersuite.hash(&buffer);
+        value.truncate(GROUP_FINGERPRINT_LENGTH);
+        Self { value }
+    }
+    pub fn as_slice(&self) -> &[u8] {
+        &self.value
+    }
+    /// Compare with a fingerprint received from another member in constant
+    /// time.
+    pub fn matches(&self, other: &GroupFingerprint) -> bool {
+        equal_ct(&self.value, &other.value)
+    }
+}
+
+/// Formats the fingerprint as groups of four hex digits.
+impl fmt::Display for GroupFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, chunk) in self.value.chunks(2).enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            for byte in chunk {
+                write!(f, "{:02x}", byte)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
 pub struct GroupConfig {
     pub(crate) padding_block_size: u32,
     pub(crate) additional_as_epochs: u32,
+    // Not encoded with the config
+    pub(crate) time_provider: Arc<dyn TimeProvider>,
+    // Not encoded with the config
+    pub(crate) rng_provider: Arc<dyn RngProvider>,
 }
 
 impl GroupConfig {
     /// Create a new `GroupConfig` with the given ciphersuite.
     pub fn new() -> Self {
-        Self {
-            padding_block_size: 10,
-            additional_as_epochs: 0,
-        }
+        Self::default()
     }
 
     /// Get the padding block size used in this config.
     pub fn get_padding_block_size(&self) -> u32 {
         self.padding_block_size
     }
+
+    /// Set the source of the current time of groups that are created or
+    /// joined with this config, e.g. for tests or platforms without a system
+    /// clock. The default is `SystemTimeProvider`.
+    pub fn set_time_provider(&mut self, time_provider: Arc<dyn TimeProvider>) {
+        self.time_provider = time_provider;
+    }
+
+    /// Get the source of the current time.
+    pub fn get_time_provider(&self) -> &dyn TimeProvider {
+        self.time_provider.as_ref()
+    }
+
+    /// Set the source of the randomness of the leaf secrets of Commits of
+    /// groups that are created or joined with this config. Only tests should
+    /// replace the default `OsRngProvider`.
+    pub fn set_rng_provider(&mut self, rng_provider: Arc<dyn RngProvider>) {
+        self.rng_provider = rng_provider;
+    }
+
+    /// Get the source of randomness.
+    pub fn get_rng_provider(&self) -> &dyn RngProvider {
+        self.rng_provider.as_ref()
+    }
 }
 
 impl Default for GroupConfig {
@@ -159,6 +300,8 @@ impl Default for GroupConfig {
         Self {
             padding_block_size: 10,
             additional_as_epochs: 0,
+            time_provider: Arc::new(SystemTimeProvider),
+            rng_provider: Arc::new(OsRngProvider),
         }
     }
 }
@@ -175,6 +318,55 @@ impl Codec for GroupConfig {
         Ok(GroupConfig {
             padding_block_size,
             additional_as_epochs,
+            ..GroupConfig::default()
         })
     }
 }
+
+/// Limits for the Commits of a group. Members have to agree on the limits
+/// out of band, `None` means unlimited. Sizes are given in bytes of the
+/// encoded message.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CommitPolicy {
+    /// Maximum number of proposals a Commit may cover
+    pub max_proposals: Option<usize>,
+    /// Maximum size of a Commit
+    pub max_commit_size: Option<usize>,
+    /// Maximum size of a Welcome
+    pub max_welcome_size: Option<usize>,
+}
+
+/// Limits for the lifetime of an epoch, for deployments that have to rotate
+/// the group's keys regularly. `None` means unlimited. Violations are
+/// reported by `MlsGroup::policy_violations` and fixed by a self-update.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EpochPolicy {
+    /// Maximum age of an epoch in seconds
+    pub max_epoch_age: Option<u64>,
+    /// Maximum number of messages that are encrypted or decrypted in an epoch
+    pub max_epoch_messages: Option<u32>,
+}
+
+/// A limit of the `EpochPolicy` the current epoch exceeds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PolicyViolation {
+    /// The epoch started `age` seconds ago
+    EpochTooOld { age: u64, max_age: u64 },
+    /// `count` messages were encrypted or decrypted in the epoch
+    TooManyMessages { count: u32, max_count: u32 },
+}
+
+#[test]
+fn group_context_codec() {
+    let group_context = GroupContext {
+        group_id: GroupId::random(),
+        epoch: GroupEpoch(1u64),
+        tree_hash: vec![1, 2, 3],
+        confirmed_transcript_hash: vec![4, 5, 6],
+        extensions: vec![LifetimeExtension::new(60).to_extension()],
+    };
+    let encoded = group_context.serialize();
+    let decoded = GroupContext::decode(&mut Cursor::new(&encoded)).unwrap();
+    assert_eq!(decoded.serialize(), encoded);
+    assert_eq!(decoded.extensions, group_context.extensions);
+}
@@ -0,0 +1,61 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+use crate::codec::*;
+use crate::messages::*;
+
+use std::collections::HashMap;
+
+/// Number of past epochs whose resumption secrets a group keeps to resolve
+/// resumption PSKs.
+pub const RESUMPTION_PSK_EPOCHS: usize = 8;
+
+/// Resolves the IDs of pre-shared keys to their secrets. A group consults
+/// its `PskStore` when a Commit covers PSK proposals. Resumption PSKs of the
+/// group itself are resolved from the group's own history first.
+pub trait PskStore: Send + Sync {
+    /// Get the secret of the PSK with the ID `psk_id`, or `None` if it is
+    /// unknown.
+    fn get_psk(&self, psk_id: &PreSharedKeyID) -> Option<Vec<u8>>;
+}
+
+/// `PskStore` that keeps PSKs in memory.
+#[derive(Default)]
+pub struct MemoryPskStore {
+    psks: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MemoryPskStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an external PSK that was agreed on out of band.
+    pub fn add_external_psk(&mut self, psk_id: &[u8], psk: Vec<u8>) {
+        self.add_psk(&PreSharedKeyID::external(psk_id), psk);
+    }
+
+    /// Add a PSK of any type, e.g. the resumption secret of another group.
+    pub fn add_psk(&mut self, psk_id: &PreSharedKeyID, psk: Vec<u8>) {
+        self.psks.insert(psk_id.encode_detached().unwrap(), psk);
+    }
+}
+
+impl PskStore for MemoryPskStore {
+    fn get_psk(&self, psk_id: &PreSharedKeyID) -> Option<Vec<u8>> {
+        self.psks.get(&psk_id.encode_detached().unwrap()).cloned()
+    }
+}
@@ -0,0 +1,52 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+use crate::ciphersuite::*;
+use crate::extensions::*;
+
+/// Public state of a group that backend services can index without access
+/// to any secret, e.g. to show conversations before a client synced its
+/// groups. With the `serde` feature it can be serialized, e.g. to JSON.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct PublicGroupState {
+    pub group_id: Vec<u8>,
+    pub epoch: u64,
+    pub ciphersuite: CiphersuiteName,
+    pub members: Vec<PublicMemberState>,
+    /// Types of the group context extensions
+    pub extensions: Vec<ExtensionType>,
+}
+
+/// Public state of a member of a group.
+#[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde_crate::Serialize, serde_crate::Deserialize),
+    serde(crate = "serde_crate")
+)]
+pub struct PublicMemberState {
+    /// Index of the member's leaf
+    pub index: u32,
+    /// Identity of the member's credential
+    pub identity: Vec<u8>,
+    /// Encoding of the member's `Credential`
+    pub credential: Vec<u8>,
+}
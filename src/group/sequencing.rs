@@ -0,0 +1,58 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+//! Ordering of Commits in deployments that funnel all Commits through a
+//! server-ordered queue.
+//!
+//! The committer obtains a token from the server through its
+//! `CommitSequencer` and embeds it at the start of the authenticated data of
+//! the Commit. Members that have a `CommitSequencer` set only accept Commits
+//! whose token is greater than the one of the last Commit they applied.
+
+use crate::codec::*;
+use crate::group::*;
+
+/// Hands out server-assigned ordering tokens for Commits. Tokens have to
+/// increase strictly with every Commit of a group.
+pub trait CommitSequencer: Send + Sync {
+    /// Get the token for the next Commit of the group `group_id` in `epoch`,
+    /// or `None` if no token could be obtained.
+    fn next_token(&self, group_id: &GroupId, epoch: GroupEpoch) -> Option<u64>;
+}
+
+impl<F: Fn(&GroupId, GroupEpoch) -> Option<u64> + Send + Sync> CommitSequencer for F {
+    fn next_token(&self, group_id: &GroupId, epoch: GroupEpoch) -> Option<u64> {
+        self(group_id, epoch)
+    }
+}
+
+/// Prefix the authenticated data `aad` with the ordering `token`.
+pub fn sequenced_aad(token: u64, aad: &[u8]) -> Vec<u8> {
+    let mut buffer = token.encode_detached().unwrap();
+    buffer.extend_from_slice(aad);
+    buffer
+}
+
+/// Split authenticated data created with `sequenced_aad` into the ordering
+/// token and the application's authenticated data. Returns `None` if it is
+/// too short to contain a token.
+pub fn split_sequenced_aad(aad: &[u8]) -> Option<(u64, &[u8])> {
+    if aad.len() < 8 {
+        return None;
+    }
+    let token = u64::decode(&mut Cursor::new(&aad[..8])).ok()?;
+    Some((token, &aad[8..]))
+}
@@ -0,0 +1,144 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+use crate::ciphersuite::*;
+use crate::creds::*;
+use crate::framing::*;
+use crate::group::*;
+use crate::key_packages::*;
+use crate::messages::{proposals::*, *};
+
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Handle to an `MlsGroup` that can be shared between threads.
+/// Operations that only read the group state can run concurrently, while
+/// operations that change it get exclusive access. Cloning the handle
+/// doesn't clone the group.
+///
+/// A thread that panics while holding access doesn't make the handle
+/// unusable for the other threads, the group is handed out as the panicking
+/// thread left it. Applications can check `is_poisoned` and restore the group
+/// from storage instead.
+#[derive(Clone)]
+pub struct SharedMlsGroup {
+    group: Arc<RwLock<MlsGroup>>,
+}
+
+impl SharedMlsGroup {
+    pub fn new(group: MlsGroup) -> Self {
+        Self {
+            group: Arc::new(RwLock::new(group)),
+        }
+    }
+
+    /// Get shared access to the group to inspect its state. Other readers
+    /// are not blocked.
+    pub fn read(&self) -> RwLockReadGuard<'_, MlsGroup> {
+        self.group.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Get exclusive access to the group.
+    pub fn write(&self) -> RwLockWriteGuard<'_, MlsGroup> {
+        self.group.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns `true` if a thread panicked while it had exclusive access to
+    /// the group, which might have left the group in an inconsistent state.
+    pub fn is_poisoned(&self) -> bool {
+        self.group.is_poisoned()
+    }
+
+    /// Get the `GroupContext` of the current epoch.
+    pub fn get_context(&self) -> GroupContext {
+        self.read().get_context().clone()
+    }
+
+    /// Get the `GroupFingerprint` of the current epoch.
+    pub fn get_fingerprint(&self) -> GroupFingerprint {
+        self.read().get_fingerprint()
+    }
+
+    pub fn export_secret(&self, label: &str, key_length: usize) -> Vec<u8> {
+        self.read().export_secret(label, key_length)
+    }
+
+    pub fn verify_plaintext(
+        &self,
+        mls_plaintext: &MLSPlaintext,
+    ) -> Result<Credential, SenderError> {
+        self.read().verify_plaintext(mls_plaintext)
+    }
+
+    /// Create and encrypt an application message. The message is signed
+    /// while holding shared access. Only advancing the sender ratchet needs
+    /// exclusive access. If the group moved to a new epoch in between, the
+    /// message is signed again for the new epoch.
+    pub fn send_application_message(
+        &self,
+        aad: &[u8],
+        msg: &[u8],
+        signature_key: &SignaturePrivateKey,
+    ) -> Result<MLSCiphertext, EncryptionError> {
+        let (mls_plaintext, epoch) = {
+            let group = self.read();
+            (
+                group.create_application_message(aad, msg, signature_key),
+                group.get_context().epoch,
+            )
+        };
+        let mut group = self.write();
+        let mls_plaintext = if group.get_context().epoch == epoch {
+            mls_plaintext
+        } else {
+            group.create_application_message(aad, msg, signature_key)
+        };
+        group.encrypt(mls_plaintext)
+    }
+
+    /// Decrypt a message. This needs exclusive access because the sender
+    /// ratchet is advanced.
+    pub fn decrypt(
+        &self,
+        mls_ciphertext: MLSCiphertext,
+    ) -> Result<ProcessedMessage, DecryptionError> {
+        self.write().decrypt(mls_ciphertext)
+    }
+
+    pub fn create_commit(
+        &self,
+        aad: &[u8],
+        signature_key: &SignaturePrivateKey,
+        key_package_bundle: KeyPackageBundle,
+        proposals: Vec<(Sender, Proposal)>,
+        force_self_update: bool,
+    ) -> CreateCommitResult {
+        self.write().create_commit(
+            aad,
+            signature_key,
+            key_package_bundle,
+            proposals,
+            force_self_update,
+        )
+    }
+
+    pub fn apply_commit(
+        &self,
+        mls_plaintext: MLSPlaintext,
+        proposals: Vec<(Sender, Proposal)>,
+    ) -> Result<MembershipChanges, ApplyCommitError> {
+        self.write().apply_commit(mls_plaintext, proposals)
+    }
+}
@@ -0,0 +1,194 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+use crate::ciphersuite::*;
+use crate::codec::*;
+use crate::creds::*;
+use crate::group::*;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+
+/// Events of `SignatureKeyPins` that the application should show to the user.
+#[derive(Debug, PartialEq, Clone)]
+pub enum PinEvent {
+    /// A signature key of an identity was seen for the first time and
+    /// pinned.
+    SignatureKeyPinned {
+        identity: Vec<u8>,
+        signature_key: SignaturePublicKey,
+    },
+    /// An identity uses a signature key that is not among its pinned keys.
+    /// The key is not pinned until the application calls
+    /// `SignatureKeyPins::pin` with it, e.g. after the user verified it.
+    SignatureKeyChanged {
+        identity: Vec<u8>,
+        pinned_keys: Vec<SignaturePublicKey>,
+        new_key: SignaturePublicKey,
+    },
+}
+
+/// Hook that is called for every `PinEvent`.
+pub type PinEventHook = Box<dyn FnMut(&PinEvent) + Send>;
+
+/// Pins the signature keys of every identity the first time it is seen in a
+/// group and reports when an identity shows up with another key later, in
+/// the same or in another group. An identity can have several pinned keys,
+/// e.g. one per device. Updates and Commits with a path can replace the leaf
+/// of a member, so groups should be checked again after every Commit.
+#[derive(Default)]
+pub struct SignatureKeyPins {
+    pins: BTreeMap<Vec<u8>, Vec<SignaturePublicKey>>,
+    event_hook: Option<PinEventHook>,
+}
+
+impl SignatureKeyPins {
+    /// Create an empty set of pins.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the hook that is called for every `PinEvent`.
+    pub fn set_event_hook(&mut self, event_hook: PinEventHook) {
+        self.event_hook = Some(event_hook);
+    }
+
+    /// Get the pinned signature keys of `identity`.
+    pub fn get(&self, identity: &[u8]) -> &[SignaturePublicKey] {
+        self.pins.get(identity).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// Pin `signature_key` for `identity` in addition to its pinned keys.
+    pub fn pin(&mut self, identity: &[u8], signature_key: SignaturePublicKey) {
+        let pinned_keys = self.pins.entry(identity.to_vec()).or_default();
+        if !pinned_keys.contains(&signature_key) {
+            pinned_keys.push(signature_key);
+        }
+    }
+
+    /// Forget the pinned key `signature_key` of `identity`. Returns `false`
+    /// if it wasn't pinned.
+    pub fn unpin_key(&mut self, identity: &[u8], signature_key: &SignaturePublicKey) -> bool {
+        let pinned_keys = match self.pins.get_mut(identity) {
+            Some(pinned_keys) => pinned_keys,
+            None => return false,
+        };
+        let len = pinned_keys.len();
+        pinned_keys.retain(|pinned_key| pinned_key != signature_key);
+        let removed = pinned_keys.len() != len;
+        if pinned_keys.is_empty() {
+            self.pins.remove(identity);
+        }
+        removed
+    }
+
+    /// Forget all pinned keys of `identity`.
+    pub fn unpin(&mut self, identity: &[u8]) -> Vec<SignaturePublicKey> {
+        self.pins.remove(identity).unwrap_or_default()
+    }
+
+    /// Check the signature key of `credential` against the pins of its
+    /// identity. Returns `None` if it is pinned.
+    pub fn check_credential(&mut self, credential: &Credential) -> Option<PinEvent> {
+        self.check(credential, &BTreeSet::new())
+    }
+
+    /// Check the signature keys of all members of `group`. All keys of an
+    /// identity that has no pins yet are pinned, so that members with several
+    /// devices don't cause `SignatureKeyChanged` events.
+    pub fn check_group(&mut self, group: &MlsGroup) -> Vec<PinEvent> {
+        let nodes = group.get_tree().nodes();
+        let credentials: Vec<&Credential> = nodes
+            .iter()
+            .filter_map(|node| node.leaf_node.as_ref())
+            .map(|leaf_node| leaf_node.get_credential())
+            .collect();
+        let new_identities: BTreeSet<&[u8]> = credentials
+            .iter()
+            .map(|credential| {
+                let Credential::Basic(basic_credential) = credential;
+                basic_credential.identity.as_slice()
+            })
+            .filter(|identity| !self.pins.contains_key(*identity))
+            .collect();
+        credentials
+            .into_iter()
+            .filter_map(|credential| self.check(credential, &new_identities))
+            .collect()
+    }
+
+    // Check `credential` and pin its key if its identity is in
+    // `new_identities` or has no pins yet
+    fn check(
+        &mut self,
+        credential: &Credential,
+        new_identities: &BTreeSet<&[u8]>,
+    ) -> Option<PinEvent> {
+        let Credential::Basic(basic_credential) = credential;
+        let identity = &basic_credential.identity;
+        let signature_key = &basic_credential.public_key;
+        let event = match self.pins.get(identity) {
+            Some(pinned_keys) if pinned_keys.contains(signature_key) => return None,
+            Some(pinned_keys) if !new_identities.contains(identity.as_slice()) => {
+                PinEvent::SignatureKeyChanged {
+                    identity: identity.clone(),
+                    pinned_keys: pinned_keys.clone(),
+                    new_key: signature_key.clone(),
+                }
+            }
+            _ => {
+                self.pin(identity, signature_key.clone());
+                PinEvent::SignatureKeyPinned {
+                    identity: identity.clone(),
+                    signature_key: signature_key.clone(),
+                }
+            }
+        };
+        if let Some(event_hook) = &mut self.event_hook {
+            event_hook(&event);
+        }
+        Some(event)
+    }
+}
+
+/// Only the pins are encoded, the event hook has to be set again after
+/// decoding.
+impl Codec for SignatureKeyPins {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        u32::try_from(self.pins.len())
+            .map_err(|_| CodecError::EncodingError)?
+            .encode(buffer)?;
+        for (identity, pinned_keys) in self.pins.iter() {
+            encode_vec(VecSize::VecU16, buffer, identity)?;
+            encode_vec(VecSize::VecU16, buffer, pinned_keys)?;
+        }
+        Ok(())
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let len = u32::decode(cursor)?;
+        let mut pins = BTreeMap::new();
+        for _ in 0..len {
+            let identity = decode_vec(VecSize::VecU16, cursor)?;
+            let pinned_keys = decode_vec(VecSize::VecU16, cursor)?;
+            pins.insert(identity, pinned_keys);
+        }
+        Ok(SignatureKeyPins {
+            pins,
+            event_hook: None,
+        })
+    }
+}
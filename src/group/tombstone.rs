@@ -0,0 +1,67 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+use crate::codec::*;
+use crate::framing::*;
+use crate::group::*;
+use crate::storage::*;
+
+/// What is left of a group after `MlsGroup::close`: enough to audit how the
+/// group ended, but no secrets. Messages that arrive for a closed group can't
+/// be decrypted anymore and should be dropped, see `is_late_message`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct GroupTombstone {
+    pub group_id: GroupId,
+    /// The epoch the final Commit leads to
+    pub epoch: GroupEpoch,
+    /// The confirmed transcript hash that includes the final Commit
+    pub confirmed_transcript_hash: Vec<u8>,
+    /// Time the group was closed at, in seconds since the Unix epoch
+    pub closed_at: u64,
+}
+
+impl GroupTombstone {
+    /// Returns `true` if `mls_ciphertext` was sent to the closed group.
+    pub fn is_late_message(&self, mls_ciphertext: &MLSCiphertext) -> bool {
+        mls_ciphertext.group_id == self.group_id
+    }
+}
+
+impl Codec for GroupTombstone {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        self.group_id.encode(buffer)?;
+        self.epoch.encode(buffer)?;
+        encode_vec(VecSize::VecU8, buffer, &self.confirmed_transcript_hash)?;
+        self.closed_at.encode(buffer)?;
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let group_id = GroupId::decode(cursor)?;
+        let epoch = GroupEpoch::decode(cursor)?;
+        let confirmed_transcript_hash = decode_vec(VecSize::VecU8, cursor)?;
+        let closed_at = u64::decode(cursor)?;
+        Ok(GroupTombstone {
+            group_id,
+            epoch,
+            confirmed_transcript_hash,
+            closed_at,
+        })
+    }
+}
+
+impl Storable for GroupTombstone {
+    const STORAGE_TYPE: StorageType = StorageType::GroupTombstone;
+}
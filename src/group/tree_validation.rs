@@ -0,0 +1,128 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+use crate::ciphersuite::*;
+use crate::creds::*;
+use crate::extensions::*;
+use crate::group::*;
+use crate::tree::{index::*, node::*, *};
+use std::convert::TryFrom;
+
+/// Summary of an exported tree that was verified with
+/// `verify_exported_tree`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TreeSummary {
+    /// Tree hash of the exported tree
+    pub tree_hash: Vec<u8>,
+    /// Number of leaves, including blank ones
+    pub leaf_count: usize,
+    /// Leaf index and credential of every member
+    pub members: Vec<(LeafIndex, Credential)>,
+}
+
+impl TreeSummary {
+    /// Get the number of members in the tree.
+    pub fn member_count(&self) -> usize {
+        self.members.len()
+    }
+}
+
+/// Verify an exported tree without being a member of the group, e.g. on a
+/// delivery service that stores the public tree. Checks the structure of the
+/// tree, the parent hashes, that the signature key of every leaf matches its
+/// credential, the signature of every leaf and that no leaf has expired. The
+/// tree hash has to match the one in `group_context`.
+pub fn verify_exported_tree(
+    ciphersuite: &Ciphersuite,
+    nodes: &[Option<Node>],
+    group_context: &GroupContext,
+) -> Result<TreeSummary, TreeValidationError> {
+    verify_exported_tree_at(ciphersuite, nodes, group_context, &SystemTimeProvider)
+}
+
+/// Same as `verify_exported_tree`, but checks the lifetime of the leaves
+/// against the current time of `time_provider`.
+pub fn verify_exported_tree_at(
+    ciphersuite: &Ciphersuite,
+    nodes: &[Option<Node>],
+    group_context: &GroupContext,
+    time_provider: &dyn TimeProvider,
+) -> Result<TreeSummary, TreeValidationError> {
+    if nodes.len() % 2 != 1 {
+        return Err(TreeValidationError::InvalidStructure);
+    }
+    // All nodes need node indices
+    let leaf_count =
+        LeafIndex::leaf_count(nodes.len()).map_err(|_| TreeValidationError::InvalidStructure)?;
+    let now = time_provider.now();
+    let mut members = vec![];
+    for (i, node) in nodes.iter().enumerate() {
+        let node = match node {
+            Some(node) => node,
+            None => continue,
+        };
+        if i % 2 == 1 {
+            let parent_node = match (node.node_type, &node.node, &node.leaf_node) {
+                (NodeType::Parent, Some(parent_node), None) => parent_node,
+                _ => return Err(TreeValidationError::InvalidStructure),
+            };
+            // Unmerged leaves have to be leaves of the tree
+            if parent_node
+                .get_unmerged_leaves()
+                .iter()
+                .any(|&leaf| leaf % 2 != 0 || leaf as usize >= nodes.len())
+            {
+                return Err(TreeValidationError::InvalidStructure);
+            }
+            continue;
+        }
+        let leaf_node = match (node.node_type, &node.leaf_node, &node.node) {
+            (NodeType::Leaf, Some(leaf_node), None) => leaf_node,
+            _ => return Err(TreeValidationError::InvalidStructure),
+        };
+        let Credential::Basic(basic_credential) = leaf_node.get_credential();
+        if &basic_credential.public_key != leaf_node.get_signature_key() {
+            return Err(TreeValidationError::InvalidLeafCredential);
+        }
+        if !leaf_node.verify() {
+            return Err(TreeValidationError::InvalidLeafSignature);
+        }
+        if let Some(ExtensionPayload::Lifetime(lifetime_extension)) =
+            leaf_node.get_extension(ExtensionType::Lifetime)
+        {
+            if !lifetime_extension.is_valid_at(now) {
+                return Err(TreeValidationError::ExpiredLeaf);
+            }
+        }
+        members.push((
+            LeafIndex::try_from(i / 2).map_err(|_| TreeValidationError::InvalidStructure)?,
+            leaf_node.get_credential().clone(),
+        ));
+    }
+    if !RatchetTree::verify_integrity(ciphersuite, nodes) {
+        return Err(TreeValidationError::ParentHashMismatch);
+    }
+    let tree_hash =
+        public_tree_hash(ciphersuite, nodes).ok_or(TreeValidationError::InvalidStructure)?;
+    if tree_hash != group_context.tree_hash {
+        return Err(TreeValidationError::TreeHashMismatch);
+    }
+    Ok(TreeSummary {
+        tree_hash,
+        leaf_count: leaf_count as usize,
+        members,
+    })
+}
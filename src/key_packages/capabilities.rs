@@ -0,0 +1,121 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+use crate::key_packages::*;
+
+use thiserror::Error;
+
+/// Configuration for a new group that all candidate members support.
+#[derive(Debug, PartialEq)]
+pub struct GroupConfiguration {
+    pub version: ProtocolVersion,
+    pub ciphersuite: CiphersuiteName,
+    pub extensions: Vec<ExtensionType>,
+}
+
+/// Errors that can occur when a group configuration is negotiated.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum CapabilityError {
+    /// No key packages were given
+    #[error("no key packages were given")]
+    NoCandidates,
+    /// The key packages at these indices don't support the configuration that
+    /// is supported by most candidates
+    #[error("the key packages at {0:?} don't support the common configuration")]
+    IncompatibleMembers(Vec<usize>),
+}
+
+/// Compute the protocol versions, ciphersuites and extensions that are
+/// supported by this implementation and by all `key_packages`. The order of
+/// the supported values of this implementation is kept.
+pub fn intersect_capabilities(key_packages: &[KeyPackage]) -> CapabilitiesExtension {
+    let capabilities: Vec<CapabilitiesExtension> = key_packages
+        .iter()
+        .map(|kp| kp.get_capabilities())
+        .collect();
+    let versions = SUPPORTED_PROTOCOL_VERSIONS
+        .iter()
+        .filter(|v| capabilities.iter().all(|c| c.versions.contains(v)))
+        .cloned()
+        .collect();
+    let ciphersuites = CIPHERSUITES
+        .iter()
+        .filter(|cs| capabilities.iter().all(|c| c.ciphersuites.contains(cs)))
+        .cloned()
+        .collect();
+    let extensions = SUPPORTED_EXTENSIONS
+        .iter()
+        .filter(|e| capabilities.iter().all(|c| c.extensions.contains(e)))
+        .cloned()
+        .collect();
+    CapabilitiesExtension::new(versions, ciphersuites, extensions)
+}
+
+/// Recommend a configuration for a group with the members of `key_packages`.
+/// The highest common protocol version and the first common ciphersuite in
+/// the order of preference of this implementation are chosen.
+/// If there is no common configuration, the indices of the key packages that
+/// don't support the configuration with the widest support are returned.
+/// They have to be left out or provide other key packages.
+pub fn recommend_group_configuration(
+    key_packages: &[KeyPackage],
+) -> Result<GroupConfiguration, CapabilityError> {
+    if key_packages.is_empty() {
+        return Err(CapabilityError::NoCandidates);
+    }
+    let intersection = intersect_capabilities(key_packages);
+    let version = intersection.versions.iter().max();
+    if let (Some(version), Some(ciphersuite)) = (version, intersection.ciphersuites.first()) {
+        return Ok(GroupConfiguration {
+            version: *version,
+            ciphersuite: *ciphersuite,
+            extensions: intersection.extensions,
+        });
+    }
+
+    let capabilities: Vec<CapabilitiesExtension> = key_packages
+        .iter()
+        .map(|kp| kp.get_capabilities())
+        .collect();
+    let supports = |c: &CapabilitiesExtension, v: &ProtocolVersion, cs: &CiphersuiteName| {
+        c.versions.contains(v) && c.ciphersuites.contains(cs)
+    };
+    let mut versions = SUPPORTED_PROTOCOL_VERSIONS.to_vec();
+    versions.sort_by(|a, b| b.cmp(a));
+    let mut best = None;
+    let mut best_count = 0;
+    for version in versions.iter() {
+        for ciphersuite in CIPHERSUITES.iter() {
+            let count = capabilities
+                .iter()
+                .filter(|c| supports(c, version, ciphersuite))
+                .count();
+            if best.is_none() || count > best_count {
+                best = Some((version, ciphersuite));
+                best_count = count;
+            }
+        }
+    }
+    let (version, ciphersuite) = best.unwrap();
+    let incompatible = capabilities
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| !supports(c, version, ciphersuite))
+        .map(|(i, _)| i)
+        .collect();
+    Err(CapabilityError::IncompatibleMembers(incompatible))
+}
@@ -1,5 +1,16 @@
 use crate::key_packages::*;
 
+impl Codec for KeyPackageRef {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        encode_vec(VecSize::VecU8, buffer, &self.0)?;
+        Ok(())
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        Ok(KeyPackageRef(decode_vec(VecSize::VecU8, cursor)?))
+    }
+}
+
 impl Codec for KeyPackage {
     fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
         buffer.append(&mut self.unsigned_payload()?);
@@ -8,71 +19,29 @@ impl Codec for KeyPackage {
     }
 
     fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let kp = KeyPackage::decode_unvalidated(cursor)?;
+        kp.validate().map_err(|_| CodecError::DecodingError)?;
+        Ok(kp)
+    }
+}
+
+impl KeyPackage {
+    /// Decode a key package without validating it.
+    pub(crate) fn decode_unvalidated(cursor: &mut Cursor) -> Result<Self, CodecError> {
         let protocol_version = ProtocolVersion::decode(cursor)?;
         let cipher_suite = Ciphersuite::decode(cursor)?;
         let hpke_init_key = HPKEPublicKey::decode(cursor)?;
         let credential = Credential::decode(cursor)?;
         let extensions = decode_vec(VecSize::VecU16, cursor)?;
         let signature = Signature::decode(cursor)?;
-        let kp = KeyPackage {
+        Ok(KeyPackage {
             protocol_version,
             cipher_suite,
             hpke_init_key,
             credential,
             extensions,
             signature,
-        };
-
-        // TODO: check extensions
-
-        let mut extensions = kp.extensions.clone();
-        extensions.dedup();
-        if kp.extensions.len() != extensions.len() {
-            return Err(CodecError::DecodingError);
-        }
-
-        for e in extensions.iter() {
-            match e.extension_type {
-                ExtensionType::Capabilities => {
-                    let capabilities_extension =
-                        CapabilitiesExtension::new_from_bytes(&e.extension_data);
-                    for v in capabilities_extension.versions.iter() {
-                        if *v > CURRENT_PROTOCOL_VERSION {
-                            return Err(CodecError::DecodingError);
-                        }
-                    }
-                    if !capabilities_extension
-                        .ciphersuites
-                        .contains(&CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519)
-                    {
-                        return Err(CodecError::DecodingError);
-                    }
-                }
-                ExtensionType::Lifetime => {
-                    let lifetime_extension = LifetimeExtension::new_from_bytes(&e.extension_data);
-                    if lifetime_extension.is_expired() {
-                        return Err(CodecError::DecodingError);
-                    }
-                }
-                ExtensionType::KeyID => {
-                    let _key_id_extension = KeyIDExtension::new_from_bytes(&e.extension_data);
-                }
-                ExtensionType::ParentHash => {
-                    let _parent_hash_extension =
-                        ParentHashExtension::new_from_bytes(&e.extension_data);
-                }
-                ExtensionType::RatchetTree => {}
-                ExtensionType::Invalid => {}
-                ExtensionType::Default => {}
-            }
-        }
-
-        for _ in 0..kp.extensions.len() {}
-
-        if !kp.verify() {
-            return Err(CodecError::DecodingError);
-        }
-        Ok(kp)
+        })
     }
 }
 
@@ -92,3 +61,32 @@ impl Codec for KeyPackageBundle {
         })
     }
 }
+
+impl Codec for KeyPackagePublication {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        buffer.append(&mut self.unsigned_payload()?);
+        self.signature.encode(buffer)?;
+        Ok(())
+    }
+
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let key_package = KeyPackage::decode(cursor)?;
+        let user_id = decode_vec(VecSize::VecU16, cursor)?;
+        let device_id = decode_vec(VecSize::VecU16, cursor)?;
+        let expiry = u64::decode(cursor)?;
+        let last_resort = match u8::decode(cursor)? {
+            0 => false,
+            1 => true,
+            _ => return Err(CodecError::DecodingError),
+        };
+        let signature = Signature::decode(cursor)?;
+        Ok(KeyPackagePublication {
+            key_package,
+            user_id,
+            device_id,
+            expiry,
+            last_resort,
+            signature,
+        })
+    }
+}
@@ -0,0 +1,81 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+use crate::creds::*;
+use crate::extensions::*;
+use crate::key_packages::*;
+
+use std::future::Future;
+use std::pin::Pin;
+use thiserror::Error;
+
+/// Errors that can occur when key packages are fetched from a directory.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum KeyPackageFetchError {
+    #[error("the directory could not be reached")]
+    DirectoryUnavailable,
+    #[error("the directory doesn't know the user")]
+    UnknownUser,
+    #[error("no key package of the user meets the requirements")]
+    NoSuitableKeyPackage,
+}
+
+/// Future that resolves to the `KeyPackagePublication`s of a user.
+pub type KeyPackageFetch<'a> = Pin<
+    Box<dyn Future<Output = Result<Vec<KeyPackagePublication>, KeyPackageFetchError>> + Send + 'a>,
+>;
+
+/// Directory that key packages of prospective members can be fetched from,
+/// e.g. over the network. The crate doesn't depend on an async runtime, the
+/// futures are driven by the caller's executor.
+pub trait KeyPackageFetcher: Send + Sync {
+    /// Fetch the publications of all devices of the user `user_id`.
+    fn fetch<'a>(&'a self, user_id: &'a [u8]) -> KeyPackageFetch<'a>;
+}
+
+/// Fetch the publications of every user in `user_ids` and select one key
+/// package per user with `select_key_package`. Publications of other users
+/// than the requested one are ignored. The user ID of a publication is only
+/// asserted by its signer, a publication is therefore only considered if the
+/// identity of the key package's credential is the requested user ID.
+pub async fn fetch_key_packages(
+    fetcher: &dyn KeyPackageFetcher,
+    user_ids: &[&[u8]],
+    requirements: &KeyPackageRequirements,
+    time_provider: &dyn TimeProvider,
+) -> Result<Vec<KeyPackage>, KeyPackageFetchError> {
+    let mut key_packages = Vec::with_capacity(user_ids.len());
+    for user_id in user_ids {
+        let publications: Vec<KeyPackagePublication> = fetcher
+            .fetch(user_id)
+            .await?
+            .into_iter()
+            .filter(|publication| {
+                publication.get_user_id() == *user_id
+                    && match publication.get_key_package().get_credential() {
+                        Credential::Basic(basic_credential) => {
+                            basic_credential.identity == *user_id
+                        }
+                    }
+            })
+            .collect();
+        let publication = select_key_package(&publications, requirements, time_provider)
+            .ok_or(KeyPackageFetchError::NoSuitableKeyPackage)?;
+        key_packages.push(publication.get_key_package().clone());
+    }
+    Ok(key_packages)
+}
@@ -0,0 +1,161 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+use crate::group::*;
+use crate::key_packages::*;
+use crate::messages::*;
+use crate::tree::node::*;
+
+use std::collections::HashMap;
+
+/// Hook that is called when fresh key packages should be generated and
+/// published. The argument is the number of key packages that are missing.
+pub type ReplenishHook = Box<dyn FnMut(usize) + Send>;
+
+/// Events of a `KeyPackageManager` that the application has to act on.
+#[derive(Debug, PartialEq, Clone)]
+pub enum KeyPackageEvent {
+    /// A group was joined with the referenced key package and its bundle was
+    /// deleted. The key package should be removed from the directory it was
+    /// published in.
+    KeyPackageConsumed(KeyPackageRef),
+}
+
+/// Hook that is called for every `KeyPackageEvent`.
+pub type EventHook = Box<dyn FnMut(&KeyPackageEvent) + Send>;
+
+/// Keeps track of the `KeyPackageBundle`s a client has published.
+/// Bundles are removed when they are consumed by a `Welcome` or when their
+/// `LifetimeExtension` expired. Whenever fewer than `target` bundles are left,
+/// the replenish hook is called.
+pub struct KeyPackageManager {
+    bundles: HashMap<KeyPackageRef, KeyPackageBundle>,
+    target: usize,
+    replenish_hook: ReplenishHook,
+    event_hook: Option<EventHook>,
+}
+
+impl KeyPackageManager {
+    /// Create a new `KeyPackageManager` that tries to keep `target` bundles
+    /// available.
+    pub fn new(target: usize, replenish_hook: ReplenishHook) -> Self {
+        Self {
+            bundles: HashMap::new(),
+            target,
+            replenish_hook,
+            event_hook: None,
+        }
+    }
+
+    /// Set the hook that is called for every `KeyPackageEvent`.
+    pub fn set_event_hook(&mut self, event_hook: EventHook) {
+        self.event_hook = Some(event_hook);
+    }
+
+    /// Track a newly issued `KeyPackageBundle`.
+    pub fn add(&mut self, key_package_bundle: KeyPackageBundle) {
+        let key_package_ref = key_package_bundle.get_key_package().key_package_ref();
+        self.bundles.insert(key_package_ref, key_package_bundle);
+    }
+
+    /// Get the bundle of the referenced key package.
+    pub fn get(&self, key_package_ref: &KeyPackageRef) -> Option<&KeyPackageBundle> {
+        self.bundles.get(key_package_ref)
+    }
+
+    /// Remove the bundle of the referenced key package and return it.
+    pub fn consume(&mut self, key_package_ref: &KeyPackageRef) -> Option<KeyPackageBundle> {
+        let key_package_bundle = self.bundles.remove(key_package_ref);
+        if key_package_bundle.is_some() {
+            self.check_replenish();
+        }
+        key_package_bundle
+    }
+
+    /// Find the bundle that was used to invite us with `welcome`, remove it and
+    /// return it so it can be used to join the group.
+    pub fn consume_for_welcome(&mut self, welcome: &Welcome) -> Option<KeyPackageBundle> {
+        let key_package_ref = self.find_for_welcome(welcome)?;
+        self.consume(&key_package_ref)
+    }
+
+    /// Join a group from `welcome` with the bundle that was used to invite us.
+    /// Only if the join succeeds, the bundle and with it the init private key
+    /// are deleted for forward secrecy and a
+    /// `KeyPackageEvent::KeyPackageConsumed` is fired. If the join fails, the
+    /// bundle is kept, so that an invalid `Welcome` can't use it up.
+    pub fn join_from_welcome(
+        &mut self,
+        welcome: Welcome,
+        ratchet_tree: Option<Vec<Option<Node>>>,
+    ) -> Result<MlsGroup, WelcomeError> {
+        let key_package_ref = self
+            .find_for_welcome(&welcome)
+            .ok_or(WelcomeError::JoinerSecretNotFound)?;
+        let key_package_bundle = self.bundles[&key_package_ref].clone();
+        let group = MlsGroup::new_from_welcome(welcome, ratchet_tree, key_package_bundle)?;
+        self.consume(&key_package_ref);
+        if let Some(event_hook) = &mut self.event_hook {
+            event_hook(&KeyPackageEvent::KeyPackageConsumed(key_package_ref));
+        }
+        Ok(group)
+    }
+
+    /// Remove all bundles whose `LifetimeExtension` doesn't include the current
+    /// time of `time_provider`.
+    pub fn remove_expired(&mut self, time_provider: &dyn TimeProvider) {
+        let now = time_provider.now();
+        let count = self.bundles.len();
+        self.bundles.retain(|_, kpb| {
+            match kpb.get_key_package().get_extension(ExtensionType::Lifetime) {
+                Some(ExtensionPayload::Lifetime(lifetime_extension)) => {
+                    lifetime_extension.is_valid_at(now)
+                }
+                _ => true,
+            }
+        });
+        if self.bundles.len() != count {
+            self.check_replenish();
+        }
+    }
+
+    /// Get the number of tracked bundles.
+    pub fn len(&self) -> usize {
+        self.bundles.len()
+    }
+
+    /// Returns `true` if no bundles are tracked.
+    pub fn is_empty(&self) -> bool {
+        self.bundles.is_empty()
+    }
+
+    /// Find the reference of the tracked key package `welcome` was sent to.
+    fn find_for_welcome(&self, welcome: &Welcome) -> Option<KeyPackageRef> {
+        welcome
+            .secrets
+            .iter()
+            .map(|egs| &egs.key_package_ref)
+            .find(|key_package_ref| self.bundles.contains_key(*key_package_ref))
+            .cloned()
+    }
+
+    /// Call the replenish hook if fewer than `target` bundles are left.
+    fn check_replenish(&mut self) {
+        if self.bundles.len() < self.target {
+            (self.replenish_hook)(self.target - self.bundles.len());
+        }
+    }
+}
@@ -19,10 +19,21 @@ use crate::codec::*;
 use crate::creds::*;
 use crate::extensions::*;
 
+use thiserror::Error;
+
+mod capabilities;
 mod codec;
+mod fetcher;
+mod manager;
+mod selection;
 
 mod test_key_packages;
 
+pub use capabilities::*;
+pub use fetcher::*;
+pub use manager::*;
+pub use selection::*;
+
 // This implementation currently supports the following
 pub(crate) const CIPHERSUITES: &[CiphersuiteName] = &[
     CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519,
@@ -30,6 +41,49 @@ pub(crate) const CIPHERSUITES: &[CiphersuiteName] = &[
 ];
 pub(crate) const SUPPORTED_PROTOCOL_VERSIONS: &[ProtocolVersion] = &[CURRENT_PROTOCOL_VERSION];
 pub(crate) const SUPPORTED_EXTENSIONS: &[ExtensionType] = &[ExtensionType::Lifetime];
+/// Extensions every `KeyPackage` must contain. A `Lifetime` is only required
+/// if the `LifetimeConfig` of the validation asks for it.
+pub(crate) const REQUIRED_EXTENSIONS: &[ExtensionType] = &[ExtensionType::Capabilities];
+
+/// Errors that can occur when a `KeyPackage` is validated.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum KeyPackageError {
+    #[error("the signature of the key package is invalid")]
+    InvalidSignature,
+    #[error("the protocol version of the key package is not supported")]
+    UnsupportedProtocolVersion,
+    #[error("the ciphersuite of the key package is not supported")]
+    UnsupportedCiphersuite,
+    #[error("the lifetime of the key package expired")]
+    Expired,
+    #[error("an extension of the key package is invalid")]
+    Extension(#[from] ExtensionError),
+    #[error("the key package could not be decoded")]
+    Malformed,
+    #[error("the signature of the key package publication is invalid")]
+    InvalidPublicationSignature,
+}
+
+impl From<CodecError> for KeyPackageError {
+    fn from(_: CodecError) -> KeyPackageError {
+        KeyPackageError::Malformed
+    }
+}
+
+/// Reference to a `KeyPackage`, the hash of its encoding. It identifies the
+/// recipients of a `Welcome` and the bundles of a client's own key packages.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct KeyPackageRef(Vec<u8>);
+
+impl KeyPackageRef {
+    pub fn from_slice(bytes: &[u8]) -> Self {
+        KeyPackageRef(bytes.to_vec())
+    }
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct KeyPackage {
@@ -60,54 +114,194 @@ impl KeyPackage {
             extensions: extensions.to_vec(),
             signature: Signature::new_empty(),
         };
-        let payload = &key_package.unsigned_payload().unwrap();
-
-        key_package.signature = ciphersuite.sign(signature_key, payload).unwrap();
+        key_package.signature = key_package.sign(&ciphersuite, signature_key);
         key_package
     }
 
     /// Verify that the signature on this key package is valid.
     pub(crate) fn verify(&self) -> bool {
-        self.credential
-            .verify(&self.unsigned_payload().unwrap(), &self.signature)
+        self.verify_signature(&self.credential, &self.signature)
+    }
+
+    /// Validate the key package against the system clock, see
+    /// `validate_with_time`.
+    pub fn validate(&self) -> Result<(), KeyPackageError> {
+        self.validate_with_time(&SystemTimeProvider)
+    }
+
+    /// Validate the key package. The signature has to be valid, the
+    /// extensions well-formed and unique, and the required `Capabilities`
+    /// extension present and supported. If the key package has a `Lifetime`
+    /// extension, it has to include the current time of `time_provider`.
+    pub fn validate_with_time(
+        &self,
+        time_provider: &dyn TimeProvider,
+    ) -> Result<(), KeyPackageError> {
+        self.validate_at(time_provider.now(), 0, false)
     }
 
-    /// Compute the hash of the encoding of this key package.
-    pub(crate) fn hash(&self) -> Vec<u8> {
+    /// Validate the key package like `validate_with_time`, with the clock and
+    /// the acceptance margin of `lifetime_config`. The `Lifetime` extension
+    /// is required if `lifetime_config` says so.
+    pub fn validate_with_config(
+        &self,
+        lifetime_config: &LifetimeConfig,
+    ) -> Result<(), KeyPackageError> {
+        self.validate_at(
+            lifetime_config.get_time_provider().now(),
+            lifetime_config.get_acceptance_margin(),
+            lifetime_config.get_require_lifetime(),
+        )
+    }
+
+    fn validate_at(
+        &self,
+        now: u64,
+        acceptance_margin: u64,
+        require_lifetime: bool,
+    ) -> Result<(), KeyPackageError> {
+        if !self.verify() {
+            return Err(KeyPackageError::InvalidSignature);
+        }
+        validate_extensions(&self.extensions, REQUIRED_EXTENSIONS)?;
+        if require_lifetime
+            && !self
+                .extensions
+                .iter()
+                .any(|e| e.extension_type == ExtensionType::Lifetime)
+        {
+            return Err(ExtensionError::MissingExtension.into());
+        }
+        if let Some(ExtensionPayload::Capabilities(capabilities_extension)) =
+            self.get_extension(ExtensionType::Capabilities)
+        {
+            if capabilities_extension
+                .versions
+                .iter()
+                .any(|v| *v > CURRENT_PROTOCOL_VERSION)
+            {
+                return Err(KeyPackageError::UnsupportedProtocolVersion);
+            }
+            if !capabilities_extension
+                .ciphersuites
+                .contains(&self.cipher_suite.get_name())
+            {
+                return Err(KeyPackageError::UnsupportedCiphersuite);
+            }
+        }
+        if let Some(ExtensionPayload::Lifetime(lifetime_extension)) =
+            self.get_extension(ExtensionType::Lifetime)
+        {
+            if !lifetime_extension.is_valid_at_with_margin(now, acceptance_margin) {
+                return Err(KeyPackageError::Expired);
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode the key package in the MLS wire format, e.g. to publish it to a
+    /// directory that is shared with other MLS implementations.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, CodecError> {
+        self.encode_detached()
+    }
+
+    /// Decode and validate a key package against the system clock, see
+    /// `from_bytes_with_time`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, KeyPackageError> {
+        Self::from_bytes_with_time(bytes, &SystemTimeProvider)
+    }
+
+    /// Decode a key package that may have been created by another
+    /// implementation and validate it with `validate_with_time`. Unlike
+    /// `Codec::decode`, trailing bytes, protocol versions and ciphersuites
+    /// this crate doesn't support and init keys of the wrong length are
+    /// rejected. The key package can be added to a group like one created by
+    /// this crate.
+    pub fn from_bytes_with_time(
+        bytes: &[u8],
+        time_provider: &dyn TimeProvider,
+    ) -> Result<Self, KeyPackageError> {
+        let key_package = Self::decode_foreign(bytes)?;
+        key_package.validate_with_time(time_provider)?;
+        Ok(key_package)
+    }
+
+    /// Decode a key package like `from_bytes_with_time` and validate it with
+    /// `validate_with_config`.
+    pub fn from_bytes_with_config(
+        bytes: &[u8],
+        lifetime_config: &LifetimeConfig,
+    ) -> Result<Self, KeyPackageError> {
+        let key_package = Self::decode_foreign(bytes)?;
+        key_package.validate_with_config(lifetime_config)?;
+        Ok(key_package)
+    }
+
+    // Decode a key package of another implementation and check everything
+    // `Codec::decode` doesn't
+    fn decode_foreign(bytes: &[u8]) -> Result<Self, KeyPackageError> {
+        let cursor = &mut Cursor::new(bytes);
+        let key_package = KeyPackage::decode_unvalidated(cursor)?;
+        if cursor.has_more() {
+            return Err(KeyPackageError::Malformed);
+        }
+        if !SUPPORTED_PROTOCOL_VERSIONS.contains(&key_package.protocol_version) {
+            return Err(KeyPackageError::UnsupportedProtocolVersion);
+        }
+        let Credential::Basic(basic_credential) = &key_package.credential;
+        if !CIPHERSUITES.contains(&key_package.cipher_suite.get_name())
+            || !CIPHERSUITES.contains(&basic_credential.ciphersuite.get_name())
+        {
+            return Err(KeyPackageError::UnsupportedCiphersuite);
+        }
+        let hpke_init_key = key_package.hpke_init_key.as_slice();
+        if hpke_init_key.is_empty() {
+            return Err(KeyPackageError::Malformed);
+        }
+        if let Some(length) = key_package.cipher_suite.hpke_public_key_length() {
+            if hpke_init_key.len() != length {
+                return Err(KeyPackageError::Malformed);
+            }
+        }
+        Ok(key_package)
+    }
+
+    /// Compute the hash of the encoding of this key package with the hash
+    /// function of its ciphersuite.
+    pub fn hash(&self) -> Vec<u8> {
         let bytes = self.encode_detached().unwrap();
         self.cipher_suite.hash(&bytes)
     }
 
+    /// Get the `KeyPackageRef` of this key package.
+    pub fn key_package_ref(&self) -> KeyPackageRef {
+        KeyPackageRef(self.hash())
+    }
+
     /// Get the extension of `extension_type`.
-    /// Returns `Some(extension)` if present and `None` if the extension is not present.
+    /// Returns `Some(extension)` if present and `None` if the extension is not
+    /// present or its data is malformed.
     pub fn get_extension(&self, extension_type: ExtensionType) -> Option<ExtensionPayload> {
-        for e in &self.extensions {
-            if e.get_type() == extension_type {
-                match extension_type {
-                    ExtensionType::Capabilities => {
-                        let capabilities_extension =
-                            CapabilitiesExtension::new_from_bytes(&e.extension_data);
-                        return Some(ExtensionPayload::Capabilities(capabilities_extension));
-                    }
-                    ExtensionType::Lifetime => {
-                        let lifetime_extension =
-                            LifetimeExtension::new_from_bytes(&e.extension_data);
-                        return Some(ExtensionPayload::Lifetime(lifetime_extension));
-                    }
-                    ExtensionType::KeyID => {
-                        let key_id_extension = KeyIDExtension::new_from_bytes(&e.extension_data);
-                        return Some(ExtensionPayload::KeyID(key_id_extension));
-                    }
-                    ExtensionType::ParentHash => {
-                        let parent_hash_extension =
-                            ParentHashExtension::new_from_bytes(&e.extension_data);
-                        return Some(ExtensionPayload::ParentHash(parent_hash_extension));
-                    }
-                    _ => return None,
-                }
-            }
+        get_extension_payload(&self.extensions, extension_type)
+    }
+
+    /// Get the capabilities of the key package. If the key package has no
+    /// `CapabilitiesExtension`, only its own protocol version and ciphersuite
+    /// are assumed.
+    pub fn get_capabilities(&self) -> CapabilitiesExtension {
+        match self.get_extension(ExtensionType::Capabilities) {
+            Some(ExtensionPayload::Capabilities(capabilities_extension)) => capabilities_extension,
+            _ => CapabilitiesExtension::new(
+                vec![CURRENT_PROTOCOL_VERSION],
+                vec![self.cipher_suite.get_name()],
+                vec![],
+            ),
         }
-        None
+    }
+
+    /// Re-sign the key package after it was modified.
+    pub(crate) fn update_signature(&mut self, signature_key: &SignaturePrivateKey) {
+        self.signature = self.sign(&self.cipher_suite.clone(), signature_key);
     }
 
     /// Add (or replace) an extension to the KeyPackage.
@@ -136,9 +330,26 @@ impl KeyPackage {
     pub(crate) fn get_cipher_suite(&self) -> &Ciphersuite {
         &self.cipher_suite
     }
+
+    /// Get a reference to the extensions.
+    pub(crate) fn get_extensions(&self) -> &[Extension] {
+        &self.extensions
+    }
+
+    /// Get the protocol version.
+    pub(crate) fn get_protocol_version(&self) -> ProtocolVersion {
+        self.protocol_version
+    }
+
+    /// Get a reference to the signature.
+    pub(crate) fn get_signature(&self) -> &Signature {
+        &self.signature
+    }
 }
 
 impl Signable for KeyPackage {
+    const SIGNATURE_LABEL: &'static str = "KeyPackageTBS";
+
     fn unsigned_payload(&self) -> Result<Vec<u8>, CodecError> {
         let buffer = &mut Vec::new();
         self.protocol_version.encode(buffer)?;
@@ -158,7 +369,9 @@ pub struct KeyPackageBundle {
 
 impl KeyPackageBundle {
     /// Create a new `KeyPackageBundle` for the given `ciphersuite`, `identity`,
-    /// and `extensions`.
+    /// and `extensions`. A `CapabilitiesExtension` is added unless
+    /// `extensions` contains one. The key package only gets a lifetime if
+    /// `extensions` contains a `LifetimeExtension`.
     /// This generates a fresh HPKE key pair for this bundle.
     ///
     /// Returns a new `KeyPackageBundle`.
@@ -194,9 +407,14 @@ impl KeyPackageBundle {
             CIPHERSUITES.to_vec(),
             SUPPORTED_EXTENSIONS.to_vec(),
         );
-        let mut final_extensions = vec![capabilities_extension.to_extension()];
-        if let Some(mut extensions) = extensions {
-            final_extensions.append(&mut extensions);
+        let mut final_extensions = extensions.unwrap_or_default();
+        let has_extension = |extensions: &[Extension], extension_type| {
+            extensions
+                .iter()
+                .any(|e| e.extension_type == extension_type)
+        };
+        if !has_extension(&final_extensions, ExtensionType::Capabilities) {
+            final_extensions.insert(0, capabilities_extension.to_extension());
         }
         let key_package = KeyPackage::new(
             *ciphersuite,
@@ -231,4 +449,143 @@ impl KeyPackageBundle {
     pub fn get_private_key(&self) -> &HPKEPrivateKey {
         &self.private_key
     }
+
+    /// Overwrite the private key with zeros.
+    pub(crate) fn wipe(&mut self) {
+        self.private_key.wipe();
+    }
+
+    /// Replace the `LifetimeExtension` of the key package with a new one
+    /// created with `lifetime_config`, i.e. starting at the current time of
+    /// its clock, and re-sign it. The HPKE init key is kept. Note that this
+    /// changes the hash of the key package.
+    pub fn update_lifetime(
+        &mut self,
+        signature_key: &SignaturePrivateKey,
+        lifetime_config: &LifetimeConfig,
+    ) {
+        self.update_extensions(
+            signature_key,
+            vec![LifetimeExtension::new_with_config(lifetime_config).to_extension()],
+        );
+    }
+
+    /// Add the given `extensions` to the key package, replacing existing
+    /// extensions of the same type, and re-sign it. The HPKE init key is
+    /// kept. Note that this changes the hash of the key package.
+    pub fn update_extensions(
+        &mut self,
+        signature_key: &SignaturePrivateKey,
+        extensions: Vec<Extension>,
+    ) {
+        for extension in extensions {
+            self.key_package.add_extension(extension);
+        }
+        self.key_package.update_signature(signature_key);
+    }
+}
+
+/// A `KeyPackage` together with the metadata a directory server needs to store
+/// and hand it out. The publication is signed with the signature key of the
+/// key package's credential.
+#[derive(Debug, PartialEq, Clone)]
+pub struct KeyPackagePublication {
+    key_package: KeyPackage,
+    user_id: Vec<u8>,
+    device_id: Vec<u8>,
+    expiry: u64,
+    last_resort: bool,
+    signature: Signature,
+}
+
+impl KeyPackagePublication {
+    /// Create a new signed `KeyPackagePublication` for `key_package`.
+    /// `expiry` is given in seconds since the UNIX epoch. Last resort key
+    /// packages are handed out by the server when no other ones are left.
+    pub fn new(
+        key_package: KeyPackage,
+        signature_key: &SignaturePrivateKey,
+        user_id: &[u8],
+        device_id: &[u8],
+        expiry: u64,
+        last_resort: bool,
+    ) -> Self {
+        let ciphersuite = *key_package.get_cipher_suite();
+        let mut publication = Self {
+            key_package,
+            user_id: user_id.to_vec(),
+            device_id: device_id.to_vec(),
+            expiry,
+            last_resort,
+            signature: Signature::new_empty(),
+        };
+        publication.signature = publication.sign(&ciphersuite, signature_key);
+        publication
+    }
+
+    /// Decode a `KeyPackagePublication` and verify it.
+    /// Returns `KeyPackageError::Malformed` if the publication can't be
+    /// decoded, `KeyPackageError::InvalidSignature` if the signature of the
+    /// key package is invalid and `KeyPackageError::InvalidPublicationSignature`
+    /// if the signature over the publication is invalid.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, KeyPackageError> {
+        let mut cursor = Cursor::new(bytes);
+        let publication = Self::decode(&mut cursor)?;
+        if !publication.key_package.verify() {
+            return Err(KeyPackageError::InvalidSignature);
+        }
+        if !publication.verify_signature(
+            publication.key_package.get_credential(),
+            &publication.signature,
+        ) {
+            return Err(KeyPackageError::InvalidPublicationSignature);
+        }
+        Ok(publication)
+    }
+
+    /// Verify the signatures on the publication and on the key package.
+    /// Both have to be made with the key package's credential.
+    pub fn verify(&self) -> bool {
+        self.key_package.verify()
+            && self.verify_signature(self.key_package.get_credential(), &self.signature)
+    }
+
+    /// Get a reference to the `KeyPackage`.
+    pub fn get_key_package(&self) -> &KeyPackage {
+        &self.key_package
+    }
+
+    /// Get the user ID.
+    pub fn get_user_id(&self) -> &[u8] {
+        &self.user_id
+    }
+
+    /// Get the device ID.
+    pub fn get_device_id(&self) -> &[u8] {
+        &self.device_id
+    }
+
+    /// Get the expiry in seconds since the UNIX epoch.
+    pub fn get_expiry(&self) -> u64 {
+        self.expiry
+    }
+
+    /// Returns `true` if this is a last resort key package.
+    pub fn is_last_resort(&self) -> bool {
+        self.last_resort
+    }
+}
+
+impl Signable for KeyPackagePublication {
+    const SIGNATURE_LABEL: &'static str = "KeyPackagePublicationTBS";
+
+    fn unsigned_payload(&self) -> Result<Vec<u8>, CodecError> {
+        let buffer = &mut Vec::new();
+        self.key_package.encode(buffer)?;
+        encode_vec(VecSize::VecU16, buffer, &self.user_id)?;
+        encode_vec(VecSize::VecU16, buffer, &self.device_id)?;
+        self.expiry.encode(buffer)?;
+        (self.last_resort as u8).encode(buffer)?;
+        Ok(buffer.to_vec())
+    }
 }
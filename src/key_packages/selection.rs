@@ -0,0 +1,58 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+use crate::key_packages::*;
+
+/// Requirements a prospective member's `KeyPackage` has to meet to be used
+/// to add the member to a group.
+pub struct KeyPackageRequirements {
+    pub ciphersuite: CiphersuiteName,
+    pub required_extensions: Vec<ExtensionType>,
+}
+
+/// Select the best of the `KeyPackagePublication`s a directory returned for
+/// a prospective member. Publications are skipped if their signatures are
+/// invalid, if they expired at the current time of `time_provider`, if the
+/// key package doesn't validate, uses another ciphersuite or doesn't support
+/// all required extensions.
+/// Of the remaining ones, regular key packages are preferred over last resort
+/// ones and the one with the latest expiry is chosen. Returns `None` if no
+/// publication meets the requirements.
+pub fn select_key_package<'a>(
+    publications: &'a [KeyPackagePublication],
+    requirements: &KeyPackageRequirements,
+    time_provider: &dyn TimeProvider,
+) -> Option<&'a KeyPackagePublication> {
+    let now = time_provider.now();
+    publications
+        .iter()
+        .filter(|publication| {
+            let key_package = publication.get_key_package();
+            let capabilities = key_package.get_capabilities();
+            publication.get_expiry() > now
+                && key_package.get_cipher_suite().get_name() == requirements.ciphersuite
+                && requirements
+                    .required_extensions
+                    .iter()
+                    .all(|e| capabilities.extensions.contains(e))
+                && publication.verify()
+                && key_package.validate_with_time(time_provider).is_ok()
+        })
+        // `max_by_key` returns the last maximum, keep the directory's order on
+        // ties
+        .rev()
+        .max_by_key(|publication| (!publication.is_last_resort(), publication.get_expiry()))
+}
@@ -35,3 +35,619 @@ fn test_codec() {
     // let kp = KeyPackage::decode(&mut Cursor::new(&enc)).unwrap();
     // assert_eq!(kpb.key_package, kp);
 }
+
+#[test]
+fn decode_unknown_ciphersuite() {
+    use crate::key_packages::*;
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let signature_keypair = ciphersuite.new_signature_keypair();
+    let identity =
+        Identity::new_with_keypair(ciphersuite, vec![1, 2, 3], signature_keypair.clone());
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let kpb = KeyPackageBundle::new(
+        &ciphersuite,
+        signature_keypair.get_private_key(),
+        credential,
+        None,
+    );
+    let bytes = kpb.get_key_package().encode_detached().unwrap();
+
+    // Unknown and unimplemented ciphersuites are decoding errors, not panics
+    for value in &[[0x00, 0x00], [0x00, 0x04], [0x00, 0x06], [0xff, 0xff]] {
+        let mut modified = bytes.clone();
+        modified[1..3].copy_from_slice(value);
+        assert_eq!(
+            KeyPackage::decode(&mut Cursor::new(&modified)).err(),
+            Some(CodecError::DecodingError)
+        );
+        assert_eq!(
+            Ciphersuite::decode(&mut Cursor::new(value)).err(),
+            Some(CodecError::DecodingError)
+        );
+    }
+}
+
+#[test]
+fn key_package_ref() {
+    use crate::key_packages::*;
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let signature_keypair = ciphersuite.new_signature_keypair();
+    let identity =
+        Identity::new_with_keypair(ciphersuite, vec![1, 2, 3], signature_keypair.clone());
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let new_kpb = || {
+        KeyPackageBundle::new(
+            &ciphersuite,
+            signature_keypair.get_private_key(),
+            credential.clone(),
+            None,
+        )
+    };
+    let key_package = new_kpb().get_key_package().clone();
+
+    // The reference is the hash of the key package
+    let key_package_ref = key_package.key_package_ref();
+    assert_eq!(key_package_ref.as_slice(), key_package.hash().as_slice());
+    assert_eq!(key_package_ref, key_package.clone().key_package_ref());
+    assert_ne!(
+        key_package_ref,
+        new_kpb().get_key_package().key_package_ref()
+    );
+
+    let encoded = key_package_ref.encode_detached().unwrap();
+    let decoded = KeyPackageRef::decode(&mut Cursor::new(&encoded)).unwrap();
+    assert_eq!(decoded, key_package_ref);
+}
+
+#[test]
+fn key_package_publication() {
+    use crate::key_packages::*;
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let signature_keypair = ciphersuite.new_signature_keypair();
+    let identity =
+        Identity::new_with_keypair(ciphersuite, vec![1, 2, 3], signature_keypair.clone());
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let kpb = KeyPackageBundle::new(
+        &ciphersuite,
+        signature_keypair.get_private_key(),
+        credential,
+        None,
+    );
+    let publication = KeyPackagePublication::new(
+        kpb.get_key_package().clone(),
+        signature_keypair.get_private_key(),
+        b"alice",
+        b"phone",
+        1_000_000,
+        true,
+    );
+    assert!(publication.verify());
+
+    // Parsing verifies the publication
+    let mut enc = publication.encode_detached().unwrap();
+    let decoded = KeyPackagePublication::from_bytes(&enc).unwrap();
+    assert_eq!(decoded, publication);
+    assert_eq!(decoded.get_user_id(), b"alice");
+    assert_eq!(decoded.get_device_id(), b"phone");
+    assert_eq!(decoded.get_expiry(), 1_000_000);
+    assert!(decoded.is_last_resort());
+
+    // Tampering with the metadata invalidates the signature
+    let last_resort_position =
+        enc.len() - publication.signature.encode_detached().unwrap().len() - 1;
+    enc[last_resort_position] = 0;
+    assert_eq!(
+        KeyPackagePublication::from_bytes(&enc),
+        Err(KeyPackageError::InvalidPublicationSignature)
+    );
+}
+
+#[test]
+fn key_package_manager() {
+    use crate::key_packages::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let signature_keypair = ciphersuite.new_signature_keypair();
+    let identity =
+        Identity::new_with_keypair(ciphersuite, vec![1, 2, 3], signature_keypair.clone());
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+
+    let missing = Arc::new(AtomicUsize::new(0));
+    let missing_hook = missing.clone();
+    let mut manager = KeyPackageManager::new(
+        2,
+        Box::new(move |n| missing_hook.store(n, Ordering::SeqCst)),
+    );
+    let mut hashes = vec![];
+    for _ in 0..2 {
+        let kpb = KeyPackageBundle::new(
+            &ciphersuite,
+            signature_keypair.get_private_key(),
+            credential.clone(),
+            Some(vec![LifetimeExtension::new(60).to_extension()]),
+        );
+        hashes.push(kpb.get_key_package().key_package_ref());
+        manager.add(kpb);
+    }
+
+    // Bundles that are still valid are kept
+    manager.remove_expired(&SystemTimeProvider);
+    assert_eq!(manager.len(), 2);
+    assert_eq!(missing.load(Ordering::SeqCst), 0);
+
+    // Consuming a bundle triggers the hook
+    assert!(manager.consume(&hashes[0]).is_some());
+    assert!(manager.get(&hashes[0]).is_none());
+    assert_eq!(missing.load(Ordering::SeqCst), 1);
+
+    // Bundles are removed once their lifetime ended
+    struct FixedTime(u64);
+    impl TimeProvider for FixedTime {
+        fn now(&self) -> u64 {
+            self.0
+        }
+    }
+    let later = FixedTime(SystemTimeProvider.now() + LifetimeExtension::LIFETIME_1_DAY);
+    assert_eq!(
+        manager
+            .get(&hashes[1])
+            .unwrap()
+            .get_key_package()
+            .validate_with_time(&later),
+        Err(KeyPackageError::Expired)
+    );
+    manager.remove_expired(&later);
+    assert!(manager.is_empty());
+    assert_eq!(missing.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn lifetime_validity() {
+    use crate::key_packages::*;
+    struct FixedTime(u64);
+    impl TimeProvider for FixedTime {
+        fn now(&self) -> u64 {
+            self.0
+        }
+    }
+    let lifetime = LifetimeExtension::new_with_margin(100, 10, &FixedTime(1_000));
+
+    // The lifetime is valid between its bounds, including the bounds
+    assert!(lifetime.is_valid_at(1_000));
+    assert!(lifetime.is_valid_at(990));
+    assert!(lifetime.is_valid_at(1_110));
+
+    // and invalid before and after
+    assert!(!lifetime.is_valid_at(989));
+    assert!(!lifetime.is_valid_at(1_111));
+}
+
+#[test]
+fn malformed_extensions() {
+    use crate::key_packages::*;
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let signature_keypair = ciphersuite.new_signature_keypair();
+    let identity =
+        Identity::new_with_keypair(ciphersuite, vec![1, 2, 3], signature_keypair.clone());
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let kpb = KeyPackageBundle::new(
+        &ciphersuite,
+        signature_keypair.get_private_key(),
+        credential,
+        None,
+    );
+
+    // A correctly signed key package with a truncated lifetime extension
+    let mut key_package = kpb.get_key_package().clone();
+    key_package.add_extension(Extension {
+        extension_type: ExtensionType::Lifetime,
+        extension_data: vec![0, 1, 2],
+    });
+    key_package.update_signature(signature_keypair.get_private_key());
+    assert!(key_package.verify());
+
+    assert_eq!(key_package.get_extension(ExtensionType::Lifetime), None);
+    let bytes = key_package.encode_detached().unwrap();
+    assert!(KeyPackage::decode(&mut Cursor::new(&bytes)).is_err());
+}
+
+#[test]
+fn validate_key_package() {
+    use crate::key_packages::*;
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let signature_keypair = ciphersuite.new_signature_keypair();
+    let identity =
+        Identity::new_with_keypair(ciphersuite, vec![1, 2, 3], signature_keypair.clone());
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let signature_key = signature_keypair.get_private_key();
+    let kpb = KeyPackageBundle::new(
+        &ciphersuite,
+        signature_key,
+        credential.clone(),
+        Some(vec![LifetimeExtension::new(60).to_extension()]),
+    );
+
+    // Capabilities are added by default
+    assert_eq!(kpb.get_key_package().validate(), Ok(()));
+
+    // Duplicate extension types
+    let mut key_package = kpb.get_key_package().clone();
+    key_package
+        .extensions
+        .push(ParentHashExtension::new(&[1, 2, 3]).to_extension());
+    key_package
+        .extensions
+        .push(ParentHashExtension::new(&[4, 5, 6]).to_extension());
+    key_package.update_signature(signature_key);
+    assert_eq!(
+        key_package.validate(),
+        Err(KeyPackageError::Extension(
+            ExtensionError::DuplicateExtension
+        ))
+    );
+
+    // The lifetime extension is only required if the config asks for it
+    let mut key_package = kpb.get_key_package().clone();
+    key_package.remove_extension(ExtensionType::Lifetime);
+    key_package.update_signature(signature_key);
+    assert_eq!(key_package.validate(), Ok(()));
+    let mut lifetime_config = LifetimeConfig::new();
+    lifetime_config.set_require_lifetime(true);
+    assert_eq!(
+        key_package.validate_with_config(&lifetime_config),
+        Err(KeyPackageError::Extension(ExtensionError::MissingExtension))
+    );
+    assert_eq!(
+        kpb.get_key_package().validate_with_config(&lifetime_config),
+        Ok(())
+    );
+
+    // Missing capabilities extension
+    let mut key_package = kpb.get_key_package().clone();
+    key_package.remove_extension(ExtensionType::Capabilities);
+    key_package.update_signature(signature_key);
+    assert_eq!(
+        key_package.validate(),
+        Err(KeyPackageError::Extension(ExtensionError::MissingExtension))
+    );
+
+    // The key package's own ciphersuite has to be supported
+    let capabilities_extension = CapabilitiesExtension::new(
+        SUPPORTED_PROTOCOL_VERSIONS.to_vec(),
+        vec![CiphersuiteName::MLS10_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519],
+        SUPPORTED_EXTENSIONS.to_vec(),
+    );
+    let kpb = KeyPackageBundle::new(
+        &ciphersuite,
+        signature_key,
+        credential,
+        Some(vec![capabilities_extension.to_extension()]),
+    );
+    assert_eq!(
+        kpb.get_key_package().validate(),
+        Err(KeyPackageError::UnsupportedCiphersuite)
+    );
+
+    // A modified key package doesn't verify
+    let mut key_package = kpb.get_key_package().clone();
+    key_package.remove_extension(ExtensionType::Capabilities);
+    assert_eq!(
+        key_package.validate(),
+        Err(KeyPackageError::InvalidSignature)
+    );
+}
+
+#[test]
+fn update_key_package_bundle() {
+    use crate::key_packages::*;
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let signature_keypair = ciphersuite.new_signature_keypair();
+    let identity =
+        Identity::new_with_keypair(ciphersuite, vec![1, 2, 3], signature_keypair.clone());
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let signature_key = signature_keypair.get_private_key();
+    let mut kpb = KeyPackageBundle::new(
+        &ciphersuite,
+        signature_key,
+        credential,
+        Some(vec![LifetimeExtension::new(60).to_extension()]),
+    );
+    let init_key = kpb.get_key_package().get_hpke_init_key().clone();
+    let hash = kpb.get_key_package().hash();
+
+    // Extending the lifetime keeps the init key and re-signs
+    let tomorrow = SystemTimeProvider.now() + LifetimeExtension::LIFETIME_1_DAY;
+    assert!(
+        !match kpb.get_key_package().get_extension(ExtensionType::Lifetime) {
+            Some(ExtensionPayload::Lifetime(lifetime_extension)) => {
+                lifetime_extension.is_valid_at(tomorrow)
+            }
+            _ => false,
+        }
+    );
+    let mut lifetime_config = LifetimeConfig::new();
+    lifetime_config.set_lifetime(LifetimeExtension::LIFETIME_1_WEEK);
+    kpb.update_lifetime(signature_key, &lifetime_config);
+    assert!(
+        match kpb.get_key_package().get_extension(ExtensionType::Lifetime) {
+            Some(ExtensionPayload::Lifetime(lifetime_extension)) => {
+                lifetime_extension.is_valid_at(tomorrow)
+            }
+            _ => false,
+        }
+    );
+    assert_eq!(kpb.get_key_package().get_hpke_init_key(), &init_key);
+    assert_ne!(kpb.get_key_package().hash(), hash);
+    assert_eq!(kpb.get_key_package().validate(), Ok(()));
+
+    // The new lifetime starts at the time of the configured clock
+    struct FixedTime(u64);
+    impl TimeProvider for FixedTime {
+        fn now(&self) -> u64 {
+            self.0
+        }
+    }
+    lifetime_config.set_time_provider(std::sync::Arc::new(FixedTime(1_000)));
+    let mut backdated_kpb = kpb.clone();
+    backdated_kpb.update_lifetime(signature_key, &lifetime_config);
+    assert_eq!(
+        backdated_kpb
+            .get_key_package()
+            .get_extension(ExtensionType::Lifetime),
+        Some(ExtensionPayload::Lifetime(
+            LifetimeExtension::new_with_margin(
+                LifetimeExtension::LIFETIME_1_WEEK,
+                LifetimeExtension::LIFETIME_MARGIN,
+                &FixedTime(1_000),
+            )
+        ))
+    );
+    assert_eq!(
+        backdated_kpb.get_key_package().get_hpke_init_key(),
+        &init_key
+    );
+
+    // Other extensions are added or replaced
+    kpb.update_extensions(
+        signature_key,
+        vec![ParentHashExtension::new(&[1, 2, 3]).to_extension()],
+    );
+    assert_eq!(
+        kpb.get_key_package()
+            .get_extension(ExtensionType::ParentHash),
+        Some(ExtensionPayload::ParentHash(ParentHashExtension::new(&[
+            1, 2, 3
+        ])))
+    );
+    assert_eq!(kpb.get_key_package().get_hpke_init_key(), &init_key);
+    assert_eq!(kpb.get_key_package().validate(), Ok(()));
+}
+
+#[test]
+fn group_configuration() {
+    use crate::key_packages::*;
+    let aes = CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
+    let chacha = CiphersuiteName::MLS10_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519;
+    let key_package = |name: CiphersuiteName, ciphersuites: Vec<CiphersuiteName>| {
+        let ciphersuite = Ciphersuite::new(name);
+        let identity = Identity::new(ciphersuite, vec![1, 2, 3]);
+        let credential = Credential::Basic(BasicCredential::from(&identity));
+        let capabilities_extension = CapabilitiesExtension::new(
+            SUPPORTED_PROTOCOL_VERSIONS.to_vec(),
+            ciphersuites,
+            SUPPORTED_EXTENSIONS.to_vec(),
+        );
+        KeyPackageBundle::new(
+            &ciphersuite,
+            identity.get_signature_key_pair().get_private_key(),
+            credential,
+            Some(vec![capabilities_extension.to_extension()]),
+        )
+        .get_key_package()
+        .clone()
+    };
+    let alice = key_package(aes, CIPHERSUITES.to_vec());
+    let bob = key_package(chacha, vec![chacha]);
+    let charlie = key_package(aes, vec![aes]);
+
+    // The common ciphersuite is recommended
+    let key_packages = vec![alice.clone(), bob.clone()];
+    assert_eq!(
+        intersect_capabilities(&key_packages).ciphersuites,
+        vec![chacha]
+    );
+    assert_eq!(
+        recommend_group_configuration(&key_packages),
+        Ok(GroupConfiguration {
+            version: CURRENT_PROTOCOL_VERSION,
+            ciphersuite: chacha,
+            extensions: SUPPORTED_EXTENSIONS.to_vec(),
+        })
+    );
+
+    // Without a common ciphersuite, the members that don't support the most
+    // widely supported ciphersuite are reported
+    let key_packages = vec![alice, bob, charlie];
+    assert!(intersect_capabilities(&key_packages)
+        .ciphersuites
+        .is_empty());
+    assert_eq!(
+        recommend_group_configuration(&key_packages),
+        Err(CapabilityError::IncompatibleMembers(vec![1]))
+    );
+    assert_eq!(
+        recommend_group_configuration(&[]),
+        Err(CapabilityError::NoCandidates)
+    );
+}
+
+#[test]
+fn key_package_selection() {
+    use crate::key_packages::*;
+    let aes = CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
+    let chacha = CiphersuiteName::MLS10_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519;
+    let identity = Identity::new(Ciphersuite::new(aes), vec![1, 2, 3]);
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let signature_key = identity.get_signature_key_pair().get_private_key();
+    let now = SystemTimeProvider.now();
+    let publish = |name, extensions: Vec<ExtensionType>, expiry, last_resort| {
+        let capabilities_extension = CapabilitiesExtension::new(
+            SUPPORTED_PROTOCOL_VERSIONS.to_vec(),
+            vec![name],
+            extensions,
+        );
+        let kpb = KeyPackageBundle::new(
+            &Ciphersuite::new(name),
+            signature_key,
+            credential.clone(),
+            Some(vec![capabilities_extension.to_extension()]),
+        );
+        KeyPackagePublication::new(
+            kpb.get_key_package().clone(),
+            signature_key,
+            b"alice",
+            b"phone",
+            expiry,
+            last_resort,
+        )
+    };
+    let lifetime = vec![ExtensionType::Lifetime];
+    let publications = vec![
+        publish(chacha, lifetime.clone(), now + 300, false),
+        publish(aes, lifetime.clone(), now + 400, true),
+        publish(aes, vec![], now + 300, false),
+        publish(aes, lifetime.clone(), now - 1, false),
+        publish(aes, lifetime.clone(), now + 100, false),
+        publish(aes, lifetime.clone(), now + 200, false),
+        publish(aes, lifetime.clone(), now + 200, false),
+    ];
+    let requirements = KeyPackageRequirements {
+        ciphersuite: aes,
+        required_extensions: lifetime,
+    };
+
+    // The regular key package with the latest expiry is chosen, the first one
+    // on ties
+    let selected = select_key_package(&publications, &requirements, &SystemTimeProvider);
+    assert_eq!(selected, Some(&publications[5]));
+
+    // Last resort key packages are used if nothing else is left
+    let selected = select_key_package(&publications[..4], &requirements, &SystemTimeProvider);
+    assert_eq!(selected, Some(&publications[1]));
+
+    // The ciphersuite has to match
+    let requirements = KeyPackageRequirements {
+        ciphersuite: chacha,
+        required_extensions: vec![],
+    };
+    let selected = select_key_package(&publications, &requirements, &SystemTimeProvider);
+    assert_eq!(selected, Some(&publications[0]));
+    assert_eq!(
+        select_key_package(&publications[1..], &requirements, &SystemTimeProvider),
+        None
+    );
+}
+
+#[test]
+fn key_package_bytes() {
+    use crate::key_packages::*;
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let signature_keypair = ciphersuite.new_signature_keypair();
+    let identity =
+        Identity::new_with_keypair(ciphersuite, vec![1, 2, 3], signature_keypair.clone());
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let kpb = KeyPackageBundle::new(
+        &ciphersuite,
+        signature_keypair.get_private_key(),
+        credential,
+        Some(vec![LifetimeExtension::new(60).to_extension()]),
+    );
+    let key_package = kpb.get_key_package();
+    let bytes = key_package.to_bytes().unwrap();
+    assert_eq!(bytes, key_package.encode_detached().unwrap());
+    assert_eq!(&KeyPackage::from_bytes(&bytes).unwrap(), key_package);
+
+    // Trailing and missing bytes
+    let mut modified = bytes.clone();
+    modified.push(0);
+    assert_eq!(
+        KeyPackage::from_bytes(&modified),
+        Err(KeyPackageError::Malformed)
+    );
+    assert_eq!(
+        KeyPackage::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(KeyPackageError::Malformed)
+    );
+    assert_eq!(KeyPackage::from_bytes(&[]), Err(KeyPackageError::Malformed));
+
+    // Unknown protocol versions and ciphersuites are rejected without
+    // panicking. Ciphersuites that are known but not supported by key
+    // packages of this crate are reported as such.
+    let mut modified = bytes.clone();
+    modified[0] = 7;
+    assert_eq!(
+        KeyPackage::from_bytes(&modified),
+        Err(KeyPackageError::UnsupportedProtocolVersion)
+    );
+    let mut modified = bytes.clone();
+    modified[1..3].copy_from_slice(&[0xff, 0xff]);
+    assert_eq!(
+        KeyPackage::from_bytes(&modified),
+        Err(KeyPackageError::Malformed)
+    );
+    let mut modified = bytes.clone();
+    modified[1..3].copy_from_slice(&[0x00, 0x02]);
+    assert_eq!(
+        KeyPackage::from_bytes(&modified),
+        Err(KeyPackageError::UnsupportedCiphersuite)
+    );
+
+    // A modified key package doesn't verify
+    let mut modified = bytes.clone();
+    let last = modified.len() - 1;
+    modified[last] ^= 0xff;
+    assert_eq!(
+        KeyPackage::from_bytes(&modified),
+        Err(KeyPackageError::InvalidSignature)
+    );
+
+    // The lifetime is checked against the given time
+    struct FixedTime(u64);
+    impl TimeProvider for FixedTime {
+        fn now(&self) -> u64 {
+            self.0
+        }
+    }
+    assert_eq!(
+        KeyPackage::from_bytes_with_time(&bytes, &FixedTime(0)),
+        Err(KeyPackageError::Expired)
+    );
+
+    // Clients with a clock that is slightly off are accepted within the
+    // acceptance margin
+    let key_package = KeyPackage::from_bytes(&bytes).unwrap();
+    let not_before = match key_package.get_extension(ExtensionType::Lifetime) {
+        Some(ExtensionPayload::Lifetime(lifetime_extension)) => lifetime_extension.not_before,
+        _ => panic!("Expected a lifetime extension"),
+    };
+    let mut lifetime_config = LifetimeConfig::new();
+    lifetime_config.set_time_provider(std::sync::Arc::new(FixedTime(not_before - 60)));
+    assert_eq!(
+        KeyPackage::from_bytes_with_config(&bytes, &lifetime_config),
+        Err(KeyPackageError::Expired)
+    );
+    lifetime_config.set_acceptance_margin(60);
+    assert_eq!(
+        KeyPackage::from_bytes_with_config(&bytes, &lifetime_config),
+        Ok(key_package)
+    );
+}
@@ -14,15 +14,69 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see http://www.gnu.org/licenses/.
 
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+
+// Node indices and the lengths of encoded vectors are `u32`s that are used as
+// `usize`s without checks
+#[cfg(not(any(target_pointer_width = "32", target_pointer_width = "64")))]
+compile_error!("maelstrom requires a target with 32-bit or 64-bit pointers");
+
+// Declared first so that the tracing macros are available in all modules
+#[cfg(feature = "std")]
+#[macro_use]
+pub mod metrics;
+
+#[cfg(feature = "std")]
 pub mod ciphersuite;
 pub mod codec;
+#[cfg(feature = "std")]
 pub mod creds;
+#[cfg(feature = "std")]
+pub mod diagnostics;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
 pub mod extensions;
+#[cfg(feature = "std")]
 pub mod framing;
+#[cfg(feature = "std")]
 pub mod group;
+#[cfg(feature = "std")]
 pub mod key_packages;
+#[cfg(feature = "std")]
 pub mod messages;
+#[cfg(feature = "std")]
 pub mod schedule;
+#[cfg(feature = "std")]
+pub mod storage;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
+#[cfg(feature = "transport")]
+pub mod transport;
+#[cfg(feature = "std")]
 mod tree;
+#[cfg(feature = "std")]
 pub mod utils;
+#[cfg(feature = "std")]
 pub mod validator;
+
+// Without `std`, the index types and the tree math are public on their own
+#[cfg(not(feature = "std"))]
+#[path = "tree"]
+pub mod tree {
+    pub mod index;
+    pub mod treemath;
+}
+
+// Without `std`, only the extension types and the parsing of the extensions
+// that don't depend on the crypto and the tree are built
+#[cfg(not(feature = "std"))]
+#[path = "extensions"]
+pub mod extensions {
+    mod parsing;
+
+    pub use self::parsing::*;
+}
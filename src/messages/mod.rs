@@ -19,7 +19,8 @@ use crate::codec::*;
 use crate::creds::*;
 use crate::extensions::*;
 use crate::group::*;
-use crate::tree::{index::*, *};
+use crate::key_packages::*;
+use crate::tree::{index::*, node::*, *};
 use std::fmt;
 
 pub(crate) mod proposals;
@@ -30,10 +31,53 @@ pub enum MessageError {
     UnknownOperation,
 }
 
+/// A member whose leaf changed in a Commit.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MemberChange {
+    /// The leaf of the member. For added members, this is the leaf they were
+    /// assigned to, the leftmost free leaf at the time of their Add.
+    pub index: LeafIndex,
+    /// The leaf of the member that was added or removed
+    pub leaf_node: LeafNode,
+}
+
+/// A member that updated its leaf in a Commit.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MemberUpdate {
+    /// The leaf of the member
+    pub index: LeafIndex,
+    /// The leaf that was replaced
+    pub old_leaf_node: LeafNode,
+    /// The new leaf from the Update proposal
+    pub new_leaf_node: LeafNode,
+}
+
+/// A member that changed its device capabilities with an Update.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CapabilityChange {
+    /// The leaf of the member
+    pub index: LeafIndex,
+    /// The capabilities before the Update
+    pub old_capabilities: DeviceCapabilitiesExtension,
+    /// The capabilities after the Update
+    pub new_capabilities: DeviceCapabilitiesExtension,
+}
+
+/// The membership changes of a Commit. Added members are listed in the order
+/// of the Add proposals.
 pub struct MembershipChanges {
-    pub updates: Vec<Credential>,
-    pub removes: Vec<Credential>,
-    pub adds: Vec<Credential>,
+    /// The leaf of the member that created the Commit
+    pub committer: LeafIndex,
+    /// The `Credential` of the member that created the Commit
+    pub committer_credential: Credential,
+    /// The members that updated their leaf
+    pub updates: Vec<MemberUpdate>,
+    /// The members that were removed, with the leaves they had
+    pub removes: Vec<MemberChange>,
+    /// The members that were added, with the leaves they were assigned to
+    pub adds: Vec<MemberChange>,
+    /// The members whose Update changed their device capabilities
+    pub capability_changes: Vec<CapabilityChange>,
 }
 
 impl MembershipChanges {
@@ -44,30 +88,95 @@ impl MembershipChanges {
 
 impl fmt::Debug for MembershipChanges {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fn list_members(f: &mut fmt::Formatter<'_>, members: &[Credential]) -> fmt::Result {
-            for m in members {
-                let Credential::Basic(bc) = m;
-                write!(f, "{} ", String::from_utf8(bc.identity.clone()).unwrap())?;
-            }
-            Ok(())
+        fn write_member(
+            f: &mut fmt::Formatter<'_>,
+            index: LeafIndex,
+            leaf_node: &LeafNode,
+        ) -> fmt::Result {
+            let Credential::Basic(bc) = leaf_node.get_credential();
+            write!(
+                f,
+                "{} ({}) ",
+                String::from_utf8(bc.identity.clone()).unwrap(),
+                index.as_u32()
+            )
         }
         write!(f, "Membership changes:")?;
+        let Credential::Basic(bc) = &self.committer_credential;
+        write!(
+            f,
+            "\n\tCommitter: {} ({})",
+            String::from_utf8(bc.identity.clone()).unwrap(),
+            self.committer.as_u32()
+        )?;
         write!(f, "\n\tUpdates: ")?;
-        list_members(f, &self.updates)?;
+        for m in &self.updates {
+            write_member(f, m.index, &m.new_leaf_node)?;
+        }
         write!(f, "\n\tRemoves: ")?;
-        list_members(f, &self.removes)?;
+        for m in &self.removes {
+            write_member(f, m.index, &m.leaf_node)?;
+        }
         write!(f, "\n\tAdds: ")?;
-        list_members(f, &self.adds)?;
+        for m in &self.adds {
+            write_member(f, m.index, &m.leaf_node)?;
+        }
+        write!(f, "\n\tCapability changes: ")?;
+        for c in &self.capability_changes {
+            write!(
+                f,
+                "{} {:?} -> {:?} ",
+                c.index.as_u32(),
+                c.old_capabilities.get_capabilities(),
+                c.new_capabilities.get_capabilities()
+            )?;
+        }
         writeln!(f)
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Commit {
-    pub updates: Vec<ProposalID>,
-    pub removes: Vec<ProposalID>,
-    pub adds: Vec<ProposalID>,
-    pub path: Option<DirectPath>,
+    pub(crate) updates: Vec<ProposalID>,
+    pub(crate) removes: Vec<ProposalID>,
+    pub(crate) adds: Vec<ProposalID>,
+    pub(crate) psks: Vec<ProposalID>,
+    // Proposals of the committer that are included by value. They are
+    // referenced in the lists above like the other proposals.
+    pub(crate) proposals: Vec<Proposal>,
+    pub(crate) path: Option<DirectPath>,
+}
+
+impl Commit {
+    /// Get the IDs of the Update proposals the Commit covers.
+    pub fn updates(&self) -> &[ProposalID] {
+        &self.updates
+    }
+
+    /// Get the IDs of the Remove proposals the Commit covers.
+    pub fn removes(&self) -> &[ProposalID] {
+        &self.removes
+    }
+
+    /// Get the IDs of the Add proposals the Commit covers.
+    pub fn adds(&self) -> &[ProposalID] {
+        &self.adds
+    }
+
+    /// Get the IDs of the PSK proposals the Commit covers.
+    pub fn psks(&self) -> &[ProposalID] {
+        &self.psks
+    }
+
+    /// Get the proposals of the committer that the Commit includes by value.
+    pub fn proposals(&self) -> &[Proposal] {
+        &self.proposals
+    }
+
+    /// Get the path of the Commit, if it has one.
+    pub fn path(&self) -> Option<&DirectPath> {
+        self.path.as_ref()
+    }
 }
 
 impl Codec for Commit {
@@ -75,21 +184,27 @@ impl Codec for Commit {
         encode_vec(VecSize::VecU32, buffer, &self.updates)?;
         encode_vec(VecSize::VecU32, buffer, &self.removes)?;
         encode_vec(VecSize::VecU32, buffer, &self.adds)?;
+        encode_vec(VecSize::VecU32, buffer, &self.psks)?;
+        encode_vec(VecSize::VecU32, buffer, &self.proposals)?;
         self.path.encode(buffer)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let updates = decode_vec(VecSize::VecU32, cursor)?;
-    //     let removes = decode_vec(VecSize::VecU32, cursor)?;
-    //     let adds = decode_vec(VecSize::VecU32, cursor)?;
-    //     let path = Option::<DirectPath>::decode(cursor)?;
-    //     Ok(Commit {
-    //         updates,
-    //         removes,
-    //         adds,
-    //         path,
-    //     })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let updates = decode_vec(VecSize::VecU32, cursor)?;
+        let removes = decode_vec(VecSize::VecU32, cursor)?;
+        let adds = decode_vec(VecSize::VecU32, cursor)?;
+        let psks = decode_vec(VecSize::VecU32, cursor)?;
+        let proposals = decode_vec(VecSize::VecU32, cursor)?;
+        let path = Option::<DirectPath>::decode(cursor)?;
+        Ok(Commit {
+            updates,
+            removes,
+            adds,
+            psks,
+            proposals,
+            path,
+        })
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -116,10 +231,10 @@ impl Codec for ConfirmationTag {
         encode_vec(VecSize::VecU8, buffer, &self.0)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let inner = decode_vec(VecSize::VecU8, cursor)?;
-    //     Ok(ConfirmationTag(inner))
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let inner = decode_vec(VecSize::VecU8, cursor)?;
+        Ok(ConfirmationTag(inner))
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -178,6 +293,12 @@ impl GroupInfo {
             signature,
         })
     }
+
+    /// Get the `CorrelationId` of the Commit that started the epoch of this
+    /// `GroupInfo`.
+    pub fn correlation_id(&self) -> CorrelationId {
+        CorrelationId::new(&self.confirmed_transcript_hash)
+    }
 }
 
 impl Codec for GroupInfo {
@@ -211,6 +332,8 @@ impl Codec for GroupInfo {
 }
 
 impl Signable for GroupInfo {
+    const SIGNATURE_LABEL: &'static str = "GroupInfoTBS";
+
     fn unsigned_payload(&self) -> Result<Vec<u8>, CodecError> {
         let buffer = &mut vec![];
         self.group_id.encode(buffer)?;
@@ -234,53 +357,135 @@ impl Codec for PathSecret {
         encode_vec(VecSize::VecU8, buffer, &self.path_secret)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let path_secret = decode_vec(VecSize::VecU8, cursor)?;
-    //     Ok(PathSecret { path_secret })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let path_secret = decode_vec(VecSize::VecU8, cursor)?;
+        Ok(PathSecret { path_secret })
+    }
+}
+
+/// Type of a pre-shared key
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[repr(u8)]
+pub enum PSKType {
+    Reserved = 0,
+    /// A key that was agreed on out of band
+    External = 1,
+    /// The resumption secret of a previous epoch of a group
+    Resumption = 2,
+    Default = 255,
 }
 
+impl From<u8> for PSKType {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => PSKType::Reserved,
+            1 => PSKType::External,
+            2 => PSKType::Resumption,
+            _ => PSKType::Default,
+        }
+    }
+}
+
+impl Codec for PSKType {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        (*self as u8).encode(buffer)?;
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        Ok(PSKType::from(u8::decode(cursor)?))
+    }
+}
+
+/// Identifies a pre-shared key that is injected into the key schedule.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PreSharedKeyID {
+    pub psk_type: PSKType,
+    pub psk_id: Vec<u8>,
+}
+
+impl PreSharedKeyID {
+    /// Create the ID of an external PSK
+    pub fn external(psk_id: &[u8]) -> Self {
+        Self {
+            psk_type: PSKType::External,
+            psk_id: psk_id.to_vec(),
+        }
+    }
+    /// Create the ID of the resumption secret of `epoch` of the group
+    /// `group_id`
+    pub fn resumption(group_id: &GroupId, epoch: GroupEpoch) -> Self {
+        let mut psk_id = vec![];
+        group_id.encode(&mut psk_id).unwrap();
+        epoch.encode(&mut psk_id).unwrap();
+        Self {
+            psk_type: PSKType::Resumption,
+            psk_id,
+        }
+    }
+}
+
+impl Codec for PreSharedKeyID {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        self.psk_type.encode(buffer)?;
+        encode_vec(VecSize::VecU8, buffer, &self.psk_id)?;
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let psk_type = PSKType::decode(cursor)?;
+        let psk_id = decode_vec(VecSize::VecU8, cursor)?;
+        Ok(PreSharedKeyID { psk_type, psk_id })
+    }
+}
+
+/// A pre-shared key together with its ID
+pub type PreSharedKey = (PreSharedKeyID, Vec<u8>);
+
 pub struct GroupSecrets {
     pub joiner_secret: Vec<u8>,
     pub path_secret: Option<PathSecret>,
+    /// The PSKs the joiner has to inject into the key schedule
+    pub psks: Vec<PreSharedKeyID>,
 }
 
 impl Codec for GroupSecrets {
     fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
         encode_vec(VecSize::VecU8, buffer, &self.joiner_secret)?;
         self.path_secret.encode(buffer)?;
+        encode_vec(VecSize::VecU16, buffer, &self.psks)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let joiner_secret = decode_vec(VecSize::VecU8, cursor)?;
-    //     let path_secret = Option::<PathSecret>::decode(cursor)?;
-    //     Ok(GroupSecrets {
-    //         joiner_secret,
-    //         path_secret,
-    //     })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let joiner_secret = decode_vec(VecSize::VecU8, cursor)?;
+        let path_secret = Option::<PathSecret>::decode(cursor)?;
+        let psks = decode_vec(VecSize::VecU16, cursor)?;
+        Ok(GroupSecrets {
+            joiner_secret,
+            path_secret,
+            psks,
+        })
+    }
 }
 
 #[derive(Clone)]
 pub struct EncryptedGroupSecrets {
-    pub key_package_hash: Vec<u8>,
+    pub key_package_ref: KeyPackageRef,
     pub encrypted_group_secrets: HpkeCiphertext,
 }
 
 impl Codec for EncryptedGroupSecrets {
     fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
-        encode_vec(VecSize::VecU8, buffer, &self.key_package_hash)?;
+        self.key_package_ref.encode(buffer)?;
         self.encrypted_group_secrets.encode(buffer)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let key_package_hash = decode_vec(VecSize::VecU8, cursor)?;
-    //     let encrypted_group_secrets = HpkeCiphertext::decode(cursor)?;
-    //     Ok(EncryptedGroupSecrets {
-    //         key_package_hash,
-    //         encrypted_group_secrets,
-    //     })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let key_package_ref = KeyPackageRef::decode(cursor)?;
+        let encrypted_group_secrets = HpkeCiphertext::decode(cursor)?;
+        Ok(EncryptedGroupSecrets {
+            key_package_ref,
+            encrypted_group_secrets,
+        })
+    }
 }
 
 #[derive(Clone)]
@@ -299,18 +504,23 @@ impl Codec for Welcome {
         encode_vec(VecSize::VecU32, buffer, &self.encrypted_group_info)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let version = ProtocolVersion::decode(cursor)?;
-    //     let cipher_suite = Ciphersuite::decode(cursor)?;
-    //     let secrets = decode_vec(VecSize::VecU32, cursor)?;
-    //     let encrypted_group_info = decode_vec(VecSize::VecU32, cursor)?;
-    //     Ok(Welcome {
-    //         version,
-    //         cipher_suite,
-    //         secrets,
-    //         encrypted_group_info,
-    //     })
-    // }
+    // Welcomes are decoded from untrusted bytes by the transport adapters, so
+    // unknown protocol versions and ciphersuites are rejected right away.
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let version = ProtocolVersion::decode(cursor)?;
+        if version != CURRENT_PROTOCOL_VERSION {
+            return Err(CodecError::DecodingError);
+        }
+        let cipher_suite = Ciphersuite::decode(cursor)?;
+        let secrets = decode_vec(VecSize::VecU32, cursor)?;
+        let encrypted_group_info = decode_vec(VecSize::VecU32, cursor)?;
+        Ok(Welcome {
+            version,
+            cipher_suite,
+            secrets,
+            encrypted_group_info,
+        })
+    }
 }
 
 pub type WelcomeBundle = (Welcome, Extension);
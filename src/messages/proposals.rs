@@ -2,6 +2,7 @@ use crate::ciphersuite::*;
 use crate::codec::*;
 use crate::framing::*;
 use crate::key_packages::*;
+use crate::messages::PreSharedKeyID;
 use crate::tree::index::LeafIndex;
 use std::collections::HashMap;
 
@@ -12,6 +13,7 @@ pub enum ProposalType {
     Add = 1,
     Update = 2,
     Remove = 3,
+    PreSharedKey = 4,
     Default = 255,
 }
 
@@ -22,6 +24,7 @@ impl From<u8> for ProposalType {
             1 => ProposalType::Add,
             2 => ProposalType::Update,
             3 => ProposalType::Remove,
+            4 => ProposalType::PreSharedKey,
             _ => ProposalType::Default,
         }
     }
@@ -43,6 +46,7 @@ pub enum Proposal {
     Add(AddProposal),
     Update(UpdateProposal),
     Remove(RemoveProposal),
+    PreSharedKey(PreSharedKeyProposal),
 }
 
 impl Proposal {
@@ -67,6 +71,12 @@ impl Proposal {
             _ => None,
         }
     }
+    pub fn as_psk(&self) -> Option<PreSharedKeyProposal> {
+        match self {
+            Proposal::PreSharedKey(psk_proposal) => Some(psk_proposal.clone()),
+            _ => None,
+        }
+    }
 }
 
 impl Codec for Proposal {
@@ -84,29 +94,42 @@ impl Codec for Proposal {
                 ProposalType::Remove.encode(buffer)?;
                 remove.encode(buffer)?;
             }
+            Proposal::PreSharedKey(psk) => {
+                ProposalType::PreSharedKey.encode(buffer)?;
+                psk.encode(buffer)?;
+            }
         }
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let proposal_type = ProposalType::from(u8::decode(cursor)?);
-    //     match proposal_type {
-    //         ProposalType::Add => Ok(Proposal::Add(AddProposal::decode(cursor)?)),
-    //         ProposalType::Update => Ok(Proposal::Update(UpdateProposal::decode(cursor)?)),
-    //         ProposalType::Remove => Ok(Proposal::Remove(RemoveProposal::decode(cursor)?)),
-    //         _ => Err(CodecError::DecodingError),
-    //     }
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let proposal_type = ProposalType::from(u8::decode(cursor)?);
+        match proposal_type {
+            ProposalType::Add => Ok(Proposal::Add(AddProposal::decode(cursor)?)),
+            ProposalType::Update => Ok(Proposal::Update(UpdateProposal::decode(cursor)?)),
+            ProposalType::Remove => Ok(Proposal::Remove(RemoveProposal::decode(cursor)?)),
+            ProposalType::PreSharedKey => {
+                let psk_proposal = PreSharedKeyProposal::decode(cursor)?;
+                Ok(Proposal::PreSharedKey(psk_proposal))
+            }
+            _ => Err(CodecError::DecodingError),
+        }
+    }
 }
 
+/// Label of the reference hash of proposals
+pub(crate) const PROPOSAL_REFERENCE_LABEL: &str = "MLS 1.0 Proposal Reference";
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct ProposalID {
     value: Vec<u8>,
 }
 
 impl ProposalID {
+    /// Compute the reference of `proposal` as the labeled hash of its
+    /// encoding.
     pub fn from_proposal(ciphersuite: &Ciphersuite, proposal: &Proposal) -> Self {
         let encoded = proposal.encode_detached().unwrap();
-        let value = ciphersuite.hash(&encoded);
+        let value = ciphersuite.reference_hash(PROPOSAL_REFERENCE_LABEL, &encoded);
         Self { value }
     }
 }
@@ -116,10 +139,10 @@ impl Codec for ProposalID {
         encode_vec(VecSize::VecU8, buffer, &self.value)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let value = decode_vec(VecSize::VecU8, cursor)?;
-    //     Ok(ProposalID { value })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let value = decode_vec(VecSize::VecU8, cursor)?;
+        Ok(ProposalID { value })
+    }
 }
 
 #[derive(Eq, PartialEq, Hash, Copy, Clone)]
@@ -206,17 +229,20 @@ impl ProposalQueue {
         let mut updates = vec![];
         let mut removes = vec![];
         let mut adds = vec![];
+        let mut psks = vec![];
         for (_spi, p) in self.tuples.values() {
             match p.proposal {
                 Proposal::Update(_) => updates.push(p.proposal.to_proposal_id(ciphersuite)),
                 Proposal::Remove(_) => removes.push(p.proposal.to_proposal_id(ciphersuite)),
                 Proposal::Add(_) => adds.push(p.proposal.to_proposal_id(ciphersuite)),
+                Proposal::PreSharedKey(_) => psks.push(p.proposal.to_proposal_id(ciphersuite)),
             }
         }
         ProposalIDList {
             updates,
             removes,
             adds,
+            psks,
         }
     }
 }
@@ -237,6 +263,7 @@ pub struct ProposalIDList {
     pub updates: Vec<ProposalID>,
     pub removes: Vec<ProposalID>,
     pub adds: Vec<ProposalID>,
+    pub psks: Vec<ProposalID>,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -249,10 +276,10 @@ impl Codec for AddProposal {
         self.key_package.encode(buffer)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let key_package = KeyPackage::decode(cursor)?;
-    //     Ok(AddProposal { key_package })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let key_package = KeyPackage::decode(cursor)?;
+        Ok(AddProposal { key_package })
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -265,10 +292,10 @@ impl Codec for UpdateProposal {
         self.key_package.encode(buffer)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let key_package = KeyPackage::decode(cursor)?;
-    //     Ok(UpdateProposal { key_package })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let key_package = KeyPackage::decode(cursor)?;
+        Ok(UpdateProposal { key_package })
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -281,8 +308,26 @@ impl Codec for RemoveProposal {
         self.removed.encode(buffer)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let removed = u32::decode(cursor)?;
-    //     Ok(RemoveProposal { removed })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let removed = u32::decode(cursor)?;
+        Ok(RemoveProposal { removed })
+    }
+}
+
+/// Proposal to inject a pre-shared key into the key schedule of the next
+/// epoch.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PreSharedKeyProposal {
+    pub psk: PreSharedKeyID,
+}
+
+impl Codec for PreSharedKeyProposal {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        self.psk.encode(buffer)?;
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let psk = PreSharedKeyID::decode(cursor)?;
+        Ok(PreSharedKeyProposal { psk })
+    }
 }
@@ -0,0 +1,71 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+//! Counters and optional `tracing` instrumentation.
+//!
+//! With the `tracing` feature enabled, commit creation and application,
+//! Welcome processing, tree operations and decryption are wrapped in spans and
+//! emit events. The counters are always available.
+
+use crate::group::{DecryptionError, GroupEpoch};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Enter a `tracing` span at debug level that lasts until the end of the
+/// current scope. Does nothing without the `tracing` feature.
+macro_rules! trace_span {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!($($arg)*).entered();
+    };
+}
+
+/// Emit a `tracing` event at debug level. Does nothing without the `tracing`
+/// feature.
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+
+static EPOCH_TRANSITIONS: AtomicU64 = AtomicU64::new(0);
+static DECRYPTION_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+/// Get the number of epoch transitions of all groups since the process started.
+pub fn epoch_transitions() -> u64 {
+    EPOCH_TRANSITIONS.load(Ordering::Relaxed)
+}
+
+/// Get the number of messages of all groups that failed to decrypt since the
+/// process started.
+pub fn decryption_failures() -> u64 {
+    DECRYPTION_FAILURES.load(Ordering::Relaxed)
+}
+
+pub(crate) fn record_epoch_transition(epoch: GroupEpoch) {
+    EPOCH_TRANSITIONS.fetch_add(1, Ordering::Relaxed);
+    trace_event!(epoch = epoch.0, "epoch transition");
+    #[cfg(not(feature = "tracing"))]
+    let _ = epoch;
+}
+
+pub(crate) fn record_decryption_failure(error: &DecryptionError) {
+    DECRYPTION_FAILURES.fetch_add(1, Ordering::Relaxed);
+    #[cfg(feature = "tracing")]
+    tracing::warn!(?error, "decryption failure");
+    #[cfg(not(feature = "tracing"))]
+    let _ = error;
+}
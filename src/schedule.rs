@@ -18,11 +18,30 @@ use crate::ciphersuite::*;
 use crate::codec::*;
 use crate::group::*;
 use crate::messages::*;
+use crate::utils::*;
+
+use zeroize::Zeroize;
+
+/// `DeriveTreeSecret` from the spec, which derives the keys and nonces of the
+/// sender ratchets. Only exposed for known-answer tests.
+#[cfg(feature = "test-vector")]
+pub fn derive_tree_secret(
+    ciphersuite: &Ciphersuite,
+    secret: &[u8],
+    label: &str,
+    generation: u32,
+    length: usize,
+) -> Vec<u8> {
+    crate::tree::astree::derive_tree_secret(ciphersuite, secret, label, generation, length)
+}
 
 pub fn derive_secret(ciphersuite: &Ciphersuite, secret: &[u8], label: &str) -> Vec<u8> {
     hkdf_expand_label(ciphersuite, secret, label, &[], ciphersuite.hash_length())
 }
 
+/// `MLS-Exporter` from the spec: derives a secret of `key_length` bytes for
+/// `label` from the exporter secret of `epoch_secrets`. The context is the
+/// hash of the serialized `group_context`.
 pub fn mls_exporter(
     ciphersuite: &Ciphersuite,
     epoch_secrets: &EpochSecrets,
@@ -42,6 +61,42 @@ pub fn mls_exporter(
     )
 }
 
+/// Combine the pre-shared keys `psks` into a single PSK secret that is
+/// injected into the key schedule. Returns `None` if there are no PSKs.
+pub fn psk_secret(ciphersuite: &Ciphersuite, psks: &[PreSharedKey]) -> Option<Vec<u8>> {
+    if psks.is_empty() {
+        return None;
+    }
+    let zero_secret = zero(ciphersuite.hash_length());
+    let mut psk_secret = zero_secret.clone();
+    for (index, (psk_id, psk)) in psks.iter().enumerate() {
+        let mut psk_label = vec![];
+        psk_id.encode(&mut psk_label).unwrap();
+        (index as u16).encode(&mut psk_label).unwrap();
+        (psks.len() as u16).encode(&mut psk_label).unwrap();
+        let psk_extracted = ciphersuite.hkdf_extract(&zero_secret, psk);
+        let psk_input = hkdf_expand_label(
+            ciphersuite,
+            &psk_extracted,
+            "derived psk",
+            &psk_label,
+            ciphersuite.hash_length(),
+        );
+        psk_secret = ciphersuite.hkdf_extract(&psk_input, &psk_secret);
+    }
+    Some(psk_secret)
+}
+
+/// Derive the membership key of the current epoch from the sender data secret.
+/// The key can be handed to the delivery service to verify membership tags
+/// without giving it access to message contents.
+pub fn membership_key(ciphersuite: &Ciphersuite, epoch_secrets: &EpochSecrets) -> Vec<u8> {
+    derive_secret(ciphersuite, &epoch_secrets.sender_data_secret, "membership")
+}
+
+/// `HKDF-Expand-Label` from the spec: expands `secret` to `length` bytes with
+/// an `HkdfLabel` made of `length`, `"mls10 "` followed by `label`, and
+/// `context` as info.
 pub fn hkdf_expand_label(
     ciphersuite: &Ciphersuite,
     secret: &[u8],
@@ -87,10 +142,27 @@ pub struct EpochSecrets {
     pub application_secret: Vec<u8>,
     pub exporter_secret: Vec<u8>,
     pub confirmation_key: Vec<u8>,
+    pub authentication_secret: Vec<u8>,
     pub init_secret: Vec<u8>,
+    // Not encoded with the other secrets, groups keep the resumption secrets
+    // of recent epochs separately
+    pub resumption_secret: Vec<u8>,
 }
 
 impl EpochSecrets {
+    /// Overwrite all secrets with zeros.
+    pub(crate) fn wipe(&mut self) {
+        self.welcome_secret.zeroize();
+        self.sender_data_secret.zeroize();
+        self.handshake_secret.zeroize();
+        self.application_secret.zeroize();
+        self.exporter_secret.zeroize();
+        self.confirmation_key.zeroize();
+        self.authentication_secret.zeroize();
+        self.init_secret.zeroize();
+        self.resumption_secret.zeroize();
+    }
+
     pub fn new() -> Self {
         let welcome_secret = vec![];
         let sender_data_secret = vec![];
@@ -98,7 +170,9 @@ impl EpochSecrets {
         let application_secret = vec![];
         let exporter_secret = vec![];
         let confirmation_key = vec![];
+        let authentication_secret = vec![];
         let init_secret = vec![];
+        let resumption_secret = vec![];
         Self {
             welcome_secret,
             sender_data_secret,
@@ -106,9 +180,28 @@ impl EpochSecrets {
             application_secret,
             exporter_secret,
             confirmation_key,
+            authentication_secret,
             init_secret,
+            resumption_secret,
         }
     }
+    /// Derive the secrets of epoch 0 of a new group from a fresh `init_secret`.
+    /// The commit secret of the initial one-member group is all zeros.
+    pub fn new_initial(
+        ciphersuite: &Ciphersuite,
+        init_secret: Vec<u8>,
+        group_context: &GroupContext,
+    ) -> Self {
+        let mut epoch_secrets = Self {
+            init_secret,
+            ..Self::new()
+        };
+        let commit_secret = CommitSecret(zero(ciphersuite.hash_length()));
+        epoch_secrets.get_new_epoch_secrets(ciphersuite, commit_secret, None, group_context);
+        epoch_secrets
+    }
+    /// Advance the key schedule to the next epoch and return the joiner
+    /// secret, which is sent to new members in the `Welcome`.
     pub fn get_new_epoch_secrets(
         &mut self,
         ciphersuite: &Ciphersuite,
@@ -116,23 +209,25 @@ impl EpochSecrets {
         psk: Option<&[u8]>,
         group_context: &GroupContext,
     ) -> Vec<u8> {
-        let current_init_secret = self.init_secret.clone();
-        let joiner_secret =
-            &ciphersuite.hkdf_extract(commit_secret.as_slice(), &current_init_secret);
-        let welcome_secret = derive_secret(ciphersuite, &joiner_secret, "welcome");
-        let pre_member_secret = derive_secret(ciphersuite, &joiner_secret, "member");
+        let joiner_secret = ciphersuite.hkdf_extract(commit_secret.as_slice(), &self.init_secret);
+        *self = Self::from_joiner_secret(ciphersuite, &joiner_secret, psk, group_context);
+        joiner_secret
+    }
+
+    /// Derive the secrets of an epoch from its joiner secret. This is where
+    /// new members enter the key schedule.
+    pub fn from_joiner_secret(
+        ciphersuite: &Ciphersuite,
+        joiner_secret: &[u8],
+        psk: Option<&[u8]>,
+        group_context: &GroupContext,
+    ) -> Self {
+        let welcome_secret = derive_secret(ciphersuite, joiner_secret, "welcome");
+        let pre_member_secret = derive_secret(ciphersuite, joiner_secret, "member");
         let member_secret = ciphersuite.hkdf_extract(&psk.unwrap_or(&[]), &pre_member_secret);
         let pre_epoch_secret = derive_secret(ciphersuite, &member_secret, "epoch");
         let epoch_secret = ciphersuite.hkdf_extract(&group_context.serialize(), &pre_epoch_secret);
-        let epoch_secrets = Self::derive_epoch_secrets(ciphersuite, &epoch_secret, welcome_secret);
-        self.welcome_secret = epoch_secrets.welcome_secret;
-        self.sender_data_secret = epoch_secrets.sender_data_secret;
-        self.handshake_secret = epoch_secrets.handshake_secret;
-        self.application_secret = epoch_secrets.application_secret;
-        self.exporter_secret = epoch_secrets.exporter_secret;
-        self.confirmation_key = epoch_secrets.confirmation_key;
-        self.init_secret = epoch_secrets.init_secret;
-        epoch_secret
+        Self::derive_epoch_secrets(ciphersuite, &epoch_secret, welcome_secret)
     }
 
     pub fn derive_epoch_secrets(
@@ -145,7 +240,9 @@ impl EpochSecrets {
         let application_secret = derive_secret(ciphersuite, epoch_secret, "app");
         let exporter_secret = derive_secret(ciphersuite, epoch_secret, "exporter");
         let confirmation_key = derive_secret(ciphersuite, epoch_secret, "confirm");
+        let authentication_secret = derive_secret(ciphersuite, epoch_secret, "authentication");
         let init_secret = derive_secret(ciphersuite, epoch_secret, "init");
+        let resumption_secret = derive_secret(ciphersuite, epoch_secret, "resumption");
         EpochSecrets {
             welcome_secret,
             sender_data_secret,
@@ -153,7 +250,9 @@ impl EpochSecrets {
             application_secret,
             exporter_secret,
             confirmation_key,
+            authentication_secret,
             init_secret,
+            resumption_secret,
         }
     }
 }
@@ -167,6 +266,7 @@ impl Codec for EpochSecrets {
         encode_vec(VecSize::VecU8, buffer, &self.exporter_secret)?;
         encode_vec(VecSize::VecU8, buffer, &self.confirmation_key)?;
         encode_vec(VecSize::VecU8, buffer, &self.init_secret)?;
+        encode_vec(VecSize::VecU8, buffer, &self.authentication_secret)?;
         Ok(())
     }
     fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
@@ -177,6 +277,7 @@ impl Codec for EpochSecrets {
         let exporter_secret = decode_vec(VecSize::VecU8, cursor)?;
         let confirmation_key = decode_vec(VecSize::VecU8, cursor)?;
         let init_secret = decode_vec(VecSize::VecU8, cursor)?;
+        let authentication_secret = decode_vec(VecSize::VecU8, cursor)?;
         Ok(EpochSecrets {
             welcome_secret,
             sender_data_secret,
@@ -184,7 +285,147 @@ impl Codec for EpochSecrets {
             application_secret,
             exporter_secret,
             confirmation_key,
+            authentication_secret,
             init_secret,
+            resumption_secret: vec![],
         })
     }
 }
+
+#[test]
+fn initial_epoch_secrets() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let group_context = GroupContext {
+        group_id: GroupId::from_slice(&[1, 2, 3]),
+        epoch: GroupEpoch(0),
+        tree_hash: vec![],
+        confirmed_transcript_hash: vec![],
+        extensions: vec![],
+    };
+    let init_secret = vec![0x42; ciphersuite.hash_length()];
+    let epoch_secrets =
+        EpochSecrets::new_initial(&ciphersuite, init_secret.clone(), &group_context);
+
+    // All secrets of epoch 0 are derived and the init secret is replaced
+    assert_eq!(
+        epoch_secrets.application_secret.len(),
+        ciphersuite.hash_length()
+    );
+    assert_eq!(
+        epoch_secrets.sender_data_secret.len(),
+        ciphersuite.hash_length()
+    );
+    assert_eq!(
+        epoch_secrets.confirmation_key.len(),
+        ciphersuite.hash_length()
+    );
+    assert_ne!(epoch_secrets.init_secret, init_secret);
+
+    // The derivation is deterministic
+    assert_eq!(
+        epoch_secrets,
+        EpochSecrets::new_initial(&ciphersuite, init_secret, &group_context)
+    );
+}
+
+#[test]
+fn verify_binary_test_vector_crypto() {
+    use std::fs::File;
+    use std::io::Read;
+
+    let mut file = File::open("test_vectors/crypto.bin").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+
+    let cursor = &mut Cursor::new(&buffer);
+
+    let hkdf_extract_salt: Vec<u8> = decode_vec(VecSize::VecU8, cursor).unwrap();
+    let hkdf_extract_ikm: Vec<u8> = decode_vec(VecSize::VecU8, cursor).unwrap();
+    let derive_secret_secret: Vec<u8> = decode_vec(VecSize::VecU8, cursor).unwrap();
+    let derive_secret_label: Vec<u8> = decode_vec(VecSize::VecU8, cursor).unwrap();
+    let derive_secret_context: Vec<u8> = decode_vec(VecSize::VecU8, cursor).unwrap();
+    let _derive_key_pair_seed: Vec<u8> = decode_vec(VecSize::VecU8, cursor).unwrap();
+    let _ecies_plaintext: Vec<u8> = decode_vec(VecSize::VecU8, cursor).unwrap();
+
+    // The vectors contain a P-256 and an X25519 case, both with SHA-256.
+    // Derive-Secret hashes its context before expanding it.
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let label = String::from_utf8(derive_secret_label).unwrap();
+    for _ in 0..2 {
+        let hkdf_extract_out: Vec<u8> = decode_vec(VecSize::VecU8, cursor).unwrap();
+        let derive_secret_out: Vec<u8> = decode_vec(VecSize::VecU8, cursor).unwrap();
+        let _derive_key_pair_pub: Vec<u8> = decode_vec(VecSize::VecU16, cursor).unwrap();
+        let _ecies_kem_output: Vec<u8> = decode_vec(VecSize::VecU16, cursor).unwrap();
+        let _ecies_ciphertext: Vec<u8> = decode_vec(VecSize::VecU32, cursor).unwrap();
+
+        assert_eq!(
+            ciphersuite.hkdf_extract(&hkdf_extract_salt, &hkdf_extract_ikm),
+            hkdf_extract_out
+        );
+        assert_eq!(
+            hkdf_expand_label(
+                &ciphersuite,
+                &derive_secret_secret,
+                &label,
+                &ciphersuite.hash(&derive_secret_context),
+                ciphersuite.hash_length(),
+            ),
+            derive_secret_out
+        );
+    }
+    assert!(!cursor.has_more());
+}
+
+#[test]
+fn verify_binary_test_vector_key_schedule() {
+    use std::fs::File;
+    use std::io::Read;
+
+    let mut file = File::open("test_vectors/key_schedule.bin").unwrap();
+    let mut buffer = Vec::new();
+    file.read_to_end(&mut buffer).unwrap();
+
+    let cursor = &mut Cursor::new(&buffer);
+
+    let n_epochs = u32::decode(cursor).unwrap();
+    let _target_generation = u32::decode(cursor).unwrap();
+    let _base_init_secret: Vec<u8> = decode_vec(VecSize::VecU8, cursor).unwrap();
+    let _group_id: Vec<u8> = decode_vec(VecSize::VecU8, cursor).unwrap();
+    let _epoch = u64::decode(cursor).unwrap();
+    let _tree_hash: Vec<u8> = decode_vec(VecSize::VecU8, cursor).unwrap();
+    let _confirmed_transcript_hash: Vec<u8> = decode_vec(VecSize::VecU8, cursor).unwrap();
+    let _extensions: Vec<u8> = decode_vec(VecSize::VecU16, cursor).unwrap();
+
+    // The vectors contain a P-256 and an X25519 case, both with SHA-256. They
+    // follow an earlier draft, in which the epoch secret is extracted from
+    // the update secret with the init secret of the previous epoch as salt,
+    // starting from zero. The secrets that draft derives from the epoch
+    // secret use a group context that differs from `GroupContext` and are
+    // only read.
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    for _ in 0..2 {
+        let _suite = u16::decode(cursor).unwrap();
+        let epochs: Vec<u8> = decode_vec(VecSize::VecU16, cursor).unwrap();
+        let epochs_cursor = &mut Cursor::new(&epochs);
+        let mut init_secret = zero(ciphersuite.hash_length());
+        for _ in 0..n_epochs {
+            let update_secret: Vec<u8> = decode_vec(VecSize::VecU8, epochs_cursor).unwrap();
+            let epoch_secret: Vec<u8> = decode_vec(VecSize::VecU8, epochs_cursor).unwrap();
+            for _ in 0..2 {
+                let _derived_secret: Vec<u8> = decode_vec(VecSize::VecU8, epochs_cursor).unwrap();
+            }
+            let next_init_secret: Vec<u8> = decode_vec(VecSize::VecU8, epochs_cursor).unwrap();
+
+            assert_eq!(
+                ciphersuite.hkdf_extract(&init_secret, &update_secret),
+                epoch_secret
+            );
+            init_secret = next_init_secret;
+        }
+        assert!(!epochs_cursor.has_more());
+    }
+    assert!(!cursor.has_more());
+}
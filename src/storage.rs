@@ -0,0 +1,221 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+//! Envelopes for persisting key material and group state.
+//!
+//! Values are encoded together with a version, their type and the ciphersuite
+//! that protects them. Without a storage key, the envelope only carries a hash
+//! to detect corruption. With a storage key, the value is encrypted with an
+//! AEAD and the header is authenticated.
+
+use crate::ciphersuite::*;
+use crate::codec::*;
+use crate::key_packages::*;
+use crate::schedule::*;
+
+use thiserror::Error;
+
+/// Current version of the envelope format
+pub const STORAGE_VERSION: u8 = 1;
+
+/// Type of the value in an envelope
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[repr(u8)]
+pub enum StorageType {
+    Invalid = 0,
+    HPKEKeyPair = 1,
+    SignaturePrivateKey = 2,
+    SignatureKeypair = 3,
+    KeyPackageBundle = 4,
+    MlsGroup = 5,
+    GroupTransfer = 6,
+    HistoryKeyStore = 7,
+    GroupTombstone = 8,
+    Default = 255,
+}
+
+impl From<u8> for StorageType {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => StorageType::Invalid,
+            1 => StorageType::HPKEKeyPair,
+            2 => StorageType::SignaturePrivateKey,
+            3 => StorageType::SignatureKeypair,
+            4 => StorageType::KeyPackageBundle,
+            5 => StorageType::MlsGroup,
+            6 => StorageType::GroupTransfer,
+            7 => StorageType::HistoryKeyStore,
+            8 => StorageType::GroupTombstone,
+            _ => StorageType::Default,
+        }
+    }
+}
+
+/// Errors that can occur when an envelope is opened.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum StorageError {
+    /// The envelope could not be decoded
+    #[error("the envelope could not be decoded")]
+    MalformedEnvelope,
+    /// The envelope was created with an unknown version of the format
+    #[error("the envelope has an unsupported version")]
+    UnsupportedVersion,
+    /// The envelope is protected with a ciphersuite this crate doesn't support
+    #[error("the envelope has an unsupported ciphersuite")]
+    UnsupportedCiphersuite,
+    /// The envelope contains a value of another type
+    #[error("the envelope contains a value of another type")]
+    WrongType,
+    /// The envelope is encrypted, but no storage key was given
+    #[error("the envelope is encrypted, but no storage key was given")]
+    MissingStorageKey,
+    /// A storage key was given, but the envelope is not encrypted
+    #[error("the envelope is not encrypted")]
+    NotEncrypted,
+    /// The envelope was modified or the storage key is wrong
+    #[error("the envelope was modified or the storage key is wrong")]
+    IntegrityFailure,
+    /// The value could not be encrypted
+    #[error("the value could not be encrypted")]
+    EncryptionFailure,
+}
+
+impl From<CodecError> for StorageError {
+    fn from(_: CodecError) -> StorageError {
+        StorageError::MalformedEnvelope
+    }
+}
+
+/// Values that can be stored in an envelope.
+pub trait Storable: Codec {
+    const STORAGE_TYPE: StorageType;
+}
+
+impl Storable for HPKEKeyPair {
+    const STORAGE_TYPE: StorageType = StorageType::HPKEKeyPair;
+}
+
+impl Storable for SignaturePrivateKey {
+    const STORAGE_TYPE: StorageType = StorageType::SignaturePrivateKey;
+}
+
+impl Storable for SignatureKeypair {
+    const STORAGE_TYPE: StorageType = StorageType::SignatureKeypair;
+}
+
+impl Storable for KeyPackageBundle {
+    const STORAGE_TYPE: StorageType = StorageType::KeyPackageBundle;
+}
+
+/// Put `value` into an envelope. If a `storage_key` is given, the value is
+/// encrypted under a key derived from it.
+pub fn seal<T: Storable>(
+    ciphersuite: &Ciphersuite,
+    value: &T,
+    storage_key: Option<&[u8]>,
+) -> Result<Vec<u8>, StorageError> {
+    let mut buffer = vec![];
+    STORAGE_VERSION.encode(&mut buffer)?;
+    (T::STORAGE_TYPE as u8).encode(&mut buffer)?;
+    ciphersuite.encode(&mut buffer)?;
+    let payload = value.encode_detached()?;
+    match storage_key {
+        Some(storage_key) => {
+            1u8.encode(&mut buffer)?;
+            let key = derive_storage_key(ciphersuite, storage_key, T::STORAGE_TYPE);
+            let nonce = AeadNonce::random();
+            let ciphertext = ciphersuite
+                .aead_seal(&payload, &buffer, &key, &nonce)
+                .map_err(|_| StorageError::EncryptionFailure)?;
+            encode_vec(VecSize::VecU8, &mut buffer, nonce.as_slice())?;
+            encode_vec(VecSize::VecU32, &mut buffer, &ciphertext)?;
+        }
+        None => {
+            0u8.encode(&mut buffer)?;
+            encode_vec(VecSize::VecU32, &mut buffer, &payload)?;
+            let checksum = ciphersuite.hash(&buffer);
+            encode_vec(VecSize::VecU8, &mut buffer, &checksum)?;
+        }
+    }
+    Ok(buffer)
+}
+
+/// Open an envelope created with `seal`. The `storage_key` must be given if
+/// and only if it was given when the envelope was sealed.
+pub fn open<T: Storable>(bytes: &[u8], storage_key: Option<&[u8]>) -> Result<T, StorageError> {
+    let cursor = &mut Cursor::new(bytes);
+    if u8::decode(cursor)? != STORAGE_VERSION {
+        return Err(StorageError::UnsupportedVersion);
+    }
+    if StorageType::from(u8::decode(cursor)?) != T::STORAGE_TYPE {
+        return Err(StorageError::WrongType);
+    }
+    let ciphersuite =
+        Ciphersuite::decode(cursor).map_err(|_| StorageError::UnsupportedCiphersuite)?;
+    let encrypted = u8::decode(cursor)?;
+    // Length of version, type, ciphersuite and encryption flag
+    let header = &bytes[..5];
+    let payload = match (encrypted, storage_key) {
+        (1, Some(storage_key)) => {
+            let nonce = decode_vec(VecSize::VecU8, cursor)?;
+            let ciphertext = decode_vec(VecSize::VecU32, cursor)?;
+            if nonce.len() != ciphersuite.aead_nonce_length() {
+                return Err(StorageError::MalformedEnvelope);
+            }
+            let key = derive_storage_key(&ciphersuite, storage_key, T::STORAGE_TYPE);
+            ciphersuite
+                .aead_open(&ciphertext, header, &key, &AeadNonce::from_slice(&nonce))
+                .map_err(|_| StorageError::IntegrityFailure)?
+        }
+        (1, None) => return Err(StorageError::MissingStorageKey),
+        (0, Some(_)) => return Err(StorageError::NotEncrypted),
+        (0, None) => {
+            let payload: Vec<u8> = decode_vec(VecSize::VecU32, cursor)?;
+            let checksum: Vec<u8> = decode_vec(VecSize::VecU8, cursor)?;
+            let checksum_input = &bytes[..bytes.len() - checksum.len() - 1];
+            if ciphersuite.hash(checksum_input) != checksum {
+                return Err(StorageError::IntegrityFailure);
+            }
+            payload
+        }
+        _ => return Err(StorageError::MalformedEnvelope),
+    };
+    if cursor.has_more() {
+        return Err(StorageError::MalformedEnvelope);
+    }
+    let payload_cursor = &mut Cursor::new(&payload);
+    let value = T::decode(payload_cursor)?;
+    if payload_cursor.has_more() {
+        return Err(StorageError::MalformedEnvelope);
+    }
+    Ok(value)
+}
+
+fn derive_storage_key(
+    ciphersuite: &Ciphersuite,
+    storage_key: &[u8],
+    storage_type: StorageType,
+) -> AeadKey {
+    let prk = ciphersuite.hkdf_extract(&[], storage_key);
+    AeadKey::from_slice(&hkdf_expand_label(
+        ciphersuite,
+        &prk,
+        "storage",
+        &[storage_type as u8],
+        ciphersuite.aead_key_length(),
+    ))
+}
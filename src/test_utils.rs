@@ -0,0 +1,302 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+//! Helpers for tests of applications that use this crate. Only available
+//! with the `test-utils` feature.
+
+use crate::ciphersuite::*;
+use crate::framing::*;
+use crate::group::{GroupContext, GroupEpoch, GroupId};
+use crate::transport::*;
+
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use thiserror::Error;
+
+/// Transport pathologies a `MockDeliveryService` simulates. They are applied
+/// to every message and recipient independently.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DeliveryOptions {
+    /// Shuffle the messages that are waiting for a client before it receives
+    /// them
+    pub reorder: bool,
+    /// Probability that a message is delivered twice
+    pub duplicate_rate: f64,
+    /// Probability that a message is not delivered at all
+    pub drop_rate: f64,
+}
+
+impl DeliveryOptions {
+    /// Deliver every message exactly once and in order.
+    pub fn reliable() -> Self {
+        Self::default()
+    }
+
+    /// Check that the rates are probabilities between 0 and 1.
+    pub fn validate(&self) -> Result<(), DeliveryOptionsError> {
+        for rate in [self.duplicate_rate, self.drop_rate].iter() {
+            if !(0.0..=1.0).contains(rate) {
+                return Err(DeliveryOptionsError::InvalidRate);
+            }
+        }
+        Ok(())
+    }
+
+    /// Decide with `rng` whether a message is dropped.
+    pub fn drops(&self, rng: &mut impl Rng) -> bool {
+        rng.gen_bool(self.drop_rate)
+    }
+
+    /// Decide with `rng` whether a message is delivered twice.
+    pub fn duplicates(&self, rng: &mut impl Rng) -> bool {
+        rng.gen_bool(self.duplicate_rate)
+    }
+}
+
+/// Errors of invalid `DeliveryOptions`.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum DeliveryOptionsError {
+    /// A rate is not a probability between 0 and 1
+    #[error("a rate is not between 0 and 1")]
+    InvalidRate,
+}
+
+/// In-memory `DeliveryService` that can reorder, duplicate and drop
+/// messages according to its `DeliveryOptions`. The choices are made by an
+/// RNG seeded with `seed`, so a failing test can be reproduced.
+pub struct MockDeliveryService {
+    options: DeliveryOptions,
+    rng: StdRng,
+    subscribers: HashMap<Vec<u8>, Vec<Vec<u8>>>,
+    queues: HashMap<Vec<u8>, Vec<MLSMessage>>,
+    dropped: usize,
+    duplicated: usize,
+}
+
+impl MockDeliveryService {
+    /// Create a delivery service with the pathologies of `options`. Fails if
+    /// the `options` are invalid.
+    pub fn new(options: DeliveryOptions, seed: u64) -> Result<Self, DeliveryOptionsError> {
+        options.validate()?;
+        Ok(Self {
+            options,
+            rng: StdRng::seed_from_u64(seed),
+            subscribers: HashMap::new(),
+            queues: HashMap::new(),
+            dropped: 0,
+            duplicated: 0,
+        })
+    }
+
+    /// Change the pathologies for the messages that are sent from now on,
+    /// e.g. to deliver handshake messages reliably. Invalid `options` are
+    /// rejected and the previous ones are kept.
+    pub fn set_options(&mut self, options: DeliveryOptions) -> Result<(), DeliveryOptionsError> {
+        options.validate()?;
+        self.options = options;
+        Ok(())
+    }
+
+    /// Get the number of messages that were dropped so far.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Get the number of messages that were delivered twice so far.
+    pub fn duplicated(&self) -> usize {
+        self.duplicated
+    }
+
+    /// Get the number of messages that are waiting for `client`.
+    pub fn pending(&self, client: &[u8]) -> usize {
+        self.queues.get(client).map_or(0, |queue| queue.len())
+    }
+
+    fn enqueue(&mut self, client: &[u8], message: MLSMessage) {
+        if self.options.drops(&mut self.rng) {
+            self.dropped += 1;
+            return;
+        }
+        let queue = self.queues.entry(client.to_vec()).or_default();
+        if self.options.duplicates(&mut self.rng) {
+            self.duplicated += 1;
+            queue.push(message.clone());
+        }
+        queue.push(message);
+    }
+}
+
+impl DeliveryService for MockDeliveryService {
+    fn subscribe(&mut self, group_id: &GroupId, client: &[u8]) {
+        let subscribers = self.subscribers.entry(group_id.as_slice()).or_default();
+        if !subscribers.iter().any(|subscriber| subscriber == client) {
+            subscribers.push(client.to_vec());
+        }
+    }
+
+    fn unsubscribe(&mut self, group_id: &GroupId, client: &[u8]) {
+        if let Some(subscribers) = self.subscribers.get_mut(&group_id.as_slice()) {
+            subscribers.retain(|subscriber| subscriber != client);
+        }
+    }
+
+    fn send(&mut self, group_id: &GroupId, sender: &[u8], message: MLSMessage) {
+        let recipients: Vec<Vec<u8>> = self
+            .subscribers
+            .get(&group_id.as_slice())
+            .map(|subscribers| {
+                subscribers
+                    .iter()
+                    .filter(|subscriber| subscriber.as_slice() != sender)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        for recipient in recipients {
+            self.enqueue(&recipient, message.clone());
+        }
+    }
+
+    fn send_to(&mut self, client: &[u8], message: MLSMessage) {
+        self.enqueue(client, message);
+    }
+
+    fn receive(&mut self, client: &[u8]) -> Vec<MLSMessage> {
+        let mut messages = self.queues.remove(client).unwrap_or_default();
+        if self.options.reorder {
+            messages.shuffle(&mut self.rng);
+        }
+        messages
+    }
+}
+
+/// Builds `MLSPlaintext`s field by field, so that negative tests can create
+/// messages with a wrong epoch, a mismatched sender or content type or an
+/// invalid signature without editing encoded messages.
+#[derive(Clone)]
+pub struct MLSPlaintextBuilder {
+    group_id: GroupId,
+    epoch: GroupEpoch,
+    sender: Sender,
+    authenticated_data: Vec<u8>,
+    content_type: Option<ContentType>,
+    content: MLSPlaintextContentType,
+    signature: Option<Signature>,
+}
+
+impl MLSPlaintextBuilder {
+    /// Start a message of `sender` with `content` in the group and epoch of
+    /// `context`.
+    pub fn new(context: &GroupContext, sender: Sender, content: MLSPlaintextContentType) -> Self {
+        MLSPlaintextBuilder {
+            group_id: context.group_id.clone(),
+            epoch: context.epoch,
+            sender,
+            authenticated_data: vec![],
+            content_type: None,
+            content,
+            signature: None,
+        }
+    }
+
+    /// Start from the fields of an existing message, including its
+    /// signature.
+    pub fn from_plaintext(mls_plaintext: &MLSPlaintext) -> Self {
+        MLSPlaintextBuilder {
+            group_id: mls_plaintext.group_id.clone(),
+            epoch: mls_plaintext.epoch,
+            sender: mls_plaintext.sender,
+            authenticated_data: mls_plaintext.authenticated_data.clone(),
+            content_type: Some(mls_plaintext.content_type),
+            content: mls_plaintext.content.clone(),
+            signature: Some(mls_plaintext.signature.clone()),
+        }
+    }
+
+    /// Set the group ID.
+    pub fn group_id(mut self, group_id: GroupId) -> Self {
+        self.group_id = group_id;
+        self
+    }
+
+    /// Set the epoch.
+    pub fn epoch(mut self, epoch: GroupEpoch) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    /// Set the sender.
+    pub fn sender(mut self, sender: Sender) -> Self {
+        self.sender = sender;
+        self
+    }
+
+    /// Set the authenticated additional data.
+    pub fn authenticated_data(mut self, authenticated_data: &[u8]) -> Self {
+        self.authenticated_data = authenticated_data.to_vec();
+        self
+    }
+
+    /// Set the content. The content type follows the content unless it was
+    /// set explicitly.
+    pub fn content(mut self, content: MLSPlaintextContentType) -> Self {
+        self.content = content;
+        self
+    }
+
+    /// Set a content type, even one that doesn't match the content.
+    pub fn content_type(mut self, content_type: ContentType) -> Self {
+        self.content_type = Some(content_type);
+        self
+    }
+
+    /// Set a signature, which isn't checked against the other fields.
+    pub fn signature(mut self, signature: Signature) -> Self {
+        self.signature = Some(signature);
+        self
+    }
+
+    /// Build the message. It has an empty signature unless one was set.
+    pub fn build(self) -> MLSPlaintext {
+        MLSPlaintext {
+            group_id: self.group_id,
+            epoch: self.epoch,
+            sender: self.sender,
+            authenticated_data: self.authenticated_data,
+            content_type: self
+                .content_type
+                .unwrap_or_else(|| ContentType::from(self.content.clone())),
+            content: self.content,
+            signature: self.signature.unwrap_or_else(Signature::new_empty),
+        }
+    }
+
+    /// Build the message and sign it with `signature_key` under `context`,
+    /// which doesn't have to match the fields of the message.
+    pub fn sign(
+        self,
+        ciphersuite: &Ciphersuite,
+        signature_key: &SignaturePrivateKey,
+        context: &GroupContext,
+    ) -> MLSPlaintext {
+        let mut mls_plaintext = self.build();
+        mls_plaintext.sign(ciphersuite, signature_key, context);
+        mls_plaintext
+    }
+}
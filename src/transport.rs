@@ -0,0 +1,316 @@
+// maelstrom
+// Copyright (C) 2020 Raphael Robert
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see http://www.gnu.org/licenses/.
+
+//! Sample adapters that carry MLS messages over a network connection.
+//!
+//! Messages are wrapped in an `MLSMessage` and framed either as one binary
+//! frame per message on a WebSocket, or with a 4 byte length prefix on a byte
+//! stream like a TCP connection or a QUIC stream. `receive_loop` and
+//! `send_loop` move messages between a transport and the application. A
+//! `DeliveryService` fans the messages of a group out to its members. Only
+//! available with the `transport` feature.
+//!
+//! The adapters don't depend on a particular network library. Streams only
+//! have to implement `std::io::Read` and `std::io::Write`, WebSocket
+//! connections have to implement `WebSocketIo`.
+
+use crate::codec::*;
+use crate::framing::*;
+use crate::group::GroupId;
+use crate::messages::*;
+use crate::tree::node::*;
+
+use std::io::{self, Read, Write};
+use thiserror::Error;
+
+/// Maximum size of an encoded `MLSMessage` in bytes
+pub const MAX_MESSAGE_SIZE: usize = 1 << 24;
+
+/// A message of any type that is exchanged between clients and the delivery
+/// service.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+pub enum MLSMessage {
+    Plaintext(MLSPlaintext),
+    Ciphertext(MLSCiphertext),
+    /// A `Welcome` together with the ratchet tree the new member needs to
+    /// join, if it isn't sent some other way
+    Welcome {
+        welcome: Welcome,
+        ratchet_tree: Option<Vec<Option<Node>>>,
+    },
+}
+
+impl Codec for MLSMessage {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        match self {
+            MLSMessage::Plaintext(mls_plaintext) => {
+                1u8.encode(buffer)?;
+                mls_plaintext.encode(buffer)?;
+            }
+            MLSMessage::Ciphertext(mls_ciphertext) => {
+                2u8.encode(buffer)?;
+                mls_ciphertext.encode(buffer)?;
+            }
+            MLSMessage::Welcome {
+                welcome,
+                ratchet_tree,
+            } => {
+                3u8.encode(buffer)?;
+                welcome.encode(buffer)?;
+                match ratchet_tree {
+                    Some(nodes) => {
+                        1u8.encode(buffer)?;
+                        encode_vec(VecSize::VecU32, buffer, nodes)?;
+                    }
+                    None => 0u8.encode(buffer)?,
+                }
+            }
+        }
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        match u8::decode(cursor)? {
+            1 => Ok(MLSMessage::Plaintext(MLSPlaintext::decode(cursor)?)),
+            2 => Ok(MLSMessage::Ciphertext(MLSCiphertext::decode(cursor)?)),
+            3 => {
+                let welcome = Welcome::decode(cursor)?;
+                let ratchet_tree = match u8::decode(cursor)? {
+                    0 => None,
+                    1 => Some(decode_vec(VecSize::VecU32, cursor)?),
+                    _ => return Err(CodecError::DecodingError),
+                };
+                Ok(MLSMessage::Welcome {
+                    welcome,
+                    ratchet_tree,
+                })
+            }
+            _ => Err(CodecError::DecodingError),
+        }
+    }
+}
+
+/// Errors that can occur when messages are sent or received.
+#[derive(Debug, PartialEq, Error)]
+#[non_exhaustive]
+pub enum TransportError {
+    /// The connection failed
+    #[error("the connection failed: {0:?}")]
+    Io(io::ErrorKind),
+    /// The peer closed the connection
+    #[error("the connection was closed")]
+    Closed,
+    /// The connection ended in the middle of a frame
+    #[error("the connection ended in the middle of a message")]
+    Truncated,
+    /// A message exceeds `MAX_MESSAGE_SIZE`
+    #[error("the message is too large")]
+    MessageTooLarge,
+    /// A received message could not be decoded
+    #[error("the message could not be decoded")]
+    Codec(#[from] CodecError),
+}
+
+impl From<io::Error> for TransportError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => TransportError::Closed,
+            kind => TransportError::Io(kind),
+        }
+    }
+}
+
+/// A connection that `MLSMessage`s can be sent and received on.
+pub trait MessageTransport {
+    /// Send `message` to the peer.
+    fn send(&mut self, message: &MLSMessage) -> Result<(), TransportError>;
+    /// Wait for the next message of the peer. Returns
+    /// `TransportError::Closed` once the peer closed the connection.
+    fn receive(&mut self) -> Result<MLSMessage, TransportError>;
+}
+
+/// Frames messages on a byte stream, e.g. a TCP connection or a QUIC stream,
+/// with a 4 byte big-endian length prefix.
+pub struct StreamTransport<S: Read + Write> {
+    stream: S,
+}
+
+impl<S: Read + Write> StreamTransport<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream }
+    }
+
+    /// Get the underlying stream back.
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S: Read + Write> MessageTransport for StreamTransport<S> {
+    fn send(&mut self, message: &MLSMessage) -> Result<(), TransportError> {
+        let encoded = encode_message(message)?;
+        if encoded.len() > MAX_MESSAGE_SIZE {
+            return Err(TransportError::MessageTooLarge);
+        }
+        self.stream
+            .write_all(&(encoded.len() as u32).to_be_bytes())?;
+        self.stream.write_all(&encoded)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<MLSMessage, TransportError> {
+        let mut length = [0u8; 4];
+        // The stream may only end between two frames
+        if read_frame_part(&mut self.stream, &mut length)? == 0 {
+            return Err(TransportError::Closed);
+        }
+        let length = u32::from_be_bytes(length) as usize;
+        if length > MAX_MESSAGE_SIZE {
+            return Err(TransportError::MessageTooLarge);
+        }
+        let mut encoded = vec![0u8; length];
+        if read_frame_part(&mut self.stream, &mut encoded)? < length {
+            return Err(TransportError::Truncated);
+        }
+        decode_message(&encoded)
+    }
+}
+
+/// Fill `buffer` from `stream`. Returns 0 if the stream ended before the
+/// first byte and `TransportError::Truncated` if it ended after that.
+fn read_frame_part<S: Read>(stream: &mut S, buffer: &mut [u8]) -> Result<usize, TransportError> {
+    let mut read = 0;
+    while read < buffer.len() {
+        match stream.read(&mut buffer[read..]) {
+            Ok(0) if read == 0 => return Ok(0),
+            Ok(0) => return Err(TransportError::Truncated),
+            Ok(n) => read += n,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+    Ok(read)
+}
+
+/// Binary frames of a WebSocket connection. Implement it for the connection
+/// type of the WebSocket library in use.
+pub trait WebSocketIo {
+    /// Send `frame` as a single binary frame.
+    fn send_binary(&mut self, frame: &[u8]) -> io::Result<()>;
+    /// Wait for the next binary frame. Returns `None` once the connection was
+    /// closed. Control and text frames should be handled or skipped by the
+    /// implementation.
+    fn receive_binary(&mut self) -> io::Result<Option<Vec<u8>>>;
+}
+
+/// Sends every message as one binary frame on a WebSocket connection.
+pub struct WebSocketTransport<W: WebSocketIo> {
+    connection: W,
+}
+
+impl<W: WebSocketIo> WebSocketTransport<W> {
+    pub fn new(connection: W) -> Self {
+        Self { connection }
+    }
+
+    /// Get the underlying connection back.
+    pub fn into_inner(self) -> W {
+        self.connection
+    }
+}
+
+impl<W: WebSocketIo> MessageTransport for WebSocketTransport<W> {
+    fn send(&mut self, message: &MLSMessage) -> Result<(), TransportError> {
+        let encoded = encode_message(message)?;
+        self.connection.send_binary(&encoded)?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<MLSMessage, TransportError> {
+        match self.connection.receive_binary()? {
+            Some(frame) if frame.len() > MAX_MESSAGE_SIZE => Err(TransportError::MessageTooLarge),
+            Some(frame) => decode_message(&frame),
+            None => Err(TransportError::Closed),
+        }
+    }
+}
+
+/// Fans the messages of a group out to its members. Clients are identified
+/// by an opaque ID, e.g. the identity of their credential.
+pub trait DeliveryService {
+    /// Deliver the messages of the group `group_id` to `client` from now on.
+    fn subscribe(&mut self, group_id: &GroupId, client: &[u8]);
+    /// Stop delivering the messages of the group `group_id` to `client`.
+    fn unsubscribe(&mut self, group_id: &GroupId, client: &[u8]);
+    /// Send `message` to all subscribers of the group `group_id` except its
+    /// `sender`.
+    fn send(&mut self, group_id: &GroupId, sender: &[u8], message: MLSMessage);
+    /// Send `message` to `client` only, e.g. a `Welcome` to a new member.
+    fn send_to(&mut self, client: &[u8], message: MLSMessage);
+    /// Take the messages that are waiting for `client`.
+    fn receive(&mut self, client: &[u8]) -> Vec<MLSMessage>;
+}
+
+/// Pass the messages received on `transport` to `handler` until the peer
+/// closes the connection or `handler` returns `false`.
+pub fn receive_loop<T, H>(transport: &mut T, mut handler: H) -> Result<(), TransportError>
+where
+    T: MessageTransport + ?Sized,
+    H: FnMut(MLSMessage) -> bool,
+{
+    loop {
+        match transport.receive() {
+            Ok(message) => {
+                if !handler(message) {
+                    return Ok(());
+                }
+            }
+            Err(TransportError::Closed) => return Ok(()),
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Send all `messages` on `transport`, e.g. everything that is queued on a
+/// channel until its senders are dropped.
+pub fn send_loop<T, I>(transport: &mut T, messages: I) -> Result<(), TransportError>
+where
+    T: MessageTransport + ?Sized,
+    I: IntoIterator<Item = MLSMessage>,
+{
+    for message in messages {
+        transport.send(&message)?;
+    }
+    Ok(())
+}
+
+fn encode_message(message: &MLSMessage) -> Result<Vec<u8>, TransportError> {
+    let encoded = message.encode_detached()?;
+    if encoded.len() > MAX_MESSAGE_SIZE {
+        return Err(TransportError::MessageTooLarge);
+    }
+    Ok(encoded)
+}
+
+fn decode_message(encoded: &[u8]) -> Result<MLSMessage, TransportError> {
+    let mut cursor = Cursor::new(encoded);
+    let message = MLSMessage::decode(&mut cursor)?;
+    if cursor.has_more() {
+        return Err(TransportError::Codec(CodecError::DecodingError));
+    }
+    Ok(message)
+}
@@ -19,30 +19,36 @@ use crate::codec::*;
 use crate::schedule::*;
 use crate::tree::{index::*, sender_ratchet::*, treemath::*};
 
+use std::convert::TryFrom;
+use thiserror::Error;
+use zeroize::Zeroize;
+
 // TODO: get rif of Ciphersuite (pass it in get_secret)
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Error)]
 pub enum ASError {
+    #[error("the generation is too far in the past")]
     TooDistantInThePast,
+    #[error("the generation is too far in the future")]
     TooDistantInTheFuture,
+    #[error("the sender index is out of bounds")]
     IndexOutOfBounds,
 }
 
-pub(crate) fn derive_app_secret(
+/// `DeriveTreeSecret` from the spec: expands `secret` with the `generation`
+/// as context. Used for the keys and nonces of the sender ratchets.
+pub(crate) fn derive_tree_secret(
     ciphersuite: &Ciphersuite,
     secret: &[u8],
     label: &str,
-    node: u32,
     generation: u32,
     length: usize,
 ) -> Vec<u8> {
-    let application_context = ApplicationContext { node, generation };
-    let serialized_application_context = application_context.encode_detached().unwrap();
     hkdf_expand_label(
         ciphersuite,
         secret,
         label,
-        &serialized_application_context,
+        &generation.to_be_bytes(),
         length,
     )
 }
@@ -69,22 +75,21 @@ impl ApplicationSecrets {
     }
 }
 
-pub struct ApplicationContext {
-    node: u32,
-    generation: u32,
-}
-
-impl Codec for ApplicationContext {
-    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
-        self.node.encode(buffer)?;
-        self.generation.encode(buffer)?;
-        Ok(())
-    }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let node = u32::decode(cursor)?;
-    //     let generation = u32::decode(cursor)?;
-    //     Ok(ApplicationContext { node, generation })
-    // }
+/// Position of the sender ratchet of a member, see `MlsGroup::ratchet_state`.
+/// It doesn't contain any secrets.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SenderRatchetState {
+    /// The leaf of the sender
+    pub sender: LeafIndex,
+    /// The latest generation that was derived
+    pub generation: u32,
+    /// The number of generations up to `generation` that can still be
+    /// decrypted, at most `window_size`
+    pub window_occupancy: usize,
+    /// The size of the window for out-of-order messages
+    pub window_size: usize,
+    /// `true` if the ratchet was evicted because of the sender ratchet limit
+    pub evicted: bool,
 }
 
 #[derive(Clone)]
@@ -92,62 +97,201 @@ pub struct ASTreeNode {
     pub secret: Vec<u8>,
 }
 
+/// Secret tree of an epoch. Sender ratchets are derived when a sender is
+/// used for the first time. If a limit is set, the least recently used
+/// ratchets are evicted and re-derived from the application secret when the
+/// sender reappears.
+///
+/// The application secret is only kept while a limit is set. Anyone who
+/// obtains it can derive the secrets of all senders of the epoch, so a limit
+/// trades forward secrecy within the epoch for memory. It is never encoded;
+/// after decoding, it has to be restored with `retain_application_secret`.
+#[derive(Clone)]
 pub struct ASTree {
     nodes: Vec<Option<ASTreeNode>>,
     sender_ratchets: Vec<Option<SenderRatchet>>,
-    size: LeafIndex,
+    size: u32,
+    // Only kept while there is a limit, to re-derive evicted ratchets
+    application_secret: Option<Vec<u8>>,
+    // Maximum number of sender ratchets that are kept, 0 if there is no limit
+    max_sender_ratchets: u32,
+    // Senders with a ratchet, least recently used first. Only tracked if
+    // there is a limit.
+    recently_used: Vec<LeafIndex>,
+    // Generations of evicted sender ratchets
+    evicted_generations: Vec<Option<u32>>,
 }
 
 impl Codec for ASTree {
-    // fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
-    //     self.group.get_ciphersuite().encode(buffer)?;
-    //     encode_vec(VecSize::VecU32, buffer, &self.nodes)?;
-    //     encode_vec(VecSize::VecU32, buffer, &self.sender_ratchets)?;
-    //     self.size.encode(buffer)?;
-    //     Ok(())
-    // }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let ciphersuite = Ciphersuite::decode(cursor)?;
-    //     let nodes = decode_vec(VecSize::VecU32, cursor)?;
-    //     let sender_ratchets = decode_vec(VecSize::VecU32, cursor)?;
-    //     let size = LeafIndex::from(u32::decode(cursor)?);
-    //     Ok(ASTree {
-    //         ciphersuite,
-    //         nodes,
-    //         sender_ratchets,
-    //         size,
-    //     })
-    // }
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        encode_vec(VecSize::VecU32, buffer, &self.nodes)?;
+        encode_vec(VecSize::VecU32, buffer, &self.sender_ratchets)?;
+        self.size.encode(buffer)?;
+        self.max_sender_ratchets.encode(buffer)?;
+        encode_vec(VecSize::VecU32, buffer, &self.recently_used)?;
+        encode_vec(VecSize::VecU32, buffer, &self.evicted_generations)?;
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let nodes = decode_vec(VecSize::VecU32, cursor)?;
+        let sender_ratchets = decode_vec(VecSize::VecU32, cursor)?;
+        let size = u32::decode(cursor)?;
+        let max_sender_ratchets = u32::decode(cursor)?;
+        let recently_used = decode_vec(VecSize::VecU32, cursor)?;
+        let evicted_generations = decode_vec(VecSize::VecU32, cursor)?;
+        Ok(ASTree {
+            nodes,
+            sender_ratchets,
+            size,
+            application_secret: None,
+            max_sender_ratchets,
+            recently_used,
+            evicted_generations,
+        })
+    }
 }
 
 impl ASTree {
-    pub fn new(application_secret: &[u8], size: LeafIndex) -> Self {
+    pub fn new(application_secret: &[u8], size: u32) -> Self {
         let mut out = Self {
             nodes: vec![],
-            sender_ratchets: vec![None; size.as_usize()],
+            sender_ratchets: vec![None; size as usize],
             size,
+            application_secret: None,
+            max_sender_ratchets: 0,
+            recently_used: vec![],
+            evicted_generations: vec![None; size as usize],
         };
         out.set_application_secrets(application_secret);
         out
     }
-    pub(crate) fn set_application_secrets(&mut self, application_secret: &[u8]) {
+    /// Resize the tree to `size` leaves and re-derive it from the
+    /// `application_secret` of a new epoch. Sender ratchets of the previous
+    /// epoch are discarded.
+    pub(crate) fn resize(&mut self, application_secret: &[u8], size: u32) {
+        self.size = size;
+        self.sender_ratchets = vec![None; size as usize];
+        self.recently_used.clear();
+        self.evicted_generations = vec![None; size as usize];
+        self.set_application_secrets(application_secret);
+    }
+    fn set_application_secrets(&mut self, application_secret: &[u8]) {
         let root = root(self.size);
-        let num_indices = NodeIndex::from(self.size).as_usize() - 1;
+        // 2 * size - 1 nodes, computed without overflowing for the largest
+        // tree
+        let num_indices = self.size as usize + (self.size as usize).saturating_sub(1);
         let mut nodes = vec![None; num_indices];
         nodes[root.as_usize()] = Some(ASTreeNode {
             secret: application_secret.to_vec(),
         });
         self.nodes = nodes;
+        self.retain_application_secret(application_secret);
     }
-    pub(crate) fn set_size(&mut self, size: LeafIndex) {
-        self.size = size;
+
+    /// Keep at most `limit` sender ratchets. `None` removes the limit. The
+    /// `application_secret` of the epoch is kept while there is a limit.
+    /// When the limit is removed, evicted ratchets are re-derived before the
+    /// application secret is dropped.
+    pub(crate) fn set_max_sender_ratchets(
+        &mut self,
+        ciphersuite: &Ciphersuite,
+        limit: Option<usize>,
+        application_secret: &[u8],
+    ) -> Result<(), ASError> {
+        let previous_limit = self.max_sender_ratchets;
+        self.max_sender_ratchets = limit.map(|limit| limit.max(1) as u32).unwrap_or(0);
+        if self.max_sender_ratchets == 0 {
+            for i in 0..self.size {
+                if self.evicted_generations[i as usize].is_none() {
+                    continue;
+                }
+                if let Err(e) = self.get_sender_ratchet(ciphersuite, LeafIndex::from(i)) {
+                    // Keep the previous limit, the evicted ratchets still
+                    // need the application secret
+                    self.max_sender_ratchets = previous_limit;
+                    self.track_recently_used();
+                    return Err(e);
+                }
+            }
+        }
+        self.retain_application_secret(application_secret);
+        self.track_recently_used();
+        Ok(())
+    }
+
+    /// Rebuild the list of recently used senders from the ratchets that are
+    /// kept and evict ratchets until the limit is met.
+    fn track_recently_used(&mut self) {
+        self.recently_used = (0..self.size)
+            .filter(|i| self.sender_ratchets[*i as usize].is_some())
+            .map(LeafIndex::from)
+            .collect();
+        self.evict_sender_ratchets();
+    }
+
+    /// Keep `application_secret` if there is a limit and drop it otherwise.
+    /// Has to be called after decoding, since the secret is not encoded.
+    pub(crate) fn retain_application_secret(&mut self, application_secret: &[u8]) {
+        if let Some(mut secret) = self.application_secret.take() {
+            secret.zeroize();
+        }
+        if self.max_sender_ratchets > 0 {
+            self.application_secret = Some(application_secret.to_vec());
+        }
+    }
+
+    /// Overwrite all secrets of the tree and the sender ratchets with zeros.
+    pub(crate) fn wipe(&mut self) {
+        for node in self.nodes.iter_mut().flatten() {
+            node.secret.zeroize();
+        }
+        for sender_ratchet in self.sender_ratchets.iter_mut().flatten() {
+            sender_ratchet.wipe();
+        }
+        if let Some(secret) = &mut self.application_secret {
+            secret.zeroize();
+        }
+    }
+
+    /// Get the number of sender ratchets that are currently kept.
+    pub(crate) fn sender_ratchet_count(&self) -> usize {
+        self.sender_ratchets.iter().filter(|r| r.is_some()).count()
+    }
+
+    /// Get the state of all senders whose ratchet was used in this epoch.
+    /// Senders that are not listed are at generation 0.
+    pub(crate) fn ratchet_state(&self) -> Vec<SenderRatchetState> {
+        (0..self.size)
+            .filter_map(|i| {
+                let sender = LeafIndex::from(i);
+                let (generation, window_occupancy, evicted) = match (
+                    &self.sender_ratchets[sender.as_usize()],
+                    self.evicted_generations[sender.as_usize()],
+                ) {
+                    (Some(sender_ratchet), _) => (
+                        sender_ratchet.get_generation(),
+                        sender_ratchet.window_occupancy(),
+                        false,
+                    ),
+                    (None, Some(generation)) => (generation, 1, true),
+                    (None, None) => return None,
+                };
+                Some(SenderRatchetState {
+                    sender,
+                    generation,
+                    window_occupancy,
+                    window_size: OUT_OF_ORDER_TOLERANCE as usize,
+                    evicted,
+                })
+            })
+            .collect()
     }
 
     pub fn get_generation(&self, sender: LeafIndex) -> u32 {
         if let Some(sender_ratchet) = &self.sender_ratchets[sender.as_usize()] {
             sender_ratchet.get_generation()
         } else {
-            0
+            self.evicted_generations[sender.as_usize()].unwrap_or(0)
         }
     }
 
@@ -157,15 +301,95 @@ impl ASTree {
         index: LeafIndex,
         generation: u32,
     ) -> Result<ApplicationSecrets, ASError> {
-        let index_in_tree = NodeIndex::from(index);
-        if index >= self.size {
+        self.get_sender_ratchet(ciphersuite, index)?
+            .get_secret(generation, ciphersuite)
+    }
+
+    /// Get the secrets of `generation` of the sender `index` without
+    /// ratcheting forward. The ratchet is only advanced with `get_secret`,
+    /// e.g. once the message was verified.
+    pub fn peek_secret(
+        &mut self,
+        ciphersuite: &Ciphersuite,
+        index: LeafIndex,
+        generation: u32,
+    ) -> Result<ApplicationSecrets, ASError> {
+        self.get_sender_ratchet(ciphersuite, index)?
+            .peek_secret(generation, ciphersuite)
+    }
+
+    /// Fast-forward the sender ratchet of `index` to `generation`, discarding
+    /// the secrets of all earlier generations.
+    pub fn advance_to(
+        &mut self,
+        ciphersuite: &Ciphersuite,
+        index: LeafIndex,
+        generation: u32,
+    ) -> Result<(), ASError> {
+        self.get_sender_ratchet(ciphersuite, index)?
+            .advance_to(generation, ciphersuite)
+    }
+
+    /// Get the sender ratchet of `index`. It is derived from the tree when it
+    /// is used for the first time. Ratchets that were evicted are re-derived
+    /// from the application secret and fast-forwarded to their last
+    /// generation, so earlier generations can't be decrypted anymore.
+    fn get_sender_ratchet(
+        &mut self,
+        ciphersuite: &Ciphersuite,
+        index: LeafIndex,
+    ) -> Result<&mut SenderRatchet, ASError> {
+        if index.as_u32() >= self.size {
             return Err(ASError::IndexOutOfBounds);
         }
-        if let Some(ratchet_opt) = self.sender_ratchets.get_mut(index.as_usize()) {
-            if let Some(ratchet) = ratchet_opt {
-                return ratchet.get_secret(generation, ciphersuite);
+        let index_in_tree = NodeIndex::try_from(index).map_err(|_| ASError::IndexOutOfBounds)?;
+        if self.sender_ratchets[index.as_usize()].is_none() {
+            let node_secret = match self.evicted_generations[index.as_usize()] {
+                Some(_) => self.derive_node_secret(ciphersuite, index_in_tree)?,
+                None => self.consume_node_secret(ciphersuite, index_in_tree),
+            };
+            let ratchet_secret = hkdf_expand_label(
+                ciphersuite,
+                &node_secret,
+                "application",
+                &[],
+                ciphersuite.hash_length(),
+            );
+            let mut sender_ratchet = SenderRatchet::new(&ratchet_secret);
+            if let Some(generation) = self.evicted_generations[index.as_usize()].take() {
+                sender_ratchet.advance_to(generation, ciphersuite)?;
             }
+            self.sender_ratchets[index.as_usize()] = Some(sender_ratchet);
         }
+        if self.max_sender_ratchets > 0 {
+            self.recently_used.retain(|i| *i != index);
+            self.recently_used.push(index);
+            self.evict_sender_ratchets();
+        }
+        Ok(self.sender_ratchets[index.as_usize()].as_mut().unwrap())
+    }
+
+    /// Evict the least recently used sender ratchets until the limit is met.
+    fn evict_sender_ratchets(&mut self) {
+        if self.max_sender_ratchets == 0 {
+            return;
+        }
+        while self.recently_used.len() > self.max_sender_ratchets as usize {
+            let index = self.recently_used.remove(0);
+            if let Some(sender_ratchet) = self.sender_ratchets[index.as_usize()].take() {
+                self.evicted_generations[index.as_usize()] = Some(sender_ratchet.get_generation());
+            }
+        }
+    }
+
+    /// Get the secret of the leaf node `index_in_tree` by hashing down from
+    /// the closest ancestor that is still in the tree. The nodes on the way
+    /// are consumed.
+    fn consume_node_secret(
+        &mut self,
+        ciphersuite: &Ciphersuite,
+        index_in_tree: NodeIndex,
+    ) -> Vec<u8> {
         let mut dir_path = vec![index_in_tree];
         dir_path.extend(dirpath(index_in_tree, self.size));
         dir_path.push(root(self.size));
@@ -181,12 +405,44 @@ impl ASTree {
         for n in empty_nodes {
             self.hash_down(ciphersuite, n);
         }
-        let node_secret = &self.nodes[index_in_tree.as_usize()].clone().unwrap().secret;
-        let mut sender_ratchet = SenderRatchet::new(index, node_secret);
-        let application_secret = sender_ratchet.get_secret(generation, ciphersuite);
-        self.nodes[index_in_tree.as_usize()] = None;
-        self.sender_ratchets[index.as_usize()] = Some(sender_ratchet);
-        application_secret
+        self.nodes[index_in_tree.as_usize()].take().unwrap().secret
+    }
+
+    /// Derive the secret of the leaf node `index_in_tree` from the application
+    /// secret without modifying the tree. Fails if the application secret is
+    /// not kept anymore.
+    fn derive_node_secret(
+        &self,
+        ciphersuite: &Ciphersuite,
+        index_in_tree: NodeIndex,
+    ) -> Result<Vec<u8>, ASError> {
+        let mut secret = match &self.application_secret {
+            Some(application_secret) => application_secret.clone(),
+            None => return Err(ASError::TooDistantInThePast),
+        };
+        let mut path = dirpath(index_in_tree, self.size);
+        path.reverse();
+        path.push(index_in_tree);
+        let mut parent = root(self.size);
+        for node in path {
+            if node == parent {
+                continue;
+            }
+            let label: &[u8] = if node == left(parent) {
+                b"left"
+            } else {
+                b"right"
+            };
+            secret = hkdf_expand_label(
+                ciphersuite,
+                &secret,
+                "tree",
+                label,
+                ciphersuite.hash_length(),
+            );
+            parent = node;
+        }
+        Ok(secret)
     }
 
     fn hash_down(&mut self, ciphersuite: &Ciphersuite, index_in_tree: NodeIndex) {
@@ -194,22 +450,8 @@ impl ASTree {
         let node_secret = &self.nodes[index_in_tree.as_usize()].clone().unwrap().secret;
         let left_index = left(index_in_tree);
         let right_index = right(index_in_tree, self.size);
-        let left_secret = derive_app_secret(
-            &ciphersuite,
-            &node_secret,
-            "tree",
-            left_index.as_u32(),
-            0,
-            hash_len,
-        );
-        let right_secret = derive_app_secret(
-            &ciphersuite,
-            &node_secret,
-            "tree",
-            right_index.as_u32(),
-            0,
-            hash_len,
-        );
+        let left_secret = hkdf_expand_label(ciphersuite, node_secret, "tree", b"left", hash_len);
+        let right_secret = hkdf_expand_label(ciphersuite, node_secret, "tree", b"right", hash_len);
         self.nodes[left_index.as_usize()] = Some(ASTreeNode {
             secret: left_secret,
         });
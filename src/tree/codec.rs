@@ -1,3 +1,4 @@
+use crate::ciphersuite::signable::*;
 use crate::tree::{astree::*, node::*, *};
 
 impl Codec for NodeType {
@@ -5,28 +6,99 @@ impl Codec for NodeType {
         (*self as u8).encode(buffer)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     Ok(NodeType::from(u8::decode(cursor)?))
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        match NodeType::from(u8::decode(cursor)?) {
+            NodeType::Default => Err(CodecError::DecodingError),
+            node_type => Ok(node_type),
+        }
+    }
+}
+
+impl Codec for LeafNodeSource {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        (*self as u8).encode(buffer)?;
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        match LeafNodeSource::from(u8::decode(cursor)?) {
+            LeafNodeSource::Default => Err(CodecError::DecodingError),
+            leaf_node_source => Ok(leaf_node_source),
+        }
+    }
+}
+
+impl Codec for LeafNode {
+    // The signed fields of the `KeyPackage` and its signature, followed by the
+    // source. The signature key and the capabilities are taken from the
+    // credential and the extensions.
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        buffer.append(&mut self.unsigned_payload()?);
+        self.signature.encode(buffer)?;
+        self.leaf_node_source.encode(buffer)?;
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let protocol_version = ProtocolVersion::decode(cursor)?;
+        let cipher_suite = Ciphersuite::decode(cursor)?;
+        let encryption_key = HPKEPublicKey::decode(cursor)?;
+        let credential = Credential::decode(cursor)?;
+        let extensions: Vec<Extension> = decode_vec(VecSize::VecU16, cursor)?;
+        let signature = Signature::decode(cursor)?;
+        let leaf_node_source = LeafNodeSource::decode(cursor)?;
+        let signature_key = match &credential {
+            Credential::Basic(basic_credential) => basic_credential.public_key.clone(),
+        };
+        let capabilities = match extensions
+            .iter()
+            .find(|e| e.get_type() == ExtensionType::Capabilities)
+        {
+            Some(extension) => CapabilitiesExtension::try_from_bytes(&extension.extension_data)
+                .map_err(|_| CodecError::DecodingError)?,
+            None => CapabilitiesExtension::new(
+                vec![CURRENT_PROTOCOL_VERSION],
+                vec![cipher_suite.get_name()],
+                vec![],
+            ),
+        };
+        Ok(LeafNode {
+            encryption_key,
+            signature_key,
+            credential,
+            capabilities,
+            leaf_node_source,
+            extensions,
+            protocol_version,
+            cipher_suite,
+            signature,
+        })
+    }
 }
 
 impl Codec for Node {
     fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
         self.node_type.encode(buffer)?;
-        self.key_package.encode(buffer)?;
+        self.leaf_node.encode(buffer)?;
         self.node.encode(buffer)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let node_type = NodeType::decode(cursor)?;
-    //     let key_package = Option::<KeyPackage>::decode(cursor)?;
-    //     let node = Option::<ParentNode>::decode(cursor)?;
-    //     Ok(Node {
-    //         node_type,
-    //         key_package,
-    //         node,
-    //     })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let node_type = NodeType::decode(cursor)?;
+        let leaf_node = Option::<LeafNode>::decode(cursor)?;
+        let node = Option::<ParentNode>::decode(cursor)?;
+        // Only the content that matches the node type may be present
+        let mismatch = match node_type {
+            NodeType::Leaf => node.is_some(),
+            _ => leaf_node.is_some(),
+        };
+        if mismatch {
+            return Err(CodecError::DecodingError);
+        }
+        Ok(Node {
+            node_type,
+            leaf_node,
+            node,
+        })
+    }
 }
 
 impl Codec for PathKeypairs {
@@ -34,10 +106,10 @@ impl Codec for PathKeypairs {
         encode_vec(VecSize::VecU32, buffer, &self.keypairs)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let keypairs = decode_vec(VecSize::VecU32, cursor)?;
-    //     Ok(PathKeypairs { keypairs })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let keypairs = decode_vec(VecSize::VecU32, cursor)?;
+        Ok(PathKeypairs { keypairs })
+    }
 }
 
 impl Codec for OwnLeaf {
@@ -47,16 +119,16 @@ impl Codec for OwnLeaf {
         self.path_keypairs.encode(buffer)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let kpb = KeyPackageBundle::decode(cursor)?;
-    //     let node_index = NodeIndex::from(u32::decode(cursor)?);
-    //     let path_keypairs = PathKeypairs::decode(cursor)?;
-    //     Ok(OwnLeaf {
-    //         kpb,
-    //         node_index,
-    //         path_keypairs,
-    //     })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let kpb = KeyPackageBundle::decode(cursor)?;
+        let node_index = NodeIndex::from(u32::decode(cursor)?);
+        let path_keypairs = PathKeypairs::decode(cursor)?;
+        Ok(OwnLeaf {
+            kpb,
+            node_index,
+            path_keypairs,
+        })
+    }
 }
 
 impl Codec for RatchetTree {
@@ -66,16 +138,17 @@ impl Codec for RatchetTree {
         self.own_leaf.encode(buffer)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<RatchetTree, CodecError> {
-    //     let ciphersuite = Ciphersuite::decode(cursor)?;
-    //     let nodes = decode_vec(VecSize::VecU32, cursor)?;
-    //     let own_leaf = OwnLeaf::decode(cursor)?;
-    //     Ok(RatchetTree {
-    //         ciphersuite,
-    //         nodes,
-    //         own_leaf,
-    //     })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<RatchetTree, CodecError> {
+        let ciphersuite = Ciphersuite::decode(cursor)?;
+        let nodes = decode_vec(VecSize::VecU32, cursor)?;
+        let own_leaf = OwnLeaf::decode(cursor)?;
+        Ok(RatchetTree {
+            ciphersuite,
+            nodes,
+            own_leaf,
+            resolutions: vec![],
+        })
+    }
 }
 
 impl<'a> Codec for ParentNodeHashInput<'a> {
@@ -94,7 +167,19 @@ impl<'a> Codec for ParentNodeHashInput<'a> {
 impl<'a> Codec for LeafNodeHashInput<'a> {
     fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
         self.node_index.as_u32().encode(buffer)?;
-        self.key_package.encode(buffer)?;
+        self.leaf_node.encode(buffer)?;
+        Ok(())
+    }
+    fn decode(_cursor: &mut Cursor) -> Result<Self, CodecError> {
+        unimplemented!()
+    }
+}
+
+impl<'a> Codec for ParentHashInput<'a> {
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        self.public_key.encode(buffer)?;
+        encode_vec(VecSize::VecU8, buffer, &self.parent_hash)?;
+        encode_vec(VecSize::VecU8, buffer, &self.original_sibling_tree_hash)?;
         Ok(())
     }
     fn decode(_cursor: &mut Cursor) -> Result<Self, CodecError> {
@@ -108,14 +193,14 @@ impl Codec for DirectPathNode {
         encode_vec(VecSize::VecU32, buffer, &self.encrypted_path_secret)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let public_key = HPKEPublicKey::decode(cursor)?;
-    //     let encrypted_path_secret = decode_vec(VecSize::VecU32, cursor)?;
-    //     Ok(DirectPathNode {
-    //         public_key,
-    //         encrypted_path_secret,
-    //     })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let public_key = HPKEPublicKey::decode(cursor)?;
+        let encrypted_path_secret = decode_vec(VecSize::VecU32, cursor)?;
+        Ok(DirectPathNode {
+            public_key,
+            encrypted_path_secret,
+        })
+    }
 }
 
 impl Codec for DirectPath {
@@ -124,14 +209,14 @@ impl Codec for DirectPath {
         encode_vec(VecSize::VecU16, buffer, &self.nodes)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let leaf_key_package = KeyPackage::decode(cursor)?;
-    //     let nodes = decode_vec(VecSize::VecU16, cursor)?;
-    //     Ok(DirectPath {
-    //         leaf_key_package,
-    //         nodes,
-    //     })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let leaf_key_package = KeyPackage::decode(cursor)?;
+        let nodes = decode_vec(VecSize::VecU16, cursor)?;
+        Ok(DirectPath {
+            leaf_key_package,
+            nodes,
+        })
+    }
 }
 
 // ASTree Codecs
@@ -141,8 +226,8 @@ impl Codec for ASTreeNode {
         encode_vec(VecSize::VecU8, buffer, &self.secret)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let secret = decode_vec(VecSize::VecU8, cursor)?;
-    //     Ok(ASTreeNode { secret })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let secret = decode_vec(VecSize::VecU8, cursor)?;
+        Ok(ASTreeNode { secret })
+    }
 }
@@ -1,4 +1,8 @@
 use crate::codec::*;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::num::TryFromIntError;
 
 #[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Copy, Clone)]
 pub struct NodeIndex(u32);
@@ -18,15 +22,20 @@ impl From<u32> for NodeIndex {
     }
 }
 
-impl From<usize> for NodeIndex {
-    fn from(i: usize) -> NodeIndex {
-        NodeIndex(i as u32)
+impl TryFrom<usize> for NodeIndex {
+    type Error = TryFromIntError;
+    fn try_from(i: usize) -> Result<NodeIndex, TryFromIntError> {
+        Ok(NodeIndex(u32::try_from(i)?))
     }
 }
 
-impl From<LeafIndex> for NodeIndex {
-    fn from(node_index: LeafIndex) -> NodeIndex {
-        NodeIndex(node_index.as_u32() * 2)
+/// Fails if the leaf is beyond the largest tree with `u32` node indices.
+impl TryFrom<LeafIndex> for NodeIndex {
+    type Error = TryFromIntError;
+    fn try_from(leaf_index: LeafIndex) -> Result<NodeIndex, TryFromIntError> {
+        Ok(NodeIndex(u32::try_from(
+            u64::from(leaf_index.as_u32()) * 2,
+        )?))
     }
 }
 
@@ -40,6 +49,29 @@ impl LeafIndex {
     pub fn as_usize(self) -> usize {
         self.0 as usize
     }
+    /// Get the index of the leaf at `node_index`. Returns `None` if
+    /// `node_index` is a parent node.
+    pub fn try_from_node_index(node_index: NodeIndex) -> Option<LeafIndex> {
+        if node_index.as_u32() % 2 != 0 {
+            return None;
+        }
+        Some(LeafIndex(node_index.as_u32() / 2))
+    }
+    /// Get the number of leaves of a tree with `node_count` nodes, including
+    /// a trailing blank leaf. Tree sizes are leaf counts. Fails if the nodes
+    /// of the tree don't fit into `u32` node indices, which can only happen
+    /// on 64-bit targets.
+    pub fn leaf_count(node_count: usize) -> Result<u32, TryFromIntError> {
+        let node_count = u32::try_from(node_count)?;
+        Ok(node_count / 2 + node_count % 2)
+    }
+}
+
+impl TryFrom<usize> for LeafIndex {
+    type Error = TryFromIntError;
+    fn try_from(i: usize) -> Result<LeafIndex, TryFromIntError> {
+        Ok(LeafIndex(u32::try_from(i)?))
+    }
 }
 
 impl From<u32> for LeafIndex {
@@ -60,20 +92,11 @@ impl Into<usize> for LeafIndex {
     }
 }
 
-impl From<usize> for LeafIndex {
-    fn from(i: usize) -> LeafIndex {
-        LeafIndex(i as u32)
-    }
-}
-
-impl From<NodeIndex> for LeafIndex {
-    fn from(tree_index: NodeIndex) -> LeafIndex {
-        LeafIndex((tree_index.as_u32() + 1) / 2)
-    }
-}
-
 impl Codec for LeafIndex {
     fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
         self.0.encode(buffer)
     }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        Ok(LeafIndex(u32::decode(cursor)?))
+    }
 }
@@ -16,12 +16,17 @@
 
 use rayon::prelude::*;
 
-use crate::ciphersuite::{signable::*, *};
+use crate::ciphersuite::*;
 use crate::codec::*;
+use crate::creds::*;
 use crate::extensions::*;
+use crate::group::{PathValidationError, ProposalValidationError};
 use crate::key_packages::*;
 use crate::messages::{proposals::*, *};
 use crate::schedule::*;
+use crate::validator::*;
+use std::collections::HashMap;
+use std::convert::TryFrom;
 
 // Tree modules
 pub(crate) mod astree;
@@ -34,8 +39,18 @@ pub(crate) mod treemath;
 use index::*;
 use node::*;
 
+/// Subtrees at or above this level are hashed on several threads if the
+/// `parallel` feature is enabled. Below, spawning tasks costs more than the
+/// hashes.
+const PARALLEL_HASH_LEVEL: usize = 6;
+
+/// Trees built from nodes are checked to have node indices and Add proposals
+/// are rejected before the tree would outgrow them.
+const TREE_SIZE_INVARIANT: &str = "the nodes of the tree have node indices";
+
 // Internal tree tests
 mod test_astree;
+mod test_tree;
 mod test_treemath;
 
 // TODO improve the storage memory footprint
@@ -61,6 +76,12 @@ impl PathKeypairs {
             self.keypairs[index.as_usize()] = Some(keypairs[i].clone());
         }
     }
+    /// Overwrite all private keys with zeros.
+    pub(crate) fn wipe(&mut self) {
+        for keypair in self.keypairs.iter_mut().flatten() {
+            keypair.wipe();
+        }
+    }
     pub fn get(&self, index: NodeIndex) -> Option<&HPKEKeyPair> {
         if index.as_usize() >= self.keypairs.len() {
             return None;
@@ -145,11 +166,23 @@ impl OwnLeaf {
     }
 }
 
+/// Parent nodes on the direct path of a leaf and the tree hashes of their
+/// other children, starting at the leaf
+pub type MembershipPath = Vec<(Option<ParentNode>, Vec<u8>)>;
+
+/// Membership changes, invited members with their position in the tree and
+/// whether the own leaf was removed
+pub type ApplyProposalsValues = (MembershipChanges, Vec<(NodeIndex, AddProposal)>, bool);
+
 #[derive(Debug, Clone)]
 pub struct RatchetTree {
     ciphersuite: Ciphersuite,
-    pub nodes: Vec<Node>,
+    // Only modified through methods that keep the resolutions up to date
+    nodes: Vec<Node>,
     pub own_leaf: OwnLeaf,
+    // Resolutions of the nodes, computed on demand. The entries of a node and
+    // its ancestors are reset when the node changes.
+    resolutions: Vec<Option<Vec<NodeIndex>>>,
 }
 
 impl RatchetTree {
@@ -157,13 +190,17 @@ impl RatchetTree {
         let own_leaf = OwnLeaf::new(kpb, NodeIndex::from(0u32), PathKeypairs::new());
         let nodes = vec![Node {
             node_type: NodeType::Leaf,
-            key_package: Some(own_leaf.kpb.get_key_package().clone()),
+            leaf_node: Some(LeafNode::from_key_package(
+                own_leaf.kpb.get_key_package(),
+                LeafNodeSource::KeyPackage,
+            )),
             node: None,
         }];
         RatchetTree {
             ciphersuite,
             nodes,
             own_leaf,
+            resolutions: vec![],
         }
     }
     pub(crate) fn new_from_nodes(
@@ -171,20 +208,16 @@ impl RatchetTree {
         kpb: KeyPackageBundle,
         node_options: &[Option<Node>],
     ) -> Option<RatchetTree> {
-        fn find_kp_in_tree(key_package: &KeyPackage, nodes: &[Option<Node>]) -> Option<NodeIndex> {
-            for (i, node_option) in nodes.iter().enumerate() {
-                if let Some(node) = node_option {
-                    if let Some(kp) = &node.key_package {
-                        if kp == key_package {
-                            return Some(NodeIndex::from(i));
-                        }
-                    }
-                }
-            }
-            None
-        }
-
-        let index = find_kp_in_tree(kpb.get_key_package(), node_options)?;
+        // All nodes need node indices
+        NodeIndex::try_from(node_options.len()).ok()?;
+        let index = find_leaf(
+            node_options.iter().map(|node_option| {
+                node_option
+                    .as_ref()
+                    .and_then(|node| node.leaf_node.as_ref())
+            }),
+            kpb.get_key_package(),
+        )?;
 
         let mut nodes = Vec::with_capacity(node_options.len());
         for (i, node_option) in node_options.iter().enumerate() {
@@ -196,28 +229,31 @@ impl RatchetTree {
                 nodes.push(Node::new_blank_parent_node());
             }
         }
-        let secret = kpb.get_private_key().as_slice();
-        let dirpath = treemath::dirpath_root(index, NodeIndex::from(nodes.len()).into());
-        let (path_secrets, _commit_secret) =
-            OwnLeaf::generate_path_secrets(&ciphersuite, secret, dirpath.len());
-        let keypairs = OwnLeaf::generate_path_keypairs(&ciphersuite, &path_secrets);
-        let mut path_keypairs = PathKeypairs::new();
-        path_keypairs.add(&keypairs, &dirpath);
-        let own_leaf = OwnLeaf::new(kpb, index, path_keypairs);
+        // The keys of the direct path are only known once a path secret was
+        // received
+        let own_leaf = OwnLeaf::new(kpb, index, PathKeypairs::new());
         Some(RatchetTree {
             ciphersuite,
             nodes,
             own_leaf,
+            resolutions: vec![],
         })
     }
     fn tree_size(&self) -> NodeIndex {
-        NodeIndex::from(self.nodes.len())
+        NodeIndex::try_from(self.nodes.len()).expect(TREE_SIZE_INVARIANT)
     }
     pub(crate) fn get_own_index(&self) -> NodeIndex {
         self.own_leaf.node_index
     }
 
-    pub(crate) fn public_key_tree(&self) -> Vec<Option<Node>> {
+    /// Overwrite the private keys of the own leaf and its path with zeros.
+    pub(crate) fn wipe_own_secrets(&mut self) {
+        self.own_leaf.kpb.wipe();
+        self.own_leaf.path_keypairs.wipe();
+    }
+
+    /// Get the public part of the tree, e.g. to send it to new members.
+    pub fn public_key_tree(&self) -> Vec<Option<Node>> {
         let mut tree = vec![];
         for node in self.nodes.iter() {
             if node.is_blank() {
@@ -229,37 +265,75 @@ impl RatchetTree {
         tree
     }
 
-    pub(crate) fn leaf_count(&self) -> LeafIndex {
-        self.tree_size().into()
+    /// Copy the tree for an observer that must not hold the secrets of the
+    /// own leaf. The own leaf keeps its position, but its private key is
+    /// replaced with an unrelated one and the keys of its path are dropped,
+    /// so the observer can't decrypt path secrets.
+    pub(crate) fn observer_copy(&self) -> RatchetTree {
+        let keypair = self.ciphersuite.new_hpke_keypair();
+        let kpb = KeyPackageBundle::from_values(
+            self.own_leaf.kpb.get_key_package().clone(),
+            keypair.get_private_key().clone(),
+        );
+        RatchetTree {
+            ciphersuite: self.ciphersuite,
+            nodes: self.nodes.clone(),
+            own_leaf: OwnLeaf::new(kpb, self.own_leaf.node_index, PathKeypairs::new()),
+            resolutions: vec![],
+        }
     }
 
-    fn resolve(&self, index: NodeIndex) -> Vec<NodeIndex> {
-        let size = self.leaf_count();
+    pub(crate) fn leaf_count(&self) -> u32 {
+        LeafIndex::leaf_count(self.nodes.len()).expect(TREE_SIZE_INVARIANT)
+    }
 
-        if self.nodes[index.as_usize()].node_type == NodeType::Leaf {
-            if self.nodes[index.as_usize()].is_blank() {
-                return vec![];
-            } else {
-                return vec![index];
-            }
-        }
+    /// Get the index of the leaf that was created from `key_package`, if the
+    /// key package is still in the tree.
+    pub(crate) fn find_leaf(&self, key_package: &KeyPackage) -> Option<LeafIndex> {
+        let node_index = find_leaf(
+            self.nodes.iter().map(|node| node.leaf_node.as_ref()),
+            key_package,
+        )?;
+        LeafIndex::try_from_node_index(node_index)
+    }
 
-        if !self.nodes[index.as_usize()].is_blank() {
-            let mut unmerged_leaves = vec![index];
-            let node = &self.nodes[index.as_usize()].node.as_ref();
-            unmerged_leaves.extend(
-                node.unwrap()
-                    .get_unmerged_leaves()
-                    .iter()
-                    .map(|n| NodeIndex::from(*n)),
-            );
-            return unmerged_leaves;
+    /// Get the resolution of the node at `index`. Resolutions are cached, the
+    /// resolutions of blank parent nodes are built from the ones of their
+    /// children.
+    fn resolve(&mut self, index: NodeIndex) -> Vec<NodeIndex> {
+        // The tree changed its size, all resolutions can have changed
+        if self.resolutions.len() != self.nodes.len() {
+            self.resolutions = vec![None; self.nodes.len()];
+        }
+        if let Some(resolution) = &self.resolutions[index.as_usize()] {
+            return resolution.clone();
+        }
+        let node = &self.nodes[index.as_usize()];
+        let resolution = if node.node_type == NodeType::Parent && node.is_blank() {
+            let mut left = self.resolve(treemath::left(index));
+            left.extend(self.resolve(treemath::right(index, self.leaf_count())));
+            left
+        } else {
+            resolve(&self.nodes, index, self.leaf_count())
+        };
+        self.resolutions[index.as_usize()] = Some(resolution.clone());
+        resolution
+    }
+    /// Reset the cached resolutions of the node at `index` and its ancestors.
+    /// Has to be called whenever a node changes.
+    fn invalidate_resolutions(&mut self, index: NodeIndex) {
+        let size = self.leaf_count();
+        let root = treemath::root(size);
+        let mut index = index;
+        loop {
+            if let Some(resolution) = self.resolutions.get_mut(index.as_usize()) {
+                *resolution = None;
+            }
+            if index == root {
+                break;
+            }
+            index = treemath::parent(index, size);
         }
-
-        let mut left = self.resolve(treemath::left(index));
-        let right = self.resolve(treemath::right(index, size));
-        left.extend(right);
-        left
     }
     pub(crate) fn blank_member(&mut self, index: NodeIndex) {
         let size = self.leaf_count();
@@ -268,61 +342,117 @@ impl RatchetTree {
         for index in treemath::dirpath(index, size) {
             self.nodes[index.as_usize()].blank();
         }
+        self.invalidate_resolutions(index);
     }
-    pub(crate) fn free_leaves(&self) -> Vec<NodeIndex> {
-        let mut free_leaves = vec![];
-        for i in 0..self.leaf_count().as_usize() {
-            // TODO use an iterator instead
-            if self.nodes[NodeIndex::from(i).as_usize()].is_blank() {
-                free_leaves.push(NodeIndex::from(i));
-            }
+    /// Returns `true` if `index` is a non-blank leaf within the tree.
+    pub(crate) fn is_member(&self, index: NodeIndex) -> bool {
+        match self.nodes.get(index.as_usize()) {
+            Some(node) => node.node_type == NodeType::Leaf && !node.is_blank(),
+            None => false,
         }
-        free_leaves
+    }
+    /// Get the nodes of the tree.
+    pub(crate) fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+    /// Get the leaf node of the member at `leaf_index`. Returns `None` if the
+    /// leaf is blank or not in the tree.
+    pub(crate) fn get_leaf_node(&self, leaf_index: LeafIndex) -> Option<&LeafNode> {
+        NodeIndex::try_from(leaf_index)
+            .ok()
+            .and_then(|index| self.nodes.get(index.as_usize()))
+            .and_then(|node| node.leaf_node.as_ref())
+    }
+    /// Get the number of members, i.e. of non-blank leaves.
+    pub fn member_count(&self) -> usize {
+        self.leaf_count() as usize - self.free_leaf_count()
+    }
+    /// Get the number of blank leaves that can be filled by new members
+    /// without growing the tree.
+    pub fn free_leaf_count(&self) -> usize {
+        self.free_leaves().count()
+    }
+    /// Returns `true` if there is no member at `leaf_index`. Leaves beyond
+    /// the end of the tree are blank.
+    pub fn is_leaf_blank(&self, leaf_index: LeafIndex) -> bool {
+        NodeIndex::try_from(leaf_index).map_or(true, |index| !self.is_member(index))
+    }
+    /// Iterate over the blank leaves from left to right.
+    pub(crate) fn free_leaves(&self) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.free_leaves_from(LeafIndex::from(0u32))
+    }
+    /// Iterate over the blank leaves from `leaf_index` on.
+    fn free_leaves_from(&self, leaf_index: LeafIndex) -> impl Iterator<Item = NodeIndex> + '_ {
+        // Leaves of the tree always have a node index
+        (leaf_index.as_u32()..self.leaf_count())
+            .filter_map(|i| NodeIndex::try_from(LeafIndex::from(i)).ok())
+            .filter(move |index| self.nodes[index.as_usize()].is_blank())
     }
 
+    /// Decrypt the path secret of the Commit from `sender` and merge its
+    /// `direct_path` into the tree. The path is fully validated before the tree
+    /// is modified, so the tree is unchanged if an error is returned.
     pub(crate) fn update_direct_path(
         &mut self,
         sender: LeafIndex,
         direct_path: &DirectPath,
         group_context: &[u8],
-    ) -> CommitSecret {
+    ) -> Result<CommitSecret, PathValidationError> {
+        trace_span!("update_direct_path", sender = sender.as_u32());
         let own_index = self.own_leaf.node_index;
-        // TODO check that the direct path is long enough
+        let sender_index =
+            NodeIndex::try_from(sender).map_err(|_| PathValidationError::InvalidSender)?;
+        if sender_index >= self.tree_size() || sender_index == own_index {
+            return Err(PathValidationError::InvalidSender);
+        }
 
-        // Find common ancestor of own leaf and sender leaf
-        let common_ancestor =
-            treemath::common_ancestor(NodeIndex::from(sender), self.own_leaf.node_index);
+        // Calculate sender direct path & copath and check that there is a
+        // node in the path for each of them
+        let sender_dirpath = treemath::dirpath_root(sender_index, self.leaf_count());
+        let sender_copath = treemath::copath(sender_index, self.leaf_count());
+        if direct_path.nodes.len() != sender_dirpath.len()
+            || direct_path.nodes.len() != sender_copath.len()
+        {
+            return Err(PathValidationError::InvalidPathLength);
+        }
 
-        // Calculate sender direct path & copath, common path
-        let sender_dirpath = treemath::dirpath_root(NodeIndex::from(sender), self.leaf_count());
-        let sender_copath = treemath::copath(NodeIndex::from(sender), self.leaf_count());
+        // Every copath node must have one ciphertext per node in its
+        // resolution
+        for (path_node, copath_node) in direct_path.nodes.iter().zip(sender_copath.iter()) {
+            if path_node.encrypted_path_secret.len() != self.resolve(*copath_node).len() {
+                return Err(PathValidationError::ResolutionSizeMismatch);
+            }
+        }
+
+        // Find common ancestor of own leaf and sender leaf
+        let common_ancestor = treemath::common_ancestor(sender_index, own_index);
 
         // Find the position of the common ancestor in the sender's direct path
         let common_ancestor_sender_dirpath_index = sender_dirpath
             .iter()
             .position(|x| *x == common_ancestor)
-            .unwrap();
+            .ok_or(PathValidationError::InvalidPathLength)?;
         let common_ancestor_copath_index = sender_copath[common_ancestor_sender_dirpath_index];
 
         // Resolve the node of that copath index
         let resolution = self.resolve(common_ancestor_copath_index);
         let position_in_resolution = resolution
             .iter()
-            .position(|x| *x == self.own_leaf.node_index)
-            .unwrap_or(0);
-        // TODO Check resolution.len() == encrypted_path_secret.len()
+            .position(|x| *x == own_index || self.own_leaf.path_keypairs.get(*x).is_some())
+            .ok_or(PathValidationError::OwnLeafNotInResolution)?;
 
         // Decrypt the ciphertext of that node
         let hpke_ciphertext = &direct_path.nodes[common_ancestor_sender_dirpath_index]
             .encrypted_path_secret[position_in_resolution];
 
         // Check whether the secret was encrypted to the leaf node
-        let private_key = if resolution[position_in_resolution] == own_index {
+        let resolution_node = resolution[position_in_resolution];
+        let private_key = if resolution_node == own_index {
             self.own_leaf.kpb.get_private_key()
         } else {
             self.own_leaf
                 .path_keypairs
-                .get(common_ancestor_copath_index)
+                .get(resolution_node)
                 .unwrap()
                 .get_private_key()
         };
@@ -333,34 +463,39 @@ impl RatchetTree {
         // Decrypt the secret and derive path secrets
         let secret = self
             .ciphersuite
-            .hpke_open(hpke_ciphertext, &private_key, group_context, &[]);
+            .hpke_open(hpke_ciphertext, &private_key, group_context, &[])
+            .map_err(|_| PathValidationError::DecryptionFailure)?;
         let (path_secrets, commit_secret) =
             OwnLeaf::continue_path_secrets(&self.ciphersuite, &secret, common_path.len());
         let keypairs = OwnLeaf::generate_path_keypairs(&self.ciphersuite, &path_secrets);
         let sender_path_offset = sender_dirpath.len() - common_path.len();
 
-        // Generate keypairs from the path secrets
-        for (i, keypair) in keypairs.iter().enumerate().take(common_path.len()) {
-            // TODO return an error if public keys don't match
-            assert_eq!(
-                &direct_path.nodes[sender_path_offset + i].public_key,
-                keypair.get_public_key()
-            );
+        // The derived public keys have to match the ones in the path
+        for (i, keypair) in keypairs.iter().enumerate() {
+            if &direct_path.nodes[sender_path_offset + i].public_key != keypair.get_public_key() {
+                return Err(PathValidationError::PublicKeyMismatch);
+            }
         }
 
         // Merge new nodes and path secrets
         self.merge_public_keys(direct_path, sender_dirpath);
         self.own_leaf.path_keypairs.add(&keypairs, &common_path);
         self.merge_keypairs(&keypairs, &common_path);
-        self.nodes[NodeIndex::from(sender).as_usize()] =
-            Node::new_leaf(Some(direct_path.leaf_key_package.clone()));
-        self.compute_parent_hash(NodeIndex::from(sender));
-        commit_secret
+        self.nodes[sender_index.as_usize()] = Node::new_leaf(Some(LeafNode::from_key_package(
+            &direct_path.leaf_key_package,
+            LeafNodeSource::Commit,
+        )));
+        self.invalidate_resolutions(sender_index);
+        self.compute_parent_hash(sender_index);
+        Ok(commit_secret)
     }
+    /// Replace the own leaf with `kpb` and derive a new path from
+    /// `leaf_secret`, which must be fresh randomness for every Commit.
     pub(crate) fn update_own_leaf(
         &mut self,
         signature_key_option: Option<&SignaturePrivateKey>,
         kpb: KeyPackageBundle,
+        leaf_secret: &[u8],
         group_context: &[u8],
         with_direct_path: bool,
     ) -> (
@@ -369,35 +504,37 @@ impl RatchetTree {
         Option<DirectPath>,
         Option<Vec<Vec<u8>>>,
     ) {
-        // Extract the private key from the KeyPackageBundle
-        let private_key = kpb.get_private_key();
-
+        trace_span!("update_own_leaf");
         // Compute the direct path and keypairs along it
         let own_index = self.own_leaf.node_index;
         let dirpath_root = treemath::dirpath_root(own_index, self.leaf_count());
-        let node_secret = private_key.as_slice();
         let (path_secrets, confirmation) =
-            OwnLeaf::generate_path_secrets(&self.ciphersuite, &node_secret, dirpath_root.len());
+            OwnLeaf::generate_path_secrets(&self.ciphersuite, leaf_secret, dirpath_root.len());
         let keypairs = OwnLeaf::generate_path_keypairs(&self.ciphersuite, &path_secrets);
         self.merge_keypairs(&keypairs, &dirpath_root);
 
+        // Compute the parent hashes along the new path
+        let parent_hash = self.compute_parent_hash(own_index);
+
         // Check if we need to add the parent hash extension and re-sign the KeyPackage
         let key_package_bundle = match signature_key_option {
             Some(signature_key) => {
-                // Compute the parent hash extension and add it to the KeyPackage
-                let parent_hash = self.compute_parent_hash(own_index);
+                // Add the parent hash extension to the KeyPackage
                 let parent_hash_extension = ParentHashExtension::new(&parent_hash).to_extension();
                 let mut key_package = kpb.get_key_package().clone();
                 key_package.add_extension(parent_hash_extension);
-                key_package.sign(&self.ciphersuite, signature_key);
+                key_package.update_signature(signature_key);
                 KeyPackageBundle::from_values(key_package, kpb.get_private_key().clone())
             }
             None => kpb,
         };
 
         // Update own leaf node with the new values
-        self.nodes[own_index.as_usize()] =
-            Node::new_leaf(Some(key_package_bundle.get_key_package().clone()));
+        self.nodes[own_index.as_usize()] = Node::new_leaf(Some(LeafNode::from_key_package(
+            key_package_bundle.get_key_package(),
+            LeafNodeSource::Commit,
+        )));
+        self.invalidate_resolutions(own_index);
         let mut path_keypairs = PathKeypairs::new();
         path_keypairs.add(&keypairs, &dirpath_root);
         let own_leaf = OwnLeaf::new(key_package_bundle.clone(), own_index, path_keypairs);
@@ -418,8 +555,31 @@ impl RatchetTree {
             (confirmation, key_package_bundle, None, None)
         }
     }
+    /// Replace the own leaf with `kpb` and set the path from the
+    /// `path_secrets` an own Commit was created with, without deriving them
+    /// again.
+    pub(crate) fn apply_own_path_secrets(
+        &mut self,
+        kpb: KeyPackageBundle,
+        path_secrets: &[Vec<u8>],
+    ) {
+        trace_span!("apply_own_path_secrets");
+        let own_index = self.own_leaf.node_index;
+        let dirpath_root = treemath::dirpath_root(own_index, self.leaf_count());
+        let keypairs = OwnLeaf::generate_path_keypairs(&self.ciphersuite, path_secrets);
+        self.merge_keypairs(&keypairs, &dirpath_root);
+        self.compute_parent_hash(own_index);
+        self.nodes[own_index.as_usize()] = Node::new_leaf(Some(LeafNode::from_key_package(
+            kpb.get_key_package(),
+            LeafNodeSource::Commit,
+        )));
+        self.invalidate_resolutions(own_index);
+        let mut path_keypairs = PathKeypairs::new();
+        path_keypairs.add(&keypairs, &dirpath_root);
+        self.own_leaf = OwnLeaf::new(kpb, own_index, path_keypairs);
+    }
     pub fn encrypt_to_copath(
-        &self,
+        &mut self,
         path_secrets: Vec<Vec<u8>>,
         keypairs: Vec<HPKEKeyPair>,
         group_context: &[u8],
@@ -432,15 +592,34 @@ impl RatchetTree {
         let mut ciphertexts = vec![];
         for pair in path_secrets.iter().zip(copath.iter()) {
             let (path_secret, copath_node) = pair;
-            let node_ciphertexts: Vec<HpkeCiphertext> = self
-                .resolve(*copath_node)
-                .par_iter()
-                .map(|&x| {
+            let resolution = self.resolve(*copath_node);
+            // Every copath node has its own path secret and every ciphertext
+            // has to be decryptable on its own, so encapsulations can't be
+            // shared between nodes. A key only repeats within a resolution if
+            // several leaves use the same init key, those share the ciphertext.
+            let mut distinct_keys: Vec<&HPKEPublicKey> = vec![];
+            let mut key_positions: HashMap<&[u8], usize> = HashMap::new();
+            let positions: Vec<usize> = resolution
+                .iter()
+                .map(|x| {
                     let pk = self.nodes[x.as_usize()].get_public_hpke_key().unwrap();
+                    *key_positions.entry(pk.as_slice()).or_insert_with(|| {
+                        distinct_keys.push(pk);
+                        distinct_keys.len() - 1
+                    })
+                })
+                .collect();
+            let distinct_ciphertexts: Vec<HpkeCiphertext> = distinct_keys
+                .par_iter()
+                .map(|pk| {
                     self.ciphersuite
-                        .hpke_seal(&pk, group_context, &[], &path_secret)
+                        .hpke_seal(pk, group_context, &[], &path_secret)
                 })
                 .collect();
+            let node_ciphertexts: Vec<HpkeCiphertext> = positions
+                .iter()
+                .map(|&position| distinct_ciphertexts[position].clone())
+                .collect();
             // TODO Check that all public keys are non-empty
             // TODO Handle potential errors
             ciphertexts.push(node_ciphertexts);
@@ -463,6 +642,7 @@ impl RatchetTree {
             let public_key = direct_path.nodes[i].clone().public_key;
             let node = ParentNode::new(public_key.clone(), &[], &[]);
             self.nodes[p.as_usize()].node = Some(node);
+            self.invalidate_resolutions(*p);
         }
     }
     pub fn merge_keypairs(&mut self, keypairs: &[HPKEKeyPair], path: &[NodeIndex]) {
@@ -470,15 +650,91 @@ impl RatchetTree {
         for i in 0..path.len() {
             let node = ParentNode::new(keypairs[i].get_public_key().clone(), &[], &[]);
             self.nodes[path[i].as_usize()].node = Some(node);
+            self.invalidate_resolutions(path[i]);
         }
     }
     pub fn apply_proposals(
         &mut self,
+        committer: LeafIndex,
         proposal_id_list: &ProposalIDList,
         proposal_queue: ProposalQueue,
-        pending_kpbs: Vec<KeyPackageBundle>,
-    ) -> (MembershipChanges, Vec<(NodeIndex, AddProposal)>, bool) {
+        pending_kpbs: &HashMap<KeyPackageRef, KeyPackageBundle>,
+    ) -> Result<ApplyProposalsValues, ProposalValidationError> {
+        trace_span!("apply_proposals", committer = committer.as_u32());
+        // Validate Update and Remove proposals against the current tree before
+        // anything is modified
+        let committer_credential = match self.get_leaf_node(committer) {
+            Some(leaf_node) => leaf_node.get_credential().clone(),
+            None => return Err(ProposalValidationError::CommitterNotAMember),
+        };
+        // Every reference has to resolve to a queued proposal of the right
+        // type, so that the proposals can be applied below without checks
+        for u in proposal_id_list.updates.iter() {
+            let (_proposal_id, queued_proposal) = proposal_queue
+                .get(u)
+                .ok_or(ProposalValidationError::UnknownProposal)?;
+            let update_proposal = queued_proposal
+                .proposal
+                .as_update()
+                .ok_or(ProposalValidationError::UnknownProposal)?;
+            let old_leaf_node = self
+                .get_leaf_node(queued_proposal.sender.as_leaf_index())
+                .ok_or(ProposalValidationError::UpdateFromNonMember)?;
+            validate_device_capabilities(Some(old_leaf_node), &update_proposal.key_package)?;
+            if queued_proposal.sender.as_node_index() == Some(self.own_leaf.node_index)
+                && !pending_kpbs.contains_key(&update_proposal.key_package.key_package_ref())
+            {
+                return Err(ProposalValidationError::MissingOwnKeyPackageBundle);
+            }
+        }
+        let mut removed_leaves = Vec::with_capacity(proposal_id_list.removes.len());
+        for r in proposal_id_list.removes.iter() {
+            let (_proposal_id, queued_proposal) = proposal_queue
+                .get(r)
+                .ok_or(ProposalValidationError::UnknownProposal)?;
+            let remove_proposal = queued_proposal
+                .proposal
+                .as_remove()
+                .ok_or(ProposalValidationError::UnknownProposal)?;
+            // Every leaf can only be removed once
+            if removed_leaves.contains(&remove_proposal.removed) {
+                return Err(ProposalValidationError::DuplicateRemove);
+            }
+            removed_leaves.push(remove_proposal.removed);
+            let removed = self
+                .get_leaf_node(LeafIndex::from(remove_proposal.removed))
+                .ok_or(ProposalValidationError::RemoveTargetNotAMember)?;
+            validate_removable(removed)?;
+        }
+        for a in proposal_id_list.adds.iter() {
+            let (_proposal_id, queued_proposal) = proposal_queue
+                .get(a)
+                .ok_or(ProposalValidationError::UnknownProposal)?;
+            let add_proposal = queued_proposal
+                .proposal
+                .as_add()
+                .ok_or(ProposalValidationError::UnknownProposal)?;
+            let sender = queued_proposal.sender;
+            let granter = if sender.is_member() {
+                self.get_leaf_node(sender.as_leaf_index())
+            } else {
+                None
+            };
+            validate_device_capabilities(granter, &add_proposal.key_package)?;
+        }
+        // Even if every joiner is appended, the nodes of the tree need node
+        // indices
+        let max_node_count = proposal_id_list
+            .adds
+            .len()
+            .checked_mul(2)
+            .and_then(|added_nodes| added_nodes.checked_add(self.nodes.len()));
+        if max_node_count.map_or(true, |node_count| NodeIndex::try_from(node_count).is_err()) {
+            return Err(ProposalValidationError::TreeFull);
+        }
+
         let mut updated_members = vec![];
+        let mut capability_changes = vec![];
         let mut removed_members = vec![];
         let mut added_members = Vec::with_capacity(proposal_id_list.adds.len());
         let mut invited_members = Vec::with_capacity(proposal_id_list.adds.len());
@@ -490,16 +746,35 @@ impl RatchetTree {
             let proposal = &queued_proposal.proposal;
             let update_proposal = proposal.as_update().unwrap();
             let sender = queued_proposal.sender;
-            let index = sender.as_node_index();
-            let leaf_node = Node::new_leaf(Some(update_proposal.key_package.clone()));
-            updated_members.push(update_proposal.key_package.get_credential().clone());
+            let index = sender
+                .as_node_index()
+                .ok_or(ProposalValidationError::UpdateFromNonMember)?;
+            let leaf_node =
+                LeafNode::from_key_package(&update_proposal.key_package, LeafNodeSource::Update);
+            if let Some(old_leaf_node) = &self.nodes[index.as_usize()].leaf_node {
+                let old_capabilities = get_device_capabilities(old_leaf_node);
+                let new_capabilities =
+                    get_key_package_device_capabilities(&update_proposal.key_package);
+                if old_capabilities != new_capabilities {
+                    capability_changes.push(CapabilityChange {
+                        index: sender.sender,
+                        old_capabilities,
+                        new_capabilities,
+                    });
+                }
+                updated_members.push(MemberUpdate {
+                    index: sender.sender,
+                    old_leaf_node: old_leaf_node.clone(),
+                    new_leaf_node: leaf_node.clone(),
+                });
+            }
             self.blank_member(index);
-            self.nodes[index.as_usize()] = leaf_node;
+            self.nodes[index.as_usize()] = Node::new_leaf(Some(leaf_node));
+            self.invalidate_resolutions(index);
             if index == self.own_leaf.node_index {
                 let own_kpb = pending_kpbs
-                    .iter()
-                    .find(|&kpb| kpb.get_key_package() == &update_proposal.key_package)
-                    .unwrap();
+                    .get(&update_proposal.key_package.key_package_ref())
+                    .ok_or(ProposalValidationError::MissingOwnKeyPackageBundle)?;
                 self.own_leaf = OwnLeaf::new(own_kpb.clone(), index, PathKeypairs::new());
             }
         }
@@ -507,25 +782,25 @@ impl RatchetTree {
             let (_proposal_id, queued_proposal) = proposal_queue.get(&r).unwrap();
             let proposal = &queued_proposal.proposal;
             let remove_proposal = proposal.as_remove().unwrap();
-            let removed = NodeIndex::from(remove_proposal.removed);
+            let removed = NodeIndex::try_from(LeafIndex::from(remove_proposal.removed))
+                .map_err(|_| ProposalValidationError::RemoveTargetNotAMember)?;
             if removed == self.own_leaf.node_index {
                 self_removed = true;
             }
-            let removed_member_node = self.nodes[removed.as_usize()].clone();
-            let removed_member = if let Some(key_package) = removed_member_node.key_package {
-                key_package
-            } else {
-                // TODO check it's really a leaf node
-                panic!("Cannot remove a parent/empty node")
-            };
-            removed_members.push(removed_member.get_credential().clone());
+            // The target was validated above
+            if let Some(leaf_node) = &self.nodes[removed.as_usize()].leaf_node {
+                removed_members.push(MemberChange {
+                    index: LeafIndex::from(remove_proposal.removed),
+                    leaf_node: leaf_node.clone(),
+                });
+            }
             self.blank_member(removed);
         }
 
         if !proposal_id_list.adds.is_empty() {
-            if proposal_id_list.adds.len() > (2 * self.leaf_count().as_usize()) {
+            if proposal_id_list.adds.len() > (2 * self.leaf_count() as usize) {
                 self.nodes.reserve_exact(
-                    (2 * proposal_id_list.adds.len()) - (2 * self.leaf_count().as_usize()),
+                    (2 * proposal_id_list.adds.len()) - (2 * self.leaf_count() as usize),
                 );
             }
             let add_proposals: Vec<AddProposal> = proposal_id_list
@@ -538,166 +813,625 @@ impl RatchetTree {
                 })
                 .collect();
 
-            let free_leaves = self.free_leaves();
-            // TODO make sure intermediary nodes are updated with unmerged_leaves
-            let (add_in_place, add_append) = add_proposals.split_at(free_leaves.len());
-            for (add_proposal, leaf_index) in add_in_place.iter().zip(free_leaves) {
-                self.nodes[leaf_index.as_usize()] =
-                    Node::new_leaf(Some(add_proposal.key_package.clone()));
+            // Joiners are placed in the leftmost free leaves, including leaves
+            // that were freed by Remove proposals in the same Commit, in the
+            // order of the Add proposals. Joiners that don't fit are appended.
+            let mut next_free_leaf = LeafIndex::from(0u32);
+            let mut new_leaves = Vec::with_capacity(add_proposals.len());
+            for add_proposal in add_proposals.iter() {
+                let free_leaf = self.free_leaves_from(next_free_leaf).next();
+                let leaf_index = match free_leaf {
+                    Some(leaf_index) => leaf_index,
+                    None => {
+                        self.nodes.push(Node::new_blank_parent_node());
+                        self.nodes.push(Node::new_leaf(None));
+                        self.resolutions.clear();
+                        NodeIndex::try_from(self.nodes.len() - 1)
+                            .map_err(|_| ProposalValidationError::TreeFull)?
+                    }
+                };
+                let leaf_node = LeafNode::from_key_package(
+                    &add_proposal.key_package,
+                    LeafNodeSource::KeyPackage,
+                );
+                self.nodes[leaf_index.as_usize()] = Node::new_leaf(Some(leaf_node.clone()));
+                self.invalidate_resolutions(leaf_index);
+                next_free_leaf = LeafIndex::try_from_node_index(leaf_index).unwrap();
+                new_leaves.push(leaf_index);
+                added_members.push(MemberChange {
+                    index: next_free_leaf,
+                    leaf_node,
+                });
+                invited_members.push((leaf_index, add_proposal.clone()));
+            }
+
+            // Add the new leaves to the unmerged leaves of their non-blank
+            // ancestors. This is done once the tree has its final size.
+            for leaf_index in new_leaves {
                 let dirpath = treemath::dirpath_root(leaf_index, self.leaf_count());
                 for d in dirpath.iter() {
-                    if !self.nodes[d.as_usize()].is_blank() {
-                        let node = &self.nodes[d.as_usize()];
-                        let index = d.as_u32();
-                        // TODO handle error
-                        let mut parent_node = node.node.clone().unwrap();
+                    if let Some(parent_node) = self.nodes[d.as_usize()].node.as_mut() {
+                        let index = leaf_index.as_u32();
                         if !parent_node.get_unmerged_leaves().contains(&index) {
                             parent_node.get_unmerged_leaves_mut().push(index);
                         }
-                        self.nodes[d.as_usize()].node = Some(parent_node);
                     }
                 }
-                added_members.push(add_proposal.key_package.get_credential().clone());
-                invited_members.push((leaf_index, add_proposal.clone()));
-            }
-            let mut new_nodes = Vec::with_capacity(proposal_id_list.adds.len() * 2);
-            let mut leaf_index = self.nodes.len() + 1;
-            for add_proposal in add_append.iter() {
-                new_nodes.extend(vec![
-                    Node::new_blank_parent_node(),
-                    Node::new_leaf(Some(add_proposal.key_package.clone())),
-                ]);
-                added_members.push(add_proposal.key_package.get_credential().clone());
-                invited_members.push((NodeIndex::from(leaf_index), add_proposal.clone()));
-                leaf_index += 2;
+                self.invalidate_resolutions(leaf_index);
             }
-            self.nodes.extend(new_nodes);
-            self.trim_tree();
         }
-        (
+        // Truncate the tree to the rightmost non-blank leaf
+        self.trim_tree();
+        Ok((
             MembershipChanges {
+                committer,
+                committer_credential,
                 updates: updated_members,
                 removes: removed_members,
                 adds: added_members,
+                capability_changes,
             },
             invited_members,
             self_removed,
-        )
+        ))
     }
     pub fn trim_tree(&mut self) {
         let mut new_tree_size = 0;
 
-        for i in 0..self.nodes.len() {
+        for i in (0..self.nodes.len()).step_by(2) {
             if !self.nodes[i].is_blank() {
                 new_tree_size = i + 1;
             }
         }
 
-        if new_tree_size > 0 {
+        if new_tree_size > 0 && new_tree_size < self.nodes.len() {
             self.nodes.truncate(new_tree_size);
+            self.resolutions.clear();
         }
     }
     pub fn compute_tree_hash(&self) -> Vec<u8> {
-        fn node_hash(ciphersuite: &Ciphersuite, tree: &RatchetTree, index: NodeIndex) -> Vec<u8> {
-            let node = &tree.nodes[index.as_usize()];
-            match node.node_type {
+        let size = self.leaf_count();
+        subtree_hash(&self.ciphersuite, &self.nodes, size, treemath::root(size))
+    }
+    /// Get the leaf node at `leaf_index` together with what is needed to
+    /// recompute the tree hash from it: the parent nodes on its direct path
+    /// and the tree hashes of their other children, starting at the leaf.
+    /// Returns `None` if the leaf is blank or out of range.
+    pub(crate) fn membership_path(
+        &self,
+        leaf_index: LeafIndex,
+    ) -> Option<(LeafNode, MembershipPath)> {
+        let size = self.leaf_count();
+        if leaf_index.as_u32() >= size {
+            return None;
+        }
+        let mut index = NodeIndex::try_from(leaf_index).ok()?;
+        let leaf_node = self.nodes[index.as_usize()].leaf_node.clone()?;
+        let root = treemath::root(size);
+        let mut path = vec![];
+        while index != root {
+            let parent = treemath::parent(index, size);
+            let left = treemath::left(parent);
+            let sibling = if left == index {
+                treemath::right(parent, size)
+            } else {
+                left
+            };
+            let sibling_hash = subtree_hash(&self.ciphersuite, &self.nodes, size, sibling);
+            path.push((self.nodes[parent.as_usize()].node.clone(), sibling_hash));
+            index = parent;
+        }
+        Some((leaf_node, path))
+    }
+    /// Compute the parent hashes of the nodes on the direct path of `index`,
+    /// store them in the parent nodes and return the parent hash of `index`.
+    /// The parent hash of the root is empty and blank parent nodes are
+    /// skipped.
+    pub fn compute_parent_hash(&mut self, index: NodeIndex) -> Vec<u8> {
+        let leaf_ends = leaf_ends(&self.nodes);
+        self.set_parent_hashes(index, &leaf_ends)
+    }
+    fn set_parent_hashes(&mut self, index: NodeIndex, leaf_ends: &[usize]) -> Vec<u8> {
+        let size = self.leaf_count();
+        if index == treemath::root(size) {
+            return vec![];
+        }
+        let parent = treemath::parent(index, size);
+        let parent_parent_hash = self.set_parent_hashes(parent, leaf_ends);
+        match self.nodes[parent.as_usize()].node.as_mut() {
+            Some(parent_node) => parent_node.set_parent_hash(parent_parent_hash),
+            None => return parent_parent_hash,
+        }
+        let original_tree = OriginalTree {
+            ciphersuite: &self.ciphersuite,
+            nodes: &self.nodes,
+            hashes: &[],
+            leaf_ends,
+        };
+        original_tree
+            .parent_hash(parent, index, self.nodes.len())
+            .unwrap()
+    }
+    /// Verify that all leaves are signed correctly and that every non-blank
+    /// parent node has a child whose parent hash matches it.
+    pub fn verify_integrity(ciphersuite: &Ciphersuite, nodes: &[Option<Node>]) -> bool {
+        let mut verifier = match TreeVerifier::new(*ciphersuite, nodes) {
+            Some(verifier) => verifier,
+            None => return false,
+        };
+        // The parent hashes are checked against the tree hashes of the
+        // subtrees, which are computed once up front
+        verifier.hash_nodes(usize::MAX);
+        let verify = |index: u32| verifier.verify_node(NodeIndex::from(index));
+        // The parent hashes of different nodes are independent of each other
+        if cfg!(feature = "parallel") {
+            (0..verifier.node_count.as_u32())
+                .into_par_iter()
+                .all(verify)
+        } else {
+            (0..verifier.node_count.as_u32()).all(verify)
+        }
+    }
+}
+
+/// Checks an exported tree a bounded number of nodes at a time, so that a
+/// large tree can be checked without blocking the caller for long.
+pub(crate) struct TreeVerifier {
+    ciphersuite: Ciphersuite,
+    nodes: Vec<Node>,
+    node_count: NodeIndex,
+    leaf_count: u32,
+    // Nodes are hashed level by level, so that the hashes of the children of
+    // a node are known when it is hashed
+    hashes: Vec<Vec<u8>>,
+    hash_level: usize,
+    next_hashed: usize,
+    hashed: usize,
+    leaf_ends: Vec<usize>,
+    verified: usize,
+}
+
+impl TreeVerifier {
+    /// Start checking `nodes` as returned by `RatchetTree::public_key_tree`.
+    /// Returns `None` if the number of nodes doesn't describe a tree.
+    pub(crate) fn new(ciphersuite: Ciphersuite, nodes: &[Option<Node>]) -> Option<Self> {
+        if nodes.len() % 2 != 1 {
+            return None;
+        }
+        let node_count = NodeIndex::try_from(nodes.len()).ok()?;
+        let leaf_count = LeafIndex::leaf_count(nodes.len()).ok()?;
+        let nodes = fill_blank_nodes(nodes);
+        let leaf_ends = leaf_ends(&nodes);
+        Some(TreeVerifier {
+            ciphersuite,
+            hashes: vec![vec![]; nodes.len()],
+            nodes,
+            node_count,
+            leaf_count,
+            hash_level: 0,
+            next_hashed: 0,
+            hashed: 0,
+            leaf_ends,
+            verified: 0,
+        })
+    }
+
+    /// Get the number of nodes that were hashed.
+    pub(crate) fn hashed(&self) -> usize {
+        self.hashed
+    }
+
+    /// Get the number of nodes whose signature or parent hash was checked.
+    pub(crate) fn verified(&self) -> usize {
+        self.verified
+    }
+
+    /// Hash up to `max_nodes` more nodes. Returns the tree hash once all
+    /// nodes were hashed.
+    pub(crate) fn hash_nodes(&mut self, max_nodes: usize) -> Option<Vec<u8>> {
+        let mut remaining = max_nodes;
+        while self.hashed < self.nodes.len() && remaining > 0 {
+            if self.next_hashed >= self.nodes.len() {
+                self.hash_level += 1;
+                self.next_hashed = (1 << self.hash_level) - 1;
+                continue;
+            }
+            let index = NodeIndex::try_from(self.next_hashed).ok()?;
+            let node = &self.nodes[index.as_usize()];
+            self.hashes[index.as_usize()] = match node.node_type {
                 NodeType::Leaf => {
-                    let leaf_node_hash = LeafNodeHashInput::new(&index, &node.key_package);
-                    leaf_node_hash.hash(ciphersuite)
+                    LeafNodeHashInput::new(&index, &node.leaf_node).hash(&self.ciphersuite)
                 }
-                NodeType::Parent => {
+                _ => {
                     let left = treemath::left(index);
-                    let left_hash = node_hash(ciphersuite, tree, left);
-                    let right = treemath::right(index, tree.leaf_count());
-                    let right_hash = node_hash(ciphersuite, tree, right);
-                    let parent_node_hash = ParentNodeHashInput::new(
+                    let right = treemath::right(index, self.leaf_count);
+                    ParentNodeHashInput::new(
                         index.as_u32(),
                         &node.node,
-                        &left_hash,
-                        &right_hash,
-                    );
-                    parent_node_hash.hash(ciphersuite)
+                        &self.hashes[left.as_usize()],
+                        &self.hashes[right.as_usize()],
+                    )
+                    .hash(&self.ciphersuite)
                 }
-                NodeType::Default => panic!("Default node type not supported in tree hash."),
-            }
+            };
+            self.next_hashed += 2 << self.hash_level;
+            self.hashed += 1;
+            remaining -= 1;
+        }
+        if self.hashed < self.nodes.len() {
+            return None;
         }
-        let root = treemath::root(self.leaf_count());
-        node_hash(&self.ciphersuite, &self, root)
+        let root = treemath::root(self.leaf_count);
+        Some(self.hashes[root.as_usize()].clone())
     }
-    pub fn compute_parent_hash(&mut self, index: NodeIndex) -> Vec<u8> {
-        let parent = treemath::parent(index, self.leaf_count());
-        let parent_hash = if parent == treemath::root(self.leaf_count()) {
-            let root_node = &self.nodes[parent.as_usize()];
-            root_node.hash(&self.ciphersuite).unwrap()
-        } else {
-            self.compute_parent_hash(parent)
-        };
-        let current_node = &self.nodes[index.as_usize()];
-        if let Some(mut parent_node) = current_node.node.clone() {
-            parent_node.set_parent_hash(parent_hash);
-            self.nodes[index.as_usize()].node = Some(parent_node);
-            let updated_parent_node = &self.nodes[index.as_usize()];
-            updated_parent_node.hash(&self.ciphersuite).unwrap()
+
+    /// Check the signatures and parent hashes of up to `max_nodes` more
+    /// nodes. Returns the result once all nodes were checked or a node is
+    /// invalid. This is fastest once all nodes were hashed with `hash_nodes`.
+    pub(crate) fn verify_nodes(&mut self, max_nodes: usize) -> Option<bool> {
+        let end = self
+            .nodes
+            .len()
+            .min(self.verified.saturating_add(max_nodes));
+        while self.verified < end {
+            let index = NodeIndex::try_from(self.verified).ok()?;
+            if !self.verify_node(index) {
+                return Some(false);
+            }
+            self.verified += 1;
+        }
+        if self.verified < self.nodes.len() {
+            None
         } else {
-            parent_hash
+            Some(true)
         }
     }
-    pub fn verify_integrity(ciphersuite: &Ciphersuite, nodes: &[Option<Node>]) -> bool {
-        let node_count = NodeIndex::from(nodes.len());
-        let size = node_count;
-        for i in 0..node_count.as_usize() {
-            let node_option = &nodes[i];
-            if let Some(node) = node_option {
-                match node.node_type {
-                    NodeType::Parent => {
-                        let left_index = treemath::left(NodeIndex::from(i));
-                        let right_index = treemath::right(NodeIndex::from(i), size.into());
-                        if right_index >= node_count {
-                            return false;
-                        }
-                        let left_option = &nodes[left_index.as_usize()];
-                        let right_option = &nodes[right_index.as_usize()];
-                        let own_hash = node.hash(ciphersuite).unwrap();
-                        if let Some(right) = right_option {
-                            if let Some(left) = left_option {
-                                let left_parent_hash = left.parent_hash().unwrap_or_else(Vec::new);
-                                let right_parent_hash =
-                                    right.parent_hash().unwrap_or_else(Vec::new);
-                                if (left_parent_hash != own_hash) && (right_parent_hash != own_hash)
-                                {
-                                    return false;
-                                }
-                                if left_parent_hash == right_parent_hash {
-                                    return false;
-                                }
-                            } else if right.parent_hash().unwrap() != own_hash {
-                                return false;
-                            }
-                        } else if let Some(left) = left_option {
-                            if left.parent_hash().unwrap() != own_hash {
-                                return false;
-                            }
-                        }
+
+    /// Check the leaf signature or the parent hash of the node at `index`.
+    fn verify_node(&self, index: NodeIndex) -> bool {
+        let nodes = &self.nodes;
+        let original_tree = OriginalTree {
+            ciphersuite: &self.ciphersuite,
+            nodes,
+            hashes: if self.hashed == nodes.len() {
+                &self.hashes[..]
+            } else {
+                &[]
+            },
+            leaf_ends: &self.leaf_ends,
+        };
+        let node = &nodes[index.as_usize()];
+        match node.node_type {
+            NodeType::Parent => {
+                if node.is_blank() {
+                    return true;
+                }
+                if index.as_u32() % 2 != 1 {
+                    return false;
+                }
+                let left = treemath::left(index);
+                let right = treemath::right(index, self.leaf_count);
+                if right >= self.node_count {
+                    return false;
+                }
+                // The node that holds the parent hash can be below blank
+                // parent nodes that were inserted when the tree grew
+                let matches = |child: NodeIndex| {
+                    let parent_hashes = original_tree.valid_parent_hashes(index, child);
+                    resolve(nodes, child, self.leaf_count)
+                        .iter()
+                        .any(
+                            |descendant| match nodes[descendant.as_usize()].parent_hash() {
+                                Some(parent_hash) => parent_hashes.contains(&parent_hash),
+                                None => false,
+                            },
+                        )
+                };
+                if !matches(left) && !matches(right) {
+                    return false;
+                }
+            }
+            NodeType::Leaf => {
+                if let Some(leaf_node) = &node.leaf_node {
+                    if index.as_u32() % 2 != 0 {
+                        return false;
                     }
-                    NodeType::Leaf => {
-                        if let Some(kp) = &node.key_package {
-                            if i % 2 != 0 {
-                                return false;
-                            }
-                            if !kp.verify() {
-                                return false;
-                            }
-                        }
+                    // Leaves carry the signature of the KeyPackage they were
+                    // created from
+                    if !leaf_node.verify() {
+                        return false;
                     }
-
-                    NodeType::Default => {}
                 }
             }
+            NodeType::Default => {}
         }
         true
     }
 }
 
+/// Get the resolution of the node at `index`: the node itself and its unmerged
+/// leaves if it is not blank, the resolutions of its children otherwise.
+fn resolve(nodes: &[Node], index: NodeIndex, size: u32) -> Vec<NodeIndex> {
+    if nodes[index.as_usize()].node_type == NodeType::Leaf {
+        if nodes[index.as_usize()].is_blank() {
+            return vec![];
+        } else {
+            return vec![index];
+        }
+    }
+
+    if !nodes[index.as_usize()].is_blank() {
+        let mut unmerged_leaves = vec![index];
+        let node = &nodes[index.as_usize()].node.as_ref();
+        unmerged_leaves.extend(
+            node.unwrap()
+                .get_unmerged_leaves()
+                .iter()
+                .map(|n| NodeIndex::from(*n)),
+        );
+        return unmerged_leaves;
+    }
+
+    let mut left = resolve(nodes, treemath::left(index), size);
+    let right = resolve(nodes, treemath::right(index, size), size);
+    left.extend(right);
+    left
+}
+
+/// Replace the blank nodes of an exported tree with blank leaf and parent
+/// nodes.
+fn fill_blank_nodes(nodes: &[Option<Node>]) -> Vec<Node> {
+    nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node_option)| match node_option {
+            Some(node) => node.clone(),
+            None if i % 2 == 0 => Node::new_leaf(None),
+            None => Node::new_blank_parent_node(),
+        })
+        .collect()
+}
+
+/// Compute the tree hash of an exported tree as returned by
+/// `RatchetTree::public_key_tree`. Returns `None` if the number of nodes
+/// doesn't describe a tree.
+pub(crate) fn public_tree_hash(
+    ciphersuite: &Ciphersuite,
+    nodes: &[Option<Node>],
+) -> Option<Vec<u8>> {
+    if nodes.len() % 2 != 1 {
+        return None;
+    }
+    let leaf_count = LeafIndex::leaf_count(nodes.len()).ok()?;
+    let root = treemath::root(leaf_count);
+    Some(subtree_hash(
+        ciphersuite,
+        &fill_blank_nodes(nodes),
+        leaf_count,
+        root,
+    ))
+}
+
+/// Compute the tree hash of the subtree of `nodes` rooted at `index`, where
+/// `size` is the leaf count of `nodes`.
+fn subtree_hash(ciphersuite: &Ciphersuite, nodes: &[Node], size: u32, index: NodeIndex) -> Vec<u8> {
+    let node = &nodes[index.as_usize()];
+    match node.node_type {
+        NodeType::Leaf => {
+            let leaf_node_hash = LeafNodeHashInput::new(&index, &node.leaf_node);
+            leaf_node_hash.hash(ciphersuite)
+        }
+        NodeType::Parent => {
+            let left = treemath::left(index);
+            let right = treemath::right(index, size);
+            let (left_hash, right_hash) =
+                if cfg!(feature = "parallel") && treemath::level(index) >= PARALLEL_HASH_LEVEL {
+                    rayon::join(
+                        || subtree_hash(ciphersuite, nodes, size, left),
+                        || subtree_hash(ciphersuite, nodes, size, right),
+                    )
+                } else {
+                    (
+                        subtree_hash(ciphersuite, nodes, size, left),
+                        subtree_hash(ciphersuite, nodes, size, right),
+                    )
+                };
+            let parent_node_hash =
+                ParentNodeHashInput::new(index.as_u32(), &node.node, &left_hash, &right_hash);
+            parent_node_hash.hash(ciphersuite)
+        }
+        NodeType::Default => panic!("Default node type not supported in tree hash."),
+    }
+}
+
+/// Compute the tree hash of a tree with `leaf_count` leaves from the leaf
+/// node at `leaf_index` and its path as returned by
+/// `RatchetTree::membership_path`. Returns `None` if the path doesn't lead to
+/// the root.
+pub(crate) fn membership_path_tree_hash(
+    ciphersuite: &Ciphersuite,
+    leaf_count: u32,
+    leaf_index: LeafIndex,
+    leaf_node: &LeafNode,
+    path: &[(Option<ParentNode>, Vec<u8>)],
+) -> Option<Vec<u8>> {
+    if leaf_index.as_u32() >= leaf_count {
+        return None;
+    }
+    // All leaves of the tree need node indices
+    NodeIndex::try_from(LeafIndex::from(leaf_count - 1)).ok()?;
+    let root = treemath::root(leaf_count);
+    let mut index = NodeIndex::try_from(leaf_index).ok()?;
+    let mut hash = LeafNodeHashInput::new(&index, &Some(leaf_node.clone())).hash(ciphersuite);
+    for (parent_node, sibling_hash) in path {
+        if index == root {
+            return None;
+        }
+        let parent = treemath::parent(index, leaf_count);
+        let (left_hash, right_hash) = if treemath::left(parent) == index {
+            (&hash, sibling_hash)
+        } else {
+            (sibling_hash, &hash)
+        };
+        hash = ParentNodeHashInput::new(parent.as_u32(), parent_node, left_hash, right_hash)
+            .hash(ciphersuite);
+        index = parent;
+    }
+    if index != root {
+        return None;
+    }
+    Some(hash)
+}
+
+/// Get the number of nodes up to and including the last non-blank leaf
+/// before every node of `nodes`, and before the end of `nodes`.
+fn leaf_ends(nodes: &[Node]) -> Vec<usize> {
+    let mut leaf_ends = Vec::with_capacity(nodes.len() + 1);
+    let mut leaf_end = 0;
+    for (i, node) in nodes.iter().enumerate() {
+        leaf_ends.push(leaf_end);
+        if i % 2 == 0 && !node.is_blank() {
+            leaf_end = i + 1;
+        }
+    }
+    leaf_ends.push(leaf_end);
+    leaf_ends
+}
+
+/// A tree as seen by the parent hashes of its parent nodes. When a parent
+/// node was set, its unmerged leaves weren't part of the tree yet and the
+/// tree could have been smaller. The subtrees that didn't change since reuse
+/// the tree `hashes` of the current tree, so that checking all parent hashes
+/// takes linear time.
+struct OriginalTree<'a> {
+    ciphersuite: &'a Ciphersuite,
+    nodes: &'a [Node],
+    // The tree hashes of all nodes, or empty if they are not known
+    hashes: &'a [Vec<u8>],
+    // As returned by `leaf_ends`
+    leaf_ends: &'a [usize],
+}
+
+impl<'a> OriginalTree<'a> {
+    /// Compute the parent hashes that the `child` of `parent` may carry. If
+    /// the tree ended within the subtree of `parent` when the hash was
+    /// computed, the sibling of `child` didn't include the leaves that were
+    /// added to the right since.
+    fn valid_parent_hashes(&self, parent: NodeIndex, child: NodeIndex) -> Vec<Vec<u8>> {
+        let mut parent_hashes: Vec<Vec<u8>> = self
+            .parent_hash(parent, child, self.nodes.len())
+            .into_iter()
+            .collect();
+        let subtree_end = parent.as_usize() + (1 << treemath::level(parent));
+        if subtree_end < self.nodes.len() {
+            parent_hashes.extend(self.parent_hash(parent, child, subtree_end));
+        }
+        parent_hashes
+    }
+
+    /// Compute the parent hash that the `child` of `parent` must carry, i.e.
+    /// the hash of the public key and parent hash of `parent` together with
+    /// the original tree hash of the sibling of `child`, if the tree ended
+    /// at node `end`. Returns `None` if `parent` is blank.
+    fn parent_hash(&self, parent: NodeIndex, child: NodeIndex, end: usize) -> Option<Vec<u8>> {
+        let parent_node = self.nodes[parent.as_usize()].node.as_ref()?;
+        let unmerged_leaves = parent_node.get_unmerged_leaves();
+        let node_count = self.original_node_count(parent, end, unmerged_leaves);
+        let size = LeafIndex::leaf_count(node_count).ok()?;
+        let left = treemath::left(parent);
+        let sibling = if child == left {
+            treemath::right(parent, size)
+        } else {
+            left
+        };
+        let original_sibling_tree_hash =
+            self.original_subtree_hash(sibling, size, node_count, unmerged_leaves);
+        let parent_hash_input = ParentHashInput::new(
+            parent_node.get_public_key(),
+            parent_node.get_parent_hash(),
+            &original_sibling_tree_hash,
+        );
+        Some(parent_hash_input.hash(self.ciphersuite))
+    }
+
+    /// Get the number of nodes of the tree ending at node `end` before the
+    /// `unmerged_leaves` of `parent` were added: the tree is truncated after
+    /// the last remaining leaf, but keeps `parent` and a leaf to its right.
+    fn original_node_count(&self, parent: NodeIndex, end: usize, unmerged_leaves: &[u32]) -> usize {
+        if unmerged_leaves.is_empty() {
+            return end;
+        }
+        let mut leaf_end = self.leaf_ends[end];
+        while leaf_end > 0 && unmerged_leaves.contains(&((leaf_end - 1) as u32)) {
+            leaf_end = self.leaf_ends[leaf_end - 1];
+        }
+        leaf_end.max(1).max(parent.as_usize() + 2)
+    }
+
+    /// Compute the tree hash of the subtree at `index` of the original tree
+    /// with `size` leaves and `node_count` nodes, where the `unmerged_leaves`
+    /// are blank and removed from the unmerged leaves of all parent nodes.
+    fn original_subtree_hash(
+        &self,
+        index: NodeIndex,
+        size: u32,
+        node_count: usize,
+        unmerged_leaves: &[u32],
+    ) -> Vec<u8> {
+        let level = treemath::level(index);
+        let subtree_start = index.as_usize() + 1 - (1 << level);
+        let subtree_end = index.as_usize() + (1 << level);
+        let unchanged = subtree_end <= node_count
+            && !unmerged_leaves
+                .iter()
+                .any(|leaf| (subtree_start..subtree_end).contains(&(*leaf as usize)));
+        if unchanged && self.hashes.len() == self.nodes.len() {
+            return self.hashes[index.as_usize()].clone();
+        }
+        let node = &self.nodes[index.as_usize()];
+        let removed = unmerged_leaves.contains(&index.as_u32());
+        match node.node_type {
+            NodeType::Leaf => {
+                let leaf_node = if removed {
+                    None
+                } else {
+                    node.leaf_node.clone()
+                };
+                LeafNodeHashInput::new(&index, &leaf_node).hash(self.ciphersuite)
+            }
+            NodeType::Parent => {
+                let left = treemath::left(index);
+                let right = treemath::right(index, size);
+                let (left_hash, right_hash) =
+                    if cfg!(feature = "parallel") && level >= PARALLEL_HASH_LEVEL {
+                        rayon::join(
+                            || self.original_subtree_hash(left, size, node_count, unmerged_leaves),
+                            || self.original_subtree_hash(right, size, node_count, unmerged_leaves),
+                        )
+                    } else {
+                        (
+                            self.original_subtree_hash(left, size, node_count, unmerged_leaves),
+                            self.original_subtree_hash(right, size, node_count, unmerged_leaves),
+                        )
+                    };
+                let parent_node = match &node.node {
+                    Some(parent_node) if !removed => {
+                        let mut parent_node = parent_node.clone();
+                        parent_node
+                            .get_unmerged_leaves_mut()
+                            .retain(|leaf| !unmerged_leaves.contains(leaf));
+                        Some(parent_node)
+                    }
+                    _ => None,
+                };
+                ParentNodeHashInput::new(index.as_u32(), &parent_node, &left_hash, &right_hash)
+                    .hash(self.ciphersuite)
+            }
+            NodeType::Default => panic!("Default node type not supported in tree hash."),
+        }
+    }
+}
+
 pub struct ParentNodeHashInput<'a> {
     node_index: u32,
     parent_node: &'a Option<ParentNode>,
@@ -725,16 +1459,52 @@ impl<'a> ParentNodeHashInput<'a> {
     }
 }
 
+/// Get the index of the node whose leaf was created from `key_package`.
+fn find_leaf<'a>(
+    mut leaf_nodes: impl Iterator<Item = Option<&'a LeafNode>>,
+    key_package: &KeyPackage,
+) -> Option<NodeIndex> {
+    let position = leaf_nodes.position(|leaf_node| match leaf_node {
+        Some(leaf_node) => leaf_node.is_from_key_package(key_package),
+        None => false,
+    })?;
+    NodeIndex::try_from(position).ok()
+}
+
 pub struct LeafNodeHashInput<'a> {
     node_index: &'a NodeIndex,
-    key_package: &'a Option<KeyPackage>,
+    leaf_node: &'a Option<LeafNode>,
 }
 
 impl<'a> LeafNodeHashInput<'a> {
-    pub fn new(node_index: &'a NodeIndex, key_package: &'a Option<KeyPackage>) -> Self {
+    pub fn new(node_index: &'a NodeIndex, leaf_node: &'a Option<LeafNode>) -> Self {
         Self {
             node_index,
-            key_package,
+            leaf_node,
+        }
+    }
+    pub fn hash(&self, ciphersuite: &Ciphersuite) -> Vec<u8> {
+        let payload = self.encode_detached().unwrap();
+        ciphersuite.hash(&payload)
+    }
+}
+
+pub struct ParentHashInput<'a> {
+    public_key: &'a HPKEPublicKey,
+    parent_hash: &'a [u8],
+    original_sibling_tree_hash: &'a [u8],
+}
+
+impl<'a> ParentHashInput<'a> {
+    pub fn new(
+        public_key: &'a HPKEPublicKey,
+        parent_hash: &'a [u8],
+        original_sibling_tree_hash: &'a [u8],
+    ) -> Self {
+        Self {
+            public_key,
+            parent_hash,
+            original_sibling_tree_hash,
         }
     }
     pub fn hash(&self, ciphersuite: &Ciphersuite) -> Vec<u8> {
@@ -1,5 +1,6 @@
-use crate::ciphersuite::*;
+use crate::ciphersuite::{signable::*, *};
 use crate::codec::*;
+use crate::creds::*;
 use crate::extensions::*;
 use crate::key_packages::*;
 
@@ -21,33 +22,152 @@ impl From<u8> for NodeType {
     }
 }
 
+/// Where the content of a `LeafNode` comes from.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[repr(u8)]
+pub enum LeafNodeSource {
+    /// The `KeyPackage` of an Add proposal
+    KeyPackage = 1,
+    /// The `KeyPackage` of an Update proposal
+    Update = 2,
+    /// The leaf `KeyPackage` of a Commit path
+    Commit = 3,
+    Default = 255,
+}
+
+impl From<u8> for LeafNodeSource {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => LeafNodeSource::KeyPackage,
+            2 => LeafNodeSource::Update,
+            3 => LeafNodeSource::Commit,
+            _ => LeafNodeSource::Default,
+        }
+    }
+}
+
+/// The content of a leaf in the tree. It is converted from the `KeyPackage`
+/// a member was added or updated with. It keeps the signed fields and the
+/// signature of the `KeyPackage`, so that the leaf can be verified when the
+/// tree is received from another member.
+#[derive(Debug, PartialEq, Clone)]
+pub struct LeafNode {
+    pub(crate) encryption_key: HPKEPublicKey,
+    pub(crate) signature_key: SignaturePublicKey,
+    pub(crate) credential: Credential,
+    pub(crate) capabilities: CapabilitiesExtension,
+    pub(crate) leaf_node_source: LeafNodeSource,
+    // All extensions of the `KeyPackage` in their signed order, including the
+    // capabilities
+    pub(crate) extensions: Vec<Extension>,
+    pub(crate) protocol_version: ProtocolVersion,
+    pub(crate) cipher_suite: Ciphersuite,
+    pub(crate) signature: Signature,
+}
+
+impl LeafNode {
+    /// Create a `LeafNode` from a `KeyPackage`. The HPKE init key becomes the
+    /// encryption key of the leaf. If the `KeyPackage` has no
+    /// `CapabilitiesExtension`, only its own protocol version and ciphersuite
+    /// are assumed.
+    pub fn from_key_package(key_package: &KeyPackage, leaf_node_source: LeafNodeSource) -> Self {
+        let credential = key_package.get_credential().clone();
+        let signature_key = match &credential {
+            Credential::Basic(basic_credential) => basic_credential.public_key.clone(),
+        };
+        LeafNode {
+            encryption_key: key_package.get_hpke_init_key().clone(),
+            signature_key,
+            credential,
+            capabilities: key_package.get_capabilities(),
+            leaf_node_source,
+            extensions: key_package.get_extensions().to_vec(),
+            protocol_version: key_package.get_protocol_version(),
+            cipher_suite: *key_package.get_cipher_suite(),
+            signature: key_package.get_signature().clone(),
+        }
+    }
+    /// Verify the signature of the `KeyPackage` the leaf was created from
+    /// with the credential of the leaf.
+    pub fn verify(&self) -> bool {
+        self.verify_signature(&self.credential, &self.signature)
+    }
+    /// Get a reference to the HPKE public key of the leaf.
+    pub fn get_encryption_key(&self) -> &HPKEPublicKey {
+        &self.encryption_key
+    }
+    /// Get a reference to the signature key of the member.
+    pub fn get_signature_key(&self) -> &SignaturePublicKey {
+        &self.signature_key
+    }
+    /// Get a reference to the credential of the member.
+    pub fn get_credential(&self) -> &Credential {
+        &self.credential
+    }
+    /// Get a reference to the capabilities of the member.
+    pub fn get_capabilities(&self) -> &CapabilitiesExtension {
+        &self.capabilities
+    }
+    /// Get the source of the leaf.
+    pub fn get_leaf_node_source(&self) -> LeafNodeSource {
+        self.leaf_node_source
+    }
+    /// Get the extension of `extension_type`.
+    /// Returns `None` if the extension is not present or malformed.
+    pub fn get_extension(&self, extension_type: ExtensionType) -> Option<ExtensionPayload> {
+        get_extension_payload(&self.extensions, extension_type)
+    }
+    /// Check whether the leaf was created from `key_package`, i.e. whether it
+    /// has the same encryption key and credential.
+    pub fn is_from_key_package(&self, key_package: &KeyPackage) -> bool {
+        &self.encryption_key == key_package.get_hpke_init_key()
+            && &self.credential == key_package.get_credential()
+    }
+}
+
+impl Signable for LeafNode {
+    // The signature is the one of the `KeyPackage`
+    const SIGNATURE_LABEL: &'static str = KeyPackage::SIGNATURE_LABEL;
+
+    fn unsigned_payload(&self) -> Result<Vec<u8>, CodecError> {
+        let buffer = &mut Vec::new();
+        self.protocol_version.encode(buffer)?;
+        self.cipher_suite.encode(buffer)?;
+        self.encryption_key.encode(buffer)?;
+        self.credential.encode(buffer)?;
+        encode_vec(VecSize::VecU16, buffer, &self.extensions)?;
+        Ok(buffer.to_vec())
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Node {
     pub node_type: NodeType,
-    pub key_package: Option<KeyPackage>,
+    pub leaf_node: Option<LeafNode>,
     pub node: Option<ParentNode>,
 }
 
 impl Node {
-    pub fn new_leaf(kp_option: Option<KeyPackage>) -> Self {
+    pub fn new_leaf(leaf_node_option: Option<LeafNode>) -> Self {
         Node {
             node_type: NodeType::Leaf,
-            key_package: kp_option,
+            leaf_node: leaf_node_option,
             node: None,
         }
     }
     pub fn new_blank_parent_node() -> Self {
         Node {
             node_type: NodeType::Parent,
-            key_package: None,
+            leaf_node: None,
             node: None,
         }
     }
+    /// Get the HPKE public key of the node, or `None` if the node is blank.
     pub fn get_public_hpke_key(&self) -> Option<&HPKEPublicKey> {
         match self.node_type {
             NodeType::Leaf => {
-                if let Some(ref kp) = self.key_package {
-                    Some(kp.get_hpke_init_key())
+                if let Some(ref leaf_node) = self.leaf_node {
+                    Some(leaf_node.get_encryption_key())
                 } else {
                     None
                 }
@@ -62,12 +182,20 @@ impl Node {
             NodeType::Default => None,
         }
     }
+    /// Get the unmerged leaves of a parent node. Leaves and blank nodes have
+    /// no unmerged leaves.
+    pub fn unmerged_leaves(&self) -> &[u32] {
+        match &self.node {
+            Some(parent_node) => parent_node.get_unmerged_leaves(),
+            None => &[],
+        }
+    }
     pub fn blank(&mut self) {
-        self.key_package = None;
+        self.leaf_node = None;
         self.node = None;
     }
     pub fn is_blank(&self) -> bool {
-        self.key_package.is_none() && self.node.is_none()
+        self.leaf_node.is_none() && self.node.is_none()
     }
     pub fn hash(&self, ciphersuite: &Ciphersuite) -> Option<Vec<u8>> {
         if let Some(parent_node) = &self.node {
@@ -78,6 +206,8 @@ impl Node {
             None
         }
     }
+    /// Get the parent hash of a parent node, or the one in the
+    /// `ParentHashExtension` of a leaf. Returns `None` for blank nodes.
     pub fn parent_hash(&self) -> Option<Vec<u8>> {
         if self.is_blank() {
             return None;
@@ -91,9 +221,9 @@ impl Node {
                 }
             }
             NodeType::Leaf => {
-                if let Some(key_package) = &self.key_package {
+                if let Some(leaf_node) = &self.leaf_node {
                     if let Some(extension_payload) =
-                        key_package.get_extension(ExtensionType::ParentHash)
+                        leaf_node.get_extension(ExtensionType::ParentHash)
                     {
                         if let ExtensionPayload::ParentHash(parent_hash_extension) =
                             extension_payload
@@ -153,14 +283,14 @@ impl Codec for ParentNode {
         encode_vec(VecSize::VecU8, buffer, &self.parent_hash)?;
         Ok(())
     }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let public_key = HPKEPublicKey::decode(cursor)?;
-    //     let unmerged_leaves = decode_vec(VecSize::VecU32, cursor)?;
-    //     let parent_hash = decode_vec(VecSize::VecU8, cursor)?;
-    //     Ok(ParentNode {
-    //         public_key,
-    //         unmerged_leaves,
-    //         parent_hash,
-    //     })
-    // }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let public_key = HPKEPublicKey::decode(cursor)?;
+        let unmerged_leaves = decode_vec(VecSize::VecU32, cursor)?;
+        let parent_hash = decode_vec(VecSize::VecU8, cursor)?;
+        Ok(ParentNode {
+            public_key,
+            unmerged_leaves,
+            parent_hash,
+        })
+    }
 }
@@ -1,68 +1,91 @@
 use crate::ciphersuite::*;
 use crate::codec::*;
-use crate::tree::{astree::*, index::LeafIndex};
+use crate::tree::astree::*;
+use std::convert::TryFrom;
+use zeroize::Zeroize;
 
-const OUT_OF_ORDER_TOLERANCE: u32 = 5;
+pub(crate) const OUT_OF_ORDER_TOLERANCE: u32 = 5;
 const MAXIMUM_FORWARD_DISTANCE: u32 = 1000;
 
 #[derive(Clone)]
 pub struct SenderRatchet {
-    index: LeafIndex,
     generation: u32,
     past_secrets: Vec<Vec<u8>>,
 }
 
 impl Codec for SenderRatchet {
-    // fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
-    //     self.ciphersuite.encode(buffer)?;
-    //     self.index.encode(buffer)?;
-    //     self.generation.encode(buffer)?;
-    //     let len = self.past_secrets.len();
-    //     (len as u32).encode(buffer)?;
-    //     for i in 0..len {
-    //         encode_vec(VecSize::VecU8, buffer, &self.past_secrets[i])?;
-    //     }
-    //     Ok(())
-    // }
-    // fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
-    //     let ciphersuite = Ciphersuite::decode(cursor)?;
-    //     let index = LeafIndex::from(u32::decode(cursor)?);
-    //     let generation = u32::decode(cursor)?;
-    //     let len = u32::decode(cursor)? as usize;
-    //     let mut past_secrets = vec![];
-    //     for _ in 0..len {
-    //         let secret = decode_vec(VecSize::VecU8, cursor)?;
-    //         past_secrets.push(secret);
-    //     }
-    //     Ok(SenderRatchet {
-    //         ciphersuite,
-    //         index,
-    //         generation,
-    //         past_secrets,
-    //     })
-    // }
+    fn encode(&self, buffer: &mut Vec<u8>) -> Result<(), CodecError> {
+        self.generation.encode(buffer)?;
+        let len = u32::try_from(self.past_secrets.len()).map_err(|_| CodecError::EncodingError)?;
+        len.encode(buffer)?;
+        for secret in self.past_secrets.iter() {
+            encode_vec(VecSize::VecU8, buffer, secret)?;
+        }
+        Ok(())
+    }
+    fn decode(cursor: &mut Cursor) -> Result<Self, CodecError> {
+        let generation = u32::decode(cursor)?;
+        let len = u32::decode(cursor)? as usize;
+        let mut past_secrets = vec![];
+        for _ in 0..len {
+            let secret = decode_vec(VecSize::VecU8, cursor)?;
+            past_secrets.push(secret);
+        }
+        Ok(SenderRatchet {
+            generation,
+            past_secrets,
+        })
+    }
 }
 
 impl SenderRatchet {
-    pub fn new(index: LeafIndex, secret: &[u8]) -> Self {
+    pub fn new(secret: &[u8]) -> Self {
         Self {
-            index,
             generation: 0,
             past_secrets: vec![secret.to_vec()],
         }
     }
-    pub fn get_secret(
-        &mut self,
-        generation: u32,
-        ciphersuite: &Ciphersuite,
-    ) -> Result<ApplicationSecrets, ASError> {
-        if generation > (self.generation + MAXIMUM_FORWARD_DISTANCE) {
+    /// Check that the secrets of `generation` can be derived.
+    fn check_generation(&self, generation: u32) -> Result<(), ASError> {
+        if generation > self.generation.saturating_add(MAXIMUM_FORWARD_DISTANCE) {
             return Err(ASError::TooDistantInTheFuture);
         }
-        if generation < self.generation && (self.generation - generation) >= OUT_OF_ORDER_TOLERANCE
+        // The window can be shorter than the tolerance after `advance_to`
+        if generation < self.generation
+            && ((self.generation - generation) >= OUT_OF_ORDER_TOLERANCE
+                || (self.generation - generation) as usize >= self.past_secrets.len())
         {
             return Err(ASError::TooDistantInThePast);
         }
+        Ok(())
+    }
+    /// Derive the secrets of `generation` without ratcheting forward, e.g.
+    /// to decrypt a message before it was verified.
+    pub fn peek_secret(
+        &self,
+        generation: u32,
+        ciphersuite: &Ciphersuite,
+    ) -> Result<ApplicationSecrets, ASError> {
+        self.check_generation(generation)?;
+        if generation <= self.generation {
+            let window_index =
+                (self.past_secrets.len() as u32 - (self.generation - generation) - 1) as usize;
+            let secret = &self.past_secrets[window_index];
+            Ok(self.derive_key_nonce(secret, generation, ciphersuite))
+        } else {
+            let mut secret = self.past_secrets.last().unwrap().clone();
+            for current_generation in self.generation..generation {
+                secret = self.ratchet_secret(&secret, current_generation, ciphersuite);
+            }
+            Ok(self.derive_key_nonce(&secret, generation, ciphersuite))
+        }
+    }
+    pub fn get_secret(
+        &mut self,
+        generation: u32,
+        ciphersuite: &Ciphersuite,
+    ) -> Result<ApplicationSecrets, ASError> {
+        self.check_generation(generation)?;
         if generation <= self.generation {
             let window_index =
                 (self.past_secrets.len() as u32 - (self.generation - generation) - 1) as usize;
@@ -70,12 +93,15 @@ impl SenderRatchet {
             let application_secrets = self.derive_key_nonce(&secret, generation, ciphersuite);
             Ok(application_secrets)
         } else {
-            for _ in 0..(generation - self.generation) {
+            for current_generation in self.generation..generation {
                 if self.past_secrets.len() == OUT_OF_ORDER_TOLERANCE as usize {
                     self.past_secrets.remove(0);
                 }
-                let new_secret =
-                    self.ratchet_secret(self.past_secrets.last().unwrap(), ciphersuite);
+                let new_secret = self.ratchet_secret(
+                    self.past_secrets.last().unwrap(),
+                    current_generation,
+                    ciphersuite,
+                );
                 self.past_secrets.push(new_secret);
             }
             let secret = self.past_secrets.last().unwrap();
@@ -84,13 +110,36 @@ impl SenderRatchet {
             Ok(application_secrets)
         }
     }
-    fn ratchet_secret(&self, secret: &[u8], ciphersuite: &Ciphersuite) -> Vec<u8> {
-        derive_app_secret(
+    /// Ratchet forward to `generation` without keeping the secrets of the
+    /// skipped generations. Messages of earlier generations, including the
+    /// ones in the out-of-order window, can't be decrypted afterwards.
+    pub fn advance_to(
+        &mut self,
+        generation: u32,
+        ciphersuite: &Ciphersuite,
+    ) -> Result<(), ASError> {
+        if generation > self.generation.saturating_add(MAXIMUM_FORWARD_DISTANCE) {
+            return Err(ASError::TooDistantInTheFuture);
+        }
+        if generation < self.generation {
+            return Err(ASError::TooDistantInThePast);
+        }
+        let mut secret = self.past_secrets.last().unwrap().clone();
+        for current_generation in self.generation..generation {
+            secret = self.ratchet_secret(&secret, current_generation, ciphersuite);
+        }
+        self.past_secrets = vec![secret];
+        self.generation = generation;
+        Ok(())
+    }
+    /// Derive the secret of the next generation from the secret of
+    /// `generation`.
+    fn ratchet_secret(&self, secret: &[u8], generation: u32, ciphersuite: &Ciphersuite) -> Vec<u8> {
+        derive_tree_secret(
             ciphersuite,
             secret,
-            "app-secret",
-            self.index.into(),
-            self.generation,
+            "secret",
+            generation,
             ciphersuite.hash_length(),
         )
     }
@@ -100,19 +149,17 @@ impl SenderRatchet {
         generation: u32,
         ciphersuite: &Ciphersuite,
     ) -> ApplicationSecrets {
-        let nonce = derive_app_secret(
-            &ciphersuite,
+        let nonce = derive_tree_secret(
+            ciphersuite,
             secret,
-            "app-nonce",
-            self.index.into(),
+            "nonce",
             generation,
             ciphersuite.aead_nonce_length(),
         );
-        let key = derive_app_secret(
-            &ciphersuite,
+        let key = derive_tree_secret(
+            ciphersuite,
             secret,
-            "app-key",
-            self.index.into(),
+            "key",
             generation,
             ciphersuite.aead_key_length(),
         );
@@ -122,4 +169,17 @@ impl SenderRatchet {
     pub(crate) fn get_generation(&self) -> u32 {
         self.generation
     }
+
+    /// Get the number of generations up to the current one whose secrets are
+    /// kept for out-of-order messages.
+    pub(crate) fn window_occupancy(&self) -> usize {
+        self.past_secrets.len()
+    }
+
+    /// Overwrite the kept secrets with zeros.
+    pub(crate) fn wipe(&mut self) {
+        for secret in self.past_secrets.iter_mut() {
+            secret.zeroize();
+        }
+    }
 }
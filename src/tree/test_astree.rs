@@ -5,7 +5,7 @@ fn test_boundaries() {
 
     let ciphersuite =
         Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519);
-    let mut astree = ASTree::new(&[0u8; 32], LeafIndex::from(2u32));
+    let mut astree = ASTree::new(&[0u8; 32], 2);
     assert!(astree
         .get_secret(&ciphersuite, LeafIndex::from(0u32), 0)
         .is_ok());
@@ -33,7 +33,19 @@ fn test_boundaries() {
         astree.get_secret(&ciphersuite, LeafIndex::from(2u32), 0),
         Err(ASError::IndexOutOfBounds)
     );
-    let mut largetree = ASTree::new(&[0u8; 32], LeafIndex::from(100_000u32));
+    assert_eq!(
+        astree.get_secret(&ciphersuite, LeafIndex::from(u32::MAX), 0),
+        Err(ASError::IndexOutOfBounds)
+    );
+    assert_eq!(
+        astree.get_secret(&ciphersuite, LeafIndex::from(1u32), u32::MAX),
+        Err(ASError::TooDistantInTheFuture)
+    );
+    assert_eq!(
+        astree.advance_to(&ciphersuite, LeafIndex::from(1u32), u32::MAX),
+        Err(ASError::TooDistantInTheFuture)
+    );
+    let mut largetree = ASTree::new(&[0u8; 32], 100_000);
     assert!(largetree
         .get_secret(&ciphersuite, LeafIndex::from(0u32), 0)
         .is_ok());
@@ -48,3 +60,301 @@ fn test_boundaries() {
         Err(ASError::IndexOutOfBounds)
     );
 }
+
+#[test]
+fn test_resize() {
+    use crate::ciphersuite::*;
+    use crate::tree::{astree::*, index::*};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519);
+    let mut astree = ASTree::new(&[0u8; 32], 2);
+    let old_secret = astree
+        .get_secret(&ciphersuite, LeafIndex::from(1u32), 0)
+        .unwrap();
+
+    // Add-heavy epoch: the tree grows and all leaves are derived from the new
+    // application secret
+    astree.resize(&[1u8; 32], 7);
+    let mut fresh_tree = ASTree::new(&[1u8; 32], 7);
+    for leaf in 0..7u32 {
+        assert_eq!(
+            astree.get_secret(&ciphersuite, LeafIndex::from(leaf), 0),
+            fresh_tree.get_secret(&ciphersuite, LeafIndex::from(leaf), 0)
+        );
+    }
+    assert_ne!(
+        astree
+            .get_secret(&ciphersuite, LeafIndex::from(1u32), 1)
+            .unwrap(),
+        old_secret
+    );
+    assert_eq!(
+        astree.get_secret(&ciphersuite, LeafIndex::from(7u32), 0),
+        Err(ASError::IndexOutOfBounds)
+    );
+
+    // Remove-heavy epoch: leaves beyond the new size are no longer available
+    astree.resize(&[2u8; 32], 3);
+    let mut fresh_tree = ASTree::new(&[2u8; 32], 3);
+    for leaf in 0..3u32 {
+        assert_eq!(
+            astree.get_secret(&ciphersuite, LeafIndex::from(leaf), 0),
+            fresh_tree.get_secret(&ciphersuite, LeafIndex::from(leaf), 0)
+        );
+    }
+    assert_eq!(
+        astree.get_secret(&ciphersuite, LeafIndex::from(3u32), 0),
+        Err(ASError::IndexOutOfBounds)
+    );
+}
+
+#[test]
+fn test_secret_tree_derivation() {
+    use crate::ciphersuite::*;
+    use crate::schedule::*;
+    use crate::tree::{astree::*, index::*};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let hash_len = ciphersuite.hash_length();
+    let root_secret = [7u8; 32];
+    let mut astree = ASTree::new(&root_secret, 3);
+
+    // Recompute the spec's derivation for a tree with 3 leaves. The root is
+    // node 3, its right child is leaf 2 (node 4).
+    let expand = |secret: &[u8], label: &str, context: &[u8], length: usize| {
+        hkdf_expand_label(&ciphersuite, secret, label, context, length)
+    };
+    let left_secret = expand(&root_secret, "tree", b"left", hash_len);
+    let leaf_secrets = vec![
+        expand(&left_secret, "tree", b"left", hash_len),
+        expand(&left_secret, "tree", b"right", hash_len),
+        expand(&root_secret, "tree", b"right", hash_len),
+    ];
+    for (leaf, leaf_secret) in leaf_secrets.iter().enumerate() {
+        let mut ratchet_secret = expand(leaf_secret, "application", &[], hash_len);
+        for generation in 0..3u32 {
+            let expected = ApplicationSecrets::new(
+                AeadNonce::from_slice(&derive_tree_secret(
+                    &ciphersuite,
+                    &ratchet_secret,
+                    "nonce",
+                    generation,
+                    ciphersuite.aead_nonce_length(),
+                )),
+                AeadKey::from_slice(&derive_tree_secret(
+                    &ciphersuite,
+                    &ratchet_secret,
+                    "key",
+                    generation,
+                    ciphersuite.aead_key_length(),
+                )),
+            );
+            assert_eq!(
+                astree.get_secret(&ciphersuite, LeafIndex::from(leaf as u32), generation),
+                Ok(expected)
+            );
+            ratchet_secret = derive_tree_secret(
+                &ciphersuite,
+                &ratchet_secret,
+                "secret",
+                generation,
+                hash_len,
+            );
+        }
+    }
+}
+
+#[test]
+fn test_advance_to() {
+    use crate::ciphersuite::*;
+    use crate::tree::{astree::*, index::*};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519);
+    let mut astree = ASTree::new(&[0u8; 32], 2);
+    let mut reference = ASTree::new(&[0u8; 32], 2);
+    let sender = LeafIndex::from(1u32);
+
+    // The skipped generations are discarded
+    assert_eq!(astree.advance_to(&ciphersuite, sender, 10), Ok(()));
+    assert_eq!(astree.get_generation(sender), 10);
+    assert_eq!(
+        astree.get_secret(&ciphersuite, sender, 9),
+        Err(ASError::TooDistantInThePast)
+    );
+
+    // Later generations derive the same secrets as without skipping
+    assert_eq!(
+        astree.get_secret(&ciphersuite, sender, 10),
+        reference.get_secret(&ciphersuite, sender, 10)
+    );
+    assert_eq!(
+        astree.get_secret(&ciphersuite, sender, 12),
+        reference.get_secret(&ciphersuite, sender, 12)
+    );
+
+    // The ratchet can't be moved back or too far ahead
+    assert_eq!(
+        astree.advance_to(&ciphersuite, sender, 11),
+        Err(ASError::TooDistantInThePast)
+    );
+    assert_eq!(
+        astree.advance_to(&ciphersuite, sender, 1_013),
+        Err(ASError::TooDistantInTheFuture)
+    );
+    assert_eq!(
+        astree.advance_to(&ciphersuite, LeafIndex::from(2u32), 0),
+        Err(ASError::IndexOutOfBounds)
+    );
+}
+
+#[test]
+fn test_peek_secret() {
+    use crate::ciphersuite::*;
+    use crate::tree::{astree::*, index::*};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519);
+    let mut astree = ASTree::new(&[0u8; 32], 2);
+    let mut reference = ASTree::new(&[0u8; 32], 2);
+    let sender = LeafIndex::from(1u32);
+
+    // Peeking derives the same secrets without moving the ratchet
+    assert_eq!(
+        astree.peek_secret(&ciphersuite, sender, 5),
+        reference.get_secret(&ciphersuite, sender, 5)
+    );
+    assert_eq!(astree.get_generation(sender), 0);
+    assert_eq!(
+        astree.peek_secret(&ciphersuite, sender, 3),
+        reference.get_secret(&ciphersuite, sender, 3)
+    );
+
+    // Once advanced, earlier generations in the window can still be peeked
+    assert!(astree.get_secret(&ciphersuite, sender, 5).is_ok());
+    assert_eq!(astree.get_generation(sender), 5);
+    assert_eq!(
+        astree.peek_secret(&ciphersuite, sender, 4),
+        reference.get_secret(&ciphersuite, sender, 4)
+    );
+    assert_eq!(
+        astree.peek_secret(&ciphersuite, sender, 1_006),
+        Err(ASError::TooDistantInTheFuture)
+    );
+}
+
+#[test]
+fn test_sender_ratchet_limit() {
+    use crate::ciphersuite::*;
+    use crate::codec::*;
+    use crate::tree::{astree::*, index::*};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519);
+    let application_secret = [7u8; 32];
+    let mut astree = ASTree::new(&application_secret, 5);
+    let mut reference = ASTree::new(&application_secret, 5);
+    astree
+        .set_max_sender_ratchets(&ciphersuite, Some(2), &application_secret)
+        .unwrap();
+
+    // Only the most recently used ratchets are kept
+    for i in 0..5u32 {
+        assert_eq!(
+            astree.get_secret(&ciphersuite, LeafIndex::from(i), 3),
+            reference.get_secret(&ciphersuite, LeafIndex::from(i), 3)
+        );
+        assert!(astree.sender_ratchet_count() <= 2);
+    }
+    assert_eq!(astree.sender_ratchet_count(), 2);
+
+    // Evicted ratchets are re-derived and continue at their last generation
+    let sender = LeafIndex::from(1u32);
+    assert_eq!(astree.get_generation(sender), 3);
+    assert_eq!(
+        astree.get_secret(&ciphersuite, sender, 4),
+        reference.get_secret(&ciphersuite, sender, 4)
+    );
+    assert_eq!(
+        astree.get_secret(&ciphersuite, sender, 2),
+        Err(ASError::TooDistantInThePast)
+    );
+    assert_eq!(astree.sender_ratchet_count(), 2);
+
+    // The application secret is not encoded and has to be restored to
+    // re-derive evicted ratchets
+    let encoded = astree.encode_detached().unwrap();
+    assert!(!encoded
+        .windows(application_secret.len())
+        .any(|window| window == &application_secret[..]));
+    let mut decoded = ASTree::decode(&mut Cursor::new(&encoded)).unwrap();
+    assert_eq!(
+        decoded.get_secret(&ciphersuite, LeafIndex::from(0u32), 4),
+        Err(ASError::TooDistantInThePast)
+    );
+    decoded.retain_application_secret(&application_secret);
+    assert_eq!(
+        decoded.get_secret(&ciphersuite, LeafIndex::from(0u32), 4),
+        reference.get_secret(&ciphersuite, LeafIndex::from(0u32), 4)
+    );
+
+    // Without a limit, evicted ratchets are re-derived and all ratchets are
+    // kept
+    astree
+        .set_max_sender_ratchets(&ciphersuite, None, &application_secret)
+        .unwrap();
+    assert_eq!(astree.sender_ratchet_count(), 5);
+    for i in 0..5u32 {
+        assert!(astree
+            .get_secret(&ciphersuite, LeafIndex::from(i), 5)
+            .is_ok());
+    }
+    assert_eq!(astree.sender_ratchet_count(), 5);
+}
+
+#[test]
+fn test_ratchet_state() {
+    use crate::ciphersuite::*;
+    use crate::tree::{astree::*, index::*};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519);
+    let mut astree = ASTree::new(&[0u8; 32], 4);
+    assert!(astree.ratchet_state().is_empty());
+
+    // Only used ratchets are listed
+    astree
+        .get_secret(&ciphersuite, LeafIndex::from(1u32), 2)
+        .unwrap();
+    astree
+        .get_secret(&ciphersuite, LeafIndex::from(3u32), 10)
+        .unwrap();
+    let state = astree.ratchet_state();
+    assert_eq!(state.len(), 2);
+    assert_eq!(state[0].sender, LeafIndex::from(1u32));
+    assert_eq!(state[0].generation, 2);
+    assert_eq!(state[0].window_occupancy, 3);
+    assert_eq!(state[1].sender, LeafIndex::from(3u32));
+    assert_eq!(state[1].generation, 10);
+    assert_eq!(state[1].window_occupancy, state[1].window_size);
+    assert!(!state[1].evicted);
+
+    // Skipping generations empties the window
+    astree
+        .advance_to(&ciphersuite, LeafIndex::from(1u32), 20)
+        .unwrap();
+    assert_eq!(astree.ratchet_state()[0].generation, 20);
+    assert_eq!(astree.ratchet_state()[0].window_occupancy, 1);
+
+    // Evicted ratchets keep their generation
+    astree
+        .set_max_sender_ratchets(&ciphersuite, Some(1), &[0u8; 32])
+        .unwrap();
+    let state = astree.ratchet_state();
+    assert_eq!(state.len(), 2);
+    assert!(state[0].evicted);
+    assert_eq!(state[0].generation, 20);
+    assert!(!state[1].evicted);
+}
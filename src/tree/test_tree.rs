@@ -0,0 +1,852 @@
+#[test]
+fn test_leaf_assignment() {
+    use crate::ciphersuite::*;
+    use crate::creds::*;
+    use crate::key_packages::*;
+    use crate::messages::{proposals::*, *};
+    use crate::tree::{index::*, *};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let new_kpb = |name: &str| {
+        let identity = Identity::new(ciphersuite, name.as_bytes().to_vec());
+        let credential = Credential::Basic(BasicCredential::from(&identity));
+        KeyPackageBundle::new(
+            &ciphersuite,
+            identity.get_signature_key_pair().get_private_key(),
+            credential,
+            None,
+        )
+    };
+    // Build the proposal queue and the ID lists in the given order
+    let proposals = |adds: &[&KeyPackageBundle], removes: &[u32]| {
+        let mut proposal_queue = ProposalQueue::new();
+        let mut proposal_id_list = ProposalIDList {
+            updates: vec![],
+            removes: vec![],
+            adds: vec![],
+            psks: vec![],
+        };
+        for removed in removes {
+            let proposal = Proposal::Remove(RemoveProposal { removed: *removed });
+            proposal_id_list
+                .removes
+                .push(proposal.to_proposal_id(&ciphersuite));
+            proposal_queue.add(
+                QueuedProposal::new(proposal, LeafIndex::from(0u32), None),
+                &ciphersuite,
+            );
+        }
+        for kpb in adds {
+            let proposal = Proposal::Add(AddProposal {
+                key_package: kpb.get_key_package().clone(),
+            });
+            proposal_id_list
+                .adds
+                .push(proposal.to_proposal_id(&ciphersuite));
+            proposal_queue.add(
+                QueuedProposal::new(proposal, LeafIndex::from(0u32), None),
+                &ciphersuite,
+            );
+        }
+        (proposal_id_list, proposal_queue)
+    };
+    let added_leaves = |membership_changes: &MembershipChanges| {
+        membership_changes
+            .adds
+            .iter()
+            .map(|member| member.index)
+            .collect::<Vec<LeafIndex>>()
+    };
+    let leaf_node =
+        |tree: &RatchetTree, leaf: u32| tree.get_leaf_node(LeafIndex::from(leaf)).cloned();
+    let added_leaf_node = |kpb: &KeyPackageBundle| {
+        LeafNode::from_key_package(kpb.get_key_package(), LeafNodeSource::KeyPackage)
+    };
+
+    let kpbs: Vec<KeyPackageBundle> = ["Alice", "Bob", "Charlie", "Dave", "Eve", "Frank", "Grace"]
+        .iter()
+        .map(|name| new_kpb(name))
+        .collect();
+    let mut tree = RatchetTree::new(ciphersuite, kpbs[0].clone());
+
+    // Joiners are appended when there are no free leaves
+    let (proposal_id_list, proposal_queue) = proposals(&[&kpbs[1], &kpbs[2], &kpbs[3]], &[]);
+    let (membership_changes, invited_members, _) = tree
+        .apply_proposals(
+            LeafIndex::from(0u32),
+            &proposal_id_list,
+            proposal_queue,
+            &HashMap::new(),
+        )
+        .unwrap();
+    assert_eq!(
+        added_leaves(&membership_changes),
+        vec![
+            LeafIndex::from(1u32),
+            LeafIndex::from(2u32),
+            LeafIndex::from(3u32)
+        ]
+    );
+    assert_eq!(
+        invited_members
+            .iter()
+            .map(|(index, _)| *index)
+            .collect::<Vec<NodeIndex>>(),
+        vec![
+            NodeIndex::from(2u32),
+            NodeIndex::from(4u32),
+            NodeIndex::from(6u32)
+        ]
+    );
+    assert_eq!(tree.leaf_count(), 4);
+    assert_eq!(tree.member_count(), 4);
+    assert_eq!(tree.free_leaf_count(), 0);
+
+    // Leaves freed in the same Commit are filled from the left, the remaining
+    // joiners are appended
+    let mut other_tree = tree.clone();
+    let adds = [&kpbs[4], &kpbs[5], &kpbs[6]];
+    let (proposal_id_list, proposal_queue) = proposals(&adds, &[2, 1]);
+    let (membership_changes, _, _) = tree
+        .apply_proposals(
+            LeafIndex::from(0u32),
+            &proposal_id_list,
+            proposal_queue.clone(),
+            &HashMap::new(),
+        )
+        .unwrap();
+    assert_eq!(
+        added_leaves(&membership_changes),
+        vec![
+            LeafIndex::from(1u32),
+            LeafIndex::from(2u32),
+            LeafIndex::from(4u32)
+        ]
+    );
+    for (kpb, leaf) in adds.iter().zip(&[1u32, 2, 4]) {
+        assert_eq!(leaf_node(&tree, *leaf), Some(added_leaf_node(kpb)));
+    }
+    assert_eq!(
+        membership_changes.removes,
+        vec![
+            MemberChange {
+                index: LeafIndex::from(2u32),
+                leaf_node: added_leaf_node(&kpbs[2])
+            },
+            MemberChange {
+                index: LeafIndex::from(1u32),
+                leaf_node: added_leaf_node(&kpbs[1])
+            }
+        ]
+    );
+    assert_eq!(
+        &membership_changes.committer_credential,
+        kpbs[0].get_key_package().get_credential()
+    );
+    assert_eq!(leaf_node(&tree, 3), Some(added_leaf_node(&kpbs[3])));
+
+    // Every member applying the same Commit assigns the same leaves
+    other_tree
+        .apply_proposals(
+            LeafIndex::from(0u32),
+            &proposal_id_list,
+            proposal_queue,
+            &HashMap::new(),
+        )
+        .unwrap();
+    assert_eq!(tree.compute_tree_hash(), other_tree.compute_tree_hash());
+
+    // Removing the rightmost leaves truncates the tree
+    let (proposal_id_list, proposal_queue) = proposals(&[], &[4, 3]);
+    tree.apply_proposals(
+        LeafIndex::from(0u32),
+        &proposal_id_list,
+        proposal_queue,
+        &HashMap::new(),
+    )
+    .unwrap();
+    assert_eq!(tree.leaf_count(), 3);
+
+    // Removing other leaves leaves blanks behind
+    let (proposal_id_list, proposal_queue) = proposals(&[], &[1]);
+    tree.apply_proposals(
+        LeafIndex::from(0u32),
+        &proposal_id_list,
+        proposal_queue,
+        &HashMap::new(),
+    )
+    .unwrap();
+    assert_eq!(tree.leaf_count(), 3);
+    assert_eq!(tree.member_count(), 2);
+    assert_eq!(tree.free_leaf_count(), 1);
+    assert_eq!(
+        tree.free_leaves().collect::<Vec<NodeIndex>>(),
+        vec![NodeIndex::from(2u32)]
+    );
+    assert!(tree.is_leaf_blank(LeafIndex::from(1u32)));
+    assert!(!tree.is_leaf_blank(LeafIndex::from(2u32)));
+    assert!(tree.is_leaf_blank(LeafIndex::from(3u32)));
+}
+
+#[test]
+fn test_update_own_leaf_leaf_secret() {
+    use crate::ciphersuite::*;
+    use crate::creds::*;
+    use crate::key_packages::*;
+    use crate::messages::proposals::*;
+    use crate::tree::{index::*, *};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let new_kpb = |name: &str| {
+        let identity = Identity::new(ciphersuite, name.as_bytes().to_vec());
+        let credential = Credential::Basic(BasicCredential::from(&identity));
+        KeyPackageBundle::new(
+            &ciphersuite,
+            identity.get_signature_key_pair().get_private_key(),
+            credential,
+            None,
+        )
+    };
+
+    // Create a tree with two members
+    let mut tree = RatchetTree::new(ciphersuite, new_kpb("Alice"));
+    let proposal = Proposal::Add(AddProposal {
+        key_package: new_kpb("Bob").get_key_package().clone(),
+    });
+    let proposal_id_list = ProposalIDList {
+        updates: vec![],
+        removes: vec![],
+        adds: vec![proposal.to_proposal_id(&ciphersuite)],
+        psks: vec![],
+    };
+    let mut proposal_queue = ProposalQueue::new();
+    proposal_queue.add(
+        QueuedProposal::new(proposal, LeafIndex::from(0u32), None),
+        &ciphersuite,
+    );
+    tree.apply_proposals(
+        LeafIndex::from(0u32),
+        &proposal_id_list,
+        proposal_queue,
+        &HashMap::new(),
+    )
+    .unwrap();
+
+    // The path only depends on the leaf secret, not on the leaf key
+    let kpb = new_kpb("Alice");
+    let update = |leaf_secret: &[u8]| {
+        let (commit_secret, _, _, path_secrets) =
+            tree.clone()
+                .update_own_leaf(None, kpb.clone(), leaf_secret, &[], true);
+        (commit_secret, path_secrets.unwrap())
+    };
+    let leaf_secret = vec![1u8; ciphersuite.hash_length()];
+    let (commit_secret, path_secrets) = update(&leaf_secret);
+    let (same_commit_secret, same_path_secrets) = update(&leaf_secret);
+    assert_eq!(commit_secret, same_commit_secret);
+    assert_eq!(path_secrets, same_path_secrets);
+    let (other_commit_secret, other_path_secrets) = update(&vec![2u8; ciphersuite.hash_length()]);
+    assert_ne!(commit_secret, other_commit_secret);
+    assert_ne!(path_secrets, other_path_secrets);
+    assert_ne!(path_secrets[0], kpb.get_private_key().as_slice());
+}
+
+#[test]
+fn test_parent_hash() {
+    use crate::ciphersuite::*;
+    use crate::codec::*;
+    use crate::creds::*;
+    use crate::extensions::*;
+    use crate::key_packages::*;
+    use crate::messages::proposals::*;
+    use crate::tree::{index::*, *};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, b"Alice".to_vec());
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let new_kpb = |name: &str| {
+        let identity = Identity::new(ciphersuite, name.as_bytes().to_vec());
+        let credential = Credential::Basic(BasicCredential::from(&identity));
+        KeyPackageBundle::new(
+            &ciphersuite,
+            identity.get_signature_key_pair().get_private_key(),
+            credential,
+            None,
+        )
+    };
+    let add_members = |tree: &mut RatchetTree, kpbs: &[KeyPackageBundle]| {
+        let mut proposal_queue = ProposalQueue::new();
+        let mut proposal_id_list = ProposalIDList {
+            updates: vec![],
+            removes: vec![],
+            adds: vec![],
+            psks: vec![],
+        };
+        for kpb in kpbs {
+            let proposal = Proposal::Add(AddProposal {
+                key_package: kpb.get_key_package().clone(),
+            });
+            proposal_id_list
+                .adds
+                .push(proposal.to_proposal_id(&ciphersuite));
+            proposal_queue.add(
+                QueuedProposal::new(proposal, LeafIndex::from(0u32), None),
+                &ciphersuite,
+            );
+        }
+        tree.apply_proposals(
+            LeafIndex::from(0u32),
+            &proposal_id_list,
+            proposal_queue,
+            &HashMap::new(),
+        )
+        .unwrap();
+    };
+    let parent_hash_input = |parent: &Node, sibling_hash: &[u8]| {
+        let parent_node = parent.node.as_ref().unwrap();
+        let mut buffer = vec![];
+        parent_node.get_public_key().encode(&mut buffer).unwrap();
+        encode_vec(VecSize::VecU8, &mut buffer, parent_node.get_parent_hash()).unwrap();
+        encode_vec(VecSize::VecU8, &mut buffer, sibling_hash).unwrap();
+        ciphersuite.hash(&buffer)
+    };
+    let leaf_hash = |tree: &RatchetTree, index: u32| {
+        let index = NodeIndex::from(index);
+        LeafNodeHashInput::new(&index, &tree.nodes[index.as_usize()].leaf_node).hash(&ciphersuite)
+    };
+
+    // Alice, Bob and Charlie are in leaves 0, 2 and 4, Alice updates her path
+    let alice_kpb = KeyPackageBundle::new(
+        &ciphersuite,
+        alice_signature_key,
+        alice_credential.clone(),
+        None,
+    );
+    let mut tree = RatchetTree::new(ciphersuite, alice_kpb);
+    add_members(&mut tree, &[new_kpb("Bob"), new_kpb("Charlie")]);
+    let new_alice_kpb =
+        KeyPackageBundle::new(&ciphersuite, alice_signature_key, alice_credential, None);
+    let (_, kpb, _, _) = tree.update_own_leaf(
+        Some(alice_signature_key),
+        new_alice_kpb,
+        &[1u8; 32],
+        &[],
+        true,
+    );
+
+    // The root has an empty parent hash, every other node on the path commits
+    // to its parent and the tree hash of its sibling
+    let root = &tree.nodes[3];
+    assert_eq!(root.parent_hash(), Some(vec![]));
+    let expected_parent_hash = parent_hash_input(root, &leaf_hash(&tree, 4));
+    assert_eq!(tree.nodes[1].parent_hash(), Some(expected_parent_hash));
+    let expected_leaf_parent_hash = parent_hash_input(&tree.nodes[1], &leaf_hash(&tree, 2));
+    match kpb
+        .get_key_package()
+        .get_extension(ExtensionType::ParentHash)
+    {
+        Some(ExtensionPayload::ParentHash(parent_hash_extension)) => {
+            assert_eq!(parent_hash_extension.parent_hash, expected_leaf_parent_hash)
+        }
+        _ => panic!("Missing parent hash extension"),
+    }
+    assert!(RatchetTree::verify_integrity(
+        &ciphersuite,
+        &tree.public_key_tree()
+    ));
+
+    // Adding a member extends the tree, the original sibling tree hash still
+    // matches since the new leaf is an unmerged leaf of the root
+    add_members(&mut tree, &[new_kpb("Dave")]);
+    assert_eq!(tree.leaf_count(), 4);
+    assert!(RatchetTree::verify_integrity(
+        &ciphersuite,
+        &tree.public_key_tree()
+    ));
+
+    // The parent hashes can also be checked before the tree was hashed
+    let mut verifier = TreeVerifier::new(ciphersuite, &tree.public_key_tree()).unwrap();
+    assert_eq!(verifier.verify_nodes(usize::MAX), Some(true));
+
+    // The exported tree exposes what is needed to recompute the hashes
+    let nodes = tree.public_key_tree();
+    let root = nodes[3].as_ref().unwrap();
+    assert_eq!(
+        root.get_public_hpke_key(),
+        Some(root.node.as_ref().unwrap().get_public_key())
+    );
+    assert_eq!(root.unmerged_leaves(), &[6]);
+    assert_eq!(root.parent_hash(), Some(vec![]));
+    let alice_leaf = nodes[0].as_ref().unwrap();
+    assert_eq!(
+        alice_leaf.get_public_hpke_key(),
+        Some(kpb.get_key_package().get_hpke_init_key())
+    );
+    assert!(alice_leaf.unmerged_leaves().is_empty());
+    assert_eq!(alice_leaf.parent_hash(), Some(expected_leaf_parent_hash));
+    assert!(nodes[5].is_none());
+
+    // Tampering with a parent hash is detected
+    let mut nodes = tree.public_key_tree();
+    if let Some(Some(node)) = nodes.get_mut(1) {
+        node.node.as_mut().unwrap().set_parent_hash(vec![1, 2, 3]);
+    }
+    assert!(!RatchetTree::verify_integrity(&ciphersuite, &nodes));
+}
+
+#[test]
+fn test_parent_hash_below_blank_parent() {
+    use crate::ciphersuite::*;
+    use crate::creds::*;
+    use crate::key_packages::*;
+    use crate::messages::proposals::*;
+    use crate::tree::{index::*, *};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let new_kpb = |name: &str| {
+        let identity = Identity::new(ciphersuite, name.as_bytes().to_vec());
+        let credential = Credential::Basic(BasicCredential::from(&identity));
+        KeyPackageBundle::new(
+            &ciphersuite,
+            identity.get_signature_key_pair().get_private_key(),
+            credential,
+            None,
+        )
+    };
+    let add_members = |tree: &mut RatchetTree, kpbs: &[KeyPackageBundle]| {
+        let mut proposal_queue = ProposalQueue::new();
+        let mut proposal_id_list = ProposalIDList {
+            updates: vec![],
+            removes: vec![],
+            adds: vec![],
+            psks: vec![],
+        };
+        for kpb in kpbs {
+            let proposal = Proposal::Add(AddProposal {
+                key_package: kpb.get_key_package().clone(),
+            });
+            proposal_id_list
+                .adds
+                .push(proposal.to_proposal_id(&ciphersuite));
+            proposal_queue.add(
+                QueuedProposal::new(proposal, LeafIndex::from(0u32), None),
+                &ciphersuite,
+            );
+        }
+        tree.apply_proposals(
+            LeafIndex::from(0u32),
+            &proposal_id_list,
+            proposal_queue,
+            &HashMap::new(),
+        )
+        .unwrap();
+    };
+
+    // Charlie is in leaf 4, which is a child of the root of a tree with three
+    // leaves, and updates his path
+    let charlie_identity = Identity::new(ciphersuite, b"Charlie".to_vec());
+    let charlie_signature_key = charlie_identity.get_signature_key_pair().get_private_key();
+    let new_charlie_kpb = || {
+        KeyPackageBundle::new(
+            &ciphersuite,
+            charlie_signature_key,
+            Credential::Basic(BasicCredential::from(&charlie_identity)),
+            None,
+        )
+    };
+    let mut alice_tree = RatchetTree::new(ciphersuite, new_kpb("Alice"));
+    let charlie_kpb = new_charlie_kpb();
+    add_members(&mut alice_tree, &[new_kpb("Bob"), charlie_kpb.clone()]);
+    let mut tree =
+        RatchetTree::new_from_nodes(ciphersuite, charlie_kpb, &alice_tree.public_key_tree())
+            .unwrap();
+    tree.update_own_leaf(
+        Some(charlie_signature_key),
+        new_charlie_kpb(),
+        &[1u8; 32],
+        &[],
+        true,
+    );
+
+    // Adding Dave inserts the blank parent node 5 between the root and
+    // Charlie's leaf, which holds the parent hash of the root
+    add_members(&mut tree, &[new_kpb("Dave")]);
+    let nodes = tree.public_key_tree();
+    assert!(nodes[5].is_none());
+    assert!(RatchetTree::verify_integrity(&ciphersuite, &nodes));
+
+    // The root still has to match the parent hash of Charlie's leaf
+    let mut nodes = tree.public_key_tree();
+    if let Some(Some(node)) = nodes.get_mut(3) {
+        node.node.as_mut().unwrap().set_parent_hash(vec![1, 2, 3]);
+    }
+    assert!(!RatchetTree::verify_integrity(&ciphersuite, &nodes));
+}
+
+#[test]
+fn test_leaf_node() {
+    use crate::ciphersuite::*;
+    use crate::codec::*;
+    use crate::creds::*;
+    use crate::extensions::*;
+    use crate::key_packages::*;
+    use crate::tree::{node::*, *};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let identity = Identity::new(ciphersuite, b"Alice".to_vec());
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let kpb = KeyPackageBundle::new(
+        &ciphersuite,
+        identity.get_signature_key_pair().get_private_key(),
+        credential.clone(),
+        Some(vec![ParentHashExtension::new(&[1, 2, 3]).to_extension()]),
+    );
+    let key_package = kpb.get_key_package();
+
+    // The leaf keeps the keys, the credential and the extensions
+    let leaf_node = LeafNode::from_key_package(key_package, LeafNodeSource::Commit);
+    assert_eq!(
+        leaf_node.get_encryption_key(),
+        key_package.get_hpke_init_key()
+    );
+    assert_eq!(
+        leaf_node.get_signature_key(),
+        identity.get_signature_key_pair().get_public_key()
+    );
+    assert_eq!(leaf_node.get_credential(), &credential);
+    assert_eq!(
+        Some(ExtensionPayload::Capabilities(
+            leaf_node.get_capabilities().clone()
+        )),
+        key_package.get_extension(ExtensionType::Capabilities)
+    );
+    assert_eq!(leaf_node.get_leaf_node_source(), LeafNodeSource::Commit);
+    assert_eq!(
+        leaf_node.get_extension(ExtensionType::ParentHash),
+        key_package.get_extension(ExtensionType::ParentHash)
+    );
+
+    // It keeps the signature of the KeyPackage and can be decoded again
+    assert!(leaf_node.verify());
+    let encoded = leaf_node.encode_detached().unwrap();
+    let mut key_package_bytes = key_package.encode_detached().unwrap();
+    key_package_bytes.push(LeafNodeSource::Commit as u8);
+    assert_eq!(encoded, key_package_bytes);
+    let decoded = LeafNode::decode(&mut Cursor::new(&encoded)).unwrap();
+    assert_eq!(decoded, leaf_node);
+
+    // Modified leaves don't verify, neither on their own nor in a tree
+    let mut modified = leaf_node.clone();
+    modified.extensions.pop();
+    assert!(!modified.verify());
+    assert!(RatchetTree::verify_integrity(
+        &ciphersuite,
+        &[Some(Node::new_leaf(Some(leaf_node.clone())))]
+    ));
+    assert!(!RatchetTree::verify_integrity(
+        &ciphersuite,
+        &[Some(Node::new_leaf(Some(modified)))]
+    ));
+
+    // Unknown sources are rejected
+    let mut invalid = encoded;
+    let source_position = invalid.len() - 1;
+    invalid[source_position] = 0;
+    assert!(LeafNode::decode(&mut Cursor::new(&invalid)).is_err());
+
+    // Nodes must have a known type, valid presence flags and only the
+    // content of their type
+    let node = Node {
+        node_type: NodeType::Leaf,
+        leaf_node: Some(leaf_node),
+        node: None,
+    };
+    let encoded = node.encode_detached().unwrap();
+    assert_eq!(Node::decode(&mut Cursor::new(&encoded)).unwrap(), node);
+    for (position, value) in &[(0, 1), (0, 2), (1, 2)] {
+        let mut invalid = encoded.clone();
+        invalid[*position] = *value;
+        assert!(Node::decode(&mut Cursor::new(&invalid)).is_err());
+    }
+}
+
+#[test]
+fn test_update_direct_path_validation() {
+    use crate::ciphersuite::*;
+    use crate::creds::*;
+    use crate::group::PathValidationError;
+    use crate::key_packages::*;
+    use crate::messages::proposals::*;
+    use crate::tree::{index::*, *};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let new_kpb = |name: &str| {
+        let identity = Identity::new(ciphersuite, name.as_bytes().to_vec());
+        let credential = Credential::Basic(BasicCredential::from(&identity));
+        KeyPackageBundle::new(
+            &ciphersuite,
+            identity.get_signature_key_pair().get_private_key(),
+            credential,
+            None,
+        )
+    };
+
+    // Alice adds Bob and Charlie
+    let mut alice_tree = RatchetTree::new(ciphersuite, new_kpb("Alice"));
+    let bob_kpb = new_kpb("Bob");
+    let mut proposal_queue = ProposalQueue::new();
+    let mut proposal_id_list = ProposalIDList {
+        updates: vec![],
+        removes: vec![],
+        adds: vec![],
+        psks: vec![],
+    };
+    for kpb in &[bob_kpb.clone(), new_kpb("Charlie")] {
+        let proposal = Proposal::Add(AddProposal {
+            key_package: kpb.get_key_package().clone(),
+        });
+        proposal_id_list
+            .adds
+            .push(proposal.to_proposal_id(&ciphersuite));
+        proposal_queue.add(
+            QueuedProposal::new(proposal, LeafIndex::from(0u32), None),
+            &ciphersuite,
+        );
+    }
+    alice_tree
+        .apply_proposals(
+            LeafIndex::from(0u32),
+            &proposal_id_list,
+            proposal_queue,
+            &HashMap::new(),
+        )
+        .unwrap();
+    let mut bob_tree =
+        RatchetTree::new_from_nodes(ciphersuite, bob_kpb, &alice_tree.public_key_tree()).unwrap();
+    // Bob doesn't know any keys of his direct path yet
+    assert!(
+        treemath::dirpath_root(bob_tree.get_own_index(), bob_tree.leaf_count())
+            .iter()
+            .all(|&index| bob_tree.own_leaf.path_keypairs.get(index).is_none())
+    );
+
+    // Alice updates her path
+    let (commit_secret, _, direct_path, _) =
+        alice_tree.update_own_leaf(None, new_kpb("Alice"), &[1u8; 32], &[], true);
+    let direct_path = direct_path.unwrap();
+
+    // Invalid paths are rejected without modifying Bob's tree
+    let bob_nodes = bob_tree.public_key_tree();
+    let mut invalid_path = direct_path.clone();
+    invalid_path.nodes.pop();
+    assert_eq!(
+        bob_tree.update_direct_path(LeafIndex::from(0u32), &invalid_path, &[]),
+        Err(PathValidationError::InvalidPathLength)
+    );
+    let mut invalid_path = direct_path.clone();
+    invalid_path.nodes[0].encrypted_path_secret.clear();
+    assert_eq!(
+        bob_tree.update_direct_path(LeafIndex::from(0u32), &invalid_path, &[]),
+        Err(PathValidationError::ResolutionSizeMismatch)
+    );
+    let mut invalid_path = direct_path.clone();
+    invalid_path.nodes[1].public_key = invalid_path.nodes[0].public_key.clone();
+    assert_eq!(
+        bob_tree.update_direct_path(LeafIndex::from(0u32), &invalid_path, &[]),
+        Err(PathValidationError::PublicKeyMismatch)
+    );
+    assert_eq!(
+        bob_tree.update_direct_path(LeafIndex::from(1u32), &direct_path, &[]),
+        Err(PathValidationError::InvalidSender)
+    );
+    assert_eq!(bob_tree.public_key_tree(), bob_nodes);
+
+    // The valid path is merged
+    assert_eq!(
+        bob_tree.update_direct_path(LeafIndex::from(0u32), &direct_path, &[]),
+        Ok(commit_secret)
+    );
+    assert_eq!(bob_tree.public_key_tree(), alice_tree.public_key_tree());
+
+    // The keys Bob learned from the path match the public keys of the tree
+    for index in treemath::dirpath_root(bob_tree.get_own_index(), bob_tree.leaf_count()) {
+        let keypair = bob_tree.own_leaf.path_keypairs.get(index).unwrap();
+        assert_eq!(
+            bob_tree.nodes()[index.as_usize()].get_public_hpke_key(),
+            Some(keypair.get_public_key())
+        );
+    }
+}
+
+#[test]
+fn test_resolution_cache() {
+    use crate::ciphersuite::*;
+    use crate::creds::*;
+    use crate::key_packages::*;
+    use crate::messages::proposals::*;
+    use crate::tree::{index::*, *};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let new_kpb = |name: &str| {
+        let identity = Identity::new(ciphersuite, name.as_bytes().to_vec());
+        let credential = Credential::Basic(BasicCredential::from(&identity));
+        KeyPackageBundle::new(
+            &ciphersuite,
+            identity.get_signature_key_pair().get_private_key(),
+            credential,
+            None,
+        )
+    };
+    let apply = |tree: &mut RatchetTree, adds: &[&str], removes: &[u32]| {
+        let mut proposal_queue = ProposalQueue::new();
+        let mut proposal_id_list = ProposalIDList {
+            updates: vec![],
+            removes: vec![],
+            adds: vec![],
+            psks: vec![],
+        };
+        for removed in removes {
+            let proposal = Proposal::Remove(RemoveProposal { removed: *removed });
+            proposal_id_list
+                .removes
+                .push(proposal.to_proposal_id(&ciphersuite));
+            proposal_queue.add(
+                QueuedProposal::new(proposal, LeafIndex::from(0u32), None),
+                &ciphersuite,
+            );
+        }
+        for name in adds {
+            let proposal = Proposal::Add(AddProposal {
+                key_package: new_kpb(name).get_key_package().clone(),
+            });
+            proposal_id_list
+                .adds
+                .push(proposal.to_proposal_id(&ciphersuite));
+            proposal_queue.add(
+                QueuedProposal::new(proposal, LeafIndex::from(0u32), None),
+                &ciphersuite,
+            );
+        }
+        tree.apply_proposals(
+            LeafIndex::from(0u32),
+            &proposal_id_list,
+            proposal_queue,
+            &HashMap::new(),
+        )
+        .unwrap();
+    };
+    // The cached resolutions have to match freshly computed ones
+    let check = |tree: &mut RatchetTree| {
+        for i in 0..tree.nodes.len() {
+            let index = NodeIndex::try_from(i).unwrap();
+            assert_eq!(
+                tree.resolve(index),
+                resolve(&tree.nodes, index, tree.leaf_count())
+            );
+        }
+    };
+
+    let mut tree = RatchetTree::new(ciphersuite, new_kpb("Alice"));
+    apply(&mut tree, &["Bob", "Charlie", "Dave", "Eve", "Frank"], &[]);
+    check(&mut tree);
+
+    // A new path changes the resolutions of the own ancestors
+    tree.update_own_leaf(None, new_kpb("Alice"), &[1u8; 32], &[], true);
+    check(&mut tree);
+
+    // Removed members are blanked along their direct path
+    apply(&mut tree, &[], &[3]);
+    check(&mut tree);
+
+    // Joiners become unmerged leaves of their non-blank ancestors
+    apply(&mut tree, &["Grace", "Heidi", "Ivan"], &[]);
+    check(&mut tree);
+
+    // The tree shrinks when the rightmost members are removed
+    apply(&mut tree, &[], &[7, 6]);
+    check(&mut tree);
+    assert_eq!(tree.leaf_count(), 6);
+}
+
+#[test]
+fn test_encrypt_to_copath_repeated_keys() {
+    use crate::ciphersuite::*;
+    use crate::creds::*;
+    use crate::key_packages::*;
+    use crate::messages::proposals::*;
+    use crate::tree::{index::*, *};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let new_kpb = |name: &str, key_pair: Option<&HPKEKeyPair>| {
+        let identity = Identity::new(ciphersuite, name.as_bytes().to_vec());
+        let credential = Credential::Basic(BasicCredential::from(&identity));
+        let signature_key = identity.get_signature_key_pair().get_private_key();
+        match key_pair {
+            Some(key_pair) => KeyPackageBundle::new_with_keypair(
+                &ciphersuite,
+                signature_key,
+                credential,
+                None,
+                key_pair,
+            ),
+            None => KeyPackageBundle::new(&ciphersuite, signature_key, credential, None),
+        }
+    };
+
+    // Two of Bob's devices share an init key and end up in the resolution of
+    // the same copath node of Alice
+    let key_pair = ciphersuite.new_hpke_keypair();
+    let mut tree = RatchetTree::new(ciphersuite, new_kpb("Alice", None));
+    let mut proposal_queue = ProposalQueue::new();
+    let mut proposal_id_list = ProposalIDList {
+        updates: vec![],
+        removes: vec![],
+        adds: vec![],
+        psks: vec![],
+    };
+    for kpb in &[
+        new_kpb("Charlie", None),
+        new_kpb("Bob", Some(&key_pair)),
+        new_kpb("Bob's phone", Some(&key_pair)),
+    ] {
+        let proposal = Proposal::Add(AddProposal {
+            key_package: kpb.get_key_package().clone(),
+        });
+        proposal_id_list
+            .adds
+            .push(proposal.to_proposal_id(&ciphersuite));
+        proposal_queue.add(
+            QueuedProposal::new(proposal, LeafIndex::from(0u32), None),
+            &ciphersuite,
+        );
+    }
+    tree.apply_proposals(
+        LeafIndex::from(0u32),
+        &proposal_id_list,
+        proposal_queue,
+        &HashMap::new(),
+    )
+    .unwrap();
+
+    // The path secret is only encrypted once for the repeated key
+    let (_, _, direct_path, path_secrets) =
+        tree.update_own_leaf(None, new_kpb("Alice", None), &[1u8; 32], &[], true);
+    let ciphertexts = &direct_path.unwrap().nodes[1].encrypted_path_secret;
+    assert_eq!(ciphertexts.len(), 2);
+    assert_eq!(ciphertexts[0], ciphertexts[1]);
+    assert_eq!(
+        ciphersuite
+            .hpke_open(&ciphertexts[0], key_pair.get_private_key(), &[], &[])
+            .unwrap(),
+        path_secrets.unwrap()[1]
+    );
+}
@@ -12,7 +12,7 @@ fn verify_binary_test_vector_treemath() {
 
     let cursor = &mut Cursor::new(&buffer);
 
-    let tree_size = LeafIndex::from(u32::decode(cursor).unwrap());
+    let tree_size = u32::decode(cursor).unwrap();
 
     let root: Vec<u32> = decode_vec(VecSize::VecU32, cursor).unwrap();
     let left: Vec<u32> = decode_vec(VecSize::VecU32, cursor).unwrap();
@@ -21,28 +21,103 @@ fn verify_binary_test_vector_treemath() {
     let sibling: Vec<u32> = decode_vec(VecSize::VecU32, cursor).unwrap();
 
     for (i, r) in root.iter().enumerate() {
-        assert_eq!(NodeIndex::from(*r), treemath::root(LeafIndex::from(i + 1)));
+        assert_eq!(NodeIndex::from(*r), treemath::root(i as u32 + 1));
     }
     for (i, l) in left.iter().enumerate() {
-        assert_eq!(NodeIndex::from(*l), treemath::left(NodeIndex::from(i)));
+        assert_eq!(
+            NodeIndex::from(*l),
+            treemath::left(NodeIndex::from(i as u32))
+        );
     }
     for (i, r) in right.iter().enumerate() {
         assert_eq!(
             NodeIndex::from(*r),
-            treemath::right(NodeIndex::from(i), tree_size)
+            treemath::right(NodeIndex::from(i as u32), tree_size)
         );
     }
     for (i, p) in parent.iter().enumerate() {
         assert_eq!(
             NodeIndex::from(*p),
-            treemath::parent(NodeIndex::from(i), tree_size)
+            treemath::parent(NodeIndex::from(i as u32), tree_size)
         );
     }
     for (i, s) in sibling.iter().enumerate() {
         assert_eq!(
             NodeIndex::from(*s),
-            treemath::sibling(NodeIndex::from(i), tree_size)
+            treemath::sibling(NodeIndex::from(i as u32), tree_size)
         );
     }
     assert_eq!(cursor.has_more(), false);
 }
+
+#[test]
+fn test_index_conversions() {
+    use crate::tree::*;
+    use std::convert::TryFrom;
+
+    for i in 0..8u32 {
+        let leaf_index = LeafIndex::from(i);
+        let node_index = NodeIndex::try_from(leaf_index).unwrap();
+        assert_eq!(node_index, NodeIndex::from(2 * i));
+        assert_eq!(LeafIndex::try_from_node_index(node_index), Some(leaf_index));
+        assert_eq!(
+            LeafIndex::try_from_node_index(NodeIndex::from(2 * i + 1)),
+            None
+        );
+    }
+
+    assert_eq!(LeafIndex::leaf_count(0), Ok(0));
+    assert_eq!(LeafIndex::leaf_count(1), Ok(1));
+    assert_eq!(LeafIndex::leaf_count(7), Ok(4));
+    assert_eq!(LeafIndex::leaf_count(8), Ok(4));
+
+    assert!(NodeIndex::try_from(u32::MAX as usize).is_ok());
+    assert!(LeafIndex::try_from(u32::MAX as usize).is_ok());
+    if let Some(too_large) = (u32::MAX as usize).checked_add(1) {
+        assert!(NodeIndex::try_from(too_large).is_err());
+        assert!(LeafIndex::try_from(too_large).is_err());
+    }
+}
+
+#[test]
+fn leaf_index_overflow() {
+    use crate::tree::index::*;
+    use std::convert::TryFrom;
+
+    // The largest leaf index has no node index
+    assert!(NodeIndex::try_from(LeafIndex::from(u32::MAX)).is_err());
+    assert_eq!(
+        NodeIndex::try_from(LeafIndex::from(u32::MAX / 2)),
+        Ok(NodeIndex::from(u32::MAX - 1))
+    );
+}
+
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn leaf_count_overflow() {
+    use crate::tree::index::*;
+
+    // Only 64-bit targets can have more nodes than fit into node indices
+    assert_eq!(LeafIndex::leaf_count(u32::MAX as usize), Ok(1u32 << 31));
+    assert!(LeafIndex::leaf_count(u32::MAX as usize + 1).is_err());
+    assert!(LeafIndex::leaf_count(u32::MAX as usize * 2 + 2).is_err());
+}
+
+#[cfg(target_pointer_width = "32")]
+#[test]
+fn index_conversions_32_bit() {
+    use crate::tree::index::*;
+    use crate::tree::treemath;
+    use std::convert::TryFrom;
+
+    // Every `usize` is a valid index, the largest tree has a root
+    assert!(NodeIndex::try_from(usize::MAX).is_ok());
+    assert!(LeafIndex::try_from(usize::MAX).is_ok());
+    assert_eq!(LeafIndex::leaf_count(usize::MAX), Ok(1u32 << 31));
+    let size = 1u32 << 31;
+    assert_eq!(treemath::root(size), NodeIndex::from((1u32 << 31) - 1));
+    assert_eq!(
+        treemath::dirpath_root(NodeIndex::from(0u32), size).len(),
+        31
+    );
+}
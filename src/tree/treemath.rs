@@ -14,10 +14,19 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see http://www.gnu.org/licenses/.
 
-use std::cmp::Ordering;
 use crate::tree::index::*;
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+use core::cmp::Ordering;
+use core::convert::TryFrom;
 
-pub(crate) fn log2(x: usize) -> usize {
+/// Convert the result of index arithmetic back into a `NodeIndex`. Indices
+/// are computed within the bounds of a tree, so they always fit.
+fn node_index(x: usize) -> NodeIndex {
+    NodeIndex::try_from(x).unwrap()
+}
+
+pub fn log2(x: usize) -> usize {
     if x == 0 {
         return 0;
     }
@@ -28,7 +37,7 @@ pub(crate) fn log2(x: usize) -> usize {
     k - 1
 }
 
-pub(crate) fn level(index: NodeIndex) -> usize {
+pub fn level(index: NodeIndex) -> usize {
     let x = index.as_usize();
     if (x & 0x01) == 0 {
         return 0;
@@ -40,48 +49,48 @@ pub(crate) fn level(index: NodeIndex) -> usize {
     k
 }
 
-pub(crate) fn node_width(n: usize) -> usize {
+pub fn node_width(n: usize) -> usize {
     2 * (n - 1) + 1
 }
 
-pub(crate) fn root(size: LeafIndex) -> NodeIndex {
-    let n = size.as_usize();
+pub fn root(size: u32) -> NodeIndex {
+    let n = size as usize;
     let w = node_width(n);
-    NodeIndex::from((1usize << log2(w)) - 1)
+    node_index((1usize << log2(w)) - 1)
 }
 
-pub(crate) fn left(index: NodeIndex) -> NodeIndex {
+pub fn left(index: NodeIndex) -> NodeIndex {
     let x = index.as_usize();
-    let k = level(NodeIndex::from(x));
+    let k = level(index);
     if k == 0 {
-        return NodeIndex::from(x);
+        return index;
     }
-    NodeIndex::from(x ^ (0x01 << (k - 1)))
+    node_index(x ^ (0x01 << (k - 1)))
 }
 
-pub(crate) fn right(index: NodeIndex, size: LeafIndex) -> NodeIndex {
+pub fn right(index: NodeIndex, size: u32) -> NodeIndex {
     let x = index.as_usize();
-    let n = size.as_usize();
-    let k = level(NodeIndex::from(x));
+    let n = size as usize;
+    let k = level(index);
     if k == 0 {
-        return NodeIndex::from(x);
+        return index;
     }
     let mut r = x ^ (0x03 << (k - 1));
     while r >= node_width(n) {
-        r = left(NodeIndex::from(r)).as_usize();
+        r = left(node_index(r)).as_usize();
     }
-    NodeIndex::from(r)
+    node_index(r)
 }
 
-pub(crate) fn parent_step(x: usize) -> usize {
-    let k = level(NodeIndex::from(x));
+pub fn parent_step(x: usize) -> usize {
+    let k = level(node_index(x));
     let b = (x >> (k + 1)) & 0x01;
     (x | (1 << k)) ^ (b << (k + 1))
 }
 
-pub(crate) fn parent(index: NodeIndex, size: LeafIndex) -> NodeIndex {
+pub fn parent(index: NodeIndex, size: u32) -> NodeIndex {
     let x = index.as_usize();
-    let n = size.as_usize();
+    let n = size as usize;
     if index == root(size) {
         return index;
     }
@@ -89,10 +98,10 @@ pub(crate) fn parent(index: NodeIndex, size: LeafIndex) -> NodeIndex {
     while p >= node_width(n) {
         p = parent_step(p)
     }
-    NodeIndex::from(p)
+    node_index(p)
 }
 
-pub(crate) fn sibling(index: NodeIndex, size: LeafIndex) -> NodeIndex {
+pub fn sibling(index: NodeIndex, size: u32) -> NodeIndex {
     let p = parent(index, size);
     match index.cmp(&p) {
         Ordering::Less => right(p, size),
@@ -103,7 +112,7 @@ pub(crate) fn sibling(index: NodeIndex, size: LeafIndex) -> NodeIndex {
 
 // Ordered from leaf to root
 // Includes neither leaf nor root
-pub(crate) fn dirpath(index: NodeIndex, size: LeafIndex) -> Vec<NodeIndex> {
+pub fn dirpath(index: NodeIndex, size: u32) -> Vec<NodeIndex> {
     let mut d = vec![];
     let mut p = parent(index, size);
     let r = root(size);
@@ -116,7 +125,7 @@ pub(crate) fn dirpath(index: NodeIndex, size: LeafIndex) -> Vec<NodeIndex> {
 
 // Ordered from leaf to root
 // Includes leaf and root
-pub(crate) fn dirpath_long(index: NodeIndex, size: LeafIndex) -> Vec<NodeIndex> {
+pub fn dirpath_long(index: NodeIndex, size: u32) -> Vec<NodeIndex> {
     let mut d = vec![index];
     let mut p = parent(index, size);
     let r = root(size);
@@ -133,7 +142,7 @@ pub(crate) fn dirpath_long(index: NodeIndex, size: LeafIndex) -> Vec<NodeIndex>
 
 // Ordered from leaf to root
 // Includes root but not leaf
-pub(crate) fn dirpath_root(index: NodeIndex, size: LeafIndex) -> Vec<NodeIndex> {
+pub fn dirpath_root(index: NodeIndex, size: u32) -> Vec<NodeIndex> {
     let mut d = vec![];
     let mut p = parent(index, size);
     let r = root(size);
@@ -146,13 +155,13 @@ pub(crate) fn dirpath_root(index: NodeIndex, size: LeafIndex) -> Vec<NodeIndex>
 }
 
 // Ordered from leaf to root
-pub(crate) fn copath(index: NodeIndex, size: LeafIndex) -> Vec<NodeIndex> {
+pub fn copath(index: NodeIndex, size: u32) -> Vec<NodeIndex> {
     let mut d = vec![index];
     d.append(&mut dirpath(index, size));
     d.iter().map(|&index| sibling(index, size)).collect()
 }
 
-pub(crate) fn common_ancestor(x: NodeIndex, y: NodeIndex) -> NodeIndex {
+pub fn common_ancestor(x: NodeIndex, y: NodeIndex) -> NodeIndex {
     let (mut xn, mut yn) = (x.as_usize(), y.as_usize());
     let mut k = 0;
     while xn != yn {
@@ -160,5 +169,5 @@ pub(crate) fn common_ancestor(x: NodeIndex, y: NodeIndex) -> NodeIndex {
         yn >>= 1;
         k += 1;
     }
-    NodeIndex::from((xn << k) + (1 << (k - 1)) - 1)
+    node_index((xn << k) + (1 << (k - 1)) - 1)
 }
@@ -14,6 +14,188 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see http://www.gnu.org/licenses/.
 
+use crate::ciphersuite::*;
+use crate::creds::*;
+use crate::extensions::*;
+use crate::framing::*;
+use crate::group::*;
+use crate::key_packages::*;
+use crate::messages::proposals::*;
+use crate::tree::{index::*, node::*};
+use std::convert::TryFrom;
+
+/// Validate a `KeyPackage` received from another client at the current time
+/// of `time_provider`, see `KeyPackage::validate_with_time`.
+pub fn validate_key_package(
+    key_package: &KeyPackage,
+    time_provider: &dyn TimeProvider,
+) -> Result<(), KeyPackageError> {
+    key_package.validate_with_time(time_provider)
+}
+
+/// Validate the extensions of a group context. Extension types must be unique
+/// and the data of known extensions well-formed.
+pub fn validate_group_context_extensions(extensions: &[Extension]) -> Result<(), ExtensionError> {
+    validate_extensions(extensions, &[])
+}
+
+/// Find the leaf of the member whose `KeyIDExtension` contains `key_id` in an
+/// exported tree.
+pub fn find_member_by_key_id(nodes: &[Option<Node>], key_id: &[u8]) -> Option<LeafIndex> {
+    let leaves = nodes
+        .iter()
+        .step_by(2)
+        .map(|node| node.as_ref().and_then(|node| node.leaf_node.as_ref()));
+    find_leaf_by_key_id(leaves, key_id)
+}
+
+/// Find the leaf whose `KeyIDExtension` contains `key_id` among `leaves`,
+/// which are the leaves of a tree in order, `None` for blank ones.
+pub(crate) fn find_leaf_by_key_id<'a>(
+    mut leaves: impl Iterator<Item = Option<&'a LeafNode>>,
+    key_id: &[u8],
+) -> Option<LeafIndex> {
+    leaves
+        .position(|leaf_node| {
+            leaf_node.map_or(false, |leaf_node| leaf_has_key_id(leaf_node, key_id))
+        })
+        .and_then(|i| LeafIndex::try_from(i).ok())
+}
+
+/// Returns `true` if the `KeyIDExtension` of `leaf_node` contains `key_id`.
+fn leaf_has_key_id(leaf_node: &LeafNode, key_id: &[u8]) -> bool {
+    match leaf_node.get_extension(ExtensionType::KeyID) {
+        Some(ExtensionPayload::KeyID(key_id_extension)) => key_id_extension.get_key_id() == key_id,
+        _ => false,
+    }
+}
+
+/// Get the `DeviceCapabilitiesExtension` of `leaf_node`. Leaves without the
+/// extension have no capabilities.
+pub fn get_device_capabilities(leaf_node: &LeafNode) -> DeviceCapabilitiesExtension {
+    device_capabilities(leaf_node.get_extension(ExtensionType::DeviceCapabilities))
+}
+
+/// Like `get_device_capabilities`, for the leaf created from `key_package`.
+pub fn get_key_package_device_capabilities(
+    key_package: &KeyPackage,
+) -> DeviceCapabilitiesExtension {
+    device_capabilities(key_package.get_extension(ExtensionType::DeviceCapabilities))
+}
+
+fn device_capabilities(payload: Option<ExtensionPayload>) -> DeviceCapabilitiesExtension {
+    match payload {
+        Some(ExtensionPayload::DeviceCapabilities(device_capabilities)) => device_capabilities,
+        _ => DeviceCapabilitiesExtension::default(),
+    }
+}
+
+/// Returns `true` if the `DeviceCapabilitiesExtension` of `leaf_node` contains
+/// `capability`.
+pub fn leaf_has_device_capability(leaf_node: &LeafNode, capability: DeviceCapability) -> bool {
+    get_device_capabilities(leaf_node).has(capability)
+}
+
+/// Validate the device capabilities of the `key_package` of an Add or Update
+/// proposal. `granter` is the leaf of the member that grants the
+/// capabilities: the sender of an Add, or the old leaf of the member for an
+/// Update. `NonRemovableCap` can only be granted by members that have it, so
+/// members can keep it through an Update but can't give it to themselves.
+/// Other capabilities can be changed freely, e.g. to promote a temporary
+/// device to a permanent one.
+pub fn validate_device_capabilities(
+    granter: Option<&LeafNode>,
+    key_package: &KeyPackage,
+) -> Result<(), ProposalValidationError> {
+    let grants_non_removable =
+        get_key_package_device_capabilities(key_package).has(DeviceCapability::NonRemovableCap);
+    let may_grant = granter
+        .map(|leaf_node| leaf_has_device_capability(leaf_node, DeviceCapability::NonRemovableCap))
+        .unwrap_or(false);
+    if grants_non_removable && !may_grant {
+        return Err(ProposalValidationError::InvalidDeviceCapabilities);
+    }
+    Ok(())
+}
+
+/// Validate that the member `removed` can be removed, i.e. doesn't have
+/// `NonRemovableCap`.
+pub fn validate_removable(removed: &LeafNode) -> Result<(), ProposalValidationError> {
+    if leaf_has_device_capability(removed, DeviceCapability::NonRemovableCap) {
+        return Err(ProposalValidationError::NonRemovableMember);
+    }
+    Ok(())
+}
+
+/// Create a Remove proposal for the member at `removed` on behalf of the
+/// external sender at `sender_index` in the group's
+/// `ExternalSendersExtension`, e.g. for a server that removes the clients of
+/// a deleted account. `group_context` is the context of the current epoch.
+pub fn create_external_remove_proposal(
+    ciphersuite: &Ciphersuite,
+    group_context: &GroupContext,
+    sender_index: u32,
+    removed: LeafIndex,
+    signature_key: &SignaturePrivateKey,
+) -> MLSPlaintext {
+    let proposal = Proposal::Remove(RemoveProposal {
+        removed: removed.as_u32(),
+    });
+    MLSPlaintext::new_with_sender(
+        ciphersuite,
+        Sender::external(sender_index),
+        &[],
+        MLSPlaintextContentType::Proposal(proposal),
+        signature_key,
+        group_context,
+    )
+}
+
+/// Like `create_external_remove_proposal`, for the member whose
+/// `KeyIDExtension` contains `key_id` in the exported tree `nodes`.
+/// Returns `None` if there is no such member.
+pub fn create_external_remove_proposal_by_key_id(
+    ciphersuite: &Ciphersuite,
+    group_context: &GroupContext,
+    nodes: &[Option<Node>],
+    sender_index: u32,
+    key_id: &[u8],
+    signature_key: &SignaturePrivateKey,
+) -> Option<MLSPlaintext> {
+    let removed = find_member_by_key_id(nodes, key_id)?;
+    Some(create_external_remove_proposal(
+        ciphersuite,
+        group_context,
+        sender_index,
+        removed,
+        signature_key,
+    ))
+}
+
+/// Verify that `mls_plaintext` is a valid Remove proposal of one of the
+/// group's external senders, see `MlsGroup::process_proposal`.
+/// Returns the credentials of the external sender and of the removed member.
+pub fn verify_external_remove_proposal(
+    group: &MlsGroup,
+    mls_plaintext: &MLSPlaintext,
+) -> Result<(Credential, Credential), ProposalError> {
+    if mls_plaintext.sender.sender_type != SenderType::External {
+        return Err(ProposalError::InvalidSender);
+    }
+    let (queued_proposal, sender_credential) = group.verify_proposal(mls_plaintext.clone())?;
+    let remove_proposal = queued_proposal
+        .proposal
+        .as_remove()
+        .ok_or(ProposalError::UnexpectedProposalType)?;
+    let removed_credential = group
+        .get_tree()
+        .get_leaf_node(LeafIndex::from(remove_proposal.removed))
+        .ok_or(ProposalError::RemoveTargetNotAMember)?
+        .get_credential()
+        .clone();
+    Ok((sender_credential, removed_credential))
+}
+
 /*
 use crate::framing::*;
 use crate::group::*;
@@ -56,7 +238,7 @@ impl<'a> Validator<'a> {
                 if removed >= self.group.tree.tree_size() {
                     return false;
                 }
-                if self.group.tree.nodes[removed.as_usize()].is_blank() {
+                if self.group.tree.nodes()[removed.as_usize()].is_blank() {
                     return false;
                 }
                 true
@@ -0,0 +1,153 @@
+#![cfg(feature = "test-utils")]
+
+use maelstrom::ciphersuite::*;
+use maelstrom::creds::*;
+use maelstrom::group::*;
+use maelstrom::key_packages::*;
+use maelstrom::test_utils::*;
+use maelstrom::transport::*;
+
+#[test]
+fn mock_delivery_service() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let names: Vec<&[u8]> = vec![b"Alice", b"Bob", b"Charlie"];
+    let identities: Vec<Identity> = names
+        .iter()
+        .map(|name| Identity::new(ciphersuite, name.to_vec()))
+        .collect();
+    let key_package_bundles: Vec<KeyPackageBundle> = identities
+        .iter()
+        .map(|identity| {
+            KeyPackageBundle::new(
+                &ciphersuite,
+                identity.get_signature_key_pair().get_private_key(),
+                Credential::Basic(BasicCredential::from(identity)),
+                None,
+            )
+        })
+        .collect();
+    let alice_signature_key = identities[0].get_signature_key_pair().get_private_key();
+
+    // Alice creates the group, the Welcome is delivered reliably
+    let mut delivery_service = MockDeliveryService::new(DeliveryOptions::reliable(), 42).unwrap();
+    let (mut group_alice, welcome) = MlsGroup::new_with_members(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        key_package_bundles[0].clone(),
+        alice_signature_key,
+        key_package_bundles[1..]
+            .iter()
+            .map(|kpb| kpb.get_key_package().clone())
+            .collect(),
+    )
+    .unwrap();
+    let group_id = group_alice.get_context().group_id.clone();
+    for name in names.iter() {
+        delivery_service.subscribe(&group_id, name);
+    }
+    for name in names[1..].iter() {
+        delivery_service.send_to(
+            name,
+            MLSMessage::Welcome {
+                welcome: welcome.clone(),
+                ratchet_tree: Some(group_alice.get_tree().public_key_tree()),
+            },
+        );
+    }
+    let mut groups: Vec<MlsGroup> = names[1..]
+        .iter()
+        .zip(key_package_bundles[1..].iter())
+        .map(|(name, kpb)| match delivery_service.receive(name).pop() {
+            Some(MLSMessage::Welcome {
+                welcome,
+                ratchet_tree,
+            }) => MlsGroup::new_from_welcome(welcome, ratchet_tree, kpb.clone()).unwrap(),
+            _ => panic!("Expected a Welcome"),
+        })
+        .collect();
+    assert_eq!(delivery_service.pending(b"Alice"), 0);
+
+    // Application messages are reordered, duplicated and dropped
+    delivery_service
+        .set_options(DeliveryOptions {
+            reorder: true,
+            duplicate_rate: 0.2,
+            drop_rate: 0.2,
+        })
+        .unwrap();
+    const MESSAGES: usize = 50;
+    for i in 0..MESSAGES {
+        let mls_plaintext =
+            group_alice.create_application_message(&[], &[i as u8], alice_signature_key);
+        let mls_ciphertext = group_alice.encrypt(mls_plaintext).unwrap();
+        delivery_service.send(&group_id, b"Alice", MLSMessage::Ciphertext(mls_ciphertext));
+    }
+    assert!(delivery_service.dropped() > 0);
+    assert!(delivery_service.duplicated() > 0);
+
+    // Every copy that wasn't dropped arrives and can be decrypted
+    let mut received = 0;
+    for (name, group) in names[1..].iter().zip(groups.iter_mut()) {
+        for message in delivery_service.receive(name) {
+            match message {
+                MLSMessage::Ciphertext(mls_ciphertext) => {
+                    group.decrypt(mls_ciphertext).unwrap();
+                    received += 1;
+                }
+                _ => panic!("Expected a ciphertext"),
+            }
+        }
+    }
+    assert_eq!(
+        received + delivery_service.dropped(),
+        MESSAGES * (names.len() - 1) + delivery_service.duplicated()
+    );
+
+    // Unsubscribed clients don't receive messages
+    delivery_service
+        .set_options(DeliveryOptions::reliable())
+        .unwrap();
+    delivery_service.unsubscribe(&group_id, b"Charlie");
+    let mls_plaintext = group_alice.create_application_message(&[], &[0], alice_signature_key);
+    let mls_ciphertext = group_alice.encrypt(mls_plaintext).unwrap();
+    delivery_service.send(&group_id, b"Alice", MLSMessage::Ciphertext(mls_ciphertext));
+    assert_eq!(delivery_service.pending(b"Bob"), 1);
+    assert_eq!(delivery_service.pending(b"Charlie"), 0);
+}
+
+#[test]
+fn invalid_delivery_options() {
+    let invalid_options = [
+        DeliveryOptions {
+            drop_rate: 1.5,
+            ..DeliveryOptions::default()
+        },
+        DeliveryOptions {
+            duplicate_rate: -0.1,
+            ..DeliveryOptions::default()
+        },
+        DeliveryOptions {
+            drop_rate: f64::NAN,
+            ..DeliveryOptions::default()
+        },
+    ];
+    for options in invalid_options.iter() {
+        assert_eq!(
+            MockDeliveryService::new(*options, 42).err(),
+            Some(DeliveryOptionsError::InvalidRate)
+        );
+    }
+
+    let mut delivery_service = MockDeliveryService::new(DeliveryOptions::reliable(), 42).unwrap();
+    assert_eq!(
+        delivery_service.set_options(invalid_options[0]),
+        Err(DeliveryOptionsError::InvalidRate)
+    );
+    assert!(delivery_service
+        .set_options(DeliveryOptions {
+            drop_rate: 1.0,
+            ..DeliveryOptions::default()
+        })
+        .is_ok());
+}
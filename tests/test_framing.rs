@@ -2,9 +2,14 @@ mod test_utils;
 use test_utils::*;
 
 use maelstrom::ciphersuite::*;
+use maelstrom::codec::*;
 use maelstrom::creds::*;
+use maelstrom::extensions::*;
+use maelstrom::framing::*;
 use maelstrom::group::*;
 use maelstrom::key_packages::*;
+use maelstrom::metrics;
+use maelstrom::transport::MLSMessage;
 
 #[test]
 fn padding() {
@@ -32,7 +37,7 @@ fn padding() {
             &message,
             signature_keypair.get_private_key(),
         );
-        let encrypted_message = group_alice.encrypt(mls_plaintext).as_slice();
+        let encrypted_message = group_alice.encrypt(mls_plaintext).unwrap().as_slice();
         let length = encrypted_message.len();
         let overflow = length % PADDING_SIZE;
         if overflow != 0 {
@@ -43,3 +48,382 @@ fn padding() {
         }
     }
 }
+
+#[test]
+fn authenticated_data() {
+    let ciphersuite_name = CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
+    let ciphersuite = Ciphersuite::new(ciphersuite_name);
+    let id = vec![1, 2, 3];
+    let signature_keypair = ciphersuite.new_signature_keypair();
+    let identity =
+        Identity::new_with_keypair(ciphersuite, vec![1, 2, 3], signature_keypair.clone());
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let kpb = KeyPackageBundle::new(
+        &ciphersuite,
+        signature_keypair.get_private_key(),
+        credential,
+        None,
+    );
+
+    // Messages are timestamped by the time provider of the group
+    struct FixedTime;
+    impl TimeProvider for FixedTime {
+        fn now(&self) -> u64 {
+            1_234_567
+        }
+    }
+    let mut config = GroupConfig::default();
+    config.set_time_provider(std::sync::Arc::new(FixedTime));
+    let mut group_alice = MlsGroup::new_with_config(&id, ciphersuite, kpb, vec![], &config);
+
+    // The receiver can read the AAD of a decrypted message
+    let aad = randombytes(100);
+    let mls_plaintext = group_alice.create_application_message(
+        &aad,
+        &[1, 2, 3],
+        signature_keypair.get_private_key(),
+    );
+    let mls_ciphertext = group_alice.encrypt(mls_plaintext).unwrap();
+    let processed_message = group_alice.decrypt(mls_ciphertext).unwrap();
+    assert_eq!(processed_message.aad(), &aad[..]);
+
+    // The sender is identified by its leaf index and credential
+    assert_eq!(processed_message.sender_index().as_u32(), 0);
+    assert_eq!(
+        processed_message.sender_credential(),
+        &Credential::Basic(BasicCredential::from(&identity))
+    );
+
+    // The authenticated context doesn't require looking into the plaintext
+    let authenticated_content = processed_message.authenticated_content();
+    assert_eq!(
+        authenticated_content.group_id(),
+        &group_alice.get_context().group_id
+    );
+    assert_eq!(
+        authenticated_content.epoch(),
+        group_alice.get_context().epoch
+    );
+    assert_eq!(authenticated_content.sender_index().as_u32(), 0);
+    assert_eq!(
+        authenticated_content.sender_credential(),
+        processed_message.sender_credential()
+    );
+    assert_eq!(
+        authenticated_content.content_type(),
+        ContentType::Application
+    );
+    assert_eq!(authenticated_content.authenticated_data(), &aad[..]);
+    assert_eq!(authenticated_content.timestamp(), 1_234_567);
+
+    // AAD exceeding the maximum size is rejected
+    let mls_plaintext = group_alice.create_application_message(
+        &vec![0u8; MAX_AAD_SIZE + 1],
+        &[1, 2, 3],
+        signature_keypair.get_private_key(),
+    );
+    assert_eq!(
+        group_alice.encrypt(mls_plaintext).err(),
+        Some(EncryptionError::AadTooLarge)
+    );
+}
+
+#[test]
+fn membership_tag() {
+    let ciphersuite_name = CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
+    let ciphersuite = Ciphersuite::new(ciphersuite_name);
+    let id = vec![1, 2, 3];
+    let identity = Identity::new(ciphersuite, vec![1, 2, 3]);
+    let signature_keypair = ciphersuite.new_signature_keypair();
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let kpb = KeyPackageBundle::new(
+        &ciphersuite,
+        signature_keypair.get_private_key(),
+        credential,
+        None,
+    );
+
+    let mut group_alice = MlsGroup::new(&id, ciphersuite, kpb);
+    let mls_plaintext = group_alice.create_application_message(
+        &[],
+        &[1, 2, 3],
+        signature_keypair.get_private_key(),
+    );
+    let mut mls_ciphertext = group_alice.encrypt(mls_plaintext).unwrap();
+    let membership_tag = group_alice.membership_tag(&mls_ciphertext);
+
+    // The delivery service only needs the membership key to verify the tag
+    let membership_key = group_alice.export_membership_key();
+    assert!(membership_tag.verify(&ciphersuite, &membership_key, &mls_ciphertext));
+
+    // A modified ciphertext doesn't verify
+    mls_ciphertext.ciphertext[0] ^= 0xff;
+    assert!(!membership_tag.verify(&ciphersuite, &membership_key, &mls_ciphertext));
+}
+
+#[test]
+fn decryption_errors() {
+    let ciphersuite_name = CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
+    let ciphersuite = Ciphersuite::new(ciphersuite_name);
+    let id = vec![1, 2, 3];
+    let signature_keypair = ciphersuite.new_signature_keypair();
+    let identity =
+        Identity::new_with_keypair(ciphersuite, vec![1, 2, 3], signature_keypair.clone());
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let kpb = KeyPackageBundle::new(
+        &ciphersuite,
+        signature_keypair.get_private_key(),
+        credential,
+        None,
+    );
+
+    let mut group_alice = MlsGroup::new(&id, ciphersuite, kpb);
+    let mls_plaintext = group_alice.create_application_message(
+        &[],
+        &[1, 2, 3],
+        signature_keypair.get_private_key(),
+    );
+    let mls_ciphertext = group_alice.encrypt(mls_plaintext).unwrap();
+    assert!(group_alice.decrypt(mls_ciphertext.clone()).is_ok());
+    let decryption_failures = metrics::decryption_failures();
+
+    // A message from another epoch is rejected before decryption
+    let mut wrong_epoch = mls_ciphertext.clone();
+    wrong_epoch.epoch = GroupEpoch(wrong_epoch.epoch.0 + 1);
+    assert_eq!(
+        group_alice.decrypt(wrong_epoch).err(),
+        Some(DecryptionError::WrongEpoch)
+    );
+
+    // Modified sender data or content fails to decrypt
+    let mut modified_sender_data = mls_ciphertext.clone();
+    modified_sender_data.encrypted_sender_data[0] ^= 0xff;
+    assert_eq!(
+        group_alice.decrypt(modified_sender_data).err(),
+        Some(DecryptionError::AeadError)
+    );
+    let mut modified_content = mls_ciphertext;
+    modified_content.ciphertext[0] ^= 0xff;
+    assert_eq!(
+        group_alice.decrypt(modified_content).err(),
+        Some(DecryptionError::AeadError)
+    );
+
+    // A message signed with a key that doesn't match the sender's credential
+    let other_keypair = ciphersuite.new_signature_keypair();
+    let mls_plaintext =
+        group_alice.create_application_message(&[], &[1, 2, 3], other_keypair.get_private_key());
+    let mls_ciphertext = group_alice.encrypt(mls_plaintext).unwrap();
+    assert_eq!(
+        group_alice.decrypt(mls_ciphertext).err(),
+        Some(DecryptionError::InvalidSignature)
+    );
+
+    // All failures are counted
+    assert!(metrics::decryption_failures() >= decryption_failures + 4);
+}
+
+#[test]
+fn message_header() {
+    let ciphersuite_name = CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
+    let ciphersuite = Ciphersuite::new(ciphersuite_name);
+    let id = vec![1, 2, 3];
+    let identity = Identity::new(ciphersuite, vec![1, 2, 3]);
+    let signature_keypair = ciphersuite.new_signature_keypair();
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let kpb = KeyPackageBundle::new(
+        &ciphersuite,
+        signature_keypair.get_private_key(),
+        credential,
+        None,
+    );
+
+    let mut group_alice = MlsGroup::new(&id, ciphersuite, kpb);
+    let mls_plaintext = group_alice.create_application_message(
+        &[],
+        &[1, 2, 3],
+        signature_keypair.get_private_key(),
+    );
+    let mls_ciphertext = group_alice.encrypt(mls_plaintext).unwrap();
+    let bytes = MLSMessage::Ciphertext(mls_ciphertext)
+        .encode_detached()
+        .unwrap();
+
+    // The header is parsed without decoding the rest of the message
+    let (group_id, epoch, content_type_hint) = parse_message_header(&bytes).unwrap();
+    assert_eq!(group_id, group_alice.get_context().group_id);
+    assert_eq!(epoch, group_alice.get_context().epoch);
+    assert_eq!(
+        content_type_hint,
+        ContentTypeHint::Ciphertext(ContentType::Application)
+    );
+    assert!(content_type_hint.is_encrypted());
+
+    // The content type of a plaintext comes after the sender and the AAD
+    let mls_plaintext = group_alice.create_application_message(
+        &[7; 100],
+        &[1, 2, 3],
+        signature_keypair.get_private_key(),
+    );
+    let bytes = MLSMessage::Plaintext(mls_plaintext)
+        .encode_detached()
+        .unwrap();
+    let (group_id, epoch, content_type_hint) = parse_message_header(&bytes).unwrap();
+    assert_eq!(group_id, group_alice.get_context().group_id);
+    assert_eq!(epoch, group_alice.get_context().epoch);
+    assert_eq!(
+        content_type_hint,
+        ContentTypeHint::Plaintext(ContentType::Application)
+    );
+    assert_eq!(content_type_hint.content_type(), ContentType::Application);
+
+    // A truncated header and unknown wire formats are rejected
+    assert!(parse_message_header(&bytes[..2]).is_err());
+    assert!(parse_message_header(&bytes[..40]).is_err());
+    let mut bytes = bytes;
+    bytes[0] = 3;
+    assert!(parse_message_header(&bytes).is_err());
+}
+
+#[test]
+fn content_accessors() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let identity = Identity::new(ciphersuite, vec![1, 2, 3]);
+    let signature_key = identity.get_signature_key_pair().get_private_key();
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let new_kpb = || KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None);
+    let mut group_alice = MlsGroup::new(&[1, 2, 3], ciphersuite, new_kpb());
+
+    // Application messages
+    let mls_plaintext = group_alice.create_application_message(b"aad", b"Hello", signature_key);
+    assert_eq!(mls_plaintext.content_type(), ContentType::Application);
+    assert_eq!(
+        mls_plaintext.group_id(),
+        &group_alice.get_context().group_id
+    );
+    assert_eq!(mls_plaintext.epoch(), group_alice.get_context().epoch);
+    assert_eq!(mls_plaintext.sender().sender.as_u32(), 0);
+    assert_eq!(mls_plaintext.authenticated_data(), b"aad");
+    assert_eq!(mls_plaintext.as_application().unwrap().data, b"Hello");
+    assert!(mls_plaintext.as_proposal().is_none());
+    assert!(mls_plaintext.as_commit().is_none());
+    assert!(mls_plaintext.confirmation_tag().is_none());
+
+    // Proposals
+    let (mls_plaintext, update_proposal) = group_alice
+        .create_update_proposal(&[], signature_key, new_kpb())
+        .unwrap();
+    assert_eq!(mls_plaintext.content_type(), ContentType::Proposal);
+    assert_eq!(mls_plaintext.as_proposal(), Some(&update_proposal));
+    assert!(mls_plaintext.as_application().is_none());
+
+    // Commits
+    let commit_bundle = group_alice
+        .update_self(&[], signature_key, new_kpb())
+        .unwrap();
+    let mls_plaintext = commit_bundle.get_commit();
+    assert_eq!(mls_plaintext.content_type(), ContentType::Commit);
+    let commit = mls_plaintext.as_commit().unwrap();
+    assert!(commit.path().is_some());
+    assert!(commit.updates().is_empty());
+    assert!(commit.removes().is_empty());
+    assert!(commit.adds().is_empty());
+    assert!(commit.psks().is_empty());
+    assert!(mls_plaintext.confirmation_tag().is_some());
+    match mls_plaintext.content() {
+        MLSPlaintextContentType::Commit((content, _)) => assert_eq!(content, commit),
+        _ => panic!("Expected a Commit"),
+    }
+}
+
+#[cfg(feature = "test-utils")]
+#[test]
+fn plaintext_builder() {
+    use maelstrom::messages::*;
+    use maelstrom::test_utils::MLSPlaintextBuilder;
+
+    let ciphersuite_name = CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
+    let ciphersuite = Ciphersuite::new(ciphersuite_name);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    let group_alice = MlsGroup::new(
+        &[1, 2, 3],
+        ciphersuite,
+        KeyPackageBundle::new(&ciphersuite, alice_signature_key, alice_credential, None),
+    );
+    let bob_key_package =
+        KeyPackageBundle::new(&ciphersuite, bob_signature_key, bob_credential, None)
+            .get_key_package()
+            .clone();
+    let context = group_alice.get_context();
+    let content = MLSPlaintextContentType::Proposal(Proposal::Add(AddProposal {
+        key_package: bob_key_package,
+    }));
+
+    // A correctly built and signed message is accepted
+    let mls_plaintext =
+        MLSPlaintextBuilder::new(context, Sender::member(0u32.into()), content.clone())
+            .authenticated_data(&[1, 2, 3])
+            .sign(&ciphersuite, alice_signature_key, context);
+    assert!(group_alice.process_proposal(mls_plaintext.clone()).is_ok());
+
+    // Wrong epoch
+    let mut next_epoch = context.epoch;
+    next_epoch.increment();
+    let wrong_epoch = MLSPlaintextBuilder::from_plaintext(&mls_plaintext)
+        .epoch(next_epoch)
+        .sign(&ciphersuite, alice_signature_key, context);
+    assert_eq!(
+        group_alice.process_proposal(wrong_epoch).err(),
+        Some(ProposalError::WrongEpoch)
+    );
+
+    // Wrong group
+    let wrong_group = MLSPlaintextBuilder::from_plaintext(&mls_plaintext)
+        .group_id(GroupId::random())
+        .sign(&ciphersuite, alice_signature_key, context);
+    assert_eq!(
+        group_alice.process_proposal(wrong_group).err(),
+        Some(ProposalError::WrongGroup)
+    );
+
+    // The signature doesn't cover the modified AAD
+    let bad_signature = MLSPlaintextBuilder::from_plaintext(&mls_plaintext)
+        .authenticated_data(&[4, 5, 6])
+        .build();
+    assert_eq!(
+        group_alice.process_proposal(bad_signature).err(),
+        Some(ProposalError::InvalidSignature)
+    );
+
+    // Unsigned messages are rejected
+    let unsigned = MLSPlaintextBuilder::new(context, Sender::member(0u32.into()), content)
+        .authenticated_data(&[1, 2, 3])
+        .build();
+    assert_eq!(
+        group_alice.process_proposal(unsigned).err(),
+        Some(ProposalError::InvalidSignature)
+    );
+
+    // The sender isn't a member
+    let unknown_sender = MLSPlaintextBuilder::from_plaintext(&mls_plaintext)
+        .sender(Sender::member(5u32.into()))
+        .sign(&ciphersuite, alice_signature_key, context);
+    assert_eq!(
+        group_alice.process_proposal(unknown_sender).err(),
+        Some(ProposalError::UnknownSender)
+    );
+
+    // The content type doesn't change the content
+    let wrong_content_type = MLSPlaintextBuilder::from_plaintext(&mls_plaintext)
+        .content_type(ContentType::Commit)
+        .build();
+    assert_eq!(wrong_content_type.content_type(), ContentType::Commit);
+    assert!(wrong_content_type.as_proposal().is_some());
+}
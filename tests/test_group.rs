@@ -1,7 +1,13 @@
 use maelstrom::ciphersuite::*;
 use maelstrom::creds::*;
+use maelstrom::framing::*;
 use maelstrom::group::*;
 use maelstrom::key_packages::*;
+use maelstrom::messages::*;
+use maelstrom::metrics;
+
+mod test_utils;
+use test_utils::*;
 
 #[test]
 fn basic_group_setup() {
@@ -48,11 +54,13 @@ fn basic_group_setup() {
     let mut group_alice_1234 = MlsGroup::new(&group_id, ciphersuite, alice_key_package_bundle);
 
     // Alice adds Bob
-    let bob_add_proposal = group_alice_1234.create_add_proposal(
-        &[],
-        &alice_identity.get_signature_key_pair().get_private_key(),
-        bob_key_package.clone(),
-    );
+    let bob_add_proposal = group_alice_1234
+        .create_add_proposal(
+            &[],
+            &alice_identity.get_signature_key_pair().get_private_key(),
+            bob_key_package.clone(),
+        )
+        .unwrap();
     // group_alice_1234.create_commit(
     //     &[],
     //     &alice_identity.get_signature_key_pair().get_private_key(),
@@ -219,3 +227,4420 @@ fn group_operations() {
     group_alice.tree.print(&format!("\n{:?}", ms9));
 }
 */
+
+#[test]
+fn transcript_hashes() {
+    use evercrypt::prelude::*;
+    use maelstrom::codec::*;
+    use maelstrom::framing::*;
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+    // Compute the transcript hashes following the spec
+    let next_transcript_hashes = |interim_transcript_hash: &[u8], commit: &MLSPlaintext| {
+        let commit_content = MLSPlaintextCommitContent::from(commit.clone())
+            .encode_detached()
+            .unwrap();
+        let confirmed_transcript_hash = hash(
+            DigestMode::Sha256,
+            &[interim_transcript_hash, &commit_content].concat(),
+        );
+        let auth_data = MLSPlaintextCommitAuthData::from(commit.clone())
+            .encode_detached()
+            .unwrap();
+        let interim_transcript_hash = hash(
+            DigestMode::Sha256,
+            &[&confirmed_transcript_hash[..], &auth_data].concat(),
+        );
+        (confirmed_transcript_hash, interim_transcript_hash)
+    };
+
+    // Alice creates a group, the transcript starts out empty
+    let mut group_alice = MlsGroup::new(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(alice_signature_key, &alice_credential),
+    );
+    assert!(group_alice.get_confirmed_transcript_hash().is_empty());
+    assert!(group_alice.get_interim_transcript_hash().is_empty());
+
+    // Alice adds Bob
+    let bob_key_package_bundle = new_kpb(bob_signature_key, &bob_credential);
+    let (add_plaintext, add_proposal) = group_alice
+        .create_add_proposal(
+            &[],
+            alice_signature_key,
+            bob_key_package_bundle.get_key_package().clone(),
+        )
+        .unwrap();
+    let proposals = vec![(add_plaintext.sender(), add_proposal)];
+    let commit_bundle = group_alice
+        .create_commit(
+            &[1, 2, 3],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+            proposals.clone(),
+            true,
+        )
+        .unwrap();
+    let commit = commit_bundle.get_commit().clone();
+    let welcome_option = commit_bundle.get_welcome().cloned();
+    let kpb_option = commit_bundle.get_key_package_bundle();
+    assert!(kpb_option.is_some());
+    assert!(commit_bundle.get_group_info().is_some());
+    assert_eq!(group_alice.pending_key_package_bundles(), 1);
+    let expected = next_transcript_hashes(group_alice.get_interim_transcript_hash(), &commit);
+    let membership_changes = group_alice.apply_commit(commit, proposals).unwrap();
+    assert_eq!(group_alice.pending_key_package_bundles(), 0);
+    assert_eq!(membership_changes.committer_credential, alice_credential);
+    assert_eq!(membership_changes.adds.len(), 1);
+    assert_eq!(
+        membership_changes.adds[0].leaf_node.get_credential(),
+        &bob_credential
+    );
+    assert_eq!(group_alice.get_confirmed_transcript_hash(), &expected.0[..]);
+    assert_eq!(group_alice.get_interim_transcript_hash(), &expected.1[..]);
+
+    let nodes = group_alice.get_tree().public_key_tree();
+    let mut group_bob =
+        MlsGroup::new_from_welcome(welcome_option.unwrap(), Some(nodes), bob_key_package_bundle)
+            .unwrap();
+    assert_eq!(
+        group_alice.get_confirmed_transcript_hash(),
+        group_bob.get_confirmed_transcript_hash()
+    );
+    assert_eq!(
+        group_alice.get_interim_transcript_hash(),
+        group_bob.get_interim_transcript_hash()
+    );
+
+    // Bob and Alice take turns updating their leaves
+    for (committer, signature_key, credential) in &[
+        ("bob", bob_signature_key, &bob_credential),
+        ("alice", alice_signature_key, &alice_credential),
+        ("bob", bob_signature_key, &bob_credential),
+    ] {
+        let (group_committer, group_other) = if *committer == "bob" {
+            (&mut group_bob, &mut group_alice)
+        } else {
+            (&mut group_alice, &mut group_bob)
+        };
+        let commit_bundle = group_committer
+            .create_commit(
+                &[],
+                signature_key,
+                new_kpb(signature_key, credential),
+                vec![],
+                true,
+            )
+            .unwrap();
+        let commit = commit_bundle.get_commit().clone();
+        let kpb_option = commit_bundle.get_key_package_bundle();
+        assert!(kpb_option.is_some());
+        let previous_confirmed_transcript_hash =
+            group_committer.get_confirmed_transcript_hash().to_vec();
+        let expected =
+            next_transcript_hashes(group_committer.get_interim_transcript_hash(), &commit);
+        group_other.apply_commit(commit.clone(), vec![]).unwrap();
+        group_committer.apply_commit(commit, vec![]).unwrap();
+        for group in &[&group_committer, &group_other] {
+            assert_eq!(group.get_confirmed_transcript_hash(), &expected.0[..]);
+            assert_eq!(group.get_interim_transcript_hash(), &expected.1[..]);
+        }
+        assert_ne!(
+            group_committer.get_confirmed_transcript_hash(),
+            &previous_confirmed_transcript_hash[..]
+        );
+    }
+}
+
+#[test]
+fn sender_authentication() {
+    use maelstrom::extensions::*;
+    use maelstrom::framing::*;
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let charlie_identity = Identity::new(ciphersuite, "Charlie".into());
+    let dave_identity = Identity::new(ciphersuite, "Dave".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let charlie_credential = Credential::Basic(BasicCredential::from(&charlie_identity));
+    let dave_credential = Credential::Basic(BasicCredential::from(&dave_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let charlie_signature_key = charlie_identity.get_signature_key_pair().get_private_key();
+    let dave_signature_key = dave_identity.get_signature_key_pair().get_private_key();
+
+    // Charlie is a preconfigured external sender
+    let external_senders = ExternalSendersExtension::new(vec![charlie_credential.clone()]);
+    let mut group_alice = MlsGroup::new_with_extensions(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        KeyPackageBundle::new(
+            &ciphersuite,
+            alice_signature_key,
+            alice_credential.clone(),
+            None,
+        ),
+        vec![external_senders.to_extension()],
+    );
+    let dave_key_package_bundle = KeyPackageBundle::new(
+        &ciphersuite,
+        dave_signature_key,
+        dave_credential.clone(),
+        None,
+    );
+
+    // Members sign with the key of their leaf
+    let (member_plaintext, add_proposal) = group_alice
+        .create_add_proposal(
+            &[],
+            alice_signature_key,
+            dave_key_package_bundle.get_key_package().clone(),
+        )
+        .unwrap();
+    assert_eq!(
+        group_alice.verify_plaintext(&member_plaintext),
+        Ok(alice_credential.clone())
+    );
+
+    let plaintext = |sender, signature_key| {
+        MLSPlaintext::new_with_sender(
+            &ciphersuite,
+            sender,
+            &[],
+            MLSPlaintextContentType::Proposal(add_proposal.clone()),
+            signature_key,
+            group_alice.get_context(),
+        )
+    };
+
+    // External senders sign with the key from the extension
+    assert_eq!(
+        group_alice.verify_plaintext(&plaintext(Sender::external(0), charlie_signature_key)),
+        Ok(charlie_credential)
+    );
+    assert_eq!(
+        group_alice.verify_plaintext(&plaintext(Sender::external(0), dave_signature_key)),
+        Err(SenderError::SignatureFailure)
+    );
+    assert_eq!(
+        group_alice.verify_plaintext(&plaintext(Sender::external(1), charlie_signature_key)),
+        Err(SenderError::UnknownExternalSender)
+    );
+    let external_application_plaintext = MLSPlaintext::new_with_sender(
+        &ciphersuite,
+        Sender::external(0),
+        &[],
+        MLSPlaintextContentType::Application(vec![1, 2, 3].into()),
+        charlie_signature_key,
+        group_alice.get_context(),
+    );
+    group_alice.set_allow_plaintext_application_messages(true);
+    assert_eq!(
+        group_alice.verify_plaintext(&external_application_plaintext),
+        Err(SenderError::InvalidContentForSender)
+    );
+    group_alice.set_allow_plaintext_application_messages(false);
+
+    // New members sign with the key of the KeyPackage they propose
+    assert_eq!(
+        group_alice.verify_plaintext(&plaintext(
+            Sender::new_member_proposal(),
+            dave_signature_key
+        )),
+        Ok(dave_credential)
+    );
+    assert_eq!(
+        group_alice.verify_plaintext(&plaintext(
+            Sender::new_member_proposal(),
+            charlie_signature_key
+        )),
+        Err(SenderError::SignatureFailure)
+    );
+    assert_eq!(
+        group_alice.verify_plaintext(&plaintext(Sender::new_member_commit(), dave_signature_key)),
+        Err(SenderError::InvalidContentForSender)
+    );
+
+    // Application messages are only accepted as plaintext if allowed
+    let application_plaintext =
+        group_alice.create_application_message(&[], &[1, 2, 3], alice_signature_key);
+    assert_eq!(
+        group_alice.verify_plaintext(&application_plaintext),
+        Err(SenderError::UnencryptedApplicationMessage)
+    );
+    group_alice.set_allow_plaintext_application_messages(true);
+    assert_eq!(
+        group_alice.verify_plaintext(&application_plaintext),
+        Ok(alice_credential)
+    );
+    group_alice.set_allow_plaintext_application_messages(false);
+
+    // Commits without a path are signed with the key of the committer's leaf
+    // and can only be sent by members
+    let commit_bundle = group_alice
+        .create_commit(
+            &[],
+            alice_signature_key,
+            KeyPackageBundle::new(
+                &ciphersuite,
+                alice_signature_key,
+                alice_credential.clone(),
+                None,
+            ),
+            vec![(member_plaintext.sender(), add_proposal.clone())],
+            false,
+        )
+        .unwrap();
+    let commit = commit_bundle.get_commit();
+    let forged_commit = |sender, signature_key| {
+        MLSPlaintext::new_with_sender(
+            &ciphersuite,
+            sender,
+            &[],
+            commit.content().clone(),
+            signature_key,
+            group_alice.get_context(),
+        )
+    };
+    let wrong_signature_commit = forged_commit(Sender::member(0u32.into()), dave_signature_key);
+    let external_commit = forged_commit(Sender::external(0), charlie_signature_key);
+    assert_eq!(
+        group_alice
+            .apply_commit(
+                wrong_signature_commit,
+                vec![(member_plaintext.sender(), add_proposal.clone())],
+            )
+            .err(),
+        Some(ApplyCommitError::PlaintextSignatureFailure)
+    );
+    assert_eq!(
+        group_alice
+            .apply_commit(
+                external_commit,
+                vec![(member_plaintext.sender(), add_proposal.clone())],
+            )
+            .err(),
+        Some(ApplyCommitError::InvalidCommitSender)
+    );
+    assert!(group_alice
+        .apply_commit(
+            commit.clone(),
+            vec![(member_plaintext.sender(), add_proposal)],
+        )
+        .is_ok());
+}
+
+#[test]
+fn invalid_remove_proposals() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = || {
+        KeyPackageBundle::new(
+            &ciphersuite,
+            alice_signature_key,
+            alice_credential.clone(),
+            None,
+        )
+    };
+    let mut group_alice = MlsGroup::new(&[1, 2, 3, 4], ciphersuite, new_kpb());
+
+    // Removing a leaf that is not in the tree returns an error
+    let (remove_plaintext, remove_proposal) = group_alice
+        .create_remove_proposal(&[], alice_signature_key, 3u32.into())
+        .unwrap();
+    let result = group_alice.create_commit(
+        &[],
+        alice_signature_key,
+        new_kpb(),
+        vec![(remove_plaintext.sender(), remove_proposal)],
+        false,
+    );
+    assert_eq!(
+        result.err(),
+        Some(CreateCommitError::RemoveTargetNotAMember)
+    );
+
+    // The committer cannot remove itself
+    let (remove_plaintext, remove_proposal) = group_alice
+        .create_remove_proposal(&[], alice_signature_key, 0u32.into())
+        .unwrap();
+    let result = group_alice.create_commit(
+        &[],
+        alice_signature_key,
+        new_kpb(),
+        vec![(remove_plaintext.sender(), remove_proposal)],
+        false,
+    );
+    assert_eq!(result.err(), Some(CreateCommitError::CannotRemoveSelf));
+}
+
+#[test]
+fn pending_own_key_package_bundles() {
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        ciphersuite,
+        alice_identity,
+        bob_identity,
+        alice_credential,
+        bob_credential,
+        mut group_alice,
+        mut group_bob,
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Bob proposes an update, the group keeps the bundle
+    let (update_plaintext, update_proposal) = group_bob
+        .create_update_proposal(
+            &[],
+            bob_signature_key,
+            new_kpb(bob_signature_key, &bob_credential),
+        )
+        .unwrap();
+    assert_eq!(group_bob.pending_key_package_bundles(), 1);
+
+    // Alice commits Bob's update
+    let proposals = vec![(update_plaintext.sender(), update_proposal)];
+    let commit_bundle = group_alice
+        .create_commit(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+            proposals.clone(),
+            false,
+        )
+        .unwrap();
+    let commit = commit_bundle.get_commit().clone();
+    group_alice
+        .apply_commit(commit.clone(), proposals.clone())
+        .unwrap();
+    group_bob.apply_commit(commit, proposals).unwrap();
+    assert_eq!(group_bob.pending_key_package_bundles(), 0);
+    assert_eq!(
+        group_alice.export_secret("test", 32),
+        group_bob.export_secret("test", 32)
+    );
+
+    // Bob can still commit with his new leaf
+    let commit_bundle = group_bob
+        .create_commit(
+            &[],
+            bob_signature_key,
+            new_kpb(bob_signature_key, &bob_credential),
+            vec![],
+            true,
+        )
+        .unwrap();
+    let commit = commit_bundle.get_commit().clone();
+    group_alice.apply_commit(commit.clone(), vec![]).unwrap();
+    group_bob.apply_commit(commit, vec![]).unwrap();
+    assert_eq!(
+        group_alice.export_secret("test", 32),
+        group_bob.export_secret("test", 32)
+    );
+}
+
+#[test]
+fn failed_commit_leaves_group_unchanged() {
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        ciphersuite,
+        alice_identity,
+        alice_credential,
+        mut group_alice,
+        mut group_bob,
+        ..
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Alice updates her path, but the confirmation tag is invalid
+    let commit_bundle = group_alice
+        .create_commit(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+            vec![],
+            true,
+        )
+        .unwrap();
+    let commit = commit_bundle.get_commit().clone();
+    let mut invalid_confirmation_tag = commit.confirmation_tag().unwrap().clone();
+    invalid_confirmation_tag.0[0] ^= 1;
+    let invalid_commit = MLSPlaintext::new_with_sender(
+        &ciphersuite,
+        commit.sender(),
+        commit.authenticated_data(),
+        MLSPlaintextContentType::Commit((
+            commit.as_commit().unwrap().clone(),
+            invalid_confirmation_tag,
+        )),
+        alice_signature_key,
+        group_alice.get_context(),
+    );
+    let bob_tree = group_bob.get_tree().public_key_tree();
+    let fingerprint = group_bob.get_fingerprint();
+    assert!(fingerprint.matches(&group_alice.get_fingerprint()));
+    assert_eq!(
+        group_bob.apply_commit(invalid_commit, vec![]).err(),
+        Some(ApplyCommitError::ConfirmationTagMismatch)
+    );
+    assert_eq!(group_bob.get_tree().public_key_tree(), bob_tree);
+    assert_eq!(group_bob.get_fingerprint(), fingerprint);
+
+    // The valid Commit can still be applied
+    let epoch_transitions = metrics::epoch_transitions();
+    group_alice.apply_commit(commit.clone(), vec![]).unwrap();
+    group_bob.apply_commit(commit, vec![]).unwrap();
+    assert!(metrics::epoch_transitions() >= epoch_transitions + 2);
+    assert!(group_bob
+        .get_fingerprint()
+        .matches(&group_alice.get_fingerprint()));
+    assert!(!group_bob.get_fingerprint().matches(&fingerprint));
+    assert_eq!(
+        fingerprint.to_string().len(),
+        GROUP_FINGERPRINT_LENGTH / 2 * 5 - 1
+    );
+    assert_eq!(
+        group_alice.export_secret("test", 32),
+        group_bob.export_secret("test", 32)
+    );
+}
+
+#[test]
+fn welcome_with_psks() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Alice adds Bob with a Commit that injects two PSKs
+    let mut group_alice = MlsGroup::new(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(alice_signature_key, &alice_credential),
+    );
+    let psks = vec![
+        (PreSharedKeyID::external(b"external"), vec![1u8; 32]),
+        (
+            PreSharedKeyID::resumption(&group_alice.get_context().group_id, GroupEpoch(0)),
+            vec![2u8; 32],
+        ),
+    ];
+    let bob_key_package_bundle = new_kpb(bob_signature_key, &bob_credential);
+    let (add_plaintext, add_proposal) = group_alice
+        .create_add_proposal(
+            &[],
+            alice_signature_key,
+            bob_key_package_bundle.get_key_package().clone(),
+        )
+        .unwrap();
+    let proposals = vec![(add_plaintext.sender(), add_proposal)];
+    let commit_bundle = group_alice
+        .create_commit_with_psks(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+            proposals.clone(),
+            false,
+            &psks,
+        )
+        .unwrap();
+    group_alice
+        .apply_commit_with_psks(commit_bundle.get_commit().clone(), proposals, &psks)
+        .unwrap();
+    let welcome = commit_bundle.get_welcome().unwrap();
+    let nodes = group_alice.get_tree().public_key_tree();
+
+    // Bob needs all PSKs to join
+    assert_eq!(
+        MlsGroup::new_from_welcome(
+            welcome.clone(),
+            Some(nodes.clone()),
+            bob_key_package_bundle.clone()
+        )
+        .err(),
+        Some(WelcomeError::MissingPsk)
+    );
+    let wrong_psks = vec![psks[0].clone(), (psks[1].0.clone(), vec![3u8; 32])];
+    assert_eq!(
+        MlsGroup::new_from_welcome_with_psks(
+            welcome.clone(),
+            Some(nodes.clone()),
+            bob_key_package_bundle.clone(),
+            &wrong_psks
+        )
+        .err(),
+        Some(WelcomeError::ConfirmationTagMismatch)
+    );
+    let group_bob = MlsGroup::new_from_welcome_with_psks(
+        welcome.clone(),
+        Some(nodes),
+        bob_key_package_bundle,
+        &psks,
+    )
+    .unwrap();
+    assert_eq!(
+        group_alice.export_secret("test", 32),
+        group_bob.export_secret("test", 32)
+    );
+}
+
+#[test]
+fn authentication_codes() {
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        alice_identity,
+        mut group_alice,
+        mut group_bob,
+        ..
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+
+    // Bob has the second leaf
+    let bob_index = 1u32.into();
+
+    // Bob learns Alice's leaf index from one of her messages
+    let mls_plaintext =
+        group_alice.create_application_message(&[], &[1, 2, 3], alice_signature_key);
+    let mls_ciphertext = group_alice.encrypt(mls_plaintext).unwrap();
+    let alice_index = group_bob.decrypt(mls_ciphertext).unwrap().sender_index();
+
+    // Both compute the same code
+    let code = group_alice.authentication_code(bob_index).unwrap();
+    assert_eq!(
+        group_bob.authentication_code(alice_index),
+        Some(code.clone())
+    );
+    assert_eq!(code.len(), 35);
+    assert!(code
+        .split(' ')
+        .all(|group| group.len() == 5 && group.chars().all(|c| c.is_ascii_digit())));
+
+    // There is no code with the own leaf or leaves beyond the tree
+    assert_eq!(group_alice.authentication_code(alice_index), None);
+    assert_eq!(group_alice.authentication_code(u32::MAX.into()), None);
+
+    // The code survives storing the group
+    let stored = group_alice.save_encrypted(&[7u8; 32]).unwrap();
+    let loaded = MlsGroup::load_encrypted(&stored, &[7u8; 32]).unwrap();
+    assert_eq!(loaded.authentication_code(bob_index), Some(code));
+}
+
+#[test]
+fn group_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<MlsGroup>();
+}
+
+#[test]
+fn shared_group() {
+    use std::thread;
+
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        alice_identity,
+        mut group_alice,
+        group_bob,
+        ..
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+
+    // Alice sends messages from several threads
+    let shared_alice = SharedMlsGroup::new(group_alice);
+    let threads: Vec<_> = (0..4u8)
+        .map(|i| {
+            let shared_alice = shared_alice.clone();
+            let signature_key = alice_signature_key.clone();
+            thread::spawn(move || {
+                shared_alice
+                    .send_application_message(&[], &[i], &signature_key)
+                    .unwrap()
+            })
+        })
+        .collect();
+    let ciphertexts: Vec<MLSCiphertext> = threads
+        .into_iter()
+        .map(|thread| thread.join().unwrap())
+        .collect();
+
+    // Bob receives all of them
+    let shared_bob = SharedMlsGroup::new(group_bob);
+    let mut messages: Vec<Vec<u8>> = ciphertexts
+        .into_iter()
+        .map(|mls_ciphertext| {
+            let mls_plaintext = shared_bob.decrypt(mls_ciphertext).unwrap().into_plaintext();
+            mls_plaintext
+                .as_application()
+                .expect("Expected an application message")
+                .data
+                .clone()
+        })
+        .collect();
+    messages.sort();
+    assert_eq!(messages, vec![vec![0], vec![1], vec![2], vec![3]]);
+    assert_eq!(
+        shared_alice.export_secret("test", 32),
+        shared_bob.export_secret("test", 32)
+    );
+    assert!(shared_alice
+        .get_fingerprint()
+        .matches(&shared_bob.read().get_fingerprint()));
+
+    // A thread that panics with exclusive access doesn't lock out the others
+    assert!(!shared_bob.is_poisoned());
+    let panicking_bob = shared_bob.clone();
+    assert!(thread::spawn(move || {
+        let _group = panicking_bob.write();
+        panic!("Panic while holding the group");
+    })
+    .join()
+    .is_err());
+    assert!(shared_bob.is_poisoned());
+    assert_eq!(
+        shared_alice.export_secret("test", 32),
+        shared_bob.export_secret("test", 32)
+    );
+    let mls_ciphertext = shared_alice
+        .send_application_message(&[], &[4], alice_signature_key)
+        .unwrap();
+    assert!(shared_bob.decrypt(mls_ciphertext).is_ok());
+}
+
+#[test]
+fn decrypt_batch() {
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        alice_identity,
+        mut group_alice,
+        mut group_bob,
+        ..
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+
+    // Bob catches up on more messages than fit into the out-of-order window
+    let mut ciphertexts: Vec<MLSCiphertext> = (0..8u8)
+        .map(|i| {
+            let mls_plaintext =
+                group_alice.create_application_message(&[], &[i], alice_signature_key);
+            group_alice.encrypt(mls_plaintext).unwrap()
+        })
+        .collect();
+    ciphertexts.reverse();
+    let mut tampered = ciphertexts[0].clone();
+    tampered.ciphertext[0] ^= 1;
+    ciphertexts.push(tampered);
+
+    let results = group_bob.decrypt_batch(ciphertexts);
+    assert_eq!(results.len(), 9);
+    for (i, result) in results[..8].iter().enumerate() {
+        let processed_message = result.as_ref().unwrap();
+        assert_eq!(
+            processed_message.plaintext().content(),
+            &MLSPlaintextContentType::Application(vec![7 - i as u8].into())
+        );
+    }
+    // The tampered message can't be decrypted
+    assert!(results[8].is_err());
+
+    // Messages that can't be verified don't advance the sender ratchet
+    let ratchet_state = group_bob.ratchet_state();
+    let mut ciphertexts: Vec<MLSCiphertext> = (8..20u8)
+        .map(|i| {
+            let mls_plaintext =
+                group_alice.create_application_message(&[], &[i], alice_signature_key);
+            group_alice.encrypt(mls_plaintext).unwrap()
+        })
+        .collect();
+    let mut tampered = ciphertexts.pop().unwrap();
+    tampered.ciphertext[0] ^= 1;
+    assert!(group_bob.decrypt(tampered.clone()).is_err());
+    assert!(group_bob.decrypt_batch(vec![tampered])[0].is_err());
+    assert_eq!(group_bob.ratchet_state(), ratchet_state);
+    let results = group_bob.decrypt_batch(ciphertexts);
+    assert!(results.iter().all(|result| result.is_ok()));
+}
+
+#[test]
+fn membership_proofs() {
+    use maelstrom::codec::*;
+
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        ciphersuite,
+        alice_identity,
+        alice_credential,
+        bob_credential,
+        mut group_alice,
+        group_bob,
+        ..
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Bob has the second leaf
+    let bob_index = 1u32.into();
+
+    // Alice proves that Bob is a member
+    let membership_proof = group_alice
+        .export_membership_proof(bob_index, alice_signature_key)
+        .unwrap();
+    assert_eq!(
+        verify_membership_proof(&membership_proof),
+        Ok(&bob_credential)
+    );
+    assert_eq!(membership_proof.get_signer(), &alice_credential);
+    assert_eq!(membership_proof.get_epoch(), group_bob.get_context().epoch);
+    assert_eq!(
+        membership_proof.get_tree_hash(),
+        &group_bob.get_context().tree_hash[..]
+    );
+
+    // The proof can be sent to a service
+    let bytes = membership_proof.encode_detached().unwrap();
+    let decoded = MembershipProof::decode(&mut Cursor::new(&bytes)).unwrap();
+    assert_eq!(verify_membership_proof(&decoded), Ok(&bob_credential));
+
+    // Modified proofs are rejected
+    let mut modified = bytes.clone();
+    let last = modified.len() - 1;
+    modified[last] ^= 1;
+    let decoded = MembershipProof::decode(&mut Cursor::new(&modified)).unwrap();
+    assert_eq!(
+        verify_membership_proof(&decoded).err(),
+        Some(MembershipProofError::InvalidSignature)
+    );
+
+    // Proofs with an unsupported ciphersuite are rejected when decoding
+    let mut modified = bytes.clone();
+    modified[0..2].copy_from_slice(&[0x00, 0x05]);
+    assert_eq!(
+        MembershipProof::decode(&mut Cursor::new(&modified)).err(),
+        Some(CodecError::DecodingError)
+    );
+
+    // Removed members can't be proven
+    let (remove_plaintext, remove_proposal) = group_alice
+        .create_remove_proposal(&[], alice_signature_key, bob_index)
+        .unwrap();
+    let proposals = vec![(remove_plaintext.sender(), remove_proposal)];
+    let commit_bundle = group_alice
+        .create_commit(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+            proposals.clone(),
+            false,
+        )
+        .unwrap();
+    group_alice
+        .apply_commit(commit_bundle.get_commit().clone(), proposals)
+        .unwrap();
+    assert!(group_alice
+        .export_membership_proof(bob_index, alice_signature_key)
+        .is_none());
+}
+
+#[test]
+fn group_info_verification() {
+    use maelstrom::codec::*;
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Alice creates a group and adds Bob
+    let mut group_alice = MlsGroup::new(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(alice_signature_key, &alice_credential),
+    );
+    let old_nodes = group_alice.get_tree().public_key_tree();
+    let (add_plaintext, add_proposal) = group_alice
+        .create_add_proposal(
+            &[],
+            alice_signature_key,
+            new_kpb(bob_signature_key, &bob_credential)
+                .get_key_package()
+                .clone(),
+        )
+        .unwrap();
+    let proposals = vec![(add_plaintext.sender(), add_proposal)];
+    let commit_bundle = group_alice
+        .create_commit(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+            proposals.clone(),
+            false,
+        )
+        .unwrap();
+    let membership_changes = group_alice
+        .apply_commit(commit_bundle.get_commit().clone(), proposals)
+        .unwrap();
+    let bob_index = membership_changes.adds[0].index;
+    let nodes = group_alice.get_tree().public_key_tree();
+
+    // A server decodes the GroupInfo and verifies it against the exported tree
+    let group_info_bytes = commit_bundle
+        .get_group_info()
+        .unwrap()
+        .encode_detached()
+        .unwrap();
+    let mut group_info = GroupInfo::from_bytes(&group_info_bytes).unwrap();
+    assert_eq!(
+        verify_group_info(&ciphersuite, &group_info, &nodes),
+        Ok(&alice_credential)
+    );
+
+    // The tree of the previous epoch doesn't match
+    assert_eq!(
+        verify_group_info(&ciphersuite, &group_info, &old_nodes),
+        Err(GroupInfoError::TreeHashMismatch)
+    );
+
+    // Trees with an even number of nodes are rejected
+    assert_eq!(
+        verify_group_info(&ciphersuite, &group_info, &nodes[..nodes.len() - 1]),
+        Err(GroupInfoError::InvalidRatchetTree)
+    );
+    assert_eq!(
+        verify_group_info(&ciphersuite, &group_info, &[]),
+        Err(GroupInfoError::InvalidRatchetTree)
+    );
+
+    // Modified GroupInfos are rejected
+    group_info.epoch = GroupEpoch(group_info.epoch.0 + 1);
+    assert_eq!(
+        verify_group_info(&ciphersuite, &group_info, &nodes),
+        Err(GroupInfoError::InvalidSignature)
+    );
+    let mut group_info = GroupInfo::from_bytes(&group_info_bytes).unwrap();
+    group_info.signer_index = bob_index;
+    assert_eq!(
+        verify_group_info(&ciphersuite, &group_info, &nodes),
+        Err(GroupInfoError::InvalidSignature)
+    );
+    group_info.signer_index = u32::MAX.into();
+    assert_eq!(
+        verify_group_info(&ciphersuite, &group_info, &nodes),
+        Err(GroupInfoError::UnknownSigner)
+    );
+}
+
+#[test]
+fn new_with_members() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let charlie_identity = Identity::new(ciphersuite, "Charlie".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let charlie_credential = Credential::Basic(BasicCredential::from(&charlie_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+    let charlie_signature_key = charlie_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Alice creates a group with Bob and Charlie
+    let bob_key_package_bundle = new_kpb(bob_signature_key, &bob_credential);
+    let charlie_key_package_bundle = new_kpb(charlie_signature_key, &charlie_credential);
+    let (mut group_alice, welcome) = MlsGroup::new_with_members(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(alice_signature_key, &alice_credential),
+        alice_signature_key,
+        vec![
+            bob_key_package_bundle.get_key_package().clone(),
+            charlie_key_package_bundle.get_key_package().clone(),
+        ],
+    )
+    .unwrap();
+    assert_eq!(group_alice.get_context().epoch, GroupEpoch(1));
+
+    // Both join from the same Welcome
+    let nodes = group_alice.get_tree().public_key_tree();
+    let mut group_bob =
+        MlsGroup::new_from_welcome(welcome.clone(), Some(nodes.clone()), bob_key_package_bundle)
+            .unwrap();
+    let mut group_charlie =
+        MlsGroup::new_from_welcome(welcome, Some(nodes), charlie_key_package_bundle).unwrap();
+    assert_eq!(group_bob.get_fingerprint(), group_alice.get_fingerprint());
+    assert_eq!(
+        group_charlie.get_fingerprint(),
+        group_alice.get_fingerprint()
+    );
+
+    let mls_plaintext =
+        group_alice.create_application_message(&[], &[1, 2, 3], alice_signature_key);
+    let mls_ciphertext = group_alice.encrypt(mls_plaintext).unwrap();
+    let processed_message = group_bob.decrypt(mls_ciphertext.clone()).unwrap();
+    assert_eq!(processed_message.sender_credential(), &alice_credential);
+    let processed_message = group_charlie.decrypt(mls_ciphertext).unwrap();
+    assert_eq!(processed_message.sender_credential(), &alice_credential);
+
+    // There has to be at least one member to add
+    assert_eq!(
+        MlsGroup::new_with_members(
+            &[1, 2, 3, 4],
+            ciphersuite,
+            new_kpb(alice_signature_key, &alice_credential),
+            alice_signature_key,
+            vec![],
+        )
+        .err(),
+        Some(CreateCommitError::NoMembersToAdd)
+    );
+}
+
+#[test]
+fn one_shot_operations() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let charlie_identity = Identity::new(ciphersuite, "Charlie".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let charlie_credential = Credential::Basic(BasicCredential::from(&charlie_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+    let charlie_signature_key = charlie_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Alice adds Bob and Charlie
+    let mut group_alice = MlsGroup::new(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(alice_signature_key, &alice_credential),
+    );
+    let bob_key_package_bundle = new_kpb(bob_signature_key, &bob_credential);
+    let charlie_key_package_bundle = new_kpb(charlie_signature_key, &charlie_credential);
+    let commit_bundle = group_alice
+        .add_members(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+            &[
+                bob_key_package_bundle.get_key_package().clone(),
+                charlie_key_package_bundle.get_key_package().clone(),
+            ],
+        )
+        .unwrap();
+    // The proposals are included in the Commit by value
+    let commit = commit_bundle.get_commit().as_commit().unwrap();
+    assert_eq!(commit.proposals().len(), 2);
+    assert_eq!(commit_bundle.get_proposal_list().len(), 2);
+    let membership_changes = group_alice
+        .apply_commit(
+            commit_bundle.get_commit().clone(),
+            commit_bundle.get_proposal_list(),
+        )
+        .unwrap();
+    // The order of the new leaves depends on the proposals
+    let charlie_index = membership_changes
+        .adds
+        .iter()
+        .find(|add| add.leaf_node.get_credential() == &charlie_credential)
+        .unwrap()
+        .index;
+    let nodes = group_alice.get_tree().public_key_tree();
+    let welcome = commit_bundle.get_welcome().unwrap();
+    let mut group_bob =
+        MlsGroup::new_from_welcome(welcome.clone(), Some(nodes.clone()), bob_key_package_bundle)
+            .unwrap();
+    let mut group_charlie =
+        MlsGroup::new_from_welcome(welcome.clone(), Some(nodes), charlie_key_package_bundle)
+            .unwrap();
+
+    // Alice removes Charlie
+    let commit_bundle = group_alice
+        .remove_members(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+            &[charlie_index],
+        )
+        .unwrap();
+    assert_eq!(commit_bundle.get_proposal_list().len(), 1);
+    // Members don't need separate proposals to apply the Commit
+    for group in [&mut group_alice, &mut group_bob].iter_mut() {
+        let membership_changes = group
+            .apply_commit(commit_bundle.get_commit().clone(), vec![])
+            .unwrap();
+        assert_eq!(membership_changes.removes[0].index, charlie_index);
+    }
+    assert_eq!(
+        group_charlie
+            .apply_commit(
+                commit_bundle.get_commit().clone(),
+                commit_bundle.get_proposal_list()
+            )
+            .err(),
+        Some(ApplyCommitError::SelfRemoved)
+    );
+
+    // Bob updates his leaf
+    let commit_bundle = group_bob
+        .update_self(
+            &[],
+            bob_signature_key,
+            new_kpb(bob_signature_key, &bob_credential),
+        )
+        .unwrap();
+    assert!(commit_bundle.get_proposal_list().is_empty());
+    assert!(commit_bundle.get_key_package_bundle().is_some());
+    for group in [&mut group_alice, &mut group_bob].iter_mut() {
+        group
+            .apply_commit(commit_bundle.get_commit().clone(), vec![])
+            .unwrap();
+    }
+    assert_eq!(group_alice.get_fingerprint(), group_bob.get_fingerprint());
+}
+
+#[test]
+fn commit_policy() {
+    use maelstrom::codec::*;
+    use maelstrom::extensions::*;
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let identities: Vec<Identity> = (0..3)
+        .map(|i| Identity::new(ciphersuite, vec![i]))
+        .collect();
+    let key_package_bundles: Vec<KeyPackageBundle> = identities
+        .iter()
+        .map(|identity| {
+            KeyPackageBundle::new(
+                &ciphersuite,
+                identity.get_signature_key_pair().get_private_key(),
+                Credential::Basic(BasicCredential::from(identity)),
+                None,
+            )
+        })
+        .collect();
+    let key_packages: Vec<KeyPackage> = key_package_bundles
+        .iter()
+        .map(|kpb| kpb.get_key_package().clone())
+        .collect();
+    let new_alice_kpb = || {
+        KeyPackageBundle::new(
+            &ciphersuite,
+            alice_signature_key,
+            alice_credential.clone(),
+            None,
+        )
+    };
+
+    let mut group_alice = MlsGroup::new(&[1, 2, 3, 4], ciphersuite, new_alice_kpb());
+    let commit_policy = CommitPolicy {
+        max_proposals: Some(2),
+        ..CommitPolicy::default()
+    };
+    group_alice.set_commit_policy(commit_policy);
+    assert_eq!(group_alice.get_commit_policy(), &commit_policy);
+
+    // Adds beyond the limit are deferred to the next epoch
+    let commit_bundle = group_alice
+        .add_members(&[], alice_signature_key, new_alice_kpb(), &key_packages)
+        .unwrap();
+    assert_eq!(commit_bundle.get_deferred_proposals().len(), 1);
+    let membership_changes = group_alice
+        .apply_commit(
+            commit_bundle.get_commit().clone(),
+            commit_bundle.get_proposal_list(),
+        )
+        .unwrap();
+    assert_eq!(membership_changes.adds.len(), 2);
+
+    // One of the new members joins
+    let nodes = group_alice.get_tree().public_key_tree();
+    let joiner = identities
+        .iter()
+        .position(|identity| {
+            membership_changes.adds[0].leaf_node.get_credential()
+                == &Credential::Basic(BasicCredential::from(identity))
+        })
+        .unwrap();
+    let mut group_joiner = MlsGroup::new_from_welcome(
+        commit_bundle.get_welcome().unwrap().clone(),
+        Some(nodes),
+        key_package_bundles[joiner].clone(),
+    )
+    .unwrap();
+
+    // The deferred proposal is committed in the next epoch
+    let proposals = commit_bundle.get_deferred_proposals().to_vec();
+    let commit_bundle = group_alice
+        .create_commit(
+            &[],
+            alice_signature_key,
+            new_alice_kpb(),
+            proposals.clone(),
+            false,
+        )
+        .unwrap();
+    assert!(commit_bundle.get_deferred_proposals().is_empty());
+    group_alice
+        .apply_commit(commit_bundle.get_commit().clone(), proposals.clone())
+        .unwrap();
+
+    // Members reject Commits that exceed their limits
+    group_joiner.set_commit_policy(CommitPolicy {
+        max_commit_size: Some(1),
+        ..CommitPolicy::default()
+    });
+    assert_eq!(
+        group_joiner
+            .apply_commit(commit_bundle.get_commit().clone(), proposals)
+            .err(),
+        Some(ApplyCommitError::CommitTooLarge)
+    );
+
+    // Updates and removes can't be deferred
+    group_alice.set_commit_policy(CommitPolicy {
+        max_proposals: Some(1),
+        ..CommitPolicy::default()
+    });
+    let members: Vec<_> = membership_changes
+        .adds
+        .iter()
+        .map(|add| add.index)
+        .collect();
+    assert_eq!(
+        group_alice
+            .remove_members(&[], alice_signature_key, new_alice_kpb(), &members)
+            .err(),
+        Some(CreateCommitError::TooManyProposals)
+    );
+
+    // A Commit that can't be made smaller fails
+    group_alice.set_commit_policy(CommitPolicy {
+        max_commit_size: Some(1),
+        ..CommitPolicy::default()
+    });
+    assert_eq!(
+        group_alice
+            .update_self(&[], alice_signature_key, new_alice_kpb())
+            .err(),
+        Some(CreateCommitError::CommitTooLarge)
+    );
+
+    // An Add that is too large on its own is rejected without failing the
+    // Commit
+    let new_key_package = |id, extensions| {
+        let identity = Identity::new(ciphersuite, vec![id]);
+        KeyPackageBundle::new(
+            &ciphersuite,
+            identity.get_signature_key_pair().get_private_key(),
+            Credential::Basic(BasicCredential::from(&identity)),
+            extensions,
+        )
+        .get_key_package()
+        .clone()
+    };
+    let small_key_package = new_key_package(4, None);
+    let large_key_package = new_key_package(
+        5,
+        Some(vec![KeyIDExtension::new(&[0u8; 8192]).to_extension()]),
+    );
+    let add_proposal = |key_package| {
+        let (add_plaintext, add_proposal) = group_alice
+            .create_add_proposal(&[], alice_signature_key, key_package)
+            .unwrap();
+        (add_plaintext.sender(), add_proposal)
+    };
+    let small_add = add_proposal(small_key_package);
+    let large_add = add_proposal(large_key_package.clone());
+    group_alice.set_commit_policy(CommitPolicy::default());
+    let commit_bundle = group_alice
+        .create_commit(
+            &[],
+            alice_signature_key,
+            new_alice_kpb(),
+            vec![small_add.clone()],
+            false,
+        )
+        .unwrap();
+    let small_commit_size = commit_bundle.get_commit().encode_detached().unwrap().len();
+    group_alice.discard_pending_commit();
+    group_alice.set_commit_policy(CommitPolicy {
+        max_commit_size: Some(small_commit_size + 2048),
+        ..CommitPolicy::default()
+    });
+    let commit_bundle = group_alice
+        .create_commit(
+            &[],
+            alice_signature_key,
+            new_alice_kpb(),
+            vec![large_add, small_add],
+            false,
+        )
+        .unwrap();
+    let rejected_proposals = commit_bundle.get_rejected_proposals();
+    assert_eq!(rejected_proposals.len(), 1);
+    assert_eq!(
+        rejected_proposals[0].1.as_add().unwrap().key_package,
+        large_key_package
+    );
+    // The other Add is deferred, it was split off before
+    assert_eq!(commit_bundle.get_deferred_proposals().len(), 1);
+}
+
+#[test]
+fn process_proposals() {
+    use maelstrom::extensions::*;
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let charlie_identity = Identity::new(ciphersuite, "Charlie".into());
+    let dave_identity = Identity::new(ciphersuite, "Dave".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let charlie_credential = Credential::Basic(BasicCredential::from(&charlie_identity));
+    let dave_credential = Credential::Basic(BasicCredential::from(&dave_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let charlie_signature_key = charlie_identity.get_signature_key_pair().get_private_key();
+    let dave_signature_key = dave_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |ciphersuite, signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Charlie is a preconfigured external sender
+    let external_senders = ExternalSendersExtension::new(vec![charlie_credential]);
+    let mut group_alice = MlsGroup::new_with_extensions(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(ciphersuite, alice_signature_key, &alice_credential),
+        vec![external_senders.to_extension()],
+    );
+    let dave_key_package = new_kpb(ciphersuite, dave_signature_key, &dave_credential)
+        .get_key_package()
+        .clone();
+    let (_, update_proposal) = group_alice
+        .create_update_proposal(
+            &[],
+            alice_signature_key,
+            new_kpb(ciphersuite, alice_signature_key, &alice_credential),
+        )
+        .unwrap();
+    let plaintext = |sender, proposal, signature_key| {
+        MLSPlaintext::new_with_sender(
+            &ciphersuite,
+            sender,
+            &[],
+            MLSPlaintextContentType::Proposal(proposal),
+            signature_key,
+            group_alice.get_context(),
+        )
+    };
+
+    // Valid proposals from members and external senders are accepted
+    let (add_plaintext, add_proposal) = group_alice
+        .create_add_proposal(&[], alice_signature_key, dave_key_package.clone())
+        .unwrap();
+    let queued_proposal = group_alice.process_proposal(add_plaintext.clone()).unwrap();
+    assert_eq!(queued_proposal.proposal, add_proposal);
+    assert_eq!(queued_proposal.sender, add_plaintext.sender());
+    let external_plaintext = plaintext(
+        Sender::external(0),
+        add_proposal.clone(),
+        charlie_signature_key,
+    );
+    assert!(group_alice.process_proposal(external_plaintext).is_ok());
+
+    // The signature has to match the sender
+    let external_plaintext = plaintext(
+        Sender::external(0),
+        add_proposal.clone(),
+        dave_signature_key,
+    );
+    assert_eq!(
+        group_alice.process_proposal(external_plaintext).err(),
+        Some(ProposalError::InvalidSignature)
+    );
+
+    // External senders can't update a leaf
+    let external_plaintext = plaintext(Sender::external(0), update_proposal, charlie_signature_key);
+    assert_eq!(
+        group_alice.process_proposal(external_plaintext).err(),
+        Some(ProposalError::InvalidSender)
+    );
+
+    // Key packages have to use the group's ciphersuite
+    let chacha =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519);
+    let (add_plaintext, _) = group_alice
+        .create_add_proposal(
+            &[],
+            alice_signature_key,
+            new_kpb(chacha, dave_signature_key, &dave_credential)
+                .get_key_package()
+                .clone(),
+        )
+        .unwrap();
+    assert_eq!(
+        group_alice.process_proposal(add_plaintext).err(),
+        Some(ProposalError::InvalidKeyPackage)
+    );
+
+    // Remove proposals have to target a member
+    let (remove_plaintext, _) = group_alice
+        .create_remove_proposal(&[], alice_signature_key, 3u32.into())
+        .unwrap();
+    assert_eq!(
+        group_alice.process_proposal(remove_plaintext).err(),
+        Some(ProposalError::RemoveTargetNotAMember)
+    );
+
+    // Commits are not proposals
+    let commit_bundle = group_alice
+        .update_self(
+            &[],
+            alice_signature_key,
+            new_kpb(ciphersuite, alice_signature_key, &alice_credential),
+        )
+        .unwrap();
+    assert_eq!(
+        group_alice
+            .process_proposal(commit_bundle.get_commit().clone())
+            .err(),
+        Some(ProposalError::NotAProposal)
+    );
+
+    // Proposals from a previous epoch are rejected
+    let (add_plaintext, _) = group_alice
+        .create_add_proposal(&[], alice_signature_key, dave_key_package.clone())
+        .unwrap();
+    group_alice
+        .apply_commit(commit_bundle.get_commit().clone(), vec![])
+        .unwrap();
+    assert_eq!(
+        group_alice.process_proposal(add_plaintext).err(),
+        Some(ProposalError::WrongEpoch)
+    );
+}
+
+#[test]
+fn external_remove() {
+    use maelstrom::extensions::*;
+    use maelstrom::validator::*;
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let server_identity = Identity::new(ciphersuite, "Server".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let server_credential = Credential::Basic(BasicCredential::from(&server_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+    let server_signature_key = server_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential, extensions| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), extensions)
+    };
+
+    // The server is a preconfigured external sender, Bob's client is
+    // registered with a key ID
+    let external_senders = ExternalSendersExtension::new(vec![server_credential.clone()]);
+    let mut group_alice = MlsGroup::new_with_extensions(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(alice_signature_key, &alice_credential, None),
+        vec![external_senders.to_extension()],
+    );
+    let bob_key_package = new_kpb(
+        bob_signature_key,
+        &bob_credential,
+        Some(vec![KeyIDExtension::new(b"bob-phone").to_extension()]),
+    )
+    .get_key_package()
+    .clone();
+    let commit_bundle = group_alice
+        .add_members(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential, None),
+            &[bob_key_package],
+        )
+        .unwrap();
+    let membership_changes = group_alice
+        .apply_commit(
+            commit_bundle.get_commit().clone(),
+            commit_bundle.get_proposal_list(),
+        )
+        .unwrap();
+    let bob_index = membership_changes.adds[0].index;
+    assert_eq!(
+        group_alice.find_member_by_key_id(b"bob-phone"),
+        Some(bob_index)
+    );
+    assert_eq!(group_alice.find_member_by_key_id(b"bob-laptop"), None);
+
+    // The server removes Bob's client after his account was deleted
+    let nodes = group_alice.get_tree().public_key_tree();
+    assert!(create_external_remove_proposal_by_key_id(
+        &ciphersuite,
+        group_alice.get_context(),
+        &nodes,
+        0,
+        b"bob-laptop",
+        server_signature_key,
+    )
+    .is_none());
+    let remove_plaintext = create_external_remove_proposal_by_key_id(
+        &ciphersuite,
+        group_alice.get_context(),
+        &nodes,
+        0,
+        b"bob-phone",
+        server_signature_key,
+    )
+    .unwrap();
+    assert_eq!(
+        verify_external_remove_proposal(&group_alice, &remove_plaintext),
+        Ok((server_credential, bob_credential))
+    );
+
+    // Only external senders can send such proposals
+    let forged_plaintext = create_external_remove_proposal(
+        &ciphersuite,
+        group_alice.get_context(),
+        0,
+        bob_index,
+        alice_signature_key,
+    );
+    assert_eq!(
+        verify_external_remove_proposal(&group_alice, &forged_plaintext),
+        Err(ProposalError::InvalidSignature)
+    );
+    let (member_plaintext, _) = group_alice
+        .create_remove_proposal(&[], alice_signature_key, bob_index)
+        .unwrap();
+    assert_eq!(
+        verify_external_remove_proposal(&group_alice, &member_plaintext),
+        Err(ProposalError::InvalidSender)
+    );
+
+    // Alice commits the removal
+    let queued_proposal = group_alice.process_proposal(remove_plaintext).unwrap();
+    let proposals = vec![(queued_proposal.sender, queued_proposal.proposal)];
+    let commit_bundle = group_alice
+        .create_commit(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential, None),
+            proposals.clone(),
+            false,
+        )
+        .unwrap();
+    let membership_changes = group_alice
+        .apply_commit(commit_bundle.get_commit().clone(), proposals)
+        .unwrap();
+    assert_eq!(membership_changes.removes[0].index, bob_index);
+    assert_eq!(group_alice.find_member_by_key_id(b"bob-phone"), None);
+}
+
+#[test]
+fn exported_tree_verification() {
+    use maelstrom::codec::*;
+    use maelstrom::extensions::*;
+
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        ciphersuite,
+        alice_identity,
+        alice_credential,
+        bob_credential,
+        mut group_alice,
+        ..
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+
+    // Alice updates her path so that the root is not blank. Her new leaf has
+    // a lifetime.
+    let lifetime_extension = LifetimeExtension::new(LifetimeExtension::LIFETIME_1_DAY);
+    let commit_bundle = group_alice
+        .update_self(
+            &[],
+            alice_signature_key,
+            KeyPackageBundle::new(
+                &ciphersuite,
+                alice_signature_key,
+                alice_credential.clone(),
+                Some(vec![lifetime_extension.to_extension()]),
+            ),
+        )
+        .unwrap();
+    group_alice
+        .apply_commit(commit_bundle.get_commit().clone(), vec![])
+        .unwrap();
+
+    // The delivery service verifies the exported tree
+    let nodes = group_alice.get_tree().public_key_tree();
+    let group_context = group_alice.get_context();
+    let summary = verify_exported_tree(&ciphersuite, &nodes, group_context).unwrap();
+    assert_eq!(summary.tree_hash, group_context.tree_hash);
+    assert_eq!(summary.leaf_count, 2);
+    assert_eq!(summary.member_count(), 2);
+    let credentials: Vec<&Credential> = summary.members.iter().map(|(_, c)| c).collect();
+    assert_eq!(credentials, vec![&alice_credential, &bob_credential]);
+
+    // The lifetime of the leaves is checked
+    struct FarFuture;
+    impl TimeProvider for FarFuture {
+        fn now(&self) -> u64 {
+            u64::MAX
+        }
+    }
+    assert_eq!(
+        verify_exported_tree_at(&ciphersuite, &nodes, group_context, &FarFuture),
+        Err(TreeValidationError::ExpiredLeaf)
+    );
+
+    // Tampered or truncated trees are rejected
+    let mut tampered_nodes = nodes.clone();
+    if let Some(Some(node)) = tampered_nodes.get_mut(1) {
+        node.node.as_mut().unwrap().set_parent_hash(vec![1, 2, 3]);
+    }
+    assert_eq!(
+        verify_exported_tree(&ciphersuite, &tampered_nodes, group_context),
+        Err(TreeValidationError::ParentHashMismatch)
+    );
+    // The node type, the presence flag, the protocol version, the
+    // ciphersuite and the length of the encryption key precede the key
+    let mut leaf_bytes = nodes[2].as_ref().unwrap().encode_detached().unwrap();
+    leaf_bytes[7] ^= 1;
+    let mut tampered_nodes = nodes.clone();
+    tampered_nodes[2] = Some(Codec::decode(&mut Cursor::new(&leaf_bytes)).unwrap());
+    assert_eq!(
+        verify_exported_tree(&ciphersuite, &tampered_nodes, group_context),
+        Err(TreeValidationError::InvalidLeafSignature)
+    );
+    let mut tampered_nodes = nodes.clone();
+    tampered_nodes.swap(0, 2);
+    assert!(verify_exported_tree(&ciphersuite, &tampered_nodes, group_context).is_err());
+    assert_eq!(
+        verify_exported_tree(&ciphersuite, &nodes[..2], group_context),
+        Err(TreeValidationError::InvalidStructure)
+    );
+    let mut other_context = group_context.clone();
+    other_context.tree_hash = vec![0; other_context.tree_hash.len()];
+    assert_eq!(
+        verify_exported_tree(&ciphersuite, &nodes, &other_context),
+        Err(TreeValidationError::TreeHashMismatch)
+    );
+}
+
+#[test]
+fn psk_proposals() {
+    use maelstrom::codec::*;
+
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        ciphersuite,
+        alice_identity,
+        bob_identity,
+        alice_credential,
+        bob_credential,
+        mut group_alice,
+        mut group_bob,
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+    let new_psk_store = || {
+        let mut psk_store = MemoryPskStore::new();
+        psk_store.add_external_psk(b"external", vec![1u8; 32]);
+        Box::new(psk_store)
+    };
+
+    group_alice.set_psk_store(new_psk_store());
+
+    // Alice proposes an external PSK and the resumption PSK of the current
+    // epoch
+    let external_psk_id = PreSharedKeyID::external(b"external");
+    let resumption_psk_id =
+        PreSharedKeyID::resumption(&group_alice.get_context().group_id, GroupEpoch(1));
+    assert!(group_alice.get_resumption_secret(GroupEpoch(0)).is_some());
+    assert_eq!(
+        group_alice.get_resumption_secret(GroupEpoch(1)),
+        group_bob.get_resumption_secret(GroupEpoch(1))
+    );
+    let (external_plaintext, external_proposal) = group_alice
+        .create_psk_proposal(&[], alice_signature_key, external_psk_id)
+        .unwrap();
+    let (resumption_plaintext, resumption_proposal) = group_alice
+        .create_psk_proposal(&[], alice_signature_key, resumption_psk_id)
+        .unwrap();
+
+    // Bob can only resolve the external PSK once it is in his store
+    assert_eq!(
+        group_bob.process_proposal(external_plaintext.clone()).err(),
+        Some(ProposalError::UnknownPsk)
+    );
+    assert!(group_bob
+        .process_proposal(resumption_plaintext.clone())
+        .is_ok());
+
+    // Alice commits to both PSKs
+    let proposals = vec![
+        (external_plaintext.sender(), external_proposal),
+        (resumption_plaintext.sender(), resumption_proposal),
+    ];
+    let commit_bundle = group_alice
+        .create_commit(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+            proposals.clone(),
+            false,
+        )
+        .unwrap();
+    assert_eq!(
+        group_bob
+            .apply_commit(commit_bundle.get_commit().clone(), proposals.clone())
+            .err(),
+        Some(ApplyCommitError::MissingPsk)
+    );
+    group_bob.set_psk_store(new_psk_store());
+    assert!(group_bob.process_proposal(external_plaintext).is_ok());
+    // All PSK proposals the Commit refers to have to be provided
+    assert_eq!(
+        group_bob
+            .apply_commit(commit_bundle.get_commit().clone(), proposals[1..].to_vec())
+            .err(),
+        Some(ApplyCommitError::MissingPsk)
+    );
+    for group in [&mut group_alice, &mut group_bob].iter_mut() {
+        group
+            .apply_commit(commit_bundle.get_commit().clone(), proposals.clone())
+            .unwrap();
+    }
+    assert_eq!(group_alice.get_fingerprint(), group_bob.get_fingerprint());
+    assert_eq!(
+        group_alice.get_resumption_secret(GroupEpoch(2)),
+        group_bob.get_resumption_secret(GroupEpoch(2))
+    );
+
+    // Commits fail if the committer can't resolve a PSK
+    let mut group_alice_without_psk = MlsGroup::new_with_members(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(alice_signature_key, &alice_credential),
+        alice_signature_key,
+        vec![new_kpb(bob_signature_key, &bob_credential)
+            .get_key_package()
+            .clone()],
+    )
+    .unwrap()
+    .0;
+    let (plaintext, proposal) = group_alice_without_psk
+        .create_psk_proposal(
+            &[],
+            alice_signature_key,
+            PreSharedKeyID::external(b"external"),
+        )
+        .unwrap();
+    assert_eq!(
+        group_alice_without_psk
+            .create_commit(
+                &[],
+                alice_signature_key,
+                new_kpb(alice_signature_key, &alice_credential),
+                vec![(plaintext.sender(), proposal)],
+                false,
+            )
+            .err(),
+        Some(CreateCommitError::MissingPsk)
+    );
+
+    // Resumption secrets are stored with the group
+    let encoded = group_bob.encode_detached().unwrap();
+    let decoded = MlsGroup::decode(&mut Cursor::new(&encoded)).unwrap();
+    assert_eq!(
+        decoded.get_resumption_secret(GroupEpoch(1)),
+        group_bob.get_resumption_secret(GroupEpoch(1))
+    );
+}
+
+#[cfg(feature = "dangerous-epoch-escrow")]
+#[test]
+fn epoch_escrow() {
+    use maelstrom::group::escrow::*;
+    use std::sync::{Arc, Mutex};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let new_kpb = || {
+        KeyPackageBundle::new(
+            &ciphersuite,
+            alice_signature_key,
+            alice_credential.clone(),
+            None,
+        )
+    };
+
+    // The escrow receives the current epoch right away and every later one
+    let mut group_alice = MlsGroup::new(&[1, 2, 3, 4], ciphersuite, new_kpb());
+    let escrowed = Arc::new(Mutex::new(vec![]));
+    let escrowed_clone = escrowed.clone();
+    group_alice.set_epoch_escrow(Box::new(move |escrowed_epoch: &EscrowedEpoch| {
+        escrowed_clone.lock().unwrap().push((
+            escrowed_epoch.epoch,
+            escrowed_epoch.exporter_secret.to_vec(),
+        ))
+    }));
+    let commit_bundle = group_alice
+        .update_self(&[], alice_signature_key, new_kpb())
+        .unwrap();
+    group_alice
+        .apply_commit(commit_bundle.get_commit().clone(), vec![])
+        .unwrap();
+
+    let escrowed = escrowed.lock().unwrap();
+    assert_eq!(escrowed.len(), 2);
+    assert_eq!(escrowed[0].0, GroupEpoch(0));
+    assert_eq!(escrowed[1].0, GroupEpoch(1));
+    assert_ne!(escrowed[0].1, escrowed[1].1);
+}
+
+#[test]
+fn delivery_hints() {
+    use maelstrom::codec::*;
+
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        ciphersuite,
+        alice_identity,
+        bob_identity,
+        bob_credential,
+        mut group_alice,
+        mut group_bob,
+        ..
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Alice sends two messages with delivery hints
+    let mut messages = vec![];
+    for message in &[b"first", b"later"] {
+        let mls_plaintext =
+            group_alice.create_application_message(&[], &message[..], alice_signature_key);
+        messages.push(
+            group_alice
+                .encrypt_with_delivery_hint(mls_plaintext)
+                .unwrap(),
+        );
+    }
+
+    // The server reads the hints without decrypting the messages
+    let hints: Vec<DeliveryHint> = messages
+        .iter()
+        .map(|(_, hint)| {
+            let bytes = hint.encode_detached().unwrap();
+            DeliveryHint::decode(&mut Cursor::new(&bytes)).unwrap()
+        })
+        .collect();
+    assert_eq!(hints[0].get_epoch(), group_alice.get_context().epoch);
+    assert_eq!(hints[0].get_sender(), hints[1].get_sender());
+    assert_ne!(hints[0], hints[1]);
+
+    // Bob verifies the hints before decrypting
+    for ((mls_ciphertext, _), hint) in messages.iter().zip(hints.iter()) {
+        assert_eq!(group_bob.verify_delivery_hint(hint, mls_ciphertext), Ok(()));
+    }
+    assert_eq!(
+        group_bob.verify_delivery_hint(&hints[1], &messages[0].0),
+        Err(DeliveryHintError::InvalidTag)
+    );
+
+    // The tag is bound to the sender of the hint
+    let mut bytes = hints[0].encode_detached().unwrap();
+    bytes[11] ^= 1;
+    let forged_hint = DeliveryHint::decode(&mut Cursor::new(&bytes)).unwrap();
+    assert_ne!(forged_hint.get_sender(), hints[0].get_sender());
+    assert_eq!(
+        group_bob.verify_delivery_hint(&forged_hint, &messages[0].0),
+        Err(DeliveryHintError::InvalidTag)
+    );
+    let processed_message = group_bob.decrypt(messages[0].0.clone()).unwrap();
+    assert_eq!(processed_message.sender_index(), hints[0].get_sender());
+
+    // Hints can only be verified in their epoch
+    let commit_bundle = group_bob
+        .update_self(
+            &[],
+            bob_signature_key,
+            new_kpb(bob_signature_key, &bob_credential),
+        )
+        .unwrap();
+    group_bob
+        .apply_commit(commit_bundle.get_commit().clone(), vec![])
+        .unwrap();
+    assert_eq!(
+        group_bob.verify_delivery_hint(&hints[1], &messages[1].0),
+        Err(DeliveryHintError::WrongEpoch)
+    );
+}
+
+#[test]
+fn send_counter() {
+    use maelstrom::codec::*;
+
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        ciphersuite,
+        alice_identity,
+        bob_identity,
+        bob_credential,
+        mut group_alice,
+        mut group_bob,
+        ..
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    assert_eq!(group_alice.send_counter(), 0);
+
+    // Every message Alice encrypts gets the next counter value
+    for expected in 1..=3 {
+        let mls_plaintext =
+            group_alice.create_application_message(&[], b"message", alice_signature_key);
+        let (mls_ciphertext, counter) = group_alice
+            .encrypt_with_send_counter(mls_plaintext)
+            .unwrap();
+        assert_eq!(counter, expected);
+        group_bob.decrypt(mls_ciphertext).unwrap();
+    }
+    assert_eq!(group_alice.send_counter(), 3);
+    assert_eq!(group_bob.send_counter(), 0);
+
+    // The counter keeps increasing in the next epoch
+    let commit_bundle = group_bob
+        .update_self(
+            &[],
+            bob_signature_key,
+            new_kpb(bob_signature_key, &bob_credential),
+        )
+        .unwrap();
+    let proposals = commit_bundle.get_proposal_list();
+    group_alice
+        .apply_commit(commit_bundle.get_commit().clone(), proposals.clone())
+        .unwrap();
+    group_bob
+        .apply_commit(commit_bundle.get_commit().clone(), proposals)
+        .unwrap();
+    let mls_plaintext =
+        group_alice.create_application_message(&[], b"message", alice_signature_key);
+    let (mls_ciphertext, counter) = group_alice
+        .encrypt_with_send_counter(mls_plaintext)
+        .unwrap();
+    assert_eq!(counter, 4);
+    group_bob.decrypt(mls_ciphertext).unwrap();
+
+    // The counter is stored with the group
+    let encoded = group_alice.encode_detached().unwrap();
+    let decoded = MlsGroup::decode(&mut Cursor::new(&encoded)).unwrap();
+    assert_eq!(decoded.send_counter(), 4);
+}
+
+#[test]
+fn key_package_consumed() {
+    use std::sync::{Arc, Mutex};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Bob publishes two key packages
+    let replenished = Arc::new(Mutex::new(vec![]));
+    let events = Arc::new(Mutex::new(vec![]));
+    let replenish_hook = replenished.clone();
+    let event_hook = events.clone();
+    let mut manager =
+        KeyPackageManager::new(2, Box::new(move |n| replenish_hook.lock().unwrap().push(n)));
+    manager.set_event_hook(Box::new(move |event| {
+        event_hook.lock().unwrap().push(event.clone())
+    }));
+    let bob_key_package_bundle = new_kpb(bob_signature_key, &bob_credential);
+    let bob_key_package = bob_key_package_bundle.get_key_package().clone();
+    manager.add(bob_key_package_bundle);
+    manager.add(new_kpb(bob_signature_key, &bob_credential));
+
+    // Alice adds Bob with one of them
+    let (group_alice, welcome) = MlsGroup::new_with_members(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(alice_signature_key, &alice_credential),
+        alice_signature_key,
+        vec![bob_key_package.clone()],
+    )
+    .unwrap();
+
+    // The Welcome is addressed to Bob's key package, which identifies his leaf
+    assert_eq!(
+        welcome.secrets[0].key_package_ref,
+        bob_key_package.key_package_ref()
+    );
+    assert_eq!(
+        group_alice.find_member_by_key_package(&bob_key_package),
+        Some(1u32.into())
+    );
+
+    // A failed join keeps the bundle
+    assert_eq!(
+        manager.join_from_welcome(welcome.clone(), None).err(),
+        Some(WelcomeError::MissingRatchetTree)
+    );
+    assert_eq!(manager.len(), 2);
+    assert!(events.lock().unwrap().is_empty());
+
+    // A successful join deletes it and reports it
+    let group_bob = manager
+        .join_from_welcome(welcome, Some(group_alice.get_tree().public_key_tree()))
+        .unwrap();
+    assert_eq!(group_bob.get_fingerprint(), group_alice.get_fingerprint());
+    assert_eq!(manager.len(), 1);
+    assert!(manager.get(&bob_key_package.key_package_ref()).is_none());
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![KeyPackageEvent::KeyPackageConsumed(
+            bob_key_package.key_package_ref()
+        )]
+    );
+    assert_eq!(*replenished.lock().unwrap(), vec![1]);
+}
+
+#[test]
+fn error_hierarchy() {
+    use maelstrom::error::MlsError;
+    use maelstrom::extensions::ExtensionError;
+    use std::error::Error;
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Errors of different operations can be propagated with `?`
+    let join = |welcome, key_package_bundle| -> Result<MlsGroup, MlsError> {
+        let mut group = MlsGroup::new_from_welcome(welcome, None, key_package_bundle)?;
+        let mls_plaintext =
+            group.create_application_message(&[0u8; 1 << 17], b"hello", bob_signature_key);
+        group.encrypt(mls_plaintext)?;
+        Ok(group)
+    };
+    let bob_key_package_bundle = new_kpb(bob_signature_key, &bob_credential);
+    let (_, welcome) = MlsGroup::new_with_members(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(alice_signature_key, &alice_credential),
+        alice_signature_key,
+        vec![bob_key_package_bundle.get_key_package().clone()],
+    )
+    .unwrap();
+    let error = join(welcome, bob_key_package_bundle).err().unwrap();
+    assert_eq!(error, MlsError::Welcome(WelcomeError::MissingRatchetTree));
+
+    // The original error is the source and both have a message
+    assert_eq!(error.to_string(), "the group could not be joined");
+    let source = error.source().unwrap();
+    assert_eq!(
+        source.to_string(),
+        "the ratchet tree is neither given nor in the Welcome"
+    );
+    assert!(source.source().is_none());
+    let error: MlsError = KeyPackageError::from(ExtensionError::MissingExtension).into();
+    assert_eq!(
+        error.source().unwrap().source().unwrap().to_string(),
+        "a required extension is missing"
+    );
+}
+
+#[test]
+fn public_state() {
+    use maelstrom::codec::*;
+    use maelstrom::extensions::*;
+
+    let ciphersuite_name = CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
+
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        bob_credential,
+        group_alice,
+        ..
+    } = TwoMemberGroup::new();
+
+    // The public state lists the members, but no secrets
+    let public_state = group_alice.export_public_state();
+    assert_eq!(public_state.group_id, vec![1, 2, 3, 4]);
+    assert_eq!(public_state.epoch, 1);
+    assert_eq!(public_state.ciphersuite, ciphersuite_name);
+    assert_eq!(
+        public_state
+            .members
+            .iter()
+            .map(|member| (member.index, member.identity.clone()))
+            .collect::<Vec<_>>(),
+        vec![(0, b"Alice".to_vec()), (1, b"Bob".to_vec())]
+    );
+    assert_eq!(
+        Credential::decode(&mut Cursor::new(&public_state.members[1].credential)).unwrap(),
+        bob_credential
+    );
+    assert_eq!(
+        public_state.extensions,
+        group_alice
+            .get_context()
+            .extensions
+            .iter()
+            .map(|extension| extension.get_type())
+            .collect::<Vec<ExtensionType>>()
+    );
+
+    // It can be stored as JSON
+    #[cfg(feature = "serde")]
+    {
+        let json = serde_json::to_string(&public_state).unwrap();
+        let parsed: PublicGroupState = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, public_state);
+    }
+}
+
+#[test]
+fn application_protocol() {
+    use maelstrom::extensions::*;
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let charlie_identity = Identity::new(ciphersuite, "Charlie".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let charlie_credential = Credential::Basic(BasicCredential::from(&charlie_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+    let charlie_signature_key = charlie_identity.get_signature_key_pair().get_private_key();
+
+    let chat = ApplicationProtocolExtension::new(b"chat-v2");
+    let mail = ApplicationProtocolExtension::new(b"mail-v1");
+    let new_kpb = |signature_key,
+                   credential: &Credential,
+                   protocol: Option<&ApplicationProtocolExtension>| {
+        KeyPackageBundle::new(
+            &ciphersuite,
+            signature_key,
+            credential.clone(),
+            protocol.map(|p| vec![p.to_extension()]),
+        )
+    };
+
+    // Alice creates a chat group and invites Bob, a chat client, Charlie, a
+    // mail client, and Dave, who doesn't announce an application protocol
+    let mut group_alice = MlsGroup::new_with_extensions(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(alice_signature_key, &alice_credential, Some(&chat)),
+        vec![chat.to_extension()],
+    );
+    assert_eq!(group_alice.get_application_protocol(), Some(chat.clone()));
+    let bob_key_package_bundle = new_kpb(bob_signature_key, &bob_credential, Some(&chat));
+    let charlie_key_package_bundle =
+        new_kpb(charlie_signature_key, &charlie_credential, Some(&mail));
+    let dave_key_package_bundle = new_kpb(charlie_signature_key, &charlie_credential, None);
+    let commit_bundle = group_alice
+        .add_members(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential, Some(&chat)),
+            &[
+                bob_key_package_bundle.get_key_package().clone(),
+                charlie_key_package_bundle.get_key_package().clone(),
+                dave_key_package_bundle.get_key_package().clone(),
+            ],
+        )
+        .unwrap();
+    let proposals = commit_bundle.get_proposal_list();
+    group_alice
+        .apply_commit(commit_bundle.get_commit().clone(), proposals)
+        .unwrap();
+    let welcome = commit_bundle.get_welcome().unwrap().clone();
+    let nodes = group_alice.get_tree().public_key_tree();
+
+    // Only Bob can join
+    let group_bob =
+        MlsGroup::new_from_welcome(welcome.clone(), Some(nodes.clone()), bob_key_package_bundle)
+            .unwrap();
+    assert_eq!(group_bob.get_application_protocol(), Some(chat));
+    assert_eq!(
+        MlsGroup::new_from_welcome(
+            welcome.clone(),
+            Some(nodes.clone()),
+            charlie_key_package_bundle
+        )
+        .err(),
+        Some(WelcomeError::ApplicationProtocolMismatch)
+    );
+    assert_eq!(
+        MlsGroup::new_from_welcome(welcome, Some(nodes), dave_key_package_bundle).err(),
+        Some(WelcomeError::ApplicationProtocolMismatch)
+    );
+}
+
+#[test]
+fn device_capabilities() {
+    use maelstrom::extensions::*;
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let charlie_identity = Identity::new(ciphersuite, "Charlie".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let charlie_credential = Credential::Basic(BasicCredential::from(&charlie_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+    let charlie_signature_key = charlie_identity.get_signature_key_pair().get_private_key();
+
+    use DeviceCapability::*;
+    let new_kpb = |signature_key, credential: &Credential, capabilities: &[DeviceCapability]| {
+        KeyPackageBundle::new(
+            &ciphersuite,
+            signature_key,
+            credential.clone(),
+            Some(vec![
+                DeviceCapabilitiesExtension::new(capabilities).to_extension()
+            ]),
+        )
+    };
+
+    // Alice's device can't be removed, Bob joins with a temporary device
+    let bob_key_package_bundle = new_kpb(bob_signature_key, &bob_credential, &[]);
+    let (mut group_alice, welcome) = MlsGroup::new_with_members(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(
+            alice_signature_key,
+            &alice_credential,
+            &[Permanent, NonRemovableCap],
+        ),
+        alice_signature_key,
+        vec![bob_key_package_bundle.get_key_package().clone()],
+    )
+    .unwrap();
+    let mut group_bob = MlsGroup::new_from_welcome(
+        welcome,
+        Some(group_alice.get_tree().public_key_tree()),
+        bob_key_package_bundle,
+    )
+    .unwrap();
+
+    // Bob can't grant himself NonRemovableCap, neither with an Update nor
+    // to a new member
+    let (update_plaintext, _) = group_bob
+        .create_update_proposal(
+            &[],
+            bob_signature_key,
+            new_kpb(bob_signature_key, &bob_credential, &[NonRemovableCap]),
+        )
+        .unwrap();
+    assert_eq!(
+        group_alice.process_proposal(update_plaintext).err(),
+        Some(ProposalError::InvalidDeviceCapabilities)
+    );
+    let charlie_key_package = new_kpb(
+        charlie_signature_key,
+        &charlie_credential,
+        &[NonRemovableCap],
+    )
+    .get_key_package()
+    .clone();
+    let (add_plaintext, _) = group_bob
+        .create_add_proposal(&[], bob_signature_key, charlie_key_package.clone())
+        .unwrap();
+    assert_eq!(
+        group_alice.process_proposal(add_plaintext).err(),
+        Some(ProposalError::InvalidDeviceCapabilities)
+    );
+
+    // Alice can grant it and can't be removed
+    let (add_plaintext, _) = group_alice
+        .create_add_proposal(&[], alice_signature_key, charlie_key_package)
+        .unwrap();
+    assert!(group_bob.process_proposal(add_plaintext).is_ok());
+    let (remove_plaintext, _) = group_bob
+        .create_remove_proposal(&[], bob_signature_key, 0u32.into())
+        .unwrap();
+    assert_eq!(
+        group_alice.process_proposal(remove_plaintext).err(),
+        Some(ProposalError::NonRemovableMember)
+    );
+
+    // Bob promotes his device to a permanent one
+    let (update_plaintext, update_proposal) = group_bob
+        .create_update_proposal(
+            &[],
+            bob_signature_key,
+            new_kpb(bob_signature_key, &bob_credential, &[Permanent]),
+        )
+        .unwrap();
+    let queued_proposal = group_alice.process_proposal(update_plaintext).unwrap();
+    let proposals = vec![(queued_proposal.sender, update_proposal)];
+    let commit_bundle = group_alice
+        .create_commit(
+            &[],
+            alice_signature_key,
+            new_kpb(
+                alice_signature_key,
+                &alice_credential,
+                &[Permanent, NonRemovableCap],
+            ),
+            proposals.clone(),
+            false,
+        )
+        .unwrap();
+    group_alice
+        .apply_commit(commit_bundle.get_commit().clone(), proposals.clone())
+        .unwrap();
+    let membership_changes = group_bob
+        .apply_commit(commit_bundle.get_commit().clone(), proposals)
+        .unwrap();
+    assert_eq!(membership_changes.capability_changes.len(), 1);
+    let capability_change = &membership_changes.capability_changes[0];
+    assert_eq!(capability_change.index, queued_proposal.sender.sender);
+    assert!(!capability_change.old_capabilities.has(Permanent));
+    assert!(capability_change.new_capabilities.has(Permanent));
+
+    // The path of a Commit can't grant new capabilities either
+    let commit_bundle = group_bob
+        .update_self(
+            &[],
+            bob_signature_key,
+            new_kpb(
+                bob_signature_key,
+                &bob_credential,
+                &[Permanent, NonRemovableCap],
+            ),
+        )
+        .unwrap();
+    assert_eq!(
+        group_alice
+            .apply_commit(commit_bundle.get_commit().clone(), vec![])
+            .err(),
+        Some(ApplyCommitError::InvalidDeviceCapabilities)
+    );
+}
+
+#[test]
+fn application_content_types() {
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        alice_identity,
+        mut group_alice,
+        mut group_bob,
+        ..
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+
+    // Alice sends a text message and a read receipt
+    const TEXT: u16 = 1;
+    const RECEIPT: u16 = 2;
+    let text = group_alice.create_application_message_with_content_type(
+        &[],
+        b"Hello Bob",
+        TEXT,
+        alice_signature_key,
+    );
+    let receipt = group_alice.create_application_message_with_content_type(
+        &[],
+        &[42],
+        RECEIPT,
+        alice_signature_key,
+    );
+    let untyped = group_alice.create_application_message(&[], b"untyped", alice_signature_key);
+
+    let processed_message = group_bob
+        .decrypt(group_alice.encrypt(text).unwrap())
+        .unwrap();
+    assert_eq!(processed_message.application_content_type(), Some(TEXT));
+    assert_eq!(
+        processed_message.application_data(),
+        Some(&b"Hello Bob"[..])
+    );
+    let processed_message = group_bob
+        .decrypt(group_alice.encrypt(receipt).unwrap())
+        .unwrap();
+    assert_eq!(processed_message.application_content_type(), Some(RECEIPT));
+    assert_eq!(processed_message.application_data(), Some(&[42u8][..]));
+
+    // Messages without a content type are still application messages
+    let processed_message = group_bob
+        .decrypt(group_alice.encrypt(untyped).unwrap())
+        .unwrap();
+    assert_eq!(processed_message.application_content_type(), None);
+    assert_eq!(processed_message.application_data(), Some(&b"untyped"[..]));
+}
+
+#[test]
+fn deniable_application_messages() {
+    use maelstrom::extensions::*;
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+    let new_groups = |extensions| {
+        let bob_key_package_bundle = new_kpb(bob_signature_key, &bob_credential);
+        let mut group_alice = MlsGroup::new_with_extensions(
+            &[1, 2, 3, 4],
+            ciphersuite,
+            new_kpb(alice_signature_key, &alice_credential),
+            extensions,
+        );
+        let commit_bundle = group_alice
+            .add_members(
+                &[],
+                alice_signature_key,
+                new_kpb(alice_signature_key, &alice_credential),
+                &[bob_key_package_bundle.get_key_package().clone()],
+            )
+            .unwrap();
+        let proposals = commit_bundle.get_proposal_list();
+        group_alice
+            .apply_commit(commit_bundle.get_commit().clone(), proposals)
+            .unwrap();
+        let group_bob = MlsGroup::new_from_welcome(
+            commit_bundle.get_welcome().unwrap().clone(),
+            Some(group_alice.get_tree().public_key_tree()),
+            bob_key_package_bundle,
+        )
+        .unwrap();
+        (group_alice, group_bob)
+    };
+
+    // Application messages of deniable groups are not signed
+    let (mut group_alice, mut group_bob) =
+        new_groups(vec![DeniabilityExtension::new().to_extension()]);
+    assert!(group_bob.get_context().is_deniable());
+    let mls_plaintext =
+        group_alice.create_application_message(&[], b"Hello Bob", alice_signature_key);
+    assert!(!mls_plaintext.verify(group_alice.get_context(), &alice_credential));
+    let processed_message = group_bob
+        .decrypt(group_alice.encrypt(mls_plaintext).unwrap())
+        .unwrap();
+    assert_eq!(
+        processed_message.application_data(),
+        Some(&b"Hello Bob"[..])
+    );
+    assert_eq!(processed_message.sender_credential(), &alice_credential);
+
+    // Handshake messages are still signed
+    let commit_bundle = group_alice
+        .update_self(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+        )
+        .unwrap();
+    assert!(commit_bundle
+        .get_commit()
+        .verify(group_alice.get_context(), &alice_credential));
+
+    // Other groups reject application messages without a valid signature
+    let (mut group_alice, mut group_bob) = new_groups(vec![]);
+    assert!(!group_bob.get_context().is_deniable());
+    let mut mls_plaintext =
+        group_alice.create_application_message(&[], b"Hello Bob", alice_signature_key);
+    assert!(mls_plaintext.verify(group_alice.get_context(), &alice_credential));
+    mls_plaintext.sign(&ciphersuite, bob_signature_key, group_alice.get_context());
+    assert_eq!(
+        group_bob
+            .decrypt(group_alice.encrypt(mls_plaintext).unwrap())
+            .err(),
+        Some(DecryptionError::InvalidSignature)
+    );
+}
+
+#[test]
+fn group_simulation() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+
+    for seed in 0..3 {
+        let mut simulation = GroupSimulation::new(ciphersuite, 6, seed);
+        simulation.run(100);
+        let stats = simulation.get_stats();
+        assert!(stats.commits > 0);
+        assert!(stats.joins > 0);
+        assert!(stats.messages_delivered > 0);
+    }
+
+    // Without drops every message is delivered
+    let mut simulation = GroupSimulation::new(ciphersuite, 4, 4);
+    simulation.set_drop_rate(0.0);
+    simulation.run(100);
+    let stats = simulation.get_stats();
+    assert_eq!(stats.messages_dropped, 0);
+    assert!(simulation.members().len() > 1);
+}
+
+#[test]
+fn provisional_welcome() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Alice adds Bob with a path, so that the root is not blank
+    let mut group_alice = MlsGroup::new(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(alice_signature_key, &alice_credential),
+    );
+    let bob_key_package_bundle = new_kpb(bob_signature_key, &bob_credential);
+    let (add_plaintext, add_proposal) = group_alice
+        .create_add_proposal(
+            &[],
+            alice_signature_key,
+            bob_key_package_bundle.get_key_package().clone(),
+        )
+        .unwrap();
+    let proposals = vec![(add_plaintext.sender(), add_proposal)];
+    let commit_bundle = group_alice
+        .create_commit(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+            proposals.clone(),
+            true,
+        )
+        .unwrap();
+    group_alice
+        .apply_commit(commit_bundle.get_commit().clone(), proposals)
+        .unwrap();
+    let welcome = commit_bundle.get_welcome().unwrap();
+    let nodes = group_alice.get_tree().public_key_tree();
+
+    // A tampered tree is only detected once the tree is validated
+    let mut tampered_nodes = nodes.clone();
+    if let Some(Some(node)) = tampered_nodes.get_mut(1) {
+        node.node.as_mut().unwrap().set_parent_hash(vec![1, 2, 3]);
+    }
+    assert_eq!(
+        MlsGroup::new_from_welcome(
+            welcome.clone(),
+            Some(tampered_nodes.clone()),
+            bob_key_package_bundle.clone()
+        )
+        .err(),
+        Some(WelcomeError::TreeHashMismatch)
+    );
+    let mut group_bob = MlsGroup::new_from_welcome_provisional(
+        welcome.clone(),
+        Some(tampered_nodes),
+        bob_key_package_bundle.clone(),
+    )
+    .unwrap();
+    assert_eq!(
+        group_bob.finish_validation(),
+        Err(WelcomeError::TreeHashMismatch)
+    );
+    assert!(group_bob.is_provisional());
+
+    // Bob can decrypt messages right away
+    let mut group_bob = MlsGroup::new_from_welcome_provisional(
+        welcome.clone(),
+        Some(nodes),
+        bob_key_package_bundle,
+    )
+    .unwrap();
+    assert!(group_bob.is_provisional());
+    let message = group_alice
+        .encrypt(group_alice.create_application_message(&[], b"Hi Bob", alice_signature_key))
+        .unwrap();
+    assert_eq!(
+        group_bob.decrypt(message).unwrap().application_data(),
+        Some(&b"Hi Bob"[..])
+    );
+
+    // But can't commit or store the group before the tree was validated
+    assert_eq!(
+        group_bob
+            .update_self(
+                &[],
+                bob_signature_key,
+                new_kpb(bob_signature_key, &bob_credential)
+            )
+            .err(),
+        Some(CreateCommitError::ProvisionalGroup)
+    );
+    assert!(group_bob.save_encrypted(&[7u8; 32]).is_err());
+
+    group_bob.finish_validation().unwrap();
+    assert!(!group_bob.is_provisional());
+    let commit_bundle = group_bob
+        .update_self(
+            &[],
+            bob_signature_key,
+            new_kpb(bob_signature_key, &bob_credential),
+        )
+        .unwrap();
+    group_bob
+        .apply_commit(commit_bundle.get_commit().clone(), vec![])
+        .unwrap();
+    group_alice
+        .apply_commit(commit_bundle.get_commit().clone(), vec![])
+        .unwrap();
+    assert_eq!(
+        group_alice.export_secret("test", 32),
+        group_bob.export_secret("test", 32)
+    );
+}
+
+#[test]
+fn welcome_path_secrets() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let identities: Vec<Identity> = ["Alice", "Bob", "Charlie", "Dave"]
+        .iter()
+        .map(|name| Identity::new(ciphersuite, name.as_bytes().to_vec()))
+        .collect();
+    let new_kpb = |identity: &Identity| {
+        KeyPackageBundle::new(
+            &ciphersuite,
+            identity.get_signature_key_pair().get_private_key(),
+            Credential::Basic(BasicCredential::from(identity)),
+            None,
+        )
+    };
+    let alice_signature_key = identities[0].get_signature_key_pair().get_private_key();
+
+    // Alice adds everyone else with a path. Charlie's path secret is for the
+    // root, which is not the parent of his leaf.
+    let mut group_alice = MlsGroup::new(&[1, 2, 3, 4], ciphersuite, new_kpb(&identities[0]));
+    let key_package_bundles: Vec<KeyPackageBundle> = identities[1..].iter().map(new_kpb).collect();
+    let mut proposals = vec![];
+    for key_package_bundle in key_package_bundles.iter() {
+        let (plaintext, proposal) = group_alice
+            .create_add_proposal(
+                &[],
+                alice_signature_key,
+                key_package_bundle.get_key_package().clone(),
+            )
+            .unwrap();
+        proposals.push((plaintext.sender(), proposal));
+    }
+    let commit_bundle = group_alice
+        .create_commit(
+            &[],
+            alice_signature_key,
+            new_kpb(&identities[0]),
+            proposals.clone(),
+            true,
+        )
+        .unwrap();
+    group_alice
+        .apply_commit(commit_bundle.get_commit().clone(), proposals)
+        .unwrap();
+    let welcome = commit_bundle.get_welcome().unwrap();
+    let nodes = group_alice.get_tree().public_key_tree();
+
+    // A path secret that doesn't match the public keys in the tree is rejected
+    let mut tampered_nodes = nodes.clone();
+    tampered_nodes[3] = tampered_nodes[1].clone();
+    assert_eq!(
+        MlsGroup::new_from_welcome_provisional(
+            welcome.clone(),
+            Some(tampered_nodes),
+            key_package_bundles[1].clone(),
+        )
+        .err(),
+        Some(WelcomeError::PathPublicKeyMismatch)
+    );
+
+    let mut groups = vec![group_alice];
+    for key_package_bundle in key_package_bundles {
+        groups.push(
+            MlsGroup::new_from_welcome(welcome.clone(), Some(nodes.clone()), key_package_bundle)
+                .unwrap(),
+        );
+    }
+
+    // Charlie's update path is encrypted to the keys every member derived
+    // from the path secrets in the Welcome
+    let charlie_signature_key = identities[2].get_signature_key_pair().get_private_key();
+    let commit_bundle = groups[2]
+        .update_self(&[], charlie_signature_key, new_kpb(&identities[2]))
+        .unwrap();
+    for group in groups.iter_mut() {
+        group
+            .apply_commit(commit_bundle.get_commit().clone(), vec![])
+            .unwrap();
+    }
+    for group in groups[1..].iter() {
+        assert_eq!(
+            group.export_secret("test", 32),
+            groups[0].export_secret("test", 32)
+        );
+    }
+}
+
+#[test]
+fn commit_randomness() {
+    use maelstrom::utils::RngProvider;
+    use std::sync::Arc;
+
+    struct FixedRng;
+    impl RngProvider for FixedRng {
+        fn random_bytes(&self, length: usize) -> Vec<u8> {
+            vec![7; length]
+        }
+    }
+
+    let TwoMemberGroup {
+        ciphersuite,
+        alice_identity,
+        alice_credential,
+        mut group_alice,
+        ..
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    let path_key = |group_alice: &mut MlsGroup| {
+        let commit_bundle = group_alice
+            .update_self(
+                &[],
+                alice_signature_key,
+                new_kpb(alice_signature_key, &alice_credential),
+            )
+            .unwrap();
+        commit_bundle
+            .get_commit()
+            .as_commit()
+            .and_then(|commit| commit.path())
+            .map(|path| path.nodes[0].public_key.clone())
+            .unwrap()
+    };
+
+    // Every Commit uses a fresh leaf secret
+    assert_ne!(path_key(&mut group_alice), path_key(&mut group_alice));
+
+    // The leaf secrets come from the configured source of randomness
+    let mut config = GroupConfig::default();
+    config.set_rng_provider(Arc::new(FixedRng));
+    group_alice.set_config(&config);
+    assert_eq!(path_key(&mut group_alice), path_key(&mut group_alice));
+}
+
+#[test]
+fn epoch_policy() {
+    use maelstrom::extensions::TimeProvider;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    struct TestClock(AtomicU64);
+    impl TimeProvider for TestClock {
+        fn now(&self) -> u64 {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let new_alice_kpb = || {
+        KeyPackageBundle::new(
+            &ciphersuite,
+            alice_signature_key,
+            alice_credential.clone(),
+            None,
+        )
+    };
+
+    // The group takes the time from the clock of the config
+    let clock = Arc::new(TestClock(AtomicU64::new(1_000_000)));
+    let mut config = GroupConfig::default();
+    config.set_time_provider(clock.clone());
+    let mut group_alice =
+        MlsGroup::new_with_config(&[1, 2, 3, 4], ciphersuite, new_alice_kpb(), vec![], &config);
+    let epoch_policy = EpochPolicy {
+        max_epoch_age: Some(3600),
+        max_epoch_messages: Some(2),
+    };
+    group_alice.set_epoch_policy(epoch_policy);
+    assert_eq!(group_alice.get_epoch_policy(), &epoch_policy);
+
+    // A fresh epoch doesn't violate the policy
+    let now = clock.now();
+    assert!(group_alice.policy_violations(now).is_empty());
+    assert!(group_alice
+        .enforce_epoch_policy(now, &[], alice_signature_key, new_alice_kpb())
+        .is_none());
+
+    // Old epochs and epochs with too many messages do
+    let later = now + 2 * 3600;
+    assert!(matches!(
+        group_alice.policy_violations(later)[..],
+        [PolicyViolation::EpochTooOld { max_age: 3600, .. }]
+    ));
+    for _ in 0..3 {
+        let mls_plaintext =
+            group_alice.create_application_message(&[], &[1, 2, 3], alice_signature_key);
+        group_alice.encrypt(mls_plaintext).unwrap();
+    }
+    assert_eq!(
+        group_alice.policy_violations(now),
+        vec![PolicyViolation::TooManyMessages {
+            count: 3,
+            max_count: 2
+        }]
+    );
+    assert_eq!(group_alice.policy_violations(later).len(), 2);
+
+    // The epoch statistics are stored with the group, the policy isn't
+    let storage_key = [7u8; 32];
+    let stored = group_alice.save_encrypted(&storage_key).unwrap();
+    let mut loaded = MlsGroup::load_encrypted(&stored, &storage_key).unwrap();
+    assert!(loaded.policy_violations(later).is_empty());
+    loaded.set_epoch_policy(epoch_policy);
+    assert_eq!(
+        loaded.policy_violations(later),
+        group_alice.policy_violations(later)
+    );
+
+    // The corrective self-update starts a new epoch that complies again
+    let commit_bundle = group_alice
+        .enforce_epoch_policy(later, &[], alice_signature_key, new_alice_kpb())
+        .unwrap()
+        .unwrap();
+    group_alice
+        .apply_commit(
+            commit_bundle.get_commit().clone(),
+            commit_bundle.get_proposal_list(),
+        )
+        .unwrap();
+    assert_eq!(group_alice.get_context().epoch, GroupEpoch(1));
+    assert!(group_alice.policy_violations(now).is_empty());
+
+    // New epochs start at the time of the clock
+    assert!(!group_alice.policy_violations(later).is_empty());
+    clock.0.store(later, Ordering::SeqCst);
+    let commit_bundle = group_alice
+        .enforce_epoch_policy(later, &[], alice_signature_key, new_alice_kpb())
+        .unwrap()
+        .unwrap();
+    group_alice
+        .apply_commit(
+            commit_bundle.get_commit().clone(),
+            commit_bundle.get_proposal_list(),
+        )
+        .unwrap();
+    assert!(group_alice.policy_violations(later + 3600).is_empty());
+}
+
+#[test]
+fn commit_sequencing() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        ciphersuite,
+        alice_identity,
+        bob_identity,
+        alice_credential,
+        bob_credential,
+        mut group_alice,
+        mut group_bob,
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Both members get their tokens from the same server
+    let server = Arc::new(AtomicU64::new(0));
+    let new_sequencer = || {
+        let server = server.clone();
+        Box::new(move |_: &GroupId, _: GroupEpoch| Some(server.fetch_add(1, Ordering::SeqCst) + 1))
+    };
+    group_alice.set_commit_sequencer(new_sequencer());
+    group_bob.set_commit_sequencer(new_sequencer());
+
+    // The token is embedded in the authenticated data of the Commit
+    let commit_bundle = group_alice
+        .update_self(
+            b"aad",
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+        )
+        .unwrap();
+    let commit = commit_bundle.get_commit().clone();
+    assert_eq!(
+        split_sequenced_aad(commit.authenticated_data()),
+        Some((1, &b"aad"[..]))
+    );
+    // Receivers only see the authenticated data of the application
+    let processed_message = group_bob
+        .decrypt(group_alice.encrypt(commit.clone()).unwrap())
+        .unwrap();
+    assert_eq!(
+        processed_message
+            .authenticated_content()
+            .authenticated_data(),
+        b"aad"
+    );
+    group_alice.apply_commit(commit.clone(), vec![]).unwrap();
+    group_bob.apply_commit(commit, vec![]).unwrap();
+    assert_eq!(group_alice.get_last_commit_token(), Some(1));
+    assert_eq!(group_bob.get_last_commit_token(), Some(1));
+
+    // Commits that violate the policy don't use up a token
+    group_alice.set_commit_policy(CommitPolicy {
+        max_proposals: Some(0),
+        ..CommitPolicy::default()
+    });
+    let (remove_plaintext, remove_proposal) = group_alice
+        .create_remove_proposal(&[], alice_signature_key, 1u32.into())
+        .unwrap();
+    assert_eq!(
+        group_alice
+            .create_commit(
+                &[],
+                alice_signature_key,
+                new_kpb(alice_signature_key, &alice_credential),
+                vec![(remove_plaintext.sender(), remove_proposal)],
+                false,
+            )
+            .err(),
+        Some(CreateCommitError::TooManyProposals)
+    );
+    assert_eq!(server.load(Ordering::SeqCst), 1);
+    group_alice.set_commit_policy(CommitPolicy::default());
+
+    // Commits with stale tokens are rejected
+    group_bob.set_commit_sequencer(Box::new(|_: &GroupId, _: GroupEpoch| Some(1)));
+    let commit_bundle = group_bob
+        .update_self(
+            &[],
+            bob_signature_key,
+            new_kpb(bob_signature_key, &bob_credential),
+        )
+        .unwrap();
+    assert_eq!(
+        group_alice
+            .apply_commit(commit_bundle.get_commit().clone(), vec![])
+            .err(),
+        Some(ApplyCommitError::SequenceTokenNotIncreasing)
+    );
+
+    // Commits can't be created without a token
+    group_bob.set_commit_sequencer(Box::new(|_: &GroupId, _: GroupEpoch| None));
+    assert_eq!(
+        group_bob
+            .update_self(
+                &[],
+                bob_signature_key,
+                new_kpb(bob_signature_key, &bob_credential)
+            )
+            .err(),
+        Some(CreateCommitError::SequenceTokenUnavailable)
+    );
+
+    // The last token is stored with the group, the sequencer isn't, so a
+    // loaded group creates Commits without a token
+    let storage_key = [7u8; 32];
+    let stored = group_bob.save_encrypted(&storage_key).unwrap();
+    let mut loaded_bob = MlsGroup::load_encrypted(&stored, &storage_key).unwrap();
+    assert_eq!(loaded_bob.get_last_commit_token(), Some(1));
+    let commit_bundle = loaded_bob
+        .update_self(
+            &[],
+            bob_signature_key,
+            new_kpb(bob_signature_key, &bob_credential),
+        )
+        .unwrap();
+    assert_eq!(
+        group_alice
+            .apply_commit(commit_bundle.get_commit().clone(), vec![])
+            .err(),
+        Some(ApplyCommitError::MissingSequenceToken)
+    );
+}
+
+#[test]
+fn correlation_id() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Alice adds Bob
+    let mut group_alice = MlsGroup::new(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(alice_signature_key, &alice_credential),
+    );
+    let bob_key_package_bundle = new_kpb(bob_signature_key, &bob_credential);
+    let commit_bundle = group_alice
+        .add_members(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+            &[bob_key_package_bundle.get_key_package().clone()],
+        )
+        .unwrap();
+    let correlation_id = commit_bundle.correlation_id().clone();
+    assert_eq!(
+        commit_bundle.get_group_info().unwrap().correlation_id(),
+        correlation_id
+    );
+    group_alice
+        .apply_commit(
+            commit_bundle.get_commit().clone(),
+            commit_bundle.get_proposal_list(),
+        )
+        .unwrap();
+    assert_eq!(group_alice.correlation_id(), correlation_id);
+
+    // Bob recovers the same ID from the Welcome
+    let group_bob = MlsGroup::new_from_welcome(
+        commit_bundle.get_welcome().unwrap().clone(),
+        Some(group_alice.get_tree().public_key_tree()),
+        bob_key_package_bundle,
+    )
+    .unwrap();
+    assert_eq!(group_bob.correlation_id(), correlation_id);
+
+    // The next Commit has a different one
+    let commit_bundle = group_alice
+        .update_self(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+        )
+        .unwrap();
+    assert_ne!(commit_bundle.correlation_id(), &correlation_id);
+}
+
+#[test]
+fn conflicting_commits() {
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        ciphersuite,
+        alice_identity,
+        bob_identity,
+        alice_credential,
+        bob_credential,
+        mut group_alice,
+        mut group_bob,
+    } = TwoMemberGroup::new();
+    let charlie_identity = Identity::new(ciphersuite, "Charlie".into());
+    let charlie_credential = Credential::Basic(BasicCredential::from(&charlie_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+    let charlie_signature_key = charlie_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Alice adds Charlie while Bob updates, Bob's Commit is delivered first
+    let charlie_key_package = new_kpb(charlie_signature_key, &charlie_credential)
+        .get_key_package()
+        .clone();
+    let alice_commit_bundle = group_alice
+        .add_members(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+            std::slice::from_ref(&charlie_key_package),
+        )
+        .unwrap();
+    let bob_commit_bundle = group_bob
+        .update_self(
+            &[],
+            bob_signature_key,
+            new_kpb(bob_signature_key, &bob_credential),
+        )
+        .unwrap();
+
+    // Alice discards her Commit and learns that Charlie has to be added again
+    let conflict = group_alice
+        .handle_conflicting_commit(bob_commit_bundle.get_commit().clone(), vec![])
+        .unwrap();
+    assert_eq!(
+        conflict.discarded_commit.as_ref(),
+        Some(alice_commit_bundle.get_commit())
+    );
+    assert_eq!(conflict.membership_changes.committer, 1u32.into());
+    assert_eq!(conflict.uncommitted_proposals.len(), 1);
+    let (sender, proposal) = &conflict.uncommitted_proposals[0];
+    assert_eq!(sender, &alice_commit_bundle.get_proposal_list()[0].0);
+    assert_eq!(proposal.as_add().unwrap().key_package, charlie_key_package);
+
+    // Bob's own Commit doesn't conflict
+    let conflict = group_bob
+        .handle_conflicting_commit(bob_commit_bundle.get_commit().clone(), vec![])
+        .unwrap();
+    assert!(conflict.discarded_commit.is_none());
+    assert!(conflict.uncommitted_proposals.is_empty());
+    assert_eq!(group_alice.get_fingerprint(), group_bob.get_fingerprint());
+
+    // The discarded Commit is from a past epoch now
+    assert_eq!(
+        group_bob
+            .handle_conflicting_commit(
+                alice_commit_bundle.get_commit().clone(),
+                alice_commit_bundle.get_proposal_list(),
+            )
+            .err(),
+        Some(ApplyCommitError::EpochMismatch)
+    );
+
+    // Charlie is added in the new epoch
+    let commit_bundle = group_alice
+        .add_members(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+            &[charlie_key_package],
+        )
+        .unwrap();
+    group_alice
+        .apply_commit(
+            commit_bundle.get_commit().clone(),
+            commit_bundle.get_proposal_list(),
+        )
+        .unwrap();
+    group_bob
+        .apply_commit(
+            commit_bundle.get_commit().clone(),
+            commit_bundle.get_proposal_list(),
+        )
+        .unwrap();
+    assert_eq!(group_alice.get_fingerprint(), group_bob.get_fingerprint());
+    assert_eq!(group_alice.member_count(), 3);
+}
+
+#[test]
+fn merge_pending_commit() {
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        ciphersuite,
+        alice_identity,
+        bob_identity,
+        alice_credential,
+        bob_credential,
+        mut group_alice,
+        mut group_bob,
+    } = TwoMemberGroup::new();
+    let charlie_identity = Identity::new(ciphersuite, "Charlie".into());
+    let charlie_credential = Credential::Basic(BasicCredential::from(&charlie_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+    let charlie_signature_key = charlie_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Nothing to merge without an own Commit
+    assert_eq!(
+        group_alice.merge_pending_commit().err(),
+        Some(ApplyCommitError::NoPendingCommit)
+    );
+
+    // Alice merges her update, Bob processes it
+    let commit_bundle = group_alice
+        .update_self(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+        )
+        .unwrap();
+    let membership_changes = group_alice.merge_pending_commit().unwrap();
+    assert_eq!(membership_changes.committer, 0u32.into());
+    assert_eq!(group_alice.pending_key_package_bundles(), 0);
+    group_bob
+        .apply_commit(commit_bundle.get_commit().clone(), vec![])
+        .unwrap();
+    assert_eq!(group_alice.get_fingerprint(), group_bob.get_fingerprint());
+
+    // The Commit can only be merged once
+    assert_eq!(
+        group_alice.merge_pending_commit().err(),
+        Some(ApplyCommitError::NoPendingCommit)
+    );
+
+    // Charlie joins from the Welcome of a merged Commit
+    let charlie_key_package_bundle = new_kpb(charlie_signature_key, &charlie_credential);
+    let commit_bundle = group_alice
+        .add_members(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+            &[charlie_key_package_bundle.get_key_package().clone()],
+        )
+        .unwrap();
+    let membership_changes = group_alice.merge_pending_commit().unwrap();
+    assert_eq!(membership_changes.adds.len(), 1);
+    group_bob
+        .apply_commit(
+            commit_bundle.get_commit().clone(),
+            commit_bundle.get_proposal_list(),
+        )
+        .unwrap();
+    let group_charlie = MlsGroup::new_from_welcome(
+        commit_bundle.get_welcome().unwrap().clone(),
+        Some(group_alice.get_tree().public_key_tree()),
+        charlie_key_package_bundle,
+    )
+    .unwrap();
+    assert_eq!(group_alice.get_fingerprint(), group_bob.get_fingerprint());
+    assert_eq!(
+        group_alice.get_fingerprint(),
+        group_charlie.get_fingerprint()
+    );
+
+    // A Commit of another member that was applied first discards the own one
+    group_alice
+        .update_self(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+        )
+        .unwrap();
+    let bob_commit_bundle = group_bob
+        .update_self(
+            &[],
+            bob_signature_key,
+            new_kpb(bob_signature_key, &bob_credential),
+        )
+        .unwrap();
+    group_alice
+        .handle_conflicting_commit(bob_commit_bundle.get_commit().clone(), vec![])
+        .unwrap();
+    assert_eq!(
+        group_alice.merge_pending_commit().err(),
+        Some(ApplyCommitError::NoPendingCommit)
+    );
+}
+
+#[test]
+fn discard_pending_commit() {
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        ciphersuite,
+        alice_identity,
+        alice_credential,
+        mut group_alice,
+        mut group_bob,
+        ..
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    assert!(group_alice.discard_pending_commit().is_none());
+
+    // The discarded Commits can't be applied anymore, the key material of
+    // all attempts is gone
+    let first_commit_bundle = group_alice
+        .update_self(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+        )
+        .unwrap();
+    let commit_bundle = group_alice
+        .update_self(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+        )
+        .unwrap();
+    assert_eq!(group_alice.pending_key_package_bundles(), 2);
+    assert_eq!(
+        group_alice.discard_pending_commit().as_ref(),
+        Some(commit_bundle.get_commit())
+    );
+    assert_eq!(group_alice.pending_key_package_bundles(), 0);
+    for commit_bundle in [&first_commit_bundle, &commit_bundle].iter() {
+        assert_eq!(
+            group_alice
+                .apply_commit(commit_bundle.get_commit().clone(), vec![])
+                .err(),
+            Some(ApplyCommitError::MissingOwnKeyPackageBundle)
+        );
+    }
+    assert_eq!(
+        group_alice.merge_pending_commit().err(),
+        Some(ApplyCommitError::NoPendingCommit)
+    );
+
+    // A new Commit in the same epoch is applied from its kept path secrets
+    let commit_bundle = group_alice
+        .update_self(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+        )
+        .unwrap();
+    group_alice
+        .apply_commit(commit_bundle.get_commit().clone(), vec![])
+        .unwrap();
+    group_bob
+        .apply_commit(commit_bundle.get_commit().clone(), vec![])
+        .unwrap();
+    assert_eq!(group_alice.get_fingerprint(), group_bob.get_fingerprint());
+}
+
+#[test]
+fn welcome_processor() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    let bob_key_package_bundle = new_kpb(bob_signature_key, &bob_credential);
+    let (group_alice, welcome) = MlsGroup::new_with_members(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(alice_signature_key, &alice_credential),
+        alice_signature_key,
+        vec![bob_key_package_bundle.get_key_package().clone()],
+    )
+    .unwrap();
+    let nodes = group_alice.get_tree().public_key_tree();
+
+    // Bob joins one stage at a time
+    let mut welcome_processor = WelcomeProcessor::new(
+        welcome.clone(),
+        Some(nodes.clone()),
+        bob_key_package_bundle.clone(),
+        &[],
+    );
+    let mut stages = vec![];
+    let group_bob = loop {
+        stages.push(welcome_processor.stage());
+        if let Some(group) = welcome_processor.step().unwrap() {
+            break group;
+        }
+    };
+    assert_eq!(
+        stages,
+        vec![
+            WelcomeStage::DecryptGroupInfo,
+            WelcomeStage::BuildTree,
+            WelcomeStage::VerifyTreeHash,
+            WelcomeStage::VerifyParentHashes,
+            WelcomeStage::ComputeGroupState,
+        ]
+    );
+    assert_eq!(welcome_processor.stage(), WelcomeStage::Done);
+    // Three stages and two checks of the three nodes of the tree
+    assert_eq!(welcome_processor.progress(), (9, 9));
+    assert_eq!(group_alice.get_fingerprint(), group_bob.get_fingerprint());
+    assert_eq!(
+        welcome_processor.step().err(),
+        Some(WelcomeError::WelcomeProcessed)
+    );
+
+    // A failing stage ends the processing
+    let mut tampered_nodes = nodes.clone();
+    if let Some(Some(node)) = tampered_nodes.get_mut(1) {
+        node.node.as_mut().unwrap().set_parent_hash(vec![1, 2, 3]);
+    }
+    let mut welcome_processor = WelcomeProcessor::new(
+        welcome.clone(),
+        Some(tampered_nodes),
+        bob_key_package_bundle.clone(),
+        &[],
+    );
+    assert!(welcome_processor.step().unwrap().is_none());
+    assert!(welcome_processor.step().unwrap().is_none());
+    assert_eq!(
+        welcome_processor.step().err(),
+        Some(WelcomeError::TreeHashMismatch)
+    );
+    assert_eq!(welcome_processor.stage(), WelcomeStage::Done);
+
+    // The checks of the tree are split into steps of a few nodes, the
+    // progress counts the nodes
+    let mut welcome_processor = WelcomeProcessor::new(
+        welcome.clone(),
+        Some(nodes.clone()),
+        bob_key_package_bundle.clone(),
+        &[],
+    );
+    welcome_processor.set_nodes_per_step(1);
+    let mut progress = vec![welcome_processor.progress()];
+    let mut stages = vec![];
+    let group_bob = loop {
+        stages.push(welcome_processor.stage());
+        if let Some(group) = welcome_processor.step().unwrap() {
+            break group;
+        }
+        progress.push(welcome_processor.progress());
+    };
+    assert_eq!(
+        stages,
+        vec![
+            WelcomeStage::DecryptGroupInfo,
+            WelcomeStage::BuildTree,
+            WelcomeStage::VerifyTreeHash,
+            WelcomeStage::VerifyTreeHash,
+            WelcomeStage::VerifyTreeHash,
+            WelcomeStage::VerifyParentHashes,
+            WelcomeStage::VerifyParentHashes,
+            WelcomeStage::VerifyParentHashes,
+            WelcomeStage::ComputeGroupState,
+        ]
+    );
+    assert_eq!(
+        progress,
+        (0..9).map(|completed| (completed, 9)).collect::<Vec<_>>()
+    );
+    assert_eq!(group_alice.get_fingerprint(), group_bob.get_fingerprint());
+
+    // The checks of the tree can be deferred
+    let mut welcome_processor = WelcomeProcessor::new(
+        welcome.clone(),
+        Some(nodes.clone()),
+        bob_key_package_bundle.clone(),
+        &[],
+    );
+    welcome_processor.set_defer_validation(true);
+    assert_eq!(welcome_processor.progress(), (0, 3));
+    let mut group_bob = loop {
+        if let Some(group) = welcome_processor.step().unwrap() {
+            break group;
+        }
+    };
+    assert!(group_bob.is_provisional());
+    group_bob.finish_validation().unwrap();
+    assert_eq!(group_alice.get_fingerprint(), group_bob.get_fingerprint());
+
+    // A cancelled join can't be continued
+    let mut welcome_processor = WelcomeProcessor::new(
+        welcome.clone(),
+        Some(nodes.clone()),
+        bob_key_package_bundle.clone(),
+        &[],
+    );
+    welcome_processor.set_nodes_per_step(1);
+    for _ in 0..3 {
+        assert!(welcome_processor.step().unwrap().is_none());
+    }
+    assert_eq!(welcome_processor.stage(), WelcomeStage::VerifyTreeHash);
+    welcome_processor.cancel();
+    assert_eq!(welcome_processor.stage(), WelcomeStage::Done);
+    assert_eq!(
+        welcome_processor.step().err(),
+        Some(WelcomeError::WelcomeProcessed)
+    );
+
+    // Dropping the processor cancels the join, the Welcome can be processed
+    // again
+    let mut welcome_processor = WelcomeProcessor::new(
+        welcome.clone(),
+        Some(nodes.clone()),
+        bob_key_package_bundle.clone(),
+        &[],
+    );
+    assert!(welcome_processor.step().unwrap().is_none());
+    drop(welcome_processor);
+    let group_bob =
+        MlsGroup::new_from_welcome(welcome, Some(nodes), bob_key_package_bundle).unwrap();
+    assert_eq!(group_alice.get_fingerprint(), group_bob.get_fingerprint());
+}
+
+#[test]
+fn signature_key_pins() {
+    use std::sync::{Arc, Mutex};
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Bob is in the group with a second device
+    let bob_phone_identity = Identity::new(ciphersuite, "Bob".into());
+    let bob_phone_credential = Credential::Basic(BasicCredential::from(&bob_phone_identity));
+    let bob_phone_signature_key = bob_phone_identity
+        .get_signature_key_pair()
+        .get_private_key();
+
+    let bob_key_package_bundle = new_kpb(bob_signature_key, &bob_credential);
+    let (mut group_alice, welcome) = MlsGroup::new_with_members(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        new_kpb(alice_signature_key, &alice_credential),
+        alice_signature_key,
+        vec![
+            bob_key_package_bundle.get_key_package().clone(),
+            new_kpb(bob_phone_signature_key, &bob_phone_credential)
+                .get_key_package()
+                .clone(),
+        ],
+    )
+    .unwrap();
+    let mut group_bob = MlsGroup::new_from_welcome(
+        welcome,
+        Some(group_alice.get_tree().public_key_tree()),
+        bob_key_package_bundle,
+    )
+    .unwrap();
+
+    // Alice pins all keys of both members at first sight
+    let events = Arc::new(Mutex::new(vec![]));
+    let hook_events = events.clone();
+    let mut pins = SignatureKeyPins::new();
+    pins.set_event_hook(Box::new(move |event: &PinEvent| {
+        hook_events.lock().unwrap().push(event.clone())
+    }));
+    assert_eq!(pins.check_group(&group_alice).len(), 3);
+    assert_eq!(events.lock().unwrap().len(), 3);
+    let bob_keys = vec![
+        bob_identity
+            .get_signature_key_pair()
+            .get_public_key()
+            .clone(),
+        bob_phone_identity
+            .get_signature_key_pair()
+            .get_public_key()
+            .clone(),
+    ];
+    assert_eq!(pins.get(b"Bob"), &bob_keys[..]);
+
+    // An Update with the same signature key doesn't change the pin
+    let commit_bundle = group_bob
+        .update_self(
+            &[],
+            bob_signature_key,
+            new_kpb(bob_signature_key, &bob_credential),
+        )
+        .unwrap();
+    group_alice
+        .apply_commit(commit_bundle.get_commit().clone(), vec![])
+        .unwrap();
+    group_bob
+        .apply_commit(commit_bundle.get_commit().clone(), vec![])
+        .unwrap();
+    assert!(pins.check_group(&group_alice).is_empty());
+
+    // Bob shows up with a new signature key
+    let new_bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let new_bob_credential = Credential::Basic(BasicCredential::from(&new_bob_identity));
+    let new_bob_signature_key = new_bob_identity.get_signature_key_pair().get_private_key();
+    let commit_bundle = group_bob
+        .update_self(
+            &[],
+            new_bob_signature_key,
+            new_kpb(new_bob_signature_key, &new_bob_credential),
+        )
+        .unwrap();
+    group_alice
+        .apply_commit(commit_bundle.get_commit().clone(), vec![])
+        .unwrap();
+    let changed = PinEvent::SignatureKeyChanged {
+        identity: b"Bob".to_vec(),
+        pinned_keys: bob_keys.clone(),
+        new_key: new_bob_identity
+            .get_signature_key_pair()
+            .get_public_key()
+            .clone(),
+    };
+    assert_eq!(pins.check_group(&group_alice), vec![changed.clone()]);
+    assert_eq!(events.lock().unwrap().last(), Some(&changed));
+
+    // The change is reported until the new key is pinned
+    assert_eq!(pins.check_group(&group_alice), vec![changed]);
+    pins.pin(
+        b"Bob",
+        new_bob_identity
+            .get_signature_key_pair()
+            .get_public_key()
+            .clone(),
+    );
+    assert!(pins.check_group(&group_alice).is_empty());
+
+    // The old keys of Bob stay pinned until they are unpinned
+    assert_eq!(pins.check_credential(&bob_credential), None);
+    assert!(pins.unpin_key(b"Bob", &bob_keys[0]));
+    assert!(!pins.unpin_key(b"Bob", &bob_keys[0]));
+    assert!(matches!(
+        pins.check_credential(&bob_credential),
+        Some(PinEvent::SignatureKeyChanged { .. })
+    ));
+
+    // Pins survive encoding, the hook doesn't
+    let mut decoded_pins =
+        SignatureKeyPins::decode(&mut Cursor::new(&pins.encode_detached().unwrap())).unwrap();
+    assert!(decoded_pins.check_group(&group_alice).is_empty());
+    assert_eq!(decoded_pins.get(b"Alice"), pins.get(b"Alice"));
+}
+
+#[test]
+fn history_keys() {
+    use maelstrom::extensions::TimeProvider;
+    use maelstrom::storage::*;
+
+    struct FixedTime(u64);
+    impl TimeProvider for FixedTime {
+        fn now(&self) -> u64 {
+            self.0
+        }
+    }
+    const DAY: u64 = 24 * 60 * 60;
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let charlie_identity = Identity::new(ciphersuite, "Charlie".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let charlie_credential = Credential::Basic(BasicCredential::from(&charlie_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+    let charlie_signature_key = charlie_identity.get_signature_key_pair().get_private_key();
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Alice records the history keys of three epochs, a day apart
+    let mut alice_store = HistoryKeyStore::new(30 * DAY);
+    let mut group_alice = MlsGroup::new(
+        &[1, 2, 3],
+        ciphersuite,
+        new_kpb(alice_signature_key, &alice_credential),
+    );
+    alice_store.record_epoch(&group_alice, &FixedTime(100 * DAY));
+    group_alice
+        .update_self(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+        )
+        .unwrap();
+    group_alice.merge_pending_commit().unwrap();
+    alice_store.record_epoch(&group_alice, &FixedTime(101 * DAY));
+    let bob_key_package_bundle = new_kpb(bob_signature_key, &bob_credential);
+    let commit_bundle = group_alice
+        .add_members(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+            &[bob_key_package_bundle.get_key_package().clone()],
+        )
+        .unwrap();
+    group_alice.merge_pending_commit().unwrap();
+    alice_store.record_epoch(&group_alice, &FixedTime(102 * DAY));
+    assert_eq!(alice_store.len(), 3);
+
+    // Recording the same epoch again doesn't add a key
+    alice_store.record_epoch(&group_alice, &FixedTime(103 * DAY));
+    assert_eq!(alice_store.len(), 3);
+
+    // Bob receives the keys of the last two days
+    let group_id = group_alice.get_context().group_id.clone();
+    let package = alice_store
+        .wrap_for_member(
+            &group_alice,
+            101 * DAY,
+            bob_key_package_bundle.get_key_package(),
+        )
+        .unwrap();
+    let mut bob_store = HistoryKeyStore::new(30 * DAY);
+    assert_eq!(
+        bob_store
+            .import_wrapped(&package, &bob_key_package_bundle)
+            .unwrap(),
+        2
+    );
+    let bob_keys: Vec<&HistoryKey> = bob_store.keys_of_group(&group_id).collect();
+    let alice_keys: Vec<&HistoryKey> = alice_store.keys_of_group(&group_id).skip(1).collect();
+    assert_eq!(bob_keys, alice_keys);
+    assert!(bob_store.get(&group_id, GroupEpoch(0)).is_none());
+
+    // Bob derives the same key for the epoch he joined in
+    let group_bob = MlsGroup::new_from_welcome(
+        commit_bundle.get_welcome().unwrap().clone(),
+        Some(group_alice.get_tree().public_key_tree()),
+        bob_key_package_bundle.clone(),
+    )
+    .unwrap();
+    bob_store.record_epoch(&group_bob, &FixedTime(102 * DAY));
+    assert_eq!(bob_store.len(), 2);
+
+    // The package can only be imported with the bundle it was wrapped to
+    let charlie_key_package_bundle = new_kpb(charlie_signature_key, &charlie_credential);
+    assert_eq!(
+        HistoryKeyStore::new(DAY)
+            .import_wrapped(&package, &charlie_key_package_bundle)
+            .err(),
+        Some(HistoryError::WrongRecipient)
+    );
+    assert_eq!(
+        HistoryKeyStore::new(DAY)
+            .import_wrapped(&package[..package.len() - 1], &bob_key_package_bundle)
+            .err(),
+        Some(HistoryError::MalformedPackage)
+    );
+    let mut modified = package.clone();
+    modified[0..2].copy_from_slice(&[0x00, 0x04]);
+    assert_eq!(
+        HistoryKeyStore::new(DAY)
+            .import_wrapped(&modified, &bob_key_package_bundle)
+            .err(),
+        Some(HistoryError::MalformedPackage)
+    );
+    let mut modified = package.clone();
+    let last = modified.len() - 1;
+    modified[last] ^= 1;
+    assert_eq!(
+        HistoryKeyStore::new(DAY)
+            .import_wrapped(&modified, &bob_key_package_bundle)
+            .err(),
+        Some(HistoryError::DecryptionFailure)
+    );
+
+    // The store is sealed with a storage key
+    let storage_key = [7u8; 32];
+    let sealed = seal(&ciphersuite, &alice_store, Some(&storage_key)).unwrap();
+    let opened: HistoryKeyStore = open(&sealed, Some(&storage_key)).unwrap();
+    assert_eq!(opened, alice_store);
+
+    // Keys are only retained for the maximum age
+    alice_store.remove_expired(&FixedTime(131 * DAY));
+    assert_eq!(alice_store.len(), 1);
+    assert!(alice_store.get(&group_id, GroupEpoch(2)).is_some());
+    alice_store.remove_group(&group_id);
+    assert!(alice_store.is_empty());
+}
+
+#[test]
+fn add_members_by_id() {
+    use maelstrom::error::MlsError;
+    use std::collections::HashMap;
+
+    struct Directory {
+        publications: HashMap<Vec<u8>, Vec<KeyPackagePublication>>,
+        available: bool,
+    }
+    impl KeyPackageFetcher for Directory {
+        fn fetch<'a>(&'a self, user_id: &'a [u8]) -> KeyPackageFetch<'a> {
+            Box::pin(async move {
+                if !self.available {
+                    return Err(KeyPackageFetchError::DirectoryUnavailable);
+                }
+                self.publications
+                    .get(user_id)
+                    .cloned()
+                    .ok_or(KeyPackageFetchError::UnknownUser)
+            })
+        }
+    }
+
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "alice@example.com".into());
+    let bob_identity = Identity::new(ciphersuite, "bob@example.com".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Bob published a key package, the directory also returns one of Alice
+    // and one that Alice published in Bob's name
+    let bob_key_package_bundle = new_kpb(bob_signature_key, &bob_credential);
+    let publish = |kpb: &KeyPackageBundle, signature_key, user_id: &[u8]| {
+        KeyPackagePublication::new(
+            kpb.get_key_package().clone(),
+            signature_key,
+            user_id,
+            b"phone",
+            u64::MAX,
+            false,
+        )
+    };
+    let mut publications = HashMap::new();
+    publications.insert(
+        b"bob@example.com".to_vec(),
+        vec![
+            publish(
+                &new_kpb(alice_signature_key, &alice_credential),
+                alice_signature_key,
+                b"bob@example.com",
+            ),
+            publish(
+                &new_kpb(alice_signature_key, &alice_credential),
+                alice_signature_key,
+                b"alice@example.com",
+            ),
+            publish(
+                &bob_key_package_bundle,
+                bob_signature_key,
+                b"bob@example.com",
+            ),
+        ],
+    );
+    publications.insert(b"charlie@example.com".to_vec(), vec![]);
+    let mut directory = Directory {
+        publications,
+        available: true,
+    };
+
+    let mut group_alice = MlsGroup::new(
+        &[1, 2, 3],
+        ciphersuite,
+        new_kpb(alice_signature_key, &alice_credential),
+    );
+
+    // Users without a suitable key package and unknown users can't be added
+    fn assert_send<T: Send>(future: T) -> T {
+        future
+    }
+    let mut add = |group: &mut MlsGroup, directory: &Directory, user_id: &[u8]| {
+        block_on(assert_send(group.add_members_by_id(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+            &[user_id],
+            directory,
+        )))
+    };
+    assert_eq!(
+        add(&mut group_alice, &directory, b"charlie@example.com").err(),
+        Some(MlsError::KeyPackageFetch(
+            KeyPackageFetchError::NoSuitableKeyPackage
+        ))
+    );
+    assert_eq!(
+        add(&mut group_alice, &directory, b"dave@example.com").err(),
+        Some(MlsError::KeyPackageFetch(KeyPackageFetchError::UnknownUser))
+    );
+    directory.available = false;
+    assert_eq!(
+        add(&mut group_alice, &directory, b"bob@example.com").err(),
+        Some(MlsError::KeyPackageFetch(
+            KeyPackageFetchError::DirectoryUnavailable
+        ))
+    );
+    directory.available = true;
+
+    // Bob's own key package is used, Alice's ones are ignored
+    let commit_bundle = add(&mut group_alice, &directory, b"bob@example.com").unwrap();
+    group_alice.merge_pending_commit().unwrap();
+    let group_bob = MlsGroup::new_from_welcome(
+        commit_bundle.get_welcome().unwrap().clone(),
+        Some(group_alice.get_tree().public_key_tree()),
+        bob_key_package_bundle,
+    )
+    .unwrap();
+    assert_eq!(group_alice.member_count(), 2);
+    assert_eq!(group_alice.get_fingerprint(), group_bob.get_fingerprint());
+}
+
+#[test]
+fn close_group() {
+    use maelstrom::storage::*;
+
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        ciphersuite,
+        alice_identity,
+        bob_identity,
+        alice_credential,
+        bob_credential,
+        mut group_alice,
+        mut group_bob,
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    let epoch = group_alice.get_context().epoch;
+
+    // Bob sends a message that only arrives after the group was closed
+    let mls_plaintext = group_bob.create_application_message(&[], b"Hello", bob_signature_key);
+    let late_message = group_bob.encrypt(mls_plaintext).unwrap();
+
+    // If the final Commit can't be created, the group is returned
+    group_alice.set_commit_policy(CommitPolicy {
+        max_proposals: Some(0),
+        ..CommitPolicy::default()
+    });
+    let (mut group_alice, error) = group_alice
+        .close(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+        )
+        .err()
+        .unwrap();
+    assert_eq!(error, CreateCommitError::TooManyProposals);
+    assert_eq!(group_alice.get_context().epoch, epoch);
+    group_alice.set_commit_policy(CommitPolicy::default());
+
+    // Alice closes the group, Bob learns that he was removed
+    let (commit_bundle, tombstone) = group_alice
+        .close(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+        )
+        .unwrap();
+    let mut final_epoch = epoch;
+    final_epoch.increment();
+    assert_eq!(tombstone.group_id, group_bob.get_context().group_id);
+    assert_eq!(tombstone.epoch, final_epoch);
+    let commit = commit_bundle.get_commit();
+    assert_eq!(commit.as_commit().unwrap().removes().len(), 1);
+    assert_eq!(
+        group_bob
+            .apply_commit(commit.clone(), commit_bundle.get_proposal_list())
+            .err(),
+        Some(ApplyCommitError::SelfRemoved)
+    );
+
+    // Late messages are recognized, messages of other groups are not
+    assert!(tombstone.is_late_message(&late_message));
+    let group_other = MlsGroup::new(
+        &[4, 5, 6],
+        ciphersuite,
+        new_kpb(bob_signature_key, &bob_credential),
+    );
+    let mls_plaintext = group_other.create_application_message(&[], b"Hello", bob_signature_key);
+    let mut group_other = group_other;
+    assert!(!tombstone.is_late_message(&group_other.encrypt(mls_plaintext).unwrap()));
+
+    // The tombstone can be stored for audit
+    let stored = seal(&ciphersuite, &tombstone, None).unwrap();
+    assert_eq!(open::<GroupTombstone>(&stored, None).unwrap(), tombstone);
+
+    // A group without other members is closed with an update of the own
+    // leaf
+    let (commit_bundle, tombstone) = group_other
+        .close(
+            &[],
+            bob_signature_key,
+            new_kpb(bob_signature_key, &bob_credential),
+        )
+        .unwrap();
+    assert!(commit_bundle
+        .get_commit()
+        .as_commit()
+        .unwrap()
+        .removes()
+        .is_empty());
+    assert_eq!(tombstone.epoch, GroupEpoch(1));
+}
+
+#[test]
+fn send_queue() {
+    use maelstrom::codec::*;
+
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        alice_identity,
+        mut group_alice,
+        mut group_bob,
+        ..
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+
+    // Alice encrypts two messages, sending them fails
+    let mut tokens = vec![];
+    for message in &[b"first", b"other"] {
+        let mls_plaintext =
+            group_alice.create_application_message(&[], *message, alice_signature_key);
+        let (_, token) = group_alice.encrypt_and_stash(mls_plaintext).unwrap();
+        tokens.push(token);
+    }
+    assert!(tokens[0] < tokens[1]);
+    assert_eq!(group_alice.stashed_tokens(), tokens);
+
+    // The stashed messages survive a restart
+    let encoded = group_alice.encode_detached().unwrap();
+    let mut group_alice = MlsGroup::decode(&mut Cursor::new(&encoded)).unwrap();
+    assert_eq!(group_alice.stashed_tokens(), tokens);
+
+    // A retry sends the same ciphertext without advancing the ratchet
+    let first = group_alice.stashed_message(tokens[0]).unwrap().clone();
+    let retry = group_alice.stashed_message(tokens[0]).unwrap().clone();
+    assert_eq!(
+        first.encode_detached().unwrap(),
+        retry.encode_detached().unwrap()
+    );
+    assert_eq!(group_alice.encode_detached().unwrap(), encoded);
+
+    // Bob receives both messages in order
+    for (token, expected) in tokens.iter().zip(&[b"first", b"other"]) {
+        let mls_ciphertext = group_alice.stashed_message(*token).unwrap().clone();
+        let mls_plaintext = group_bob.decrypt(mls_ciphertext).unwrap().into_plaintext();
+        assert_eq!(
+            mls_plaintext.as_application().unwrap().data,
+            expected.to_vec()
+        );
+    }
+
+    // Delivered messages are removed from the queue
+    assert!(group_alice.confirm_sent(tokens[0]).is_some());
+    assert!(group_alice.confirm_sent(tokens[0]).is_none());
+    assert_eq!(group_alice.stashed_tokens(), vec![tokens[1]]);
+    assert!(group_alice.confirm_sent(tokens[1]).is_some());
+    assert!(group_alice.stashed_tokens().is_empty());
+}
+
+#[test]
+fn sequence_numbers() {
+    use maelstrom::codec::*;
+
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        ciphersuite,
+        alice_identity,
+        bob_identity,
+        alice_credential,
+        mut group_alice,
+        mut group_bob,
+        ..
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+    // Sequenced messages can only be created once sequence numbers are
+    // enabled
+    assert_eq!(
+        group_alice
+            .create_sequenced_application_message(&[], b"Hello", alice_signature_key)
+            .err(),
+        Some(EncryptionError::SequenceNumbersDisabled)
+    );
+    group_alice.set_sequence_numbers(true);
+    group_bob.set_sequence_numbers(true);
+    assert!(group_bob.sequence_numbers_enabled());
+
+    let mut ciphertexts = vec![];
+    for _ in 0..3 {
+        let mls_plaintext = group_alice
+            .create_sequenced_application_message(&[7], b"Hello", alice_signature_key)
+            .unwrap();
+        ciphertexts.push(group_alice.encrypt(mls_plaintext).unwrap());
+    }
+
+    // The server delays the second message
+    let processed_message = group_bob.decrypt(ciphertexts[0].clone()).unwrap();
+    assert_eq!(processed_message.sequence_number(), Some(1));
+    assert_eq!(processed_message.missed_messages(), 0);
+    assert_eq!(processed_message.aad(), &[0, 0, 0, 0, 0, 0, 0, 1, 7]);
+    assert_eq!(
+        processed_message
+            .authenticated_content()
+            .authenticated_data(),
+        &[7]
+    );
+    let processed_message = group_bob.decrypt(ciphertexts[2].clone()).unwrap();
+    assert_eq!(processed_message.sequence_number(), Some(3));
+    assert_eq!(processed_message.missed_messages(), 1);
+    assert_eq!(
+        group_bob.decrypt(ciphertexts[1].clone()).err(),
+        Some(DecryptionError::StaleSequenceNumber)
+    );
+
+    // Messages without a sequence number are rejected
+    let mls_plaintext = group_alice.create_application_message(&[], b"Hello", alice_signature_key);
+    assert_eq!(
+        group_bob
+            .decrypt(group_alice.encrypt(mls_plaintext).unwrap())
+            .err(),
+        Some(DecryptionError::MissingSequenceNumber)
+    );
+
+    // The sequence numbers continue in the next epoch and are stored with the
+    // group
+    let commit_bundle = group_alice
+        .update_self(
+            &[],
+            alice_signature_key,
+            KeyPackageBundle::new(
+                &ciphersuite,
+                alice_signature_key,
+                alice_credential.clone(),
+                None,
+            ),
+        )
+        .unwrap();
+    group_alice.merge_pending_commit().unwrap();
+    group_bob
+        .apply_commit(commit_bundle.get_commit().clone(), vec![])
+        .unwrap();
+    let encoded = group_bob.encode_detached().unwrap();
+    let mut group_bob = MlsGroup::decode(&mut Cursor::new(&encoded)).unwrap();
+    let mls_plaintext = group_alice
+        .create_sequenced_application_message(&[], b"Hello", alice_signature_key)
+        .unwrap();
+    let processed_message = group_bob
+        .decrypt(group_alice.encrypt(mls_plaintext).unwrap())
+        .unwrap();
+    assert_eq!(processed_message.sequence_number(), Some(4));
+    assert_eq!(processed_message.missed_messages(), 0);
+
+    // Bob sends two messages, then his leaf is reused by Charlie
+    group_alice.set_sequence_numbers(true);
+    let mut bob_index = None;
+    for _ in 0..2 {
+        let mls_plaintext = group_bob
+            .create_sequenced_application_message(&[], b"Hello", bob_signature_key)
+            .unwrap();
+        let processed_message = group_alice
+            .decrypt(group_bob.encrypt(mls_plaintext).unwrap())
+            .unwrap();
+        bob_index = Some(processed_message.sender_index());
+    }
+    let bob_index = bob_index.unwrap();
+    let new_alice_kpb = || {
+        KeyPackageBundle::new(
+            &ciphersuite,
+            alice_signature_key,
+            alice_credential.clone(),
+            None,
+        )
+    };
+    group_alice
+        .remove_members(&[], alice_signature_key, new_alice_kpb(), &[bob_index])
+        .unwrap();
+    group_alice.merge_pending_commit().unwrap();
+    let charlie_identity = Identity::new(ciphersuite, "Charlie".into());
+    let charlie_signature_key = charlie_identity.get_signature_key_pair().get_private_key();
+    let charlie_key_package_bundle = KeyPackageBundle::new(
+        &ciphersuite,
+        charlie_signature_key,
+        Credential::Basic(BasicCredential::from(&charlie_identity)),
+        None,
+    );
+    let commit_bundle = group_alice
+        .add_members(
+            &[],
+            alice_signature_key,
+            new_alice_kpb(),
+            &[charlie_key_package_bundle.get_key_package().clone()],
+        )
+        .unwrap();
+    let membership_changes = group_alice.merge_pending_commit().unwrap();
+    assert_eq!(membership_changes.adds[0].index, bob_index);
+    let mut group_charlie = MlsGroup::new_from_welcome(
+        commit_bundle.get_welcome().unwrap().clone(),
+        Some(group_alice.get_tree().public_key_tree()),
+        charlie_key_package_bundle,
+    )
+    .unwrap();
+
+    // Charlie's sequence numbers start at 1 on the reused leaf
+    group_charlie.set_sequence_numbers(true);
+    let mls_plaintext = group_charlie
+        .create_sequenced_application_message(&[], b"Hello", charlie_signature_key)
+        .unwrap();
+    let processed_message = group_alice
+        .decrypt(group_charlie.encrypt(mls_plaintext).unwrap())
+        .unwrap();
+    assert_eq!(processed_message.sequence_number(), Some(1));
+    assert_eq!(processed_message.missed_messages(), 0);
+}
+
+#[test]
+fn payload_signatures() {
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        ciphersuite,
+        alice_identity,
+        alice_credential,
+        mut group_alice,
+        mut group_bob,
+        ..
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let group_other = MlsGroup::new(
+        &[4, 5, 6],
+        ciphersuite,
+        KeyPackageBundle::new(&ciphersuite, alice_signature_key, alice_credential, None),
+    );
+
+    // Bob learns Alice's leaf index from one of her messages
+    let mls_plaintext = group_alice.create_application_message(&[], b"Hello", alice_signature_key);
+    let mls_ciphertext = group_alice.encrypt(mls_plaintext).unwrap();
+    let alice_index = group_bob.decrypt(mls_ciphertext).unwrap().sender_index();
+
+    let payload = b"call offer";
+    let signature = group_alice.sign_payload(payload, alice_signature_key);
+    let credential = group_bob
+        .verify_member_payload(alice_index, payload, &signature)
+        .unwrap();
+    let Credential::Basic(basic_credential) = credential;
+    assert_eq!(basic_credential.identity, b"Alice");
+
+    // The signature doesn't verify for another payload, signer or group
+    assert_eq!(
+        group_bob
+            .verify_member_payload(alice_index, b"other offer", &signature)
+            .err(),
+        Some(PayloadSignatureError::InvalidSignature)
+    );
+    assert_eq!(
+        group_bob
+            .verify_member_payload(1u32.into(), payload, &signature)
+            .err(),
+        Some(PayloadSignatureError::InvalidSignature)
+    );
+    assert_eq!(
+        group_other
+            .verify_member_payload(alice_index, payload, &signature)
+            .err(),
+        Some(PayloadSignatureError::InvalidSignature)
+    );
+    assert_eq!(
+        group_bob
+            .verify_member_payload(5u32.into(), payload, &signature)
+            .err(),
+        Some(PayloadSignatureError::UnknownMember)
+    );
+    assert_eq!(
+        group_bob
+            .verify_member_payload(u32::MAX.into(), payload, &signature)
+            .err(),
+        Some(PayloadSignatureError::UnknownMember)
+    );
+}
+
+#[test]
+fn group_diagnostics() {
+    use maelstrom::diagnostics::*;
+
+    let TwoMemberGroup {
+        alice_identity,
+        mut group_alice,
+        ..
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let mls_plaintext = group_alice.create_application_message(&[], b"Hello", alice_signature_key);
+    group_alice.encrypt(mls_plaintext).unwrap();
+
+    let diagnostics = group_alice.diagnostics();
+    assert_eq!(
+        diagnostics.ciphersuite,
+        CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519
+    );
+    assert_eq!(diagnostics.epoch.group_id, vec![1, 2, 3, 4]);
+    assert_eq!(diagnostics.epoch.epoch, group_alice.get_context().epoch.0);
+    assert_eq!(diagnostics.epoch.member_count, 2);
+    assert_eq!(diagnostics.own_leaf, 0);
+    assert_eq!(diagnostics.send_counter, 1);
+    assert_eq!(diagnostics.ratchets.len(), 1);
+    assert!(!diagnostics.observer);
+
+    let kinds: Vec<NodeKind> = diagnostics
+        .tree
+        .nodes
+        .iter()
+        .map(|node| node.kind)
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![NodeKind::Leaf, NodeKind::Parent, NodeKind::Leaf]
+    );
+    assert_eq!(diagnostics.tree.nodes[0].identity, b"Alice");
+    assert_eq!(diagnostics.tree.nodes[2].identity, b"Bob");
+    assert_eq!(diagnostics.tree.nodes[1].level, 1);
+
+    // The report contains public values
+    let report = diagnostics.to_string();
+    assert!(report.contains(&bytes_to_hex(&diagnostics.epoch.tree_hash)));
+    assert!(report.contains(&bytes_to_hex(&diagnostics.tree.nodes[0].public_key)));
+    assert_eq!(bytes_to_hex(&[0x0a, 0xff]), "0AFF");
+}
+
+#[test]
+fn add_imported_key_package() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let new_kpb = |identity: &Identity| {
+        KeyPackageBundle::new(
+            &ciphersuite,
+            identity.get_signature_key_pair().get_private_key(),
+            Credential::Basic(BasicCredential::from(identity)),
+            None,
+        )
+    };
+
+    // Bob publishes his key package, Alice only sees the bytes
+    let bob_key_package_bundle = new_kpb(&bob_identity);
+    let published = bob_key_package_bundle.get_key_package().to_bytes().unwrap();
+    let bob_key_package = KeyPackage::from_bytes(&published).unwrap();
+
+    let (group_alice, welcome) = MlsGroup::new_with_members(
+        &[1, 2, 3],
+        ciphersuite,
+        new_kpb(&alice_identity),
+        alice_signature_key,
+        vec![bob_key_package],
+    )
+    .unwrap();
+    let group_bob = MlsGroup::new_from_welcome(
+        welcome,
+        Some(group_alice.get_tree().public_key_tree()),
+        bob_key_package_bundle,
+    )
+    .unwrap();
+    assert_eq!(group_alice.get_fingerprint(), group_bob.get_fingerprint());
+}
@@ -0,0 +1,310 @@
+use maelstrom::ciphersuite::*;
+use maelstrom::codec::*;
+use maelstrom::creds::*;
+use maelstrom::group::*;
+use maelstrom::key_packages::*;
+use maelstrom::storage::*;
+
+mod test_utils;
+use test_utils::*;
+
+#[test]
+fn key_material_envelopes() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let identity = Identity::new(ciphersuite, "Alice".into());
+    let signature_keypair = identity.get_signature_key_pair();
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let kpb = KeyPackageBundle::new(
+        &ciphersuite,
+        signature_keypair.get_private_key(),
+        credential,
+        None,
+    );
+    let storage_key = [7u8; 32];
+
+    // Bundles survive a round trip with and without encryption
+    for key in &[None, Some(&storage_key[..])] {
+        let envelope = seal(&ciphersuite, &kpb, *key).unwrap();
+        let opened: KeyPackageBundle = open(&envelope, *key).unwrap();
+        assert_eq!(opened.get_key_package(), kpb.get_key_package());
+        assert_eq!(
+            opened.encode_detached().unwrap(),
+            kpb.encode_detached().unwrap()
+        );
+    }
+    let envelope = seal(&ciphersuite, signature_keypair, Some(&storage_key)).unwrap();
+    let opened: SignatureKeypair = open(&envelope, Some(&storage_key)).unwrap();
+    assert_eq!(
+        opened.encode_detached().unwrap(),
+        signature_keypair.encode_detached().unwrap()
+    );
+
+    // The private key is not readable from an encrypted envelope
+    let private_key = kpb.get_private_key().encode_detached().unwrap();
+    let envelope = seal(&ciphersuite, &kpb, Some(&storage_key)).unwrap();
+    assert!(!envelope
+        .windows(private_key.len())
+        .any(|window| window == &private_key[..]));
+
+    // Wrong keys, types and modifications are detected
+    assert_eq!(
+        open::<KeyPackageBundle>(&envelope, Some(&[8u8; 32])).err(),
+        Some(StorageError::IntegrityFailure)
+    );
+    assert_eq!(
+        open::<KeyPackageBundle>(&envelope, None).err(),
+        Some(StorageError::MissingStorageKey)
+    );
+    assert_eq!(
+        open::<SignaturePrivateKey>(&envelope, Some(&storage_key)).err(),
+        Some(StorageError::WrongType)
+    );
+    let mut modified = envelope.clone();
+    let last = modified.len() - 1;
+    modified[last] ^= 1;
+    assert_eq!(
+        open::<KeyPackageBundle>(&modified, Some(&storage_key)).err(),
+        Some(StorageError::IntegrityFailure)
+    );
+    let mut modified = seal(&ciphersuite, &kpb, None).unwrap();
+    modified[10] ^= 1;
+    assert!(open::<KeyPackageBundle>(&modified, None).is_err());
+    let mut modified = envelope.clone();
+    modified[0] = STORAGE_VERSION + 1;
+    assert_eq!(
+        open::<KeyPackageBundle>(&modified, Some(&storage_key)).err(),
+        Some(StorageError::UnsupportedVersion)
+    );
+    for ciphersuite in &[[0x00, 0x04], [0xff, 0xff]] {
+        let mut modified = envelope.clone();
+        modified[2..4].copy_from_slice(ciphersuite);
+        assert_eq!(
+            open::<KeyPackageBundle>(&modified, Some(&storage_key)).err(),
+            Some(StorageError::UnsupportedCiphersuite)
+        );
+    }
+}
+
+#[test]
+fn encrypted_group_state() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let identity = Identity::new(ciphersuite, "Alice".into());
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let kpb = KeyPackageBundle::new(
+        &ciphersuite,
+        identity.get_signature_key_pair().get_private_key(),
+        credential,
+        None,
+    );
+    let mut group = MlsGroup::new(&[1, 2, 3], ciphersuite, kpb);
+    let storage_key = [7u8; 32];
+
+    // The loaded group is in the same state
+    let stored = group.save_encrypted(&storage_key).unwrap();
+    let loaded = MlsGroup::load_encrypted(&stored, &storage_key).unwrap();
+    assert_eq!(loaded.get_fingerprint(), group.get_fingerprint());
+    assert_eq!(
+        loaded.export_secret("test", 32),
+        group.export_secret("test", 32)
+    );
+
+    // Other versions of the state and invalid flags are rejected
+    let encoded = group.encode_detached().unwrap();
+    assert!(MlsGroup::decode(&mut Cursor::new(&encoded)).is_ok());
+    let mut modified = encoded.clone();
+    modified[0] += 1;
+    assert!(MlsGroup::decode(&mut Cursor::new(&modified)).is_err());
+    let mut modified = encoded;
+    // The sequence numbers flag is followed by the empty map of pending
+    // bundles
+    let flag = modified.len() - 5;
+    assert_eq!(modified[flag], 0);
+    modified[flag] = 2;
+    assert!(MlsGroup::decode(&mut Cursor::new(&modified)).is_err());
+
+    // The state is not readable without the key
+    let secret = group.export_secret("test", 32);
+    assert!(!stored
+        .windows(secret.len())
+        .any(|window| window == &secret[..]));
+    assert_eq!(
+        MlsGroup::load_encrypted(&stored, &[8u8; 32]).err(),
+        Some(StorageError::IntegrityFailure)
+    );
+    let mut modified = stored.clone();
+    let last = modified.len() - 1;
+    modified[last] ^= 1;
+    assert_eq!(
+        MlsGroup::load_encrypted(&modified, &storage_key).err(),
+        Some(StorageError::IntegrityFailure)
+    );
+
+    // The bundle of a pending own Update is stored with the group
+    let signature_key = identity.get_signature_key_pair().get_private_key();
+    let new_kpb = || {
+        KeyPackageBundle::new(
+            &ciphersuite,
+            signature_key,
+            Credential::Basic(BasicCredential::from(&identity)),
+            None,
+        )
+    };
+    let (update_plaintext, update_proposal) = group
+        .create_update_proposal(&[], signature_key, new_kpb())
+        .unwrap();
+    let proposals = vec![(update_plaintext.sender(), update_proposal)];
+    let stored_with_update = group.save_encrypted(&storage_key).unwrap();
+    let mut loaded = MlsGroup::load_encrypted(&stored_with_update, &storage_key).unwrap();
+    assert_eq!(loaded.pending_key_package_bundles(), 1);
+    assert!(loaded
+        .create_commit(&[], signature_key, new_kpb(), proposals.clone(), false)
+        .is_ok());
+
+    // Without the bundle, the Update can't be committed
+    let mut loaded = MlsGroup::load_encrypted(&stored, &storage_key).unwrap();
+    assert_eq!(
+        loaded
+            .create_commit(&[], signature_key, new_kpb(), proposals, false)
+            .err(),
+        Some(CreateCommitError::MissingOwnKeyPackageBundle)
+    );
+}
+
+#[test]
+fn group_transfer_package() {
+    // Alice creates a group with Bob
+    let TwoMemberGroup {
+        ciphersuite,
+        alice_identity,
+        bob_identity,
+        alice_credential,
+        bob_credential,
+        mut group_alice,
+        mut group_bob,
+    } = TwoMemberGroup::new();
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+    let new_kpb = |signature_key, credential: &Credential| {
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential.clone(), None)
+    };
+
+    // Alice transfers the group to her new device while her Update is pending
+    group_alice
+        .create_update_proposal(
+            &[],
+            alice_signature_key,
+            new_kpb(alice_signature_key, &alice_credential),
+        )
+        .unwrap();
+    let transfer_key = [9u8; 32];
+    let package = group_alice.export_transfer_package(&transfer_key).unwrap();
+    let mut group_device = MlsGroup::import_transfer_package(&package, &transfer_key).unwrap();
+    assert!(group_device.is_observer());
+    assert!(!group_alice.is_observer());
+    assert_eq!(
+        group_device.get_fingerprint(),
+        group_alice.get_fingerprint()
+    );
+
+    // The package doesn't contain the secrets of Alice's leaf
+    assert_eq!(group_device.pending_key_package_bundles(), 0);
+    assert_ne!(
+        group_device.get_tree().own_leaf.kpb.get_private_key(),
+        group_alice.get_tree().own_leaf.kpb.get_private_key()
+    );
+
+    // The new device follows Bob's Commit that only adds Charlie
+    let charlie_identity = Identity::new(ciphersuite, "Charlie".into());
+    let charlie_credential = Credential::Basic(BasicCredential::from(&charlie_identity));
+    let charlie_signature_key = charlie_identity.get_signature_key_pair().get_private_key();
+    let commit_bundle = group_bob
+        .add_members(
+            &[],
+            bob_signature_key,
+            new_kpb(bob_signature_key, &bob_credential),
+            &[new_kpb(charlie_signature_key, &charlie_credential)
+                .get_key_package()
+                .clone()],
+        )
+        .unwrap();
+    let commit = commit_bundle.get_commit().clone();
+    assert!(commit.as_commit().unwrap().path().is_none());
+    for group in [&mut group_bob, &mut group_alice, &mut group_device].iter_mut() {
+        group.apply_commit(commit.clone(), vec![]).unwrap();
+    }
+    assert_eq!(group_device.get_fingerprint(), group_bob.get_fingerprint());
+
+    // The new device can decrypt, but neither send, propose nor commit
+    let mls_plaintext = group_bob.create_application_message(&[], b"Hello", bob_signature_key);
+    let mls_ciphertext = group_bob.encrypt(mls_plaintext).unwrap();
+    let processed_message = group_device.decrypt(mls_ciphertext).unwrap();
+    assert_eq!(
+        processed_message
+            .into_plaintext()
+            .as_application()
+            .unwrap()
+            .data,
+        b"Hello"
+    );
+    let mls_plaintext = group_device.create_application_message(&[], b"Hi", alice_signature_key);
+    assert_eq!(
+        group_device.encrypt(mls_plaintext).err(),
+        Some(EncryptionError::ObserverGroup)
+    );
+    assert_eq!(
+        group_device
+            .create_update_proposal(
+                &[],
+                alice_signature_key,
+                new_kpb(alice_signature_key, &alice_credential),
+            )
+            .err(),
+        Some(CreateProposalError::ObserverGroup)
+    );
+    assert_eq!(group_device.pending_key_package_bundles(), 0);
+    assert_eq!(
+        group_device
+            .update_self(
+                &[],
+                alice_signature_key,
+                new_kpb(alice_signature_key, &alice_credential),
+            )
+            .err(),
+        Some(CreateCommitError::ObserverGroup)
+    );
+
+    // The path of a Commit can't be decrypted by the new device
+    let commit_bundle = group_bob
+        .update_self(
+            &[],
+            bob_signature_key,
+            new_kpb(bob_signature_key, &bob_credential),
+        )
+        .unwrap();
+    let commit = commit_bundle.get_commit().clone();
+    group_alice.apply_commit(commit.clone(), vec![]).unwrap();
+    assert_eq!(
+        group_device.apply_commit(commit, vec![]).err(),
+        Some(ApplyCommitError::ObserverGroup)
+    );
+
+    // The observer flag is kept when the new device stores the group
+    let storage_key = [7u8; 32];
+    let stored = group_device.save_encrypted(&storage_key).unwrap();
+    assert!(MlsGroup::load_encrypted(&stored, &storage_key)
+        .unwrap()
+        .is_observer());
+
+    // The package can only be imported with the transfer key and is not a
+    // stored group
+    assert_eq!(
+        MlsGroup::import_transfer_package(&package, &[8u8; 32]).err(),
+        Some(StorageError::IntegrityFailure)
+    );
+    assert_eq!(
+        MlsGroup::load_encrypted(&package, &transfer_key).err(),
+        Some(StorageError::WrongType)
+    );
+}
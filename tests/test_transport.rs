@@ -0,0 +1,251 @@
+#![cfg(feature = "transport")]
+
+use maelstrom::ciphersuite::*;
+use maelstrom::codec::*;
+use maelstrom::creds::*;
+use maelstrom::group::*;
+use maelstrom::key_packages::*;
+use maelstrom::transport::*;
+
+use std::collections::VecDeque;
+use std::io;
+
+#[test]
+fn stream_transport() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let alice_identity = Identity::new(ciphersuite, "Alice".into());
+    let bob_identity = Identity::new(ciphersuite, "Bob".into());
+    let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+    let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+    let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+    let bob_key_package_bundle = KeyPackageBundle::new(
+        &ciphersuite,
+        bob_identity.get_signature_key_pair().get_private_key(),
+        bob_credential,
+        None,
+    );
+
+    let (mut group_alice, welcome) = MlsGroup::new_with_members(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        KeyPackageBundle::new(
+            &ciphersuite,
+            alice_signature_key,
+            alice_credential.clone(),
+            None,
+        ),
+        alice_signature_key,
+        vec![bob_key_package_bundle.get_key_package().clone()],
+    )
+    .unwrap();
+    let mls_plaintext =
+        group_alice.create_application_message(&[], &[1, 2, 3], alice_signature_key);
+    let mls_ciphertext = group_alice.encrypt(mls_plaintext.clone()).unwrap();
+    let (add_plaintext, _) = group_alice
+        .create_add_proposal(
+            &[],
+            alice_signature_key,
+            KeyPackageBundle::new(
+                &ciphersuite,
+                alice_signature_key,
+                alice_credential.clone(),
+                None,
+            )
+            .get_key_package()
+            .clone(),
+        )
+        .unwrap();
+    let (remove_plaintext, remove_proposal) = group_alice
+        .create_remove_proposal(&[], alice_signature_key, 1u32.into())
+        .unwrap();
+    let commit_plaintext = group_alice
+        .create_commit(
+            &[],
+            alice_signature_key,
+            KeyPackageBundle::new(&ciphersuite, alice_signature_key, alice_credential, None),
+            vec![(remove_plaintext.sender(), remove_proposal)],
+            true,
+        )
+        .unwrap()
+        .get_commit()
+        .clone();
+
+    // All message types survive the framing
+    let messages = vec![
+        MLSMessage::Welcome {
+            welcome,
+            ratchet_tree: Some(group_alice.get_tree().public_key_tree()),
+        },
+        MLSMessage::Ciphertext(mls_ciphertext),
+        MLSMessage::Plaintext(mls_plaintext.clone()),
+        MLSMessage::Plaintext(add_plaintext.clone()),
+        MLSMessage::Plaintext(remove_plaintext.clone()),
+        MLSMessage::Plaintext(commit_plaintext.clone()),
+    ];
+    let frames: Vec<Vec<u8>> = messages
+        .iter()
+        .map(|message| message.encode_detached().unwrap())
+        .collect();
+    let mut transport = StreamTransport::new(io::Cursor::new(vec![]));
+    send_loop(&mut transport, messages).unwrap();
+    let mut stream = transport.into_inner();
+    stream.set_position(0);
+    let mut transport = StreamTransport::new(stream);
+    let mut received = vec![];
+    receive_loop(&mut transport, |message| {
+        received.push(message);
+        true
+    })
+    .unwrap();
+    assert_eq!(received.len(), 6);
+
+    let mut group_bob = match received.remove(0) {
+        MLSMessage::Welcome {
+            welcome,
+            ratchet_tree,
+        } => MlsGroup::new_from_welcome(welcome, ratchet_tree, bob_key_package_bundle).unwrap(),
+        _ => panic!("Expected a Welcome"),
+    };
+    match received.remove(0) {
+        MLSMessage::Ciphertext(mls_ciphertext) => {
+            let processed_message = group_bob.decrypt(mls_ciphertext).unwrap();
+            assert_eq!(processed_message.application_data(), Some(&[1, 2, 3][..]));
+        }
+        _ => panic!("Expected a ciphertext"),
+    }
+    for expected_plaintext in &[
+        mls_plaintext.clone(),
+        add_plaintext,
+        remove_plaintext,
+        commit_plaintext,
+    ] {
+        match received.remove(0) {
+            MLSMessage::Plaintext(received_plaintext) => {
+                assert_eq!(&received_plaintext, expected_plaintext)
+            }
+            _ => panic!("Expected a plaintext"),
+        }
+    }
+
+    // Oversized frames are rejected before they are read
+    let oversized = ((MAX_MESSAGE_SIZE + 1) as u32).to_be_bytes().to_vec();
+    let mut transport = StreamTransport::new(io::Cursor::new(oversized));
+    assert_eq!(
+        transport.receive().err(),
+        Some(TransportError::MessageTooLarge)
+    );
+
+    // The stream ending inside the length prefix or the message is an error,
+    // not a closed connection
+    let frame = MLSMessage::Plaintext(mls_plaintext)
+        .encode_detached()
+        .unwrap();
+    let mut framed = (frame.len() as u32).to_be_bytes().to_vec();
+    framed.extend_from_slice(&frame);
+    for truncated_length in &[2, 4, framed.len() - 1] {
+        let mut transport =
+            StreamTransport::new(io::Cursor::new(framed[..*truncated_length].to_vec()));
+        assert_eq!(transport.receive().err(), Some(TransportError::Truncated));
+        let mut transport =
+            StreamTransport::new(io::Cursor::new(framed[..*truncated_length].to_vec()));
+        assert_eq!(
+            receive_loop(&mut transport, |_| true),
+            Err(TransportError::Truncated)
+        );
+    }
+    let mut transport = StreamTransport::new(io::Cursor::new(vec![]));
+    assert_eq!(transport.receive().err(), Some(TransportError::Closed));
+
+    // Complete frames with a truncated or malformed message of any type are
+    // rejected
+    let receive_frame = |frame: &[u8]| {
+        let mut framed = (frame.len() as u32).to_be_bytes().to_vec();
+        framed.extend_from_slice(frame);
+        StreamTransport::new(io::Cursor::new(framed)).receive()
+    };
+    for frame in frames.iter() {
+        for truncated_length in 1..frame.len() {
+            assert_eq!(
+                receive_frame(&frame[..truncated_length]).err(),
+                Some(TransportError::Codec(CodecError::DecodingError))
+            );
+        }
+        let mut garbage = vec![frame[0]];
+        garbage.extend_from_slice(&[0xff; 64]);
+        assert_eq!(
+            receive_frame(&garbage).err(),
+            Some(TransportError::Codec(CodecError::DecodingError))
+        );
+    }
+}
+
+/// WebSocket connection that delivers the frames it sent to itself
+#[derive(Default)]
+struct LoopbackWebSocket {
+    frames: VecDeque<Vec<u8>>,
+}
+
+impl WebSocketIo for LoopbackWebSocket {
+    fn send_binary(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.frames.push_back(frame.to_vec());
+        Ok(())
+    }
+    fn receive_binary(&mut self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.frames.pop_front())
+    }
+}
+
+#[test]
+fn websocket_transport() {
+    let ciphersuite =
+        Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+    let identity = Identity::new(ciphersuite, "Alice".into());
+    let signature_key = identity.get_signature_key_pair().get_private_key();
+    let credential = Credential::Basic(BasicCredential::from(&identity));
+    let group = MlsGroup::new(
+        &[1, 2, 3, 4],
+        ciphersuite,
+        KeyPackageBundle::new(&ciphersuite, signature_key, credential, None),
+    );
+    let mls_plaintext = group.create_application_message(&[], &[1, 2, 3], signature_key);
+
+    // Every message is sent as one frame
+    let mut transport = WebSocketTransport::new(LoopbackWebSocket::default());
+    transport
+        .send(&MLSMessage::Plaintext(mls_plaintext.clone()))
+        .unwrap();
+    let mut connection = transport.into_inner();
+    assert_eq!(connection.frames.len(), 1);
+    assert_eq!(
+        connection.frames[0],
+        MLSMessage::Plaintext(mls_plaintext.clone())
+            .encode_detached()
+            .unwrap()
+    );
+
+    // Frames with trailing data, unknown message types or Welcomes with an
+    // unknown protocol version or ciphersuite are rejected
+    let mut frame = connection.frames[0].clone();
+    frame.push(0);
+    connection.frames.push_back(frame);
+    connection.frames.push_back(vec![0xff]);
+    let empty_welcome = [0, 0, 0, 0, 0, 0, 0, 0, 0];
+    for header in &[[3, 1, 0x00, 0x01], [3, 0, 0x00, 0x04], [3, 0, 0xff, 0xff]] {
+        let mut frame = header.to_vec();
+        frame.extend_from_slice(&empty_welcome);
+        connection.frames.push_back(frame);
+    }
+    let mut transport = WebSocketTransport::new(connection);
+    match transport.receive().unwrap() {
+        MLSMessage::Plaintext(received_plaintext) => assert_eq!(received_plaintext, mls_plaintext),
+        _ => panic!("Expected a plaintext"),
+    }
+    for _ in 0..5 {
+        assert_eq!(
+            transport.receive().err(),
+            Some(TransportError::Codec(CodecError::DecodingError))
+        );
+    }
+    assert_eq!(transport.receive().err(), Some(TransportError::Closed));
+}
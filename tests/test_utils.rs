@@ -1,8 +1,15 @@
 #![allow(dead_code)]
 
-use rand::rngs::OsRng;
-use rand::RngCore;
 use evercrypt::prelude::*;
+use maelstrom::ciphersuite::*;
+use maelstrom::creds::*;
+use maelstrom::framing::*;
+use maelstrom::group::*;
+use maelstrom::key_packages::*;
+use maelstrom::test_utils::DeliveryOptions;
+use rand::rngs::{OsRng, StdRng};
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore, SeedableRng};
 
 pub(crate) fn random_usize() -> usize {
     OsRng.next_u64() as usize
@@ -20,3 +27,548 @@ pub(crate) fn hex_to_bytes(hex: &str) -> Vec<u8> {
     }
     bytes
 }
+
+/// Application messages are delivered in chunks of at most this many
+/// messages, which are shuffled. This keeps reordering within the
+/// out-of-order tolerance of the sender ratchets.
+const REORDER_WINDOW: usize = 3;
+
+/// A client of a `GroupSimulation`. Clients that are not a member of the
+/// group have no `MlsGroup`.
+pub(crate) struct SimulatedClient {
+    pub(crate) identity: Identity,
+    pub(crate) credential: Credential,
+    pub(crate) group: Option<MlsGroup>,
+    leaf_index: Option<u32>,
+    /// The `KeyPackageBundle` of a pending Add proposal for this client
+    joiner_kpb: Option<KeyPackageBundle>,
+    /// Application messages that were sent but not yet delivered, with the
+    /// sending client and the expected payload
+    inbox: Vec<(MLSCiphertext, usize, Vec<u8>)>,
+}
+
+impl SimulatedClient {
+    fn signature_key(&self) -> &SignaturePrivateKey {
+        self.identity.get_signature_key_pair().get_private_key()
+    }
+}
+
+/// A proposal that was sent to the group and not yet committed
+struct PendingProposal {
+    proposer: usize,
+    mls_plaintext: MLSPlaintext,
+    /// The client that is added by an Add proposal
+    joiner: Option<usize>,
+    /// The client that is removed by a Remove proposal
+    removed: Option<usize>,
+}
+
+/// Counters of what happened during a `GroupSimulation`
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct SimulationStats {
+    pub(crate) proposals: usize,
+    pub(crate) commits: usize,
+    pub(crate) joins: usize,
+    pub(crate) removals: usize,
+    pub(crate) messages_sent: usize,
+    pub(crate) messages_delivered: usize,
+    pub(crate) messages_dropped: usize,
+}
+
+/// Simulates a group of in-memory clients. Each step randomly creates a
+/// proposal, a Commit or an application message, or delivers application
+/// messages out of order, possibly dropping some of them. Handshake messages
+/// are delivered to all members in order, as a delivery service would. After
+/// every Commit all members have to agree on the group state.
+///
+/// The choices are made by an RNG seeded with `seed`, so a failing run can be
+/// reproduced.
+pub(crate) struct GroupSimulation {
+    ciphersuite: Ciphersuite,
+    rng: StdRng,
+    seed: u64,
+    delivery_options: DeliveryOptions,
+    clients: Vec<SimulatedClient>,
+    proposals: Vec<PendingProposal>,
+    stats: SimulationStats,
+}
+
+impl GroupSimulation {
+    /// Create a simulation of `client_count` clients. The first client
+    /// creates the group, the others join through Add proposals.
+    pub(crate) fn new(ciphersuite: Ciphersuite, client_count: usize, seed: u64) -> Self {
+        assert!(client_count > 0);
+        let clients = (0..client_count)
+            .map(|i| {
+                let identity = Identity::new(ciphersuite, format!("Client {}", i).into_bytes());
+                let credential = Credential::Basic(BasicCredential::from(&identity));
+                SimulatedClient {
+                    identity,
+                    credential,
+                    group: None,
+                    leaf_index: None,
+                    joiner_kpb: None,
+                    inbox: vec![],
+                }
+            })
+            .collect();
+        let mut simulation = GroupSimulation {
+            ciphersuite,
+            rng: StdRng::seed_from_u64(seed),
+            seed,
+            delivery_options: DeliveryOptions {
+                drop_rate: 0.1,
+                ..DeliveryOptions::default()
+            },
+            clients,
+            proposals: vec![],
+            stats: SimulationStats::default(),
+        };
+        let kpb = simulation.new_kpb(0);
+        simulation.clients[0].group = Some(MlsGroup::new(&[1, 2, 3, 4], ciphersuite, kpb));
+        simulation.clients[0].leaf_index = Some(0);
+        simulation
+    }
+
+    /// Set the probability with which a message is dropped, as with the
+    /// `drop_rate` of a `MockDeliveryService`
+    pub(crate) fn set_drop_rate(&mut self, drop_rate: f64) {
+        let delivery_options = DeliveryOptions {
+            drop_rate,
+            ..self.delivery_options
+        };
+        delivery_options.validate().unwrap();
+        self.delivery_options = delivery_options;
+    }
+
+    pub(crate) fn get_stats(&self) -> SimulationStats {
+        self.stats
+    }
+
+    pub(crate) fn get_clients(&self) -> &[SimulatedClient] {
+        &self.clients
+    }
+
+    /// Get the indices of the clients that are members of the group
+    pub(crate) fn members(&self) -> Vec<usize> {
+        (0..self.clients.len())
+            .filter(|&i| self.clients[i].group.is_some())
+            .collect()
+    }
+
+    /// Run `steps` random steps, then commit all pending proposals and
+    /// deliver all pending messages.
+    pub(crate) fn run(&mut self, steps: usize) {
+        for _ in 0..steps {
+            self.step();
+        }
+        self.flush();
+        if !self.proposals.is_empty() {
+            self.commit();
+        }
+        self.assert_convergence();
+    }
+
+    /// Take a single random step
+    pub(crate) fn step(&mut self) {
+        match self.rng.gen_range(0, 10) {
+            0..=2 => self.propose(),
+            3 => self.commit(),
+            4..=6 => self.send_application_message(),
+            _ => {
+                let recipients: Vec<usize> = (0..self.clients.len())
+                    .filter(|&i| !self.clients[i].inbox.is_empty())
+                    .collect();
+                if let Some(&recipient) = recipients.choose(&mut self.rng) {
+                    self.deliver(recipient);
+                }
+            }
+        }
+    }
+
+    fn is_removed(&self, client: usize) -> bool {
+        self.proposals.iter().any(|p| p.removed == Some(client))
+    }
+
+    fn has_pending_update(&self, client: usize) -> bool {
+        self.proposals
+            .iter()
+            .any(|p| p.proposer == client && p.joiner.is_none() && p.removed.is_none())
+    }
+
+    /// Members can't commit their own Update or their own removal
+    fn can_commit(&self, client: usize) -> bool {
+        self.clients[client].group.is_some()
+            && !self.is_removed(client)
+            && !self.has_pending_update(client)
+    }
+
+    /// Returns `true` if a member other than `client` can commit
+    fn has_other_committer(&self, client: usize) -> bool {
+        self.members()
+            .into_iter()
+            .any(|i| i != client && self.can_commit(i))
+    }
+
+    fn new_kpb(&self, client: usize) -> KeyPackageBundle {
+        let client = &self.clients[client];
+        KeyPackageBundle::new(
+            &self.ciphersuite,
+            client.signature_key(),
+            client.credential.clone(),
+            None,
+        )
+    }
+
+    /// Let a random member propose to add a client, to update its own leaf or
+    /// to remove another member.
+    pub(crate) fn propose(&mut self) {
+        let proposers: Vec<usize> = self
+            .members()
+            .into_iter()
+            .filter(|&i| !self.is_removed(i))
+            .collect();
+        let proposer = *proposers.choose(&mut self.rng).unwrap();
+        let joiners: Vec<usize> = (0..self.clients.len())
+            .filter(|&i| self.clients[i].group.is_none() && self.clients[i].joiner_kpb.is_none())
+            .collect();
+        // Members with pending proposals are not removed, so that all
+        // committed proposals are from members. At least two members remain.
+        let removable: Vec<usize> = if proposers.len() > 2 {
+            proposers
+                .iter()
+                .copied()
+                .filter(|&i| {
+                    i != proposer
+                        && !self.proposals.iter().any(|p| p.proposer == i)
+                        && self.has_other_committer(i)
+                })
+                .collect()
+        } else {
+            vec![]
+        };
+        let can_update = !self.has_pending_update(proposer) && self.has_other_committer(proposer);
+
+        let signature_key = self.clients[proposer].signature_key().clone();
+        let mut joiner_option = None;
+        let mut removed = None;
+        let (mls_plaintext, _) = match self.rng.gen_range(0, 3) {
+            0 if !joiners.is_empty() => {
+                let joiner = *joiners.choose(&mut self.rng).unwrap();
+                let kpb = self.new_kpb(joiner);
+                let key_package = kpb.get_key_package().clone();
+                self.clients[joiner].joiner_kpb = Some(kpb);
+                joiner_option = Some(joiner);
+                self.group(proposer)
+                    .create_add_proposal(&[], &signature_key, key_package)
+                    .unwrap()
+            }
+            1 if !removable.is_empty() => {
+                let target = *removable.choose(&mut self.rng).unwrap();
+                removed = Some(target);
+                let leaf_index = self.clients[target].leaf_index.unwrap();
+                self.group(proposer)
+                    .create_remove_proposal(&[], &signature_key, leaf_index.into())
+                    .unwrap()
+            }
+            2 if can_update => {
+                let kpb = self.new_kpb(proposer);
+                self.group_mut(proposer)
+                    .create_update_proposal(&[], &signature_key, kpb)
+                    .unwrap()
+            }
+            _ => return,
+        };
+        assert_eq!(
+            Some(mls_plaintext.sender().sender.as_u32()),
+            self.clients[proposer].leaf_index,
+            "seed {}",
+            self.seed
+        );
+
+        // Dropped proposals never reach the group
+        if self.delivery_options.drops(&mut self.rng) {
+            if let Some(joiner) = joiner_option {
+                self.clients[joiner].joiner_kpb = None;
+            }
+            return;
+        }
+        for member in self.members() {
+            let queued_proposal = self
+                .group(member)
+                .process_proposal(mls_plaintext.clone())
+                .unwrap_or_else(|e| panic!("seed {}: {:?}", self.seed, e));
+            assert_eq!(Some(&queued_proposal.proposal), mls_plaintext.as_proposal());
+        }
+        self.stats.proposals += 1;
+        self.proposals.push(PendingProposal {
+            proposer,
+            mls_plaintext,
+            joiner: joiner_option,
+            removed,
+        });
+    }
+
+    /// Let a random member commit all pending proposals. All members apply
+    /// the Commit and added clients join from the Welcome.
+    pub(crate) fn commit(&mut self) {
+        let committers: Vec<usize> = self
+            .members()
+            .into_iter()
+            .filter(|&i| self.can_commit(i))
+            .collect();
+        let committer = match committers.choose(&mut self.rng) {
+            Some(&committer) => committer,
+            None => return,
+        };
+        // Application messages of the current epoch are delivered first
+        self.flush();
+
+        let proposals: Vec<_> = self
+            .proposals
+            .iter()
+            .map(|p| {
+                let proposal = p.mls_plaintext.as_proposal().unwrap();
+                (p.mls_plaintext.sender(), proposal.clone())
+            })
+            .collect();
+        let force_self_update = proposals.is_empty() || self.rng.gen_bool(0.5);
+        let signature_key = self.clients[committer].signature_key().clone();
+        let kpb = self.new_kpb(committer);
+        let seed = self.seed;
+        let commit_bundle = self
+            .group_mut(committer)
+            .create_commit(
+                &[],
+                &signature_key,
+                kpb,
+                proposals.clone(),
+                force_self_update,
+            )
+            .unwrap_or_else(|e| panic!("seed {}: {:?}", seed, e));
+        assert!(commit_bundle.get_deferred_proposals().is_empty());
+        let commit = commit_bundle.get_commit().clone();
+
+        let mut membership_changes = None;
+        for member in self.members() {
+            let result = self
+                .group_mut(member)
+                .apply_commit(commit.clone(), proposals.clone());
+            if self.proposals.iter().any(|p| p.removed == Some(member)) {
+                assert_eq!(result.err(), Some(ApplyCommitError::SelfRemoved));
+                let client = &mut self.clients[member];
+                client.group = None;
+                client.leaf_index = None;
+                client.inbox.clear();
+                self.stats.removals += 1;
+                continue;
+            }
+            let changes = result.unwrap_or_else(|e| panic!("seed {}: {:?}", seed, e));
+            if member == committer {
+                membership_changes = Some(changes);
+            }
+        }
+
+        // Added clients join from the Welcome
+        let nodes = self.group(committer).get_tree().public_key_tree();
+        for added in membership_changes.unwrap().adds {
+            let joiner = self
+                .proposals
+                .iter()
+                .filter_map(|p| p.joiner)
+                .find(|&i| &self.clients[i].credential == added.leaf_node.get_credential())
+                .unwrap();
+            let kpb = self.clients[joiner].joiner_kpb.take().unwrap();
+            let welcome = commit_bundle.get_welcome().unwrap().clone();
+            let group = MlsGroup::new_from_welcome(welcome, Some(nodes.clone()), kpb)
+                .unwrap_or_else(|e| panic!("seed {}: {:?}", seed, e));
+            self.clients[joiner].group = Some(group);
+            self.clients[joiner].leaf_index = Some(added.index.as_u32());
+            self.stats.joins += 1;
+        }
+        self.proposals.clear();
+        self.stats.commits += 1;
+        self.assert_convergence();
+    }
+
+    /// Let a random member send an application message to all other members
+    pub(crate) fn send_application_message(&mut self) {
+        let members = self.members();
+        let sender = *members.choose(&mut self.rng).unwrap();
+        let mut payload = vec![0u8; self.rng.gen_range(1, 64)];
+        self.rng.fill(&mut payload[..]);
+        let signature_key = self.clients[sender].signature_key().clone();
+        let group = self.group_mut(sender);
+        let mls_plaintext = group.create_application_message(&[], &payload, &signature_key);
+        let mls_ciphertext = group.encrypt(mls_plaintext).unwrap();
+        for &recipient in members.iter().filter(|&&i| i != sender) {
+            self.clients[recipient]
+                .inbox
+                .push((mls_ciphertext.clone(), sender, payload.clone()));
+        }
+        self.stats.messages_sent += 1;
+    }
+
+    /// Deliver the next chunk of application messages to `recipient` in
+    /// random order, dropping some of them.
+    pub(crate) fn deliver(&mut self, recipient: usize) {
+        let chunk_size = self.clients[recipient].inbox.len().min(REORDER_WINDOW);
+        let mut chunk: Vec<_> = self.clients[recipient].inbox.drain(..chunk_size).collect();
+        chunk.shuffle(&mut self.rng);
+        for (mls_ciphertext, sender, payload) in chunk {
+            if self.delivery_options.drops(&mut self.rng) {
+                self.stats.messages_dropped += 1;
+                continue;
+            }
+            let seed = self.seed;
+            let processed_message = self
+                .group_mut(recipient)
+                .decrypt(mls_ciphertext)
+                .unwrap_or_else(|e| panic!("seed {}: {:?}", seed, e));
+            assert_eq!(
+                processed_message.sender_credential(),
+                &self.clients[sender].credential
+            );
+            assert_eq!(processed_message.application_data(), Some(&payload[..]));
+            self.stats.messages_delivered += 1;
+        }
+    }
+
+    /// Deliver all pending application messages
+    pub(crate) fn flush(&mut self) {
+        for recipient in 0..self.clients.len() {
+            while !self.clients[recipient].inbox.is_empty() {
+                self.deliver(recipient);
+            }
+        }
+    }
+
+    /// Assert that all members agree on the state of the group
+    pub(crate) fn assert_convergence(&self) {
+        let members = self.members();
+        let reference = self.group(members[0]);
+        for &member in members.iter() {
+            let group = self.group(member);
+            assert!(
+                group
+                    .get_fingerprint()
+                    .matches(&reference.get_fingerprint()),
+                "seed {}",
+                self.seed
+            );
+            assert_eq!(
+                group.get_context().epoch,
+                reference.get_context().epoch,
+                "seed {}",
+                self.seed
+            );
+            assert_eq!(
+                group.get_tree().public_key_tree(),
+                reference.get_tree().public_key_tree(),
+                "seed {}",
+                self.seed
+            );
+            assert_eq!(
+                group.export_secret("simulation", 32),
+                reference.export_secret("simulation", 32),
+                "seed {}",
+                self.seed
+            );
+            assert_eq!(group.member_count(), members.len(), "seed {}", self.seed);
+        }
+    }
+
+    fn group(&self, client: usize) -> &MlsGroup {
+        self.clients[client].group.as_ref().unwrap()
+    }
+
+    fn group_mut(&mut self, client: usize) -> &mut MlsGroup {
+        self.clients[client].group.as_mut().unwrap()
+    }
+}
+
+/// Alice and Bob in a group that Alice created with Bob as the only other
+/// member. Bob joined from the Welcome with the ratchet tree of Alice.
+pub(crate) struct TwoMemberGroup {
+    pub(crate) ciphersuite: Ciphersuite,
+    pub(crate) alice_identity: Identity,
+    pub(crate) bob_identity: Identity,
+    pub(crate) alice_credential: Credential,
+    pub(crate) bob_credential: Credential,
+    pub(crate) group_alice: MlsGroup,
+    pub(crate) group_bob: MlsGroup,
+}
+
+impl TwoMemberGroup {
+    pub(crate) fn new() -> Self {
+        let ciphersuite =
+            Ciphersuite::new(CiphersuiteName::MLS10_128_DHKEMX25519_AES128GCM_SHA256_Ed25519);
+        let alice_identity = Identity::new(ciphersuite, "Alice".into());
+        let bob_identity = Identity::new(ciphersuite, "Bob".into());
+        let alice_credential = Credential::Basic(BasicCredential::from(&alice_identity));
+        let bob_credential = Credential::Basic(BasicCredential::from(&bob_identity));
+        let alice_signature_key = alice_identity.get_signature_key_pair().get_private_key();
+        let bob_signature_key = bob_identity.get_signature_key_pair().get_private_key();
+
+        let bob_key_package_bundle = KeyPackageBundle::new(
+            &ciphersuite,
+            bob_signature_key,
+            bob_credential.clone(),
+            None,
+        );
+        let (group_alice, welcome) = MlsGroup::new_with_members(
+            &[1, 2, 3, 4],
+            ciphersuite,
+            KeyPackageBundle::new(
+                &ciphersuite,
+                alice_signature_key,
+                alice_credential.clone(),
+                None,
+            ),
+            alice_signature_key,
+            vec![bob_key_package_bundle.get_key_package().clone()],
+        )
+        .unwrap();
+        let group_bob = MlsGroup::new_from_welcome(
+            welcome,
+            Some(group_alice.get_tree().public_key_tree()),
+            bob_key_package_bundle,
+        )
+        .unwrap();
+        TwoMemberGroup {
+            ciphersuite,
+            alice_identity,
+            bob_identity,
+            alice_credential,
+            bob_credential,
+            group_alice,
+            group_bob,
+        }
+    }
+}
+
+/// Drive `future` to completion on the current thread. The crate doesn't
+/// depend on an async runtime, this is enough for futures that don't wait
+/// for I/O.
+pub(crate) fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut context = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        std::thread::yield_now();
+    }
+}